@@ -0,0 +1,336 @@
+use kraken2_rs::abundance::{self, KreportLine as Line};
+use kraken2_rs::classifier::{Classification as ClassifyResult, Classifier as Inner};
+use kraken2_rs::taxonomy::Taxonomy as InnerTaxonomy;
+use pyo3::exceptions::{PyFileNotFoundError, PyIOError, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+fn py_error(e: io::Error) -> PyErr {
+    match e.kind() {
+        io::ErrorKind::NotFound => PyFileNotFoundError::new_err(e.to_string()),
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => {
+            PyValueError::new_err(e.to_string())
+        }
+        _ => PyIOError::new_err(e.to_string()),
+    }
+}
+
+/// 一条序列 (或一对 reads) 的分类结果
+#[pyclass(module = "kr2r", frozen, get_all)]
+#[derive(Clone)]
+struct Classification {
+    /// 分类到的 taxid, 0 表示未分类
+    taxid: u64,
+    name: String,
+    /// 从 root 开始的谱系学名
+    lineage: Vec<String>,
+    /// 与 kraken 标准输出第 5 列相同的 LCA 映射, 例如 "562:13 561:4 A:31 0:1"
+    hit_string: String,
+}
+
+#[pymethods]
+impl Classification {
+    #[getter]
+    fn classified(&self) -> bool {
+        self.taxid > 0
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Classification(taxid={}, name={:?}, classified={})",
+            self.taxid,
+            self.name,
+            if self.taxid > 0 { "True" } else { "False" }
+        )
+    }
+}
+
+/// 加载整个数据库的分类器, 与 `classify-seq` 相同
+#[pyclass(module = "kr2r", frozen)]
+struct Classifier {
+    inner: Inner,
+}
+
+impl Classifier {
+    fn result(&self, result: ClassifyResult) -> Classification {
+        Classification {
+            taxid: result.taxid,
+            name: self.inner.taxon_name(result.taxid).to_string(),
+            lineage: self
+                .inner
+                .lineage(result.taxid)
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            hit_string: result.hit_string,
+        }
+    }
+}
+
+#[pymethods]
+impl Classifier {
+    #[new]
+    #[pyo3(signature = (database, confidence_threshold = 0.0, minimum_hit_groups = 2, memory_mapping = false))]
+    fn new(
+        py: Python<'_>,
+        database: PathBuf,
+        confidence_threshold: f64,
+        minimum_hit_groups: usize,
+        memory_mapping: bool,
+    ) -> PyResult<Self> {
+        let inner = py
+            .allow_threads(|| {
+                if memory_mapping {
+                    Inner::map_database(&database)
+                } else {
+                    Inner::from_database(&database)
+                }
+            })
+            .map_err(py_error)?
+            .with_confidence_threshold(confidence_threshold)
+            .with_minimum_hit_groups(minimum_hit_groups);
+        Ok(Self { inner })
+    }
+
+    /// 对一条序列分类; 给出 `mate` 时作为 paired reads 分类
+    #[pyo3(signature = (seq, mate = None))]
+    fn classify(&self, py: Python<'_>, seq: &str, mate: Option<&str>) -> Classification {
+        let result = py.allow_threads(|| match mate {
+            Some(mate) => self.inner.classify_pair(seq.as_bytes(), mate.as_bytes()),
+            None => self.inner.classify_read(seq.as_bytes()),
+        });
+        self.result(result)
+    }
+
+    /// 并行地对多条序列分类, 分类时释放 GIL
+    fn classify_many(&self, py: Python<'_>, seqs: Vec<String>) -> Vec<Classification> {
+        let results: Vec<ClassifyResult> = py.allow_threads(|| {
+            seqs.par_iter()
+                .map(|seq| self.inner.classify_read(seq.as_bytes()))
+                .collect()
+        });
+        results
+            .into_iter()
+            .map(|result| self.result(result))
+            .collect()
+    }
+
+    /// taxid 的学名, 0 为 "unclassified"
+    fn taxon_name(&self, taxid: u64) -> String {
+        self.inner.taxon_name(taxid).to_string()
+    }
+
+    /// taxid 从 root 开始的谱系学名
+    fn lineage(&self, taxid: u64) -> Vec<String> {
+        self.inner
+            .lineage(taxid)
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// 数据库的 taxo.k2d, 以 taxid (external id) 访问
+#[pyclass(module = "kr2r", frozen)]
+struct Taxonomy {
+    inner: InnerTaxonomy,
+}
+
+impl Taxonomy {
+    /// 不在 taxonomy 中的 taxid 报 KeyError
+    fn internal(&self, taxid: u64) -> PyResult<u32> {
+        match self.inner.get_internal_id(taxid) {
+            0 => Err(PyKeyError::new_err(taxid)),
+            id => Ok(id),
+        }
+    }
+
+    fn external(&self, internal_id: u32) -> u64 {
+        self.inner
+            .nodes
+            .get(internal_id as usize)
+            .map_or(0, |node| node.external_id)
+    }
+}
+
+#[pymethods]
+impl Taxonomy {
+    /// 读取 taxo.k2d, 或数据库目录中的 taxo.k2d
+    #[new]
+    #[pyo3(signature = (path, memory_mapping = false))]
+    fn new(path: PathBuf, memory_mapping: bool) -> PyResult<Self> {
+        let path = if path.is_dir() {
+            path.join("taxo.k2d")
+        } else {
+            path
+        };
+        let inner = if memory_mapping {
+            InnerTaxonomy::open_mmap(&path)
+        } else {
+            InnerTaxonomy::from_file(&path)
+        }
+        .map_err(py_error)?;
+        Ok(Self { inner })
+    }
+
+    fn __len__(&self) -> usize {
+        // 第 0 个节点是占位的
+        self.inner.node_count().saturating_sub(1)
+    }
+
+    fn __contains__(&self, taxid: u64) -> bool {
+        self.inner.get_internal_id(taxid) != 0
+    }
+
+    fn name(&self, taxid: u64) -> PyResult<String> {
+        Ok(self.inner.name(self.internal(taxid)?).to_string())
+    }
+
+    /// 分类层级, 例如 "genus"
+    fn rank(&self, taxid: u64) -> PyResult<String> {
+        Ok(self.inner.rank(self.internal(taxid)?).to_string())
+    }
+
+    /// 父节点的 taxid, root 为 None
+    fn parent(&self, taxid: u64) -> PyResult<Option<u64>> {
+        let node = &self.inner.nodes[self.internal(taxid)? as usize];
+        Ok(match node.parent_id as u32 {
+            0 => None,
+            parent => Some(self.external(parent)),
+        })
+    }
+
+    /// 子节点的 taxid
+    fn children(&self, taxid: u64) -> PyResult<Vec<u64>> {
+        let node = &self.inner.nodes[self.internal(taxid)? as usize];
+        Ok((node.first_child..node.first_child + node.child_count)
+            .map(|child| self.external(child as u32))
+            .collect())
+    }
+
+    /// 从 root 到 taxid (含) 的 taxid
+    fn lineage(&self, taxid: u64) -> PyResult<Vec<u64>> {
+        Ok(self
+            .inner
+            .lineage(self.internal(taxid)?)
+            .into_iter()
+            .map(|id| self.external(id))
+            .collect())
+    }
+
+    /// 两个 taxid 的最低公共祖先
+    fn lca(&self, a: u64, b: u64) -> PyResult<u64> {
+        let lca = self
+            .inner
+            .lowest_common_ancestor(self.internal(a)?, self.internal(b)?);
+        Ok(self.external(lca))
+    }
+
+    /// `ancestor` 是否为 `taxid` 或其祖先
+    fn is_ancestor(&self, ancestor: u64, taxid: u64) -> PyResult<bool> {
+        Ok(self
+            .inner
+            .is_a_ancestor_of_b(self.internal(ancestor)?, self.internal(taxid)?))
+    }
+}
+
+/// kreport2 中的一行
+#[pyclass(module = "kr2r", get_all, set_all)]
+#[derive(Clone)]
+struct KreportLine {
+    clade_reads: u64,
+    direct_reads: u64,
+    /// rank code, 例如 "S" 或 "G1"
+    rank: String,
+    taxid: u64,
+    name: String,
+    /// 名称前的缩进层数
+    depth: usize,
+}
+
+#[pymethods]
+impl KreportLine {
+    #[new]
+    #[pyo3(signature = (clade_reads, direct_reads, rank, taxid, name, depth = 0))]
+    fn new(
+        clade_reads: u64,
+        direct_reads: u64,
+        rank: String,
+        taxid: u64,
+        name: String,
+        depth: usize,
+    ) -> Self {
+        Self {
+            clade_reads,
+            direct_reads,
+            rank,
+            taxid,
+            name,
+            depth,
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "KreportLine(clade_reads={}, direct_reads={}, rank={:?}, taxid={}, name={:?}, depth={})",
+            self.clade_reads, self.direct_reads, self.rank, self.taxid, self.name, self.depth
+        )
+    }
+}
+
+impl From<Line> for KreportLine {
+    fn from(line: Line) -> Self {
+        Self {
+            clade_reads: line.clade_reads,
+            direct_reads: line.direct_reads,
+            rank: line.rank,
+            taxid: line.taxid,
+            name: line.name,
+            depth: line.depth,
+        }
+    }
+}
+
+impl From<KreportLine> for Line {
+    fn from(line: KreportLine) -> Self {
+        Self {
+            clade_reads: line.clade_reads,
+            direct_reads: line.direct_reads,
+            rank: line.rank,
+            taxid: line.taxid,
+            name: line.name,
+            depth: line.depth,
+        }
+    }
+}
+
+/// 读取 kreport2 (可为压缩文件), 兼容 `-K` 的 minimizer 列, 跳过 `#` 注释行
+#[pyfunction]
+fn read_kreport(path: PathBuf) -> PyResult<Vec<KreportLine>> {
+    let lines = abundance::read_kreport(path).map_err(py_error)?;
+    Ok(lines.into_iter().map(KreportLine::from).collect())
+}
+
+/// 写出 kreport2, 百分比以最上层 (unclassified 与 root) 的 reads 之和为总数
+#[pyfunction]
+fn write_kreport(path: PathBuf, lines: Vec<KreportLine>) -> PyResult<()> {
+    let lines: Vec<Line> = lines.into_iter().map(Line::from).collect();
+    let mut writer = BufWriter::new(File::create(path).map_err(py_error)?);
+    abundance::write_kreport(&mut writer, &lines).map_err(py_error)?;
+    writer.flush().map_err(py_error)
+}
+
+#[pymodule]
+fn kr2r(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_class::<Classifier>()?;
+    m.add_class::<Classification>()?;
+    m.add_class::<Taxonomy>()?;
+    m.add_class::<KreportLine>()?;
+    m.add_function(wrap_pyfunction!(read_kreport, m)?)?;
+    m.add_function(wrap_pyfunction!(write_kreport, m)?)?;
+    Ok(())
+}
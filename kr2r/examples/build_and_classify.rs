@@ -48,7 +48,7 @@ fn main() {
     if !covid_fa.exists() {
         println!(
             "kun_peng error: fasta file {} does not exists",
-            covid_fa.to_string_lossy().to_string()
+            covid_fa.to_string_lossy()
         );
     }
     let direct_args = vec![
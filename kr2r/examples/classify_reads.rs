@@ -0,0 +1,35 @@
+use kraken2_rs::classifier::Classifier;
+use seqkmer::RecordReader;
+use std::env;
+
+// cargo run --release --example classify_reads -- <database> <reads.fa|reads.fq> [mates.fq]
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("usage: classify_reads <database> <reads> [mates]");
+        std::process::exit(1);
+    }
+
+    let classifier = Classifier::from_database(&args[0])?;
+    let mut reader = RecordReader::from_path(&args[1])?;
+    let mut mates = args.get(2).map(RecordReader::from_path).transpose()?;
+
+    while let Some(record) = reader.next_record()? {
+        let result = match mates.as_mut() {
+            Some(mates) => match mates.next_record()? {
+                Some(mate) => classifier.classify_pair(&record.seq, &mate.seq),
+                None => break,
+            },
+            None => classifier.classify_read(&record.seq),
+        };
+        let id = String::from_utf8_lossy(&record.header);
+        println!(
+            "{}\t{}\t{}\t{}",
+            if result.is_classified() { "C" } else { "U" },
+            id.split_whitespace().next().unwrap_or_default(),
+            result.taxid,
+            classifier.taxon_name(result.taxid)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use kraken2_rs::classifier::Classifier;
+use std::env;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// cargo run --release --example readuntil_server -- <database> [address] [max_bases] [eject_taxids] [budget_us]
+//
+// Every request line is `<read_id>\t<bases so far>`, and every answer line is
+// `<read_id>\t<decision>\t<taxid>\t<name>\t<bases used>\t<microseconds>`, where decision is
+// `unblock` (eject the read: it belongs to one of the eject taxa), `stop_receiving` (keep
+// sequencing it) or `proceed` (unclassified so far, send more bases).
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        eprintln!(
+            "usage: readuntil_server <database> [address] [max_bases] [eject_taxids] [budget_us]"
+        );
+        std::process::exit(1);
+    }
+    let address = args.get(1).map_or("127.0.0.1:7101", String::as_str);
+    let max_bases: usize = match args.get(2).map(|n| n.parse()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            eprintln!("max_bases must be a number");
+            std::process::exit(1);
+        }
+        None => 400,
+    };
+    let eject: Vec<u64> = args
+        .get(3)
+        .map(|taxa| {
+            taxa.split(',')
+                .filter_map(|t| t.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let budget = match args.get(4).map(|n| n.parse()) {
+        Some(Ok(micros)) => Some(Duration::from_micros(micros)),
+        Some(Err(_)) => {
+            eprintln!("budget_us must be a number");
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let start = Instant::now();
+    let mut classifier = Classifier::from_database(&args[0])?;
+    if let Some(budget) = budget {
+        classifier = classifier.with_time_budget(budget);
+    }
+    let classifier = Arc::new(classifier);
+    let listener = TcpListener::bind(address)?;
+    eprintln!(
+        "database loaded in {:?}, listening on {}",
+        start.elapsed(),
+        listener.local_addr()?
+    );
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let classifier = Arc::clone(&classifier);
+        let eject = eject.clone();
+        thread::spawn(move || {
+            if let Err(e) = serve(stream, &classifier, max_bases, &eject) {
+                eprintln!("connection closed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn serve(
+    stream: TcpStream,
+    classifier: &Classifier,
+    max_bases: usize,
+    eject: &[u64],
+) -> std::io::Result<()> {
+    // answers must not wait for Nagle's algorithm
+    stream.set_nodelay(true)?;
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let taxonomy = classifier.taxonomy();
+    let eject: Vec<u32> = eject
+        .iter()
+        .map(|&taxid| taxonomy.get_internal_id(taxid))
+        .filter(|&id| id > 0)
+        .collect();
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let Some((id, seq)) = line.split_once('\t') else {
+            continue;
+        };
+        let start = Instant::now();
+        let result = classifier.classify_prefix(seq.trim_end().as_bytes(), max_bases);
+        let elapsed = start.elapsed();
+        let used = result.seq_len.single().copied().unwrap_or_default();
+        let decision = if result.is_classified() {
+            let internal_id = taxonomy.get_internal_id(result.taxid);
+            if eject
+                .iter()
+                .any(|&taxon| taxonomy.is_a_ancestor_of_b(taxon, internal_id))
+            {
+                "unblock"
+            } else {
+                "stop_receiving"
+            }
+        } else if used < max_bases {
+            "proceed"
+        } else {
+            "stop_receiving"
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            id,
+            decision,
+            result.taxid,
+            classifier.taxon_name(result.taxid),
+            used,
+            elapsed.as_micros()
+        )?;
+        writer.flush()?;
+    }
+    Ok(())
+}
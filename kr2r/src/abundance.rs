@@ -0,0 +1,400 @@
+use crate::catalog::{ChunkCatalog, ChunkKind};
+use crate::classify::{lookup_rows, process_hitgroup};
+use crate::compact_hash::{CHTable, HashConfig, Row, ShardChecksums};
+use crate::taxonomy::Taxonomy;
+use crate::utils::{find_files, open_file, read_id_to_taxon_map};
+use crate::{HitGroup, IndexOptions};
+use seqkmer::{read_parallel, FastaReader, WindowReader};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+
+/// kreport 的 rank code, 从高到低
+const RANK_CODES: [char; 9] = ['R', 'D', 'K', 'P', 'C', 'O', 'F', 'G', 'S'];
+
+/// 数据库目录下的 kmer 分布文件, 与 Bracken 的命名一致
+pub fn kmer_distrib_path<P: AsRef<Path>>(database: P, read_len: usize) -> PathBuf {
+    database
+        .as_ref()
+        .join(format!("database{}mers.kmer_distrib", read_len))
+}
+
+/// 每个参考基因组 (taxid) 模拟出的 reads 被分类到各 taxid 的数量
+#[derive(Debug, Default)]
+pub struct KmerDistrib {
+    /// mapped taxid -> [(genome taxid, reads)]
+    mapped: HashMap<u64, Vec<(u64, u64)>>,
+    /// genome taxid -> 模拟 reads 总数
+    totals: HashMap<u64, u64>,
+}
+
+impl KmerDistrib {
+    /// 以 `step` 为步长从 library 序列中截取长度为 `read_len` 的 reads, 用数据库重新分类
+    pub fn build<P: AsRef<Path>>(
+        database: P,
+        read_len: usize,
+        step: usize,
+        threads: usize,
+    ) -> Result<Self> {
+        let database = database.as_ref();
+        let idx_opts = IndexOptions::read_index_options(database.join("opts.k2d"))?;
+        let meros = idx_opts.as_meros().for_references();
+        let taxonomy = Taxonomy::from_file(database.join("taxo.k2d"))?;
+        let hash_config = HashConfig::from_hash_header(database.join("hash_config.k2d"))?;
+        let checksums = ShardChecksums::from_hash_header(database.join("hash_config.k2d"))?;
+        let hash_files = ChunkCatalog::scan_continuous(database, ChunkKind::Hash)?.paths();
+        let chtable = CHTable::from_hash_files(hash_config, &hash_files, checksums.as_ref())?;
+        let id_to_taxon_map = read_id_to_taxon_map(database.join("seqid2taxid.map"))?;
+        let library_files = find_files(database.join("library"), "library", ".fna");
+
+        let classify_counter = AtomicUsize::new(0);
+        let mut counts: HashMap<(u64, u64), u64> = HashMap::new();
+        let mut totals: HashMap<u64, u64> = HashMap::new();
+
+        for library_file in &library_files {
+            let fasta = FastaReader::from_path(library_file, 1)?;
+            let mut reader = WindowReader::new(fasta, read_len, step.max(1));
+            read_parallel(
+                &mut reader,
+                threads,
+                &meros,
+                |reads| {
+                    let mut batch = Vec::new();
+                    for read in reads {
+                        let Some(&genome) = id_to_taxon_map.get(&read.header.id) else {
+                            continue;
+                        };
+                        if read.body.reduce(0, |acc, m_iter| acc + m_iter.seq_size()) < read_len {
+                            continue;
+                        }
+                        let rows: Vec<Row> = read.fold(|rows, m_iter, offset| {
+                            lookup_rows(rows, m_iter, &hash_config, &chtable, offset)
+                        });
+                        let hits = HitGroup::new(rows, read.range());
+                        let hit_data = process_hitgroup(
+                            &hits,
+                            &taxonomy,
+                            &classify_counter,
+                            0,
+                            2,
+                            hash_config.value_mask,
+                            None,
+                            None,
+                        );
+                        batch.push((genome, hit_data.1));
+                    }
+                    batch
+                },
+                |dataset| {
+                    while let Some(data) = dataset.next() {
+                        for (genome, mapped) in data.unwrap() {
+                            *totals.entry(genome).or_insert(0) += 1;
+                            if mapped > 0 {
+                                *counts.entry((mapped, genome)).or_insert(0) += 1;
+                            }
+                        }
+                    }
+                },
+            )?;
+        }
+
+        let mut mapped: HashMap<u64, Vec<(u64, u64)>> = HashMap::new();
+        for ((taxid, genome), count) in counts {
+            mapped.entry(taxid).or_default().push((genome, count));
+        }
+        Ok(Self { mapped, totals })
+    }
+
+    /// 写出 Bracken 格式: `mapped_taxid\tgenome_taxid:reads:total_reads ...`
+    pub fn write_to_file<P: AsRef<Path>>(&self, filename: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writeln!(
+            writer,
+            "mapped_taxid\tgenome_taxids:kmers_mapped:total_genome_kmers"
+        )?;
+        let mut taxids: Vec<&u64> = self.mapped.keys().collect();
+        taxids.sort_unstable();
+        for taxid in taxids {
+            let mut genomes = self.mapped[taxid].clone();
+            genomes.sort_unstable();
+            let entries = genomes
+                .iter()
+                .map(|(genome, count)| format!("{}:{}:{}", genome, count, self.totals[genome]))
+                .collect::<Vec<String>>()
+                .join(" ");
+            writeln!(writer, "{}\t{}", taxid, entries)?;
+        }
+        writer.flush()
+    }
+
+    pub fn from_file<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        let reader = BufReader::new(open_file(filename)?);
+        let mut distrib = Self::default();
+        for line in reader.lines().skip(1) {
+            let line = line?;
+            let Some((taxid, entries)) = line.split_once('\t') else {
+                continue;
+            };
+            let Ok(taxid) = taxid.parse::<u64>() else {
+                continue;
+            };
+            for entry in entries.split_whitespace() {
+                let parts: Vec<&str> = entry.split(':').collect();
+                if parts.len() != 3 {
+                    continue;
+                }
+                let (Ok(genome), Ok(count), Ok(total)) = (
+                    parts[0].parse::<u64>(),
+                    parts[1].parse::<u64>(),
+                    parts[2].parse::<u64>(),
+                ) else {
+                    continue;
+                };
+                distrib
+                    .mapped
+                    .entry(taxid)
+                    .or_default()
+                    .push((genome, count));
+                distrib.totals.insert(genome, total);
+            }
+        }
+        Ok(distrib)
+    }
+
+    /// genome 的 reads 被分类到 `mapped` 的比例
+    pub fn genomes_mapped_to(&self, mapped: u64) -> impl Iterator<Item = (u64, f64)> + '_ {
+        self.mapped
+            .get(&mapped)
+            .into_iter()
+            .flatten()
+            .map(|(genome, count)| {
+                let total = self.totals.get(genome).copied().unwrap_or(0).max(1);
+                (*genome, *count as f64 / total as f64)
+            })
+    }
+
+    pub fn genomes(&self) -> impl Iterator<Item = &u64> {
+        self.totals.keys()
+    }
+}
+
+/// kreport2 中的一行
+#[derive(Debug, Clone)]
+pub struct KreportLine {
+    pub clade_reads: u64,
+    pub direct_reads: u64,
+    pub rank: String,
+    pub taxid: u64,
+    pub name: String,
+    /// 名称前的缩进层数 (每层两个空格)
+    pub depth: usize,
+}
+
+/// 读取 kreport2, 兼容 `-K` 输出的 minimizer 列, 跳过 `--report-headers` 的 `#` 注释行
+pub fn read_kreport<P: AsRef<Path>>(filename: P) -> Result<Vec<KreportLine>> {
+    let reader = BufReader::new(open_file(filename)?);
+    let mut lines = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').collect();
+        let (rank, taxid, name) = match parts.len() {
+            6 => (parts[3], parts[4], parts[5]),
+            8 => (parts[5], parts[6], parts[7]),
+            _ => continue,
+        };
+        let (Ok(clade_reads), Ok(direct_reads), Ok(taxid)) = (
+            parts[1].parse::<u64>(),
+            parts[2].parse::<u64>(),
+            taxid.parse::<u64>(),
+        ) else {
+            continue;
+        };
+        let trimmed = name.trim_start_matches(' ');
+        lines.push(KreportLine {
+            clade_reads,
+            direct_reads,
+            rank: rank.to_string(),
+            taxid,
+            name: trimmed.trim().to_string(),
+            depth: (name.len() - trimmed.len()) / 2,
+        });
+    }
+    Ok(lines)
+}
+
+/// 写出 kreport2 (6 列), 百分比以最上层 (unclassified 与 root) 的 reads 之和为总数
+///
+/// ```
+/// use kraken2_rs::abundance::{read_kreport, write_kreport};
+///
+/// let text = " 20.00\t2\t2\tU\t0\tunclassified\n 80.00\t8\t0\tR\t1\troot\n 80.00\t8\t8\tS\t562\t  Escherichia coli\n";
+/// let path = std::env::temp_dir().join(format!("kreport_doctest_{}.kreport2", std::process::id()));
+/// std::fs::write(&path, text).unwrap();
+/// let lines = read_kreport(&path).unwrap();
+/// assert_eq!((lines[2].name.as_str(), lines[2].depth), ("Escherichia coli", 1));
+///
+/// let mut out = Vec::new();
+/// write_kreport(&mut out, &lines).unwrap();
+/// assert_eq!(String::from_utf8(out).unwrap(), text);
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn write_kreport<W: Write>(writer: &mut W, lines: &[KreportLine]) -> Result<()> {
+    let total: u64 = lines
+        .iter()
+        .filter(|line| line.depth == 0)
+        .map(|line| line.clade_reads)
+        .sum();
+    for line in lines {
+        let percent = if total > 0 {
+            100.0 * line.clade_reads as f64 / total as f64
+        } else {
+            0.0
+        };
+        writeln!(
+            writer,
+            "{:6.2}\t{}\t{}\t{}\t{}\t{}{}",
+            percent,
+            line.clade_reads,
+            line.direct_reads,
+            line.rank,
+            line.taxid,
+            "  ".repeat(line.depth),
+            line.name
+        )?;
+    }
+    Ok(())
+}
+
+/// 单个分类单元重新估计后的丰度
+#[derive(Debug, Clone)]
+pub struct AbundanceEstimate {
+    pub taxid: u64,
+    pub name: String,
+    pub kraken_reads: u64,
+    pub added_reads: u64,
+    pub new_reads: u64,
+    pub fraction: f64,
+}
+
+fn rank_order(rank: &str) -> Option<usize> {
+    let code = rank.chars().next()?;
+    RANK_CODES.iter().position(|&c| c == code)
+}
+
+/// 按 Bracken 的方法将高于 `level` 的 reads 按概率重新分配到 `level` 层级
+///
+/// `level` 为 kreport 的 rank code (如 "S", "G"), clade reads 少于 `threshold` 的分类单元不参与估计.
+pub fn estimate_abundance(
+    report: &[KreportLine],
+    distrib: &KmerDistrib,
+    taxonomy: &Taxonomy,
+    level: &str,
+    threshold: u64,
+) -> Result<Vec<AbundanceEstimate>> {
+    let level_order = rank_order(level)
+        .filter(|_| level.len() == 1)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported abundance level: {}", level),
+            )
+        })?;
+
+    // level 层级的分类单元, key 为 internal taxid
+    let level_nodes: HashMap<u32, &KreportLine> = report
+        .iter()
+        .filter(|line| line.rank == level && line.clade_reads >= threshold)
+        .map(|line| (taxonomy.get_internal_id(line.taxid), line))
+        .filter(|(taxid, _)| *taxid > 0)
+        .collect();
+
+    // 参考基因组 -> 其所属的 level 分类单元
+    let mut genome_to_level: HashMap<u64, u32> = HashMap::new();
+    for &genome in distrib.genomes() {
+        let mut taxid = taxonomy.get_internal_id(genome);
+        while taxid != 0 {
+            if level_nodes.contains_key(&taxid) {
+                genome_to_level.insert(genome, taxid);
+                break;
+            }
+            let parent = taxonomy.nodes[taxid as usize].parent_id as u32;
+            if parent == taxid {
+                break;
+            }
+            taxid = parent;
+        }
+    }
+
+    let mut added: HashMap<u32, f64> = HashMap::new();
+    for line in report {
+        if line.direct_reads == 0 || rank_order(&line.rank).is_none_or(|o| o >= level_order) {
+            continue;
+        }
+        let mapped = taxonomy.get_internal_id(line.taxid);
+        let mut probabilities: HashMap<u32, f64> = HashMap::new();
+        for (genome, fraction) in distrib.genomes_mapped_to(line.taxid) {
+            let Some(&node) = genome_to_level.get(&genome) else {
+                continue;
+            };
+            if !taxonomy.is_a_ancestor_of_b(mapped, node) {
+                continue;
+            }
+            *probabilities.entry(node).or_insert(0.0) +=
+                fraction * level_nodes[&node].clade_reads as f64;
+        }
+        let total: f64 = probabilities.values().sum();
+        if total <= 0.0 {
+            continue;
+        }
+        for (node, probability) in probabilities {
+            *added.entry(node).or_insert(0.0) += line.direct_reads as f64 * probability / total;
+        }
+    }
+
+    let mut estimates: Vec<AbundanceEstimate> = level_nodes
+        .iter()
+        .map(|(node, line)| {
+            let added_reads = added.get(node).copied().unwrap_or(0.0).round() as u64;
+            AbundanceEstimate {
+                taxid: line.taxid,
+                name: line.name.clone(),
+                kraken_reads: line.clade_reads,
+                added_reads,
+                new_reads: line.clade_reads + added_reads,
+                fraction: 0.0,
+            }
+        })
+        .collect();
+    let total_reads: u64 = estimates.iter().map(|e| e.new_reads).sum();
+    for estimate in &mut estimates {
+        estimate.fraction = estimate.new_reads as f64 / total_reads.max(1) as f64;
+    }
+    estimates.sort_unstable_by(|a, b| b.new_reads.cmp(&a.new_reads).then(a.taxid.cmp(&b.taxid)));
+    Ok(estimates)
+}
+
+/// 写出 Bracken 格式的丰度表
+pub fn write_abundance<P: AsRef<Path>>(
+    filename: P,
+    level: &str,
+    estimates: &[AbundanceEstimate],
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writeln!(
+        writer,
+        "name\ttaxonomy_id\ttaxonomy_lvl\tkraken_assigned_reads\tadded_reads\tnew_est_reads\tfraction_total_reads"
+    )?;
+    for e in estimates {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.5}",
+            e.name, e.taxid, level, e.kraken_reads, e.added_reads, e.new_reads, e.fraction
+        )?;
+    }
+    writer.flush()
+}
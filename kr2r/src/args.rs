@@ -1,17 +1,41 @@
 // 使用时需要引用模块路径
-use crate::utils::expand_spaced_seed_mask;
+#[cfg(feature = "cli")]
+use crate::compact_hash::LOOKUP_CACHE_ENTRIES;
+#[cfg(feature = "cli")]
+use crate::domains::QcGate;
+use crate::registry::resolve_database;
+#[cfg(feature = "cli")]
+use crate::stats::{NameNormalization, DEFAULT_LENGTH_BIN_SIZE};
+use crate::utils::last_level_cache_size;
+#[cfg(feature = "cli")]
+use crate::utils::{expand_spaced_seed_mask, low_power_threads, lower_priority, LOW_POWER_NICE};
+#[cfg(feature = "cli")]
 use crate::{construct_seed_template, parse_binary};
+#[cfg(feature = "cli")]
 use clap::Parser;
-use seqkmer::Meros;
+#[cfg(feature = "cli")]
+use regex::Regex;
+use seqkmer::Threads;
+#[cfg(feature = "cli")]
 use seqkmer::{
-    BITS_PER_CHAR, DEFAULT_KMER_LENGTH, DEFAULT_MINIMIZER_LENGTH, DEFAULT_MINIMIZER_SPACES,
-    DEFAULT_TOGGLE_MASK,
+    Alphabet, Dust, Meros, PairCheck, PairMode, QualityEncoding, DEFAULT_DUST_THRESHOLD,
 };
-use std::path::PathBuf;
+#[cfg(feature = "cli")]
+use seqkmer::{
+    DEFAULT_KMER_LENGTH, DEFAULT_MINIMIZER_LENGTH, DEFAULT_MINIMIZER_SPACES,
+    DEFAULT_PROTEIN_KMER_LENGTH, DEFAULT_PROTEIN_MINIMIZER_LENGTH,
+    DEFAULT_PROTEIN_MINIMIZER_SPACES, DEFAULT_TOGGLE_MASK,
+};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+#[cfg(feature = "cli")]
+use tracing::warn;
 
 pub const U32MAXPLUS: u64 = u32::MAX as u64;
 pub const ONEGB: u64 = 1073741824;
 
+#[cfg(feature = "cli")]
 #[derive(Parser, Debug, Clone)]
 #[clap(version, about = "build database")]
 pub struct Build {
@@ -35,8 +59,13 @@ pub struct Build {
     pub threads: usize,
 }
 
+#[cfg(feature = "cli")]
 const BUFFER_SIZE: usize = 16 * 1024 * 1024;
 
+/// `--low-power` 时 annotate 每批次最多读取的 k-mer 单元数
+#[cfg(feature = "cli")]
+const LOW_POWER_BUFFER_SIZE: usize = 1024 * 1024;
+
 /// Command line arguments for the classify program.
 ///
 /// This structure defines the command line arguments that are accepted by the classify program.
@@ -44,6 +73,7 @@ const BUFFER_SIZE: usize = 16 * 1024 * 1024;
 /// combines the functionality of the 'splitr', 'annotate', and 'resolve' commands into a single workflow.
 /// This command streamlines the process of splitting fast(q/a) files, annotating sequences, and resolving the taxonomy tree,
 /// providing a comprehensive solution for sequence classification.
+#[cfg(feature = "cli")]
 #[derive(Parser, Debug, Clone)]
 #[clap(
     version,
@@ -51,8 +81,8 @@ const BUFFER_SIZE: usize = 16 * 1024 * 1024;
     long_about = "classify a set of sequences"
 )]
 pub struct ClassifyArgs {
-    /// database hash chunk directory and other files
-    #[arg(long = "db", required = true)]
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
     pub database: PathBuf,
 
     /// chunk directory
@@ -71,21 +101,85 @@ pub struct ClassifyArgs {
     #[clap(short = 'S', long = "single-file-pairs", action)]
     pub single_file_pairs: bool,
 
-    /// Minimum quality score for FASTQ data.
+    /// Treat every input file as single-end. Without -P or -S, FASTQ files named as R1/R2 mates
+    /// (e.g. x_R1_001.fastq.gz and x_R2_001.fastq.gz) are paired automatically
+    #[clap(long, value_parser, default_value_t = false)]
+    pub unpaired: bool,
+
+    /// Mate validation for paired reads: "strict" stops on mismatched read ids or read counts,
+    /// "repair" drops reads without a mate and reports the counts, "off" pairs reads in order.
+    #[clap(long, value_parser, default_value = "strict")]
+    pub pair_check: PairCheck,
+
+    /// Scoring of paired reads: "range" scores the mates as two ranges of one read, "concat" joins them with an N spacer into one sequence like old Kraken versions.
+    #[clap(long, value_parser, default_value = "range")]
+    pub pair_mode: PairMode,
+
+    /// Minimum quality score for FASTQ data, lower bases are masked as ambiguous before minimizer extraction
     #[clap(
         short = 'Q',
         long = "minimum-quality-score",
+        visible_alias = "minimum-base-quality",
         value_parser,
         default_value_t = 0
     )]
     pub minimum_quality_score: i32,
 
+    /// FASTQ quality encoding used by -Q and the quality statistics: auto, phred33, phred64 or solexa.
+    /// "auto" detects it from the first reads of each sample.
+    #[clap(long, value_parser, default_value = "auto")]
+    pub quality_encoding: QualityEncoding,
+
+    /// Only classify unmapped reads of BAM/CRAM inputs (both mates unmapped with -P -S),
+    /// e.g. the reads left after aligning to a host genome.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub unmapped_only: bool,
+
+    /// Reference FASTA (with a .fai index) used to decode CRAM inputs that contain mapped reads.
+    #[clap(long, value_parser)]
+    pub cram_reference: Option<PathBuf>,
+
     /// The number of threads to use.
     #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
     pub num_threads: usize,
 
-    #[clap(long, default_value_t = BUFFER_SIZE)]
-    pub buffer_size: usize,
+    /// Threads that process the reads of each stage (minimizers, hash table lookups, classification)
+    /// [default: --num-threads minus --io-threads]
+    #[clap(long, value_parser)]
+    pub compute_threads: Option<usize>,
+
+    /// Threads that read the input and write the output of each stage, in a pool of their own so
+    /// busy compute threads don't hold up the writer; at least 2 (one reader, one writer)
+    #[clap(long, value_parser = parse_io_threads, default_value_t = 2)]
+    pub io_threads: usize,
+
+    /// Threads used to decompress each input file: BGZF (bgzip) files are decompressed in parallel, other compressed files in a separate reader thread. 1 decompresses inline
+    #[clap(long, value_parser, default_value_t = 4)]
+    pub decompress_threads: usize,
+
+    /// Memory-map taxo.k2d and the hash_*.k2d files instead of reading them into memory, so concurrent runs on one node share the page cache
+    #[clap(long, value_parser, default_value_t = false)]
+    pub memory_mapping: bool,
+
+    /// Keep the hash_*.k2d files in shared memory (/dev/shm) after the run, so later runs on the
+    /// node map them without reading the database again (implies --memory-mapping).
+    /// Remove /dev/shm/kraken2_rs-* to free the memory
+    #[clap(long, value_parser, default_value_t = false)]
+    pub shm_cache: bool,
+
+    /// Skip checking the hash_*.k2d files against the checksums stored in hash_config.k2d
+    /// while loading them (checking reads every shard once more with --memory-mapping)
+    #[clap(long, value_parser, default_value_t = false)]
+    pub no_verify: bool,
+
+    /// Number of k-mer slots read from a chunk file per batch during annotation, or "auto" to pick it from the chunk size and CPU cache
+    #[clap(long, default_value_t = BatchSize::Fixed(BUFFER_SIZE))]
+    pub buffer_size: BatchSize,
+
+    /// Entries of the per-batch cache of hash table lookups during annotation, so minimizers
+    /// repeated across the reads of a batch (high-coverage or amplicon data) are looked up once; 0 disables it
+    #[clap(long, value_parser, default_value_t = LOOKUP_CACHE_ENTRIES)]
+    pub lookup_cache: usize,
 
     /// The size of each batch for processing taxid match results, used to control memory usage
     #[clap(long, value_parser = clap::value_parser!(u32).range(1..=32), default_value_t = 4)]
@@ -109,6 +203,15 @@ pub struct ClassifyArgs {
     )]
     pub minimum_hit_groups: usize,
 
+    /// Cap all calls at this rank (e.g. genus), more specific calls are reported at their ancestor of this rank
+    #[clap(long, value_parser = parse_max_rank)]
+    pub max_rank: Option<String>,
+
+    /// Call the most specific clade holding at least this fraction of the hit k-mers (0-1),
+    /// instead of the default root-to-leaf scoring; 1.0 gives the LCA of all hits
+    #[clap(long, value_parser = parse_fraction)]
+    pub min_clade_support: Option<f64>,
+
     /// In comb. w/ -R, provide minimizer information in report
     #[clap(short = 'K', long, value_parser, default_value_t = false)]
     pub report_kmer_data: bool,
@@ -117,32 +220,202 @@ pub struct ClassifyArgs {
     #[clap(short = 'z', long, value_parser, default_value_t = false)]
     pub report_zero_counts: bool,
 
-    // /// output file contains all unclassified sequence
-    // #[clap(long, value_parser, default_value_t = false)]
-    // pub full_output: bool,
+    /// Start each kreport with "#" comment lines: sample, database and its hash, command line, version and date
+    #[clap(long, value_parser, default_value_t = false)]
+    pub report_headers: bool,
+
+    /// Write per-taxon read-length histograms (output_*.lenstats), requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub read_length_stats: bool,
+
+    /// Bin width used by the read-length histograms
+    #[clap(long, value_parser, default_value_t = DEFAULT_LENGTH_BIN_SIZE)]
+    pub length_bin_size: usize,
+
+    /// Write per-taxon mean GC and base quality (output_*.qc), requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub qc_stats: bool,
+
+    /// Write Krona text and HTML charts (output_*.krona.txt/.html), requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub report_krona: bool,
+
+    /// Stop early once enough reads are assigned to a taxon (or its descendants),
+    /// e.g. "taxid=2697049 reads>=10"; the reports then count only the reads classified before the stop.
+    /// The rule is checked in the resolve stage, after splitr and annotate have processed every read
+    #[clap(long, value_parser = parse_stop_when)]
+    pub stop_when: Option<StopWhen>,
+
+    /// Mark each sample pass/fail in the summary and run_summary.json, e.g.
+    /// "reads>=100000,classified>=50,host<=10" (reads, classified, host or a domain name)
+    #[clap(long, value_parser)]
+    pub qc_gate: Option<QcGate>,
+
+    /// Exit with status 7 after writing all outputs when a sample fails --qc-gate
+    #[clap(long, value_parser, default_value_t = false)]
+    pub qc_fail_exit: bool,
+
+    /// Print scientific names instead of just taxids in the output, e.g. "name (taxid N)"
+    #[clap(long, value_parser, default_value_t = false)]
+    pub use_names: bool,
+
+    /// Taxid written for unclassified reads in the output and the kreports, e.g. a sentinel
+    /// node such as 12908 ("unclassified sequences") for schemas without taxid 0
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub unclassified_taxid: u64,
+
+    /// Name written for unclassified reads in the output (--use-names, json) and the kreports
+    #[clap(long, value_parser, default_value = "unclassified")]
+    pub unclassified_label: String,
+
+    /// Normalize taxon names in the output and the reports: "none", "nfc" (Unicode NFC, so names
+    /// from different sources compare equal) or "ascii" (accents removed, other non-ASCII as '?').
+    /// Tabs, newlines and other control characters in names are always written as \t, \n, \u{..}
+    #[clap(long, value_parser, default_value = "none")]
+    pub name_normalization: NameNormalization,
+
+    /// Append the number of distinct minimizers hit and the minimizer hits of each taxon
+    /// ("taxid:count ...", "-" for none) to every line of the per-read output
+    #[clap(long, value_parser, default_value_t = false)]
+    pub read_minimizer_data: bool,
+
+    /// Per-read output format: "kraken" (tab-separated lines, output_*.txt) or "json"
+    /// (one JSON record per read, output_*.ndjson)
+    #[clap(long, value_parser, default_value = "kraken")]
+    pub output_format: OutputFormat,
+
+    /// Compress the per-read output, the kreports and the --classified-out/--unclassified-out
+    /// files: "none", "gzip" (adds .gz to the file names), "zstd" (adds .zst) or "bgzf" (adds
+    /// .gz and indexes the per-read output by read id for `lookup-reads`)
+    #[clap(long, value_parser, default_value = "none")]
+    pub compress_output: OutputCompression,
+
+    /// Don't write the per-read classification lines (output_*.txt or stdout), only the
+    /// reports and summaries
+    #[clap(long, value_parser, default_value_t = false)]
+    pub no_standard_output: bool,
+
+    /// Also write the classified segments of each read (read, start, end, taxid, score) as
+    /// BED to output_*.bed, for long reads and contigs in genome browsers; requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub segments_bed: bool,
+
+    /// Split the per-read output of each sample into numbered parts of about this size
+    /// (uncompressed, e.g. '10G'), with an output_*.parts.tsv index of the reads in each part
+    #[clap(long, value_parser = parse_size)]
+    pub split_output_size: Option<usize>,
+
+    /// Name the output files of each sample after its --sample-sheet or --sample-regex name
+    /// instead of output_<N>. Names are made safe for all file systems and unique, and
+    /// sample_names.tsv in the output directory maps them
+    #[clap(long, value_parser, default_value_t = false)]
+    pub name_outputs: bool,
+
+    /// Write classified reads to this file, with " kraken:taxid|N" appended to the header.
+    /// For paired reads the name must contain '#', which is replaced by "_1" and "_2".
+    #[clap(long, value_parser)]
+    pub classified_out: Option<String>,
+
+    /// Write unclassified reads to this file ('#' is substituted as for --classified-out).
+    #[clap(long, value_parser)]
+    pub unclassified_out: Option<String>,
+
+    /// After resolve, reclassify genus-level reads against species-level sub-databases
+    /// of their genus (requires --output-dir)
+    #[clap(long, value_parser, default_value_t = false)]
+    pub downsweep: bool,
+
+    /// Route reads to separate samples by a regex over the read id (e.g. run, lane or barcode
+    /// fields). The named group `sample`, else the first group, else the whole match names the
+    /// sample; reads that do not match stay in the sample of their input file.
+    #[clap(long, value_parser)]
+    pub sample_regex: Option<Regex>,
+
+    /// Merge all sequence files found in one directory or tarball input into a single sample
+    /// (R1 and R2 separately with -P); otherwise every file (or pair) is its own sample.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub merge_inputs: bool,
+
+    /// Fail when the chunk files are not numbered continuously (e.g. after a partial rerun);
+    /// by default the files present are processed and the missing ones are listed at the end
+    #[clap(long, value_parser, default_value_t = false)]
+    pub strict_chunks: bool,
+
+    /// Fail (exit status 4) when a sample_file_*.bin has hits of a read id that is missing from its
+    /// sample_id_*.map; by default those hits are dropped and counted per chunk in run_summary.json
+    #[clap(long, value_parser, default_value_t = false)]
+    pub strict_ids: bool,
+
+    /// `shard-worker` addresses (host:port, comma separated) to send the chunks to during annotation
+    /// instead of loading the hash_*.k2d shards here; each chunk goes to a worker that holds its shard
+    #[clap(long, value_parser, value_delimiter = ',')]
+    pub workers: Vec<String>,
+
+    /// Tab-separated sample sheet with one sample per line: name, R1 and (for paired-end data) R2.
+    /// Rows with the same name (e.g. lanes) are merged; the names are written to sample_file.txt
+    #[clap(long, value_parser, conflicts_with = "input_files")]
+    pub sample_sheet: Option<PathBuf>,
+
+    /// Trade speed for a responsive machine, e.g. a laptop next to a portable sequencer: use at
+    /// most a quarter of the CPUs, smaller annotation batches, decompress inline, read the
+    /// resolve inputs one at a time instead of ahead, and run at a lower CPU priority
+    #[clap(long, value_parser, default_value_t = false)]
+    pub low_power: bool,
+
+    /// Also write the reads without any database hit to the output, as "U" lines like Kraken 2;
+    /// by default only the reads with hits are written (the reports count all reads either way)
+    #[clap(long, value_parser, default_value_t = false)]
+    pub full_output: bool,
+
     /// A list of input file paths (FASTA/FASTQ) to be processed by the classify program.
-    /// Supports fasta or fastq format files (e.g., .fasta, .fastq) and gzip compressed files (e.g., .fasta.gz, .fastq.gz).
+    /// Supports fasta or fastq format files (e.g., .fasta, .fastq) and gzip, zstd, bzip2 or xz compressed files (e.g., .fasta.gz, .fastq.zst, .fq.bz2, .fq.xz).
+    /// Directories and tarballs (.tar, .tar.gz, .tgz) are expanded to the sequence files they contain, and - reads from standard input.
     // #[clap(short = 'F', long = "files")]
     pub input_files: Vec<String>,
 }
 
+#[cfg(feature = "cli")]
+impl ClassifyArgs {
+    /// `--low-power`: 减少线程数与批次大小, 不在后台解压, 并降低进程优先级
+    pub fn apply_low_power(&mut self) {
+        if !self.low_power {
+            return;
+        }
+        self.num_threads = low_power_threads(self.num_threads);
+        self.decompress_threads = 1;
+        self.buffer_size = match self.buffer_size {
+            BatchSize::Fixed(size) => BatchSize::Fixed(size.min(LOW_POWER_BUFFER_SIZE)),
+            BatchSize::Auto => BatchSize::Fixed(LOW_POWER_BUFFER_SIZE),
+        };
+        if let Err(e) = lower_priority(LOW_POWER_NICE) {
+            warn!("failed to lower the process priority: {}", e);
+        }
+    }
+}
+
+#[cfg(feature = "cli")]
 #[derive(Parser, Debug, Clone, Copy)]
 #[clap(version, about = "k-mer")]
 pub struct KLMTArgs {
-    /// Set length of k-mers, k must be positive integer, k=35, k cannot be less than l
-    #[clap(short, long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = DEFAULT_KMER_LENGTH)]
-    pub k_mer: u64,
+    /// Build a protein database from amino acid reference sequences; reads are classified against it by 6-frame translation
+    #[cfg(feature = "protein")]
+    #[clap(long, value_parser, default_value_t = false)]
+    pub protein: bool,
 
-    /// Set length of minimizers, 1 <= l <= 31
-    #[clap(short, long, value_parser = clap::value_parser!(u8).range(1..=31), default_value_t = DEFAULT_MINIMIZER_LENGTH)]
-    pub l_mer: u8,
+    /// Set length of k-mers, k must be positive integer, k cannot be less than l [default: 35, 15 with --protein]
+    #[clap(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub k_mer: Option<u64>,
+
+    /// Set length of minimizers, 1 <= l <= 31 (15 with --protein) [default: 31, 12 with --protein]
+    #[clap(short, long, value_parser = clap::value_parser!(u8).range(1..=31))]
+    pub l_mer: Option<u8>,
 
     // /// Spaced seed mask
     // #[clap(short = 'S', long, default_value= "0", value_parser = parse_binary)]
     // spaced_seed_mask: u64,
-    /// Number of characters in minimizer that are ignored in comparisons
-    #[clap(long, default_value_t = DEFAULT_MINIMIZER_SPACES)]
-    pub minimizer_spaces: u8,
+    /// Number of characters in minimizer that are ignored in comparisons [default: 7, 0 with --protein]
+    #[clap(long)]
+    pub minimizer_spaces: Option<u8>,
 
     /// Minimizer ordering toggle mask
     #[clap(short = 'T', long, default_value_t = DEFAULT_TOGGLE_MASK)]
@@ -150,17 +423,68 @@ pub struct KLMTArgs {
 
     #[clap(long)]
     pub min_clear_hash_value: Option<u64>,
+
+    /// Do not mask low-complexity regions of the reference sequences with DUST before extracting minimizers
+    #[clap(long, value_parser, default_value_t = false)]
+    pub no_masking: bool,
+
+    /// DUST score threshold for masking low-complexity regions, higher values mask less (like dustmasker -level)
+    #[clap(long, default_value_t = DEFAULT_DUST_THRESHOLD)]
+    pub masker_threshold: u32,
 }
 
+#[cfg(feature = "cli")]
 impl KLMTArgs {
+    /// `--protein`, 没有 `protein` feature 时总是构建核苷酸数据库
+    pub fn is_protein(&self) -> bool {
+        #[cfg(feature = "protein")]
+        let protein = self.protein;
+        #[cfg(not(feature = "protein"))]
+        let protein = false;
+        protein
+    }
+
+    pub fn alphabet(&self) -> Alphabet {
+        if self.is_protein() {
+            Alphabet::Protein
+        } else {
+            Alphabet::Dna
+        }
+    }
+
+    /// 构建核苷酸数据库时屏蔽低复杂度区域的 DUST, 蛋白数据库不屏蔽
+    pub fn masker(&self) -> Option<Dust> {
+        (!self.no_masking && !self.is_protein()).then(|| Dust::new(self.masker_threshold))
+    }
+
     pub fn as_meros(&self) -> Meros {
-        let seed = construct_seed_template(self.l_mer as usize, self.minimizer_spaces as usize);
+        let (k_mer, l_mer, minimizer_spaces) = if self.is_protein() {
+            (
+                DEFAULT_PROTEIN_KMER_LENGTH,
+                DEFAULT_PROTEIN_MINIMIZER_LENGTH,
+                DEFAULT_PROTEIN_MINIMIZER_SPACES,
+            )
+        } else {
+            (
+                DEFAULT_KMER_LENGTH,
+                DEFAULT_MINIMIZER_LENGTH,
+                DEFAULT_MINIMIZER_SPACES,
+            )
+        };
+        let k_mer = self.k_mer.unwrap_or(k_mer) as usize;
+        let l_mer = self.l_mer.unwrap_or(l_mer) as usize;
+        let minimizer_spaces = self.minimizer_spaces.unwrap_or(minimizer_spaces) as usize;
+        let alphabet = self.alphabet();
+
+        let seed = construct_seed_template(l_mer, minimizer_spaces);
         let space_seed_mask = parse_binary(&seed).unwrap();
-        let space_seed_mask = expand_spaced_seed_mask(space_seed_mask, BITS_PER_CHAR as u64);
+        let space_seed_mask =
+            expand_spaced_seed_mask(space_seed_mask, alphabet.bits_per_char() as u64);
 
-        Meros::new(
-            self.k_mer as usize,
-            self.l_mer as usize,
+        Meros::with_alphabet(
+            alphabet,
+            k_mer,
+            l_mer,
             Some(space_seed_mask),
             Some(self.toggle_mask),
             self.min_clear_hash_value,
@@ -183,3 +507,406 @@ pub fn parse_size(s: &str) -> Result<usize, String> {
         _ => Err("Invalid size suffix. Use 'G', 'M', or 'K'".to_string()),
     }
 }
+
+/// 自动选择时每批次的最小字节数, 无法读取 CPU 缓存大小时也使用该值
+const MIN_AUTO_BATCH_BYTES: usize = 8 * 1024 * 1024;
+/// 自动选择时每批次的最大字节数
+const MAX_AUTO_BATCH_BYTES: usize = 256 * 1024 * 1024;
+/// 自动选择时每个文件大约分成的批次数
+const AUTO_BATCHES_PER_FILE: u64 = 16;
+
+/// 读取 chunk 文件时每批次的单元数, `auto` 时按文件大小与 CPU 缓存选择
+///
+/// ```
+/// use kraken2_rs::args::BatchSize;
+///
+/// assert_eq!("81920".parse(), Ok(BatchSize::Fixed(81920)));
+/// assert_eq!("auto".parse(), Ok(BatchSize::Auto));
+/// assert!("0".parse::<BatchSize>().is_err());
+/// assert_eq!(BatchSize::Fixed(81920).resolve(1 << 30, 8), 81920);
+/// // 自动选择时不超过文件本身的单元数
+/// assert_eq!(BatchSize::Auto.resolve(800, 8), 100);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchSize {
+    Auto,
+    Fixed(usize),
+}
+
+impl BatchSize {
+    /// 每批次的单元数, `file_len` 为文件的字节数, `slot_size` 为每个单元的字节数
+    ///
+    /// 自动选择时每批次至少为最后一级缓存的大小, 使各线程分到的单元足以抵消调度的开销;
+    /// 大文件的批次相应增大, 每个文件大约读取 16 次, 减少网络存储上的请求次数
+    pub fn resolve(&self, file_len: u64, slot_size: usize) -> usize {
+        match self {
+            BatchSize::Fixed(size) => *size,
+            BatchSize::Auto => {
+                let min_bytes = last_level_cache_size()
+                    .unwrap_or(MIN_AUTO_BATCH_BYTES)
+                    .clamp(MIN_AUTO_BATCH_BYTES, MAX_AUTO_BATCH_BYTES);
+                let bytes = ((file_len / AUTO_BATCHES_PER_FILE) as usize)
+                    .clamp(min_bytes, MAX_AUTO_BATCH_BYTES)
+                    .min(file_len as usize);
+                (bytes / slot_size).max(1)
+            }
+        }
+    }
+}
+
+impl FromStr for BatchSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(BatchSize::Auto);
+        }
+        match s.parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!(
+                "invalid batch size '{}', expected a positive number or 'auto'",
+                s
+            )),
+            Ok(size) => Ok(BatchSize::Fixed(size)),
+        }
+    }
+}
+
+impl fmt::Display for BatchSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchSize::Auto => write!(f, "auto"),
+            BatchSize::Fixed(size) => write!(f, "{}", size),
+        }
+    }
+}
+
+/// 自动选择时每个 shard 最多的单元数 (4GB)
+pub const MAX_AUTO_HASH_CAPACITY: usize = ONEGB as usize;
+
+/// `build --hash-capacity`: 每个 shard 的单元数, `auto` 时按估计的哈希表容量选择
+///
+/// ```
+/// use kraken2_rs::args::HashCapacity;
+///
+/// assert_eq!("auto".parse(), Ok(HashCapacity::Auto));
+/// assert_eq!("1M".parse(), Ok(HashCapacity::Fixed(1 << 20)));
+/// // 小数据库只有一个 shard
+/// assert_eq!(HashCapacity::Auto.resolve(30_000), 30_000);
+/// // 大数据库分成大小相同的 shard, 每个不超过 1G 个单元
+/// assert_eq!(HashCapacity::Auto.resolve(3 << 29), 3 << 28);
+/// assert_eq!(HashCapacity::Fixed(1 << 20).resolve(30_000), 1 << 20);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashCapacity {
+    Auto,
+    Fixed(usize),
+}
+
+impl HashCapacity {
+    /// 容量为 `capacity` 的哈希表中每个 shard 的单元数.
+    ///
+    /// 自动选择时 shard 数为最少的不超过 [`MAX_AUTO_HASH_CAPACITY`] 的个数, 各 shard 大小相同,
+    /// 不会留下很小的最后一个 shard
+    pub fn resolve(&self, capacity: usize) -> usize {
+        match self {
+            HashCapacity::Fixed(size) => *size,
+            HashCapacity::Auto => {
+                let partition = capacity.div_ceil(MAX_AUTO_HASH_CAPACITY).max(1);
+                capacity.div_ceil(partition).max(1)
+            }
+        }
+    }
+}
+
+impl FromStr for HashCapacity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(HashCapacity::Auto);
+        }
+        match parse_size(s) {
+            Ok(0) => Err("hash capacity must be greater than 0".to_string()),
+            Ok(size) => Ok(HashCapacity::Fixed(size)),
+            Err(e) => Err(format!("{}, or 'auto'", e)),
+        }
+    }
+}
+
+impl fmt::Display for HashCapacity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashCapacity::Auto => write!(f, "auto"),
+            HashCapacity::Fixed(size) => write!(f, "{}", size),
+        }
+    }
+}
+
+/// `--db` 的数据库目录, 不存在时按名称在数据库注册表 (`kraken2_rs db add`) 中查找
+pub fn parse_database(s: &str) -> Result<PathBuf, String> {
+    resolve_database(s).map_err(|e| e.to_string())
+}
+
+/// 解析 0 到 1 之间 (不含 0) 的比例
+///
+/// ```
+/// use kraken2_rs::args::parse_fraction;
+///
+/// assert_eq!(parse_fraction("0.8"), Ok(0.8));
+/// assert!(parse_fraction("0").is_err());
+/// assert!(parse_fraction("80").is_err());
+/// ```
+pub fn parse_fraction(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("invalid number '{}'", s))?;
+    if value > 0.0 && value <= 1.0 {
+        Ok(value)
+    } else {
+        Err(format!("'{}' must be greater than 0 and at most 1", s))
+    }
+}
+
+/// 解析 `--io-threads`: 读取与写出各需要一个线程
+///
+/// ```
+/// use kraken2_rs::args::parse_io_threads;
+///
+/// assert_eq!(parse_io_threads("4"), Ok(4));
+/// assert!(parse_io_threads("1").is_err());
+/// ```
+pub fn parse_io_threads(s: &str) -> Result<usize, String> {
+    let threads: usize = s.parse().map_err(|_| format!("invalid number '{}'", s))?;
+    if threads < 2 {
+        return Err("at least 2 I/O threads are needed, one to read and one to write".to_string());
+    }
+    Ok(threads)
+}
+
+/// 一个阶段的线程划分: 没有给出 `--compute-threads` 时, 处理线程为 `-p` 减去 `--io-threads`, 至少 1 个
+///
+/// ```
+/// use kraken2_rs::args::stage_threads;
+/// use seqkmer::Threads;
+///
+/// assert_eq!(stage_threads(16, None, 2), Threads::new(14, 2));
+/// assert_eq!(stage_threads(16, Some(8), 3), Threads::new(8, 3));
+/// assert_eq!(stage_threads(1, None, 2), Threads::new(1, 2));
+/// ```
+pub fn stage_threads(
+    num_threads: usize,
+    compute_threads: Option<usize>,
+    io_threads: usize,
+) -> Threads {
+    Threads::new(
+        compute_threads.unwrap_or(num_threads.saturating_sub(io_threads)),
+        io_threads,
+    )
+}
+
+/// 检查 `--compute-threads` 与 `--io-threads` 的和不超过 `-p`. 只给出 `--io-threads` 时需要留下至少
+/// 1 个处理线程; `-p` 小于 3 时照常使用 1 个处理线程, 不算错误
+///
+/// ```
+/// use kraken2_rs::args::check_stage_threads;
+///
+/// assert!(check_stage_threads(16, Some(14), 2).is_ok());
+/// assert!(check_stage_threads(16, None, 4).is_ok());
+/// assert!(check_stage_threads(2, None, 2).is_ok());
+/// assert!(check_stage_threads(16, Some(14), 4).is_err());
+/// assert!(check_stage_threads(8, None, 8).is_err());
+/// ```
+pub fn check_stage_threads(
+    num_threads: usize,
+    compute_threads: Option<usize>,
+    io_threads: usize,
+) -> std::io::Result<()> {
+    let message = match compute_threads {
+        Some(compute) if compute + io_threads > num_threads => format!(
+            "--compute-threads {} and --io-threads {} need {} threads, more than --num-threads {}",
+            compute,
+            io_threads,
+            compute + io_threads,
+            num_threads
+        ),
+        None if num_threads > 2 && io_threads >= num_threads => format!(
+            "--io-threads {} leaves no compute thread of --num-threads {}",
+            io_threads, num_threads
+        ),
+        _ => return Ok(()),
+    };
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        message,
+    ))
+}
+
+/// 解析 `--max-rank`, 接受层级名称或 kreport 中的层级代码 (D, K, P, C, O, F, G, S)
+///
+/// ```
+/// use kraken2_rs::args::parse_max_rank;
+///
+/// assert_eq!(parse_max_rank("Genus").unwrap(), "genus");
+/// assert_eq!(parse_max_rank("G").unwrap(), "genus");
+/// assert!(parse_max_rank("strain").is_err());
+/// ```
+pub fn parse_max_rank(s: &str) -> Result<String, String> {
+    let rank = match s {
+        "D" => "superkingdom".to_string(),
+        "K" => "kingdom".to_string(),
+        "P" => "phylum".to_string(),
+        "C" => "class".to_string(),
+        "O" => "order".to_string(),
+        "F" => "family".to_string(),
+        "G" => "genus".to_string(),
+        "S" => "species".to_string(),
+        _ => s.to_lowercase(),
+    };
+    match crate::taxonomy::rank_level(&rank) {
+        Some(_) => Ok(rank),
+        None => Err(format!(
+            "invalid rank '{}', expected one of domain, kingdom, phylum, class, order, family, genus or species",
+            s
+        )),
+    }
+}
+
+/// 每条 read 的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// 与 kraken 2 相同的 tab 分隔格式
+    #[default]
+    Kraken,
+    /// 每行一个 JSON 对象 (NDJSON)
+    Json,
+}
+
+impl OutputFormat {
+    /// 输出文件的扩展名
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Kraken => "txt",
+            OutputFormat::Json => "ndjson",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kraken" => Ok(OutputFormat::Kraken),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "invalid output format '{}', expected kraken or json",
+                s
+            )),
+        }
+    }
+}
+
+/// `--compress-output` 输出文件的压缩格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputCompression {
+    /// 不压缩
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+    /// 分块的 gzip (bgzip), 逐条 read 的输出同时写出 read id 索引, 可以随机读取
+    Bgzf,
+}
+
+impl OutputCompression {
+    /// 压缩文件名在原扩展名后追加的后缀
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputCompression::None => "",
+            OutputCompression::Gzip | OutputCompression::Bgzf => ".gz",
+            OutputCompression::Zstd => ".zst",
+        }
+    }
+
+    /// 在路径后追加压缩后缀
+    ///
+    /// ```
+    /// use kraken2_rs::args::OutputCompression;
+    /// use std::path::PathBuf;
+    ///
+    /// let path = PathBuf::from("out/output_1.txt");
+    /// assert_eq!(OutputCompression::Zstd.compressed_path(&path), PathBuf::from("out/output_1.txt.zst"));
+    /// assert_eq!(OutputCompression::None.compressed_path(&path), path);
+    /// ```
+    pub fn compressed_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let mut path = path.as_ref().as_os_str().to_owned();
+        path.push(self.extension());
+        PathBuf::from(path)
+    }
+
+    /// 由文件名的后缀 (.gz, .zst) 判断压缩格式
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => OutputCompression::Gzip,
+            Some("zst") => OutputCompression::Zstd,
+            _ => OutputCompression::None,
+        }
+    }
+}
+
+impl FromStr for OutputCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(OutputCompression::None),
+            "gzip" | "gz" => Ok(OutputCompression::Gzip),
+            "zstd" | "zst" => Ok(OutputCompression::Zstd),
+            "bgzf" | "bgzip" => Ok(OutputCompression::Bgzf),
+            _ => Err(format!(
+                "invalid output compression '{}', expected none, gzip, zstd or bgzf",
+                s
+            )),
+        }
+    }
+}
+
+/// `--stop-when` 提前结束条件: 分类到 taxid (含子节点) 的 reads 数达到阈值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StopWhen {
+    pub taxid: u64,
+    pub reads: u64,
+}
+
+/// 解析 `taxid=X reads>=N`, 两个条件之间可以用空格或逗号分隔
+///
+/// ```
+/// use kraken2_rs::args::{parse_stop_when, StopWhen};
+///
+/// let rule = parse_stop_when("taxid=2697049 reads>=10").unwrap();
+/// assert_eq!(rule, StopWhen { taxid: 2697049, reads: 10 });
+/// assert!(parse_stop_when("taxid=2697049").is_err());
+/// ```
+pub fn parse_stop_when(s: &str) -> Result<StopWhen, String> {
+    let mut taxid = None;
+    let mut reads = None;
+    for part in s.split([' ', ',']).filter(|p| !p.is_empty()) {
+        if let Some(value) = part.strip_prefix("taxid=") {
+            taxid = Some(
+                value
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid taxid".to_string())?,
+            );
+        } else if let Some(value) = part.strip_prefix("reads>=") {
+            reads = Some(
+                value
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid reads threshold".to_string())?,
+            );
+        } else {
+            return Err(format!("Unknown stop condition '{}'", part));
+        }
+    }
+    match (taxid, reads) {
+        (Some(taxid), Some(reads)) => Ok(StopWhen { taxid, reads }),
+        _ => Err("Expected 'taxid=X reads>=N'".to_string()),
+    }
+}
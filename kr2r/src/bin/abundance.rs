@@ -0,0 +1,113 @@
+use clap::Parser;
+use kraken2_rs::abundance::{
+    estimate_abundance, kmer_distrib_path, read_kreport, write_abundance, KmerDistrib,
+};
+use kraken2_rs::args::parse_database;
+use kraken2_rs::taxonomy::Taxonomy;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Re-estimate species/genus abundance from a kreport2 (Bracken-style)",
+    long_about = "Redistribute reads assigned above the requested level down to that level using the read distribution of the reference genomes (database<READ_LEN>mers.kmer_distrib)"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// kreport2 produced by classify or direct.
+    #[clap(short = 'i', long, value_parser, required = true)]
+    pub input: PathBuf,
+
+    /// Output abundance table, defaults to the input with a `.bracken` extension.
+    #[clap(short = 'o', long, value_parser)]
+    pub output: Option<PathBuf>,
+
+    /// Read length used to build the kmer distribution file.
+    #[clap(short = 'r', long, value_parser, default_value_t = 100)]
+    pub read_len: usize,
+
+    /// Taxonomic level to estimate (D, P, C, O, F, G or S).
+    #[clap(short = 'l', long, value_parser, default_value = "S")]
+    pub level: String,
+
+    /// Minimum number of reads required for a taxon at the level.
+    #[clap(short = 't', long, value_parser, default_value_t = 10)]
+    pub threshold: u64,
+
+    /// Build the kmer distribution file first if it does not exist.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub build_distrib: bool,
+
+    /// The number of threads to use when building the kmer distribution file.
+    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    pub num_threads: usize,
+}
+
+/// 构建数据库时调用, 生成 `database{read_len}mers.kmer_distrib`
+pub fn build_distrib(database: &Path, read_len: usize, threads: usize) -> Result<()> {
+    let start = Instant::now();
+    info!("build kmer distribution for read length {}...", read_len);
+    let distrib = KmerDistrib::build(database, read_len, 1, threads)?;
+    let filename = kmer_distrib_path(database, read_len);
+    distrib.write_to_file(&filename)?;
+    info!(
+        "kmer distribution written to {:?}, took: {:?}",
+        filename,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let distrib_file = kmer_distrib_path(&args.database, args.read_len);
+    if !distrib_file.exists() {
+        if !args.build_distrib {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "{:?} not found, rerun with --build-distrib or build the database with --bracken-read-len {}",
+                    distrib_file, args.read_len
+                ),
+            ));
+        }
+        build_distrib(&args.database, args.read_len, args.num_threads)?;
+    }
+
+    let taxo = Taxonomy::from_file(args.database.join("taxo.k2d"))?;
+    let distrib = KmerDistrib::from_file(&distrib_file)?;
+    let report = read_kreport(&args.input)?;
+    let estimates = estimate_abundance(&report, &distrib, &taxo, &args.level, args.threshold)?;
+
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| args.input.with_extension("bracken"));
+    write_abundance(&output, &args.level, &estimates)?;
+
+    let kraken_reads: u64 = estimates.iter().map(|e| e.kraken_reads).sum();
+    let added_reads: u64 = estimates.iter().map(|e| e.added_reads).sum();
+    info!(
+        "{} taxa at level {}: {} reads assigned by kraken, {} reads redistributed",
+        estimates.len(),
+        args.level,
+        kraken_reads,
+        added_reads
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
@@ -0,0 +1,259 @@
+use clap::Parser;
+use kraken2_rs::args::BatchSize;
+use kraken2_rs::checksums::write_checksums;
+use kraken2_rs::compact_hash::{file_checksum, HashConfig, ShardChecksums};
+use kraken2_rs::db::{convert_fna_to_k2_format, insert_k2file, InsertStats, BATCH_SIZE};
+use kraken2_rs::taxonomy::Taxonomy;
+use kraken2_rs::utils::{create_partition_files, create_partition_writers, read_id_to_taxon_map};
+use kraken2_rs::IndexOptions;
+use seqkmer::{Dust, DEFAULT_DUST_THRESHOLD};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// 插入后 shard 的填充率超过该值时提示重新构建
+const MAX_LOAD_FACTOR: f64 = 0.9;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Add genomes to an existing database without rebuilding it",
+    long_about = "Insert the minimizers of new FASTA genomes into the existing hash_*.k2d shards of a database. A minimizer already in the table gets the LCA of its old and new taxids. The taxids of the new sequences must already be in taxo.k2d, and the table keeps its capacity, so adding a large amount of sequence still needs a rebuild"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files
+    #[arg(long = "db", required = true)]
+    pub database: PathBuf,
+
+    /// FASTA files with the genomes to add
+    #[clap(value_parser, required = true)]
+    pub input_files: Vec<PathBuf>,
+
+    /// Sequence id to taxid map of the new sequences (seqid2taxid.map format), appended to the database's seqid2taxid.map
+    #[arg(long = "seqid2taxid", required = true)]
+    pub id_to_taxon_map: PathBuf,
+
+    /// Do not mask low-complexity regions of the new sequences with DUST before extracting minimizers
+    #[clap(long, value_parser, default_value_t = false)]
+    pub no_masking: bool,
+
+    /// DUST score threshold for masking low-complexity regions, higher values mask less (like dustmasker -level)
+    #[clap(long, default_value_t = DEFAULT_DUST_THRESHOLD)]
+    pub masker_threshold: u32,
+
+    /// Number of cells read from a chunk file per batch, or "auto" to pick it from the chunk size and CPU cache
+    #[arg(long, default_value_t = BatchSize::Fixed(BATCH_SIZE))]
+    pub batch_size: BatchSize,
+
+    /// Number of threads
+    #[clap(short = 'p', long, default_value_t = num_cpus::get())]
+    pub threads: usize,
+}
+
+/// 把新序列的 id 追加到数据库的 seqid2taxid.map, 已有的 id 保留原来的 taxid
+fn append_id_to_taxon_map(
+    map_file: &PathBuf,
+    new_map: &HashMap<String, u64>,
+) -> kraken2_rs::error::Result<usize> {
+    let existing = if map_file.exists() {
+        read_id_to_taxon_map(map_file)?
+    } else {
+        HashMap::new()
+    };
+    let mut entries: Vec<(&String, &u64)> = new_map
+        .iter()
+        .filter(|(id, &taxid)| match existing.get(*id) {
+            Some(&old) if old != taxid => {
+                warn!(
+                    "{} is mapped to {} in seqid2taxid.map, keeping it (minimizers were added with {})",
+                    id, old, taxid
+                );
+                false
+            }
+            Some(_) => false,
+            None => true,
+        })
+        .collect();
+    entries.sort_unstable();
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(map_file)?;
+    let mut writer = BufWriter::new(file);
+    for (id, taxid) in &entries {
+        writeln!(writer, "{}\t{}", id, taxid)?;
+    }
+    writer.flush()?;
+    Ok(entries.len())
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let k2d_dir = &args.database;
+    let idx_opts = IndexOptions::read_index_options(k2d_dir.join("opts.k2d"))?;
+    let meros = idx_opts.as_meros().for_references();
+    let masker = (!args.no_masking && idx_opts.dna_db).then(|| Dust::new(args.masker_threshold));
+    let taxonomy = Taxonomy::from_file(k2d_dir.join("taxo.k2d"))?;
+    let hash_filename = k2d_dir.join("hash_config.k2d");
+    let mut hash_config = HashConfig::from_hash_header(&hash_filename)?;
+    let checksums = ShardChecksums::from_hash_header(&hash_filename)?;
+
+    for input in &args.input_files {
+        if !input.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("input file {:?} not found", input),
+            )
+            .into());
+        }
+    }
+
+    // 内部 taxid 与 taxo.k2d 绑定, 新的 taxon 需要重新构建数据库
+    let id_to_taxon_map = read_id_to_taxon_map(&args.id_to_taxon_map)?;
+    let mut missing: Vec<u64> = id_to_taxon_map
+        .values()
+        .copied()
+        .filter(|&taxid| taxonomy.get_internal_id(taxid) == 0)
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+    if !missing.is_empty() {
+        let listed: Vec<String> = missing.iter().take(10).map(|t| t.to_string()).collect();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} taxids are not in the database taxonomy ({}{}), rebuild the database to add new taxa",
+                missing.len(),
+                listed.join(", "),
+                if missing.len() > listed.len() { ", ..." } else { "" }
+            ),
+        )
+        .into());
+    }
+
+    let start = Instant::now();
+    info!(
+        stage = "add",
+        event = "start",
+        files = args.input_files.len(),
+        "add {} files to {:?}",
+        args.input_files.len(),
+        k2d_dir
+    );
+
+    let chunk_files = create_partition_files(hash_config.partition, k2d_dir, "add_chunk")?;
+    for chunk_file in chunk_files.iter().filter(|file| file.exists()) {
+        fs::remove_file(chunk_file)?;
+    }
+    let mut writers = create_partition_writers(&chunk_files)?;
+    for input in &args.input_files {
+        info!(
+            stage = "add",
+            event = "file",
+            "convert fna file {:?}",
+            input
+        );
+        convert_fna_to_k2_format(
+            input,
+            meros,
+            &taxonomy,
+            &id_to_taxon_map,
+            hash_config,
+            &mut writers,
+            hash_config.hash_capacity,
+            args.threads,
+            masker,
+        );
+    }
+    for writer in writers.iter_mut() {
+        writer.flush()?;
+    }
+    drop(writers);
+
+    let mut total = InsertStats::default();
+    let mut shard_checksums = Vec::with_capacity(hash_config.partition);
+    for (i, chunk_file) in chunk_files.iter().enumerate() {
+        let page_index = i + 1;
+        let page_file = k2d_dir.join(format!("hash_{}.k2d", page_index));
+        if fs::metadata(chunk_file)?.len() == 0 {
+            let checksum = match checksums.as_ref().and_then(|c| c.get(page_index)) {
+                Some(checksum) => checksum,
+                None => file_checksum(&page_file)?,
+            };
+            shard_checksums.push(checksum);
+            continue;
+        }
+        let (stats, checksum) = insert_k2file(
+            hash_config,
+            chunk_file,
+            &page_file,
+            page_index,
+            &taxonomy,
+            args.batch_size,
+            checksums.as_ref(),
+        )?;
+        shard_checksums.push(checksum);
+        if stats.dropped > 0 {
+            warn!(
+                "shard {}: {} minimizers were not added, no free cell after their position",
+                page_index, stats.dropped
+            );
+        }
+        total.inserted += stats.inserted;
+        total.updated += stats.updated;
+        total.dropped += stats.dropped;
+        info!(
+            stage = "add",
+            event = "chunk",
+            chunk = page_index,
+            inserted = stats.inserted,
+            updated = stats.updated,
+            "shard {}/{}: {} new minimizers, {} LCAs updated",
+            page_index,
+            hash_config.partition,
+            stats.inserted,
+            stats.updated
+        );
+    }
+
+    hash_config.size += total.inserted;
+    hash_config.write_to_file(&hash_filename, Some(&ShardChecksums(shard_checksums)))?;
+    write_checksums(k2d_dir)?;
+    let added = append_id_to_taxon_map(&k2d_dir.join("seqid2taxid.map"), &id_to_taxon_map)?;
+    for chunk_file in &chunk_files {
+        fs::remove_file(chunk_file)?;
+    }
+
+    let load_factor = hash_config.size as f64 / hash_config.capacity as f64;
+    if load_factor > MAX_LOAD_FACTOR {
+        warn!(
+            "the hash table is {:.1}% full, rebuild the database with a larger capacity before adding more genomes",
+            load_factor * 100.0
+        );
+    }
+    let duration = start.elapsed();
+    info!(
+        stage = "add",
+        event = "end",
+        elapsed_ms = duration.as_millis() as u64,
+        "added {} new minimizers ({} LCAs updated, {} not added) and {} sequence ids, took: {:?}",
+        total.inserted,
+        total.updated,
+        total.dropped,
+        added,
+        duration
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(e.as_ref()) as i32);
+    }
+}
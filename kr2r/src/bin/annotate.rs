@@ -1,13 +1,32 @@
 use clap::Parser;
-use kraken2_rs::compact_hash::{read_next_page, Compact, HashConfig, Page, Row, Slot};
-use kraken2_rs::utils::{find_and_sort_files, open_file};
-use seqkmer::buffer_read_parallel;
+use kraken2_rs::args::{
+    check_stage_threads, parse_database, parse_io_threads, stage_threads, BatchSize,
+};
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::checkpoint::{restore_files, snapshot_files, Checkpoint};
+use kraken2_rs::compact_hash::{
+    map_next_page, read_next_page, write_row_header, ChunkRecord, Compact, HashConfig, HashPage,
+    LookupCache, Page, RecordReader, Row, ShardChecksums, Slot, LOOKUP_CACHE_ENTRIES,
+};
+#[cfg(feature = "server")]
+use kraken2_rs::distributed::{WorkerConnection, REMOTE_BATCH_SLOTS};
+use kraken2_rs::progress::StageProgress;
+use kraken2_rs::shm::shm_cache_files;
+use kraken2_rs::utils::open_file;
+use seqkmer::{buffer_read_parallel, Threads};
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Read, Result, Write};
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "server")]
+use std::sync::mpsc::sync_channel;
+#[cfg(feature = "server")]
+use std::thread;
 use std::time::Instant;
+use tracing::info;
 
 pub const BUFFER_SIZE: usize = 48 * 1024 * 1024;
 
@@ -22,16 +41,22 @@ pub const BUFFER_SIZE: usize = 48 * 1024 * 1024;
     long_about = "annotate a set of sequences"
 )]
 pub struct Args {
-    /// database hash chunk directory and other files
-    #[arg(long = "db", required = true)]
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
     pub database: PathBuf,
 
     /// chunk directory
     #[clap(long)]
     pub chunk_dir: PathBuf,
 
-    #[clap(long, default_value_t = BUFFER_SIZE)]
-    pub buffer_size: usize,
+    /// Number of k-mer slots read from a chunk file per batch, or "auto" to pick it from the chunk size and CPU cache
+    #[clap(long, default_value_t = BatchSize::Fixed(BUFFER_SIZE))]
+    pub buffer_size: BatchSize,
+
+    /// Entries of the per-batch cache of hash table lookups, so minimizers repeated across the
+    /// reads of a batch (high-coverage or amplicon data) are looked up once; 0 disables it
+    #[clap(long, value_parser, default_value_t = LOOKUP_CACHE_ENTRIES)]
+    pub lookup_cache: usize,
 
     /// The size of each batch for processing taxid match results, used to control memory usage
     #[clap(long, value_parser = clap::value_parser!(u32).range(1..=32), default_value_t = 4)]
@@ -40,6 +65,41 @@ pub struct Args {
     /// The number of threads to use.
     #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
     pub num_threads: usize,
+
+    /// Threads that process the reads of each stage (minimizers, hash table lookups, classification)
+    /// [default: --num-threads minus --io-threads]
+    #[clap(long, value_parser)]
+    pub compute_threads: Option<usize>,
+
+    /// Threads that read the input and write the output of each stage, in a pool of their own so
+    /// busy compute threads don't hold up the writer; at least 2 (one reader, one writer)
+    #[clap(long, value_parser = parse_io_threads, default_value_t = 2)]
+    pub io_threads: usize,
+
+    /// Memory-map the hash_*.k2d files instead of reading them into memory, so concurrent runs on one node share the page cache
+    #[clap(long, value_parser, default_value_t = false)]
+    pub memory_mapping: bool,
+
+    /// Keep the hash_*.k2d files in shared memory (/dev/shm) after the run, so later runs on the
+    /// node map them without reading the database again (implies --memory-mapping).
+    /// Remove /dev/shm/kraken2_rs-* to free the memory
+    #[clap(long, value_parser, default_value_t = false)]
+    pub shm_cache: bool,
+
+    /// Skip checking the hash_*.k2d files against the checksums stored in hash_config.k2d
+    /// while loading them (checking reads every shard once more with --memory-mapping)
+    #[clap(long, value_parser, default_value_t = false)]
+    pub no_verify: bool,
+
+    /// Fail when the chunk files are not numbered continuously (e.g. after a partial rerun);
+    /// by default the files present are processed and the missing ones are listed at the end
+    #[clap(long, value_parser, default_value_t = false)]
+    pub strict_chunks: bool,
+
+    /// `shard-worker` addresses (host:port, comma separated) to send the chunks to instead of
+    /// loading the hash_*.k2d shards here; each chunk goes to a worker that holds its shard
+    #[clap(long, value_parser, value_delimiter = ',')]
+    pub workers: Vec<String>,
 }
 
 fn read_chunk_header<R: Read>(reader: &mut R) -> io::Result<(usize, usize)> {
@@ -61,12 +121,23 @@ fn read_chunk_header<R: Read>(reader: &mut R) -> io::Result<(usize, usize)> {
     Ok((index as usize, chunk_size as usize))
 }
 
+/// 以追加方式打开 .bin 文件, 新文件先写入文件头
+fn open_bin_file(path: &Path) -> io::Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let empty = file.metadata()?.len() == 0;
+    let mut writer = BufWriter::new(file);
+    if empty {
+        write_row_header(&mut writer)?;
+    }
+    Ok(writer)
+}
+
 fn _write_to_file(
     file_index: u64,
     bytes: &[u8],
     last_file_index: &mut Option<u64>,
     writer: &mut Option<BufWriter<File>>,
-    chunk_dir: &PathBuf,
+    chunk_dir: &Path,
 ) -> io::Result<()> {
     if last_file_index.is_none() || last_file_index.unwrap() != file_index {
         if let Some(mut w) = writer.take() {
@@ -74,12 +145,7 @@ fn _write_to_file(
         }
 
         let file_name = format!("sample_file_{}.bin", file_index);
-        let file_path = chunk_dir.join(file_name);
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&file_path)?;
-        *writer = Some(BufWriter::new(file));
+        *writer = Some(open_bin_file(&chunk_dir.join(file_name))?);
 
         *last_file_index = Some(file_index);
     }
@@ -96,18 +162,15 @@ fn write_to_file(
     seq_id_mod: u32,
     bytes: &[u8],
     writers: &mut HashMap<(u64, u32), BufWriter<File>>,
-    chunk_dir: &PathBuf,
+    chunk_dir: &Path,
 ) -> io::Result<()> {
-    let writer = writers.entry((file_index, seq_id_mod)).or_insert_with(|| {
-        let file_name = format!("sample_file_{}_{}.bin", file_index, seq_id_mod);
-        let file_path = chunk_dir.join(file_name);
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&file_path)
-            .expect("failed to open file");
-        BufWriter::new(file)
-    });
+    let writer = match writers.entry((file_index, seq_id_mod)) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let file_name = format!("sample_file_{}_{}.bin", file_index, seq_id_mod);
+            entry.insert(open_bin_file(&chunk_dir.join(file_name))?)
+        }
+    };
 
     writer.write_all(bytes)?;
 
@@ -126,155 +189,453 @@ fn clean_up_writers(
 
     for key in keys_to_remove {
         if let Some(mut writer) = writers.remove(&key) {
-            writer.flush()?; 
+            writer.flush()?;
         }
     }
 
     Ok(())
 }
 
+/// 查找到 taxid 的 slot 写出的 Row 与它所在的输出文件 (file_index, seq_id_mod)
+fn hit_row(
+    slot: &Slot<u64>,
+    taxid: u32,
+    hash_config: &HashConfig,
+    bin_threads: u32,
+) -> ((u64, u32), Row) {
+    let value_bits = hash_config.get_value_bits();
+    let kmer_id = slot.get_kmer_id(hash_config.get_idx_bits());
+    let file_index = slot.value.right(hash_config.get_value_mask()) >> 32;
+    let seq_id = slot.get_seq_id() as u32;
+    let left = slot.value.left(value_bits) as u32;
+    let high = u32::combined(left, taxid, value_bits);
+    (
+        (file_index, seq_id % bin_threads),
+        Row::new(high, seq_id, kmer_id),
+    )
+}
+
+/// 按输出文件的顺序追加一批结果, 切换到新的 file_index 时关闭之前的文件
+fn write_results(
+    res: &HashMap<(u64, u32), Vec<u8>>,
+    writers: &mut HashMap<(u64, u32), BufWriter<File>>,
+    current_file_index: &mut Option<u64>,
+    chunk_dir: &Path,
+) -> io::Result<()> {
+    let mut file_keys: Vec<_> = res.keys().cloned().collect();
+    file_keys.sort_unstable();
+    for (file_index, seq_id_mod) in file_keys {
+        if let Some(bytes) = res.get(&(file_index, seq_id_mod)) {
+            if *current_file_index != Some(file_index) {
+                clean_up_writers(writers, file_index)?;
+                *current_file_index = Some(file_index);
+            }
+            write_to_file(file_index, seq_id_mod, bytes, writers, chunk_dir)?;
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 fn process_batch<R>(
     reader: &mut R,
     hash_config: &HashConfig,
-    page: &Page,
+    page: &HashPage,
     chunk_dir: PathBuf,
     buffer_size: usize,
+    lookup_cache: usize,
     bin_threads: u32,
     // page_index: usize,
-    num_threads: usize,
+    threads: Threads,
 ) -> std::io::Result<()>
 where
     R: Read + Send,
 {
-    let row_size = std::mem::size_of::<Row>();
     let mut writers: HashMap<(u64, u32), BufWriter<File>> = HashMap::new();
     let mut current_file_index: Option<u64> = None;
 
     let value_mask = hash_config.get_value_mask();
     let value_bits = hash_config.get_value_bits();
     let idx_mask = hash_config.get_idx_mask();
-    let idx_bits = hash_config.get_idx_bits();
+    let cache_hits = AtomicU64::new(0);
+    let lookups = AtomicU64::new(0);
 
     buffer_read_parallel(
-        reader,
-        num_threads,
-        buffer_size,
+        RecordReader::<_, Slot<u64>>::new(reader, buffer_size),
+        threads,
         |dataset: Vec<Slot<u64>>| {
             let mut results: HashMap<(u64, u32), Vec<u8>> = HashMap::new();
+            let mut cache = LookupCache::new(lookup_cache);
             for slot in dataset {
                 let indx = slot.idx & idx_mask;
                 let compacted = slot.value.left(value_bits) as u32;
                 // let taxid = chtm.get_from_page(indx, compacted, page_index);
-                let taxid = page.find_index(indx, compacted, value_bits, value_mask);
+                let taxid = cache.get_or_insert_with(indx, compacted, || {
+                    page.find_index(indx, compacted, value_bits, value_mask)
+                });
 
                 if taxid > 0 {
-                    let kmer_id = slot.idx >> idx_bits;
-                    let file_index = slot.value.right(value_mask) >> 32;
-                    let seq_id = slot.get_seq_id() as u32;
-                    let left = slot.value.left(value_bits) as u32;
-                    let high = u32::combined(left, taxid, value_bits);
-                    let row = Row::new(high, seq_id, kmer_id as u32);
-                    let value_bytes = row.as_slice(row_size);
-                    let seq_id_mod = seq_id % bin_threads;
-
-                    results
-                        .entry((file_index, seq_id_mod))
-                        .or_insert_with(Vec::new)
-                        .extend(value_bytes);
+                    let (key, row) = hit_row(&slot, taxid, hash_config, bin_threads);
+                    row.extend_bytes(results.entry(key).or_default());
                 }
             }
+            cache_hits.fetch_add(cache.hits(), Ordering::Relaxed);
+            lookups.fetch_add(cache.lookups(), Ordering::Relaxed);
             results
         },
         |result| {
             while let Some(data) = result.next() {
                 let res = data.unwrap();
-                let mut file_keys: Vec<_> = res.keys().cloned().collect();
-                file_keys.sort_unstable(); // 
+                write_results(&res, &mut writers, &mut current_file_index, &chunk_dir)
+                    .expect("write to file error");
+            }
+        },
+    )?;
 
-                for (file_index, seq_id_mod) in file_keys {
-                    if let Some(bytes) = res.get(&(file_index, seq_id_mod)) {
+    for writer in writers.values_mut() {
+        writer.flush()?;
+    }
+    if lookup_cache > 0 {
+        let lookups = lookups.load(Ordering::Relaxed);
+        let hits = cache_hits.load(Ordering::Relaxed);
+        info!(
+            "lookup cache answered {} of {} lookups ({:.1}%)",
+            hits,
+            lookups,
+            100.0 * hits as f64 / lookups.max(1) as f64
+        );
+    }
 
-                        if current_file_index != Some(file_index) {
-                            clean_up_writers(&mut writers, file_index).expect("clean writer");
-                            current_file_index = Some(file_index);
-                        }
+    Ok(())
+}
+
+/// `--workers` 的连接, 以及每个分区由哪个 worker 查找
+#[cfg(feature = "server")]
+struct Workers {
+    connections: Vec<WorkerConnection>,
+    owners: HashMap<usize, usize>,
+}
+
+#[cfg(feature = "server")]
+impl Workers {
+    /// 连接所有 worker, 检查它们的数据库与 hash_config.k2d 一致, 并且每个分区都有 worker 持有
+    fn connect(addresses: &[String], config: &HashConfig) -> Result<Self> {
+        let mut connections = Vec::new();
+        let mut owners = HashMap::new();
+        for (i, address) in addresses.iter().enumerate() {
+            let connection = WorkerConnection::connect(address)?;
+            connection
+                .hello
+                .check(config)
+                .map_err(|e| io::Error::new(e.kind(), format!("worker {}: {}", address, e)))?;
+            let shards: Vec<String> = connection
+                .hello
+                .shards
+                .iter()
+                .map(|shard| (shard + 1).to_string())
+                .collect();
+            info!("worker {}: shards {}", address, shards.join(","));
+            for &shard in &connection.hello.shards {
+                owners.entry(shard).or_insert(i);
+            }
+            connections.push(connection);
+        }
+        let missing: Vec<String> = (0..config.partition)
+            .filter(|shard| !owners.contains_key(shard))
+            .map(|shard| format!("hash_{}.k2d", shard + 1))
+            .collect();
+        if !missing.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no worker holds {}", missing.join(", ")),
+            ));
+        }
+        Ok(Self {
+            connections,
+            owners,
+        })
+    }
 
-                        write_to_file(file_index, seq_id_mod, bytes, &mut writers, &chunk_dir)
-                            .expect("write to file error");
+    /// 把一个 chunk 交给持有其分区的 worker 查找
+    fn process_chunk<R: Read + Send>(
+        &mut self,
+        args: &Args,
+        reader: &mut R,
+        page_index: usize,
+    ) -> Result<()> {
+        let config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+        let owner = *self.owners.get(&page_index).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no worker holds hash_{}.k2d", page_index + 1),
+            )
+        })?;
+        let worker = &mut self.connections[owner];
+        info!("send chunk to worker {}", worker.address);
+        process_remote_batch(
+            reader,
+            page_index,
+            worker,
+            &config,
+            &args.chunk_dir,
+            args.batch_size,
+        )
+    }
+}
+
+/// 没有 `server` feature 时不能连接 worker, `--workers` 返回错误
+#[cfg(not(feature = "server"))]
+enum Workers {}
+
+#[cfg(not(feature = "server"))]
+impl Workers {
+    fn connect(_addresses: &[String], _config: &HashConfig) -> Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--workers needs kraken2_rs built with the `server` feature",
+        ))
+    }
+
+    fn process_chunk<R: Read + Send>(
+        &mut self,
+        _args: &Args,
+        _reader: &mut R,
+        _page_index: usize,
+    ) -> Result<()> {
+        match *self {}
+    }
+}
+
+/// 把一个 chunk 的 slot 分批发给持有该分区的 worker, 按返回的 taxid 写出结果.
+/// 发送在另一个线程中进行, worker 查找一批时下一批已经在路上
+#[cfg(feature = "server")]
+fn process_remote_batch<R>(
+    reader: &mut R,
+    page_index: usize,
+    worker: &mut WorkerConnection,
+    hash_config: &HashConfig,
+    chunk_dir: &Path,
+    bin_threads: u32,
+) -> Result<()>
+where
+    R: Read + Send,
+{
+    let mut writers: HashMap<(u64, u32), BufWriter<File>> = HashMap::new();
+    let mut current_file_index: Option<u64> = None;
+    let (mut sender, mut receiver) = worker.split();
+    let (tx, rx) = sync_channel::<Vec<Slot<u64>>>(2);
+
+    thread::scope(|scope| {
+        let send = scope.spawn(move || -> Result<()> {
+            let mut records = RecordReader::<_, Slot<u64>>::new(reader, REMOTE_BATCH_SLOTS);
+            while let Some(slots) = records.next_batch()? {
+                sender.send(page_index, &slots)?;
+                if tx.send(slots).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        let received = (|| -> Result<()> {
+            for slots in rx {
+                let taxids = receiver.receive(slots.len())?;
+                let mut results: HashMap<(u64, u32), Vec<u8>> = HashMap::new();
+                for (slot, taxid) in slots.iter().zip(taxids) {
+                    if taxid > 0 {
+                        let (key, row) = hit_row(slot, taxid, hash_config, bin_threads);
+                        row.extend_bytes(results.entry(key).or_default());
                     }
                 }
+                write_results(&results, &mut writers, &mut current_file_index, chunk_dir)?;
             }
-        },
-    )
-    .expect("failed");
+            Ok(())
+        })();
+        if received.is_err() {
+            // 发送线程可能阻塞在写入上 (worker 不再读取请求), 先关闭连接再等待它
+            receiver.shutdown();
+        }
+        let sent = send.join().expect("worker sender thread panicked");
+        received.and(sent)
+    })?;
 
     for writer in writers.values_mut() {
         writer.flush()?;
     }
-
     Ok(())
 }
 
 fn process_chunk_file<P: AsRef<Path>>(
     args: &Args,
     chunk_file: P,
-    hash_files: &Vec<PathBuf>,
-    large_page: &mut Page,
+    hash_files: &[PathBuf],
+    large_page: &mut HashPage,
+    workers: Option<&mut Workers>,
 ) -> Result<()> {
     let file = open_file(chunk_file)?;
+    let file_len = file.metadata()?.len();
     let mut reader = BufReader::new(file);
 
     let (page_index, _) = read_chunk_header(&mut reader)?;
+    if let Some(workers) = workers {
+        return workers.process_chunk(args, &mut reader, page_index);
+    }
+    let buffer_size = args.buffer_size.resolve(file_len, Slot::<u64>::SIZE);
+    if args.buffer_size == BatchSize::Auto {
+        info!("batch size: {} slots", buffer_size);
+    }
 
     let start = Instant::now();
 
-    println!("start load table...");
-    let config = HashConfig::from_hash_header(&args.database.join("hash_config.k2d"))?;
-
-    read_next_page(large_page, hash_files, page_index, config)?;
+    info!("start load table...");
+    let config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    let checksums = if args.no_verify {
+        None
+    } else {
+        ShardChecksums::from_hash_header(args.database.join("hash_config.k2d"))?
+    };
+
+    if args.memory_mapping {
+        *large_page = HashPage::Mapped(map_next_page(
+            hash_files,
+            page_index,
+            config,
+            checksums.as_ref(),
+        )?);
+    } else if let HashPage::Loaded(page) = large_page {
+        read_next_page(page, hash_files, page_index, config, checksums.as_ref())?;
+    }
 
     let duration = start.elapsed();
 
-    println!("load table took: {:?}", duration);
+    info!("load table took: {:?}", duration);
     process_batch(
         &mut reader,
         &config,
-        &large_page,
+        large_page,
         args.chunk_dir.clone(),
-        args.buffer_size,
+        buffer_size,
+        args.lookup_cache,
         args.batch_size,
         // page_index,
-        args.num_threads,
+        stage_threads(args.num_threads, args.compute_threads, args.io_threads),
     )?;
 
     Ok(())
 }
 
-pub fn run(args: Args) -> Result<()> {
-    let chunk_files = find_and_sort_files(&args.chunk_dir, "sample", ".k2", true)?;
-    let hash_files = find_and_sort_files(&args.database, "hash", ".k2d", true)?;
+/// annotate 的输出 sample_file_{file_index}_{seq_id_mod}.bin
+fn is_sample_bin(name: &str) -> bool {
+    name.starts_with("sample_file_") && name.ends_with(".bin")
+}
 
+pub fn run(mut args: Args) -> Result<()> {
+    check_stage_threads(args.num_threads, args.compute_threads, args.io_threads)?;
+    let catalog = ChunkCatalog::scan(&args.chunk_dir, ChunkKind::Sample)?;
+    let chunk_files = catalog.paths();
+    let config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    // 使用 worker 时本地不需要 hash_*.k2d
+    let mut workers = if args.workers.is_empty() {
+        None
+    } else {
+        Some(Workers::connect(&args.workers, &config)?)
+    };
+    let mut hash_files = if workers.is_some() {
+        Vec::new()
+    } else {
+        ChunkCatalog::scan_continuous(&args.database, ChunkKind::Hash)?.paths()
+    };
+    if args.shm_cache && workers.is_none() {
+        hash_files = shm_cache_files(&args.database, &hash_files)?;
+        args.memory_mapping = true;
+    }
 
     let start = Instant::now();
-    println!("annotate start...");
-    let config = HashConfig::from_hash_header(&args.database.join("hash_config.k2d"))?;
-    let mut large_page = Page::with_capacity(0, config.hash_capacity);
-    for chunk_file in &chunk_files {
-        process_chunk_file(&args, chunk_file, &hash_files, &mut large_page)?;
+    info!(
+        stage = "annotate",
+        event = "start",
+        chunks = chunk_files.len(),
+        "annotate start..."
+    );
+    // 内存映射时每个 chunk 重新映射对应的 shard, 不需要预先分配
+    let mut large_page = HashPage::Loaded(if args.memory_mapping || workers.is_some() {
+        Page::default()
+    } else {
+        Page::with_capacity(0, config.hash_capacity)
+    });
+    let mut checkpoint = Checkpoint::open(&args.chunk_dir)?;
+    // 已经完成的 chunk 文件会被删除, 不算缺少
+    let missing: Vec<usize> = catalog
+        .missing_indices()
+        .into_iter()
+        .filter(|&i| !checkpoint.is_done("annotate", &catalog.file_name(i)))
+        .collect();
+    if args.strict_chunks && !missing.is_empty() {
+        return Err(catalog.missing_error(&missing));
+    }
+    let progress = StageProgress::new("annotate", chunk_files.len(), "chunks");
+    for (chunk, chunk_file) in chunk_files.iter().enumerate() {
+        let name = chunk_file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if checkpoint.is_done("annotate", &name) {
+            info!("skip annotated chunk {}", name);
+            let _ = std::fs::remove_file(chunk_file);
+            progress.inc();
+            continue;
+        }
+        // 上次运行在这个 chunk 中断, 去掉已经追加的结果
+        if let Some(snapshot) = checkpoint.started_value("annotate", &name) {
+            info!("resume annotate from chunk {}", name);
+            restore_files(&args.chunk_dir, is_sample_bin, snapshot)?;
+        }
+        let snapshot = snapshot_files(&args.chunk_dir, is_sample_bin)?;
+        checkpoint.start("annotate", &name, &snapshot)?;
+        let chunk_start = Instant::now();
+        process_chunk_file(
+            &args,
+            chunk_file,
+            &hash_files,
+            &mut large_page,
+            workers.as_mut(),
+        )?;
+        checkpoint.done("annotate", &name, "")?;
+        info!(
+            stage = "annotate",
+            event = "chunk",
+            chunk,
+            elapsed_ms = chunk_start.elapsed().as_millis() as u64,
+            "annotated chunk {}",
+            name
+        );
         let _ = std::fs::remove_file(chunk_file);
+        progress.inc();
     }
+    progress.finish();
 
     let duration = start.elapsed();
 
-    println!("annotate took: {:?}", duration);
+    info!(
+        stage = "annotate",
+        event = "end",
+        elapsed_ms = duration.as_millis() as u64,
+        "annotate took: {:?}",
+        duration
+    );
+    if !missing.is_empty() {
+        let names: Vec<String> = missing.iter().map(|&i| catalog.file_name(i)).collect();
+        info!("missing chunk files (not annotated): {}", names.join(", "));
+    }
 
     Ok(())
 }
 
 #[allow(dead_code)]
 fn main() {
+    kraken2_rs::logging::init_default_logging();
     let args = Args::parse();
     if let Err(e) = run(args) {
         eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
     }
 }
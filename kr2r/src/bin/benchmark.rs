@@ -0,0 +1,276 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::classify::process_hitgroup;
+use kraken2_rs::compact_hash::{CHTable, Compact, HashConfig, Row, ShardChecksums};
+use kraken2_rs::taxonomy::{rank_level, Taxonomy};
+use kraken2_rs::utils::{find_files, read_id_to_taxon_map};
+use kraken2_rs::{HitGroup, IndexOptions};
+use seqkmer::{read_parallel, FastaReader, MinimizerIterator, WindowReader};
+use std::fs::File;
+use std::io::{self, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Leave-one-out benchmark: mask a clade and report at what rank its reads are recovered",
+    long_about = "Mask the minimizers the database stores in a clade, classify reads simulated from the clade's library sequences and count at which rank of their true lineage the reads are classified"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Taxonomy ID of the clade to leave out of the database.
+    #[clap(short = 't', long = "exclude-taxid", value_parser, required = true)]
+    pub exclude_taxid: u64,
+
+    /// Length of the simulated reads.
+    #[clap(short = 'r', long, value_parser, default_value_t = 150)]
+    pub read_len: usize,
+
+    /// Distance between the starts of simulated reads, defaults to the read length.
+    #[clap(long, value_parser)]
+    pub read_step: Option<usize>,
+
+    /// Confidence score threshold.
+    #[clap(
+        short = 'T',
+        long = "confidence-threshold",
+        value_parser,
+        default_value_t = 0.0
+    )]
+    pub confidence_threshold: f64,
+
+    /// The minimum number of hit groups needed for a call.
+    #[clap(
+        short = 'g',
+        long = "minimum-hit-groups",
+        value_parser,
+        default_value_t = 2
+    )]
+    pub minimum_hit_groups: usize,
+
+    /// Output table, defaults to the standard output.
+    #[clap(short, long, value_parser)]
+    pub output: Option<PathBuf>,
+
+    /// Memory-map the hash shards instead of reading them into memory
+    #[clap(long, value_parser, default_value_t = false)]
+    pub memory_mapping: bool,
+
+    /// The number of threads to use.
+    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    pub num_threads: usize,
+}
+
+/// 输出表的行, 从 species 到 root, 然后是错误分类与未分类
+const CATEGORIES: [&str; 12] = [
+    "species",
+    "genus",
+    "family",
+    "order",
+    "class",
+    "phylum",
+    "kingdom",
+    "superkingdom",
+    "root",
+    "wrong_lineage",
+    "unclassified",
+    "total",
+];
+const ROOT: usize = 8;
+const WRONG_LINEAGE: usize = 9;
+const UNCLASSIFIED: usize = 10;
+
+/// 与 [`lookup_rows`](kraken2_rs::classify::lookup_rows) 相同, 但跳过存放在被排除 clade 中的 minimizer
+fn lookup_masked_rows(
+    rows: &mut Vec<Row>,
+    m_iter: &mut MinimizerIterator,
+    hash_config: &HashConfig,
+    chtable: &CHTable,
+    masked: &[bool],
+    offset: usize,
+) -> usize {
+    let chunk_size = hash_config.hash_capacity;
+    let value_bits = hash_config.value_bits;
+    for (sort, hash_key) in m_iter.by_ref() {
+        let (idx, compacted) = hash_config.compact(hash_key);
+        let taxid = chtable.get_from_page(idx % chunk_size, compacted, idx / chunk_size);
+        if taxid > 0 && !masked.get(taxid as usize).copied().unwrap_or(false) {
+            let high = u32::combined(compacted, taxid, value_bits);
+            rows.push(Row::new(high, 0, (sort + 1 + offset) as u64));
+        }
+    }
+    m_iter.size + offset
+}
+
+/// read 的分类结果在真实谱系上所处的行: 最近的有主要层级的祖先, 不在谱系上时为 wrong_lineage
+fn category(taxonomy: &Taxonomy, genome: u32, call: u32) -> usize {
+    if call == 0 {
+        return UNCLASSIFIED;
+    }
+    if !taxonomy.is_a_ancestor_of_b(call, genome) {
+        return WRONG_LINEAGE;
+    }
+    taxonomy
+        .lineage(call)
+        .iter()
+        .rev()
+        .find_map(|&node| rank_level(taxonomy.rank(node)))
+        .map_or(ROOT, |level| ROOT - level as usize)
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let start = Instant::now();
+    let database = &args.database;
+    let taxonomy = Taxonomy::from_file(database.join("taxo.k2d"))?;
+    let excluded = taxonomy.get_internal_id(args.exclude_taxid);
+    if excluded <= 1 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "taxid {} is not a clade below the root of the database taxonomy",
+                args.exclude_taxid
+            ),
+        ));
+    }
+    let masked: Vec<bool> = (0..taxonomy.node_count() as u32)
+        .map(|node| taxonomy.is_a_ancestor_of_b(excluded, node))
+        .collect();
+
+    let id_to_taxon_map = read_id_to_taxon_map(database.join("seqid2taxid.map"))?;
+    let library_files = find_files(database.join("library"), "library", ".fna");
+    if library_files.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "no library/library*.fna in {:?}, the library is needed to simulate reads",
+                database
+            ),
+        ));
+    }
+
+    let idx_opts = IndexOptions::read_index_options(database.join("opts.k2d"))?;
+    let meros = idx_opts.as_meros().for_references();
+    let hash_config = HashConfig::from_hash_header(database.join("hash_config.k2d"))?;
+    let checksums = ShardChecksums::from_hash_header(database.join("hash_config.k2d"))?;
+    let hash_files = ChunkCatalog::scan_continuous(database, ChunkKind::Hash)?.paths();
+    let chtable = if args.memory_mapping {
+        CHTable::map_hash_files(hash_config, &hash_files, checksums.as_ref())?
+    } else {
+        CHTable::from_hash_files(hash_config, &hash_files, checksums.as_ref())?
+    };
+
+    let classify_counter = AtomicUsize::new(0);
+    let mut counts = [0u64; CATEGORIES.len()];
+    for library_file in &library_files {
+        let fasta = FastaReader::from_path(library_file, 1)?;
+        let step = args.read_step.unwrap_or(args.read_len).max(1);
+        let mut reader = WindowReader::new(fasta, args.read_len, step);
+        read_parallel(
+            &mut reader,
+            args.num_threads,
+            &meros,
+            |reads| {
+                let mut batch = Vec::new();
+                for read in reads {
+                    let Some(&taxid) = id_to_taxon_map.get(&read.header.id) else {
+                        continue;
+                    };
+                    let genome = taxonomy.get_internal_id(taxid);
+                    if !masked.get(genome as usize).copied().unwrap_or(false) {
+                        continue;
+                    }
+                    if read.body.reduce(0, |acc, m_iter| acc + m_iter.seq_size()) < args.read_len {
+                        continue;
+                    }
+                    let rows: Vec<Row> = read.fold(|rows, m_iter, offset| {
+                        lookup_masked_rows(rows, m_iter, &hash_config, &chtable, &masked, offset)
+                    });
+                    let hits = HitGroup::new(rows, read.range());
+                    let hit_data = process_hitgroup(
+                        &hits,
+                        &taxonomy,
+                        &classify_counter,
+                        hits.required_score(args.confidence_threshold),
+                        args.minimum_hit_groups,
+                        hash_config.value_mask,
+                        None,
+                        None,
+                    );
+                    let call = taxonomy.get_internal_id(hit_data.1);
+                    batch.push(category(&taxonomy, genome, call));
+                }
+                batch
+            },
+            |dataset| {
+                while let Some(data) = dataset.next() {
+                    for category in data.unwrap() {
+                        counts[category] += 1;
+                    }
+                }
+            },
+        )?;
+    }
+    let total: u64 = counts[..CATEGORIES.len() - 1].iter().sum();
+    counts[CATEGORIES.len() - 1] = total;
+    if total == 0 {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "no library sequence of at least {} bp belongs to taxid {}",
+                args.read_len, args.exclude_taxid
+            ),
+        ));
+    }
+
+    let mut writer: Box<dyn Write> = match &args.output {
+        Some(output) => Box::new(BufWriter::new(File::create(output)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    writeln!(writer, "rank\treads\tpercent\tcumulative_percent")?;
+    // cumulative_percent: 在该层级或更低层级的真实谱系上恢复的 reads
+    let mut cumulative = 0;
+    for (index, (name, &reads)) in CATEGORIES.iter().zip(counts.iter()).enumerate() {
+        cumulative += reads;
+        let cumulative_percent = if index <= ROOT {
+            format!("{:.2}", cumulative as f64 * 100.0 / total as f64)
+        } else {
+            "-".to_string()
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{:.2}\t{}",
+            name,
+            reads,
+            reads as f64 * 100.0 / total as f64,
+            cumulative_percent
+        )?;
+    }
+    writer.flush()?;
+
+    info!(
+        "excluded {} ({}), {} reads of {} bp classified against the masked database, took: {:?}",
+        taxonomy.name(excluded),
+        args.exclude_taxid,
+        total,
+        args.read_len,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
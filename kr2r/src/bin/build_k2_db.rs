@@ -1,12 +1,22 @@
 // 使用时需要引用模块路径
 use clap::Parser;
-use kraken2_rs::compact_hash::HashConfig;
-use kraken2_rs::db::process_k2file;
+use kraken2_rs::args::{parse_size, BatchSize};
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::checkpoint::Checkpoint;
+use kraken2_rs::checksums::write_checksums;
+use kraken2_rs::compact_hash::{file_checksum, HashConfig, ShardChecksums};
+use kraken2_rs::db::{process_k2file, write_minimizer_cap, MinimizerCap, BATCH_SIZE};
+use kraken2_rs::progress::StageProgress;
 use kraken2_rs::taxonomy::Taxonomy;
-use kraken2_rs::utils::find_and_trans_files;
+use kraken2_rs::utils::read_id_to_taxon_map;
+use std::collections::{HashMap, HashSet};
 use std::fs::remove_file;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
+use tracing::{info, warn};
+
+/// 哈希表的填充率超过该值时提示用更小的 `--load-factor` 重新构建
+const MAX_LOAD_FACTOR: f64 = 0.9;
 
 #[derive(Parser, Debug, Clone)]
 #[clap(author, version, about="build database", long_about = None)]
@@ -14,9 +24,61 @@ pub struct Args {
     /// database hash chunk directory and other files
     #[arg(long = "db", required = true)]
     pub database: PathBuf,
+
+    /// Number of cells read from a chunk file per batch, or "auto" to pick it from the chunk size and CPU cache
+    #[arg(long, default_value_t = BatchSize::Fixed(BATCH_SIZE))]
+    pub batch_size: BatchSize,
+
+    /// Memory for one hash shard while building, e.g. 64G ('K', 'M' or 'G' suffix). A shard whose
+    /// table (hash capacity x 4 bytes) is larger is built by sorting its chunk into runs on disk
+    /// and merging them, instead of holding the whole shard in memory
+    #[arg(long, value_parser = parse_size)]
+    pub max_build_memory: Option<usize>,
+
+    #[clap(flatten)]
+    pub minimizer_cap: MinimizerCapArgs,
+}
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct MinimizerCapArgs {
+    /// Drop minimizers found in more than this many genomes (library sequences of one taxid
+    /// in a row count as one genome), e.g. ultra-conserved k-mers that only ever reach the root
+    #[arg(long)]
+    pub max_minimizer_genomes: Option<u32>,
+
+    /// Drop minimizers found in more than this fraction of the library genomes (0 to 1)
+    #[arg(long, value_parser = parse_fraction)]
+    pub max_minimizer_fraction: Option<f64>,
+}
+
+impl From<MinimizerCapArgs> for MinimizerCap {
+    fn from(item: MinimizerCapArgs) -> Self {
+        Self {
+            max_genomes: item.max_minimizer_genomes,
+            max_fraction: item.max_minimizer_fraction,
+        }
+    }
 }
 
-pub fn run(database: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => Err(format!("'{}' is not a fraction between 0 and 1", s)),
+    }
+}
+
+/// library 的基因组数: seqid2taxid.map 中不同的 taxid
+fn library_genomes(database: &Path) -> std::io::Result<usize> {
+    let id_to_taxon_map = read_id_to_taxon_map(database.join("seqid2taxid.map"))?;
+    Ok(id_to_taxon_map.values().collect::<HashSet<_>>().len())
+}
+
+pub fn run(
+    database: &Path,
+    batch_size: BatchSize,
+    max_build_memory: Option<usize>,
+    minimizer_cap: MinimizerCap,
+) -> Result<(), Box<dyn std::error::Error>> {
     let k2d_dir = database;
     let taxonomy_filename = k2d_dir.join("taxo.k2d");
     let taxonomy = Taxonomy::from_file(taxonomy_filename)?;
@@ -26,47 +88,139 @@ pub fn run(database: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
 
     let start = Instant::now();
 
-    let chunk_files = find_and_trans_files(&k2d_dir, "chunk", ".k2", true)?;
+    let chunk_files = ChunkCatalog::scan_continuous(k2d_dir, ChunkKind::DbChunk)?.by_index();
+
+    let genomes = if minimizer_cap.is_set() {
+        library_genomes(k2d_dir)?
+    } else {
+        0
+    };
+    let max_genomes = minimizer_cap.limit(genomes);
+    if let Some(limit) = max_genomes {
+        info!(
+            "drop minimizers found in more than {} of the {} library genomes",
+            limit, genomes
+        );
+    }
 
     let mut size: usize = 0;
+    let mut dropped: usize = 0;
+    // 记录已写入 hash 文件的 chunk 及其单元数 (与丢弃的 minimizer 数), 中断后重新运行时跳过
+    let mut checkpoint = Checkpoint::open(k2d_dir)?;
 
-    println!("start process k2 files...");
+    info!(
+        stage = "build",
+        event = "start",
+        chunks = chunk_files.len(),
+        "start process k2 files..."
+    );
+    let progress = StageProgress::new("build", chunk_files.len(), "chunks");
+    let mut checksums = HashMap::new();
     for (i, chunk_file) in &chunk_files {
-        let count = process_k2file(
+        let name = i.to_string();
+        if let Some((count, chunk_dropped)) =
+            checkpoint.done_value("build", &name).and_then(|value| {
+                let mut values = value.split('\t').map(|v| v.parse::<usize>().ok());
+                Some((values.next()??, values.next().flatten().unwrap_or(0)))
+            })
+        {
+            size += count;
+            dropped += chunk_dropped;
+            info!(
+                "skip processed chunk file {:?}/{:}",
+                i, hash_config.partition
+            );
+            progress.inc();
+            continue;
+        }
+        let (count, checksum, chunk_dropped) = process_k2file(
             hash_config,
-            &k2d_dir,
-            &chunk_file,
+            k2d_dir,
+            chunk_file,
             &taxonomy,
             hash_config.hash_capacity,
             *i,
+            batch_size,
+            max_build_memory,
+            max_genomes,
         )?;
+        checkpoint.done("build", &name, &format!("{}\t{}", count, chunk_dropped))?;
+        checksums.insert(*i, checksum);
         size += count;
+        dropped += chunk_dropped;
         let duration = start.elapsed();
-        println!(
+        info!(
+            stage = "build",
+            event = "chunk",
+            chunk = *i,
+            elapsed_ms = duration.as_millis() as u64,
             "process chunk file {:?}/{:}: duration: {:?}",
-            i, hash_config.partition, duration
+            i,
+            hash_config.partition,
+            duration
         );
+        progress.inc();
     }
+    progress.finish();
 
     hash_config.size = size;
-    hash_config.write_to_file(&hash_filename)?;
-
+    let load_factor = size as f64 / hash_config.capacity as f64;
+    if load_factor > MAX_LOAD_FACTOR {
+        warn!(
+            "the hash table is {:.1}% full, more minimizers than estimated; colliding minimizers \
+             were dropped, rebuild with a lower --load-factor",
+            load_factor * 100.0
+        );
+    }
+    // 上次运行写好的 shard 重新读取计算校验和
+    let checksums = (1..=hash_config.partition)
+        .map(|i| match checksums.get(&i) {
+            Some(&checksum) => Ok(checksum),
+            None => file_checksum(k2d_dir.join(format!("hash_{}.k2d", i))),
+        })
+        .collect::<std::io::Result<Vec<u64>>>()?;
+    hash_config.write_to_file(&hash_filename, Some(&ShardChecksums(checksums)))?;
+    if let Some(limit) = max_genomes {
+        info!(
+            "dropped {} minimizers found in more than {} genomes",
+            dropped, limit
+        );
+        write_minimizer_cap(k2d_dir, &minimizer_cap, genomes, dropped)?;
+    }
+    write_checksums(k2d_dir)?;
 
     let duration = start.elapsed();
 
-    println!("build k2 db took: {:?}", duration);
+    info!(
+        stage = "build",
+        event = "end",
+        elapsed_ms = duration.as_millis() as u64,
+        cells = size as u64,
+        load_factor,
+        "build k2 db took: {:?}",
+        duration
+    );
 
-    for (_, chunk_file) in &chunk_files {
+    for chunk_file in chunk_files.values() {
         remove_file(chunk_file)?;
     }
+    checkpoint.clear_stage("build")?;
+    checkpoint.clear_stage("chunk")?;
 
     Ok(())
 }
 
 #[allow(dead_code)]
 fn main() {
+    kraken2_rs::logging::init_default_logging();
     let args = Args::parse();
-    if let Err(e) = run(&args.database) {
+    if let Err(e) = run(
+        &args.database,
+        args.batch_size,
+        args.max_build_memory,
+        args.minimizer_cap.into(),
+    ) {
         eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(e.as_ref()) as i32);
     }
 }
@@ -1,5 +1,6 @@
 use clap::Parser;
 use kraken2_rs::args::{parse_size, Build};
+use kraken2_rs::checkpoint::Checkpoint;
 use kraken2_rs::compact_hash::HashConfig;
 use kraken2_rs::db::{convert_fna_to_k2_format, get_bits_for_taxid};
 use kraken2_rs::taxonomy::Taxonomy;
@@ -8,7 +9,10 @@ use kraken2_rs::utils::{
     read_id_to_taxon_map, set_fd_limit,
 };
 use kraken2_rs::IndexOptions;
+use std::fs::remove_file;
+use std::io::Write;
 use std::time::Instant;
+use tracing::info;
 
 #[derive(Parser, Debug, Clone)]
 #[clap(author, version, about="prebuild database", long_about = None)]
@@ -38,27 +42,48 @@ pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::erro
     .expect("more bits required for storing taxid");
 
     let capacity = required_capacity;
-    let partition = (capacity + args.hash_capacity - 1) / args.hash_capacity;
+    let partition = capacity.div_ceil(args.hash_capacity);
     let hash_config = HashConfig::new(1, capacity, value_bits, 0, partition, args.hash_capacity);
 
-
     let start = Instant::now();
 
-    let chunk_size = args.hash_capacity as usize;
+    let chunk_size = args.hash_capacity;
 
     if partition >= file_num_limit {
         set_fd_limit(partition as u64 + 1).expect("Failed to set file descriptor limit");
         // panic!("Exceeds File Number Limit");
     }
 
-    let chunk_files = create_partition_files(partition, &k2d_dir, "chunk");
-    let mut writers = create_partition_writers(&chunk_files);
+    let chunk_files = create_partition_files(partition, k2d_dir, "chunk")?;
+
+    // chunk 文件以追加方式写入, 上次未完成时删除后重新生成, 已完成时直接进入 build 阶段
+    let mut checkpoint = Checkpoint::open(k2d_dir)?;
+    let chunk_state = format!("{}\t{}\t{}", capacity, value_bits, args.hash_capacity);
+    if checkpoint.done_value("chunk", "all") == Some(chunk_state.as_str())
+        && chunk_files.iter().all(|file| file.exists())
+    {
+        info!("chunk files are complete, resume building hash files");
+        return Ok(());
+    }
+    checkpoint.clear_stage("chunk")?;
+    checkpoint.clear_stage("build")?;
+    for chunk_file in chunk_files.iter().filter(|file| file.exists()) {
+        remove_file(chunk_file)?;
+    }
+
+    let mut writers = create_partition_writers(&chunk_files)?;
 
     let library_dir = &args.build.database.join("library");
-    let fna_files = find_files(&library_dir, "library", ".fna");
+    let fna_files = find_files(library_dir, "library", ".fna");
 
+    info!(stage = "chunk", event = "start", files = fna_files.len());
     for fna_file in fna_files {
-        println!("convert fna file {:?}", fna_file);
+        info!(
+            stage = "chunk",
+            event = "file",
+            "convert fna file {:?}",
+            fna_file
+        );
         convert_fna_to_k2_format(
             fna_file,
             meros,
@@ -68,19 +93,31 @@ pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::erro
             &mut writers,
             chunk_size,
             args.build.threads,
+            args.build.klmt.masker(),
         );
     }
 
+    for writer in writers.iter_mut() {
+        writer.flush()?;
+    }
+
     let hash_filename = k2d_dir.join("hash_config.k2d");
-    hash_config.write_to_file(&hash_filename)?;
+    hash_config.write_to_file(&hash_filename, None)?;
 
     let duration = start.elapsed();
 
-    println!("chunk db took: {:?}", duration);
+    info!(
+        stage = "chunk",
+        event = "end",
+        elapsed_ms = duration.as_millis() as u64,
+        "chunk db took: {:?}",
+        duration
+    );
 
     let options_filename = k2d_dir.join("opts.k2d");
     let idx_opts = IndexOptions::from_meros(meros);
     idx_opts.write_to_file(options_filename)?;
+    checkpoint.done("chunk", "all", &chunk_state)?;
 
     Ok(())
 }
@@ -97,8 +134,10 @@ struct ChunkArgs {
 
 #[allow(dead_code)]
 fn main() {
+    kraken2_rs::logging::init_default_logging();
     let args = ChunkArgs::parse();
     if let Err(e) = run(args.db_args, args.required_capacity) {
         eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(e.as_ref()) as i32);
     }
 }
@@ -0,0 +1,119 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::classifier::Classifier;
+use kraken2_rs::stats::report_name;
+use std::io::{self, Result};
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Classify sequences given on the command line",
+    long_about = "Classify sequences given on the command line without creating files, printing the taxid, name and lineage of each"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// FASTA text with one or more records (">id" lines followed by sequence lines)
+    #[clap(long, value_parser)]
+    pub fasta_string: Option<String>,
+
+    /// Confidence score threshold
+    #[clap(
+        short = 'T',
+        long = "confidence-threshold",
+        value_parser,
+        default_value_t = 0.0
+    )]
+    pub confidence_threshold: f64,
+
+    /// The minimum number of hit groups needed for a call.
+    #[clap(
+        short = 'g',
+        long = "minimum-hit-groups",
+        value_parser,
+        default_value_t = 2
+    )]
+    pub minimum_hit_groups: usize,
+
+    /// Memory-map taxo.k2d and the hash_*.k2d files instead of reading them into memory
+    #[clap(long, value_parser, default_value_t = false)]
+    pub memory_mapping: bool,
+
+    /// Sequences to classify, named seq_1, seq_2, ... in the output
+    pub sequences: Vec<String>,
+}
+
+/// 解析 `--fasta-string` 中的记录, 多行序列拼接为一条
+fn parse_fasta_string(text: &str) -> Result<Vec<(String, String)>> {
+    let mut records: Vec<(String, String)> = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(header) = line.strip_prefix('>') {
+            let id = header.split_whitespace().next().unwrap_or_default();
+            records.push((id.to_string(), String::new()));
+        } else if let Some((_, seq)) = records.last_mut() {
+            seq.push_str(line);
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--fasta-string must start with a '>' header line",
+            ));
+        }
+    }
+    Ok(records)
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut records: Vec<(String, String)> = args
+        .sequences
+        .iter()
+        .enumerate()
+        .map(|(i, seq)| (format!("seq_{}", i + 1), seq.clone()))
+        .collect();
+    if let Some(text) = &args.fasta_string {
+        records.extend(parse_fasta_string(text)?);
+    }
+    if records.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no sequences given, pass them as arguments or with --fasta-string",
+        ));
+    }
+
+    let start = Instant::now();
+    let classifier = if args.memory_mapping {
+        Classifier::map_database(&args.database)?
+    } else {
+        Classifier::from_database(&args.database)?
+    }
+    .with_confidence_threshold(args.confidence_threshold)
+    .with_minimum_hit_groups(args.minimum_hit_groups);
+    info!("database loaded in {:?}", start.elapsed());
+
+    for (id, seq) in &records {
+        let result = classifier.classify_read(seq.as_bytes());
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            id,
+            if result.is_classified() { "C" } else { "U" },
+            result.taxid,
+            report_name(classifier.taxon_name(result.taxid)),
+            report_name(&classifier.lineage(result.taxid).join("; "))
+        );
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
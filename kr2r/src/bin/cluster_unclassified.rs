@@ -0,0 +1,202 @@
+use clap::Parser;
+use kraken2_rs::cluster::Clusterer;
+use rayon::prelude::*;
+use seqkmer::{scan_sequence, FastxReader, Meros, OptionPair, Reader};
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Cluster unclassified reads by minimizer similarity and write a representative per cluster",
+    long_about = "Cluster the reads written by --unclassified-out (or any FASTA/FASTQ) greedily by shared minimizers, longest reads first, and write one representative sequence per cluster with the cluster size, as a starting point for finding organisms missing from the database"
+)]
+pub struct Args {
+    /// Prefix of the output files: <prefix>.fa (representatives), <prefix>.tsv (clusters) and
+    /// <prefix>.members.tsv (cluster of every read)
+    #[clap(short, long, value_parser, required = true)]
+    pub output: PathBuf,
+
+    /// Share of a read's minimizers that a representative must contain for the read to join its cluster.
+    #[clap(short = 's', long, value_parser, default_value_t = 0.5)]
+    pub min_similarity: f64,
+
+    /// Only write the representatives of clusters with at least this many reads.
+    #[clap(long, value_parser, default_value_t = 2)]
+    pub min_size: usize,
+
+    /// Skip reads shorter than this.
+    #[clap(long, value_parser, default_value_t = 50)]
+    pub min_length: usize,
+
+    /// Length of the k-mers.
+    #[clap(short = 'k', long, value_parser, default_value_t = 35)]
+    pub k_mer: usize,
+
+    /// Length of the minimizers (at most 31).
+    #[clap(short = 'l', long, value_parser, default_value_t = 31)]
+    pub l_mer: usize,
+
+    /// The number of threads to use.
+    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    pub num_threads: usize,
+
+    /// Unclassified reads (FASTA or FASTQ, plain or compressed); mates of paired reads are
+    /// clustered as separate reads
+    #[clap(required = true)]
+    pub input_files: Vec<PathBuf>,
+}
+
+struct Read {
+    id: String,
+    seq: Vec<u8>,
+    minimizers: Vec<u64>,
+}
+
+/// 读取所有 reads, 并行计算去重后的 minimizer
+fn read_sequences(args: &Args, meros: &Meros) -> Result<Vec<Read>> {
+    let mut reads = Vec::new();
+    for (file_index, path) in args.input_files.iter().enumerate() {
+        let mut reader = FastxReader::from_paths(OptionPair::Single(path), file_index, 0)?;
+        while let Some(batch) = reader.next()? {
+            let batch: Vec<Read> = batch
+                .par_iter()
+                .filter_map(|base| {
+                    let OptionPair::Single(seq) = &base.body else {
+                        return None;
+                    };
+                    // 多行 FASTA 中的换行不属于序列
+                    let seq: Vec<u8> = seq
+                        .iter()
+                        .copied()
+                        .filter(|c| !c.is_ascii_whitespace())
+                        .collect();
+                    if seq.len() < args.min_length {
+                        return None;
+                    }
+                    let mut scan = scan_sequence(base, meros);
+                    let mut minimizers: Vec<u64> = Vec::new();
+                    scan.body.apply_mut(|m_iter| {
+                        minimizers.extend(m_iter.map(|(_, hash_key)| hash_key));
+                    });
+                    minimizers.sort_unstable();
+                    minimizers.dedup();
+                    (!minimizers.is_empty()).then(|| Read {
+                        id: base.header.id.clone(),
+                        seq,
+                        minimizers,
+                    })
+                })
+                .collect();
+            reads.extend(batch);
+        }
+    }
+    Ok(reads)
+}
+
+fn output_path(prefix: &Path, suffix: &str) -> PathBuf {
+    let mut name = prefix.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let start = Instant::now();
+    if !(args.min_similarity > 0.0 && args.min_similarity <= 1.0) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--min-similarity must be between 0 and 1",
+        ));
+    }
+    if args.l_mer == 0 || args.l_mer > 31 || args.k_mer < args.l_mer {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "-l must be between 1 and 31 and -k at least -l",
+        ));
+    }
+    let meros = Meros::new(args.k_mer, args.l_mer, None, None, None);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.num_threads)
+        .build()
+        .map_err(Error::other)?;
+    let mut reads = pool.install(|| read_sequences(&args, &meros))?;
+    // 最长的 read 先成为代表序列
+    reads.sort_by_key(|read| std::cmp::Reverse(read.seq.len()));
+
+    let mut clusterer = Clusterer::new(args.min_similarity);
+    for (i, read) in reads.iter().enumerate() {
+        clusterer.add(i, &read.minimizers);
+    }
+    let mut clusters = clusterer.into_clusters();
+    // 大的聚类在前, 相同大小时保持代表序列从长到短的顺序
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.size()));
+
+    if let Some(parent) = args.output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let create = |suffix: &str| -> Result<BufWriter<File>> {
+        Ok(BufWriter::new(File::create(output_path(
+            &args.output,
+            suffix,
+        ))?))
+    };
+    let mut fasta = create(".fa")?;
+    let mut table = create(".tsv")?;
+    let mut members = create(".members.tsv")?;
+    writeln!(table, "cluster\tsize\trepresentative\tlength")?;
+    writeln!(members, "read_id\tcluster\tsimilarity")?;
+    let mut written = 0;
+    for (number, cluster) in clusters.iter().enumerate() {
+        let name = format!("cluster_{}", number + 1);
+        let representative = &reads[cluster.representative];
+        writeln!(
+            table,
+            "{}\t{}\t{}\t{}",
+            name,
+            cluster.size(),
+            representative.id,
+            representative.seq.len()
+        )?;
+        for &(read, similarity) in &cluster.members {
+            writeln!(members, "{}\t{}\t{:.3}", reads[read].id, name, similarity)?;
+        }
+        if cluster.size() >= args.min_size {
+            writeln!(
+                fasta,
+                ">{} size={} representative={}",
+                name,
+                cluster.size(),
+                representative.id
+            )?;
+            fasta.write_all(&representative.seq)?;
+            fasta.write_all(b"\n")?;
+            written += 1;
+        }
+    }
+    fasta.flush()?;
+    table.flush()?;
+    members.flush()?;
+
+    info!(
+        "{} reads in {} clusters, {} with at least {} reads written, took: {:?}",
+        reads.len(),
+        clusters.len(),
+        written,
+        args.min_size,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
@@ -0,0 +1,321 @@
+use clap::Parser;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::classifier::Classifier;
+use kraken2_rs::inputs::detect_pairs;
+use kraken2_rs::seqout::{is_stream, mate_paths};
+use rayon::prelude::*;
+use seqkmer::{
+    pair_id, AlignmentOptions, OptionPair, PairCheck, PairOptions, PairSync, RecordReader,
+    SeqRecord,
+};
+use std::fs::File;
+use std::io::{self, BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::info;
+
+/// 每批分类的 reads (或 read pairs) 数
+const BATCH_SIZE: usize = 10_000;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Remove host reads by classifying them against a host database",
+    long_about = "Classify reads against a host database and write only the non-host reads (or, with --invert, only the host reads) to new FASTA/FASTQ files"
+)]
+pub struct Args {
+    /// host database directory, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Output file of the kept reads. For paired reads the name must contain '#', which is
+    /// replaced by "_1" and "_2", unless it is a pipe such as >(cmd), which gets both mates
+    /// interleaved. Names ending in .gz are gzip compressed
+    #[clap(short, long, value_parser, required = true)]
+    pub output: String,
+
+    /// Write the host reads instead of the non-host reads
+    #[clap(long, value_parser, default_value_t = false)]
+    pub invert: bool,
+
+    /// Only count reads classified to this taxid (or below it) as host, default: every classified read
+    #[clap(long, value_parser)]
+    pub host_taxid: Option<u64>,
+
+    /// Enable paired-end processing.
+    #[clap(short = 'P', long = "paired-end-processing", action)]
+    pub paired_end_processing: bool,
+
+    /// Process pairs with mates in the same file.
+    #[clap(short = 'S', long = "single-file-pairs", action)]
+    pub single_file_pairs: bool,
+
+    /// Mate validation for paired reads: strict, repair or off.
+    #[clap(long, value_parser, default_value = "strict")]
+    pub pair_check: PairCheck,
+
+    /// Minimum quality score for FASTQ data (Phred+33), lower bases are masked as ambiguous before minimizer extraction
+    #[clap(
+        short = 'Q',
+        long = "minimum-quality-score",
+        visible_alias = "minimum-base-quality",
+        value_parser,
+        default_value_t = 0
+    )]
+    pub minimum_quality_score: u8,
+
+    /// Confidence score threshold
+    #[clap(
+        short = 'T',
+        long = "confidence-threshold",
+        value_parser,
+        default_value_t = 0.0
+    )]
+    pub confidence_threshold: f64,
+
+    /// The minimum number of hit groups needed for a call.
+    #[clap(
+        short = 'g',
+        long = "minimum-hit-groups",
+        value_parser,
+        default_value_t = 2
+    )]
+    pub minimum_hit_groups: usize,
+
+    /// The number of threads to use.
+    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    pub num_threads: usize,
+
+    /// Memory-map taxo.k2d and the hash_*.k2d files instead of reading them into memory
+    #[clap(long, value_parser, default_value_t = false)]
+    pub memory_mapping: bool,
+
+    /// One FASTA/FASTQ (or BAM/CRAM) file, or the R1 and R2 files of paired reads
+    pub input_files: Vec<String>,
+}
+
+fn create_writer(path: &Path) -> Result<Box<dyn Write>> {
+    let file = BufWriter::new(File::create(path)?);
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        Ok(Box::new(GzEncoder::new(file, Compression::default())))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// 低于 `min_quality` 的碱基替换为 'x', 与分类时的 -Q 相同
+fn masked_seq(record: &SeqRecord, min_quality: u8) -> Vec<u8> {
+    let mut seq = record.seq.clone();
+    if let (Some(qual), true) = (&record.qual, min_quality > 0) {
+        for (base, &q) in seq.iter_mut().zip(qual.iter()) {
+            if q.saturating_sub(b'!') < min_quality {
+                *base = b'x';
+            }
+        }
+    }
+    seq
+}
+
+struct Deplete<'a> {
+    classifier: &'a Classifier,
+    host_taxid: Option<u32>,
+    min_quality: u8,
+}
+
+impl Deplete<'_> {
+    fn is_host(&self, records: &OptionPair<SeqRecord>) -> bool {
+        let result = match records {
+            OptionPair::Single(record) => self
+                .classifier
+                .classify_read(&masked_seq(record, self.min_quality)),
+            OptionPair::Pair(record1, record2) => self.classifier.classify_pair(
+                &masked_seq(record1, self.min_quality),
+                &masked_seq(record2, self.min_quality),
+            ),
+        };
+        if !result.is_classified() {
+            return false;
+        }
+        let taxonomy = self.classifier.taxonomy();
+        self.host_taxid.is_none_or(|host| {
+            taxonomy.is_a_ancestor_of_b(host, taxonomy.get_internal_id(result.taxid))
+        })
+    }
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    if !args.paired_end_processing && !args.single_file_pairs && args.input_files.len() == 2 {
+        if let Some(inputs) = detect_pairs(&args.input_files) {
+            args.input_files = inputs;
+            args.paired_end_processing = true;
+        }
+    }
+    let interleaved = args.paired_end_processing && args.single_file_pairs;
+    let paired = args.paired_end_processing;
+    let expected = if paired && !interleaved { 2 } else { 1 };
+    if args.input_files.len() != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "expected {} input file(s), got {} (use -P for R1 and R2 files, -S for interleaved pairs)",
+                expected,
+                args.input_files.len()
+            ),
+        ));
+    }
+    // 写入 FIFO 或 /dev/fd/N 时每批 flush 一次, paired reads 没有 `#` 时交替写入同一个流
+    let streaming = is_stream(&args.output);
+    let output_paths = if streaming && paired && !args.output.contains('#') {
+        OptionPair::Single(PathBuf::from(&args.output))
+    } else {
+        mate_paths(&args.output, paired)?
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.num_threads)
+        .build()
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let start = Instant::now();
+    let classifier = if args.memory_mapping {
+        Classifier::map_database(&args.database)?
+    } else {
+        Classifier::from_database(&args.database)?
+    }
+    .with_confidence_threshold(args.confidence_threshold)
+    .with_minimum_hit_groups(args.minimum_hit_groups);
+    info!("database loaded in {:?}", start.elapsed());
+
+    let host_taxid = match args.host_taxid {
+        Some(taxid) => match classifier.taxonomy().get_internal_id(taxid) {
+            0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("host taxid {} is not in the database taxonomy", taxid),
+                ))
+            }
+            internal_id => Some(internal_id),
+        },
+        None => None,
+    };
+    let deplete = Deplete {
+        classifier: &classifier,
+        host_taxid,
+        min_quality: args.minimum_quality_score,
+    };
+
+    let alignment = AlignmentOptions::default();
+    let mut readers = args
+        .input_files
+        .iter()
+        .map(|file| RecordReader::from_path_with_alignment(file, &alignment, paired))
+        .collect::<Result<Vec<_>>>()?;
+    let last = readers.len() - 1;
+    let mut sync = PairSync::new(PairOptions {
+        interleaved,
+        check: args.pair_check,
+    });
+    let mut writers = output_paths.map(|path| create_writer(path))?;
+
+    let (mut total, mut host) = (0u64, 0u64);
+    loop {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while batch.len() < BATCH_SIZE {
+            let records = if paired {
+                sync.next_pair(
+                    |mate| readers[mate.min(last)].next_record(),
+                    |record| pair_id(&record.header),
+                )?
+                .map(|(record1, record2)| OptionPair::Pair(record1, record2))
+            } else {
+                readers[0].next_record()?.map(OptionPair::Single)
+            };
+            match records {
+                Some(records) => batch.push(records),
+                None => break,
+            }
+        }
+        if batch.is_empty() {
+            break;
+        }
+
+        let hosts: Vec<bool> = pool.install(|| {
+            batch
+                .par_iter()
+                .map(|records| deplete.is_host(records))
+                .collect()
+        });
+        for (records, is_host) in batch.iter().zip(hosts) {
+            total += 1;
+            if is_host {
+                host += 1;
+            }
+            if is_host != args.invert {
+                continue;
+            }
+            match (&mut writers, records) {
+                (OptionPair::Single(writer), OptionPair::Single(record)) => {
+                    record.write_to(writer, "")?
+                }
+                (OptionPair::Pair(writer1, writer2), OptionPair::Pair(record1, record2)) => {
+                    record1.write_to(writer1, "")?;
+                    record2.write_to(writer2, "")?;
+                }
+                (OptionPair::Single(writer), OptionPair::Pair(record1, record2)) => {
+                    record1.write_to(writer, "")?;
+                    record2.write_to(writer, "")?;
+                }
+                _ => unreachable!("writers and records have the same pairing"),
+            }
+        }
+        if streaming {
+            match &mut writers {
+                OptionPair::Single(writer) => writer.flush()?,
+                OptionPair::Pair(writer1, writer2) => {
+                    writer1.flush()?;
+                    writer2.flush()?;
+                }
+            }
+        }
+    }
+    match &mut writers {
+        OptionPair::Single(writer) => writer.flush()?,
+        OptionPair::Pair(writer1, writer2) => {
+            writer1.flush()?;
+            writer2.flush()?;
+        }
+    }
+    drop(writers);
+
+    if paired && sync.stats().orphan_count() > 0 {
+        info!("{}", sync.stats());
+    }
+    let kept = if args.invert { host } else { total - host };
+    info!(
+        "{} {}: {} host ({:.2}%), {} written to {}",
+        total,
+        if paired { "pairs" } else { "reads" },
+        host,
+        if total > 0 {
+            host as f64 * 100.0 / total as f64
+        } else {
+            0.0
+        },
+        kept,
+        args.output
+    );
+    info!("deplete took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
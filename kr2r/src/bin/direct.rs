@@ -1,19 +1,50 @@
 use clap::Parser;
-use kraken2_rs::classify::process_hitgroup;
-use kraken2_rs::compact_hash::{CHTable, Compact, HashConfig, Row};
+use dashmap::DashMap;
+use kraken2_rs::args::{
+    check_stage_threads, parse_database, parse_fraction, parse_io_threads, parse_max_rank,
+    parse_size, parse_stop_when, stage_threads, OutputCompression, OutputFormat, StopWhen,
+};
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::classify::{format_call, process_hitgroup, RankCeiling, StopTracker};
+use kraken2_rs::compact_hash::{CHTable, Compact, HashConfig, Row, ShardChecksums};
+use kraken2_rs::domains::{
+    apply_qc_gate, print_domain_summaries, write_run_summary, DomainSummary, QcGate, RUN_SUMMARY,
+};
+use kraken2_rs::error::Kr2rError;
+use kraken2_rs::inputs::{detect_pairs, expand_inputs};
+use kraken2_rs::logging::per_sec;
+use kraken2_rs::read_index::create_read_output_writer;
 use kraken2_rs::readcounts::{TaxonCounters, TaxonCountersDash};
-use kraken2_rs::report::report_kraken_style;
+#[cfg(feature = "cloud")]
+use kraken2_rs::remote_db::RemoteDatabase;
+use kraken2_rs::report::{report_kraken_style, report_krona, ReportHeader};
+use kraken2_rs::result_cache::{database_fingerprint, ResultCache, SampleSummary};
+use kraken2_rs::segments::write_segments_bed;
+use kraken2_rs::seqout::{check_rereadable, SeqOutput};
+use kraken2_rs::shm::shm_cache_files;
+use kraken2_rs::split_output::SplitWriter;
+use kraken2_rs::stats::{
+    check_unclassified_taxid, parse_seq_size, set_name_normalization, set_unclassified,
+    write_length_stats, write_qc_stats, LengthHistogram, NameNormalization, ReadQc,
+    TaxonLengthStatsDash, TaxonQcStatsDash, DEFAULT_LENGTH_BIN_SIZE,
+};
 use kraken2_rs::taxonomy::Taxonomy;
-use kraken2_rs::utils::{create_sample_file, find_and_sort_files, get_lastest_file_index};
+use kraken2_rs::utils::{
+    create_sample_file, format_bytes, low_power_threads, lower_priority, memlock_diagnostic,
+    reserve_file_indexes, resolve_quality_encoding, LOW_POWER_NICE,
+};
 use kraken2_rs::{HitGroup, IndexOptions};
-use seqkmer::{read_parallel, Base, FastxReader, Meros, MinimizerIterator, OptionPair, Reader};
-use std::collections::HashMap;
-use std::fs::File;
+use seqkmer::{
+    is_stdin_path, read_parallel_with, AlignmentOptions, Base, FastxReader, Meros,
+    MinimizerIterator, OptionPair, PairCheck, PairMode, PairOptions, QualityEncoding, Reader,
+};
+use std::fs::{self, create_dir_all, File, OpenOptions};
 use std::io::{self, BufWriter, Write};
 use std::io::{Error, ErrorKind, Result};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
 
 #[derive(Parser, Debug, Clone)]
 #[clap(
@@ -22,8 +53,8 @@ use std::time::Instant;
     long_about = "Directly load all hash tables for classification annotation"
 )]
 pub struct Args {
-    /// database hash chunk directory and other files
-    #[arg(long = "db", required = true)]
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
     pub database: PathBuf,
 
     /// File path for outputting normal Kraken output.
@@ -38,15 +69,44 @@ pub struct Args {
     #[clap(short = 'S', long = "single-file-pairs", action)]
     pub single_file_pairs: bool,
 
-    /// Minimum quality score for FASTQ data.
+    /// Treat every input file as single-end. Without -P or -S, FASTQ files named as R1/R2 mates
+    /// (e.g. x_R1_001.fastq.gz and x_R2_001.fastq.gz) are paired automatically
+    #[clap(long, value_parser, default_value_t = false)]
+    pub unpaired: bool,
+
+    /// Mate validation for paired reads: "strict" stops on mismatched read ids or read counts,
+    /// "repair" drops reads without a mate and reports the counts, "off" pairs reads in order.
+    #[clap(long, value_parser, default_value = "strict")]
+    pub pair_check: PairCheck,
+
+    /// Scoring of paired reads: "range" scores the mates as two ranges of one read, "concat" joins them with an N spacer into one sequence like old Kraken versions.
+    #[clap(long, value_parser, default_value = "range")]
+    pub pair_mode: PairMode,
+
+    /// Minimum quality score for FASTQ data, lower bases are masked as ambiguous before minimizer extraction
     #[clap(
         short = 'Q',
         long = "minimum-quality-score",
+        visible_alias = "minimum-base-quality",
         value_parser,
         default_value_t = 0
     )]
     pub minimum_quality_score: i32,
 
+    /// FASTQ quality encoding used by -Q and the quality statistics: auto, phred33, phred64 or solexa.
+    /// "auto" detects it from the first reads of each sample.
+    #[clap(long, value_parser, default_value = "auto")]
+    pub quality_encoding: QualityEncoding,
+
+    /// Only classify unmapped reads of BAM/CRAM inputs (both mates unmapped with -P -S),
+    /// e.g. the reads left after aligning to a host genome.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub unmapped_only: bool,
+
+    /// Reference FASTA (with a .fai index) used to decode CRAM inputs that contain mapped reads.
+    #[clap(long, value_parser)]
+    pub cram_reference: Option<PathBuf>,
+
     /// Confidence score threshold.
     #[clap(
         short = 'T',
@@ -64,6 +124,10 @@ pub struct Args {
     #[clap(short = 'z', long, value_parser, default_value_t = false)]
     pub report_zero_counts: bool,
 
+    /// Start each kreport with "#" comment lines: sample, database and its hash, command line, version and date
+    #[clap(long, value_parser, default_value_t = false)]
+    pub report_headers: bool,
+
     /// The minimum number of hit groups needed for a call.
     #[clap(
         short = 'g',
@@ -73,12 +137,168 @@ pub struct Args {
     )]
     pub minimum_hit_groups: usize,
 
+    /// Cap all calls at this rank (e.g. genus), more specific calls are reported at their ancestor of this rank
+    #[clap(long, value_parser = parse_max_rank)]
+    pub max_rank: Option<String>,
+
+    /// Call the most specific clade holding at least this fraction of the hit k-mers (0-1),
+    /// instead of the default root-to-leaf scoring; 1.0 gives the LCA of all hits
+    #[clap(long, value_parser = parse_fraction)]
+    pub min_clade_support: Option<f64>,
+
     /// The number of threads to use.
     #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
     pub num_threads: usize,
 
+    /// Threads that process the reads of each stage (minimizers, hash table lookups, classification)
+    /// [default: --num-threads minus --io-threads]
+    #[clap(long, value_parser)]
+    pub compute_threads: Option<usize>,
+
+    /// Threads that read the input and write the output of each stage, in a pool of their own so
+    /// busy compute threads don't hold up the writer; at least 2 (one reader, one writer)
+    #[clap(long, value_parser = parse_io_threads, default_value_t = 2)]
+    pub io_threads: usize,
+
+    /// Threads used to decompress each input file: BGZF (bgzip) files are decompressed in parallel, other compressed files in a separate reader thread. 1 decompresses inline
+    #[clap(long, value_parser, default_value_t = 4)]
+    pub decompress_threads: usize,
+
+    /// Memory-map taxo.k2d and the hash_*.k2d files instead of reading them into memory, so concurrent runs on one node share the page cache
+    #[clap(long, value_parser, default_value_t = false)]
+    pub memory_mapping: bool,
+
+    /// Keep the hash_*.k2d files in shared memory (/dev/shm) after the run, so later runs on the
+    /// node map them without reading the database again (implies --memory-mapping).
+    /// Remove /dev/shm/kraken2_rs-* to free the memory
+    #[clap(long, value_parser, default_value_t = false)]
+    pub shm_cache: bool,
+
+    /// Skip checking the hash_*.k2d files against the checksums stored in hash_config.k2d
+    /// while loading them (checking reads every shard once more with --memory-mapping)
+    #[clap(long, value_parser, default_value_t = false)]
+    pub no_verify: bool,
+
+    /// Lock the loaded hash tables in memory (mlock) so they are never paged out during
+    /// classification; fails with the RLIMIT_MEMLOCK (`ulimit -l`) limit when they don't fit
+    #[clap(long, value_parser, default_value_t = false)]
+    pub mlock: bool,
+
+    /// Cache each sample's results in this directory, keyed by the input checksums, the database and
+    /// the parameters; a sample seen before is not classified again and its cached output is copied
+    #[clap(long, value_parser)]
+    pub result_cache: Option<PathBuf>,
+
+    /// With a `--db` URL (s3://, gs:// or https://): keep the downloaded database files and hash
+    /// table blocks in this directory between runs (default: a directory in the temp directory)
+    #[clap(long, value_parser)]
+    pub db_cache: Option<PathBuf>,
+
+    /// With a `--db` URL: disk space for the cached hash table blocks (e.g. '16G'); the least
+    /// recently used blocks are removed, '0K' keeps them only in memory
+    #[clap(long, value_parser = parse_size, default_value = "16G")]
+    pub db_cache_size: usize,
+
+    /// Write per-taxon read-length histograms (output_*.lenstats), requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub read_length_stats: bool,
+
+    /// Bin width used by the read-length histograms
+    #[clap(long, value_parser, default_value_t = DEFAULT_LENGTH_BIN_SIZE)]
+    pub length_bin_size: usize,
+
+    /// Write per-taxon mean GC and base quality (output_*.qc), requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub qc_stats: bool,
+
+    /// Write Krona text and HTML charts (output_*.krona.txt/.html), requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub report_krona: bool,
+
+    /// Write a cumulative kreport to <output-dir>/timeseries every N reads (0 disables)
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub report_every_reads: usize,
+
+    /// Write a cumulative kreport to <output-dir>/timeseries every T seconds (0 disables)
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub report_every_secs: u64,
+
+    /// Stop early once enough reads are assigned to a taxon (or its descendants),
+    /// e.g. "taxid=2697049 reads>=10"; the reports then count only the reads classified before the stop
+    #[clap(long, value_parser = parse_stop_when)]
+    pub stop_when: Option<StopWhen>,
+
+    /// Mark each sample pass/fail in the summary and run_summary.json, e.g.
+    /// "reads>=100000,classified>=50,host<=10" (reads, classified, host or a domain name)
+    #[clap(long, value_parser)]
+    pub qc_gate: Option<QcGate>,
+
+    /// Exit with status 7 after writing all outputs when a sample fails --qc-gate
+    #[clap(long, value_parser, default_value_t = false)]
+    pub qc_fail_exit: bool,
+
+    /// Print scientific names instead of just taxids in the output, e.g. "name (taxid N)"
+    #[clap(long, value_parser, default_value_t = false)]
+    pub use_names: bool,
+
+    /// Taxid written for unclassified reads in the output and the kreports, e.g. a sentinel
+    /// node such as 12908 ("unclassified sequences") for schemas without taxid 0
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub unclassified_taxid: u64,
+
+    /// Name written for unclassified reads in the output (--use-names, json) and the kreports
+    #[clap(long, value_parser, default_value = "unclassified")]
+    pub unclassified_label: String,
+
+    /// Normalize taxon names in the output and the reports: "none", "nfc" (Unicode NFC, so names
+    /// from different sources compare equal) or "ascii" (accents removed, other non-ASCII as '?').
+    /// Tabs, newlines and other control characters in names are always written as \t, \n, \u{..}
+    #[clap(long, value_parser, default_value = "none")]
+    pub name_normalization: NameNormalization,
+
+    /// Write classified reads to this file, with " kraken:taxid|N" appended to the header.
+    /// For paired reads the name must contain '#', which is replaced by "_1" and "_2".
+    #[clap(long, value_parser)]
+    pub classified_out: Option<String>,
+
+    /// Write unclassified reads to this file ('#' is substituted as for --classified-out).
+    #[clap(long, value_parser)]
+    pub unclassified_out: Option<String>,
+
+    /// Compress the per-read output, the kreports and the --classified-out/--unclassified-out
+    /// files: "none", "gzip" (adds .gz to the file names), "zstd" (adds .zst) or "bgzf" (adds
+    /// .gz and indexes the per-read output by read id for `lookup-reads`)
+    #[clap(long, value_parser, default_value = "none")]
+    pub compress_output: OutputCompression,
+
+    /// Don't write the per-read classification lines (output_*.txt or stdout), only the
+    /// reports and summaries
+    #[clap(long, value_parser, default_value_t = false)]
+    pub no_standard_output: bool,
+
+    /// Also write the classified segments of each read (read, start, end, taxid, score) as
+    /// BED to output_*.bed, for long reads and contigs in genome browsers; requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub segments_bed: bool,
+
+    /// Split the per-read output of each sample into numbered parts of about this size
+    /// (uncompressed, e.g. '10G'), with an output_*.parts.tsv index of the reads in each part
+    #[clap(long, value_parser = parse_size)]
+    pub split_output_size: Option<usize>,
+
+    /// Merge all sequence files found in one directory or tarball input into a single sample
+    /// (R1 and R2 separately with -P); otherwise every file (or pair) is its own sample.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub merge_inputs: bool,
+
+    /// Trade speed for a responsive machine, e.g. a laptop next to a portable sequencer: use at
+    /// most a quarter of the CPUs, decompress inline and run at a lower CPU priority
+    #[clap(long, value_parser, default_value_t = false)]
+    pub low_power: bool,
+
     /// A list of input file paths (FASTA/FASTQ) to be processed by the classify program.
-    /// Supports fasta or fastq format files (e.g., .fasta, .fastq) and gzip compressed files (e.g., .fasta.gz, .fastq.gz).
+    /// Supports fasta or fastq format files (e.g., .fasta, .fastq) and gzip, zstd, bzip2 or xz compressed files (e.g., .fasta.gz, .fastq.zst, .fq.bz2, .fq.xz).
+    /// Directories and tarballs (.tar, .tar.gz, .tgz) are expanded to the sequence files they contain, and - reads from standard input.
     // #[clap(short = 'F', long = "files")]
     pub input_files: Vec<String>,
 }
@@ -101,13 +321,14 @@ fn process_seq(
         let taxid = chtable.get_from_page(index, compacted, partition_index);
         if taxid > 0 {
             let high = u32::combined(compacted, taxid, value_bits);
-            let row = Row::new(high, 0, sort as u32 + 1 + offset as u32);
+            let row = Row::new(high, 0, (sort + 1 + offset) as u64);
             rows.push(row);
         }
     }
     m_iter.size + offset
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_record(
     marker: &mut Base<MinimizerIterator>,
     args: &Args,
@@ -115,11 +336,17 @@ fn process_record(
     chtable: &CHTable,
     hash_config: &HashConfig,
     cur_taxon_counts: &TaxonCountersDash,
+    length_stats: &TaxonLengthStatsDash,
+    qc_stats: &TaxonQcStatsDash,
     classify_counter: &AtomicUsize,
+    stop_tracker: Option<&StopTracker>,
+    rank_ceiling: Option<&RankCeiling>,
+    calls: Option<&DashMap<usize, u64>>,
 ) -> String {
     let id = &marker.header.id.clone();
-    let rows: Vec<Row> = marker
-        .fold(|rows, m_iter, offset| process_seq(rows, m_iter, &hash_config, chtable, offset));
+    let read_qc = args.qc_stats.then(|| ReadQc::from_marker(marker));
+    let rows: Vec<Row> =
+        marker.fold(|rows, m_iter, offset| process_seq(rows, m_iter, hash_config, chtable, offset));
 
     let hits = HitGroup::new(rows, marker.range());
 
@@ -133,6 +360,8 @@ fn process_record(
         required_score,
         args.minimum_hit_groups,
         hash_config.value_mask,
+        rank_ceiling,
+        args.min_clade_support,
     );
 
     hit_data.3.iter().for_each(|(key, value)| {
@@ -142,12 +371,163 @@ fn process_record(
             .merge(value)
             .unwrap();
     });
+
+    if args.read_length_stats {
+        length_stats
+            .entry(hit_data.1)
+            .or_insert_with(|| LengthHistogram::new(args.length_bin_size))
+            .add(parse_seq_size(&seq_len_str));
+    }
+
+    if let Some(qc) = &read_qc {
+        qc_stats
+            .entry(hit_data.1)
+            .or_default()
+            .add(qc, parse_seq_size(&seq_len_str));
+    }
+
+    if let Some(tracker) = stop_tracker {
+        tracker.observe(taxonomy, hit_data.1);
+    }
+
+    if let Some(calls) = calls {
+        calls.insert(marker.header.reads_index, hit_data.1);
+    }
+
+    if args.no_standard_output {
+        return String::new();
+    }
     format!(
         "{}\t{}\t{}\t{}\t{}\n",
-        hit_data.0, id, hit_data.1, seq_len_str, hit_data.2
+        hit_data.0,
+        id,
+        format_call(taxonomy, hit_data.1, args.use_names),
+        seq_len_str,
+        hit_data.2
     )
 }
 
+/// 实时运行时, 定期将当前样本的累计结果写入 timeseries 目录
+struct TimeSeries {
+    dir: PathBuf,
+    every_reads: usize,
+    every: Option<Duration>,
+    next_reads: usize,
+    last: Instant,
+    index_writer: BufWriter<File>,
+}
+
+impl TimeSeries {
+    fn new(args: &Args, output: &Path) -> Result<Option<Self>> {
+        if args.report_every_reads == 0 && args.report_every_secs == 0 {
+            return Ok(None);
+        }
+        let dir = output.join("timeseries");
+        create_dir_all(&dir)?;
+        let index_file = dir.join("timeseries.tsv");
+        let is_new = !index_file.exists();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&index_file)?;
+        let mut index_writer = BufWriter::new(file);
+        if is_new {
+            writeln!(
+                index_writer,
+                "#timestamp_ms\tfile_index\treads\tclassified\treport"
+            )?;
+        }
+        Ok(Some(Self {
+            dir,
+            every_reads: args.report_every_reads,
+            every: (args.report_every_secs > 0)
+                .then(|| Duration::from_secs(args.report_every_secs)),
+            next_reads: args.report_every_reads,
+            last: Instant::now(),
+            index_writer,
+        }))
+    }
+
+    fn due(&self, seqs: usize) -> bool {
+        (self.every_reads > 0 && seqs >= self.next_reads)
+            || self.every.is_some_and(|every| self.last.elapsed() >= every)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write_snapshot(
+        &mut self,
+        args: &Args,
+        taxonomy: &Taxonomy,
+        header: Option<&ReportHeader>,
+        file_index: usize,
+        cur_taxon_counts: &TaxonCountersDash,
+        seqs: usize,
+        classified: usize,
+    ) -> Result<()> {
+        let mut snapshot = TaxonCounters::new();
+        cur_taxon_counts.iter().for_each(|entry| {
+            snapshot
+                .entry(*entry.key())
+                .or_default()
+                .merge(entry.value())
+                .unwrap();
+        });
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let report_name = format!(
+            "output_{}_{}_{}.kreport2{}",
+            file_index,
+            timestamp,
+            seqs,
+            args.compress_output.extension()
+        );
+        report_kraken_style(
+            self.dir.join(&report_name),
+            args.report_zero_counts,
+            args.report_kmer_data,
+            taxonomy,
+            &snapshot,
+            seqs as u64,
+            seqs.saturating_sub(classified) as u64,
+            header
+                .map(|h| h.with_sample(&format!("output_{}", file_index)))
+                .as_ref(),
+        )?;
+        writeln!(
+            self.index_writer,
+            "{}\t{}\t{}\t{}\t{}",
+            timestamp, file_index, seqs, classified, report_name
+        )?;
+        self.index_writer.flush()?;
+
+        if self.every_reads > 0 {
+            while self.next_reads <= seqs {
+                self.next_reads += self.every_reads;
+            }
+        }
+        self.last = Instant::now();
+        Ok(())
+    }
+}
+
+/// 满足 `--stop-when` 条件后不再读取新的序列
+struct StopReader<'a, R: Reader> {
+    inner: &'a mut R,
+    tracker: Option<&'a StopTracker>,
+}
+
+impl<R: Reader> Reader for StopReader<'_, R> {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        if self.tracker.is_some_and(|tracker| tracker.is_stopped()) {
+            return Ok(None);
+        }
+        self.inner.next()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn process_fastx_file<R>(
     args: &Args,
     meros: Meros,
@@ -156,29 +536,52 @@ fn process_fastx_file<R>(
     reader: &mut R,
     chtable: &CHTable,
     taxonomy: &Taxonomy,
-    total_taxon_counts: &mut TaxonCounters,
-) -> io::Result<(usize, usize)>
+    header: Option<&ReportHeader>,
+    stop_tracker: Option<&StopTracker>,
+    rank_ceiling: Option<&RankCeiling>,
+    calls: Option<&DashMap<usize, u64>>,
+) -> io::Result<SampleSummary>
 where
     R: Reader,
 {
     let mut writer: Box<dyn Write + Send> = match &args.output_dir {
+        _ if args.no_standard_output => Box::new(io::sink()),
         Some(ref file_path) => {
             let filename = file_path.join(format!("output_{}.txt", file_index));
-            let file = File::create(filename)?;
-            Box::new(BufWriter::new(file)) as Box<dyn Write + Send>
+            let compression = args.compress_output;
+            match args.split_output_size {
+                Some(size) => Box::new(SplitWriter::new(filename, compression, size as u64)),
+                None => create_read_output_writer(
+                    compression.compressed_path(filename),
+                    compression,
+                    OutputFormat::Kraken,
+                )?,
+            }
         }
         None => Box::new(BufWriter::new(io::stdout())) as Box<dyn Write + Send>,
     };
 
     let cur_taxon_counts = TaxonCountersDash::new();
+    let length_stats = TaxonLengthStatsDash::new();
+    let qc_stats = TaxonQcStatsDash::new();
 
     let seq_counter = AtomicUsize::new(0);
     let classify_counter = AtomicUsize::new(0);
 
-    let _ = read_parallel(
-        reader,
-        args.num_threads,
+    let mut time_series = match &args.output_dir {
+        Some(output) => TimeSeries::new(args, output)?,
+        None => None,
+    };
+
+    let mut reader = StopReader {
+        inner: reader,
+        tracker: stop_tracker,
+    };
+    read_parallel_with(
+        &mut reader,
+        stage_threads(args.num_threads, args.compute_threads, args.io_threads),
         &meros,
+        |seq| args.pair_mode.apply(seq),
         |seqs| {
             let mut buffer = String::new();
             for record in seqs {
@@ -190,7 +593,12 @@ where
                     chtable,
                     &hash_config,
                     &cur_taxon_counts,
+                    &length_stats,
+                    &qc_stats,
                     &classify_counter,
+                    stop_tracker,
+                    rank_ceiling,
+                    calls,
                 );
                 buffer.push_str(&output_line);
             }
@@ -203,45 +611,186 @@ where
                 writer
                     .write_all(res.as_bytes())
                     .expect("Failed to write date to file");
+
+                if let Some(ts) = time_series.as_mut() {
+                    let seqs = seq_counter.load(Ordering::SeqCst);
+                    if ts.due(seqs) {
+                        writer.flush().expect("Failed to flush output");
+                        ts.write_snapshot(
+                            args,
+                            taxonomy,
+                            header,
+                            file_index,
+                            &cur_taxon_counts,
+                            seqs,
+                            classify_counter.load(Ordering::SeqCst),
+                        )
+                        .expect("Failed to write time series report");
+                    }
+                }
             }
         },
-    );
+    )?;
+    writer.flush()?;
 
-    let mut sample_taxon_counts: HashMap<
-        u64,
-        kraken2_rs::readcounts::ReadCounts<
-            hyperloglogplus::HyperLogLogPlus<u64, kraken2_rs::KBuildHasher>,
-        >,
-    > = HashMap::new();
+    let mut sample_taxon_counts = TaxonCounters::new();
     cur_taxon_counts.iter().for_each(|entry| {
-        total_taxon_counts
-            .entry(*entry.key())
-            .or_default()
-            .merge(&entry.value())
-            .unwrap();
         sample_taxon_counts
             .entry(*entry.key())
             .or_default()
-            .merge(&entry.value())
+            .merge(entry.value())
             .unwrap();
     });
 
     let thread_sequences = seq_counter.load(Ordering::SeqCst);
     let thread_classified = classify_counter.load(Ordering::SeqCst);
+    let summary = SampleSummary {
+        sequences: thread_sequences as u64,
+        unclassified: (thread_sequences - thread_classified) as u64,
+        taxon_counts: sample_taxon_counts,
+        length_stats: length_stats.into_iter().collect(),
+        qc_stats: qc_stats.into_iter().collect(),
+    };
     if let Some(output) = &args.output_dir {
-        let filename = output.join(format!("output_{}.kreport2", file_index));
-        report_kraken_style(
-            filename,
-            args.report_zero_counts,
-            args.report_kmer_data,
-            &taxonomy,
-            &sample_taxon_counts,
-            thread_sequences as u64,
-            (thread_sequences - thread_classified) as u64,
+        write_reports(
+            args,
+            output,
+            &format!("output_{}", file_index),
+            taxonomy,
+            header,
+            &summary,
         )?;
     }
 
-    Ok((thread_sequences, thread_sequences - thread_classified))
+    Ok(summary)
+}
+
+/// 写出 `{stem}.kreport2` 以及按参数选择的 lenstats, qc 与 Krona 报告
+fn write_reports(
+    args: &Args,
+    output: &Path,
+    stem: &str,
+    taxonomy: &Taxonomy,
+    header: Option<&ReportHeader>,
+    summary: &SampleSummary,
+) -> Result<()> {
+    report_kraken_style(
+        args.compress_output
+            .compressed_path(output.join(format!("{}.kreport2", stem))),
+        args.report_zero_counts,
+        args.report_kmer_data,
+        taxonomy,
+        &summary.taxon_counts,
+        summary.sequences,
+        summary.unclassified,
+        header.map(|h| h.with_sample(stem)).as_ref(),
+    )?;
+
+    if args.read_length_stats {
+        let filename = output.join(format!("{}.lenstats", stem));
+        write_length_stats(filename, taxonomy, &summary.length_stats)?;
+    }
+
+    if args.qc_stats {
+        let filename = output.join(format!("{}.qc", stem));
+        write_qc_stats(filename, taxonomy, &summary.qc_stats)?;
+    }
+
+    if args.report_krona {
+        report_krona(
+            output,
+            stem,
+            taxonomy,
+            &summary.taxon_counts,
+            summary.unclassified,
+        )?;
+    }
+    Ok(())
+}
+
+fn domain_summary(
+    file_index: usize,
+    taxonomy: &Taxonomy,
+    summary: &SampleSummary,
+) -> DomainSummary {
+    DomainSummary::from_taxon_counts(
+        &format!("output_{}", file_index),
+        taxonomy,
+        &summary.taxon_counts,
+        summary.unclassified,
+    )
+}
+
+/// 缓存键中的参数: 除输入, 输出位置与资源相关的选项外的所有参数
+fn result_cache_params(args: &Args) -> String {
+    let mut args = args.clone();
+    args.database = PathBuf::new();
+    args.output_dir = None;
+    args.input_files.clear();
+    args.num_threads = 0;
+    args.compute_threads = None;
+    args.io_threads = 0;
+    args.decompress_threads = 0;
+    args.memory_mapping = false;
+    args.shm_cache = false;
+    args.no_verify = false;
+    args.mlock = false;
+    args.low_power = false;
+    args.result_cache = None;
+    args.db_cache = None;
+    args.db_cache_size = 0;
+    args.merge_inputs = false;
+    args.unpaired = false;
+    args.report_headers = false;
+    format!("{:?}", args)
+}
+
+/// 与按样本缓存结果不兼容的选项
+fn result_cache_conflict(args: &Args) -> Option<&'static str> {
+    if args.output_dir.is_none() {
+        Some("output to stdout, use --output-dir")
+    } else if args.stop_when.is_some() {
+        Some("--stop-when")
+    } else if args.report_every_reads > 0 || args.report_every_secs > 0 {
+        Some("--report-every-reads or --report-every-secs")
+    } else if args.classified_out.is_some() || args.unclassified_out.is_some() {
+        Some("--classified-out or --unclassified-out")
+    } else if args.no_standard_output {
+        Some("--no-standard-output")
+    } else if args.split_output_size.is_some() {
+        Some("--split-output-size")
+    } else if args.compress_output == OutputCompression::Bgzf {
+        Some("--compress-output bgzf")
+    } else {
+        None
+    }
+}
+
+/// 与 `--segments-bed` 不兼容的选项, 片段由写完的 output_<n>.txt 得到
+fn segments_bed_conflict(args: &Args) -> Option<&'static str> {
+    if args.output_dir.is_none() {
+        Some("output to stdout, use --output-dir")
+    } else if args.no_standard_output {
+        Some("--no-standard-output")
+    } else if args.split_output_size.is_some() {
+        Some("--split-output-size")
+    } else {
+        None
+    }
+}
+
+/// `--segments-bed`: 由样本的 per-read 输出写出 output_<n>.bed
+fn write_sample_segments(args: &Args, output: &Path, file_index: usize, k: usize) -> Result<()> {
+    let output_file = args
+        .compress_output
+        .compressed_path(output.join(format!("output_{}.txt", file_index)));
+    let bed_file = output.join(format!("output_{}.bed", file_index));
+    let segments = write_segments_bed(&output_file, &bed_file, k)?;
+    info!(
+        "file {}: {} segments in {:?}",
+        file_index, segments, bed_file
+    );
+    Ok(())
 }
 
 fn process_files(
@@ -250,42 +799,136 @@ fn process_files(
     hash_config: HashConfig,
     chtable: &CHTable,
     taxonomy: &Taxonomy,
+    result_cache: Option<&ResultCache>,
+    database_name: &str,
 ) -> Result<()> {
-    let (mut file_index, mut file_writer) = if let Some(out_dir) = &args.output_dir {
-        let file_path = out_dir.join("sample_file.map");
-        let file_writer = create_sample_file(&file_path);
-        let file_index = get_lastest_file_index(&file_path)?;
-        (
-            file_index,
-            Box::new(BufWriter::new(file_writer)) as Box<dyn Write + Send>,
-        )
+    let cache_params = result_cache_params(&args);
+    let header = if args.report_headers {
+        Some(ReportHeader::new(
+            database_name,
+            &format!("{:016x}", database_fingerprint(&args.database)?),
+        ))
     } else {
-        (
-            0,
-            Box::new(BufWriter::new(io::stdout())) as Box<dyn Write + Send>,
-        )
+        None
     };
+    let mut file_writer = if let Some(out_dir) = &args.output_dir {
+        let file_writer = create_sample_file(out_dir.join("sample_file.map"))?;
+        Box::new(BufWriter::new(file_writer)) as Box<dyn Write + Send>
+    } else {
+        // 标准输出只有每个 read 的结果, 便于在管道中使用
+        Box::new(BufWriter::new(io::stderr())) as Box<dyn Write + Send>
+    };
+
+    let stop_tracker = args
+        .stop_when
+        .as_ref()
+        .map(|rule| StopTracker::new(rule, taxonomy));
+    let rank_ceiling = args
+        .max_rank
+        .as_ref()
+        .map(|rank| RankCeiling::new(rank, taxonomy));
+
+    let paired = args.paired_end_processing && !args.single_file_pairs;
+    let sample_count = if paired {
+        args.input_files.len() / 2
+    } else {
+        args.input_files.len()
+    };
+    let seq_output = SeqOutput::new(
+        args.classified_out.clone(),
+        args.unclassified_out.clone(),
+        sample_count > 1,
+    );
+    let pairs = PairOptions {
+        interleaved: args.paired_end_processing && args.single_file_pairs,
+        check: args.pair_check,
+    };
+    let alignment = AlignmentOptions {
+        unmapped_only: args.unmapped_only,
+        reference: args.cram_reference.clone(),
+    };
+    let seq_output = seq_output.map(|seq_output| {
+        seq_output
+            .with_pairs(pairs)
+            .with_alignment(alignment.clone())
+            .with_compression(args.compress_output)
+    });
+    if let Some(seq_output) = &seq_output {
+        check_rereadable(&args.input_files)?;
+        seq_output.check(paired || pairs.interleaved)?;
+    }
 
     let mut process_funcs = |files: Vec<&[String]>| -> Result<()> {
+        // 同时写一个输出目录的 direct 从计数器得到互不重叠的序号
+        let mut file_index = match &args.output_dir {
+            Some(out_dir) => {
+                reserve_file_indexes(&out_dir.join("sample_file.map"), files.len())? - 1
+            }
+            None => 0,
+        };
         let file_bits = (((files.len() + file_index) as f64).log2().ceil() as usize).max(1);
         if file_bits > hash_config.value_bits {
             panic!("The number of files is too large to process.");
         }
 
-        let mut total_taxon_counts = TaxonCounters::new();
-        let mut total_seqs: usize = 0;
-        let mut total_unclassified: usize = 0;
+        let mut totals = SampleSummary::default();
+        let mut domain_summaries = Vec::new();
         for file_pair in files {
+            if stop_tracker.as_ref().is_some_and(|t| t.is_stopped()) {
+                info!("stop condition met, skip remaining input files");
+                break;
+            }
             file_index += 1;
 
             writeln!(file_writer, "{}\t{}", file_index, file_pair.join(","))?;
             file_writer.flush().unwrap();
 
+            // 标准输入的内容不能预先计算 hash, 不使用缓存
+            let cache_key = match result_cache {
+                Some(cache) if !file_pair.iter().any(is_stdin_path) => {
+                    Some(cache.sample_key(file_pair, &cache_params)?)
+                }
+                _ => None,
+            };
+            if let (Some(cache), Some(key), Some(output)) =
+                (result_cache, &cache_key, &args.output_dir)
+            {
+                if let Some((cached_output, summary)) = cache.load(key)? {
+                    info!("file {}: reuse cached result {}", file_index, key);
+                    fs::copy(
+                        cached_output,
+                        args.compress_output
+                            .compressed_path(output.join(format!("output_{}.txt", file_index))),
+                    )?;
+                    write_reports(
+                        &args,
+                        output,
+                        &format!("output_{}", file_index),
+                        taxonomy,
+                        header.as_ref(),
+                        &summary,
+                    )?;
+                    if args.segments_bed {
+                        write_sample_segments(&args, output, file_index, meros.k_mer)?;
+                    }
+                    domain_summaries.push(domain_summary(file_index, taxonomy, &summary));
+                    totals.merge(&summary);
+                    continue;
+                }
+            }
+
             let score = args.minimum_quality_score;
             let paths = OptionPair::from_slice(file_pair);
-            let mut reader = FastxReader::from_paths(paths, file_index, score)?;
+            let encoding =
+                resolve_quality_encoding(args.quality_encoding, &file_pair[0], file_index)?;
+            let mut reader = FastxReader::from_paths_with_options(
+                paths, file_index, score, pairs, encoding, &alignment,
+            )
+            .map_err(|e| Kr2rError::input(&file_pair[0], e))?;
             // let mut reader = create_reader(file_pair, file_index, score)?;
-            let (thread_sequences, thread_unclassified) = process_fastx_file(
+            let sample_start = Instant::now();
+            let calls = DashMap::new();
+            let summary = process_fastx_file(
                 &args,
                 meros,
                 hash_config,
@@ -293,29 +936,70 @@ fn process_files(
                 &mut reader,
                 chtable,
                 taxonomy,
-                &mut total_taxon_counts,
+                header.as_ref(),
+                stop_tracker.as_ref(),
+                rank_ceiling.as_ref(),
+                seq_output.as_ref().map(|_| &calls),
             )?;
-            total_seqs += thread_sequences;
-            total_unclassified += thread_unclassified;
+            // 远程数据库的块读取失败时, 这个样本的结果不完整
+            chtable.check_pages()?;
+            let elapsed = sample_start.elapsed();
+            info!(
+                stage = "classify",
+                event = "sample",
+                sample = file_index,
+                elapsed_ms = elapsed.as_millis() as u64,
+                reads = summary.sequences,
+                reads_per_sec = per_sec(summary.sequences, elapsed),
+                "file {}: {} reads",
+                file_index,
+                summary.sequences
+            );
+            if let Some(stats) = reader.pair_stats().filter(|stats| stats.orphan_count() > 0) {
+                info!("file {}: {}", file_index, stats);
+            }
+            if let Some(seq_output) = &seq_output {
+                seq_output.write_sample(file_pair, file_index, &calls.into_iter().collect())?;
+            }
+            if let (Some(cache), Some(key), Some(output)) =
+                (result_cache, &cache_key, &args.output_dir)
+            {
+                let filename = args
+                    .compress_output
+                    .compressed_path(output.join(format!("output_{}.txt", file_index)));
+                cache.store(key, &filename, &summary)?;
+            }
+            if let (true, Some(output)) = (args.segments_bed, &args.output_dir) {
+                write_sample_segments(&args, output, file_index, meros.k_mer)?;
+            }
+            domain_summaries.push(domain_summary(file_index, taxonomy, &summary));
+            totals.merge(&summary);
         }
+        let qc_failed = match &args.qc_gate {
+            Some(gate) => apply_qc_gate(&mut domain_summaries, gate),
+            None => 0,
+        };
+        print_domain_summaries(
+            &domain_summaries,
+            args.output_dir.is_none() && !args.no_standard_output,
+        );
         if let Some(output) = &args.output_dir {
-            let filename = output.join("output.kreport2");
-            report_kraken_style(
-                filename,
-                args.report_zero_counts,
-                args.report_kmer_data,
-                &taxonomy,
-                &total_taxon_counts,
-                total_seqs as u64,
-                total_unclassified as u64,
-            )?;
+            write_reports(&args, output, "output", taxonomy, header.as_ref(), &totals)?;
+            write_run_summary(output.join(RUN_SUMMARY), &domain_summaries, &[])?;
+        }
+        if args.qc_fail_exit && qc_failed > 0 {
+            return Err(Kr2rError::QcFailed(format!(
+                "{} of {} samples failed the QC gate",
+                qc_failed,
+                domain_summaries.len()
+            ))
+            .into());
         }
 
         Ok(())
     };
 
-    if args.paired_end_processing && !args.single_file_pairs {
-
+    if paired {
         let files = args.input_files.chunks(2).collect();
         process_funcs(files)?;
     } else {
@@ -326,12 +1010,80 @@ fn process_files(
     Ok(())
 }
 
-pub fn run(args: Args) -> Result<()> {
+/// 没有 `cloud` feature 时不能读取远程数据库; 打开 URL 数据库返回错误
+#[cfg(not(feature = "cloud"))]
+enum RemoteDatabase {}
+
+#[cfg(not(feature = "cloud"))]
+impl RemoteDatabase {
+    fn open(url: &str, _cache_dir: Option<&Path>, _max_bytes: u64) -> Result<Self> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{}: remote databases need kraken2_rs built with the `cloud` feature",
+                url
+            ),
+        ))
+    }
+
+    fn local_dir(&self) -> &Path {
+        match *self {}
+    }
+
+    fn hash_pages(&self, _config: &HashConfig) -> Result<Vec<kraken2_rs::compact_hash::HashPage>> {
+        match *self {}
+    }
+
+    fn fetched_bytes(&self) -> u64 {
+        match *self {}
+    }
+
+    fn url(&self) -> &str {
+        match *self {}
+    }
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    set_unclassified(args.unclassified_taxid, &args.unclassified_label)?;
+    set_name_normalization(args.name_normalization);
+    let database_name = args.database.display().to_string();
+    let remote_db = if seqkmer::is_remote_path(&args.database) {
+        let db = RemoteDatabase::open(
+            &database_name,
+            args.db_cache.as_deref(),
+            args.db_cache_size as u64,
+        )?;
+        args.database = db.local_dir().to_path_buf();
+        Some(db)
+    } else {
+        None
+    };
     let options_filename = &args.database.join("opts.k2d");
     let idx_opts = IndexOptions::read_index_options(options_filename)?;
 
-    if args.paired_end_processing && !args.single_file_pairs && args.input_files.len() % 2 != 0 {
+    let work_dir = args.output_dir.clone().unwrap_or_else(std::env::temp_dir);
+    if args.low_power {
+        args.num_threads = low_power_threads(args.num_threads);
+        args.decompress_threads = 1;
+        if let Err(e) = lower_priority(LOW_POWER_NICE) {
+            warn!("failed to lower the process priority: {}", e);
+        }
+    }
+    check_stage_threads(args.num_threads, args.compute_threads, args.io_threads)?;
+    seqkmer::set_decompress_threads(args.decompress_threads);
+    #[cfg(feature = "cloud")]
+    kraken2_rs::remote::enable_remote_inputs();
+    if !args.paired_end_processing && !args.single_file_pairs && !args.unpaired {
+        if let Some(inputs) = detect_pairs(&args.input_files) {
+            info!("paired-end input detected from R1/R2 file names, use --unpaired to process the files separately");
+            args.input_files = inputs;
+            args.paired_end_processing = true;
+        }
+    }
+    let paired = args.paired_end_processing && !args.single_file_pairs;
+    args.input_files = expand_inputs(&args.input_files, paired, args.merge_inputs, &work_dir)?;
 
+    if paired && !args.input_files.len().is_multiple_of(2) {
         return Err(Error::new(
             ErrorKind::InvalidInput,
             "Paired-end processing requires an even number of input files.",
@@ -339,30 +1091,126 @@ pub fn run(args: Args) -> Result<()> {
     }
 
     let taxonomy_filename = args.database.join("taxo.k2d");
-    let taxo = Taxonomy::from_file(taxonomy_filename)?;
+    let taxo = if args.memory_mapping {
+        Taxonomy::open_mmap(taxonomy_filename)?
+    } else {
+        Taxonomy::from_file(taxonomy_filename)?
+    };
+    check_unclassified_taxid(&taxo)?;
 
-    let hash_config = HashConfig::from_hash_header(&args.database.join("hash_config.k2d"))?;
+    let hash_config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    // 远程的 shard 按块读取, 不检查整个文件的校验和
+    let checksums = if args.no_verify || remote_db.is_some() {
+        None
+    } else {
+        ShardChecksums::from_hash_header(args.database.join("hash_config.k2d"))?
+    };
 
-    println!("{:?}", hash_config);
+    info!("{:?}", hash_config);
     if hash_config.hash_capacity == 0 {
         panic!("`hash_capacity` can't be zero!");
     }
-    println!("classify start...");
+    info!(stage = "classify", event = "start", "classify start...");
     let start = Instant::now();
     let meros = idx_opts.as_meros();
-    let hash_files = find_and_sort_files(&args.database, "hash", ".k2d", true)?;
-    let chtable = CHTable::from_hash_files(hash_config, &hash_files)?;
+    if remote_db.is_some() {
+        for (enabled, option) in [
+            (args.result_cache.is_some(), "--result-cache"),
+            (args.shm_cache, "--shm-cache"),
+            (args.mlock, "--mlock"),
+        ] {
+            if enabled {
+                warn!("{} is ignored with a remote database", option);
+            }
+        }
+        args.result_cache = None;
+        args.shm_cache = false;
+        args.mlock = false;
+    }
+    let mut hash_files = match remote_db {
+        Some(_) => Vec::new(),
+        None => ChunkCatalog::scan_continuous(&args.database, ChunkKind::Hash)?.paths(),
+    };
+    let result_cache = match &args.result_cache {
+        Some(dir) => match result_cache_conflict(&args) {
+            Some(reason) => {
+                warn!("--result-cache is ignored with {}", reason);
+                None
+            }
+            None => Some(ResultCache::open(dir, &args.database, &hash_files)?),
+        },
+        None => None,
+    };
+    if let Some(reason) = segments_bed_conflict(&args).filter(|_| args.segments_bed) {
+        warn!("--segments-bed is ignored with {}", reason);
+        args.segments_bed = false;
+    }
+    if args.shm_cache {
+        hash_files = shm_cache_files(&args.database, &hash_files)?;
+        args.memory_mapping = true;
+    }
+    let chtable = if let Some(db) = &remote_db {
+        let pages = db.hash_pages(&hash_config)?;
+        CHTable {
+            config: hash_config,
+            pages,
+        }
+    } else if args.memory_mapping {
+        CHTable::map_hash_files(hash_config, &hash_files, checksums.as_ref())?
+    } else {
+        CHTable::from_hash_files(hash_config, &hash_files, checksums.as_ref())?
+    };
+    if args.mlock {
+        let size = chtable.byte_size();
+        chtable.lock_in_memory().map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!(
+                    "--mlock failed to lock the hash tables in memory: {}; {}",
+                    e,
+                    memlock_diagnostic(size)
+                ),
+            )
+        })?;
+        info!(
+            "locked {} of hash tables in memory",
+            format_bytes(size as f64)
+        );
+    }
 
-    process_files(args, meros, hash_config, &chtable, &taxo)?;
+    process_files(
+        args,
+        meros,
+        hash_config,
+        &chtable,
+        &taxo,
+        result_cache.as_ref(),
+        &database_name,
+    )?;
+    if let Some(db) = &remote_db {
+        info!(
+            "downloaded {} of hash tables from {}",
+            format_bytes(db.fetched_bytes() as f64),
+            db.url()
+        );
+    }
     let duration = start.elapsed();
-    println!("classify took: {:?}", duration);
+    info!(
+        stage = "classify",
+        event = "end",
+        elapsed_ms = duration.as_millis() as u64,
+        "classify took: {:?}",
+        duration
+    );
     Ok(())
 }
 
 #[allow(dead_code)]
 fn main() {
+    kraken2_rs::logging::init_default_logging();
     let args = Args::parse();
     if let Err(e) = run(args) {
         eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
     }
 }
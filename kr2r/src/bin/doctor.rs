@@ -0,0 +1,617 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::checkpoint::{Checkpoint, CHECKPOINT_FILE};
+use kraken2_rs::compact_hash::{
+    read_row_header, ChunkRecord, Compact, HashConfig, Row, Slot, ROW_FILE_HEADER_SIZE,
+};
+use kraken2_rs::error::{Kr2rError, Result};
+use kraken2_rs::utils::{format_bytes, read_sample_file_map};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Check a chunk directory and explain which stage of classify stopped",
+    long_about = "List the files that splitr, annotate and resolve leave in a chunk directory (sample_file.map, sample_id_*.map, sample_*.k2, sample_file_*_*.bin and checkpoint.txt), check them against each other and against hash_config.k2d (chunk headers, numbering, record sizes, sample numbers and read ids), and print which stage stopped and what to do next"
+)]
+pub struct Args {
+    /// chunk directory
+    #[clap(long, value_parser, required = true)]
+    pub chunk_dir: PathBuf,
+
+    /// Database the reads were split for, or a name registered with `db add`. Without it the
+    /// chunk headers are not compared with hash_config.k2d and the .k2 records are not read
+    #[arg(long = "db", value_parser = parse_database)]
+    pub database: Option<PathBuf>,
+
+    /// Only check file names, headers and sizes, without reading the records of the .k2 and .bin files
+    #[clap(long, value_parser, default_value_t = false)]
+    pub quick: bool,
+}
+
+/// chunk 文件头: 分块编号 (从 0 开始) 与分块大小
+const CHUNK_HEADER_SIZE: u64 = 16;
+
+const MIXED_RUNS: &str = "the chunk directory holds files of different runs; remove it (or use a new --chunk-dir) and run classify again";
+const OTHER_DATABASE: &str = "the chunks were split for another database; run classify again with the same --db in a new --chunk-dir";
+const SPLITR_KILLED: &str = "splitr stopped while writing; run the same command again to roll the chunks back from checkpoint.txt, or start over in a new --chunk-dir";
+const ANNOTATE_KILLED: &str = "annotate stopped while writing; run the same command again to roll the .bin files back from checkpoint.txt, or start over in a new --chunk-dir";
+const OTHER_VERSION: &str = "the .bin files were written by a different kraken2_rs version; run classify again in a new --chunk-dir";
+const MISSING_FILES: &str =
+    "files of an unfinished run were deleted; start over in a new --chunk-dir";
+
+/// 发现的问题与对应的处理建议 (去重)
+#[derive(Default)]
+struct Findings {
+    problems: usize,
+    hints: Vec<&'static str>,
+}
+
+impl Findings {
+    fn report(&mut self, message: String, hint: &'static str) {
+        println!("FAILED: {}", message);
+        self.problems += 1;
+        if !self.hints.contains(&hint) {
+            self.hints.push(hint);
+        }
+    }
+}
+
+/// sample_id_{n}.map 中的 read 编号, 每个编号一位
+#[derive(Debug, Default)]
+struct ReadIds {
+    reads: usize,
+    min: u32,
+    max: u32,
+    ids: Vec<u64>,
+    bad_lines: usize,
+}
+
+impl ReadIds {
+    fn insert(&mut self, seq_id: u32) {
+        let word = seq_id as usize / 64;
+        if word >= self.ids.len() {
+            self.ids.resize(word + 1, 0);
+        }
+        self.ids[word] |= 1 << (seq_id % 64);
+        self.reads += 1;
+        self.min = self.min.min(seq_id);
+        self.max = self.max.max(seq_id);
+    }
+
+    fn contains(&self, seq_id: u32) -> bool {
+        self.ids
+            .get(seq_id as usize / 64)
+            .is_some_and(|word| word & (1 << (seq_id % 64)) != 0)
+    }
+}
+
+impl std::fmt::Display for ReadIds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.reads == 0 {
+            write!(f, "no reads")
+        } else {
+            write!(f, "{} reads, ids {}-{}", self.reads, self.min, self.max)
+        }
+    }
+}
+
+/// 读取 sample_id map, 每行为 `编号\tread id\t长度\tminimizer 数[|mate 2 的数][\tqc]`
+fn read_id_map(path: &Path) -> io::Result<ReadIds> {
+    let mut ids = ReadIds {
+        min: u32::MAX,
+        ..Default::default()
+    };
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let columns: Vec<&str> = line.trim().splitn(5, '\t').collect();
+        match columns[0].parse::<u32>() {
+            Ok(seq_id) if columns.len() >= 4 => ids.insert(seq_id),
+            _ if line.trim().is_empty() => {}
+            _ => ids.bad_lines += 1,
+        }
+    }
+    Ok(ids)
+}
+
+/// 依次读取文件头之后的所有完整记录
+fn scan_records<T: ChunkRecord, F: FnMut(T)>(path: &Path, header: u64, mut f: F) -> io::Result<()> {
+    let mut reader = BufReader::with_capacity(1 << 20, File::open(path)?);
+    io::copy(&mut reader.by_ref().take(header), &mut io::sink())?;
+    let mut buffer = vec![0u8; T::SIZE * 65536];
+    loop {
+        let mut len = 0;
+        while len < buffer.len() {
+            match reader.read(&mut buffer[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        buffer[..len]
+            .chunks_exact(T::SIZE)
+            .for_each(|bytes| f(T::decode(bytes)));
+        if len < buffer.len() {
+            return Ok(());
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// 检查 splitr 的 sample_{n}.k2: 文件头, 大小, 以及 (读取记录时) 样本编号与 read 编号
+fn check_chunk(
+    findings: &mut Findings,
+    index: usize,
+    path: &Path,
+    config: Option<&HashConfig>,
+    read_ids: &BTreeMap<usize, ReadIds>,
+    quick: bool,
+) -> io::Result<()> {
+    let name = file_name(path);
+    let mut header = [0u8; CHUNK_HEADER_SIZE as usize];
+    let size = fs::metadata(path)?.len();
+    if size < CHUNK_HEADER_SIZE {
+        findings.report(
+            format!(
+                "{} has {} bytes, too short for the chunk header",
+                name, size
+            ),
+            SPLITR_KILLED,
+        );
+        return Ok(());
+    }
+    File::open(path)?.read_exact(&mut header)?;
+    let stored_index = u64::from_le_bytes(header[0..8].try_into().unwrap()) as usize;
+    let chunk_size = u64::from_le_bytes(header[8..16].try_into().unwrap()) as usize;
+    if stored_index + 1 != index {
+        findings.report(
+            format!(
+                "{} stores chunk {}, expected {}",
+                name,
+                stored_index + 1,
+                index
+            ),
+            MIXED_RUNS,
+        );
+    }
+    if let Some(config) = config.filter(|config| config.hash_capacity != chunk_size) {
+        findings.report(
+            format!(
+                "{} was split with chunk size {}, hash_config.k2d has hash capacity {}",
+                name, chunk_size, config.hash_capacity
+            ),
+            OTHER_DATABASE,
+        );
+    }
+    let body = size - CHUNK_HEADER_SIZE;
+    let records = body / Slot::<u64>::SIZE as u64;
+    if !body.is_multiple_of(Slot::<u64>::SIZE as u64) {
+        findings.report(
+            format!(
+                "{} ends in a partial record ({} bytes after {} records)",
+                name,
+                body % Slot::<u64>::SIZE as u64,
+                records
+            ),
+            SPLITR_KILLED,
+        );
+    }
+    println!(
+        "{}: {} records, {}",
+        name,
+        records,
+        format_bytes(size as f64)
+    );
+
+    let Some(config) = config.filter(|_| !quick) else {
+        return Ok(());
+    };
+    let value_mask = config.get_value_mask();
+    let idx_mask = config.get_idx_mask();
+    let mut unknown_samples: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut unknown_reads: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut bad_cells = 0;
+    scan_records::<Slot<u64>, _>(path, CHUNK_HEADER_SIZE, |slot| {
+        let sample = (slot.value.right(value_mask) >> 32) as usize;
+        match read_ids.get(&sample) {
+            None => *unknown_samples.entry(sample).or_default() += 1,
+            Some(ids) if !ids.contains(slot.get_seq_id() as u32) => {
+                *unknown_reads.entry(sample).or_default() += 1
+            }
+            Some(_) => {}
+        }
+        if slot.idx & idx_mask >= config.hash_capacity {
+            bad_cells += 1;
+        }
+    })?;
+    for (sample, count) in unknown_samples {
+        findings.report(
+            format!(
+                "{} has {} records of sample {}, which has no sample_id_{}.map",
+                name, count, sample, sample
+            ),
+            MIXED_RUNS,
+        );
+    }
+    for (sample, count) in unknown_reads {
+        findings.report(
+            format!(
+                "{} has {} records with read ids missing from sample_id_{}.map ({})",
+                name, count, sample, read_ids[&sample]
+            ),
+            MIXED_RUNS,
+        );
+    }
+    if bad_cells > 0 {
+        findings.report(
+            format!(
+                "{} has {} records outside the hash capacity {} of a chunk",
+                name, bad_cells, config.hash_capacity
+            ),
+            OTHER_DATABASE,
+        );
+    }
+    Ok(())
+}
+
+/// 检查 annotate 的 sample_file_{n}_{m}.bin: 大小, 以及 (读取记录时) read 编号与 taxid
+fn check_bin(
+    findings: &mut Findings,
+    index: usize,
+    path: &Path,
+    config: Option<&HashConfig>,
+    read_ids: &BTreeMap<usize, ReadIds>,
+    quick: bool,
+) -> io::Result<u64> {
+    let name = file_name(path);
+    let size = fs::metadata(path)?.len();
+    if let Err(e) = read_row_header(&mut File::open(path)?) {
+        findings.report(format!("{}: {}", name, e), OTHER_VERSION);
+        return Ok(size);
+    }
+    let body = size.saturating_sub(ROW_FILE_HEADER_SIZE);
+    if !body.is_multiple_of(Row::SIZE as u64) {
+        findings.report(
+            format!(
+                "{} ends in a partial record ({} bytes after {} hits)",
+                name,
+                body % Row::SIZE as u64,
+                body / Row::SIZE as u64
+            ),
+            ANNOTATE_KILLED,
+        );
+    }
+    let Some(ids) = read_ids.get(&index) else {
+        findings.report(
+            format!(
+                "{} belongs to sample {}, which has no sample_id_{}.map",
+                name, index, index
+            ),
+            MIXED_RUNS,
+        );
+        return Ok(size);
+    };
+    if quick {
+        return Ok(size);
+    }
+    let mut unknown_reads = 0;
+    let mut first_unknown = None;
+    let mut no_taxid = 0;
+    scan_records::<Row, _>(path, ROW_FILE_HEADER_SIZE, |row| {
+        if !ids.contains(row.seq_id) {
+            unknown_reads += 1;
+            first_unknown.get_or_insert(row.seq_id);
+        }
+        if config.is_some_and(|config| row.value.right(config.get_value_mask()) == 0) {
+            no_taxid += 1;
+        }
+    })?;
+    if let Some(seq_id) = first_unknown {
+        findings.report(
+            format!(
+                "{} has {} hits with read ids missing from sample_id_{}.map ({}), resolve reports them as \"can't find {} in sample_id map file\"",
+                name, unknown_reads, index, ids, seq_id
+            ),
+            MIXED_RUNS,
+        );
+    }
+    if no_taxid > 0 {
+        findings.report(
+            format!("{} has {} hits without a taxid", name, no_taxid),
+            OTHER_DATABASE,
+        );
+    }
+    Ok(size)
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let dir = &args.chunk_dir;
+    if !dir.is_dir() {
+        return Err(Kr2rError::Config(format!(
+            "--chunk-dir {:?} is not a directory",
+            dir
+        )));
+    }
+    println!("chunk directory: {}", dir.display());
+    let mut findings = Findings::default();
+
+    let config = match &args.database {
+        Some(database) => {
+            let config_file = database.join("hash_config.k2d");
+            let config = HashConfig::from_hash_header(&config_file)
+                .map_err(|e| Kr2rError::index(&config_file, e))?;
+            println!(
+                "hash_config.k2d: version {}, {} chunks of hash capacity {}, {} value bits",
+                config.version, config.partition, config.hash_capacity, config.value_bits
+            );
+            Some(config)
+        }
+        None => {
+            println!("no --db given, the chunks are not compared with hash_config.k2d");
+            None
+        }
+    };
+
+    let checkpoint = Checkpoint::open(dir)?;
+    let done = |stage: &str| -> BTreeSet<String> {
+        checkpoint
+            .stage_entries(stage)
+            .into_iter()
+            .filter(|(name, _)| checkpoint.is_done(stage, name))
+            .map(|(name, _)| name.to_string())
+            .collect()
+    };
+    let started = |stage: &str| -> Vec<String> {
+        checkpoint
+            .stage_entries(stage)
+            .into_iter()
+            .filter(|(name, _)| checkpoint.started_value(stage, name).is_some())
+            .map(|(name, _)| name.to_string())
+            .collect()
+    };
+    let splitr_started = !started("splitr").is_empty();
+    let splitr_done = !done("splitr").is_empty();
+    let annotated = done("annotate");
+    let annotate_started = started("annotate");
+    let resolved = done("resolve");
+    let resolve_started = started("resolve");
+
+    // sample_file.map: 每个样本一行, 编号从 1 开始连续
+    let sample_map = dir.join("sample_file.map");
+    let rows = if sample_map.exists() {
+        read_sample_file_map(&sample_map)?
+    } else {
+        Vec::new()
+    };
+    let mut samples: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for (index, files) in rows {
+        if samples.insert(index, files).is_some() {
+            findings.report(
+                format!("sample_file.map lists sample {} more than once", index),
+                MIXED_RUNS,
+            );
+        }
+    }
+    match (samples.keys().next(), samples.keys().last()) {
+        (Some(first), Some(last)) => {
+            println!(
+                "sample_file.map: {} samples ({}-{})",
+                samples.len(),
+                first,
+                last
+            );
+            let missing: Vec<String> = (1..*last)
+                .filter(|index| !samples.contains_key(index))
+                .map(|index| index.to_string())
+                .collect();
+            if !missing.is_empty() {
+                findings.report(
+                    format!("sample_file.map has no samples {}", missing.join(", ")),
+                    MIXED_RUNS,
+                );
+            }
+        }
+        _ if sample_map.exists() => println!("sample_file.map: no samples"),
+        _ => println!("sample_file.map: missing"),
+    }
+
+    let id_catalog = ChunkCatalog::scan(dir, ChunkKind::SampleId)?;
+    let chunk_catalog = ChunkCatalog::scan(dir, ChunkKind::Sample)?;
+    let bin_catalog = ChunkCatalog::scan(dir, ChunkKind::SampleBin)?;
+    // resolve 结束时删除中间文件与断点记录, 只留下 sample_file.map
+    let finished = id_catalog.is_empty()
+        && chunk_catalog.is_empty()
+        && bin_catalog.is_empty()
+        && !dir.join(CHECKPOINT_FILE).exists();
+
+    // sample_id_{n}.map: 每个样本一个
+    let mut read_ids = BTreeMap::new();
+    let mut reads = 0;
+    for (index, path) in id_catalog.by_index() {
+        let ids = read_id_map(&path)?;
+        if ids.bad_lines > 0 {
+            findings.report(
+                format!(
+                    "{} has {} lines that are not `id<TAB>read<TAB>length<TAB>minimizers`",
+                    file_name(&path),
+                    ids.bad_lines
+                ),
+                SPLITR_KILLED,
+            );
+        }
+        if !samples.is_empty() && !samples.contains_key(&index) {
+            findings.report(
+                format!(
+                    "{} belongs to sample {}, which is not in sample_file.map",
+                    file_name(&path),
+                    index
+                ),
+                MIXED_RUNS,
+            );
+        }
+        reads += ids.reads;
+        read_ids.insert(index, ids);
+    }
+    println!("sample_id_*.map: {} files, {} reads", read_ids.len(), reads);
+    if !finished {
+        for index in samples.keys().filter(|index| !read_ids.contains_key(index)) {
+            findings.report(
+                format!(
+                    "sample {} has no sample_id_{}.map, resolve can't name its reads",
+                    index, index
+                ),
+                MISSING_FILES,
+            );
+        }
+    }
+
+    // sample_{n}.k2: hash_config.k2d 的每个分块一个, annotate 处理完后删除
+    let chunk_count = config
+        .as_ref()
+        .map(|config| config.partition)
+        .or(chunk_catalog.indices().last().copied())
+        .unwrap_or(0);
+    let chunk_files = chunk_catalog.by_index();
+    println!(
+        "sample_*.k2: {} chunk files, {} of {} chunks annotated",
+        chunk_files.len(),
+        annotated.len(),
+        chunk_count
+    );
+    for (&index, path) in &chunk_files {
+        if index > chunk_count {
+            findings.report(
+                format!(
+                    "{} is not one of the {} chunks of hash_config.k2d",
+                    file_name(path),
+                    chunk_count
+                ),
+                OTHER_DATABASE,
+            );
+        }
+        check_chunk(
+            &mut findings,
+            index,
+            path,
+            config.as_ref(),
+            &read_ids,
+            args.quick,
+        )?;
+    }
+    if !chunk_files.is_empty() || !annotated.is_empty() {
+        for index in 1..=chunk_count {
+            let name = chunk_catalog.file_name(index);
+            if !chunk_files.contains_key(&index) && !annotated.contains(&name) {
+                findings.report(
+                    format!("{} is missing and was not annotated", name),
+                    MISSING_FILES,
+                );
+            }
+        }
+    }
+
+    // sample_file_{n}_{m}.bin: annotate 按样本写出的命中
+    let mut bin_bytes = 0;
+    for entry in bin_catalog.entries() {
+        bin_bytes += check_bin(
+            &mut findings,
+            entry.index,
+            &entry.path,
+            config.as_ref(),
+            &read_ids,
+            args.quick,
+        )?;
+    }
+    println!(
+        "sample_file_*_*.bin: {} files of {} samples, {}",
+        bin_catalog.len(),
+        bin_catalog.indices().len(),
+        format_bytes(bin_bytes as f64)
+    );
+
+    if dir.join(CHECKPOINT_FILE).exists() {
+        println!(
+            "{}: splitr {}, annotate {} chunks done{}, resolve {} samples done{}",
+            CHECKPOINT_FILE,
+            match (splitr_done, splitr_started) {
+                (_, true) => "interrupted",
+                (true, false) => "done",
+                (false, false) => "not started",
+            },
+            annotated.len(),
+            match annotate_started.first() {
+                Some(name) => format!(" ({} interrupted)", name),
+                None => String::new(),
+            },
+            resolved.len(),
+            match resolve_started.first() {
+                Some(name) => format!(" (sample {} interrupted)", name),
+                None => String::new(),
+            },
+        );
+    } else {
+        println!("{}: none", CHECKPOINT_FILE);
+    }
+
+    let diagnosis = if samples.is_empty() && read_ids.is_empty() && chunk_files.is_empty() {
+        "splitr has not written anything here: classify has not run in this directory, or --chunk-dir points to another directory".to_string()
+    } else if splitr_started {
+        "splitr was interrupted; run the same command again to roll the chunks back and split the inputs again".to_string()
+    } else if let Some(name) = annotate_started.first() {
+        format!(
+            "annotate was interrupted in {}; run the same command again to resume from it",
+            name
+        )
+    } else if !chunk_files.is_empty() && annotated.is_empty() {
+        "splitr finished, annotate has not run; run the same command again (or annotate and resolve with this --chunk-dir)".to_string()
+    } else if !chunk_files.is_empty() {
+        format!(
+            "annotate stopped after {} of {} chunks; run the same command again to annotate the rest",
+            annotated.len(),
+            chunk_count
+        )
+    } else if !read_ids.is_empty() && resolved.is_empty() {
+        "annotate finished, resolve has not run; run the same command again (or resolve with this --chunk-dir)".to_string()
+    } else if !read_ids.is_empty() {
+        format!(
+            "resolve was interrupted after {} of {} samples; run the same command again to resolve the rest",
+            resolved.len(),
+            read_ids.len()
+        )
+    } else {
+        "resolve finished and removed the intermediate files, nothing is left to resume".to_string()
+    };
+    println!("diagnosis: {}", diagnosis);
+    for hint in &findings.hints {
+        println!("fix: {}", hint);
+    }
+    if findings.problems == 0 {
+        println!("no problems found");
+        return Ok(());
+    }
+    Err(Kr2rError::CorruptChunk {
+        path: dir.to_path_buf(),
+        source: io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} problems found", findings.problems),
+        ),
+    })
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
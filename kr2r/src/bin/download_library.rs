@@ -0,0 +1,191 @@
+use clap::{Parser, ValueEnum};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ncbi_dl::load::{DownTuple, NcbiFile};
+use ncbi_dl::meta::{get_local_etag, init_meta, save_meta};
+use ncbi_dl::task;
+use ncbi_dl::utils::NCBI_ASM_LEVELS;
+use std::fmt;
+use std::fs::{create_dir_all, File};
+use std::io::{self, BufReader, BufWriter, Result, Write};
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Instant;
+use tokio::runtime::Builder;
+use tracing::info;
+
+/// NCBI 没有提供 UniVec_Core 的 md5
+const UNIVEC_CORE_URL: &str = "https://ftp.ncbi.nlm.nih.gov/pub/UniVec/UniVec_Core";
+/// UniVec_Core 的序列归入 "other sequences", 与 kraken2-build 相同
+const UNIVEC_TAXID: u64 = 28384;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Library {
+    Archaea,
+    Bacteria,
+    Viral,
+    Fungi,
+    Plant,
+    Protozoa,
+    Human,
+    #[value(name = "UniVec_Core")]
+    UnivecCore,
+}
+
+impl Library {
+    /// NCBI genomes/refseq 下的目录, UniVec_Core 不是 refseq 的 assembly
+    fn refseq_group(&self) -> Option<&'static str> {
+        match self {
+            Library::Archaea => Some("archaea"),
+            Library::Bacteria => Some("bacteria"),
+            Library::Viral => Some("viral"),
+            Library::Fungi => Some("fungi"),
+            Library::Plant => Some("plant"),
+            Library::Protozoa => Some("protozoa"),
+            Library::Human => Some("vertebrate_mammalian/Homo_sapiens"),
+            Library::UnivecCore => None,
+        }
+    }
+}
+
+impl fmt::Display for Library {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().unwrap();
+        write!(f, "{}", value.get_name())
+    }
+}
+
+fn parse_asm_level(level: &str) -> std::result::Result<String, String> {
+    if NCBI_ASM_LEVELS.contains_key(level) {
+        Ok(level.to_string())
+    } else {
+        let mut levels: Vec<&String> = NCBI_ASM_LEVELS.keys().collect();
+        levels.sort_unstable();
+        Err(format!(
+            "unknown assembly level, expected one of {:?}",
+            levels
+        ))
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Download reference libraries and the NCBI taxonomy for `build` (like kraken2-build --download-library)"
+)]
+pub struct Args {
+    /// Directory to store downloaded files, pass it to `build` as --download-dir
+    #[arg(short, long, required = true)]
+    pub download_dir: PathBuf,
+
+    /// Libraries to download, comma separated
+    #[arg(
+        short,
+        long = "library",
+        value_enum,
+        value_delimiter = ',',
+        required = true
+    )]
+    pub libraries: Vec<Library>,
+
+    /// Assembly levels to download: complete_genome, chromosome, scaffold, contig, all, basic (complete_genome and chromosome) or uncomplete (scaffold and contig)
+    #[arg(long, value_parser = parse_asm_level, default_value = "basic")]
+    pub asm_level: String,
+
+    /// Do not download the NCBI taxonomy (taxonomy/nodes.dmp and names.dmp)
+    #[clap(long, value_parser, default_value_t = false)]
+    pub skip_taxonomy: bool,
+
+    /// Number of parallel downloads
+    #[arg(short = 'p', long = "threads", default_value_t = num_cpus::get() * 2)]
+    pub threads: usize,
+}
+
+/// 下载 UniVec_Core, 并写成 merge-fna 读取的 assembly_summary 与 `<site>/<name>_genomic.fna.gz`
+async fn download_univec(data_dir: &Path) -> anyhow::Result<()> {
+    let site_dir = data_dir.join("univec");
+    create_dir_all(&site_dir)?;
+
+    let raw_file = data_dir.join("UniVec_Core");
+    let etag = get_local_etag(UNIVEC_CORE_URL).await.unwrap_or_default();
+    DownTuple::new(UNIVEC_CORE_URL.to_string(), raw_file.clone(), etag)
+        .run()
+        .await?;
+
+    let mut reader = BufReader::new(File::open(&raw_file)?);
+    let fna_file = File::create(site_dir.join("UniVec_Core_genomic.fna.gz"))?;
+    let mut encoder = GzEncoder::new(BufWriter::new(fna_file), Compression::default());
+    io::copy(&mut reader, &mut encoder)?;
+    encoder.finish()?.flush()?;
+
+    // assembly_summary 的第 1 列为 accession, 第 6 列为 taxid, 第 20 列为 ftp 路径
+    let mut fields = vec!["na".to_string(); 20];
+    fields[0] = "UniVec_Core".to_string();
+    fields[5] = UNIVEC_TAXID.to_string();
+    fields[19] = UNIVEC_CORE_URL.to_string();
+    let mut summary = File::create(data_dir.join("assembly_summary_univec.txt"))?;
+    writeln!(summary, "{}", fields.join("\t"))?;
+    Ok(())
+}
+
+async fn download(args: &Args) -> anyhow::Result<()> {
+    let download_dir = &args.download_dir;
+
+    if !args.skip_taxonomy {
+        let taxo_dir = download_dir.join("taxonomy");
+        create_dir_all(&taxo_dir)?;
+        info!("download taxonomy...");
+        let taxdump = NcbiFile::new_taxo(&taxo_dir, "taxdump.tar.gz").await;
+        taxdump.run().await?;
+        taxdump.check().await?;
+        taxdump.decompress(&taxo_dir).await?;
+    }
+
+    let levels = &NCBI_ASM_LEVELS[&args.asm_level];
+    for library in &args.libraries {
+        let data_dir = download_dir.join("library").join(library.to_string());
+        match library.refseq_group() {
+            Some(group) => {
+                create_dir_all(data_dir.join("refseq"))?;
+                task::run_task("refseq", group, &data_dir, levels, args.threads).await?;
+            }
+            None => {
+                info!("{} download file start...", library);
+                download_univec(&data_dir).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let start = Instant::now();
+    create_dir_all(&args.download_dir)?;
+    let runtime = Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("download")
+        .worker_threads(args.threads.max(1))
+        .build()?;
+    runtime
+        .block_on(async {
+            init_meta(&args.download_dir).await;
+            let result = download(&args).await;
+            // 下载失败时也保存已下载文件的 etag, 下次运行时断点续传
+            save_meta(&args.download_dir).await?;
+            result
+        })
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    info!("download library took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
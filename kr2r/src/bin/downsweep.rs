@@ -0,0 +1,322 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::classify::{format_call, parse_call};
+use kraken2_rs::readcounts::{ReadCounter, TaxonCounters};
+use kraken2_rs::report::report_kraken_style;
+use kraken2_rs::subdb::SubDatabase;
+use kraken2_rs::taxonomy::Taxonomy;
+use kraken2_rs::utils::{find_files, open_file, read_id_to_taxon_map, read_sample_file_map};
+use kraken2_rs::IndexOptions;
+use seqkmer::{
+    read_parallel_with, trim_pair_info, AlignmentOptions, FastxReader, OptionPair, PairCheck,
+    PairMode, PairOptions, QualityEncoding,
+};
+use std::collections::{HashMap, HashSet};
+use std::fs::{rename, File};
+use std::io::{BufRead, BufReader, BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Reclassify genus-level reads against species-level sub-databases",
+    long_about = "Second pass over the output of resolve/direct: reads resolved only to genus level are re-queried against sub-databases extracted from the library sequences of their genus, and upgraded when a species-level call is possible"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Directory holding output_*.txt and sample_file.txt (or sample_file.map).
+    #[clap(long = "output-dir", value_parser, required = true)]
+    pub output_dir: PathBuf,
+
+    /// Minimum quality score for FASTQ data, lower bases are masked as ambiguous before minimizer extraction
+    #[clap(
+        short = 'Q',
+        long = "minimum-quality-score",
+        visible_alias = "minimum-base-quality",
+        value_parser,
+        default_value_t = 0
+    )]
+    pub minimum_quality_score: i32,
+
+    /// FASTQ quality encoding used by -Q and the quality statistics: auto, phred33, phred64 or solexa.
+    /// "auto" detects it from the first reads of each sample.
+    #[clap(long, value_parser, default_value = "auto")]
+    pub quality_encoding: QualityEncoding,
+
+    /// Only classify unmapped reads of BAM/CRAM inputs (both mates unmapped with -P -S),
+    /// e.g. the reads left after aligning to a host genome.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub unmapped_only: bool,
+
+    /// Reference FASTA (with a .fai index) used to decode CRAM inputs that contain mapped reads.
+    #[clap(long, value_parser)]
+    pub cram_reference: Option<PathBuf>,
+
+    /// Mates are interleaved in one input file (-P -S).
+    #[clap(short = 'S', long = "single-file-pairs", action)]
+    pub single_file_pairs: bool,
+
+    /// Mate validation for paired reads: strict, repair or off.
+    #[clap(long, value_parser, default_value = "strict")]
+    pub pair_check: PairCheck,
+
+    /// Scoring of paired reads, must match the one used by the first pass: range or concat.
+    #[clap(long, value_parser, default_value = "range")]
+    pub pair_mode: PairMode,
+
+    /// Confidence score threshold used against the sub-databases.
+    #[clap(
+        short = 'T',
+        long = "confidence-threshold",
+        value_parser,
+        default_value_t = 0.0
+    )]
+    pub confidence_threshold: f64,
+
+    /// Only build sub-databases for genera with at least this many genus-level reads.
+    #[clap(long, value_parser, default_value_t = 1)]
+    pub min_genus_reads: usize,
+
+    /// The number of threads to use.
+    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    pub num_threads: usize,
+}
+
+/// 读取 sample_file.txt (resolve) 或 sample_file.map (direct)
+fn read_sample_files(output_dir: &Path) -> Result<Vec<(usize, Vec<String>)>> {
+    let mut filename = output_dir.join("sample_file.txt");
+    if !filename.exists() {
+        filename = output_dir.join("sample_file.map");
+    }
+    read_sample_file_map(filename)
+}
+
+/// 找出分类到 genus 的 reads, 返回 read id -> genus (internal id)
+fn read_genus_calls(output_file: &Path, taxonomy: &Taxonomy) -> Result<HashMap<String, u32>> {
+    let reader = BufReader::new(open_file(output_file)?);
+    let mut calls = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let parts: Vec<&str> = line.splitn(4, '\t').collect();
+        if parts.len() < 3 || parts[0] != "C" {
+            continue;
+        }
+        if let Some(ext_call) = parse_call(parts[2]) {
+            let call = taxonomy.get_internal_id(ext_call);
+            if taxonomy.rank(call) == "genus" {
+                calls.insert(parts[1].to_string(), call);
+            }
+        }
+    }
+    Ok(calls)
+}
+
+fn reclassify_sample(
+    args: &Args,
+    files: &[String],
+    file_index: usize,
+    taxonomy: &Taxonomy,
+    subdb: &SubDatabase,
+    meros: seqkmer::Meros,
+    genus_calls: &HashMap<String, u32>,
+) -> Result<HashMap<String, u64>> {
+    let paths = OptionPair::from_slice(files);
+    let pairs = PairOptions {
+        interleaved: args.single_file_pairs,
+        check: args.pair_check,
+    };
+    let score = args.minimum_quality_score;
+    let encoding = args.quality_encoding.resolve(&files[0])?;
+    let alignment = AlignmentOptions {
+        unmapped_only: args.unmapped_only,
+        reference: args.cram_reference.clone(),
+    };
+    let mut reader = FastxReader::from_paths_with_options(
+        paths, file_index, score, pairs, encoding, &alignment,
+    )?;
+    let mut upgrades = HashMap::new();
+
+    read_parallel_with(
+        &mut reader,
+        args.num_threads,
+        &meros,
+        |seq| args.pair_mode.apply(seq),
+        |seqs| {
+            let mut batch = Vec::new();
+            for record in seqs {
+                let dna_id = trim_pair_info(&record.header.id);
+                let Some(&genus) = genus_calls.get(&dna_id) else {
+                    continue;
+                };
+                let call = subdb.classify(genus, record, taxonomy, args.confidence_threshold);
+                if call != genus && taxonomy.is_a_ancestor_of_b(genus, call) {
+                    batch.push((dna_id, taxonomy.nodes[call as usize].external_id));
+                }
+            }
+            batch
+        },
+        |dataset| {
+            while let Some(data) = dataset.next() {
+                upgrades.extend(data.unwrap());
+            }
+        },
+    )?;
+
+    Ok(upgrades)
+}
+
+/// 用新的分类结果改写 output 文件, 返回 (reads 总数, 未分类数, 每个 taxon 的 reads 数)
+fn rewrite_output(
+    output_file: &Path,
+    taxonomy: &Taxonomy,
+    upgrades: &HashMap<String, u64>,
+) -> Result<(u64, u64, TaxonCounters)> {
+    let tmp_file = output_file.with_extension("txt.tmp");
+    let reader = BufReader::new(open_file(output_file)?);
+    let mut writer = BufWriter::new(File::create(&tmp_file)?);
+
+    let mut call_counts: HashMap<u64, u64> = HashMap::new();
+    let mut total_seqs = 0;
+    let mut total_unclassified = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let mut parts: Vec<String> = line.splitn(4, '\t').map(|s| s.to_string()).collect();
+        if parts.len() < 3 {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        if let Some(ext_call) = upgrades.get(&parts[1]) {
+            let use_names = parts[2].contains("(taxid ");
+            parts[2] = format_call(taxonomy, *ext_call, use_names);
+        }
+        total_seqs += 1;
+        match parse_call(&parts[2]).filter(|&call| call > 0) {
+            Some(ext_call) => {
+                *call_counts
+                    .entry(taxonomy.get_internal_id(ext_call) as u64)
+                    .or_insert(0) += 1
+            }
+            None => total_unclassified += 1,
+        }
+        writeln!(writer, "{}", parts.join("\t"))?;
+    }
+    writer.flush()?;
+    rename(tmp_file, output_file)?;
+
+    let counters = call_counts
+        .into_iter()
+        .map(|(taxid, count)| (taxid, ReadCounter::new(count, 0)))
+        .collect();
+    Ok((total_seqs, total_unclassified, counters))
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let taxo = Taxonomy::from_file(args.database.join("taxo.k2d"))?;
+    let idx_opts = IndexOptions::read_index_options(args.database.join("opts.k2d"))?;
+    let meros = idx_opts.as_meros();
+
+    let start = Instant::now();
+    info!("downsweep start...");
+
+    let samples = read_sample_files(&args.output_dir)?;
+    let mut sample_calls = Vec::new();
+    let mut genus_reads: HashMap<u32, usize> = HashMap::new();
+    for (index, files) in samples {
+        let output_file = args.output_dir.join(format!("output_{}.txt", index));
+        if !output_file.exists() {
+            continue;
+        }
+        let calls = read_genus_calls(&output_file, &taxo)?;
+        for genus in calls.values() {
+            *genus_reads.entry(*genus).or_insert(0) += 1;
+        }
+        sample_calls.push((index, files, output_file, calls));
+    }
+
+    let genera: HashSet<u32> = genus_reads
+        .into_iter()
+        .filter(|(_, count)| *count >= args.min_genus_reads)
+        .map(|(genus, _)| genus)
+        .collect();
+    if genera.is_empty() {
+        info!("no genus-level reads to reclassify");
+        return Ok(());
+    }
+
+    let id_to_taxon_map = read_id_to_taxon_map(args.database.join("seqid2taxid.map"))?;
+    let library_files = find_files(args.database.join("library"), "library", ".fna");
+    let subdb = SubDatabase::build(
+        &library_files,
+        meros.for_references(),
+        &taxo,
+        &id_to_taxon_map,
+        &genera,
+        args.num_threads,
+    )?;
+    info!(
+        "built {} genus sub-databases: {:?}",
+        genera.len(),
+        start.elapsed()
+    );
+
+    for (index, files, output_file, calls) in sample_calls {
+        let calls: HashMap<String, u32> = calls
+            .into_iter()
+            .filter(|(_, genus)| subdb.contains(*genus))
+            .collect();
+        let upgrades = if calls.is_empty() {
+            HashMap::new()
+        } else {
+            reclassify_sample(&args, &files, index, &taxo, &subdb, meros, &calls)?
+        };
+
+        let (total_seqs, total_unclassified, counters) =
+            rewrite_output(&output_file, &taxo, &upgrades)?;
+        report_kraken_style(
+            args.output_dir
+                .join(format!("output_{}.downsweep.kreport2", index)),
+            false,
+            false,
+            &taxo,
+            &counters,
+            total_seqs,
+            total_unclassified,
+            None,
+        )?;
+
+        let mut writer = BufWriter::new(File::create(
+            args.output_dir
+                .join(format!("output_{}.downsweep.tsv", index)),
+        )?);
+        writeln!(writer, "#read_id\tgenus_taxid\tnew_taxid")?;
+        for (dna_id, ext_call) in &upgrades {
+            let genus = taxo.nodes[calls[dna_id] as usize].external_id;
+            writeln!(writer, "{}\t{}\t{}", dna_id, genus, ext_call)?;
+        }
+        writer.flush()?;
+        info!(
+            "sample {}: {} of {} genus-level reads upgraded",
+            index,
+            upgrades.len(),
+            calls.len()
+        );
+    }
+
+    info!("downsweep took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
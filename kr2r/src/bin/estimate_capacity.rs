@@ -1,11 +1,12 @@
 use clap::{error::ErrorKind, Error, Parser};
 use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
 use kraken2_rs::args::KLMTArgs;
+use kraken2_rs::db::mask_sequence;
 use kraken2_rs::utils::{find_files, format_bytes, open_file};
 use kraken2_rs::KBuildHasher;
+use tracing::{info, warn};
 
-use seqkmer::{read_parallel, BufferFastaReader};
-use serde_json;
+use seqkmer::{read_parallel_with, BufferFastaReader};
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::{Read, Write};
@@ -50,7 +51,7 @@ const RANGE_MASK: u64 = RANGE_SECTIONS - 1;
 fn build_output_path<P: AsRef<Path>>(input_path: &P, extension: &str) -> String {
     let path = input_path.as_ref();
     let parent_dir = path.parent().unwrap_or_else(|| Path::new(""));
-    let stem = path.file_stem().unwrap_or_else(|| path.as_os_str());
+    let stem = path.file_stem().unwrap_or(path.as_os_str());
 
     let mut output_path = parent_dir.join(stem);
     output_path.set_extension(extension);
@@ -63,11 +64,15 @@ fn process_sequence<P: AsRef<Path>>(
     // hllp: &mut HyperLogLogPlus<u64, KBuildHasher>,
     args: Args,
 ) -> HyperLogLogPlus<u64, KBuildHasher> {
-
-    let json_path = build_output_path(fna_file, &format!("hllp_{}.json", args.n));
+    let masker = args.klmt.masker();
+    // 屏蔽与否的估计结果不同, 分别缓存
+    let extension = match masker {
+        Some(dust) => format!("hllp_{}_dust{}.json", args.n, dust.threshold),
+        None => format!("hllp_{}.json", args.n),
+    };
+    let json_path = build_output_path(fna_file, &extension);
 
     if args.cache && Path::new(&json_path).exists() {
-
         let mut file = open_file(json_path).unwrap();
         let mut serialized_hllp = String::new();
         file.read_to_string(&mut serialized_hllp).unwrap();
@@ -79,16 +84,16 @@ fn process_sequence<P: AsRef<Path>>(
 
     let meros = args.klmt.as_meros();
 
-    let mut hllp: HyperLogLogPlus<u64, _> =
-        HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap();
+    let mut hllp: HyperLogLogPlus<u64, _> = HyperLogLogPlus::new(16, KBuildHasher).unwrap();
 
     let mut reader = BufferFastaReader::from_path(fna_file, 1)
         .expect("Failed to open the FASTA file with FastaReader");
     let range_n = args.n as u64;
-    read_parallel(
+    read_parallel_with(
         &mut reader,
         args.threads,
         &meros,
+        |seq| mask_sequence(masker, seq),
         |record_set| {
             let mut minimizer_set = HashSet::new();
 
@@ -115,16 +120,14 @@ fn process_sequence<P: AsRef<Path>>(
     )
     .expect("read parallel error");
 
-
     let serialized_hllp = serde_json::to_string(&hllp).unwrap();
 
     if let Ok(mut file) = File::create(&json_path) {
-
         if let Err(e) = file.write_all(serialized_hllp.as_bytes()) {
-            eprintln!("Failed to write to file: {}", e);
+            warn!("Failed to write to file: {}", e);
         }
     } else {
-        eprintln!("Failed to create file: {}", json_path);
+        warn!("Failed to create file: {}", json_path);
     }
 
     hllp
@@ -137,9 +140,20 @@ pub fn run(args: Args) -> usize {
         let err = Error::raw(ErrorKind::ValueValidation, "k cannot be less than l");
         err.exit();
     }
+    if meros.l_mer > meros.alphabet.max_minimizer_length() {
+        let err = Error::raw(
+            ErrorKind::ValueValidation,
+            format!(
+                "l cannot be more than {} for {:?} minimizers",
+                meros.alphabet.max_minimizer_length(),
+                meros.alphabet
+            ),
+        );
+        err.exit();
+    }
 
     let mut hllp: HyperLogLogPlus<u64, KBuildHasher> =
-        HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap();
+        HyperLogLogPlus::new(16, KBuildHasher).unwrap();
 
     let source: PathBuf = args.database.clone();
     let fna_files = if source.is_file() {
@@ -153,7 +167,7 @@ pub fn run(args: Args) -> usize {
         panic!("Error: No library.fna files found in the specified directory. Please ensure that the directory contains at least one library.fna file and try again.");
     }
 
-    println!("estimate start... ");
+    info!("estimate start... ");
 
     for fna_file in fna_files {
         let args_clone = Args {
@@ -162,13 +176,17 @@ pub fn run(args: Args) -> usize {
         };
         let local_hllp = process_sequence(&fna_file, args_clone);
         if let Err(e) = hllp.merge(&local_hllp) {
-            println!("hllp merge err {:?}", e);
+            info!("hllp merge err {:?}", e);
         }
     }
 
     let hllp_count = (hllp.count() * RANGE_SECTIONS as f64 / args.n as f64).round() as u64;
     let required_capacity = (hllp_count + 8192) as f64 / args.load_factor;
-    println!(
+    info!(
+        stage = "estimate",
+        event = "end",
+        minimizers = hllp_count,
+        capacity = required_capacity.ceil() as u64,
         "estimate count: {:?}, required capacity: {:?}, Estimated hash table requirement: {:}",
         hllp_count,
         required_capacity.ceil(),
@@ -179,6 +197,7 @@ pub fn run(args: Args) -> usize {
 
 #[allow(dead_code)]
 fn main() {
+    kraken2_rs::logging::init_default_logging();
     let args = Args::parse();
     run(args);
 }
@@ -0,0 +1,299 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::classify::parse_output_line;
+use kraken2_rs::simulate::{score_call, RankScore, EVALUATION_RANKS};
+use kraken2_rs::stats::{check_unclassified_taxid, is_unclassified, set_unclassified};
+use kraken2_rs::taxonomy::Taxonomy;
+use seqkmer::dyn_reader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Compare the per-read output of a simulated metagenome with its truth files",
+    long_about = "Score the per-read output of classify, resolve or direct against the <prefix>.truth.tsv written by simulate, at the taxid and at each major rank, and with --profile compare the reads called in the clade of each simulated taxon with its true number of reads"
+)]
+pub struct Args {
+    /// database directory or a name registered with `db add`, the one the reads were simulated
+    /// from and classified with
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// <prefix>.truth.tsv written by simulate
+    #[clap(long, value_parser, required = true)]
+    pub truth: PathBuf,
+
+    /// <prefix>.profile.tsv written by simulate, to add a table of the reads per simulated taxon
+    #[clap(long, value_parser)]
+    pub profile: Option<PathBuf>,
+
+    /// Taxid of the unclassified reads in the output, when it was written with --unclassified-taxid
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub unclassified_taxid: u64,
+
+    /// Output table, defaults to the standard output.
+    #[clap(short = 'o', long, value_parser)]
+    pub output: Option<PathBuf>,
+
+    /// Per-read output (Kraken format or --output-format json, plain or compressed); repeat for
+    /// --split-output-size parts or several samples
+    #[clap(required = true)]
+    pub kraken_output: Vec<PathBuf>,
+}
+
+fn internal_id(taxonomy: &Taxonomy, taxid: u64, what: &str) -> Result<u32> {
+    match taxonomy.get_internal_id(taxid) {
+        0 => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "taxid {} of {} is not in the database taxonomy",
+                taxid, what
+            ),
+        )),
+        internal => Ok(internal),
+    }
+}
+
+/// 读取 truth.tsv, 返回 read id -> 真实的 taxon (internal id), 保持文件中的顺序
+fn read_truth(path: &Path, taxonomy: &Taxonomy) -> Result<Vec<(String, u32)>> {
+    let mut truth = Vec::new();
+    for line in BufReader::new(dyn_reader(path)?).lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with("read_id\t") {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (Some(read_id), Some(Ok(taxid))) = (fields.next(), fields.next().map(str::parse))
+        else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?}: not a truth line: {}", path, line),
+            ));
+        };
+        let taxon = internal_id(taxonomy, taxid, &format!("read {}", read_id))?;
+        truth.push((read_id.to_string(), taxon));
+    }
+    Ok(truth)
+}
+
+/// 读取分类结果中 `truth` 里的 reads, 返回 read id -> 分类的 taxon (internal id, 未分类为 0)
+/// 与不在 `truth` 中的 reads 数. 同一个 read 出现多次时使用最后一次
+fn read_calls(
+    args: &Args,
+    truth: &HashMap<&str, u32>,
+    taxonomy: &Taxonomy,
+) -> Result<(HashMap<String, u32>, usize)> {
+    let mut calls = HashMap::new();
+    let mut unknown = 0;
+    for file in &args.kraken_output {
+        for line in BufReader::new(dyn_reader(file)?).lines() {
+            let line = line?;
+            // 跳过其他行, 例如 direct 写到标准输出的样本列表与大类统计
+            if !line.starts_with("C\t") && !line.starts_with("U\t") && !line.starts_with('{') {
+                continue;
+            }
+            let (read_id, taxid) = parse_output_line(&line).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{:?}: not a per-read output line: {}", file, line),
+                )
+            })?;
+            if !truth.contains_key(read_id.as_str()) {
+                unknown += 1;
+                continue;
+            }
+            let call = if line.starts_with("U\t") || is_unclassified(taxid) {
+                0
+            } else {
+                internal_id(taxonomy, taxid, &format!("the call of read {}", read_id))?
+            };
+            calls.insert(read_id, call);
+        }
+    }
+    Ok((calls, unknown))
+}
+
+/// profile.tsv 中的一个 taxon
+#[derive(Debug, Default, Clone, Copy)]
+struct ProfileTaxon {
+    /// internal id
+    taxon: u32,
+    /// 真实的 reads 数
+    true_reads: u64,
+    /// 分类到其 clade 中的 reads 数
+    called_reads: u64,
+    /// 其中真实的 taxon 也在该 clade 中的 reads 数
+    correct_reads: u64,
+}
+
+fn read_profile(path: &Path, taxonomy: &Taxonomy) -> Result<Vec<ProfileTaxon>> {
+    let mut profile = Vec::new();
+    for line in BufReader::new(dyn_reader(path)?).lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with("taxid\t") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (Some(Ok(taxid)), Some(Ok(true_reads))) = (
+            fields.first().map(|f| f.parse::<u64>()),
+            fields.get(3).map(|f| f.parse::<u64>()),
+        ) else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?}: not a profile line: {}", path, line),
+            ));
+        };
+        profile.push(ProfileTaxon {
+            taxon: internal_id(taxonomy, taxid, "the profile")?,
+            true_reads,
+            ..Default::default()
+        });
+    }
+    Ok(profile)
+}
+
+/// 统计每个模拟的 taxon 的 clade 中分类到的 reads, `reads` 为 (真实的 taxon, 分类的 taxon)
+fn count_clade_calls(taxonomy: &Taxonomy, profile: &mut [ProfileTaxon], reads: &[(u32, u32)]) {
+    let index: HashMap<u32, usize> = profile
+        .iter()
+        .enumerate()
+        .map(|(i, taxon)| (taxon.taxon, i))
+        .collect();
+    for &(truth, call) in reads.iter().filter(|(_, call)| *call != 0) {
+        let truth_lineage = taxonomy.lineage(truth);
+        for node in taxonomy.lineage(call) {
+            if let Some(&i) = index.get(&node) {
+                profile[i].called_reads += 1;
+                if truth_lineage.contains(&node) {
+                    profile[i].correct_reads += 1;
+                }
+            }
+        }
+    }
+}
+
+fn write_tables<W: Write>(
+    writer: &mut W,
+    taxonomy: &Taxonomy,
+    scores: &[RankScore],
+    profile: Option<&[ProfileTaxon]>,
+) -> Result<()> {
+    writeln!(
+        writer,
+        "rank\treads\tcorrect\tincorrect\ttoo_general\tunclassified\tsensitivity\tprecision"
+    )?;
+    for (rank, score) in EVALUATION_RANKS.iter().zip(scores) {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.6}\t{:.6}",
+            rank,
+            score.reads(),
+            score.correct,
+            score.incorrect,
+            score.too_general,
+            score.unclassified,
+            score.sensitivity(),
+            score.precision()
+        )?;
+    }
+    if let Some(profile) = profile {
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "taxid\tname\trank\ttrue_reads\tcalled_reads\tcorrect_reads"
+        )?;
+        for taxon in profile {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                taxonomy.nodes[taxon.taxon as usize].external_id,
+                taxonomy.name(taxon.taxon),
+                taxonomy.rank(taxon.taxon),
+                taxon.true_reads,
+                taxon.called_reads,
+                taxon.correct_reads
+            )?;
+        }
+    }
+    writer.flush()
+}
+
+pub fn run(args: Args) -> Result<()> {
+    set_unclassified(args.unclassified_taxid, "unclassified")?;
+    let taxonomy = Taxonomy::from_file(args.database.join("taxo.k2d"))?;
+    check_unclassified_taxid(&taxonomy)?;
+
+    let truth = read_truth(&args.truth, &taxonomy)?;
+    let truth_map: HashMap<&str, u32> = truth
+        .iter()
+        .map(|(read_id, taxon)| (read_id.as_str(), *taxon))
+        .collect();
+    let (calls, unknown) = read_calls(&args, &truth_map, &taxonomy)?;
+    let missing = truth.len() - calls.len();
+    // classify 与 resolve 不加 --full-output 时不写出没有命中的 reads
+    if missing > 0 {
+        info!(
+            "{} of {} simulated reads are not in the output, they are counted as unclassified",
+            missing,
+            truth.len()
+        );
+    }
+    if unknown > 0 {
+        warn!(
+            "{} output lines are not simulated reads and are ignored",
+            unknown
+        );
+    }
+
+    // (真实的 taxon, 分类的 taxon)
+    let reads: Vec<(u32, u32)> = truth
+        .iter()
+        .map(|(read_id, taxon)| (*taxon, calls.get(read_id).copied().unwrap_or(0)))
+        .collect();
+    let mut scores = vec![RankScore::default(); EVALUATION_RANKS.len()];
+    for &(truth, call) in &reads {
+        for (rank, score) in EVALUATION_RANKS.iter().zip(scores.iter_mut()) {
+            if let Some(outcome) = score_call(&taxonomy, truth, call, rank) {
+                score.add(outcome);
+            }
+        }
+    }
+    let profile = match &args.profile {
+        Some(path) => {
+            let mut profile = read_profile(path, &taxonomy)?;
+            count_clade_calls(&taxonomy, &mut profile, &reads);
+            Some(profile)
+        }
+        None => None,
+    };
+    let profile = profile.as_deref();
+
+    match &args.output {
+        Some(output) => {
+            let mut writer = BufWriter::new(File::create(output)?);
+            write_tables(&mut writer, &taxonomy, &scores, profile)?;
+        }
+        None => write_tables(&mut io::stdout().lock(), &taxonomy, &scores, profile)?,
+    }
+    info!(
+        "evaluated {} reads, species sensitivity {:.4}, precision {:.4}",
+        reads.len(),
+        scores[1].sensitivity(),
+        scores[1].precision()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
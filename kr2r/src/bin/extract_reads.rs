@@ -0,0 +1,273 @@
+use clap::Parser;
+use kraken2_rs::args::{parse_database, OutputCompression};
+use kraken2_rs::classify::parse_output_line;
+use kraken2_rs::inputs::detect_pairs;
+use kraken2_rs::seqout::{is_stream, mate_paths};
+use kraken2_rs::taxonomy::Taxonomy;
+use kraken2_rs::utils::create_output_writer;
+use seqkmer::{
+    dyn_reader, pair_id, AlignmentOptions, OptionPair, PairCheck, PairOptions, PairSync,
+    RecordReader,
+};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Result, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Extract the reads assigned to given taxa from the original FASTA/FASTQ files",
+    long_about = "Read the per-read output of classify, resolve or direct and write the reads of the original FASTA/FASTQ files that were assigned to the given taxids (optionally with their children or parents) to new files, like extract_kraken_reads.py"
+)]
+pub struct Args {
+    /// Per-read output (Kraken format or --output-format json, plain or compressed); repeat for --split-output-size parts
+    #[clap(short = 'k', long = "kraken-output", required = true)]
+    pub kraken_output: Vec<PathBuf>,
+
+    /// Taxids to extract (comma separated or repeated), 0 for unclassified reads
+    #[clap(short = 't', long = "taxid", value_delimiter = ',', required = true)]
+    pub taxids: Vec<u64>,
+
+    /// database directory or a name registered with `db add`, its taxonomy is needed by
+    /// --include-children and --include-parents
+    #[arg(long = "db", value_parser = parse_database)]
+    pub database: Option<PathBuf>,
+
+    /// Also extract reads assigned to taxa below the given taxids
+    #[clap(long, value_parser, default_value_t = false)]
+    pub include_children: bool,
+
+    /// Also extract reads assigned to the ancestors of the given taxids
+    #[clap(long, value_parser, default_value_t = false)]
+    pub include_parents: bool,
+
+    /// Write the reads that are not assigned to the selected taxa instead
+    #[clap(long, value_parser, default_value_t = false)]
+    pub exclude: bool,
+
+    /// Output file of the extracted reads. For paired reads the name must contain '#', which is
+    /// replaced by "_1" and "_2", unless it is a pipe such as >(cmd), which gets both mates
+    /// interleaved. Names ending in .gz or .zst are compressed
+    #[clap(short, long, value_parser, required = true)]
+    pub output: String,
+
+    /// Enable paired-end processing.
+    #[clap(short = 'P', long = "paired-end-processing", action)]
+    pub paired_end_processing: bool,
+
+    /// Process pairs with mates in the same file.
+    #[clap(short = 'S', long = "single-file-pairs", action)]
+    pub single_file_pairs: bool,
+
+    /// Mate validation for paired reads: strict, repair or off.
+    #[clap(long, value_parser, default_value = "strict")]
+    pub pair_check: PairCheck,
+
+    /// One FASTA/FASTQ (or BAM/CRAM) file, or the R1 and R2 files of paired reads
+    pub input_files: Vec<String>,
+}
+
+/// 要提取的 taxid. 有 taxonomy 时按 `include_children` / `include_parents` 扩展到子孙与祖先
+fn selected_taxa(args: &Args) -> Result<HashSet<u64>> {
+    let mut selected: HashSet<u64> = args.taxids.iter().copied().collect();
+    if !args.include_children && !args.include_parents {
+        return Ok(selected);
+    }
+    let Some(database) = &args.database else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--include-children and --include-parents need the taxonomy of --db",
+        ));
+    };
+    let taxonomy = Taxonomy::from_file(database.join("taxo.k2d"))?;
+    let mut targets = Vec::new();
+    for &taxid in args.taxids.iter().filter(|&&taxid| taxid != 0) {
+        match taxonomy.get_internal_id(taxid) {
+            0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("taxid {} is not in the database taxonomy", taxid),
+                ))
+            }
+            internal_id => targets.push(internal_id),
+        }
+    }
+    for internal_id in 1..taxonomy.node_count() as u32 {
+        let keep = targets.iter().any(|&target| {
+            (args.include_children && taxonomy.is_a_ancestor_of_b(target, internal_id))
+                || (args.include_parents && taxonomy.is_a_ancestor_of_b(internal_id, target))
+        });
+        if keep {
+            selected.insert(taxonomy.nodes[internal_id as usize].external_id);
+        }
+    }
+    Ok(selected)
+}
+
+/// 读取分类结果, 返回 read id 是否属于选中的 taxa. 同一个 read 出现多次时使用最后一次
+fn read_assignments(args: &Args, selected: &HashSet<u64>) -> Result<HashMap<String, bool>> {
+    let mut assignments = HashMap::new();
+    for file in &args.kraken_output {
+        let reader = BufReader::new(dyn_reader(file)?);
+        for line in reader.lines() {
+            let line = line?;
+            // 跳过其他行, 例如 direct 写到标准输出的样本列表与大类统计
+            if !line.starts_with("C\t") && !line.starts_with("U\t") && !line.starts_with('{') {
+                continue;
+            }
+            let (read_id, taxid) = parse_output_line(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:?}: not a per-read output line: {}", file, line),
+                )
+            })?;
+            assignments.insert(read_id, selected.contains(&taxid));
+        }
+    }
+    Ok(assignments)
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    if !args.paired_end_processing && !args.single_file_pairs && args.input_files.len() == 2 {
+        if let Some(inputs) = detect_pairs(&args.input_files) {
+            args.input_files = inputs;
+            args.paired_end_processing = true;
+        }
+    }
+    let interleaved = args.paired_end_processing && args.single_file_pairs;
+    let paired = args.paired_end_processing;
+    let expected = if paired && !interleaved { 2 } else { 1 };
+    if args.input_files.len() != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "expected {} input file(s), got {} (use -P for R1 and R2 files, -S for interleaved pairs)",
+                expected,
+                args.input_files.len()
+            ),
+        ));
+    }
+    // 写入 FIFO 或 /dev/fd/N 时不压缩, paired reads 没有 `#` 时交替写入同一个流
+    let streaming = is_stream(&args.output);
+    let output_paths = if streaming && paired && !args.output.contains('#') {
+        OptionPair::Single(PathBuf::from(&args.output))
+    } else {
+        mate_paths(&args.output, paired)?
+    };
+
+    let start = Instant::now();
+    let selected = selected_taxa(&args)?;
+    let assignments = read_assignments(&args, &selected)?;
+    info!(
+        "{} taxa selected, {} reads in the per-read output",
+        selected.len(),
+        assignments.len()
+    );
+
+    let alignment = AlignmentOptions::default();
+    let mut readers = args
+        .input_files
+        .iter()
+        .map(|file| RecordReader::from_path_with_alignment(file, &alignment, paired))
+        .collect::<Result<Vec<_>>>()?;
+    let last = readers.len() - 1;
+    let mut sync = PairSync::new(PairOptions {
+        interleaved,
+        check: args.pair_check,
+    });
+    let mut writers = output_paths.map(|path| {
+        let compression = if streaming {
+            OutputCompression::None
+        } else {
+            OutputCompression::from_path(path)
+        };
+        create_output_writer(path, compression)
+    })?;
+
+    let (mut total, mut missing, mut written) = (0u64, 0u64, 0u64);
+    loop {
+        let records = if paired {
+            sync.next_pair(
+                |mate| readers[mate.min(last)].next_record(),
+                |record| pair_id(&record.header),
+            )?
+            .map(|(record1, record2)| OptionPair::Pair(record1, record2))
+        } else {
+            readers[0].next_record()?.map(OptionPair::Single)
+        };
+        let Some(records) = records else {
+            break;
+        };
+        total += 1;
+        let header = match &records {
+            OptionPair::Single(record) | OptionPair::Pair(record, _) => &record.header,
+        };
+        let read_id = String::from_utf8_lossy(pair_id(header));
+        // 不在分类结果中的 read (resolve 不输出没有命中的 read) 视为未分类
+        let is_selected = match assignments.get(read_id.as_ref()) {
+            Some(&is_selected) => is_selected,
+            None => {
+                missing += 1;
+                selected.contains(&0)
+            }
+        };
+        if is_selected == args.exclude {
+            continue;
+        }
+        written += 1;
+        match (&mut writers, &records) {
+            (OptionPair::Single(writer), OptionPair::Single(record)) => {
+                record.write_to(writer, "")?
+            }
+            (OptionPair::Pair(writer1, writer2), OptionPair::Pair(record1, record2)) => {
+                record1.write_to(writer1, "")?;
+                record2.write_to(writer2, "")?;
+            }
+            (OptionPair::Single(writer), OptionPair::Pair(record1, record2)) => {
+                record1.write_to(writer, "")?;
+                record2.write_to(writer, "")?;
+            }
+            _ => unreachable!("writers and records have the same pairing"),
+        }
+    }
+    match &mut writers {
+        OptionPair::Single(writer) => writer.flush()?,
+        OptionPair::Pair(writer1, writer2) => {
+            writer1.flush()?;
+            writer2.flush()?;
+        }
+    }
+    drop(writers);
+
+    if paired && sync.stats().orphan_count() > 0 {
+        info!("{}", sync.stats());
+    }
+    if missing > 0 {
+        info!(
+            "{} {} are not in the per-read output, counted as unclassified",
+            missing,
+            if paired { "pairs" } else { "reads" }
+        );
+    }
+    info!(
+        "{} of {} {} written to {}",
+        written,
+        total,
+        if paired { "pairs" } else { "reads" },
+        args.output
+    );
+    info!("extract-reads took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
@@ -0,0 +1,274 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::classify::{lookup_rows, process_hitgroup, resolve_tree};
+use kraken2_rs::compact_hash::{CHTable, HashConfig, Row, ShardChecksums};
+use kraken2_rs::stats::{report_name, taxon_name};
+use kraken2_rs::taxonomy::Taxonomy;
+use kraken2_rs::{HitGroup, IndexOptions};
+use seqkmer::{read_parallel, FastaReader, Meros, Reader, WindowReader};
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Read, Result, Write};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Identify complete genomes or assemblies",
+    long_about = "Split each genome FASTA record into fixed-size windows, classify every window as a pseudo-read and report a consensus identification per genome"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Directory for genome_*.tsv and genome_*.windows.tsv.
+    #[clap(long = "output-dir", value_parser, required = true)]
+    pub output_dir: PathBuf,
+
+    /// Length of each pseudo-read window in bp.
+    #[clap(short = 'w', long, value_parser, default_value_t = 5000)]
+    pub window_size: usize,
+
+    /// Distance between window starts in bp, defaults to the window size (no overlap).
+    #[clap(long, value_parser)]
+    pub window_step: Option<usize>,
+
+    /// Confidence score threshold for each window.
+    #[clap(
+        short = 'T',
+        long = "confidence-threshold",
+        value_parser,
+        default_value_t = 0.0
+    )]
+    pub confidence_threshold: f64,
+
+    /// The minimum number of hit groups needed for a window call.
+    #[clap(
+        short = 'g',
+        long = "minimum-hit-groups",
+        value_parser,
+        default_value_t = 2
+    )]
+    pub minimum_hit_groups: usize,
+
+    /// Fraction of all windows that must fall in the clade of the consensus call.
+    #[clap(long, value_parser, default_value_t = 0.5)]
+    pub min_consensus: f64,
+
+    /// The number of threads to use.
+    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    pub num_threads: usize,
+
+    /// Genome FASTA files (plain or gzip compressed), one or more records each.
+    pub input_files: Vec<String>,
+}
+
+/// 单个窗口的分类结果
+struct WindowCall {
+    genome: String,
+    window_index: usize,
+    seq_len: usize,
+    ext_call: u64,
+}
+
+fn classify_windows<R: Reader>(
+    args: &Args,
+    meros: Meros,
+    hash_config: HashConfig,
+    chtable: &CHTable,
+    taxonomy: &Taxonomy,
+    reader: &mut WindowReader<R>,
+) -> Result<Vec<WindowCall>> {
+    let classify_counter = AtomicUsize::new(0);
+    let mut calls = Vec::new();
+
+    read_parallel(
+        reader,
+        args.num_threads,
+        &meros,
+        |windows| {
+            let mut batch = Vec::new();
+            for window in windows {
+                let rows: Vec<Row> = window.fold(|rows, m_iter, offset| {
+                    lookup_rows(rows, m_iter, &hash_config, chtable, offset)
+                });
+                let hits = HitGroup::new(rows, window.range());
+                let hit_data = process_hitgroup(
+                    &hits,
+                    taxonomy,
+                    &classify_counter,
+                    hits.required_score(args.confidence_threshold),
+                    args.minimum_hit_groups,
+                    hash_config.value_mask,
+                    None,
+                    None,
+                );
+                batch.push(WindowCall {
+                    genome: window.header.id.clone(),
+                    window_index: window.header.reads_index,
+                    seq_len: window.body.reduce(0, |acc, m_iter| acc + m_iter.seq_size()),
+                    ext_call: hit_data.1,
+                });
+            }
+            batch
+        },
+        |dataset| {
+            while let Some(data) = dataset.next() {
+                calls.extend(data.unwrap());
+            }
+        },
+    )?;
+
+    Ok(calls)
+}
+
+fn write_genome_reports(
+    args: &Args,
+    taxonomy: &Taxonomy,
+    file_index: usize,
+    genomes: &[(String, usize)],
+    mut calls: Vec<WindowCall>,
+) -> Result<()> {
+    let window_step = args.window_step.unwrap_or(args.window_size);
+    calls.sort_unstable_by_key(|call| call.window_index);
+    let mut genome_calls: HashMap<&str, Vec<&WindowCall>> = HashMap::new();
+    for call in &calls {
+        genome_calls.entry(&call.genome).or_default().push(call);
+    }
+
+    let mut window_writer = BufWriter::new(File::create(
+        args.output_dir
+            .join(format!("genome_{}.windows.tsv", file_index)),
+    )?);
+    writeln!(window_writer, "#genome\tstart\tend\ttaxid\tname")?;
+    let mut writer = BufWriter::new(File::create(
+        args.output_dir.join(format!("genome_{}.tsv", file_index)),
+    )?);
+    writeln!(
+        writer,
+        "#genome\tlength\twindows\tclassified_windows\ttaxid\tname\tsupport\ttop_window_taxa"
+    )?;
+
+    let mut identified = 0;
+    for (genome, length) in genomes {
+        let windows = genome_calls.remove(genome.as_str()).unwrap_or_default();
+        let mut counts: HashMap<u32, u64> = HashMap::new();
+        let mut ext_counts: HashMap<u64, u64> = HashMap::new();
+        for window in &windows {
+            let start = (window.window_index - 1) * window_step;
+            writeln!(
+                window_writer,
+                "{}\t{}\t{}\t{}\t{}",
+                genome,
+                start + 1,
+                start + window.seq_len,
+                window.ext_call,
+                report_name(taxon_name(taxonomy, window.ext_call))
+            )?;
+            if window.ext_call > 0 {
+                *counts
+                    .entry(taxonomy.get_internal_id(window.ext_call))
+                    .or_insert(0) += 1;
+                *ext_counts.entry(window.ext_call).or_insert(0) += 1;
+            }
+        }
+
+        let total = windows.len() as u64;
+        let classified: u64 = counts.values().sum();
+        let required_score = (args.min_consensus * total as f64).ceil() as u64;
+        let call = resolve_tree(&counts, taxonomy, required_score);
+        let support: u64 = counts
+            .iter()
+            .filter(|(&taxon, _)| call > 0 && taxonomy.is_a_ancestor_of_b(call, taxon))
+            .map(|(_, &count)| count)
+            .sum();
+        let ext_call = taxonomy.nodes[call as usize].external_id;
+
+        let mut top: Vec<(u64, u64)> = ext_counts.into_iter().collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let top_str = top
+            .iter()
+            .take(5)
+            .map(|(taxid, count)| format!("{}:{}", taxid, count))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{}",
+            genome,
+            length,
+            total,
+            classified,
+            ext_call,
+            report_name(taxon_name(taxonomy, ext_call)),
+            if total > 0 {
+                support as f64 / total as f64
+            } else {
+                0.0
+            },
+            top_str
+        )?;
+        identified += (ext_call > 0) as usize;
+    }
+
+    info!(
+        "file {}: {} of {} genomes identified",
+        file_index,
+        identified,
+        genomes.len()
+    );
+    window_writer.flush()?;
+    writer.flush()
+}
+
+pub fn run(args: Args) -> Result<()> {
+    if args.window_size == 0 || args.window_step == Some(0) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--window-size and --window-step must be positive",
+        ));
+    }
+    let idx_opts = IndexOptions::read_index_options(args.database.join("opts.k2d"))?;
+    let taxo = Taxonomy::from_file(args.database.join("taxo.k2d"))?;
+    let hash_config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    let checksums = ShardChecksums::from_hash_header(args.database.join("hash_config.k2d"))?;
+    if hash_config.hash_capacity == 0 {
+        panic!("`hash_capacity` can't be zero!");
+    }
+    create_dir_all(&args.output_dir)?;
+
+    info!("genome identification start...");
+    let start = Instant::now();
+    let meros = idx_opts.as_meros();
+    let hash_files = ChunkCatalog::scan_continuous(&args.database, ChunkKind::Hash)?.paths();
+    let chtable = CHTable::from_hash_files(hash_config, &hash_files, checksums.as_ref())?;
+
+    let window_step = args.window_step.unwrap_or(args.window_size);
+    for (i, input_file) in args.input_files.iter().enumerate() {
+        let file_index = i + 1;
+        let fasta = FastaReader::from_path(input_file, file_index)?;
+        let mut reader: WindowReader<FastaReader<Box<dyn Read + Send>>> =
+            WindowReader::new(fasta, args.window_size, window_step);
+        let calls = classify_windows(&args, meros, hash_config, &chtable, &taxo, &mut reader)?;
+        write_genome_reports(&args, &taxo, file_index, reader.genomes(), calls)?;
+    }
+
+    info!("genome identification took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
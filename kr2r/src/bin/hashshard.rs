@@ -1,10 +1,13 @@
 use clap::Parser;
 use kraken2_rs::args::parse_size;
-use kraken2_rs::compact_hash::HashConfig;
+use kraken2_rs::checksums::write_checksums;
+use kraken2_rs::compact_hash::{ChecksumWriter, HashConfig, ShardChecksums};
+use kraken2_rs::error::{Kr2rError, Result};
+use tracing::info;
 // use memmap2::MmapOptions;
 use std::fs::{self, create_dir_all, File, OpenOptions};
 use std::io::BufWriter;
-use std::io::{self, BufReader, Read, Result as IOResult, Seek, Write};
+use std::io::{self, BufReader, Read, Seek, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use std::time::Instant;
@@ -16,25 +19,29 @@ fn mmap_read_write<P: AsRef<Path>, Q: AsRef<Path>>(
     cap: usize,
     offset: u64,
     length: usize,
-) -> IOResult<()> {
-    let mut dest_file = BufWriter::new(File::create(dest_path)?);
-    dest_file
-        .write_all(&partition.to_le_bytes())
-        .expect("Failed to write capacity");
-    dest_file
-        .write_all(&cap.to_le_bytes())
-        .expect("Failed to write capacity");
-
-    let mut file = OpenOptions::new().read(true).open(&source_path)?;
+) -> Result<u64> {
+    let mut dest_file = ChecksumWriter::new(BufWriter::new(File::create(dest_path)?));
+    dest_file.write_all(&partition.to_le_bytes())?;
+    dest_file.write_all(&cap.to_le_bytes())?;
+
+    let source_path = source_path.as_ref();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .open(source_path)
+        .map_err(|e| Kr2rError::index(source_path, e))?;
     file.seek(io::SeekFrom::Start(offset))?;
     let mut reader = BufReader::new(file);
 
     let mut buffer = vec![0; length];
-    reader.read_exact(&mut buffer)?;
+    // hash.k2d 比头部记录的容量短
+    reader
+        .read_exact(&mut buffer)
+        .map_err(|e| Kr2rError::index(source_path, e))?;
 
     dest_file.write_all(&buffer)?;
+    dest_file.flush()?;
 
-    Ok(())
+    Ok(dest_file.checksum())
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -57,15 +64,15 @@ pub struct Args {
     hash_capacity: usize,
 }
 
-pub fn run(args: Args) -> IOResult<()> {
+pub fn run(args: Args) -> Result<()> {
     let index_filename = &args.database.join("hash.k2d");
 
     let mut hash_config = HashConfig::from_kraken2_header(index_filename)?;
-    let partition = (hash_config.capacity + args.hash_capacity - 1) / args.hash_capacity;
+    let partition = hash_config.capacity.div_ceil(args.hash_capacity);
     hash_config.partition = partition;
     hash_config.hash_capacity = args.hash_capacity;
 
-    println!("hashshard start...");
+    info!("hashshard start...");
 
     let start = Instant::now();
 
@@ -74,15 +81,17 @@ pub fn run(args: Args) -> IOResult<()> {
 
     let k2d_dir = args.database.clone();
 
-    create_dir_all(&k2d_dir).expect(&format!("create hash dir error {:?}", k2d_dir));
+    create_dir_all(&k2d_dir)?;
 
     let config_file = k2d_dir.join("hash_config.k2d");
     if config_file.exists() {
-        panic!("hash config is exists!!!");
+        return Err(Kr2rError::Config(format!(
+            "{:?} already exists, the database is already sharded",
+            config_file
+        )));
     }
 
-    hash_config.write_to_file(config_file)?;
-
+    let mut checksums = Vec::with_capacity(partition);
     for i in 1..=partition {
         let chunk_file = k2d_dir.join(format!("hash_{}.k2d", i));
         let offset = (32 + args.hash_capacity * (i - 1) * b_size) as u64;
@@ -91,32 +100,46 @@ pub fn run(args: Args) -> IOResult<()> {
             length = file_len - offset as usize;
         }
         let cap = length / b_size;
-        mmap_read_write(&index_filename, chunk_file, i, cap, offset, length)?
+        checksums.push(mmap_read_write(
+            index_filename,
+            chunk_file,
+            i,
+            cap,
+            offset,
+            length,
+        )?);
     }
+    // 全部 shard 写完后再写配置, 中断时不会留下不完整的数据库
+    hash_config.write_to_file(config_file, Some(&ShardChecksums(checksums)))?;
 
     let duration = start.elapsed();
 
-    println!("hashshard took: {:?}", duration);
+    info!("hashshard took: {:?}", duration);
 
     let source_taxo_file = &args.database.join("taxo.k2d");
     let dst_tax_file = k2d_dir.join("taxo.k2d");
     if !dst_tax_file.exists() {
-        fs::copy(source_taxo_file, dst_tax_file)?;
+        fs::copy(source_taxo_file, dst_tax_file)
+            .map_err(|e| Kr2rError::index(source_taxo_file, e))?;
     }
 
     let source_opts_file = &args.database.join("opts.k2d");
     let dst_opts_file = k2d_dir.join("opts.k2d");
     if !dst_opts_file.exists() {
-        fs::copy(source_opts_file, dst_opts_file)?;
+        fs::copy(source_opts_file, dst_opts_file)
+            .map_err(|e| Kr2rError::index(source_opts_file, e))?;
     }
+    write_checksums(&k2d_dir)?;
 
     Ok(())
 }
 
 #[allow(dead_code)]
 fn main() {
+    kraken2_rs::logging::init_default_logging();
     let args = Args::parse();
     if let Err(e) = run(args) {
         eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
     }
 }
@@ -0,0 +1,201 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::compact_hash::{read_page_from_file, Compact, HashConfig};
+use kraken2_rs::db::read_minimizer_cap;
+use kraken2_rs::readcounts::{ReadCounter, TaxonCounters};
+use kraken2_rs::report::{get_clade_counters, kraken_report_dfs, report_mpa_style};
+use kraken2_rs::taxonomy::Taxonomy;
+use kraken2_rs::IndexOptions;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Count the minimizers of each taxon in a database (like kraken2-inspect)",
+    long_about = "Count the minimizers of each taxon in the hash_*.k2d shards and write a kreport-style summary of the database contents (like kraken2-inspect)"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Output file of the summary, default: inspect.txt in the database directory
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Only write the database options and table size, skip counting the minimizers
+    #[clap(short, long, value_parser, default_value_t = false)]
+    pub skip_counts: bool,
+
+    /// Write the counts in MPA style (without the database options header)
+    #[clap(short = 'm', long, value_parser, default_value_t = false)]
+    pub use_mpa_style: bool,
+
+    /// Also report taxa with no minimizers
+    #[clap(short = 'z', long, value_parser, default_value_t = false)]
+    pub report_zero_counts: bool,
+}
+
+/// 与 kraken2-inspect 相同, 以 0/1 字符串显示低 `bits` 位的掩码
+fn mask_to_string(mask: u64, bits: usize) -> String {
+    (0..bits)
+        .rev()
+        .map(|i| if (mask >> i) & 1 == 1 { '1' } else { '0' })
+        .collect()
+}
+
+fn write_header(
+    file: &mut File,
+    database: &Path,
+    opts: &IndexOptions,
+    taxonomy: &Taxonomy,
+    config: &HashConfig,
+) -> Result<()> {
+    writeln!(
+        file,
+        "# Database options: {} db, k = {}, l = {}",
+        if opts.dna_db { "nucleotide" } else { "protein" },
+        opts.k,
+        opts.l
+    )?;
+    writeln!(
+        file,
+        "# Spaced mask = {}",
+        mask_to_string(
+            opts.spaced_seed_mask,
+            opts.l * if opts.dna_db { 2 } else { 4 }
+        )
+    )?;
+    writeln!(
+        file,
+        "# Toggle mask = {}",
+        mask_to_string(opts.toggle_mask, 64)
+    )?;
+    writeln!(file, "# Total taxonomy nodes: {}", taxonomy.node_count())?;
+    writeln!(file, "# Table size: {}", config.size)?;
+    writeln!(file, "# Table capacity: {}", config.capacity)?;
+    if opts.minimum_acceptable_hash_value != 0 {
+        writeln!(
+            file,
+            "# Min clear hash value = {}",
+            opts.minimum_acceptable_hash_value
+        )?;
+    }
+    let cap = read_minimizer_cap(database)?;
+    let value = |name: &str| {
+        cap.iter()
+            .find(|(key, _)| key == name)
+            .map_or("-", |(_, value)| value.as_str())
+    };
+    if !cap.is_empty() {
+        writeln!(
+            file,
+            "# Minimizer cap: {} genomes of {}, {} minimizers dropped",
+            value("limit"),
+            value("library_genomes"),
+            value("dropped_minimizers")
+        )?;
+    }
+    Ok(())
+}
+
+/// 逐个读取 shard, 统计每个 taxon (内部 id) 的 minimizer 数
+fn count_minimizers(hash_files: &[PathBuf], config: &HashConfig) -> Result<HashMap<u64, u64>> {
+    let mut counts: HashMap<u64, u64> = HashMap::new();
+    for (i, hash_file) in hash_files.iter().enumerate() {
+        let page = read_page_from_file(hash_file)?;
+        let page_counts = page.data[..page.size]
+            .par_chunks(1 << 16)
+            .fold(HashMap::new, |mut counts: HashMap<u64, u64>, cells| {
+                for cell in cells.iter().filter(|&&cell| cell != 0) {
+                    *counts
+                        .entry(cell.right(config.value_mask) as u64)
+                        .or_default() += 1;
+                }
+                counts
+            })
+            .reduce(HashMap::new, |mut a, b| {
+                for (taxid, count) in b {
+                    *a.entry(taxid).or_default() += count;
+                }
+                a
+            });
+        for (taxid, count) in page_counts {
+            *counts.entry(taxid).or_default() += count;
+        }
+        info!("count {:?}/{}", i + 1, hash_files.len());
+    }
+    Ok(counts)
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let opts = IndexOptions::read_index_options(args.database.join("opts.k2d"))?;
+    let taxonomy = Taxonomy::from_file(args.database.join("taxo.k2d"))?;
+    let config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    let hash_files = ChunkCatalog::scan_continuous(&args.database, ChunkKind::Hash)?.paths();
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| args.database.join("inspect.txt"));
+
+    info!("inspect start...");
+    let start = Instant::now();
+
+    let mut file = File::create(&output)?;
+    if !args.use_mpa_style {
+        write_header(&mut file, &args.database, &opts, &taxonomy, &config)?;
+    }
+    if args.skip_counts {
+        info!("inspect took: {:?}", start.elapsed());
+        return Ok(());
+    }
+
+    let counts = count_minimizers(&hash_files, &config)?;
+    let total: u64 = counts.values().sum();
+    let call_counters: TaxonCounters = counts
+        .into_iter()
+        .filter(|&(taxid, _)| taxid != 0)
+        .map(|(taxid, count)| (taxid, ReadCounter::new(count, 0)))
+        .collect();
+
+    if args.use_mpa_style {
+        drop(file);
+        report_mpa_style(&output, args.report_zero_counts, &taxonomy, &call_counters)?;
+    } else {
+        let mut clade_counters = get_clade_counters(&taxonomy, &call_counters);
+        kraken_report_dfs(
+            1,
+            &mut file,
+            args.report_zero_counts,
+            false,
+            &taxonomy,
+            &mut clade_counters,
+            &call_counters,
+            total,
+            'R',
+            -1,
+            0,
+        )?;
+    }
+
+    info!("inspect {} minimizers into {:?}", total, output);
+    info!("inspect took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
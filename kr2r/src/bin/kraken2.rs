@@ -1,20 +1,61 @@
 use clap::{Parser, Subcommand};
+use tracing::level_filters::LevelFilter;
+use tracing::{info, warn};
+mod abundance;
+mod add_to_db;
 mod annotate;
+mod benchmark;
 mod build_k2_db;
 mod chunk_db;
+mod classify_seq;
+mod cluster_unclassified;
+mod deplete;
 mod direct;
+mod doctor;
+mod download_library;
+mod downsweep;
 mod estimate_capacity;
+mod evaluate;
+mod extract_reads;
+mod genome;
 mod hashshard;
+mod inspect;
+mod lookup_reads;
 mod merge_fna;
+mod merge_reports;
+mod profile_db;
+#[cfg(feature = "project")]
+mod project;
+mod prune_db;
+mod registry;
+mod reshard;
 mod resolve;
 // mod seqid2taxid;
+#[cfg(feature = "server")]
+mod shard_worker;
+mod simulate;
+mod sort_bins;
 mod splitr;
+mod taxon_accessions;
+mod unshard;
+mod verify_db;
+mod warmup;
+mod watch;
 
-use kraken2_rs::args::ClassifyArgs;
-use kraken2_rs::args::{parse_size, Build};
+use kraken2_rs::args::{parse_size, BatchSize, Build, HashCapacity, MAX_AUTO_HASH_CAPACITY};
+use kraken2_rs::args::{ClassifyArgs, OutputCompression, OutputFormat};
+use kraken2_rs::checkpoint::CHECKPOINT_FILE;
+use kraken2_rs::db::BATCH_SIZE;
+use kraken2_rs::error;
+use kraken2_rs::events::{self, Event};
+use kraken2_rs::logging::{self, LogFormat};
+use kraken2_rs::progress;
+use kraken2_rs::seqout::check_rereadable;
 use kraken2_rs::utils::find_files;
 // use std::io::Result;
+use seqkmer::is_stdin_path;
 use std::path::PathBuf;
+use std::process::ExitCode;
 use std::time::Instant;
 
 #[derive(Parser, Debug, Clone)]
@@ -52,11 +93,57 @@ struct BuildArgs {
     /// library fna temp file max size
     #[arg(long = "max-file-size", value_parser = parse_size, default_value = "2G")]
     pub max_file_size: usize,
+
+    /// Also build database<N>mers.kmer_distrib for `abundance` with reads of this length
+    #[arg(long)]
+    pub bracken_read_len: Option<usize>,
+
+    /// GTDB taxonomy or metadata files (e.g. bac120_taxonomy.tsv ar53_taxonomy.tsv); assemblies are labelled with their GTDB species and the taxonomy is built from GTDB instead of nodes.dmp/names.dmp
+    #[arg(long, num_args = 1..)]
+    pub gtdb_taxonomy: Vec<PathBuf>,
+
+    /// Number of cells read from a chunk file per batch when building the hash table, or "auto" to pick it from the chunk size and CPU cache
+    #[arg(long, default_value_t = BatchSize::Fixed(BATCH_SIZE))]
+    pub batch_size: BatchSize,
+
+    /// Memory for one hash shard while building, e.g. 64G ('K', 'M' or 'G' suffix). A shard whose
+    /// table (hash capacity x 4 bytes) is larger is built by sorting its chunk into runs on disk
+    /// and merging them, instead of holding the whole shard in memory
+    #[arg(long, value_parser = parse_size)]
+    pub max_build_memory: Option<usize>,
+
+    /// Cells per hash shard (e.g. '1G', '250M', the shard file is 4 times as large), or "auto" to
+    /// split the estimated capacity into shards of equal size with at most 1G cells each
+    #[arg(long, default_value_t = HashCapacity::Auto)]
+    pub hash_capacity: HashCapacity,
+
+    #[clap(flatten)]
+    pub minimizer_cap: build_k2_db::MinimizerCapArgs,
 }
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    /// Don't show the progress bars of splitr, annotate, resolve and the database build
+    #[clap(short, long, global = true, default_value_t = false)]
+    quiet: bool,
+
+    /// Lowest level of the log messages written to stderr: error, warn, info, debug or trace
+    #[clap(long, global = true, default_value = "info")]
+    log_level: LevelFilter,
+
+    /// Append the log messages to this file instead of stderr
+    #[clap(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Format of the log messages: text, or json with one event object per line
+    #[clap(long, global = true, default_value = "text")]
+    log_format: LogFormat,
+
+    /// POST every stage event (start, chunk, sample, end) and warning or error (alert) to this URL as JSON
+    #[clap(long, global = true)]
+    event_webhook: Option<String>,
+
     #[clap(subcommand)]
     cmd: Commands,
 }
@@ -67,9 +154,22 @@ impl From<ClassifyArgs> for splitr::Args {
             database: item.database,
             paired_end_processing: item.paired_end_processing,
             single_file_pairs: item.single_file_pairs,
+            unpaired: item.unpaired,
+            pair_check: item.pair_check,
+            pair_mode: item.pair_mode,
+            unmapped_only: item.unmapped_only,
+            cram_reference: item.cram_reference,
             minimum_quality_score: item.minimum_quality_score,
+            quality_encoding: item.quality_encoding,
             num_threads: item.num_threads,
+            compute_threads: item.compute_threads,
+            io_threads: item.io_threads,
+            decompress_threads: item.decompress_threads,
             chunk_dir: item.chunk_dir,
+            qc_stats: item.qc_stats,
+            sample_regex: item.sample_regex,
+            merge_inputs: item.merge_inputs,
+            sample_sheet: item.sample_sheet,
             input_files: item.input_files,
         }
     }
@@ -82,7 +182,15 @@ impl From<ClassifyArgs> for annotate::Args {
             chunk_dir: item.chunk_dir,
             batch_size: item.batch_size,
             buffer_size: item.buffer_size,
+            lookup_cache: item.lookup_cache,
             num_threads: item.num_threads,
+            compute_threads: item.compute_threads,
+            io_threads: item.io_threads,
+            memory_mapping: item.memory_mapping,
+            shm_cache: item.shm_cache,
+            no_verify: item.no_verify,
+            strict_chunks: item.strict_chunks,
+            workers: item.workers,
         }
     }
 }
@@ -93,11 +201,64 @@ impl From<ClassifyArgs> for resolve::Args {
             database: item.database,
             chunk_dir: item.chunk_dir,
             num_threads: item.num_threads,
+            compute_threads: item.compute_threads,
+            io_threads: item.io_threads,
+            memory_mapping: item.memory_mapping,
+            single_file_pairs: item.paired_end_processing && item.single_file_pairs,
+            pair_check: item.pair_check,
+            unmapped_only: item.unmapped_only,
+            cram_reference: item.cram_reference,
             confidence_threshold: item.confidence_threshold,
             minimum_hit_groups: item.minimum_hit_groups,
+            max_rank: item.max_rank,
+            min_clade_support: item.min_clade_support,
             output_dir: item.output_dir,
             report_kmer_data: item.report_kmer_data,
             report_zero_counts: item.report_zero_counts,
+            report_headers: item.report_headers,
+            read_length_stats: item.read_length_stats,
+            length_bin_size: item.length_bin_size,
+            qc_stats: item.qc_stats,
+            report_krona: item.report_krona,
+            stop_when: item.stop_when,
+            qc_gate: item.qc_gate,
+            qc_fail_exit: item.qc_fail_exit,
+            use_names: item.use_names,
+            unclassified_taxid: item.unclassified_taxid,
+            unclassified_label: item.unclassified_label,
+            name_normalization: item.name_normalization,
+            full_output: item.full_output,
+            strict_ids: item.strict_ids,
+            read_minimizer_data: item.read_minimizer_data,
+            output_format: item.output_format,
+            compress_output: item.compress_output,
+            no_standard_output: item.no_standard_output,
+            segments_bed: item.segments_bed,
+            split_output_size: item.split_output_size,
+            name_outputs: item.name_outputs,
+            classified_out: item.classified_out,
+            unclassified_out: item.unclassified_out,
+            strict_chunks: item.strict_chunks,
+            low_power: item.low_power,
+        }
+    }
+}
+
+impl From<ClassifyArgs> for downsweep::Args {
+    fn from(item: ClassifyArgs) -> Self {
+        Self {
+            database: item.database,
+            output_dir: item.output_dir.unwrap_or_default(),
+            minimum_quality_score: item.minimum_quality_score,
+            quality_encoding: item.quality_encoding,
+            single_file_pairs: item.paired_end_processing && item.single_file_pairs,
+            pair_check: item.pair_check,
+            pair_mode: item.pair_mode,
+            unmapped_only: item.unmapped_only,
+            cram_reference: item.cram_reference,
+            confidence_threshold: item.confidence_threshold,
+            min_genus_reads: 1,
+            num_threads: item.num_threads,
         }
     }
 }
@@ -119,7 +280,8 @@ impl From<BuildArgs> for chunk_db::Args {
     fn from(item: BuildArgs) -> Self {
         Self {
             build: item.build,
-            hash_capacity: parse_size("1G").unwrap(),
+            // build 按估计的容量选择
+            hash_capacity: MAX_AUTO_HASH_CAPACITY,
         }
     }
 }
@@ -130,6 +292,8 @@ impl From<BuildArgs> for merge_fna::Args {
             download_dir: item.download_dir,
             database: item.build.database,
             max_file_size: item.max_file_size,
+            protein: item.build.klmt.is_protein(),
+            gtdb_taxonomy: item.gtdb_taxonomy,
         }
     }
 }
@@ -140,18 +304,79 @@ enum Commands {
     // Seqid2taxid(seqid2taxid::Args),
     Build(BuildArgs),
     Hashshard(hashshard::Args),
+    Unshard(unshard::Args),
+    Inspect(inspect::Args),
+    ProfileDb(profile_db::Args),
+    VerifyDb(verify_db::Args),
+    Warmup(warmup::Args),
+    Reshard(reshard::Args),
+    AddToDb(add_to_db::Args),
+    PruneDb(prune_db::Args),
     Splitr(splitr::Args),
     Annotate(annotate::Args),
+    SortBins(sort_bins::Args),
     Resolve(resolve::Args),
+    #[cfg(feature = "server")]
+    ShardWorker(shard_worker::Args),
+    Doctor(doctor::Args),
     Classify(ClassifyArgs),
     Direct(direct::Args),
+    Watch(watch::Args),
+    ClassifySeq(classify_seq::Args),
+    Deplete(deplete::Args),
+    Downsweep(downsweep::Args),
+    ExtractReads(extract_reads::Args),
+    ClusterUnclassified(cluster_unclassified::Args),
+    Genome(genome::Args),
+    Abundance(abundance::Args),
+    MergeReports(merge_reports::Args),
+    #[cfg(feature = "project")]
+    Project(project::Args),
+    TaxonAccessions(taxon_accessions::Args),
+    LookupReads(lookup_reads::Args),
+    Benchmark(benchmark::Args),
+    Simulate(simulate::Args),
+    Evaluate(evaluate::Args),
+    DownloadLibrary(download_library::Args),
     MergeFna(merge_fna::Args),
+    Db(registry::Args),
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> ExitCode {
     let args = Args::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(error::exit_code(e.as_ref()))
+        }
+    }
+}
 
-    match args.cmd {
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    progress::set_quiet(args.quiet);
+    logging::init_logging(args.log_level, args.log_format, args.log_file.as_deref())?;
+    // drop 时等待剩余的事件发送完
+    let _webhook = args
+        .event_webhook
+        .as_deref()
+        .map(events::Webhook::start)
+        .transpose()?;
+
+    let result = run_command(args.cmd);
+    if let Err(e) = &result {
+        let mut event = Event::new("", "alert", &e.to_string());
+        event.level = "ERROR".to_string();
+        events::emit(&event);
+    }
+    result
+}
+
+fn run_command(cmd: Commands) -> Result<(), Box<dyn std::error::Error>> {
+    match cmd {
+        Commands::DownloadLibrary(cmd_args) => {
+            download_library::run(cmd_args)?;
+        }
         Commands::MergeFna(cmd_args) => {
             merge_fna::run(cmd_args)?;
         }
@@ -164,51 +389,178 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let ec_args = estimate_capacity::Args::from(cmd_args.clone());
             let required_capacity = estimate_capacity::run(ec_args);
 
-            let build_args = chunk_db::Args::from(cmd_args.clone());
+            let mut build_args = chunk_db::Args::from(cmd_args.clone());
+            build_args.hash_capacity = cmd_args.hash_capacity.resolve(required_capacity);
+            info!(
+                "hash capacity: {} cells per shard, {} shards",
+                build_args.hash_capacity,
+                required_capacity.div_ceil(build_args.hash_capacity)
+            );
             let database = &build_args.build.database.clone();
             chunk_db::run(build_args, required_capacity)?;
-            build_k2_db::run(database)?;
+            build_k2_db::run(
+                database,
+                cmd_args.batch_size,
+                cmd_args.max_build_memory,
+                cmd_args.minimizer_cap.into(),
+            )?;
+            if let Some(read_len) = cmd_args.bracken_read_len {
+                abundance::build_distrib(database, read_len, cmd_args.build.threads)?;
+            }
         }
         Commands::Hashshard(cmd_args) => {
             hashshard::run(cmd_args)?;
         }
+        Commands::Unshard(cmd_args) => {
+            unshard::run(cmd_args)?;
+        }
+        Commands::Inspect(cmd_args) => {
+            inspect::run(cmd_args)?;
+        }
+        Commands::ProfileDb(cmd_args) => {
+            profile_db::run(cmd_args)?;
+        }
+        Commands::VerifyDb(cmd_args) => {
+            verify_db::run(cmd_args)?;
+        }
+        Commands::Warmup(cmd_args) => {
+            warmup::run(cmd_args)?;
+        }
+        Commands::AddToDb(cmd_args) => {
+            add_to_db::run(cmd_args)?;
+        }
+        Commands::Reshard(cmd_args) => {
+            reshard::run(cmd_args)?;
+        }
+        Commands::PruneDb(cmd_args) => {
+            prune_db::run(cmd_args)?;
+        }
         Commands::Splitr(cmd_args) => {
             splitr::run(cmd_args)?;
         }
         Commands::Annotate(cmd_args) => {
             annotate::run(cmd_args)?;
         }
+        Commands::SortBins(cmd_args) => {
+            sort_bins::run(cmd_args)?;
+        }
         Commands::Resolve(cmd_args) => {
             resolve::run(cmd_args)?;
         }
-        Commands::Classify(cmd_args) => {
+        #[cfg(feature = "server")]
+        Commands::ShardWorker(cmd_args) => {
+            shard_worker::run(cmd_args)?;
+        }
+        Commands::Doctor(cmd_args) => {
+            doctor::run(cmd_args)?;
+        }
+        Commands::Classify(mut cmd_args) => {
             let start = Instant::now();
+            cmd_args.apply_low_power();
 
             let splitr_args = splitr::Args::from(cmd_args.clone());
             let chunk_files = find_files(&splitr_args.chunk_dir, "sample", ".k2");
             let sample_files = find_files(&splitr_args.chunk_dir, "sample_id", ".map");
             let bin_files = find_files(&splitr_args.chunk_dir, "sample", ".bin");
-            if !chunk_files.is_empty() || !sample_files.is_empty() || !bin_files.is_empty() {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
+            // 有断点记录时为中断后重新运行, 由各阶段跳过已完成的部分
+            let resuming = splitr_args.chunk_dir.join(CHECKPOINT_FILE).exists();
+            if !resuming
+                && (!chunk_files.is_empty() || !sample_files.is_empty() || !bin_files.is_empty())
+            {
+                return Err(Box::new(std::io::Error::other(
                     format!(
                         "The directory '{}' must not contain files with extensions '.k2', '.map', or '.bin' for 'sample' and 'sample_id'",
                         &splitr_args.chunk_dir.display()
                     ),
                 )));
             }
+            // 标准输入在 splitr 时读完, 之后的步骤不能再次读取
+            if cmd_args.classified_out.is_some() || cmd_args.unclassified_out.is_some() {
+                check_rereadable(&cmd_args.input_files)?;
+            }
             splitr::run(splitr_args)?;
             let annotate_args = annotate::Args::from(cmd_args.clone());
             annotate::run(annotate_args)?;
             let resolve_args = resolve::Args::from(cmd_args.clone());
             resolve::run(resolve_args)?;
+            if cmd_args.downsweep {
+                if cmd_args.max_rank.is_some() {
+                    warn!("--downsweep reports species below --max-rank, skipped");
+                } else if cmd_args.output_format != OutputFormat::Kraken {
+                    warn!("--downsweep reads the kraken output format, skipped");
+                } else if cmd_args.no_standard_output {
+                    warn!(
+                        "--downsweep reads the per-read output, skipped with --no-standard-output"
+                    );
+                } else if cmd_args.split_output_size.is_some() {
+                    warn!("--downsweep rewrites a single per-read output file, skipped with --split-output-size");
+                } else if cmd_args.compress_output != OutputCompression::None {
+                    warn!("--downsweep rewrites uncompressed kraken output, skipped");
+                } else if cmd_args.input_files.iter().any(is_stdin_path) {
+                    warn!("--downsweep reads the input files again, skipped for standard input");
+                } else if cmd_args.output_dir.is_some() {
+                    let downsweep_args = downsweep::Args::from(cmd_args.clone());
+                    downsweep::run(downsweep_args)?;
+                } else {
+                    warn!("--downsweep requires --output-dir, skipped");
+                }
+            }
 
             let duration = start.elapsed();
-            println!("Classify took: {:?}", duration);
+            info!("Classify took: {:?}", duration);
         }
         Commands::Direct(cmd_args) => {
             direct::run(cmd_args)?;
         }
+        Commands::Watch(cmd_args) => {
+            watch::run(cmd_args)?;
+        }
+        Commands::ClassifySeq(cmd_args) => {
+            classify_seq::run(cmd_args)?;
+        }
+        Commands::Deplete(cmd_args) => {
+            deplete::run(cmd_args)?;
+        }
+        Commands::Downsweep(cmd_args) => {
+            downsweep::run(cmd_args)?;
+        }
+        Commands::ExtractReads(cmd_args) => {
+            extract_reads::run(cmd_args)?;
+        }
+        Commands::ClusterUnclassified(cmd_args) => {
+            cluster_unclassified::run(cmd_args)?;
+        }
+        Commands::Genome(cmd_args) => {
+            genome::run(cmd_args)?;
+        }
+        Commands::Abundance(cmd_args) => {
+            abundance::run(cmd_args)?;
+        }
+        Commands::MergeReports(cmd_args) => {
+            merge_reports::run(cmd_args)?;
+        }
+        #[cfg(feature = "project")]
+        Commands::Project(cmd_args) => {
+            project::run(cmd_args)?;
+        }
+        Commands::TaxonAccessions(cmd_args) => {
+            taxon_accessions::run(cmd_args)?;
+        }
+        Commands::LookupReads(cmd_args) => {
+            lookup_reads::run(cmd_args)?;
+        }
+        Commands::Benchmark(cmd_args) => {
+            benchmark::run(cmd_args)?;
+        }
+        Commands::Simulate(cmd_args) => {
+            simulate::run(cmd_args)?;
+        }
+        Commands::Evaluate(cmd_args) => {
+            evaluate::run(cmd_args)?;
+        }
+        Commands::Db(cmd_args) => {
+            registry::run(cmd_args)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,96 @@
+use clap::Parser;
+use kraken2_rs::read_index::{read_index_path, IndexedOutput};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::PathBuf;
+use tracing::warn;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Print the classification of given reads from bgzf per-read output",
+    long_about = "Look up reads by id in per-read output written with --compress-output bgzf, using the read id index (.ridx) next to each file, without decompressing the whole output. Pass all parts of an output split with --split-output-size to search them all"
+)]
+pub struct Args {
+    /// Per-read output files (output_*.txt.gz or output_*.ndjson.gz) with a .ridx index
+    #[clap(value_parser, required = true)]
+    pub outputs: Vec<PathBuf>,
+
+    /// Read id to look up, can be given several times
+    #[clap(short = 'r', long = "read-id", value_parser)]
+    pub read_ids: Vec<String>,
+
+    /// File with one read id per line
+    #[clap(long, value_parser)]
+    pub read_ids_file: Option<PathBuf>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut read_ids = args.read_ids.clone();
+    if let Some(file) = &args.read_ids_file {
+        for line in BufReader::new(File::open(file)?).lines() {
+            let line = line?;
+            let id = line.trim();
+            if !id.is_empty() {
+                read_ids.push(id.to_string());
+            }
+        }
+    }
+    if read_ids.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "no read ids given, use --read-id or --read-ids-file",
+        ));
+    }
+
+    let mut outputs = Vec::new();
+    for path in &args.outputs {
+        let output = IndexedOutput::open(path).map_err(|e| {
+            if e.kind() == ErrorKind::NotFound {
+                Error::new(
+                    e.kind(),
+                    format!(
+                        "{:?} or its index {:?} not found, the index is written with --compress-output bgzf",
+                        path,
+                        read_index_path(path)
+                    ),
+                )
+            } else {
+                e
+            }
+        })?;
+        outputs.push(output);
+    }
+
+    let mut writer = BufWriter::new(io::stdout().lock());
+    let mut missing = 0;
+    for read_id in &read_ids {
+        let mut found = false;
+        for output in outputs.iter_mut() {
+            if let Some(line) = output.get(read_id)? {
+                writeln!(writer, "{}", line)?;
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            warn!("read {} not found", read_id);
+            missing += 1;
+        }
+    }
+    writer.flush()?;
+    if missing > 0 {
+        warn!("{} of {} reads not found", missing, read_ids.len());
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
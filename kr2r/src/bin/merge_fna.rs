@@ -1,16 +1,18 @@
 use clap::Parser;
 use flate2::read::GzDecoder;
 use kraken2_rs::args::parse_size;
-use kraken2_rs::db::generate_taxonomy;
+use kraken2_rs::db::{generate_taxonomy, write_taxonomy};
+use kraken2_rs::taxonomy::NCBITaxonomy;
 use kraken2_rs::utils::{find_files, open_file, read_id_to_taxon_map};
 use rayon::prelude::*;
 use std::collections::HashMap;
 use std::fs::{create_dir_all, File, OpenOptions};
 use std::io::{BufRead, BufReader, BufWriter, Read, Result, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
+use tracing::{info, warn};
 
 #[derive(Parser, Debug, Clone)]
 #[clap(version, about = "A tool for processing genomic files")]
@@ -26,6 +28,14 @@ pub struct Args {
     /// library fna temp file max size
     #[arg(long = "max-file-size", value_parser = parse_size, default_value = "2G")]
     pub max_file_size: usize,
+
+    /// Merge the <assembly>_protein.faa.gz files of the downloaded assemblies instead of the genomic .fna.gz files
+    #[clap(long, value_parser, default_value_t = false)]
+    pub protein: bool,
+
+    /// GTDB taxonomy or metadata files (e.g. bac120_taxonomy.tsv ar53_taxonomy.tsv); assemblies are labelled with their GTDB species and the taxonomy is built from GTDB instead of nodes.dmp/names.dmp
+    #[arg(long, num_args = 1..)]
+    pub gtdb_taxonomy: Vec<PathBuf>,
 }
 
 struct SizedWriter {
@@ -38,14 +48,10 @@ struct SizedWriter {
 }
 
 impl SizedWriter {
-    fn new(library_dir: &PathBuf, thread_index: usize, max_file_size: u64) -> Result<Self> {
+    fn new(library_dir: &Path, thread_index: usize, max_file_size: u64) -> Result<Self> {
         let file_suffix = AtomicUsize::new(0);
         let path = Self::get_file_path(library_dir, thread_index, 0);
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .write(true)
-            .open(&path)?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
         let writer = BufWriter::new(file);
 
         Ok(Self {
@@ -58,7 +64,7 @@ impl SizedWriter {
         })
     }
 
-    fn get_file_path(library_dir: &PathBuf, thread_index: usize, suffix: usize) -> PathBuf {
+    fn get_file_path(library_dir: &Path, thread_index: usize, suffix: usize) -> PathBuf {
         library_dir.join(format!("library_{}_{}.fna", thread_index, suffix))
     }
 
@@ -76,7 +82,6 @@ impl SizedWriter {
             let new_file = OpenOptions::new()
                 .create(true)
                 .append(true)
-                .write(true)
                 .open(&new_path)?;
             self.writer = BufWriter::new(new_file);
             self.bytes_written = 0;
@@ -92,9 +97,17 @@ impl SizedWriter {
     }
 }
 
-fn parse_assembly_fna(assembly_file: &PathBuf, site: &str) -> Result<Vec<(String, String)>> {
+/// `suffix` 为 assembly 目录中序列文件名的后缀, 例如 `_genomic.fna.gz`.
+/// 指定 `gtdb_accessions` 时使用 GTDB 的 taxid, 不在 GTDB 中的 assembly 被跳过
+fn parse_assembly_fna(
+    assembly_file: &PathBuf,
+    site: &str,
+    suffix: &str,
+    gtdb_accessions: Option<&HashMap<String, u64>>,
+) -> Result<Vec<(String, String)>> {
     let mut gz_files = Vec::new();
-    let file = open_file(&assembly_file)?;
+    let mut skipped = 0;
+    let file = open_file(assembly_file)?;
     let reader = BufReader::new(file);
     let lines = reader.lines();
 
@@ -114,6 +127,16 @@ fn parse_assembly_fna(assembly_file: &PathBuf, site: &str) -> Result<Vec<(String
             if ftp_path == "na" {
                 continue;
             }
+            let taxid = match gtdb_accessions {
+                Some(accessions) => match accessions.get(fields[0]) {
+                    Some(taxid) => taxid.to_string(),
+                    None => {
+                        skipped += 1;
+                        continue;
+                    }
+                },
+                None => taxid.to_string(),
+            };
 
             // let levels = vec!["Complete Genome", "Chromosome"];
             // if !levels.contains(&asm_level) {
@@ -121,14 +144,21 @@ fn parse_assembly_fna(assembly_file: &PathBuf, site: &str) -> Result<Vec<(String
             // }
 
             let fna_file_name = format!(
-                "{}/{}/{}_genomic.fna.gz",
+                "{}/{}/{}{}",
                 parent_path.to_string_lossy(),
                 site,
-                ftp_path.split('/').last().unwrap_or_default()
+                ftp_path.split('/').next_back().unwrap_or_default(),
+                suffix
             );
-            gz_files.push((fna_file_name, taxid.into()));
+            gz_files.push((fna_file_name, taxid));
         }
     }
+    if skipped > 0 {
+        info!(
+            "{:?}: {} assemblies not in the GTDB taxonomy are skipped",
+            assembly_file, skipped
+        );
+    }
     Ok(gz_files)
 }
 
@@ -191,14 +221,16 @@ fn process_gz_file(
     Ok(())
 }
 
-const PREFIX: &'static str = "assembly_summary";
-const SUFFIX: &'static str = "txt";
+const PREFIX: &str = "assembly_summary";
+const SUFFIX: &str = "txt";
 
 fn merge_fna_parallel(
     assembly_files: &Vec<PathBuf>,
     database: &PathBuf,
-    library_dir: &PathBuf,
+    library_dir: &Path,
     max_file_size: u64,
+    suffix: &str,
+    gtdb_accessions: Option<&HashMap<String, u64>>,
 ) -> Result<()> {
     let pattern = format!(r"{}_(\S+)\.{}", PREFIX, SUFFIX);
     let file_site = regex::Regex::new(&pattern).unwrap();
@@ -210,7 +242,8 @@ fn merge_fna_parallel(
     for assembly_file in assembly_files {
         if let Some(caps) = file_site.captures(assembly_file.to_string_lossy().as_ref()) {
             if let Some(matched) = caps.get(1) {
-                let gz_files = parse_assembly_fna(assembly_file, matched.as_str())?;
+                let gz_files =
+                    parse_assembly_fna(assembly_file, matched.as_str(), suffix, gtdb_accessions)?;
 
                 gz_files.par_iter().for_each(|(gz_path, taxid)| {
                     let gz_file = PathBuf::from(&gz_path);
@@ -221,28 +254,23 @@ fn merge_fna_parallel(
 
                     let thread_index = rayon::current_thread_index().unwrap_or(0);
                     let mut writers = writers.lock().unwrap();
-                    let mut fna_writer = writers.entry(thread_index).or_insert_with(|| {
-                        SizedWriter::new(&library_dir, thread_index, max_file_size).unwrap()
+                    let fna_writer = writers.entry(thread_index).or_insert_with(|| {
+                        SizedWriter::new(library_dir, thread_index, max_file_size).unwrap()
                     });
                     let seqid2taxid_path =
                         database.join(format!("seqid2taxid_{}.map", thread_index));
                     let mut map_writer = BufWriter::new(
                         OpenOptions::new()
                             .create(true)
-                            .write(true)
                             .append(true)
                             .open(&seqid2taxid_path)
                             .unwrap(),
                     );
 
-                    if let Err(e) = process_gz_file(
-                        &gz_file,
-                        &mut map_writer,
-                        &mut fna_writer,
-                        &fna_start,
-                        &taxid,
-                    ) {
-                        eprintln!("process_gz_file error: {}", e);
+                    if let Err(e) =
+                        process_gz_file(&gz_file, &mut map_writer, fna_writer, &fna_start, taxid)
+                    {
+                        warn!("process_gz_file error: {}", e);
                     } else {
                         fna_writer.flush().unwrap();
                         map_writer.flush().unwrap();
@@ -297,35 +325,46 @@ fn merge_files(paths: &Vec<PathBuf>, output_path: &PathBuf) -> Result<()> {
 pub fn run(args: Args) -> Result<()> {
     // 开始计时
     let start = Instant::now();
-    println!("merge fna start...");
+    info!("merge fna start...");
     let download_dir = args.download_dir;
     let database = &args.database;
     let max_file_size = &args.max_file_size;
 
-    let dst_tax_dir = database.join("taxonomy");
-    create_dir_all(&dst_tax_dir)?;
-
     let library_dir = database.join("library");
     create_dir_all(&library_dir)?;
 
-    let source_names_file = &download_dir.join("taxonomy").join("names.dmp");
-    assert!(source_names_file.exists());
-    let dst_name_file = &dst_tax_dir.join("names.dmp");
-    if !dst_name_file.exists() {
-        std::fs::copy(source_names_file, dst_name_file)?;
-    }
+    // 使用 GTDB 的分类树时不需要 NCBI taxonomy 文件
+    let gtdb = if args.gtdb_taxonomy.is_empty() {
+        None
+    } else {
+        let (gtdb, accessions) = NCBITaxonomy::from_gtdb(&args.gtdb_taxonomy)?;
+        info!("GTDB taxonomy: {} genomes", accessions.len());
+        Some((gtdb, accessions))
+    };
+
+    if gtdb.is_none() {
+        let dst_tax_dir = database.join("taxonomy");
+        create_dir_all(&dst_tax_dir)?;
+
+        let source_names_file = &download_dir.join("taxonomy").join("names.dmp");
+        assert!(source_names_file.exists());
+        let dst_name_file = &dst_tax_dir.join("names.dmp");
+        if !dst_name_file.exists() {
+            std::fs::copy(source_names_file, dst_name_file)?;
+        }
 
-    let source_nodes_file = &download_dir.join("taxonomy").join("nodes.dmp");
-    assert!(source_nodes_file.exists());
-    let dst_nodes_file = &dst_tax_dir.join("nodes.dmp");
-    if !dst_nodes_file.exists() {
-        std::fs::copy(source_nodes_file, dst_nodes_file)?;
+        let source_nodes_file = &download_dir.join("taxonomy").join("nodes.dmp");
+        assert!(source_nodes_file.exists());
+        let dst_nodes_file = &dst_tax_dir.join("nodes.dmp");
+        if !dst_nodes_file.exists() {
+            std::fs::copy(source_nodes_file, dst_nodes_file)?;
+        }
     }
 
     let library_fna_path = database.join("library.fna");
     let seqid2taxid_path = database.join("seqid2taxid.map");
     if library_fna_path.exists() && seqid2taxid_path.exists() {
-        println!("library.fna and seqid2taxid.map exists!");
+        info!("library.fna and seqid2taxid.map exists!");
         return Ok(());
     }
 
@@ -335,13 +374,19 @@ pub fn run(args: Args) -> Result<()> {
     if seqid2taxid_path.exists() {
         std::fs::remove_file(seqid2taxid_path)?;
     }
-    let assembly_files = find_files(&download_dir, &PREFIX, &SUFFIX);
+    let assembly_files = find_files(&download_dir, PREFIX, SUFFIX);
 
     merge_fna_parallel(
         &assembly_files,
         &args.database,
         &library_dir,
         *max_file_size as u64,
+        if args.protein {
+            "_protein.faa.gz"
+        } else {
+            "_genomic.fna.gz"
+        },
+        gtdb.as_ref().map(|(_, accessions)| accessions),
     )?;
 
     let id_to_taxon_map_filename = args.database.join("seqid2taxid.map");
@@ -349,23 +394,31 @@ pub fn run(args: Args) -> Result<()> {
     let k2d_dir = &args.database;
     let taxonomy_filename = k2d_dir.join("taxo.k2d");
 
-    let ncbi_taxonomy_directory = &args.database.join("taxonomy");
-
-    let _ = generate_taxonomy(
-        &ncbi_taxonomy_directory,
-        &taxonomy_filename,
-        &id_to_taxon_map,
-    )?;
+    match gtdb {
+        Some((gtdb, _)) => {
+            write_taxonomy(gtdb, &taxonomy_filename, &id_to_taxon_map)?;
+        }
+        None => {
+            let ncbi_taxonomy_directory = &args.database.join("taxonomy");
+            generate_taxonomy(
+                ncbi_taxonomy_directory,
+                &taxonomy_filename,
+                &id_to_taxon_map,
+            )?;
+        }
+    }
     // 计算持续时间
     let duration = start.elapsed();
-    println!("merge fna took: {:?}", duration);
+    info!("merge fna took: {:?}", duration);
     Ok(())
 }
 
 #[allow(dead_code)]
 fn main() {
+    kraken2_rs::logging::init_default_logging();
     let args = Args::parse();
     if let Err(e) = run(args) {
         eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
     }
 }
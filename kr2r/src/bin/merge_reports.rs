@@ -0,0 +1,109 @@
+use clap::Parser;
+use kraken2_rs::abundance::read_kreport;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::report::MergedReports;
+use kraken2_rs::stats::{check_unclassified_taxid, set_unclassified};
+use kraken2_rs::taxonomy::Taxonomy;
+use std::fs::File;
+use std::io::{self, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Merge several kreport2 files into one table of taxa by samples (like combine_kreports.py)"
+)]
+pub struct Args {
+    /// kreport2 files produced by classify or direct.
+    #[clap(value_parser, required = true, num_args = 1..)]
+    pub reports: Vec<PathBuf>,
+
+    /// database whose taxonomy is used to align taxids, names and the row order, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database)]
+    pub database: Option<PathBuf>,
+
+    /// Sample names, comma separated, one per report. Defaults to the report file names without extension.
+    #[clap(long, value_parser, value_delimiter = ',')]
+    pub names: Vec<String>,
+
+    /// Only keep taxa with this kreport rank code (e.g. S, G).
+    #[clap(short = 'r', long, value_parser)]
+    pub rank: Option<String>,
+
+    /// Taxid of the unclassified reads in the reports, when they were written with --unclassified-taxid
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub unclassified_taxid: u64,
+
+    /// Output table, defaults to the standard output.
+    #[clap(short = 'o', long, value_parser)]
+    pub output: Option<PathBuf>,
+}
+
+fn sample_name(report: &Path) -> String {
+    report
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| report.display().to_string())
+}
+
+pub fn run(args: Args) -> Result<()> {
+    set_unclassified(args.unclassified_taxid, "unclassified")?;
+    if !args.names.is_empty() && args.names.len() != args.reports.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "--names has {} names but {} reports were given",
+                args.names.len(),
+                args.reports.len()
+            ),
+        ));
+    }
+
+    let mut reports = Vec::with_capacity(args.reports.len());
+    for (i, report) in args.reports.iter().enumerate() {
+        let name = args
+            .names
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| sample_name(report));
+        reports.push((name, read_kreport(report)?));
+    }
+    let mut merged = MergedReports::merge(reports);
+
+    if let Some(database) = &args.database {
+        let taxo = Taxonomy::from_file(database.join("taxo.k2d"))?;
+        check_unclassified_taxid(&taxo)?;
+        let unknown = merged.align_taxonomy(&taxo);
+        if !unknown.is_empty() {
+            let ids: Vec<String> = unknown.iter().map(|id| id.to_string()).collect();
+            warn!(
+                "{} taxids are not in the taxonomy of {:?}: {}",
+                unknown.len(),
+                database,
+                ids.join(", ")
+            );
+        }
+    }
+
+    let rank = args.rank.as_deref();
+    match &args.output {
+        Some(output) => {
+            let mut writer = BufWriter::new(File::create(output)?);
+            merged.write_tsv(&mut writer, rank)?;
+            writer.flush()?;
+        }
+        None => merged.write_tsv(&mut io::stdout().lock(), rank)?,
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
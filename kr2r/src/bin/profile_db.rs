@@ -0,0 +1,191 @@
+use clap::Parser;
+use kraken2_rs::args::{parse_database, parse_size};
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::compact_hash::{read_next_page, HashConfig, Page, ShardChecksums};
+use kraken2_rs::taxonomy::{Taxonomy, TaxonomyNode};
+use kraken2_rs::utils::{format_bytes, resident_memory};
+use std::io::Result;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Load the database shards one by one and report their resident sizes and load times",
+    long_about = "Load the database shards one by one and report their resident sizes and load times, used to choose --hash-capacity for hashshard"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Memory available on the target node (e.g. '64G'), prints a matching --hash-capacity for hashshard
+    #[clap(long, value_parser = parse_size)]
+    pub max_memory: Option<usize>,
+}
+
+/// 一次加载的测量结果
+struct LoadProfile {
+    name: String,
+    file_size: u64,
+    /// 加载后分配的字节数
+    allocated: usize,
+    /// 加载前后 RSS 的差值, 无法读取 RSS 时为 None
+    resident: Option<usize>,
+    load_time: Duration,
+}
+
+impl LoadProfile {
+    /// 衡量内存时优先使用实测的 RSS
+    fn memory(&self) -> usize {
+        self.resident.unwrap_or(self.allocated)
+    }
+
+    fn print(&self) {
+        println!(
+            "{}\t{}\t{}\t{}\t{:?}",
+            self.name,
+            format_bytes(self.file_size as f64),
+            format_bytes(self.allocated as f64),
+            self.resident
+                .map(|size| format_bytes(size as f64))
+                .unwrap_or_else(|| "NA".to_string()),
+            self.load_time
+        );
+    }
+}
+
+fn resident_delta(before: Option<usize>) -> Option<usize> {
+    Some(resident_memory()?.saturating_sub(before?))
+}
+
+fn file_size(path: &PathBuf) -> Result<u64> {
+    Ok(std::fs::metadata(path)?.len())
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let hash_files = ChunkCatalog::scan_continuous(&args.database, ChunkKind::Hash)?.paths();
+    let config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    let checksums = ShardChecksums::from_hash_header(args.database.join("hash_config.k2d"))?;
+    info!("profile-db start...");
+    debug!("{:?}", config);
+
+    let baseline = resident_memory();
+    println!("file\tfile_size\tallocated\tresident\tload_time");
+
+    let taxo_file = args.database.join("taxo.k2d");
+    let start = Instant::now();
+    let taxonomy = Taxonomy::from_file(&taxo_file)?;
+    let taxo_profile = LoadProfile {
+        name: "taxo.k2d".to_string(),
+        file_size: file_size(&taxo_file)?,
+        allocated: taxonomy.nodes.len() * std::mem::size_of::<TaxonomyNode>()
+            + taxonomy.name_data.len()
+            + taxonomy.rank_data.len(),
+        resident: resident_delta(baseline),
+        load_time: start.elapsed(),
+    };
+    taxo_profile.print();
+
+    // 与 annotate 相同, 每次只保留一个 shard (加上下一个 shard 的开头)
+    let mut shards = Vec::new();
+    for (page_index, hash_file) in hash_files.iter().enumerate() {
+        let before = resident_memory();
+        let start = Instant::now();
+        let mut page = Page::default();
+        read_next_page(
+            &mut page,
+            &hash_files,
+            page_index,
+            config,
+            checksums.as_ref(),
+        )?;
+        let profile = LoadProfile {
+            name: hash_file
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            file_size: file_size(hash_file)?,
+            allocated: page.data.capacity() * std::mem::size_of::<u32>(),
+            resident: resident_delta(before),
+            load_time: start.elapsed(),
+        };
+        drop(page);
+        profile.print();
+        shards.push(profile);
+    }
+
+    let Some(largest) = shards.iter().max_by_key(|shard| shard.memory()) else {
+        println!("no hash_*.k2d files found in {:?}", args.database);
+        return Ok(());
+    };
+    let total_load: Duration = shards.iter().map(|shard| shard.load_time).sum();
+    let all_shards: usize = shards.iter().map(|shard| shard.memory()).sum();
+    let base = baseline.unwrap_or(0) + taxo_profile.memory();
+    let chunk_peak = base + largest.memory();
+    let direct_peak = base + all_shards;
+
+    println!("summary:");
+    println!(
+        "  shards: {}, total load time {:?}",
+        shards.len(),
+        total_load
+    );
+    println!(
+        "  largest shard: {} ({})",
+        largest.name,
+        format_bytes(largest.memory() as f64)
+    );
+    println!(
+        "  classify/annotate (one shard at a time): {}",
+        format_bytes(chunk_peak as f64)
+    );
+    println!(
+        "  direct (all shards): {}",
+        format_bytes(direct_peak as f64)
+    );
+    if baseline.is_none() {
+        println!("  resident memory is not available on this system, sizes are allocations");
+    }
+
+    if let Some(max_memory) = args.max_memory {
+        println!("for a node with {}:", format_bytes(max_memory as f64));
+        if direct_peak <= max_memory {
+            println!("  direct fits in memory");
+        } else {
+            println!("  direct does not fit, use classify");
+        }
+        if max_memory <= base {
+            println!(
+                "  not enough memory for the taxonomy and process overhead ({})",
+                format_bytes(base as f64)
+            );
+            return Ok(());
+        }
+        // 每个 hash 单元为 4 字节
+        let capacity = (max_memory - base) / std::mem::size_of::<u32>();
+        let capacity_m = capacity >> 20;
+        if capacity_m == 0 {
+            println!("  not enough memory for a 1M hash capacity");
+            return Ok(());
+        }
+        println!(
+            "  hashshard --hash-capacity {}M (shards: {}), leave headroom for read batches (--batch-size, -p)",
+            capacity_m,
+            config.capacity.div_ceil(capacity_m << 20)
+        );
+    }
+
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
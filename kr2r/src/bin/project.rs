@@ -0,0 +1,145 @@
+use clap::{Parser, Subcommand};
+use kraken2_rs::project::{Project, TaxonQuery};
+use kraken2_rs::report::format_utc;
+use std::collections::BTreeMap;
+use std::io::{self, Result};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Collect the results of many runs into a project and query taxon trends",
+    long_about = "Collect run_summary.json and the kreports of many runs into one SQLite project store (tables samples, sample_metadata and taxa) and answer longitudinal queries, e.g. the abundance of a taxon over time per patient or site"
+)]
+pub struct Args {
+    /// SQLite project store, e.g. surveillance.sqlite, created by the first `project add`
+    #[clap(long, value_parser)]
+    pub project: PathBuf,
+
+    #[command(subcommand)]
+    pub cmd: ProjectCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ProjectCommand {
+    /// Add the samples of an output directory of `direct` or `classify`
+    Add {
+        /// Output directory with run_summary.json and output_*.kreport2
+        output_dir: PathBuf,
+        /// Name of the run (default: the name of the output directory)
+        #[clap(long)]
+        run: Option<String>,
+        /// Date of the run, e.g. 2024-03-01 (default: today); a collection_date or date
+        /// column of the sample sheet takes precedence for each sample
+        #[clap(long)]
+        date: Option<String>,
+        /// Replace a run that is already in the project
+        #[clap(long, value_parser, default_value_t = false)]
+        replace: bool,
+    },
+    /// List the runs in the project
+    Runs,
+    /// Remove a run from the project
+    Remove { run: String },
+    /// Print the reads and percentage of a taxon in every sample, ordered by date
+    Trend {
+        /// Taxid or scientific name
+        taxon: TaxonQuery,
+        /// Group the samples by this sample sheet column, e.g. patient_id or site
+        #[clap(long)]
+        by: Option<String>,
+    },
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut project = Project::open(&args.project)?;
+    match args.cmd {
+        ProjectCommand::Add {
+            output_dir,
+            run,
+            date,
+            replace,
+        } => {
+            let run = match run.or_else(|| {
+                output_dir
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            }) {
+                Some(run) => run,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("can't name the run of {:?}, use --run", output_dir),
+                    ))
+                }
+            };
+            let date = date.unwrap_or_else(|| {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs());
+                format_utc(now)[..10].to_string()
+            });
+            let samples = project.add_run(&output_dir, &run, &date, replace)?;
+            println!("add {}: {} samples", run, samples);
+        }
+        ProjectCommand::Runs => {
+            // 批次 -> (样本数, 最早与最晚的日期)
+            let mut runs: BTreeMap<String, (usize, String, String)> = BTreeMap::new();
+            for sample in project.samples()? {
+                let entry = runs
+                    .entry(sample.run)
+                    .or_insert_with(|| (0, sample.date.clone(), sample.date.clone()));
+                entry.0 += 1;
+                if sample.date < entry.1 {
+                    entry.1 = sample.date.clone();
+                }
+                if sample.date > entry.2 {
+                    entry.2 = sample.date;
+                }
+            }
+            println!("run\tsamples\tfirst_date\tlast_date");
+            for (run, (samples, first, last)) in runs {
+                println!("{}\t{}\t{}\t{}", run, samples, first, last);
+            }
+        }
+        ProjectCommand::Remove { run } => {
+            let removed = project.remove_run(&run)?;
+            if removed == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("run '{}' is not in the project", run),
+                ));
+            }
+            println!("remove {}: {} samples", run, removed);
+        }
+        ProjectCommand::Trend { taxon, by } => {
+            let points = project.trend(&taxon, by.as_deref())?;
+            let group = by.as_deref().unwrap_or("group");
+            println!("{}\tdate\trun\tsample\treads\tclade_reads\tpercent", group);
+            for point in points {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{:.4}",
+                    point.group,
+                    point.date,
+                    point.run,
+                    point.sample,
+                    point.reads,
+                    point.clade_reads,
+                    point.percent
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
@@ -0,0 +1,251 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::checksums::write_checksums;
+use kraken2_rs::compact_hash::{read_page_from_file, shard_checksum, HashConfig, ShardChecksums};
+use kraken2_rs::taxonomy::Taxonomy;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Write a copy of a database with taxa removed, or restricted to a whitelist of taxa",
+    long_about = "Write a copy of a database into --output-dir without the minimizers of the given taxids and their subtrees (--remove), or with only the minimizers of the given taxids and their subtrees (--keep). The hash_*.k2d shards are streamed one at a time and keep their capacity, so no rebuild is needed"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Taxids to remove together with their subtrees (comma separated or repeated)
+    #[clap(
+        long,
+        value_delimiter = ',',
+        required_unless_present = "keep",
+        conflicts_with = "keep"
+    )]
+    pub remove: Vec<u64>,
+
+    /// Taxids to keep together with their subtrees, every other minimizer is removed (comma separated or repeated)
+    #[clap(long, value_delimiter = ',')]
+    pub keep: Vec<u64>,
+
+    /// Directory of the pruned database, the other database files are copied into it
+    #[clap(long, required = true)]
+    pub output_dir: PathBuf,
+}
+
+/// 按内部 taxid 标记要删除的节点. 父节点的内部 id 总是小于子节点, 按顺序一次遍历即可标记整棵子树
+fn removed_taxa(taxonomy: &Taxonomy, remove: &[u64], keep: &[u64]) -> Result<Vec<bool>> {
+    let taxids = if keep.is_empty() { remove } else { keep };
+    let mut selected = vec![false; taxonomy.node_count()];
+    for &taxid in taxids {
+        let internal_id = taxonomy.get_internal_id(taxid);
+        if internal_id == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("taxid {} is not in the database taxonomy", taxid),
+            ));
+        }
+        selected[internal_id as usize] = true;
+    }
+    for internal_id in 2..selected.len() {
+        let parent = taxonomy.nodes[internal_id].parent_id as usize;
+        if parent < internal_id && selected[parent] {
+            selected[internal_id] = true;
+        }
+    }
+    if keep.is_empty() {
+        Ok(selected)
+    } else {
+        // 白名单之外的节点都删除, 包括白名单的祖先 (与其他 taxon 共有的 minimizer)
+        let mut removed: Vec<bool> = selected.iter().map(|&kept| !kept).collect();
+        removed[0] = false;
+        Ok(removed)
+    }
+}
+
+/// 删除 `data` 中 taxid 被标记的单元, `next_cell` 为下一个 shard 处理后的第一个单元.
+///
+/// 查询沿线性探测向后查找, 遇到空单元即停止, 而单元中不记录探测的起点, 不能直接清空.
+/// 被删除的单元改为同一段连续单元中其后第一个保留的单元 (可能在下一个 shard 开头),
+/// 经过它的查询与原来得到相同的结果; 其后没有保留的单元时没有查询依赖它, 直接清空
+fn prune_page(data: &mut [u32], removed: &[bool], value_mask: usize, next_cell: u32) {
+    let mut fill = next_cell;
+    for cell in data.iter_mut().rev() {
+        if *cell == 0 {
+            fill = 0;
+            continue;
+        }
+        let taxid = *cell as usize & value_mask;
+        if removed.get(taxid).copied().unwrap_or(false) {
+            *cell = fill;
+        } else {
+            fill = *cell;
+        }
+    }
+}
+
+fn write_page(file_path: &Path, page_index: usize, data: &[u32]) -> Result<usize> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    writer.write_all(&(page_index as u64).to_le_bytes())?;
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    let bytes = unsafe {
+        std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data))
+    };
+    writer.write_all(bytes)?;
+    writer.flush()?;
+    Ok(data.iter().filter(|&&value| value != 0).count())
+}
+
+/// 复制 hash 文件以外的数据库文件
+fn copy_database_files(database: &Path, output_dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(database)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !entry.file_type()?.is_file() || (name.starts_with("hash") && name.ends_with(".k2d")) {
+            continue;
+        }
+        fs::copy(entry.path(), output_dir.join(&*name))?;
+    }
+    Ok(())
+}
+
+/// 去掉 seqid2taxid.map 中映射到被删除 taxon 的序列, 返回去掉的行数
+fn prune_id_to_taxon_map(
+    source: &Path,
+    target: &Path,
+    taxonomy: &Taxonomy,
+    removed: &[bool],
+) -> Result<usize> {
+    let reader = BufReader::new(File::open(source)?);
+    let mut writer = BufWriter::new(File::create(target)?);
+    let mut dropped = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let taxid = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|taxid| taxid.parse::<u64>().ok())
+            .map(|taxid| taxonomy.get_internal_id(taxid) as usize);
+        if taxid.is_some_and(|taxid| taxid != 0 && removed[taxid]) {
+            dropped += 1;
+            continue;
+        }
+        writeln!(writer, "{}", line)?;
+    }
+    writer.flush()?;
+    Ok(dropped)
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    let files = ChunkCatalog::scan_continuous(&args.database, ChunkKind::Hash)?.paths();
+    if files.len() != config.partition {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "found {} hash files, hash_config.k2d expects {}",
+                files.len(),
+                config.partition
+            ),
+        ));
+    }
+    let taxonomy = Taxonomy::from_file(args.database.join("taxo.k2d"))?;
+    let removed = removed_taxa(&taxonomy, &args.remove, &args.keep)?;
+
+    create_dir_all(&args.output_dir)?;
+    let config_file = args.output_dir.join("hash_config.k2d");
+    if config_file.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", config_file),
+        ));
+    }
+
+    info!(
+        "prune start, {} of {} taxa are removed",
+        removed.iter().filter(|&&r| r).count(),
+        taxonomy.node_count() - 1
+    );
+    let start = Instant::now();
+
+    // 从最后一个 shard 开始处理, 前一个 shard 末尾的单元需要下一个 shard 处理后的开头
+    let mut size = 0;
+    let mut checksums = vec![0; config.partition];
+    let mut next_cell = 0;
+    for (i, file) in files.iter().enumerate().rev() {
+        let page_index = i + 1;
+        let mut page = read_page_from_file(file)?;
+        let expected = std::cmp::min(
+            config.hash_capacity,
+            config.capacity - i * config.hash_capacity,
+        );
+        if page.size != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{:?} holds {} cells, hash_config.k2d expects {}",
+                    file, page.size, expected
+                ),
+            ));
+        }
+        prune_page(&mut page.data, &removed, config.value_mask, next_cell);
+        next_cell = page.data.first().copied().unwrap_or(0);
+
+        let page_file = args.output_dir.join(format!("hash_{}.k2d", page_index));
+        size += write_page(&page_file, page_index, &page.data)?;
+        checksums[i] = shard_checksum(page_index, &page.data);
+        info!(
+            "write {:?}/{}: duration: {:?}",
+            page_index,
+            config.partition,
+            start.elapsed()
+        );
+    }
+
+    let new_config = HashConfig::new(
+        config.version,
+        config.capacity,
+        config.value_bits,
+        size,
+        config.partition,
+        config.hash_capacity,
+    );
+    copy_database_files(&args.database, &args.output_dir)?;
+    let map_file = args.database.join("seqid2taxid.map");
+    if map_file.exists() {
+        let dropped = prune_id_to_taxon_map(
+            &map_file,
+            &args.output_dir.join("seqid2taxid.map"),
+            &taxonomy,
+            &removed,
+        )?;
+        info!("{} sequence ids removed from seqid2taxid.map", dropped);
+    }
+    new_config.write_to_file(config_file, Some(&ShardChecksums(checksums)))?;
+    write_checksums(&args.output_dir)?;
+
+    info!(
+        "{} -> {} cells, prune took: {:?}",
+        config.size,
+        size,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
@@ -0,0 +1,128 @@
+use clap::{Parser, Subcommand};
+use kraken2_rs::registry::{DatabaseEntry, Registry};
+use std::io::{self, Result};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "List, add or remove databases registered by name",
+    long_about = "Manage the per-user database registry (~/.config/kun_peng/databases.toml), so --db accepts a registered name instead of a database path"
+)]
+pub struct Args {
+    /// Registry file, default: ~/.config/kun_peng/databases.toml
+    #[clap(long, value_parser)]
+    pub registry: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub cmd: DbCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum DbCommand {
+    /// List the registered databases
+    List,
+    /// Register a database directory under a name
+    Add {
+        /// Name to use with --db, e.g. standard-2024
+        name: String,
+        /// Database directory (with hash_config.k2d, opts.k2d, taxo.k2d and the hash files)
+        path: PathBuf,
+        /// Free-form description shown by `db list`
+        #[clap(short, long)]
+        description: Option<String>,
+        /// Replace an existing entry with the same name
+        #[clap(short, long, value_parser, default_value_t = false)]
+        force: bool,
+    },
+    /// Remove a registered name (the database files are kept)
+    Remove { name: String },
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let registry_file = match args.registry.or_else(Registry::default_path) {
+        Some(path) => path,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "HOME is not set, use --registry",
+            ))
+        }
+    };
+    let mut registry = Registry::load(&registry_file)?;
+
+    match args.cmd {
+        DbCommand::List => {
+            if registry.databases.is_empty() {
+                println!("no databases registered in {:?}", registry_file);
+                return Ok(());
+            }
+            println!("name\tpath\tk\tl\tshards\tminimizers\tdescription");
+            for (name, entry) in &registry.databases {
+                let missing = if entry.path.is_dir() {
+                    ""
+                } else {
+                    " (missing)"
+                };
+                println!(
+                    "{}\t{}{}\t{}\t{}\t{}\t{}\t{}",
+                    name,
+                    entry.path.display(),
+                    missing,
+                    entry.k,
+                    entry.l,
+                    entry.shards,
+                    entry.minimizers,
+                    entry.description.as_deref().unwrap_or("")
+                );
+            }
+        }
+        DbCommand::Add {
+            name,
+            path,
+            description,
+            force,
+        } => {
+            if name.is_empty() || name.contains(std::path::MAIN_SEPARATOR) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid database name '{}'", name),
+                ));
+            }
+            if registry.databases.contains_key(&name) && !force {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "'{}' is already registered, use --force to replace it",
+                        name
+                    ),
+                ));
+            }
+            let entry = DatabaseEntry::from_database(&path, description)?;
+            println!("register {} -> {}", name, entry.path.display());
+            registry.databases.insert(name, entry);
+            registry.save(&registry_file)?;
+        }
+        DbCommand::Remove { name } => {
+            if registry.databases.remove(&name).is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("'{}' is not registered", name),
+                ));
+            }
+            println!("remove {}", name);
+            registry.save(&registry_file)?;
+        }
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
@@ -0,0 +1,284 @@
+use clap::Parser;
+use kraken2_rs::args::{parse_database, parse_size};
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::checksums::write_checksums;
+use kraken2_rs::compact_hash::{read_page_from_file, shard_checksum, HashConfig, ShardChecksums};
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Rewrite the hash_*.k2d shards of a database with a different hash capacity",
+    long_about = "Rewrite the hash_*.k2d shards of a database with a different hash capacity, without the original hash.k2d"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// New hash file capacity (e.g. '1.5G', '250M', '1024K'), the index size is 4 times the capacity
+    #[clap(long = "hash-capacity", value_parser = parse_size, required = true)]
+    pub hash_capacity: usize,
+
+    /// Directory of the resharded database, the other database files are copied into it
+    #[clap(long, required = true)]
+    pub output_dir: PathBuf,
+}
+
+/// 按全局位置读取旧 shard 的哈希单元, 只缓存当前用到的 shard
+struct OldShards {
+    files: Vec<PathBuf>,
+    config: HashConfig,
+    pages: Vec<(usize, Vec<u32>)>,
+}
+
+impl OldShards {
+    fn page_range(&self, page_index: usize) -> (usize, usize) {
+        let start = page_index * self.config.hash_capacity;
+        let end = std::cmp::min(start + self.config.hash_capacity, self.config.capacity);
+        (start, end)
+    }
+
+    /// 线性探测所在的环: version 0 (kraken 2 转换) 为整个哈希表, 否则为所在 shard
+    fn circle(&self, pos: usize) -> (usize, usize) {
+        if self.config.version < 1 {
+            (0, self.config.capacity)
+        } else {
+            self.page_range(pos / self.config.hash_capacity)
+        }
+    }
+
+    fn next(&self, pos: usize) -> usize {
+        let (start, end) = self.circle(pos);
+        if pos + 1 == end {
+            start
+        } else {
+            pos + 1
+        }
+    }
+
+    fn prev(&self, pos: usize) -> usize {
+        let (start, end) = self.circle(pos);
+        if pos == start {
+            end - 1
+        } else {
+            pos - 1
+        }
+    }
+
+    fn cell(&mut self, pos: usize) -> Result<u32> {
+        let page_index = pos / self.config.hash_capacity;
+        let (start, end) = self.page_range(page_index);
+        if let Some((_, data)) = self.pages.iter().find(|(index, _)| *index == page_index) {
+            return Ok(data[pos - start]);
+        }
+
+        let file = self.files.get(page_index).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("missing hash_{}.k2d", page_index + 1),
+            )
+        })?;
+        let page = read_page_from_file(file)?;
+        if page.size != end - start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{:?} holds {} cells, hash_config.k2d expects {}",
+                    file,
+                    page.size,
+                    end - start
+                ),
+            ));
+        }
+        // 没有空位时无法确定探测链的起点
+        if !page.data.contains(&0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} is full and cannot be resharded", file),
+            ));
+        }
+        let value = page.data[pos - start];
+        self.pages.push((page_index, page.data));
+        Ok(value)
+    }
+
+    /// 释放 `pos` 之前的 shard, 全局环绕时需要的第一个 shard 会重新读取
+    fn release_before(&mut self, pos: usize) {
+        let hash_capacity = self.config.hash_capacity;
+        self.pages
+            .retain(|(index, _)| (index + 1) * hash_capacity > pos);
+    }
+}
+
+/// 由 `offset` 开始线性探测 (在 shard 内环绕) 放入 `value`, 返回所在位置
+fn insert_cell(data: &mut [u32], offset: usize, value: u32) -> Result<usize> {
+    let mut idx = offset;
+    for _ in 0..data.len() {
+        if data[idx] == 0 {
+            data[idx] = value;
+            return Ok(idx);
+        }
+        idx = (idx + 1) % data.len();
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "hash capacity is too small, a new shard is full",
+    ))
+}
+
+/// 生成新的第 `page_index` (从 0 开始) 个 shard.
+///
+/// 单元中不保存原始的哈希值, 只知道它的起点位于所在探测链中它之前的某个位置.
+/// 探测链在新 shard 中每一段连续的部分, 都从该段的开头依次放入该段及之后的所有单元,
+/// 因此无论起点在哪一段, 查询都能沿连续的非空单元找到它. 跨越新 shard 边界的链中的单元会被复制
+fn build_page(old: &mut OldShards, page_index: usize, hash_capacity: usize) -> Result<Vec<u32>> {
+    let start = page_index * hash_capacity;
+    let end = std::cmp::min(start + hash_capacity, old.config.capacity);
+    let mut data = vec![0u32; end - start];
+
+    for pos in start..end {
+        if old.cell(pos)? == 0 {
+            continue;
+        }
+        let prev = old.prev(pos);
+        if prev + 1 == pos && prev >= start && old.cell(prev)? != 0 {
+            continue;
+        }
+
+        // 一段的开头, 放入探测链的剩余部分
+        let circle = old.circle(pos);
+        let mut cursor = pos - start;
+        let mut chain = pos;
+        for _ in circle.0..circle.1 {
+            let value = old.cell(chain)?;
+            if value == 0 {
+                break;
+            }
+            cursor = (insert_cell(&mut data, cursor, value)? + 1) % data.len();
+            chain = old.next(chain);
+        }
+    }
+    Ok(data)
+}
+
+fn write_page(file_path: &Path, page_index: usize, data: &[u32]) -> Result<usize> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    writer.write_all(&(page_index as u64).to_le_bytes())?;
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    let bytes = unsafe {
+        std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data))
+    };
+    writer.write_all(bytes)?;
+    writer.flush()?;
+    Ok(data.iter().filter(|&&value| value != 0).count())
+}
+
+/// 复制 hash 文件以外的数据库文件
+fn copy_database_files(database: &Path, output_dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(database)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !entry.file_type()?.is_file() || (name.starts_with("hash") && name.ends_with(".k2d")) {
+            continue;
+        }
+        fs::copy(entry.path(), output_dir.join(&*name))?;
+    }
+    Ok(())
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    let files = ChunkCatalog::scan_continuous(&args.database, ChunkKind::Hash)?.paths();
+    if files.len() != config.partition {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "found {} hash files, hash_config.k2d expects {}",
+                files.len(),
+                config.partition
+            ),
+        ));
+    }
+    if args.hash_capacity == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "hash capacity must be greater than 0",
+        ));
+    }
+
+    create_dir_all(&args.output_dir)?;
+    let config_file = args.output_dir.join("hash_config.k2d");
+    if config_file.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", config_file),
+        ));
+    }
+
+    let partition = config.capacity.div_ceil(args.hash_capacity);
+    info!("reshard start...");
+    info!(
+        "hash capacity {} -> {}, shards {} -> {}",
+        config.hash_capacity, args.hash_capacity, config.partition, partition
+    );
+    let start = Instant::now();
+
+    let mut old = OldShards {
+        files,
+        config,
+        pages: Vec::new(),
+    };
+    let mut size = 0;
+    let mut checksums = Vec::with_capacity(partition);
+    for page_index in 0..partition {
+        old.release_before(page_index * args.hash_capacity);
+        let data = build_page(&mut old, page_index, args.hash_capacity)?;
+        let page_file = args.output_dir.join(format!("hash_{}.k2d", page_index + 1));
+        size += write_page(&page_file, page_index + 1, &data)?;
+        checksums.push(shard_checksum(page_index + 1, &data));
+        info!(
+            "write {:?}/{}: duration: {:?}",
+            page_index + 1,
+            partition,
+            start.elapsed()
+        );
+    }
+    if size > config.size {
+        info!(
+            "{} cells of probe chains crossing shard boundaries were duplicated",
+            size - config.size
+        );
+    }
+
+    // 新 shard 都在各自内部环绕
+    let new_config = HashConfig::new(
+        config.version.max(1),
+        config.capacity,
+        config.value_bits,
+        size,
+        partition,
+        args.hash_capacity,
+    );
+    copy_database_files(&args.database, &args.output_dir)?;
+    new_config.write_to_file(config_file, Some(&ShardChecksums(checksums)))?;
+    write_checksums(&args.output_dir)?;
+
+    info!("reshard took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
@@ -1,47 +1,99 @@
 use clap::Parser;
-use kraken2_rs::classify::process_hitgroup;
-use kraken2_rs::compact_hash::{HashConfig, Row};
+use dashmap::DashMap;
+use kraken2_rs::args::{
+    check_stage_threads, parse_database, parse_fraction, parse_io_threads, parse_max_rank,
+    parse_size, parse_stop_when, stage_threads, OutputCompression, OutputFormat, StopWhen,
+};
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::checkpoint::Checkpoint;
+use kraken2_rs::classify::{
+    call_confidence, format_call, json_record, process_hitgroup, MinimizerData, RankCeiling,
+    StopTracker,
+};
+use kraken2_rs::compact_hash::{read_row_header, HashConfig, RecordReader, Row};
+use kraken2_rs::domains::{
+    apply_qc_gate, print_domain_summaries, write_run_summary, DomainSummary, DroppedReads, QcGate,
+    RUN_SUMMARY,
+};
+use kraken2_rs::error::{self, Kr2rError};
+use kraken2_rs::filenames::{output_stems, write_output_stems, SAMPLE_NAMES_FILE};
+use kraken2_rs::logging::per_sec;
+use kraken2_rs::progress::StageProgress;
+use kraken2_rs::read_index::create_read_output_writer;
 use kraken2_rs::readcounts::{TaxonCounters, TaxonCountersDash};
-use kraken2_rs::report::report_kraken_style;
+use kraken2_rs::report::{report_kraken_style, report_krona, ReportHeader};
+use kraken2_rs::result_cache::database_fingerprint;
+use kraken2_rs::segments::write_segments_bed;
+use kraken2_rs::seqout::{check_rereadable, SeqOutput};
+use kraken2_rs::split_output::SplitWriter;
+use kraken2_rs::stats::{
+    check_unclassified_taxid, parse_seq_size, set_name_normalization, set_unclassified,
+    write_length_stats, write_qc_stats, LengthHistogram, NameNormalization, ReadQc,
+    TaxonLengthStats, TaxonLengthStatsDash, TaxonQcStats, TaxonQcStatsDash,
+    DEFAULT_LENGTH_BIN_SIZE,
+};
 use kraken2_rs::taxonomy::Taxonomy;
-use kraken2_rs::utils::{find_and_trans_bin_files, find_and_trans_files, open_file};
-use kraken2_rs::HitGroup;
+use kraken2_rs::utils::{
+    low_power_threads, lower_priority, open_file, read_ahead, read_in_order, read_sample_file_map,
+    read_sample_metadata, read_sample_names, LOW_POWER_NICE,
+};
+use kraken2_rs::{HitGroup, IndexOptions};
+use tracing::{info, warn};
 // use rayon::prelude::*;
-use seqkmer::{buffer_map_parallel, trim_pair_info, OptionPair};
-use std::collections::HashMap;
+use seqkmer::{
+    buffer_map_parallel, trim_pair_info, AlignmentOptions, OptionPair, PairCheck, PairOptions,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{create_dir_all, File};
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Result, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Result, Write};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 
+#[allow(clippy::type_complexity)]
 pub fn read_id_to_seq_map<P: AsRef<Path>>(
     filename: P,
-) -> Result<HashMap<u32, (String, String, usize, Option<usize>)>> {
+) -> error::Result<HashMap<u32, (String, String, usize, Option<usize>, Option<ReadQc>)>> {
+    let filename = filename.as_ref();
     let file = open_file(filename)?;
     let reader = BufReader::new(file);
     let mut id_map = HashMap::new();
 
-    reader.lines().for_each(|line| {
-        let line = line.expect("Could not read line");
-        let parts: Vec<&str> = line.trim().splitn(4, '\t').collect();
+    for line in reader.lines() {
+        let line = line.map_err(|e| Kr2rError::chunk(filename, e))?;
+        let parts: Vec<&str> = line.trim().splitn(5, '\t').collect();
         if parts.len() >= 4 {
-            // 解析序号为u32类型的键
-            if let Ok(id) = parts[0].parse::<u32>() {
+            // 解析序号为u32类型的键, 超过 u32 的序号不可能来自 splitr, 说明文件已损坏
+            if let Ok(id) = parts[0].parse::<u64>() {
+                let id = u32::try_from(id).map_err(|_| {
+                    Kr2rError::chunk(
+                        filename,
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("read number {} is past 2^32 - 1", id),
+                        ),
+                    )
+                })?;
                 // 第二列是序列标识符，直接作为字符串
                 let seq_id = parts[1].to_string();
                 let seq_size = parts[2].to_string();
                 let count_parts: Vec<&str> = parts[3].split('|').collect();
-                let kmer_count1 = count_parts[0].parse::<usize>().unwrap();
+                let kmer_count1 = count_parts[0].parse::<usize>().map_err(|e| {
+                    Kr2rError::chunk(filename, io::Error::new(io::ErrorKind::InvalidData, e))
+                })?;
                 let kmer_count2 = if count_parts.len() > 1 {
-                    count_parts[1].parse::<usize>().map_or(None, |i| Some(i))
+                    count_parts[1].parse::<usize>().ok()
                 } else {
                     None
                 };
-                id_map.insert(id, (seq_id, seq_size, kmer_count1, kmer_count2));
+                // 第五列为可选的 GC/质量值统计
+                let qc = parts.get(4).and_then(|column| ReadQc::parse_column(column));
+                id_map.insert(id, (seq_id, seq_size, kmer_count1, kmer_count2, qc));
             }
         }
-    });
+    }
 
     Ok(id_map)
 }
@@ -53,8 +105,8 @@ pub fn read_id_to_seq_map<P: AsRef<Path>>(
     long_about = "resolve taxonomy tree"
 )]
 pub struct Args {
-    /// database hash chunk directory and other files
-    #[arg(long = "db", required = true)]
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
     pub database: PathBuf,
 
     /// chunk directory
@@ -69,9 +121,41 @@ pub struct Args {
     #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
     pub num_threads: usize,
 
-    // /// output file contains all unclassified sequence
-    // #[clap(long, value_parser, default_value_t = false)]
-    // pub full_output: bool,
+    /// Threads that process the reads of each stage (minimizers, hash table lookups, classification)
+    /// [default: --num-threads minus --io-threads]
+    #[clap(long, value_parser)]
+    pub compute_threads: Option<usize>,
+
+    /// Threads that read the input and write the output of each stage, in a pool of their own so
+    /// busy compute threads don't hold up the writer; at least 2 (one reader, one writer)
+    #[clap(long, value_parser = parse_io_threads, default_value_t = 2)]
+    pub io_threads: usize,
+
+    /// Memory-map taxo.k2d instead of reading it into memory, so concurrent runs on one node share the page cache
+    #[clap(long, value_parser, default_value_t = false)]
+    pub memory_mapping: bool,
+
+    /// Mates are interleaved in one input file (-P -S), used by --classified-out/--unclassified-out.
+    #[clap(short = 'S', long = "single-file-pairs", action)]
+    pub single_file_pairs: bool,
+
+    /// Mate validation for paired reads, must match the one used by splitr.
+    #[clap(long, value_parser, default_value = "strict")]
+    pub pair_check: PairCheck,
+
+    /// Only unmapped reads of BAM/CRAM inputs were classified, must match the one used by splitr.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub unmapped_only: bool,
+
+    /// Reference FASTA (with a .fai index) used to decode CRAM inputs that contain mapped reads.
+    #[clap(long, value_parser)]
+    pub cram_reference: Option<PathBuf>,
+
+    /// Also write the reads without any database hit to the output, as "U" lines like Kraken 2;
+    /// by default only the reads with hits are written (the reports count all reads either way)
+    #[clap(long, value_parser, default_value_t = false)]
+    pub full_output: bool,
+
     /// Confidence score threshold, default is 0.0.
     #[clap(
         short = 'T',
@@ -89,6 +173,10 @@ pub struct Args {
     #[clap(short = 'z', long, value_parser, default_value_t = false)]
     pub report_zero_counts: bool,
 
+    /// Start each kreport with "#" comment lines: sample, database and its hash, command line, version and date
+    #[clap(long, value_parser, default_value_t = false)]
+    pub report_headers: bool,
+
     /// The minimum number of hit groups needed for a call.
     #[clap(
         short = 'g',
@@ -97,44 +185,185 @@ pub struct Args {
         default_value_t = 2
     )]
     pub minimum_hit_groups: usize,
+
+    /// Cap all calls at this rank (e.g. genus), more specific calls are reported at their ancestor of this rank
+    #[clap(long, value_parser = parse_max_rank)]
+    pub max_rank: Option<String>,
+
+    /// Call the most specific clade holding at least this fraction of the hit k-mers (0-1),
+    /// instead of the default root-to-leaf scoring; 1.0 gives the LCA of all hits
+    #[clap(long, value_parser = parse_fraction)]
+    pub min_clade_support: Option<f64>,
+
+    /// Write per-taxon read-length histograms (output_*.lenstats), requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub read_length_stats: bool,
+
+    /// Bin width used by the read-length histograms
+    #[clap(long, value_parser, default_value_t = DEFAULT_LENGTH_BIN_SIZE)]
+    pub length_bin_size: usize,
+
+    /// Write per-taxon mean GC and base quality (output_*.qc), requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub qc_stats: bool,
+
+    /// Write Krona text and HTML charts (output_*.krona.txt/.html), requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub report_krona: bool,
+
+    /// Stop early once enough reads are assigned to a taxon (or its descendants),
+    /// e.g. "taxid=2697049 reads>=10"; the reports then count only the reads classified before the stop.
+    /// splitr and annotate have already processed every read, only direct stops reading the input
+    #[clap(long, value_parser = parse_stop_when)]
+    pub stop_when: Option<StopWhen>,
+
+    /// Mark each sample pass/fail in the summary and run_summary.json, e.g.
+    /// "reads>=100000,classified>=50,host<=10" (reads, classified, host or a domain name)
+    #[clap(long, value_parser)]
+    pub qc_gate: Option<QcGate>,
+
+    /// Exit with status 7 after writing all outputs when a sample fails --qc-gate
+    #[clap(long, value_parser, default_value_t = false)]
+    pub qc_fail_exit: bool,
+
+    /// Print scientific names instead of just taxids in the output, e.g. "name (taxid N)"
+    #[clap(long, value_parser, default_value_t = false)]
+    pub use_names: bool,
+
+    /// Taxid written for unclassified reads in the output and the kreports, e.g. a sentinel
+    /// node such as 12908 ("unclassified sequences") for schemas without taxid 0
+    #[clap(long, value_parser, default_value_t = 0)]
+    pub unclassified_taxid: u64,
+
+    /// Name written for unclassified reads in the output (--use-names, json) and the kreports
+    #[clap(long, value_parser, default_value = "unclassified")]
+    pub unclassified_label: String,
+
+    /// Normalize taxon names in the output and the reports: "none", "nfc" (Unicode NFC, so names
+    /// from different sources compare equal) or "ascii" (accents removed, other non-ASCII as '?').
+    /// Tabs, newlines and other control characters in names are always written as \t, \n, \u{..}
+    #[clap(long, value_parser, default_value = "none")]
+    pub name_normalization: NameNormalization,
+
+    /// Append the number of distinct minimizers hit and the minimizer hits of each taxon
+    /// ("taxid:count ...", "-" for none) to every line of the per-read output
+    #[clap(long, value_parser, default_value_t = false)]
+    pub read_minimizer_data: bool,
+
+    /// Per-read output format: "kraken" (tab-separated lines, output_*.txt) or "json"
+    /// (one JSON record per read, output_*.ndjson)
+    #[clap(long, value_parser, default_value = "kraken")]
+    pub output_format: OutputFormat,
+
+    /// Compress the per-read output, the kreports and the --classified-out/--unclassified-out
+    /// files: "none", "gzip" (adds .gz to the file names), "zstd" (adds .zst) or "bgzf" (adds
+    /// .gz and indexes the per-read output by read id for `lookup-reads`)
+    #[clap(long, value_parser, default_value = "none")]
+    pub compress_output: OutputCompression,
+
+    /// Don't write the per-read classification lines (output_*.txt or stdout), only the
+    /// reports and summaries
+    #[clap(long, value_parser, default_value_t = false)]
+    pub no_standard_output: bool,
+
+    /// Also write the classified segments of each read (read, start, end, taxid, score) as
+    /// BED to output_*.bed, for long reads and contigs in genome browsers; requires --output-dir
+    #[clap(long, value_parser, default_value_t = false)]
+    pub segments_bed: bool,
+
+    /// Split the per-read output of each sample into numbered parts of about this size
+    /// (uncompressed, e.g. '10G'), with an output_*.parts.tsv index of the reads in each part
+    #[clap(long, value_parser = parse_size)]
+    pub split_output_size: Option<usize>,
+
+    /// Name the output files of each sample after its --sample-sheet or --sample-regex name
+    /// instead of output_<N>. Names are made safe for all file systems and unique, and
+    /// sample_names.tsv in the output directory maps them
+    #[clap(long, value_parser, default_value_t = false)]
+    pub name_outputs: bool,
+
+    /// Write classified reads to this file, with " kraken:taxid|N" appended to the header.
+    /// For paired reads the name must contain '#', which is replaced by "_1" and "_2".
+    #[clap(long, value_parser)]
+    pub classified_out: Option<String>,
+
+    /// Write unclassified reads to this file ('#' is substituted as for --classified-out).
+    #[clap(long, value_parser)]
+    pub unclassified_out: Option<String>,
+
+    /// Fail when the chunk files are not numbered continuously (e.g. after a partial rerun);
+    /// by default the files present are processed and the missing ones are listed at the end
+    #[clap(long, value_parser, default_value_t = false)]
+    pub strict_chunks: bool,
+
+    /// Fail (exit status 4) when a sample_file_*.bin has hits of a read id that is missing from its
+    /// sample_id_*.map; by default those hits are dropped and counted per chunk in run_summary.json
+    #[clap(long, value_parser, default_value_t = false)]
+    pub strict_ids: bool,
+
+    /// Use fewer threads, read the .bin files one at a time instead of in a background thread,
+    /// and run at a lower CPU priority, keeping a laptop responsive during classification
+    #[clap(long, value_parser, default_value_t = false)]
+    pub low_power: bool,
 }
 
-fn read_rows_from_file<P: AsRef<Path>>(file_path: P) -> io::Result<HashMap<u32, Vec<Row>>> {
-    let file = File::open(file_path)?;
-    let mut reader = BufReader::new(file);
-    let mut buffer = [0u8; std::mem::size_of::<Row>()]; // 确保buffer的大小与Row结构体的大小一致
+/// 每次从 .bin 文件读取的 Row 数
+const READ_BATCH_ROWS: usize = 64 * 1024;
+
+fn read_rows_from_file(file_path: &Path) -> io::Result<HashMap<u32, Vec<Row>>> {
+    let mut file = BufReader::new(File::open(file_path)?);
+    read_row_header(&mut file).map_err(|e| Kr2rError::chunk(file_path, e))?;
+    let mut reader = RecordReader::<_, Row>::new(file, READ_BATCH_ROWS);
     let mut map: HashMap<u32, Vec<Row>> = HashMap::new();
 
-    while reader.read_exact(&mut buffer).is_ok() {
-        let row: Row = unsafe { std::mem::transmute(buffer) }; // 将读取的字节直接转换为Row结构体
-        map.entry(row.seq_id).or_default().push(row); // 插入到HashMap中
+    while let Some(rows) = reader
+        .next_batch()
+        .map_err(|e| Kr2rError::chunk(file_path, e))?
+    {
+        for row in rows {
+            map.entry(row.seq_id).or_default().push(row);
+        }
     }
 
     Ok(map)
 }
 
-fn process_batch<P: AsRef<Path>>(
-    sample_files: &Vec<P>,
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn process_batch<P: AsRef<Path> + Sync>(
+    sample_files: &[P],
     args: &Args,
     taxonomy: &Taxonomy,
-    id_map: &HashMap<u32, (String, String, usize, Option<usize>)>,
+    id_map: &HashMap<u32, (String, String, usize, Option<usize>, Option<ReadQc>)>,
     writer: &mut Box<dyn Write + Send>,
     value_mask: usize,
-) -> Result<(TaxonCountersDash, usize)> {
+    length_stats: &TaxonLengthStatsDash,
+    qc_stats: &TaxonQcStatsDash,
+    stop_tracker: Option<&StopTracker>,
+    rank_ceiling: Option<&RankCeiling>,
+    calls: Option<&DashMap<usize, u64>>,
+) -> Result<(TaxonCountersDash, usize, usize, Vec<DroppedReads>)> {
     let confidence_threshold = args.confidence_threshold;
     let minimum_hit_groups = args.minimum_hit_groups;
 
     let classify_counter = AtomicUsize::new(0);
+    // 分类过的 read 数, --stop-when 停止后其余的 read 不计入报告
+    let processed_counter = AtomicUsize::new(0);
     let cur_taxon_counts = TaxonCountersDash::new();
 
-    for sample_file in sample_files {
-        let hit_counts: HashMap<u32, Vec<Row>> = read_rows_from_file(sample_file)?;
+    // 返回 id 不在 sample_id map 中的 read 数
+    let mut process = |hit_counts: HashMap<u32, Vec<Row>>| -> Result<(ControlFlow<()>, usize)> {
+        let mut written = Ok(());
+        if stop_tracker.is_some_and(|tracker| tracker.is_stopped()) {
+            return Ok((ControlFlow::Break(()), 0));
+        }
+        let missing = AtomicUsize::new(0);
 
         buffer_map_parallel(
             &hit_counts,
-            args.num_threads,
+            stage_threads(args.num_threads, args.compute_threads, args.io_threads),
             |(k, rows)| {
-                if let Some(item) = id_map.get(&k) {
+                if let Some(item) = id_map.get(k) {
+                    processed_counter.fetch_add(1, Ordering::Relaxed);
                     let mut rows = rows.to_owned();
                     rows.sort_unstable();
 
@@ -150,6 +379,8 @@ fn process_batch<P: AsRef<Path>>(
                         hits.required_score(confidence_threshold),
                         minimum_hit_groups,
                         value_mask,
+                        rank_ceiling,
+                        args.min_clade_support,
                     );
 
                     hit_data.3.iter().for_each(|(key, value)| {
@@ -160,112 +391,576 @@ fn process_batch<P: AsRef<Path>>(
                             .unwrap();
                     });
 
+                    if args.read_length_stats {
+                        length_stats
+                            .entry(hit_data.1)
+                            .or_insert_with(|| LengthHistogram::new(args.length_bin_size))
+                            .add(parse_seq_size(&item.1));
+                    }
+
+                    if args.qc_stats {
+                        if let Some(qc) = &item.4 {
+                            qc_stats
+                                .entry(hit_data.1)
+                                .or_default()
+                                .add(qc, parse_seq_size(&item.1));
+                        }
+                    }
+
+                    if let Some(tracker) = stop_tracker {
+                        tracker.observe(taxonomy, hit_data.1);
+                    }
+
+                    if let Some(calls) = calls {
+                        calls.insert(*k as usize, hit_data.1);
+                    }
+
+                    // 没有命中的 read 只在 --full-output 时写出
+                    if args.no_standard_output || (hits.rows.is_empty() && !args.full_output) {
+                        return None;
+                    }
+
+                    let minimizers = args
+                        .read_minimizer_data
+                        .then(|| MinimizerData::from_hits(&hits, taxonomy, value_mask));
                     // 使用锁来同步写入
-                    let output_line = format!(
-                        "{}\t{}\t{}\t{}\t{}\n",
-                        hit_data.0, dna_id, hit_data.1, item.1, hit_data.2
-                    );
+                    let output_line = match args.output_format {
+                        OutputFormat::Kraken => {
+                            let mut line = format!(
+                                "{}\t{}\t{}\t{}\t{}",
+                                hit_data.0,
+                                dna_id,
+                                format_call(taxonomy, hit_data.1, args.use_names),
+                                item.1,
+                                hit_data.2
+                            );
+                            if let Some(data) = &minimizers {
+                                line.push_str(&format!(
+                                    "\t{}\t{}",
+                                    data.distinct,
+                                    data.hit_string()
+                                ));
+                            }
+                            line.push('\n');
+                            line
+                        }
+                        OutputFormat::Json => {
+                            let confidence =
+                                call_confidence(&hits, taxonomy, hit_data.1, value_mask);
+                            let record = json_record(
+                                &dna_id,
+                                taxonomy,
+                                hit_data.1,
+                                &item.1,
+                                &hit_data.2,
+                                confidence,
+                                minimizers.as_ref(),
+                            );
+                            format!("{}\n", record)
+                        }
+                    };
                     Some(output_line)
                 } else {
-                    eprintln!("can't find {} in sample_id map file", k);
+                    missing.fetch_add(1, Ordering::Relaxed);
                     None
                 }
             },
             |result| {
                 while let Some(output) = result.next() {
-                    if let Some(res) = output.unwrap() {
-                        writer
-                            .write_all(res.as_bytes())
-                            .expect("write output content error");
+                    // 写出错时继续取出结果, 让工作线程正常结束
+                    if let (Ok(()), Some(res)) = (&written, output.unwrap()) {
+                        written = writer.write_all(res.as_bytes());
                     }
                 }
             },
-        )
-        .expect("failed");
+        )?;
+        written?;
+        Ok((ControlFlow::Continue(()), missing.into_inner()))
+    };
+    // .bin 文件中只有命中数据库的 read, 记下出现过的 id
+    let mut seen: HashSet<u32> = HashSet::new();
+    let mut dropped = Vec::new();
+    let mut process_seen = |(path, hit_counts): (PathBuf, HashMap<u32, Vec<Row>>)| {
+        seen.extend(hit_counts.keys().copied());
+        let (flow, missing) = process(hit_counts)?;
+        if missing > 0 {
+            if args.strict_ids {
+                let source = io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "hits of {} reads that are not in the sample_id map (--strict-ids)",
+                        missing
+                    ),
+                );
+                return Err(Kr2rError::CorruptChunk { path, source }.into());
+            }
+            warn!(
+                "{:?}: dropped the hits of {} reads that are not in the sample_id map",
+                path, missing
+            );
+            dropped.push(DroppedReads::new(&path, missing as u64));
+        }
+        Ok(flow)
+    };
+    let load = |path: &Path| Ok((path.to_path_buf(), read_rows_from_file(path)?));
+    if args.low_power {
+        read_in_order(sample_files, load, &mut process_seen)?;
+    } else {
+        // 处理当前 .bin 文件时, 后台线程读取下一个
+        read_ahead(sample_files, load, &mut process_seen)?;
+    }
+    // 其余的 read 没有任何命中, 以空的命中列表分类 (结果为 U), 计入长度与质量统计,
+    // --full-output 时写出
+    let unhit: HashMap<u32, Vec<Row>> = id_map
+        .keys()
+        .filter(|id| !seen.contains(id))
+        .map(|&id| (id, Vec::new()))
+        .collect();
+    if !unhit.is_empty() {
+        // 已经停止 (--stop-when) 时不再处理
+        let _ = process(unhit)?;
     }
 
-    Ok((cur_taxon_counts, classify_counter.load(Ordering::SeqCst)))
+    Ok((
+        cur_taxon_counts,
+        processed_counter.load(Ordering::SeqCst),
+        classify_counter.load(Ordering::SeqCst),
+        dropped,
+    ))
 }
 
-pub fn run(args: Args) -> Result<()> {
+/// 一个样本 (或多个样本汇总) 的统计. 断点续跑时, 已完成样本的统计从 chunk 目录读回, 用于汇总报告
+#[derive(Default, Serialize, Deserialize)]
+struct SampleTotals {
+    seqs: usize,
+    unclassified: usize,
+    taxon_counts: TaxonCounters,
+    length_stats: TaxonLengthStats,
+    qc_stats: TaxonQcStats,
+    /// 旧版本保存的统计中没有此项
+    #[serde(default)]
+    dropped_reads: Vec<DroppedReads>,
+}
+
+impl SampleTotals {
+    fn state_file(chunk_dir: &Path, index: usize) -> PathBuf {
+        chunk_dir.join(format!("resolve_{}.json", index))
+    }
+
+    fn load(chunk_dir: &Path, index: usize) -> Result<Self> {
+        let file = File::open(Self::state_file(chunk_dir, index))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn save(&self, chunk_dir: &Path, index: usize) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(Self::state_file(chunk_dir, index))?);
+        serde_json::to_writer(&mut writer, self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        writer.flush()?;
+        writer.get_ref().sync_data()
+    }
+
+    fn merge(&mut self, other: &SampleTotals, length_bin_size: usize) {
+        self.seqs += other.seqs;
+        self.unclassified += other.unclassified;
+        for (taxid, counter) in &other.taxon_counts {
+            self.taxon_counts
+                .entry(*taxid)
+                .or_default()
+                .merge(counter)
+                .unwrap();
+        }
+        for (taxid, hist) in &other.length_stats {
+            self.length_stats
+                .entry(*taxid)
+                .or_insert_with(|| LengthHistogram::new(length_bin_size))
+                .merge(hist);
+        }
+        for (taxid, qc) in &other.qc_stats {
+            self.qc_stats.entry(*taxid).or_default().merge(qc);
+        }
+        self.dropped_reads
+            .extend(other.dropped_reads.iter().cloned());
+    }
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    set_unclassified(args.unclassified_taxid, &args.unclassified_label)?;
+    set_name_normalization(args.name_normalization);
+    if args.low_power {
+        args.num_threads = low_power_threads(args.num_threads);
+        if let Err(e) = lower_priority(LOW_POWER_NICE) {
+            warn!("failed to lower the process priority: {}", e);
+        }
+    }
+    check_stage_threads(args.num_threads, args.compute_threads, args.io_threads)?;
+    let segments_conflict = if args.output_dir.is_none() {
+        Some("output to stdout, use --output-dir")
+    } else if args.no_standard_output {
+        Some("--no-standard-output")
+    } else if args.split_output_size.is_some() {
+        Some("--split-output-size")
+    } else {
+        None
+    };
+    if let Some(reason) = segments_conflict.filter(|_| args.segments_bed) {
+        warn!("--segments-bed is ignored with {}", reason);
+        args.segments_bed = false;
+    }
+    // 片段的碱基坐标需要 k-mer 长度
+    let k = if args.segments_bed {
+        IndexOptions::read_index_options(args.database.join("opts.k2d"))?.k
+    } else {
+        0
+    };
     let k2d_dir = &args.database;
     let taxonomy_filename = k2d_dir.join("taxo.k2d");
-    let taxo = Taxonomy::from_file(taxonomy_filename)?;
+    let taxo = if args.memory_mapping {
+        Taxonomy::open_mmap(taxonomy_filename)?
+    } else {
+        Taxonomy::from_file(taxonomy_filename)?
+    };
+    check_unclassified_taxid(&taxo)?;
 
-    let sample_files = find_and_trans_bin_files(&args.chunk_dir, "sample_file", ".bin", false)?;
-    let sample_id_files = find_and_trans_files(&args.chunk_dir, "sample_id", ".map", false)?;
+    let sample_files = ChunkCatalog::scan(&args.chunk_dir, ChunkKind::SampleBin)?.group_by_index();
+    let id_catalog = ChunkCatalog::scan(&args.chunk_dir, ChunkKind::SampleId)?;
+    let sample_id_files = id_catalog.by_index();
 
     // let partition = sample_files.len();
-    let hash_config = HashConfig::from_hash_header(&args.database.join("hash_config.k2d"))?;
+    let hash_config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
     let value_mask = hash_config.value_mask;
 
-    let mut total_taxon_counts = TaxonCounters::new();
-    let mut total_seqs = 0;
-    let mut total_unclassified = 0;
+    let mut totals = SampleTotals::default();
+    // 输出到文件时才能跳过已完成的样本, 多个样本时还需保存各样本的统计用于汇总报告
+    let mut checkpoint = Checkpoint::open(&args.chunk_dir)?;
+    let resumable = args.output_dir.is_some();
+    let keep_totals = resumable && sample_files.len() > 1;
+    // 缺少 sample_id 文件的样本, 已完成的样本不算缺少
+    let mut missing: Vec<usize> = id_catalog
+        .missing_indices()
+        .into_iter()
+        .chain(
+            sample_files
+                .keys()
+                .copied()
+                .filter(|i| !sample_id_files.contains_key(i)),
+        )
+        .filter(|i| !(resumable && checkpoint.is_done("resolve", &i.to_string())))
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+    if args.strict_chunks && !missing.is_empty() {
+        return Err(id_catalog.missing_error(&missing));
+    }
 
     if let Some(output) = &args.output_dir {
         create_dir_all(output)?;
     }
 
+    let header = if args.report_headers {
+        Some(ReportHeader::new(
+            &args.database.display().to_string(),
+            &format!("{:016x}", database_fingerprint(&args.database)?),
+        ))
+    } else {
+        None
+    };
+    let sample_names = if header.is_some() || args.name_outputs {
+        read_sample_names(args.chunk_dir.join("sample_file.map"))?
+    } else {
+        HashMap::new()
+    };
+    // 合并报告的文件名, 样本不能使用
+    let combined_stem = match (sample_files.keys().min(), sample_files.keys().max()) {
+        (Some(min), Some(max)) if max > min => Some(format!("output_{}-{}", min, max)),
+        _ => None,
+    };
+    let all_names: BTreeMap<usize, String> = sample_names
+        .iter()
+        .map(|(i, name)| (*i, name.clone()))
+        .collect();
+    let named = if args.name_outputs {
+        all_names.clone()
+    } else {
+        BTreeMap::new()
+    };
+    let stems = output_stems(
+        sample_files.keys().copied(),
+        &named,
+        combined_stem.as_slice(),
+    );
+    let stem_of = |i: &usize| {
+        stems
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| format!("output_{}", i))
+    };
+    let sample_metadata = read_sample_metadata(args.chunk_dir.join("sample_metadata.map"))?;
+    let metadata_of = |i: &usize| sample_metadata.get(i).map_or(&[][..], |m| m.as_slice());
+
     // 开始计时
     let start = Instant::now();
-    println!("resolve start...");
+    info!(stage = "resolve", event = "start", "resolve start...");
+
+    let stop_tracker = args
+        .stop_when
+        .as_ref()
+        .map(|rule| StopTracker::new(rule, &taxo));
+    let rank_ceiling = args
+        .max_rank
+        .as_ref()
+        .map(|rank| RankCeiling::new(rank, &taxo));
+
+    let seq_output = SeqOutput::new(
+        args.classified_out.clone(),
+        args.unclassified_out.clone(),
+        sample_files.len() > 1,
+    )
+    .map(|seq_output| {
+        seq_output
+            .with_pairs(PairOptions {
+                interleaved: args.single_file_pairs,
+                check: args.pair_check,
+            })
+            .with_alignment(AlignmentOptions {
+                unmapped_only: args.unmapped_only,
+                reference: args.cram_reference.clone(),
+            })
+            .with_compression(args.compress_output)
+    });
+    let input_files: HashMap<usize, Vec<String>> = match &seq_output {
+        Some(seq_output) => {
+            let input_files: HashMap<usize, Vec<String>> =
+                read_sample_file_map(args.chunk_dir.join("sample_file.map"))?
+                    .into_iter()
+                    .collect();
+            for files in input_files.values() {
+                check_rereadable(files)?;
+                seq_output.check(files.len() > 1 || args.single_file_pairs)?;
+            }
+            input_files
+        }
+        None => HashMap::new(),
+    };
 
+    let missing_files: Vec<String> = missing.iter().map(|&i| id_catalog.file_name(i)).collect();
+
+    let progress = StageProgress::new("resolve", sample_files.len(), "samples");
+    let mut domain_summaries = Vec::new();
+    // 本次或之前的运行已经处理完的样本, 结束时只删除这些样本的 chunk 文件
+    let mut resolved = Vec::new();
     for (i, sam_files) in &sample_files {
-        let sample_id_map = read_id_to_seq_map(&sample_id_files[i])?;
+        if stop_tracker.as_ref().is_some_and(|t| t.is_stopped()) {
+            info!("stop condition met, skip remaining samples");
+            break;
+        }
+        let name = i.to_string();
+        if resumable && checkpoint.is_done("resolve", &name) {
+            info!("skip resolved sample {}", i);
+            if keep_totals {
+                match SampleTotals::load(&args.chunk_dir, *i) {
+                    Ok(sample) => {
+                        let sample_name = sample_names
+                            .get(i)
+                            .cloned()
+                            .unwrap_or_else(|| format!("output_{}", i));
+                        domain_summaries.push(
+                            DomainSummary::from_taxon_counts(
+                                &sample_name,
+                                &taxo,
+                                &sample.taxon_counts,
+                                sample.unclassified as u64,
+                            )
+                            .with_metadata(metadata_of(i))
+                            .with_dropped_reads(&sample.dropped_reads),
+                        );
+                        totals.merge(&sample, args.length_bin_size);
+                    }
+                    Err(e) => warn!("sample {} is missing from the combined report: {}", i, e),
+                }
+            }
+            resolved.push(*i);
+            progress.inc();
+            continue;
+        }
+        let Some(sample_id_file) = sample_id_files.get(i) else {
+            progress.inc();
+            continue;
+        };
+        let sample_start = Instant::now();
+        let sample_id_map = read_id_to_seq_map(sample_id_file)?;
+        if args.qc_stats && sample_id_map.values().all(|item| item.4.is_none()) {
+            warn!(
+                "sample {}: the sample_id map has no qc column, run splitr with --qc-stats",
+                i
+            );
+        }
 
-        let thread_sequences = sample_id_map.len();
         let mut writer: Box<dyn Write + Send> = match &args.output_dir {
+            _ if args.no_standard_output => Box::new(io::sink()),
             Some(ref file_path) => {
-                let filename = file_path.join(format!("output_{}.txt", i));
-                let file = File::create(filename)?;
-                Box::new(BufWriter::new(file)) as Box<dyn Write + Send>
+                let filename =
+                    file_path.join(format!("{}.{}", stem_of(i), args.output_format.extension()));
+                let compression = args.compress_output;
+                match args.split_output_size {
+                    Some(size) => Box::new(
+                        SplitWriter::new(filename, compression, size as u64)
+                            .with_format(args.output_format),
+                    ),
+                    None => create_read_output_writer(
+                        compression.compressed_path(filename),
+                        compression,
+                        args.output_format,
+                    )?,
+                }
             }
             None => Box::new(BufWriter::new(io::stdout())) as Box<dyn Write + Send>,
         };
-        let (thread_taxon_counts, thread_classified) = process_batch::<PathBuf>(
-            sam_files,
-            &args,
-            &taxo,
-            &sample_id_map,
-            &mut writer,
-            value_mask,
-        )?;
+        let length_stats = TaxonLengthStatsDash::new();
+        let qc_stats = TaxonQcStatsDash::new();
+        let calls = DashMap::new();
+        let (thread_taxon_counts, thread_sequences, thread_classified, dropped_reads) =
+            process_batch::<PathBuf>(
+                sam_files,
+                &args,
+                &taxo,
+                &sample_id_map,
+                &mut writer,
+                value_mask,
+                &length_stats,
+                &qc_stats,
+                stop_tracker.as_ref(),
+                rank_ceiling.as_ref(),
+                seq_output.as_ref().map(|_| &calls),
+            )?;
+        writer.flush()?;
+        drop(writer);
+        if thread_sequences < sample_id_map.len() {
+            info!(
+                "sample {}: stopped after {} of {} reads, the reports count only those",
+                i,
+                thread_sequences,
+                sample_id_map.len()
+            );
+        }
+        if let (true, Some(output)) = (args.segments_bed, &args.output_dir) {
+            let output_file = args.compress_output.compressed_path(output.join(format!(
+                "{}.{}",
+                stem_of(i),
+                args.output_format.extension()
+            )));
+            let bed_file = output.join(format!("{}.bed", stem_of(i)));
+            let segments = write_segments_bed(&output_file, &bed_file, k)?;
+            info!("sample {}: {} segments in {:?}", i, segments, bed_file);
+        }
 
-        let mut sample_taxon_counts: HashMap<
-            u64,
-            kraken2_rs::readcounts::ReadCounts<
-                hyperloglogplus::HyperLogLogPlus<u64, kraken2_rs::KBuildHasher>,
-            >,
-        > = HashMap::new();
-        thread_taxon_counts.iter().for_each(|entry| {
-            total_taxon_counts
-                .entry(*entry.key())
-                .or_default()
-                .merge(&entry.value())
-                .unwrap();
-            sample_taxon_counts
-                .entry(*entry.key())
-                .or_default()
-                .merge(&entry.value())
-                .unwrap();
-        });
+        if let Some(seq_output) = &seq_output {
+            match input_files.get(i) {
+                Some(files) => {
+                    seq_output.write_sample_reads(
+                        files,
+                        *i,
+                        &calls.into_iter().collect(),
+                        |reads_index| sample_id_map.contains_key(&(reads_index as u32)),
+                    )?;
+                }
+                None => warn!("can't find sample {} in sample_file.map", i),
+            }
+        }
+
+        let sample = SampleTotals {
+            seqs: thread_sequences,
+            unclassified: thread_sequences - thread_classified,
+            taxon_counts: thread_taxon_counts.into_iter().collect(),
+            length_stats: length_stats.into_iter().collect(),
+            qc_stats: qc_stats.into_iter().collect(),
+            dropped_reads,
+        };
+        let sample_name = sample_names
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| format!("output_{}", i));
+        domain_summaries.push(
+            DomainSummary::from_taxon_counts(
+                &sample_name,
+                &taxo,
+                &sample.taxon_counts,
+                sample.unclassified as u64,
+            )
+            .with_metadata(metadata_of(i))
+            .with_dropped_reads(&sample.dropped_reads),
+        );
         if let Some(output) = &args.output_dir {
-            let filename = output.join(format!("output_{}.kreport2", i));
+            let filename = args
+                .compress_output
+                .compressed_path(output.join(format!("{}.kreport2", stem_of(i))));
             report_kraken_style(
                 filename,
                 args.report_zero_counts,
                 args.report_kmer_data,
                 &taxo,
-                &sample_taxon_counts,
-                thread_sequences as u64,
-                (thread_sequences - thread_classified) as u64,
+                &sample.taxon_counts,
+                sample.seqs as u64,
+                sample.unclassified as u64,
+                header
+                    .as_ref()
+                    .map(|h| h.with_sample(&sample_name).with_metadata(metadata_of(i)))
+                    .as_ref(),
             )?;
+
+            if args.read_length_stats {
+                let filename = output.join(format!("{}.lenstats", stem_of(i)));
+                write_length_stats(filename, &taxo, &sample.length_stats)?;
+            }
+
+            if args.qc_stats {
+                let filename = output.join(format!("{}.qc", stem_of(i)));
+                write_qc_stats(filename, &taxo, &sample.qc_stats)?;
+            }
+
+            if args.report_krona {
+                report_krona(
+                    output,
+                    &stem_of(i),
+                    &taxo,
+                    &sample.taxon_counts,
+                    sample.unclassified as u64,
+                )?;
+            }
+
+            if keep_totals {
+                sample.save(&args.chunk_dir, *i)?;
+            }
+            checkpoint.done("resolve", &name, "")?;
         }
 
-        total_seqs += thread_sequences;
-        total_unclassified += thread_sequences - thread_classified;
+        totals.merge(&sample, args.length_bin_size);
+        let elapsed = sample_start.elapsed();
+        info!(
+            stage = "resolve",
+            event = "sample",
+            sample = *i,
+            elapsed_ms = elapsed.as_millis() as u64,
+            reads = sample.seqs as u64,
+            reads_per_sec = per_sec(sample.seqs as u64, elapsed),
+            "sample {}: {} reads",
+            i,
+            sample.seqs
+        );
+        progress.add_reads(sample.seqs as u64);
+        resolved.push(*i);
+        progress.inc();
     }
+    progress.finish();
+    let qc_failed = match &args.qc_gate {
+        Some(gate) => apply_qc_gate(&mut domain_summaries, gate),
+        None => 0,
+    };
+    print_domain_summaries(
+        &domain_summaries,
+        args.output_dir.is_none() && !args.no_standard_output,
+    );
 
     if let Some(output) = &args.output_dir {
         if !sample_files.is_empty() {
@@ -273,47 +968,159 @@ pub fn run(args: Args) -> Result<()> {
             let max = &sample_files.keys().max().cloned().unwrap();
 
             if max > min {
-                let filename = output.join(format!("output_{}-{}.kreport2", min, max));
+                let filename = args
+                    .compress_output
+                    .compressed_path(output.join(format!("output_{}-{}.kreport2", min, max)));
                 report_kraken_style(
                     filename,
                     args.report_zero_counts,
                     args.report_kmer_data,
                     &taxo,
-                    &total_taxon_counts,
-                    total_seqs as u64,
-                    total_unclassified as u64,
+                    &totals.taxon_counts,
+                    totals.seqs as u64,
+                    totals.unclassified as u64,
+                    header
+                        .as_ref()
+                        .map(|h| h.with_sample(&format!("output_{}-{}", min, max)))
+                        .as_ref(),
                 )?;
+
+                if args.read_length_stats {
+                    let filename = output.join(format!("output_{}-{}.lenstats", min, max));
+                    write_length_stats(filename, &taxo, &totals.length_stats)?;
+                }
+
+                if args.qc_stats {
+                    let filename = output.join(format!("output_{}-{}.qc", min, max));
+                    write_qc_stats(filename, &taxo, &totals.qc_stats)?;
+                }
+
+                if args.report_krona {
+                    report_krona(
+                        output,
+                        &format!("output_{}-{}", min, max),
+                        &taxo,
+                        &totals.taxon_counts,
+                        totals.unclassified as u64,
+                    )?;
+                }
             }
 
             let source_sample_file = args.chunk_dir.join("sample_file.map");
             let to_sample_file = output.join("sample_file.txt");
             std::fs::copy(source_sample_file, to_sample_file)?;
+            let source_metadata_file = args.chunk_dir.join("sample_metadata.map");
+            if source_metadata_file.exists() {
+                std::fs::copy(source_metadata_file, output.join("sample_metadata.txt"))?;
+            }
+            if !sample_names.is_empty() {
+                write_output_stems(output.join(SAMPLE_NAMES_FILE), &all_names, &stems)?;
+            }
+            write_run_summary(output.join(RUN_SUMMARY), &domain_summaries, &missing_files)?;
         };
     }
 
     // 计算持续时间
     let duration = start.elapsed();
     // 打印运行时间
-    println!("resolve took: {:?}", duration);
+    info!(
+        stage = "resolve",
+        event = "end",
+        elapsed_ms = duration.as_millis() as u64,
+        reads = progress.reads(),
+        reads_per_sec = per_sec(progress.reads(), duration),
+        "resolve took: {:?}",
+        duration
+    );
+    if !missing_files.is_empty() {
+        info!(
+            "missing sample files (not resolved): {}",
+            missing_files.join(", ")
+        );
+    }
 
-    for (_, sam_files) in &sample_files {
-        for sample_file in sam_files {
+    for i in &resolved {
+        for sample_file in sample_files.get(i).into_iter().flatten() {
+            let _ = std::fs::remove_file(sample_file);
+        }
+        if let Some(sample_file) = sample_id_files.get(i) {
             let _ = std::fs::remove_file(sample_file);
         }
     }
-
-    for (_, sample_file) in sample_id_files {
-        let _ = std::fs::remove_file(sample_file);
+    // 还有没有处理的样本时保留检查点与已完成样本的统计, 补齐文件后重新运行只处理剩下的样本
+    if resolved.len() == sample_files.len() && missing.is_empty() {
+        for i in &resolved {
+            let _ = std::fs::remove_file(SampleTotals::state_file(&args.chunk_dir, *i));
+        }
+        checkpoint.remove()?;
     }
     // let source_sample_file = args.chunk_dir.join("sample_file.map");
     // let _ = std::fs::remove_file(source_sample_file);
+    if args.qc_fail_exit && qc_failed > 0 {
+        return Err(Kr2rError::QcFailed(format!(
+            "{} of {} samples failed the QC gate",
+            qc_failed,
+            domain_summaries.len()
+        ))
+        .into());
+    }
     Ok(())
 }
 
 #[allow(dead_code)]
 fn main() {
+    kraken2_rs::logging::init_default_logging();
     let args = Args::parse();
     if let Err(e) = run(args) {
         eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_map(name: &str, lines: &[String]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("kr2r_resolve_{}_{}.map", name, std::process::id()));
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        path
+    }
+
+    #[test]
+    fn id_map_keeps_ids_up_to_u32_max() {
+        let ids = [0, 1 << 31, u32::MAX];
+        let lines: Vec<String> = ids
+            .iter()
+            .map(|id| format!("{}\tread{}\t150|150\t120|118", id, id))
+            .collect();
+        let path = write_map("max", &lines);
+        let map = read_id_to_seq_map(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(map.len(), ids.len());
+        let (read_id, seq_size, count1, count2, _) = &map[&u32::MAX];
+        assert_eq!(read_id, &format!("read{}", u32::MAX));
+        assert_eq!(seq_size, "150|150");
+        assert_eq!((*count1, *count2), (120, Some(118)));
+    }
+
+    #[test]
+    fn id_map_rejects_ids_past_u32() {
+        for id in [1u64 << 32, u64::MAX] {
+            let lines = vec![
+                "1\tread1\t150\t120".to_string(),
+                format!("{}\tread2\t150\t120", id),
+            ];
+            let path = write_map(&format!("past_{}", id), &lines);
+            let err = read_id_to_seq_map(&path).unwrap_err();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(
+                err.exit_code(),
+                kraken2_rs::error::EXIT_CORRUPT_CHUNK,
+                "{}",
+                err
+            );
+        }
     }
 }
@@ -0,0 +1,169 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::compact_hash::{
+    map_next_page, read_next_page, HashConfig, HashPage, Page, ShardChecksums, LOOKUP_CACHE_ENTRIES,
+};
+use kraken2_rs::distributed::serve_connection;
+use kraken2_rs::utils::format_bytes;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tracing::{info, warn};
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Hold some hash shards of a database in memory and annotate chunks for `annotate --workers`",
+    long_about = "Load the given hash_*.k2d shards of a database and answer the minimizer lookups that `annotate --workers` (or `classify --workers`) sends over TCP, so a database larger than the memory of one node can be spread over several nodes"
+)]
+pub struct Args {
+    /// database directory with hash_config.k2d and the shards to serve, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Shards to serve, numbered like the hash_<n>.k2d files, e.g. "1-4,7"; default: all of them
+    #[clap(long, value_parser)]
+    pub shards: Option<String>,
+
+    /// Address to listen on. Lookups are not authenticated, so the default only accepts local connections; listen on another interface (e.g. 0.0.0.0:7200) only on a trusted network
+    #[clap(long, value_parser, default_value = "127.0.0.1:7200")]
+    pub listen: String,
+
+    /// Entries of the per-batch cache of hash table lookups; 0 disables it
+    #[clap(long, value_parser, default_value_t = LOOKUP_CACHE_ENTRIES)]
+    pub lookup_cache: usize,
+
+    /// The number of threads to use.
+    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    pub num_threads: usize,
+
+    /// Memory-map the hash_*.k2d files instead of reading them into memory
+    #[clap(long, value_parser, default_value_t = false)]
+    pub memory_mapping: bool,
+
+    /// Skip checking the hash_*.k2d files against the checksums stored in hash_config.k2d
+    #[clap(long, value_parser, default_value_t = false)]
+    pub no_verify: bool,
+}
+
+/// 解析 `1-4,7` 形式的 shard 列表, 返回从 0 开始的分区序号
+fn parse_shards(spec: &str, partition: usize) -> Result<Vec<usize>> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "invalid --shards {:?}, expected numbers between 1 and {} such as 1-4,7",
+                spec, partition
+            ),
+        )
+    };
+    let mut shards = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first.trim(), last.trim()),
+            None => (part, part),
+        };
+        let first: usize = first.parse().map_err(|_| invalid())?;
+        let last: usize = last.parse().map_err(|_| invalid())?;
+        if first == 0 || first > last || last > partition {
+            return Err(invalid());
+        }
+        shards.extend(first - 1..last);
+    }
+    shards.sort_unstable();
+    shards.dedup();
+    if shards.is_empty() {
+        return Err(invalid());
+    }
+    Ok(shards)
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    let shards = match &args.shards {
+        Some(spec) => parse_shards(spec, config.partition)?,
+        None => (0..config.partition).collect(),
+    };
+    let checksums = if args.no_verify {
+        None
+    } else {
+        ShardChecksums::from_hash_header(args.database.join("hash_config.k2d"))?
+    };
+    // 新版本数据库只需要持有的 shard 存在; 版本 0 (由 Kraken 2 数据库转换) 的
+    // read_next_page 与 map_next_page 还会打开下一个 shard, 读取跨越分区边界的 cell,
+    // 因此还需要每个持有的 shard 之后的那个 shard
+    let hash_files: Vec<PathBuf> = (1..=config.partition)
+        .map(|i| args.database.join(format!("hash_{}.k2d", i)))
+        .collect();
+    // 所有连接共用一个查找线程池
+    let pool = Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(args.num_threads)
+            .build()
+            .map_err(|e| Error::other(e.to_string()))?,
+    );
+
+    let start = Instant::now();
+    let mut pages = BTreeMap::new();
+    let mut bytes = 0;
+    for &shard in &shards {
+        let page = if args.memory_mapping {
+            HashPage::Mapped(map_next_page(
+                &hash_files,
+                shard,
+                config,
+                checksums.as_ref(),
+            )?)
+        } else {
+            let mut page = Page::with_capacity(0, config.hash_capacity);
+            read_next_page(&mut page, &hash_files, shard, config, checksums.as_ref())?;
+            HashPage::Loaded(page)
+        };
+        bytes += page.byte_size();
+        pages.insert(shard, page);
+    }
+    let names: Vec<String> = shards.iter().map(|s| (s + 1).to_string()).collect();
+    info!(
+        "loaded shards {} of {} ({}) in {:?}",
+        names.join(","),
+        config.partition,
+        format_bytes(bytes as f64),
+        start.elapsed()
+    );
+
+    let listener = TcpListener::bind(&args.listen)?;
+    info!("listening on {}", listener.local_addr()?);
+    let pages = Arc::new(pages);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        let pages = Arc::clone(&pages);
+        let pool = Arc::clone(&pool);
+        let lookup_cache = args.lookup_cache;
+        thread::spawn(move || {
+            info!("coordinator {} connected", peer);
+            match serve_connection(stream, &config, &pages, lookup_cache, &pool) {
+                Ok(()) => info!("coordinator {} disconnected", peer),
+                Err(e) => warn!("coordinator {}: {}", peer, e),
+            }
+        });
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
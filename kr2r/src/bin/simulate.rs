@@ -0,0 +1,406 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::simulate::{allocate, parse_abundances, reverse_complement, ErrorModel, SimRng};
+use kraken2_rs::taxonomy::Taxonomy;
+use kraken2_rs::utils::{find_files, read_id_to_taxon_map};
+use seqkmer::{dyn_reader, FastaReader, OptionPair};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Simulate a metagenome from database genomes, with a truth table for every read",
+    long_about = "Simulate Illumina or ONT reads from the library sequences of a database with a chosen abundance profile, and write the true taxon of every read and the true profile next to the reads"
+)]
+pub struct Args {
+    /// database directory with library/library*.fna, seqid2taxid.map and taxo.k2d,
+    /// or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Taxa and their abundances as fractions of the reads, e.g. 562:0.5,1280:0.3,1639:0.2;
+    /// every library sequence in the clade of a taxid belongs to it
+    #[clap(short = 't', long, value_parser, conflicts_with = "profile")]
+    pub taxa: Option<String>,
+
+    /// Tab-separated file of taxid and abundance per line, instead of --taxa
+    #[clap(long, value_parser)]
+    pub profile: Option<PathBuf>,
+
+    /// Abundances of the taxa when neither --taxa nor --profile is given: every taxon
+    /// of the library, with equal (uniform) or random (lognormal) abundances
+    #[clap(long, value_parser = ["uniform", "lognormal"], default_value = "uniform")]
+    pub abundance: String,
+
+    /// Number of reads (pairs with --paired) to simulate.
+    #[clap(short = 'n', long, value_parser, default_value_t = 100000)]
+    pub reads: u64,
+
+    /// Read length, the median length for ONT.
+    #[clap(short = 'r', long, value_parser, default_value_t = 150)]
+    pub read_len: usize,
+
+    /// Sequencing errors: none, illumina or ont
+    #[clap(short = 'e', long, value_parser, default_value_t = ErrorModel::Illumina)]
+    pub error_model: ErrorModel,
+
+    /// Simulate paired-end reads from both ends of each fragment.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub paired: bool,
+
+    /// Mean fragment length of paired-end reads, with a standard deviation of 10%.
+    #[clap(long, value_parser, default_value_t = 400)]
+    pub insert_size: usize,
+
+    /// Seed of the random numbers; the same seed and options give the same reads
+    #[clap(long, value_parser, default_value_t = 1)]
+    pub seed: u64,
+
+    /// Prefix of the output files: <prefix>.fq (<prefix>_1.fq and <prefix>_2.fq with --paired),
+    /// <prefix>.truth.tsv and <prefix>.profile.tsv
+    #[clap(short, long, value_parser, required = true)]
+    pub output: PathBuf,
+}
+
+/// 属于某个模拟 taxon 的 library 序列
+struct Member {
+    seqid: String,
+    /// 序列自身的 taxid
+    taxid: u64,
+    len: usize,
+    /// 在 taxa 中的下标
+    taxon: usize,
+}
+
+fn read_profile(path: &Path) -> Result<Vec<(u64, f64)>> {
+    let mut abundances = Vec::new();
+    for line in BufReader::new(dyn_reader(path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let item = match (fields.next(), fields.next()) {
+            (Some(taxid), Some(abundance)) => format!("{}:{}", taxid, abundance),
+            _ => line.to_string(),
+        };
+        // 跳过表头
+        if abundances.is_empty() && item.split(':').next().unwrap_or("").parse::<u64>().is_err() {
+            continue;
+        }
+        abundances.extend(parse_abundances(&item)?);
+    }
+    Ok(abundances)
+}
+
+/// 大写的碱基, 去掉换行
+fn clean_sequence(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .filter(|base| base.is_ascii_alphabetic())
+        .map(|base| base.to_ascii_uppercase())
+        .collect()
+}
+
+fn library_sequences(
+    library_files: &[PathBuf],
+    mut f: impl FnMut(String, Vec<u8>) -> Result<()>,
+) -> Result<()> {
+    for library_file in library_files {
+        let mut reader = FastaReader::from_path(library_file, 1)?;
+        while let Some((_, record)) = reader._next()? {
+            if let OptionPair::Single(seq) = &record.body {
+                f(record.header.id, clean_sequence(seq))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_fastq<W: Write>(writer: &mut W, id: &str, seq: &[u8], qual: &[u8]) -> Result<()> {
+    writer.write_all(b"@")?;
+    writer.write_all(id.as_bytes())?;
+    writer.write_all(b"\n")?;
+    writer.write_all(seq)?;
+    writer.write_all(b"\n+\n")?;
+    writer.write_all(qual)?;
+    writer.write_all(b"\n")
+}
+
+fn output_path(prefix: &Path, suffix: &str) -> PathBuf {
+    let mut name = prefix.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let start = Instant::now();
+    if args.read_len == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--read-len must be at least 1",
+        ));
+    }
+    if args.paired && args.error_model == ErrorModel::Ont {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--paired needs the illumina or none error model",
+        ));
+    }
+    let database = &args.database;
+    let taxonomy = Taxonomy::from_file(database.join("taxo.k2d"))?;
+    let id_to_taxon_map = read_id_to_taxon_map(database.join("seqid2taxid.map"))?;
+    let library_files = find_files(database.join("library"), "library", ".fna");
+    if library_files.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "no library/library*.fna in {:?}, the library is needed to simulate reads",
+                database
+            ),
+        ));
+    }
+
+    let requested = match (&args.taxa, &args.profile) {
+        (Some(taxa), _) => Some(parse_abundances(taxa)?),
+        (None, Some(profile)) => Some(read_profile(profile)?),
+        (None, None) => None,
+    };
+    // 模拟的 taxon (internal id) 与丰度
+    let mut taxa: Vec<(u32, f64)> = Vec::new();
+    if let Some(requested) = &requested {
+        for &(taxid, abundance) in requested {
+            let internal = taxonomy.get_internal_id(taxid);
+            if internal == 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("taxid {} is not in the database taxonomy", taxid),
+                ));
+            }
+            taxa.push((internal, abundance));
+        }
+        if taxa.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "no taxa to simulate"));
+        }
+    }
+    let mut index: HashMap<u32, usize> = taxa
+        .iter()
+        .enumerate()
+        .map(|(i, &(taxon, _))| (taxon, i))
+        .collect();
+
+    // 第一遍: 每条足够长的序列归入最深的包含它的 taxon; ONT 的 reads 可以截短
+    let min_len = match args.error_model {
+        ErrorModel::Ont => args.read_len.min(50),
+        _ => args.read_len,
+    };
+    let mut members: Vec<Member> = Vec::new();
+    library_sequences(&library_files, |seqid, seq| {
+        let Some(&taxid) = id_to_taxon_map.get(&seqid) else {
+            return Ok(());
+        };
+        let internal = taxonomy.get_internal_id(taxid);
+        if internal == 0 || seq.len() < min_len {
+            return Ok(());
+        }
+        let taxon = if requested.is_some() {
+            taxonomy
+                .lineage(internal)
+                .iter()
+                .rev()
+                .find_map(|node| index.get(node).copied())
+        } else {
+            // 没有给出 taxa 时, library 中的每个 taxon 都模拟
+            let next = taxa.len();
+            let i = *index.entry(internal).or_insert(next);
+            if i == next {
+                taxa.push((internal, 1.0));
+            }
+            Some(i)
+        };
+        if let Some(taxon) = taxon {
+            members.push(Member {
+                seqid,
+                taxid,
+                len: seq.len(),
+                taxon,
+            });
+        }
+        Ok(())
+    })?;
+
+    let mut rng = SimRng::new(args.seed);
+    if requested.is_none() && args.abundance == "lognormal" {
+        for taxon in taxa.iter_mut() {
+            taxon.1 = rng.normal().exp();
+        }
+    }
+    let mut taxon_members: Vec<Vec<usize>> = vec![Vec::new(); taxa.len()];
+    for (i, member) in members.iter().enumerate() {
+        taxon_members[member.taxon].push(i);
+    }
+    let missing: Vec<String> = taxa
+        .iter()
+        .zip(&taxon_members)
+        .filter(|(_, indices)| indices.is_empty())
+        .map(|(&(taxon, _), _)| taxonomy.nodes[taxon as usize].external_id.to_string())
+        .collect();
+    if taxa.is_empty() || !missing.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "no library sequence of at least {} bp for taxid {}",
+                min_len,
+                if missing.is_empty() {
+                    "any".to_string()
+                } else {
+                    missing.join(", ")
+                }
+            ),
+        ));
+    }
+
+    // 各 taxon 的 reads 数, 再按长度随机分给其中的序列
+    let weights: Vec<f64> = taxa.iter().map(|&(_, abundance)| abundance).collect();
+    let taxon_reads = allocate(args.reads, &weights);
+    let mut member_reads = vec![0u64; members.len()];
+    for (taxon, indices) in taxon_members.iter().enumerate() {
+        // 序列长度的累计和, 随机位置落在哪条序列上
+        let ends: Vec<u64> = indices
+            .iter()
+            .scan(0u64, |end, &i| {
+                *end += members[i].len as u64;
+                Some(*end)
+            })
+            .collect();
+        let total_len = ends.last().copied().unwrap_or(0);
+        for _ in 0..taxon_reads[taxon] {
+            let pos = rng.below(total_len);
+            member_reads[indices[ends.partition_point(|&end| end <= pos)]] += 1;
+        }
+    }
+    let wanted: HashMap<&str, usize> = members
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| member_reads[*i] > 0)
+        .map(|(i, member)| (member.seqid.as_str(), i))
+        .collect();
+
+    if let Some(parent) = args.output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let create = |suffix: &str| -> Result<BufWriter<File>> {
+        Ok(BufWriter::new(File::create(output_path(
+            &args.output,
+            suffix,
+        ))?))
+    };
+    let (mut reads_1, mut reads_2) = if args.paired {
+        (create("_1.fq")?, Some(create("_2.fq")?))
+    } else {
+        (create(".fq")?, None)
+    };
+    let mut truth = create(".truth.tsv")?;
+    writeln!(truth, "read_id\ttaxid\tseqid\tstart\tlength\tstrand")?;
+
+    // 第二遍: 从选中的序列中截取 reads
+    let mut read_num = 0u64;
+    let mut done = vec![false; members.len()];
+    library_sequences(&library_files, |seqid, seq| {
+        let Some(&i) = wanted.get(seqid.as_str()) else {
+            return Ok(());
+        };
+        // 重复的 seqid 只用第一条
+        if std::mem::replace(&mut done[i], true) {
+            return Ok(());
+        }
+        let member = &members[i];
+        for _ in 0..member_reads[i] {
+            read_num += 1;
+            let id = format!("sim.{}", read_num);
+            let fragment_len = if args.paired {
+                let sd = args.insert_size as f64 / 10.0;
+                ((args.insert_size as f64 + sd * rng.normal()).round() as usize).max(args.read_len)
+            } else {
+                args.error_model.read_length(&mut rng, args.read_len)
+            }
+            .min(seq.len());
+            let start = rng.below((seq.len() - fragment_len + 1) as u64) as usize;
+            let forward = rng.chance(0.5);
+            let fragment = &seq[start..start + fragment_len];
+            let fragment = if forward {
+                fragment.to_vec()
+            } else {
+                reverse_complement(fragment)
+            };
+            let len = fragment_len.min(if args.paired {
+                args.read_len
+            } else {
+                fragment_len
+            });
+            let (seq_1, qual_1) = args.error_model.sequence(&mut rng, &fragment[..len]);
+            write_fastq(&mut reads_1, &id, &seq_1, &qual_1)?;
+            if let Some(reads_2) = &mut reads_2 {
+                let mate = reverse_complement(&fragment[fragment_len - len..]);
+                let (seq_2, qual_2) = args.error_model.sequence(&mut rng, &mate);
+                write_fastq(reads_2, &id, &seq_2, &qual_2)?;
+            }
+            writeln!(
+                truth,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                id,
+                member.taxid,
+                member.seqid,
+                start + 1,
+                fragment_len,
+                if forward { '+' } else { '-' }
+            )?;
+        }
+        Ok(())
+    })?;
+    reads_1.flush()?;
+    if let Some(reads_2) = &mut reads_2 {
+        reads_2.flush()?;
+    }
+    truth.flush()?;
+
+    let mut profile = create(".profile.tsv")?;
+    writeln!(profile, "taxid\tname\trank\treads\tfraction")?;
+    for (i, &(taxon, _)) in taxa.iter().enumerate() {
+        writeln!(
+            profile,
+            "{}\t{}\t{}\t{}\t{:.6}",
+            taxonomy.nodes[taxon as usize].external_id,
+            taxonomy.name(taxon),
+            taxonomy.rank(taxon),
+            taxon_reads[i],
+            taxon_reads[i] as f64 / args.reads.max(1) as f64
+        )?;
+    }
+    profile.flush()?;
+
+    info!(
+        "simulated {} {} from {} sequences of {} taxa, took: {:?}",
+        read_num,
+        if args.paired { "pairs" } else { "reads" },
+        wanted.len(),
+        taxa.len(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
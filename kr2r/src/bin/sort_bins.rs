@@ -0,0 +1,96 @@
+use clap::Parser;
+use kraken2_rs::args::parse_size;
+use kraken2_rs::binsort::{is_sorted_bin, sort_bin_file};
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::compact_hash::{ChunkRecord, Row};
+use kraken2_rs::error::Result;
+use std::io;
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Sort the sample_file_*.bin files of a chunk directory by sequence id",
+    long_about = "Sort the sample_file_*.bin files written by annotate by sequence id (then k-mer position) with an external merge sort, so that the rows of each read are contiguous and the files can be streamed read by read or compared between runs. Each file is replaced by its sorted version"
+)]
+pub struct Args {
+    /// chunk directory
+    #[clap(long)]
+    pub chunk_dir: PathBuf,
+
+    /// Memory for the rows sorted at once (e.g. '1G'); larger files are sorted in parts that
+    /// are merged from temporary files
+    #[clap(long, value_parser = parse_size, default_value = "1G")]
+    pub max_memory: usize,
+
+    /// Directory for the temporary files (default: the chunk directory)
+    #[clap(long)]
+    pub tmp_dir: Option<PathBuf>,
+
+    /// Only check whether the files are sorted; exits with an error if one is not
+    #[clap(long, value_parser, default_value_t = false)]
+    pub check: bool,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let files = ChunkCatalog::scan(&args.chunk_dir, ChunkKind::SampleBin)?.paths();
+    if files.is_empty() {
+        info!("no sample_file_*.bin files in {:?}", args.chunk_dir);
+        return Ok(());
+    }
+    let start = Instant::now();
+
+    if args.check {
+        let mut unsorted = Vec::new();
+        for file in &files {
+            if !is_sorted_bin(file)? {
+                unsorted.push(file.display().to_string());
+            }
+        }
+        info!(
+            "{} of {} files sorted",
+            files.len() - unsorted.len(),
+            files.len()
+        );
+        if !unsorted.is_empty() {
+            return Err(io::Error::other(format!(
+                "not sorted by sequence id: {}",
+                unsorted.join(", ")
+            ))
+            .into());
+        }
+        return Ok(());
+    }
+
+    let tmp_dir = args.tmp_dir.as_ref().unwrap_or(&args.chunk_dir);
+    std::fs::create_dir_all(tmp_dir)?;
+    let max_rows = args.max_memory / Row::SIZE;
+    let mut rows = 0;
+    for file in &files {
+        let stats = sort_bin_file(file, max_rows, tmp_dir)?;
+        info!(
+            "{:?}: {} rows, {} sorted runs",
+            file, stats.rows, stats.runs
+        );
+        rows += stats.rows;
+    }
+    info!(
+        "sort-bins: {} rows in {} files, took: {:?}",
+        rows,
+        files.len(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
@@ -1,16 +1,30 @@
 use clap::Parser;
-use kraken2_rs::compact_hash::{HashConfig, Slot};
+use kraken2_rs::args::{check_stage_threads, parse_database, parse_io_threads, stage_threads};
+use kraken2_rs::checkpoint::{restore_files, snapshot_files, Checkpoint, CHECKPOINT_FILE};
+use kraken2_rs::compact_hash::{sample_seq_id, ChunkRecord, HashConfig, Slot};
+use kraken2_rs::error::Kr2rError;
+use kraken2_rs::inputs::{detect_pairs, expand_inputs, read_sample_sheet, SheetSample};
+use kraken2_rs::logging::per_sec;
+use kraken2_rs::progress::StageProgress;
+use kraken2_rs::stats::ReadQc;
 use kraken2_rs::utils::{
     create_partition_files, create_partition_writers, create_sample_file, get_file_limit,
-    get_lastest_file_index, set_fd_limit,
+    reserve_file_indexes, reset_file_index, resolve_quality_encoding, set_fd_limit,
 };
 use kraken2_rs::IndexOptions;
-use seqkmer::{read_parallel, FastxReader, Meros, MinimizerIterator, OptionPair, Reader};
+use regex::Regex;
+use seqkmer::{
+    read_parallel_with, AlignmentOptions, Base, FastxReader, Meros, MinimizerIterator, OptionPair,
+    PairCheck, PairMode, PairOptions, PairStats, QualityEncoding, Reader,
+};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::fs;
 use std::io::{BufWriter, Write};
 use std::io::{Error, ErrorKind, Result};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
+use tracing::{info, warn};
 
 /// Command line arguments for the splitr program.
 ///
@@ -23,8 +37,8 @@ use std::time::Instant;
     long_about = "Split fast(q/a) file into ranges"
 )]
 pub struct Args {
-    /// database hash chunk directory and other files
-    #[arg(long = "db", required = true)]
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
     pub database: PathBuf,
 
     // /// The file path for the Kraken 2 options.
@@ -38,25 +52,89 @@ pub struct Args {
     #[clap(short = 'S', long = "single-file-pairs", action)]
     pub single_file_pairs: bool,
 
-    /// Minimum quality score for FASTQ data.
+    /// Treat every input file as single-end. Without -P or -S, FASTQ files named as R1/R2 mates
+    /// (e.g. x_R1_001.fastq.gz and x_R2_001.fastq.gz) are paired automatically
+    #[clap(long, value_parser, default_value_t = false)]
+    pub unpaired: bool,
+
+    /// Mate validation for paired reads: "strict" stops on mismatched read ids or read counts,
+    /// "repair" drops reads without a mate and reports the counts, "off" pairs reads in order.
+    #[clap(long, value_parser, default_value = "strict")]
+    pub pair_check: PairCheck,
+
+    /// Scoring of paired reads: "range" scores the mates as two ranges of one read, "concat" joins them with an N spacer into one sequence like old Kraken versions.
+    #[clap(long, value_parser, default_value = "range")]
+    pub pair_mode: PairMode,
+
+    /// Minimum quality score for FASTQ data, lower bases are masked as ambiguous before minimizer extraction
     #[clap(
         short = 'Q',
         long = "minimum-quality-score",
+        visible_alias = "minimum-base-quality",
         value_parser,
         default_value_t = 0
     )]
     pub minimum_quality_score: i32,
 
+    /// FASTQ quality encoding used by -Q and the quality statistics: auto, phred33, phred64 or solexa.
+    /// "auto" detects it from the first reads of each sample.
+    #[clap(long, value_parser, default_value = "auto")]
+    pub quality_encoding: QualityEncoding,
+
+    /// Only classify unmapped reads of BAM/CRAM inputs (both mates unmapped with -P -S),
+    /// e.g. the reads left after aligning to a host genome.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub unmapped_only: bool,
+
+    /// Reference FASTA (with a .fai index) used to decode CRAM inputs that contain mapped reads.
+    #[clap(long, value_parser)]
+    pub cram_reference: Option<PathBuf>,
+
     /// The number of threads to use.
     #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
     pub num_threads: usize,
 
+    /// Threads that process the reads of each stage (minimizers, hash table lookups, classification)
+    /// [default: --num-threads minus --io-threads]
+    #[clap(long, value_parser)]
+    pub compute_threads: Option<usize>,
+
+    /// Threads that read the input and write the output of each stage, in a pool of their own so
+    /// busy compute threads don't hold up the writer; at least 2 (one reader, one writer)
+    #[clap(long, value_parser = parse_io_threads, default_value_t = 2)]
+    pub io_threads: usize,
+
+    /// Threads used to decompress each input file: BGZF (bgzip) files are decompressed in parallel, other compressed files in a separate reader thread. 1 decompresses inline
+    #[clap(long, value_parser, default_value_t = 4)]
+    pub decompress_threads: usize,
+
     /// chunk directory
     #[clap(long)]
     pub chunk_dir: PathBuf,
 
+    /// Record per-read GC and base quality in the sample_id map, needed by `resolve --qc-stats`
+    #[clap(long, value_parser, default_value_t = false)]
+    pub qc_stats: bool,
+
+    /// Route reads to separate samples by a regex over the read id (e.g. run, lane or barcode
+    /// fields). The named group `sample`, else the first group, else the whole match names the
+    /// sample; reads that do not match stay in the sample of their input file.
+    #[clap(long, value_parser)]
+    pub sample_regex: Option<Regex>,
+
+    /// Merge all sequence files found in one directory or tarball input into a single sample
+    /// (R1 and R2 separately with -P); otherwise every file (or pair) is its own sample.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub merge_inputs: bool,
+
+    /// Tab-separated sample sheet with one sample per line: name, R1 and (for paired-end data) R2.
+    /// Rows with the same name (e.g. lanes) are merged; the names are written to sample_file.txt
+    #[clap(long, value_parser, conflicts_with = "input_files")]
+    pub sample_sheet: Option<PathBuf>,
+
     /// A list of input file paths (FASTA/FASTQ) to be processed by the classify program.
-    /// Supports fasta or fastq format files (e.g., .fasta, .fastq) and gzip compressed files (e.g., .fasta.gz, .fastq.gz).
+    /// Supports fasta or fastq format files (e.g., .fasta, .fastq) and gzip, zstd, bzip2 or xz compressed files (e.g., .fasta.gz, .fastq.zst, .fq.bz2, .fq.xz).
+    /// Directories and tarballs (.tar, .tar.gz, .tgz) are expanded to the sequence files they contain, and - reads from standard input.
     // #[clap(short = 'F', long = "files")]
     pub input_files: Vec<String>,
 }
@@ -65,34 +143,101 @@ fn init_chunk_writers(
     args: &Args,
     partition: usize,
     chunk_size: usize,
-) -> Vec<BufWriter<fs::File>> {
-    let chunk_files = create_partition_files(partition, &args.chunk_dir, "sample");
+) -> Result<Vec<BufWriter<fs::File>>> {
+    let chunk_files = create_partition_files(partition, &args.chunk_dir, "sample")?;
 
-    let mut writers = create_partition_writers(&chunk_files);
+    let mut writers = create_partition_writers(&chunk_files)?;
 
-    writers.iter_mut().enumerate().for_each(|(index, writer)| {
+    for (index, writer) in writers.iter_mut().enumerate() {
         // 获取对应的文件大小
-        let file_size = writer
-            .get_ref()
-            .metadata()
-            .expect("Failed to get file metadata")
-            .len();
+        let file_size = writer.get_ref().metadata()?.len();
 
         if file_size == 0 {
-            writer
-                .write_all(&index.to_le_bytes())
-                .expect("Failed to write partition");
+            writer.write_all(&index.to_le_bytes())?;
 
             let chunk_size_bytes = chunk_size.to_le_bytes();
-            writer
-                .write_all(&chunk_size_bytes)
-                .expect("Failed to write chunk size");
+            writer.write_all(&chunk_size_bytes)?;
 
-            writer.flush().expect("Failed to flush writer");
+            writer.flush()?;
         }
-    });
+    }
+
+    Ok(writers)
+}
+
+/// 按 `--sample-regex` 把 reads 分到新的样本. 编号在读取线程中按样本出现的顺序从 sample_file.map 的计数器分配;
+/// 不匹配的 reads 留在输入文件的样本中
+struct SampleRouter<R> {
+    inner: R,
+    regex: Option<Regex>,
+    sample_file: PathBuf,
+    // 样本编号需要放进 seq_id 的高位
+    max_index: usize,
+    samples: Vec<(usize, String)>,
+    indexes: HashMap<String, usize>,
+}
 
-    writers
+/// 命名分组 `sample`, 否则第一个分组, 否则整个匹配
+fn sample_name<'a>(regex: &Regex, id: &'a str) -> Option<&'a str> {
+    let caps = regex.captures(id)?;
+    caps.name("sample")
+        .or_else(|| caps.get(1))
+        .or_else(|| caps.get(0))
+        .map(|m| m.as_str())
+}
+
+impl<R: Reader> SampleRouter<R> {
+    fn new(inner: R, regex: Option<Regex>, sample_file: PathBuf, max_index: usize) -> Self {
+        Self {
+            inner,
+            regex,
+            sample_file,
+            max_index,
+            samples: Vec::new(),
+            indexes: HashMap::new(),
+        }
+    }
+
+    fn sample_index(&mut self, name: &str) -> Result<usize> {
+        if let Some(&index) = self.indexes.get(name) {
+            return Ok(index);
+        }
+        let index = reserve_file_indexes(&self.sample_file, 1)?;
+        if index >= self.max_index {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "--sample-regex produced too many samples ({}), the database supports {}",
+                    self.samples.len() + 1,
+                    self.max_index
+                ),
+            ));
+        }
+        self.samples.push((index, name.to_string()));
+        self.indexes.insert(name.to_string(), index);
+        Ok(index)
+    }
+}
+
+impl<R: Reader> Reader for SampleRouter<R> {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        let Some(mut seqs) = self.inner.next()? else {
+            return Ok(None);
+        };
+        let Some(regex) = self.regex.clone() else {
+            return Ok(Some(seqs));
+        };
+        for seq in seqs.iter_mut() {
+            if let Some(name) = sample_name(&regex, &seq.header.id) {
+                seq.header.file_index = self.sample_index(name)?;
+            }
+        }
+        Ok(Some(seqs))
+    }
+
+    fn pair_stats(&self) -> Option<PairStats> {
+        self.inner.pair_stats()
+    }
 }
 
 /// 处理record
@@ -110,57 +255,76 @@ fn process_record(
         let seq_sort = sort + offset;
         let partition_index = slot.idx / chunk_size;
 
-        slot.idx = seq_sort << idx_bits | (slot.idx % chunk_size);
+        slot.idx %= chunk_size;
+        slot.set_kmer_id(seq_sort, idx_bits);
         k2_slot_list.push((partition_index, slot));
     }
 }
 
 fn write_data_to_file(
-    k2_map: String,
+    k2_maps: HashMap<usize, String>,
     k2_slot_list: Vec<(usize, Slot<u64>)>,
-    writers: &mut Vec<BufWriter<fs::File>>,
-    slot_size: usize,
-    sample_writer: &mut BufWriter<fs::File>,
-) {
+    writers: &mut [BufWriter<fs::File>],
+    sample_writers: &mut HashMap<usize, BufWriter<fs::File>>,
+    chunk_dir: &Path,
+) -> Result<()> {
+    let mut bytes = [0u8; Slot::<u64>::SIZE];
     for slot in k2_slot_list {
         let partition_index = slot.0;
         if let Some(writer) = writers.get_mut(partition_index) {
-            writer.write_all(slot.1.as_slice(slot_size)).unwrap();
+            slot.1.encode(&mut bytes);
+            writer.write_all(&bytes)?;
         }
     }
 
-    sample_writer.write_all(k2_map.as_bytes()).unwrap();
+    for (sample_index, k2_map) in k2_maps {
+        let sample_writer = match sample_writers.entry(sample_index) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(create_sample_file(
+                chunk_dir.join(format!("sample_id_{}.map", sample_index)),
+            )?),
+        };
+        sample_writer.write_all(k2_map.as_bytes())?;
+    }
+    Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_fastx_file<R>(
     args: &Args,
     meros: Meros,
     hash_config: HashConfig,
-    file_index: usize,
     reader: &mut R,
-    writers: &mut Vec<BufWriter<fs::File>>,
-    sample_writer: &mut BufWriter<fs::File>,
+    writers: &mut [BufWriter<fs::File>],
+    sample_writers: &mut HashMap<usize, BufWriter<fs::File>>,
+    progress: &StageProgress,
+    input_file: &str,
 ) -> Result<()>
 where
     R: Reader,
 {
+    let qc_stats = args.qc_stats;
     let chunk_size = hash_config.hash_capacity;
     let idx_bits = ((chunk_size as f64).log2().ceil() as usize).max(1);
-    let slot_size = std::mem::size_of::<Slot<u64>>();
 
-    read_parallel(
+    let mut written = Ok(());
+    read_parallel_with(
         reader,
-        args.num_threads as usize,
+        stage_threads(args.num_threads, args.compute_threads, args.io_threads),
         &meros,
-        |seqs| {
-            let mut buffer = String::new();
+        |seq| args.pair_mode.apply(seq),
+        |seqs| -> Result<_> {
+            let mut buffers: HashMap<usize, String> = HashMap::new();
             let mut k2_slot_list = Vec::new();
+            let reads = seqs.len();
             for seq in seqs {
                 let mut init: Vec<(usize, Slot<u64>)> = Vec::new();
                 let header = &seq.header;
                 let index = header.reads_index;
                 let dna_id = header.id.trim();
-                let seq_id = (file_index << 32 | index) as u64;
+                // --sample-regex 时 file_index 为 read 所属的样本
+                let sample_index = header.file_index;
+                let seq_id = sample_seq_id(sample_index, index)?;
 
                 seq.body.apply_mut(|m_iter| {
                     process_record(
@@ -176,32 +340,68 @@ where
 
                 let size_str = seq.fmt_size();
                 let seq_size_str = seq.fmt_seq_size();
+                let buffer = buffers.entry(sample_index).or_default();
                 buffer.push_str(
-                    format!("{}\t{}\t{}\t{}\n", index, dna_id, seq_size_str, size_str).as_str(),
+                    format!("{}\t{}\t{}\t{}", index, dna_id, seq_size_str, size_str).as_str(),
                 );
+                if qc_stats {
+                    buffer.push('\t');
+                    buffer.push_str(&ReadQc::from_marker(seq).to_column());
+                }
+                buffer.push('\n');
             }
-            (buffer, k2_slot_list)
+            Ok((buffers, k2_slot_list, reads))
         },
         |dataset| {
             while let Some(data) = dataset.next() {
-                let (buffer, k2_slot_list) = data.unwrap();
-                write_data_to_file(buffer, k2_slot_list, writers, slot_size, sample_writer);
+                // 出错时继续取出结果, 让读取线程正常结束
+                let (buffers, k2_slot_list, reads) = match data.unwrap() {
+                    Ok(data) => data,
+                    Err(e) => {
+                        if written.is_ok() {
+                            written = Err(e);
+                        }
+                        continue;
+                    }
+                };
+                progress.add_reads(reads as u64);
+                if written.is_ok() {
+                    written = write_data_to_file(
+                        buffers,
+                        k2_slot_list,
+                        writers,
+                        sample_writers,
+                        &args.chunk_dir,
+                    );
+                }
             }
         },
     )
-    .expect("failed");
-
-    Ok(())
+    .map_err(|e| Kr2rError::input(input_file, e))?;
+    written
 }
 
-/// 处理样本文件
-fn process_files<F>(args: &Args, hash_config: HashConfig, mut action: F) -> Result<()>
+/// 处理样本文件, `action` 返回 `--sample-regex` 分出的样本编号与名称
+///
+/// `sheet` 为 `--sample-sheet` 中的各样本, 与输入文件按顺序对应. 样本的元数据写入 sample_metadata.map
+fn process_files<F>(
+    args: &Args,
+    hash_config: HashConfig,
+    sheet: &[SheetSample],
+    mut action: F,
+) -> Result<()>
 where
-    F: FnMut(usize, OptionPair<String>) -> Result<()>,
+    F: FnMut(usize, OptionPair<String>, &StageProgress) -> Result<Vec<(usize, String)>>,
 {
     let file_path = args.chunk_dir.join("sample_file.map");
-    let mut file_writer = create_sample_file(&file_path);
-    let mut file_index = get_lastest_file_index(&file_path)?;
+    let mut file_writer = create_sample_file(&file_path)?;
+    let mut metadata_writer = if sheet.iter().any(|sample| !sample.metadata.is_empty()) {
+        Some(create_sample_file(
+            args.chunk_dir.join("sample_metadata.map"),
+        )?)
+    } else {
+        None
+    };
 
     let chunk_size = if args.paired_end_processing && !args.single_file_pairs {
         2
@@ -210,50 +410,147 @@ where
     };
     let files = args.input_files.chunks(chunk_size).collect::<Vec<_>>();
 
-    let file_bits = (((files.len() + file_index) as f64).log2().ceil() as usize).max(1);
+    // 同时向一个 chunk 目录运行的 splitr 从计数器得到互不重叠的序号
+    let first_index = reserve_file_indexes(&file_path, files.len())?;
+    let file_bits = (((files.len() + first_index - 1) as f64).log2().ceil() as usize).max(1);
     if file_bits > hash_config.value_bits {
         panic!("The number of files is too large to process.");
     }
 
-    for file_pair in files {
-        file_index += 1;
+    let progress = StageProgress::new("splitr", files.len(), "files");
+    for (i, file_pair) in files.into_iter().enumerate() {
+        let file_index = first_index + i;
         let path_pair = OptionPair::from_slice(file_pair);
-        writeln!(
-            file_writer,
-            "{}\t{}",
-            file_index,
-            path_pair.reduce_str(",", |a| a.to_string())
-        )?;
+        let files_str = path_pair.reduce_str(",", |a| a.to_string());
+        match sheet.get(i) {
+            Some(sample) => writeln!(
+                file_writer,
+                "{}\t{}\t{}",
+                file_index, files_str, sample.name
+            )?,
+            None => writeln!(file_writer, "{}\t{}", file_index, files_str)?,
+        }
         file_writer.flush().unwrap();
+        if let (Some(writer), Some(sample)) = (metadata_writer.as_mut(), sheet.get(i)) {
+            for (key, value) in &sample.metadata {
+                writeln!(writer, "{}\t{}\t{}", file_index, key, value)?;
+            }
+            writer.flush()?;
+        }
 
-        action(file_index, path_pair)?;
+        let file_start = Instant::now();
+        let reads = progress.reads();
+        let samples = action(file_index, path_pair, &progress)?;
+        let reads = progress.reads() - reads;
+        let elapsed = file_start.elapsed();
+        info!(
+            stage = "splitr",
+            event = "sample",
+            sample = file_index,
+            elapsed_ms = elapsed.as_millis() as u64,
+            reads,
+            reads_per_sec = per_sec(reads, elapsed),
+            "file {}: {} reads",
+            file_index,
+            reads
+        );
+        for (sample_index, sample) in samples {
+            writeln!(file_writer, "{}\t{}\t{}", sample_index, files_str, sample)?;
+        }
+        file_writer.flush().unwrap();
+        progress.inc();
     }
+    progress.finish();
 
     Ok(())
 }
 
-pub fn run(args: Args) -> Result<()> {
+/// splitr 写出的 chunk 文件与样本记录
+fn is_splitr_output(name: &str) -> bool {
+    name == "sample_file.map"
+        || name == "sample_metadata.map"
+        || (name.starts_with("sample_id_") && name.ends_with(".map"))
+        || (name.starts_with("sample_") && name.ends_with(".k2"))
+}
+
+pub fn run(mut args: Args) -> Result<()> {
     // let args = Args::parse();
+    check_stage_threads(args.num_threads, args.compute_threads, args.io_threads)?;
     let options_filename = &args.database.join("opts.k2d");
     let idx_opts = IndexOptions::read_index_options(options_filename)?;
 
-    if args.paired_end_processing && !args.single_file_pairs && args.input_files.len() % 2 != 0 {
+    // 断点记录以输入文件列表区分每次运行
+    let inputs_key = match &args.sample_sheet {
+        Some(sheet) => format!("sheet:{}", sheet.display()),
+        None => args.input_files.join(","),
+    };
+    fs::create_dir_all(&args.chunk_dir)?;
+    let mut checkpoint = Checkpoint::open(&args.chunk_dir)?;
+    if checkpoint.is_done("splitr", &inputs_key) {
+        info!(
+            "splitr already done for these inputs, skip (remove {} to split again)",
+            CHECKPOINT_FILE
+        );
+        return Ok(());
+    }
+    if let Some(snapshot) = checkpoint.started_value("splitr", &inputs_key) {
+        info!("resume interrupted splitr");
+        restore_files(&args.chunk_dir, is_splitr_output, snapshot)?;
+        reset_file_index(&args.chunk_dir.join("sample_file.map"))?;
+    }
+    // 新的 chunk 文件会重用 annotate 记录过的名称
+    checkpoint.clear_stage("annotate")?;
+    let snapshot = snapshot_files(&args.chunk_dir, is_splitr_output)?;
+    checkpoint.start("splitr", &inputs_key, &snapshot)?;
+
+    seqkmer::set_decompress_threads(args.decompress_threads);
+    #[cfg(feature = "cloud")]
+    kraken2_rs::remote::enable_remote_inputs();
+    let mut sheet_samples = Vec::new();
+    if let Some(sheet) = &args.sample_sheet {
+        let (samples, paired) = read_sample_sheet(sheet, &args.chunk_dir)?;
+        info!("sample sheet {:?}: {} samples", sheet, samples.len());
+        if paired {
+            args.paired_end_processing = true;
+            args.single_file_pairs = false;
+        }
+        args.input_files = samples.iter().flat_map(|s| s.files.clone()).collect();
+        sheet_samples = samples;
+    } else if !args.paired_end_processing && !args.single_file_pairs && !args.unpaired {
+        if let Some(inputs) = detect_pairs(&args.input_files) {
+            info!("paired-end input detected from R1/R2 file names, use --unpaired to process the files separately");
+            args.input_files = inputs;
+            args.paired_end_processing = true;
+        }
+    }
+    let paired = args.paired_end_processing && !args.single_file_pairs;
+    // 样本表中的文件与样本一一对应, 不展开目录
+    if args.sample_sheet.is_none() {
+        args.input_files = expand_inputs(
+            &args.input_files,
+            paired,
+            args.merge_inputs,
+            &args.chunk_dir,
+        )?;
+    }
+
+    if paired && !args.input_files.len().is_multiple_of(2) {
         // 验证文件列表是否为偶数个
         return Err(Error::new(
             ErrorKind::InvalidInput,
             "Paired-end processing requires an even number of input files.",
         ));
     }
-    let hash_config = HashConfig::from_hash_header(&args.database.join("hash_config.k2d"))?;
+    let hash_config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
 
-    println!("{:?}", hash_config);
+    info!("{:?}", hash_config);
     if hash_config.hash_capacity == 0 {
         panic!("`hash_capacity` can't be zero!");
     }
-    println!("splitr start...");
+    info!(stage = "splitr", event = "start", "splitr start...");
     let file_num_limit = get_file_limit();
     if hash_config.partition >= file_num_limit {
-        eprintln!(
+        warn!(
             "file num limit {:?}, need: {:?}",
             file_num_limit, hash_config.partition
         );
@@ -266,36 +563,92 @@ pub fn run(args: Args) -> Result<()> {
     let start = Instant::now();
     let partition = hash_config.partition;
     let mut writers: Vec<BufWriter<fs::File>> =
-        init_chunk_writers(&args, partition, hash_config.hash_capacity);
-
-    process_files(&args, hash_config, |file_index, path_pair| {
-        let mut sample_writer =
-            create_sample_file(args.chunk_dir.join(format!("sample_id_{}.map", file_index)));
-
-        let score = args.minimum_quality_score;
-        let mut reader = FastxReader::from_paths(path_pair, file_index, score)?;
-        process_fastx_file(
-            &args,
-            meros,
-            hash_config,
-            file_index,
-            &mut reader,
-            &mut writers,
-            &mut sample_writer,
-        )
-        .expect("process fastx file error");
-        Ok(())
-    })?;
+        init_chunk_writers(&args, partition, hash_config.hash_capacity)?;
+
+    process_files(
+        &args,
+        hash_config,
+        &sheet_samples,
+        |file_index, path_pair, progress| {
+            let mut sample_writers = HashMap::from([(
+                file_index,
+                create_sample_file(args.chunk_dir.join(format!("sample_id_{}.map", file_index)))?,
+            )]);
+
+            let score = args.minimum_quality_score;
+            let pairs = PairOptions {
+                interleaved: args.paired_end_processing && args.single_file_pairs,
+                check: args.pair_check,
+            };
+            let first_file = match &path_pair {
+                OptionPair::Single(path) | OptionPair::Pair(path, _) => path,
+            };
+            let input_file = first_file.clone();
+            let encoding = resolve_quality_encoding(args.quality_encoding, first_file, file_index)?;
+            let alignment = AlignmentOptions {
+                unmapped_only: args.unmapped_only,
+                reference: args.cram_reference.clone(),
+            };
+            let reader = FastxReader::from_paths_with_options(
+                path_pair, file_index, score, pairs, encoding, &alignment,
+            )
+            .map_err(|e| Kr2rError::input(&input_file, e))?;
+            let mut reader = SampleRouter::new(
+                reader,
+                args.sample_regex.clone(),
+                args.chunk_dir.join("sample_file.map"),
+                1 << hash_config.value_bits,
+            );
+            process_fastx_file(
+                &args,
+                meros,
+                hash_config,
+                &mut reader,
+                &mut writers,
+                &mut sample_writers,
+                progress,
+                &input_file,
+            )?;
+            if let Some(stats) = reader.pair_stats().filter(|stats| stats.orphan_count() > 0) {
+                info!("file {}: {}", file_index, stats);
+            }
+            if !reader.samples.is_empty() {
+                let indexes: Vec<String> = reader
+                    .samples
+                    .iter()
+                    .map(|(index, _)| index.to_string())
+                    .collect();
+                info!(
+                    "file {}: routed reads to samples {} by --sample-regex",
+                    file_index,
+                    indexes.join(",")
+                );
+            }
+            Ok(reader.samples)
+        },
+    )?;
+    for writer in writers.iter_mut() {
+        writer.flush()?;
+    }
+    checkpoint.done("splitr", &inputs_key, "")?;
     let duration = start.elapsed();
-    println!("splitr took: {:?}", duration);
+    info!(
+        stage = "splitr",
+        event = "end",
+        elapsed_ms = duration.as_millis() as u64,
+        "splitr took: {:?}",
+        duration
+    );
 
     Ok(())
 }
 
 #[allow(dead_code)]
 fn main() {
+    kraken2_rs::logging::init_default_logging();
     let args = Args::parse();
     if let Err(e) = run(args) {
         eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
     }
 }
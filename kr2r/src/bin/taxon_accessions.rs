@@ -0,0 +1,204 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::compact_hash::{CHTable, HashConfig, ShardChecksums};
+use kraken2_rs::stats::report_name;
+use kraken2_rs::taxonomy::Taxonomy;
+use kraken2_rs::utils::{find_files, read_id_to_taxon_map};
+use kraken2_rs::IndexOptions;
+use seqkmer::{read_parallel, FastaReader};
+use std::fs::File;
+use std::io::{self, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "List the library accessions that contributed minimizers to a taxon",
+    long_about = "Rescan the library sequences of a taxon's clade and count, for every accession, how many of its minimizers the database stores at the taxon or below it"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Taxonomy ID to query.
+    #[clap(short, long, value_parser, required = true)]
+    pub taxid: u64,
+
+    /// Also list accessions of the clade whose minimizers are all stored above the taxon.
+    #[clap(short, long, value_parser, default_value_t = false)]
+    pub all: bool,
+
+    /// Output table, defaults to the standard output.
+    #[clap(short, long, value_parser)]
+    pub output: Option<PathBuf>,
+
+    /// Memory-map the hash shards instead of reading them into memory
+    #[clap(long, value_parser, default_value_t = false)]
+    pub memory_mapping: bool,
+
+    /// The number of threads to use.
+    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    pub num_threads: usize,
+}
+
+/// 一条 library 序列对查询 taxon 的贡献
+struct Contribution {
+    seqid: String,
+    taxid: u64,
+    minimizers: usize,
+    /// 数据库中存放在查询 taxon 或其下的 minimizer 数
+    clade_minimizers: usize,
+}
+
+/// seqid2taxid.map 中 `taxid|<taxid>|<accession>` 形式的 seqid 只保留 accession
+fn accession(seqid: &str) -> &str {
+    match seqid.strip_prefix("taxid|") {
+        Some(rest) => rest
+            .split_once('|')
+            .map_or(rest, |(_, accession)| accession),
+        None => seqid,
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let start = Instant::now();
+    let database = &args.database;
+    let taxonomy = Taxonomy::from_file(database.join("taxo.k2d"))?;
+    let query = taxonomy.get_internal_id(args.taxid);
+    if query == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("taxid {} is not in the database taxonomy", args.taxid),
+        ));
+    }
+
+    let id_to_taxon_map = read_id_to_taxon_map(database.join("seqid2taxid.map"))?;
+    let library_files = find_files(database.join("library"), "library", ".fna");
+    if library_files.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "no library/library*.fna in {:?}, the library is needed to find the accessions",
+                database
+            ),
+        ));
+    }
+
+    let idx_opts = IndexOptions::read_index_options(database.join("opts.k2d"))?;
+    let meros = idx_opts.as_meros().for_references();
+    let hash_config = HashConfig::from_hash_header(database.join("hash_config.k2d"))?;
+    let checksums = ShardChecksums::from_hash_header(database.join("hash_config.k2d"))?;
+    let hash_files = ChunkCatalog::scan_continuous(database, ChunkKind::Hash)?.paths();
+    let chtable = if args.memory_mapping {
+        CHTable::map_hash_files(hash_config, &hash_files, checksums.as_ref())?
+    } else {
+        CHTable::from_hash_files(hash_config, &hash_files, checksums.as_ref())?
+    };
+    let chunk_size = hash_config.hash_capacity;
+    let in_clade = |taxid: u64| taxonomy.is_a_ancestor_of_b(query, taxonomy.get_internal_id(taxid));
+
+    let mut contributions = Vec::new();
+    for library_file in &library_files {
+        let mut reader = FastaReader::from_path(library_file, 1)?;
+        read_parallel(
+            &mut reader,
+            args.num_threads,
+            &meros,
+            |seqs| {
+                let mut batch = Vec::new();
+                for seq in seqs {
+                    let Some(&taxid) = id_to_taxon_map.get(&seq.header.id) else {
+                        continue;
+                    };
+                    if !in_clade(taxid) {
+                        continue;
+                    }
+                    let (mut minimizers, mut clade_minimizers) = (0, 0);
+                    seq.body.apply_mut(|m_iter| {
+                        for (_, hash_key) in m_iter.by_ref() {
+                            minimizers += 1;
+                            let (idx, compacted) = hash_config.compact(hash_key);
+                            let stored = chtable.get_from_page(
+                                idx % chunk_size,
+                                compacted,
+                                idx / chunk_size,
+                            );
+                            if stored > 0 && taxonomy.is_a_ancestor_of_b(query, stored) {
+                                clade_minimizers += 1;
+                            }
+                        }
+                    });
+                    batch.push(Contribution {
+                        seqid: seq.header.id.clone(),
+                        taxid,
+                        minimizers,
+                        clade_minimizers,
+                    });
+                }
+                batch
+            },
+            |dataset| {
+                while let Some(data) = dataset.next() {
+                    contributions.extend(data.unwrap());
+                }
+            },
+        )?;
+    }
+    let clade_sequences = contributions.len();
+    if !args.all {
+        contributions.retain(|c| c.clade_minimizers > 0);
+    }
+    contributions.sort_by(|a, b| {
+        b.clade_minimizers
+            .cmp(&a.clade_minimizers)
+            .then_with(|| a.seqid.cmp(&b.seqid))
+    });
+
+    let mut writer: Box<dyn Write> = match &args.output {
+        Some(output) => Box::new(BufWriter::new(File::create(output)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    writeln!(
+        writer,
+        "accession\ttaxid\tname\tminimizers\tclade_minimizers"
+    )?;
+    for c in &contributions {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            accession(&c.seqid),
+            c.taxid,
+            report_name(taxonomy.name(taxonomy.get_internal_id(c.taxid))),
+            c.minimizers,
+            c.clade_minimizers
+        )?;
+    }
+    writer.flush()?;
+
+    info!(
+        "{} ({}): {} of {} library sequences in the clade contributed minimizers, took: {:?}",
+        taxonomy.name(query),
+        args.taxid,
+        contributions
+            .iter()
+            .filter(|c| c.clade_minimizers > 0)
+            .count(),
+        clade_sequences,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
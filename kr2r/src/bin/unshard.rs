@@ -0,0 +1,148 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::compact_hash::{read_page_from_file, shard_checksum, HashConfig, ShardChecksums};
+use kraken2_rs::error::{Kr2rError, Result};
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::info;
+
+/// kraken 2 还需要的数据库文件
+const KRAKEN2_FILES: [&str; 2] = ["opts.k2d", "taxo.k2d"];
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Merge the hash_*.k2d shards of a database back into a Kraken 2 hash.k2d",
+    long_about = "Merge the hash_*.k2d shards and hash_config.k2d of a database back into a single hash.k2d that the original Kraken 2 can load, the reverse of hashshard"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Directory for hash.k2d, opts.k2d, taxo.k2d and seqid2taxid.map (default: the database directory)
+    #[clap(long)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Skip checking each shard against the xxhash64 recorded in hash_config.k2d
+    #[clap(long, default_value_t = false)]
+    pub no_verify: bool,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let config = HashConfig::from_hash_header(args.database.join("hash_config.k2d"))?;
+    let checksums = ShardChecksums::from_hash_header(args.database.join("hash_config.k2d"))?;
+    let files = ChunkCatalog::scan_continuous(&args.database, ChunkKind::Hash)?.paths();
+    if files.len() != config.partition {
+        return Err(Kr2rError::Config(format!(
+            "found {} hash files, hash_config.k2d expects {}",
+            files.len(),
+            config.partition
+        )));
+    }
+
+    for name in KRAKEN2_FILES {
+        let source = args.database.join(name);
+        if !source.exists() {
+            return Err(Kr2rError::MissingIndex(source));
+        }
+    }
+
+    let output_dir = args.output_dir.as_ref().unwrap_or(&args.database);
+    create_dir_all(output_dir)?;
+    let index_file = output_dir.join("hash.k2d");
+    if index_file.exists() {
+        return Err(Kr2rError::Config(format!(
+            "{:?} already exists",
+            index_file
+        )));
+    }
+
+    info!("unshard start...");
+    let start = Instant::now();
+
+    let tmp_file = output_dir.join("hash.k2d.tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_file)?);
+    // kraken 2 的头部: capacity, size, key_bits, value_bits, size 在写完所有单元后补上
+    writer.write_all(&(config.capacity as u64).to_le_bytes())?;
+    writer.write_all(&0u64.to_le_bytes())?;
+    writer.write_all(&(32 - config.value_bits as u64).to_le_bytes())?;
+    writer.write_all(&(config.value_bits as u64).to_le_bytes())?;
+
+    let mut size = 0;
+    for (i, file) in files.iter().enumerate() {
+        let page_index = i + 1;
+        let page = read_page_from_file(file).map_err(|e| Kr2rError::index(file, e))?;
+        let expected = std::cmp::min(
+            config.hash_capacity,
+            config.capacity - i * config.hash_capacity,
+        );
+        if page.size != expected {
+            return Err(Kr2rError::chunk(
+                file,
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "holds {} cells, hash_config.k2d expects {}",
+                        page.size, expected
+                    ),
+                ),
+            ));
+        }
+        if let Some(checksums) = checksums.as_ref().filter(|_| !args.no_verify) {
+            checksums.verify(file, page_index, shard_checksum(page_index, &page.data))?;
+        }
+        // 与 read_page_data 相同, 单元按小端序存放
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                page.data.as_ptr() as *const u8,
+                std::mem::size_of_val(page.data.as_slice()),
+            )
+        };
+        writer.write_all(bytes)?;
+        size += page.data.iter().filter(|&&value| value != 0).count();
+        info!(
+            "merge {:?}/{}: duration: {:?}",
+            page_index,
+            config.partition,
+            start.elapsed()
+        );
+    }
+    let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(8))?;
+    file.write_all(&(size as u64).to_le_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_file, &index_file)?;
+
+    if output_dir != &args.database {
+        for name in KRAKEN2_FILES.iter().chain(["seqid2taxid.map"].iter()) {
+            let source = args.database.join(name);
+            if source.exists() {
+                fs::copy(&source, output_dir.join(name))
+                    .map_err(|e| Kr2rError::index(&source, e))?;
+            }
+        }
+    }
+
+    info!(
+        "{} cells in {:?}, unshard took: {:?}",
+        size,
+        index_file,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
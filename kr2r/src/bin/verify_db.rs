@@ -0,0 +1,296 @@
+use byteorder::{ByteOrder, LittleEndian};
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::checksums::{
+    checksum_bytes, read_checksums, write_checksums, FileChecksum, CHECKSUM_FILE,
+};
+use kraken2_rs::compact_hash::{self, Compact, HashConfig, ShardChecksums};
+use kraken2_rs::error::{Kr2rError, Result};
+use kraken2_rs::taxonomy::Taxonomy;
+use kraken2_rs::IndexOptions;
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tracing::{info, warn};
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Check that the hash_*.k2d shards of a database match hash_config.k2d and taxo.k2d",
+    long_about = "Check hash_config.k2d against the hash_*.k2d shards (shard count, file sizes, stored indexes and capacities, number of filled cells), check that every taxid stored in the table exists in taxo.k2d, compare the shards with the xxhash64 checksums stored in hash_config.k2d, and compare the checksums of the database files with checksums.txt written when the database was built"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Record the checksums in checksums.txt when all checks pass (for databases built before
+    /// checksums were written, or after a verified copy)
+    #[clap(long, value_parser, default_value_t = false)]
+    pub write_checksums: bool,
+}
+
+/// 检查中发现的问题, 缺少文件与内容错误分开, 决定退出码
+#[derive(Default)]
+struct Problems {
+    missing: Option<PathBuf>,
+    messages: Vec<String>,
+}
+
+impl Problems {
+    fn missing(&mut self, path: &Path) {
+        self.missing.get_or_insert_with(|| path.to_path_buf());
+        self.report(format!("{:?} is missing", path));
+    }
+
+    fn report(&mut self, message: String) {
+        println!("FAILED: {}", message);
+        self.messages.push(message);
+    }
+
+    fn into_result(self, database: &Path) -> Result<()> {
+        match self.missing {
+            _ if self.messages.is_empty() => Ok(()),
+            Some(path) => Err(Kr2rError::MissingIndex(path)),
+            None => Err(Kr2rError::CorruptChunk {
+                path: database.to_path_buf(),
+                source: io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} problems found", self.messages.len()),
+                ),
+            }),
+        }
+    }
+}
+
+/// 一个 shard 中已填充的单元数与存放了不存在的 taxid 的单元数
+fn scan_cells(cells: &[u8], value_mask: usize, node_count: Option<usize>) -> (usize, usize) {
+    cells
+        .par_chunks(4 << 16)
+        .map(|block| {
+            let mut filled = 0;
+            let mut invalid = 0;
+            for cell in block.chunks_exact(4).map(LittleEndian::read_u32) {
+                if cell == 0 {
+                    continue;
+                }
+                filled += 1;
+                let taxid = cell.right(value_mask) as usize;
+                if node_count.is_some_and(|count| taxid == 0 || taxid >= count) {
+                    invalid += 1;
+                }
+            }
+            (filled, invalid)
+        })
+        .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1))
+}
+
+/// 与 [`CHECKSUM_FILE`] 中记录的大小和 seahash 比较
+fn compare_checksum(
+    problems: &mut Problems,
+    recorded: Option<&BTreeMap<String, FileChecksum>>,
+    name: &str,
+    actual: FileChecksum,
+) -> &'static str {
+    let Some(recorded) = recorded else {
+        return "";
+    };
+    match recorded.get(name) {
+        Some(expected) if *expected == actual => ", checksum ok",
+        Some(expected) => {
+            problems.report(format!(
+                "{} has size {} and checksum {:016x}, {} records {} and {:016x}",
+                name, actual.size, actual.checksum, CHECKSUM_FILE, expected.size, expected.checksum
+            ));
+            ", checksum mismatch"
+        }
+        None => {
+            problems.report(format!("{} is not listed in {}", name, CHECKSUM_FILE));
+            ", no recorded checksum"
+        }
+    }
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let database = &args.database;
+    let start = Instant::now();
+    let mut problems = Problems::default();
+    let recorded = read_checksums(database)?;
+    if recorded.is_none() {
+        warn!(
+            "no {} in {:?}, checksums are not compared (--write-checksums records them)",
+            CHECKSUM_FILE, database
+        );
+    }
+
+    for name in ["opts.k2d", "taxo.k2d"] {
+        let path = database.join(name);
+        if path.exists() {
+            let actual = FileChecksum::of_file(&path)?;
+            let status = compare_checksum(&mut problems, recorded.as_ref(), name, actual);
+            println!("{}: {} bytes{}", name, actual.size, status);
+        }
+    }
+    if let Err(e) = IndexOptions::read_index_options(database.join("opts.k2d")) {
+        match Kr2rError::index(database.join("opts.k2d"), e) {
+            Kr2rError::MissingIndex(path) => problems.missing(&path),
+            e => problems.report(e.to_string()),
+        }
+    }
+    let taxonomy = match Taxonomy::from_file(database.join("taxo.k2d")) {
+        Ok(taxonomy) => Some(taxonomy),
+        Err(e) => {
+            match Kr2rError::index(database.join("taxo.k2d"), e) {
+                Kr2rError::MissingIndex(path) => problems.missing(&path),
+                e => problems.report(e.to_string()),
+            }
+            None
+        }
+    };
+    let node_count = taxonomy.as_ref().map(|taxonomy| taxonomy.node_count());
+
+    // 没有可用的 hash_config.k2d 时无法检查 shard
+    let config_file = database.join("hash_config.k2d");
+    let config = HashConfig::from_hash_header(&config_file)?;
+    let shard_checksums = ShardChecksums::from_hash_header(&config_file)?;
+    let actual = FileChecksum::of_file(&config_file)?;
+    let status = compare_checksum(&mut problems, recorded.as_ref(), "hash_config.k2d", actual);
+    println!(
+        "hash_config.k2d: {} shards, capacity {}, hash capacity {}, {} filled cells{}",
+        config.partition, config.capacity, config.hash_capacity, config.size, status
+    );
+    if config.hash_capacity == 0
+        || config.partition != config.capacity.div_ceil(config.hash_capacity)
+    {
+        problems.report(format!(
+            "hash_config.k2d: {} shards do not hold capacity {} with hash capacity {}",
+            config.partition, config.capacity, config.hash_capacity
+        ));
+        return problems.into_result(database);
+    }
+
+    let catalog = ChunkCatalog::scan(database, ChunkKind::Hash)?;
+    for index in catalog
+        .indices()
+        .into_iter()
+        .filter(|&i| i > config.partition)
+    {
+        problems.report(format!(
+            "{} is not part of the {} shards in hash_config.k2d",
+            catalog.file_name(index),
+            config.partition
+        ));
+    }
+    let shards = catalog.by_index();
+    let mut filled_cells = 0;
+    for index in 1..=config.partition {
+        let name = catalog.file_name(index);
+        let Some(path) = shards.get(&index) else {
+            problems.missing(&database.join(&name));
+            continue;
+        };
+        let shard_start = Instant::now();
+        let mmap = unsafe { Mmap::map(&File::open(path)?)? };
+        let actual = FileChecksum {
+            size: mmap.len() as u64,
+            checksum: checksum_bytes(&mmap),
+        };
+        let expected =
+            config.capacity.min(index * config.hash_capacity) - (index - 1) * config.hash_capacity;
+        if mmap.len() < 16 {
+            problems.report(format!("{} is too short for the shard header", name));
+            continue;
+        }
+        let stored_index = LittleEndian::read_u64(&mmap[0..8]) as usize;
+        let stored_capacity = LittleEndian::read_u64(&mmap[8..16]) as usize;
+        if stored_index != index {
+            problems.report(format!("{} stores shard index {}", name, stored_index));
+        }
+        if stored_capacity != expected {
+            problems.report(format!(
+                "{} stores capacity {}, hash_config.k2d expects {}",
+                name, stored_capacity, expected
+            ));
+        }
+        if mmap.len() != 16 + expected * 4 {
+            problems.report(format!(
+                "{} has {} bytes, {} cells need {}",
+                name,
+                mmap.len(),
+                expected,
+                16 + expected * 4
+            ));
+        }
+        let cells = &mmap[16..16 + (mmap.len() - 16).min(expected * 4) / 4 * 4];
+        let (filled, invalid) = scan_cells(cells, config.value_mask, node_count);
+        filled_cells += filled;
+        if invalid > 0 {
+            problems.report(format!(
+                "{} has {} cells with taxids missing from taxo.k2d",
+                name, invalid
+            ));
+        }
+        let status = compare_checksum(&mut problems, recorded.as_ref(), &name, actual);
+        // hash_config.k2d 中记录的 xxhash64, 与加载时的检查相同
+        let embedded = match shard_checksums.as_ref().map(|c| c.get(index)) {
+            None => "",
+            Some(None) => {
+                problems.report(format!("hash_config.k2d records no checksum for {}", name));
+                ", no embedded checksum"
+            }
+            Some(Some(expected)) => {
+                let checksum = compact_hash::checksum_bytes(&mmap);
+                if checksum == expected {
+                    ", xxhash64 ok"
+                } else {
+                    problems.report(format!(
+                        "{} has xxhash64 {:016x}, hash_config.k2d records {:016x}",
+                        name, checksum, expected
+                    ));
+                    ", xxhash64 mismatch"
+                }
+            }
+        };
+        println!(
+            "{}: capacity {}, {} filled cells{}{}",
+            name, stored_capacity, filled, status, embedded
+        );
+        info!(
+            "verify {}/{}: duration: {:?}",
+            index,
+            config.partition,
+            shard_start.elapsed()
+        );
+    }
+    if filled_cells != config.size && problems.missing.is_none() {
+        problems.report(format!(
+            "the shards hold {} filled cells, hash_config.k2d records {}",
+            filled_cells, config.size
+        ));
+    }
+
+    let failed = problems.messages.len();
+    problems.into_result(database)?;
+    if args.write_checksums && failed == 0 {
+        write_checksums(database)?;
+        println!("checksums written to {:?}", database.join(CHECKSUM_FILE));
+    }
+    println!("database ok");
+    info!("verify-db took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
@@ -0,0 +1,180 @@
+use clap::Parser;
+use kraken2_rs::args::{parse_database, parse_size};
+use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+use kraken2_rs::utils::format_bytes;
+#[cfg(unix)]
+use memmap2::Advice;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::info;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Read the database files into the page cache before a batch of jobs",
+    long_about = "Touch every page of taxo.k2d, opts.k2d, hash_config.k2d and the hash_*.k2d shards in order, so that the jobs that follow (especially with --memory-mapping) find the database in the page cache. With --lock the pages are also locked in memory (like vmtouch -l) and the command keeps running to hold them until it is stopped"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Lock the touched files in memory and keep running until stopped (Ctrl-C) to hold them.
+    #[clap(long, value_parser, default_value_t = false)]
+    pub lock: bool,
+
+    /// Lock only the files that fit in this much memory (e.g. '32G'), in database order; implies --lock.
+    #[clap(long, value_parser = parse_size)]
+    pub lock_budget: Option<usize>,
+}
+
+fn page_size() -> usize {
+    #[cfg(unix)]
+    {
+        let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        if size > 0 {
+            return size as usize;
+        }
+    }
+    4096
+}
+
+/// 按顺序读取每一页的一个字节, 使整个文件进入 page cache
+fn touch_pages(mmap: &Mmap, page_size: usize) -> u8 {
+    #[cfg(unix)]
+    let _ = mmap.advise(Advice::Sequential);
+    let mut checksum = 0u8;
+    for offset in (0..mmap.len()).step_by(page_size) {
+        checksum ^= mmap[offset];
+    }
+    std::hint::black_box(checksum)
+}
+
+#[cfg(unix)]
+fn lock_pages(mmap: &Mmap) -> Result<()> {
+    mmap.lock()
+}
+
+#[cfg(not(unix))]
+fn lock_pages(_mmap: &Mmap) -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "locking pages is only supported on unix systems",
+    ))
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let database = &args.database;
+    let mut files: Vec<PathBuf> = ["taxo.k2d", "opts.k2d", "hash_config.k2d"]
+        .iter()
+        .map(|name| database.join(name))
+        .filter(|path| path.exists())
+        .collect();
+    let hash_files = ChunkCatalog::scan_continuous(database, ChunkKind::Hash)?.paths();
+    if hash_files.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no hash_*.k2d files found in {:?}", database),
+        ));
+    }
+    files.extend(hash_files);
+
+    let sizes = files
+        .iter()
+        .map(|file| Ok(std::fs::metadata(file)?.len() as usize))
+        .collect::<Result<Vec<usize>>>()?;
+    let total: usize = sizes.iter().sum();
+    let lock = args.lock || args.lock_budget.is_some();
+    let mut lock_budget = args.lock_budget.unwrap_or(usize::MAX);
+    let page_size = page_size();
+
+    info!(
+        "warmup start: {} files, {}",
+        files.len(),
+        format_bytes(total as f64)
+    );
+    let start = Instant::now();
+    let mut done = 0;
+    let mut locked = Vec::new();
+    let mut locked_bytes = 0;
+    for (index, (file, &size)) in files.iter().zip(sizes.iter()).enumerate() {
+        let name = file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if size == 0 {
+            continue;
+        }
+        let file_start = Instant::now();
+        let mmap = unsafe { Mmap::map(&File::open(file)?)? };
+        touch_pages(&mmap, page_size);
+        done += size;
+        let elapsed = file_start.elapsed();
+
+        let mut status = String::new();
+        if lock {
+            if size > lock_budget {
+                status = ", not locked (over the budget)".to_string();
+            } else {
+                match lock_pages(&mmap) {
+                    Ok(()) => {
+                        lock_budget -= size;
+                        locked_bytes += size;
+                        status = ", locked".to_string();
+                        locked.push(mmap);
+                    }
+                    Err(e) => {
+                        status = format!(", not locked ({}, check `ulimit -l`)", e);
+                    }
+                }
+            }
+        }
+        info!(
+            "[{}/{}] {} {} in {:?} ({}/s){}, {:.1}% done",
+            index + 1,
+            files.len(),
+            name,
+            format_bytes(size as f64),
+            elapsed,
+            format_bytes(size as f64 / elapsed.as_secs_f64().max(1e-9)),
+            status,
+            done as f64 * 100.0 / total.max(1) as f64
+        );
+    }
+    info!(
+        "warmup took: {:?}, {} touched",
+        start.elapsed(),
+        format_bytes(done as f64)
+    );
+
+    if lock {
+        if locked.is_empty() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "no file could be locked in memory",
+            ));
+        }
+        info!(
+            "{} files ({}) locked in memory, stop this command (Ctrl-C) to release them",
+            locked.len(),
+            format_bytes(locked_bytes as f64)
+        );
+        loop {
+            std::thread::park();
+        }
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
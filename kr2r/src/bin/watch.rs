@@ -0,0 +1,366 @@
+use clap::Parser;
+use kraken2_rs::args::parse_database;
+use kraken2_rs::classifier::Classifier;
+use kraken2_rs::inputs::{find_seq_files, is_seq_file};
+use kraken2_rs::readcounts::TaxonCounters;
+use kraken2_rs::report::report_kraken_style;
+use notify::{Config, Event, PollWatcher, RecursiveMode, Watcher};
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use seqkmer::{RecordReader, SeqRecord};
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+/// 每批分类的 reads 数
+const BATCH_SIZE: usize = 10_000;
+
+/// 累计的 kreport, 每个文件分类后整体替换
+const CUMULATIVE_REPORT: &str = "cumulative.kreport2";
+
+/// 所有 reads 的标准输出, 按文件分类的顺序追加
+const READ_OUTPUT: &str = "output.txt";
+
+/// 每个已分类文件一行, 带分类后的累计数
+const FILES_TABLE: &str = "files.tsv";
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Classify FASTQ files as they appear in a sequencing output directory",
+    long_about = "Watch a directory (for example the fastq_pass directory of a live Nanopore run) and its subdirectories, classify every FASTA/FASTQ file in it once it stops changing, and update a cumulative kreport after each file"
+)]
+pub struct Args {
+    /// database hash chunk directory and other files, or a name registered with `db add`
+    #[arg(long = "db", value_parser = parse_database, required = true)]
+    pub database: PathBuf,
+
+    /// Directory to watch; files already in it are classified first
+    #[clap(long, value_parser, required = true)]
+    pub input_dir: PathBuf,
+
+    /// Directory for cumulative.kreport2, output.txt (per-read output) and files.tsv
+    #[clap(long, value_parser, required = true)]
+    pub output_dir: PathBuf,
+
+    /// Seconds a file must go unmodified before it is classified, so files still being written are skipped
+    #[clap(long, value_parser, default_value_t = 5)]
+    pub settle_secs: u64,
+
+    /// Stop after this many seconds without a new file, default: watch until interrupted
+    #[clap(long, value_parser)]
+    pub idle_timeout: Option<u64>,
+
+    /// Scan the directory every this many seconds instead of waiting for file system events,
+    /// for network file systems that don't deliver them
+    #[clap(long, value_parser)]
+    pub poll_interval: Option<u64>,
+
+    /// Confidence score threshold
+    #[clap(
+        short = 'T',
+        long = "confidence-threshold",
+        value_parser,
+        default_value_t = 0.0
+    )]
+    pub confidence_threshold: f64,
+
+    /// The minimum number of hit groups needed for a call.
+    #[clap(
+        short = 'g',
+        long = "minimum-hit-groups",
+        value_parser,
+        default_value_t = 2
+    )]
+    pub minimum_hit_groups: usize,
+
+    /// The number of threads to use.
+    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    pub num_threads: usize,
+
+    /// Memory-map taxo.k2d and the hash_*.k2d files instead of reading them into memory
+    #[clap(long, value_parser, default_value_t = false)]
+    pub memory_mapping: bool,
+}
+
+/// 已分类的 reads 数与各 taxon (internal id) 的 reads 数
+#[derive(Default)]
+struct Totals {
+    seqs: u64,
+    classified: u64,
+    counters: TaxonCounters,
+}
+
+impl Totals {
+    fn merge(&mut self, other: Totals) {
+        self.seqs += other.seqs;
+        self.classified += other.classified;
+        for (taxid, counter) in other.counters {
+            // 没有 k-mer 数据, 合并不会失败
+            let _ = self.counters.entry(taxid).or_default().merge(&counter);
+        }
+    }
+}
+
+struct Watch {
+    classifier: Classifier,
+    pool: ThreadPool,
+    output: BufWriter<File>,
+    files: BufWriter<File>,
+    totals: Totals,
+    skipped: usize,
+}
+
+impl Watch {
+    /// 分类一个文件并写出每个 read 的结果; 读取失败时撤回已写出的部分, 不计入累计数
+    fn classify_file(&mut self, path: &Path) -> Result<Totals> {
+        self.output.flush()?;
+        let written = self.output.get_ref().metadata()?.len();
+        let result = self.classify_records(path);
+        if result.is_err() {
+            self.output.flush()?;
+            self.output.get_ref().set_len(written)?;
+        }
+        result
+    }
+
+    fn classify_records(&mut self, path: &Path) -> Result<Totals> {
+        let mut reader = RecordReader::from_path(path)?;
+        let mut totals = Totals::default();
+        let mut batch: Vec<SeqRecord> = Vec::with_capacity(BATCH_SIZE);
+        loop {
+            batch.clear();
+            while batch.len() < BATCH_SIZE {
+                match reader.next_record()? {
+                    Some(record) => batch.push(record),
+                    None => break,
+                }
+            }
+            if batch.is_empty() {
+                return Ok(totals);
+            }
+            let classifier = &self.classifier;
+            let results: Vec<_> = self.pool.install(|| {
+                batch
+                    .par_iter()
+                    .map(|record| classifier.classify_read(&record.seq))
+                    .collect()
+            });
+            let taxonomy = classifier.taxonomy();
+            for (record, result) in batch.iter().zip(results) {
+                let header = String::from_utf8_lossy(&record.header);
+                writeln!(
+                    self.output,
+                    "{}\t{}\t{}\t{}\t{}",
+                    if result.is_classified() { "C" } else { "U" },
+                    header.split_whitespace().next().unwrap_or_default(),
+                    result.taxid,
+                    record.seq.len(),
+                    result.hit_string
+                )?;
+                totals.seqs += 1;
+                if result.is_classified() {
+                    totals.classified += 1;
+                    totals
+                        .counters
+                        .entry(taxonomy.get_internal_id(result.taxid) as u64)
+                        .or_default()
+                        .increment_read_count();
+                }
+            }
+        }
+    }
+
+    /// 替换累计的 kreport, 先写临时文件再改名, 读取报告的程序不会读到写了一半的文件
+    fn write_report(&self, dir: &Path) -> Result<()> {
+        let tmp = dir.join(format!("{}.tmp", CUMULATIVE_REPORT));
+        report_kraken_style(
+            &tmp,
+            false,
+            false,
+            self.classifier.taxonomy(),
+            &self.totals.counters,
+            self.totals.seqs,
+            self.totals.seqs - self.totals.classified,
+            None,
+        )?;
+        fs::rename(tmp, dir.join(CUMULATIVE_REPORT))
+    }
+
+    fn process(&mut self, path: &Path, dir: &Path) -> Result<()> {
+        let start = Instant::now();
+        let file = match self.classify_file(path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("skip {:?}: {}", path, e);
+                self.skipped += 1;
+                return Ok(());
+            }
+        };
+        let (seqs, classified) = (file.seqs, file.classified);
+        self.totals.merge(file);
+        self.output.flush()?;
+        self.write_report(dir)?;
+        writeln!(
+            self.files,
+            "{}\t{}\t{}\t{}\t{}",
+            path.display(),
+            seqs,
+            classified,
+            self.totals.seqs,
+            self.totals.classified
+        )?;
+        self.files.flush()?;
+        info!(
+            "{:?}: {} reads, {} classified, {} reads in total, took: {:?}",
+            path,
+            seqs,
+            classified,
+            self.totals.seqs,
+            start.elapsed()
+        );
+        Ok(())
+    }
+}
+
+/// 文件最后修改后经过了 `settle`
+fn is_settled(path: &Path, settle: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .is_ok_and(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|age| age >= settle)
+        })
+}
+
+pub fn run(args: Args) -> Result<()> {
+    if !args.input_dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("input directory {:?} not found", args.input_dir),
+        ));
+    }
+    fs::create_dir_all(&args.output_dir)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.num_threads)
+        .build()
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let start = Instant::now();
+    let classifier = if args.memory_mapping {
+        Classifier::map_database(&args.database)?
+    } else {
+        Classifier::from_database(&args.database)?
+    }
+    .with_confidence_threshold(args.confidence_threshold)
+    .with_minimum_hit_groups(args.minimum_hit_groups);
+    info!("database loaded in {:?}", start.elapsed());
+
+    // 事件只用来发现文件, 文件是否写完由修改时间判断
+    let (tx, rx) = channel::<notify::Result<Event>>();
+    let mut watcher: Box<dyn Watcher> = match args.poll_interval {
+        Some(secs) => Box::new(
+            PollWatcher::new(
+                tx,
+                Config::default().with_poll_interval(Duration::from_secs(secs.max(1))),
+            )
+            .map_err(io::Error::other)?,
+        ),
+        None => Box::new(notify::recommended_watcher(tx).map_err(io::Error::other)?),
+    };
+    watcher
+        .watch(&args.input_dir, RecursiveMode::Recursive)
+        .map_err(io::Error::other)?;
+
+    let mut files = BufWriter::new(File::create(args.output_dir.join(FILES_TABLE))?);
+    writeln!(
+        files,
+        "file\treads\tclassified\ttotal_reads\ttotal_classified"
+    )?;
+    // 追加模式下, 撤回失败文件的输出 (截断) 之后的写入仍从文件末尾开始
+    File::create(args.output_dir.join(READ_OUTPUT))?;
+    let output = OpenOptions::new()
+        .append(true)
+        .open(args.output_dir.join(READ_OUTPUT))?;
+    let mut watch = Watch {
+        classifier,
+        pool,
+        output: BufWriter::new(output),
+        files,
+        totals: Totals::default(),
+        skipped: 0,
+    };
+    watch.write_report(&args.output_dir)?;
+
+    let settle = Duration::from_secs(args.settle_secs);
+    let idle_timeout = args.idle_timeout.map(Duration::from_secs);
+    let mut pending: BTreeSet<PathBuf> = find_seq_files(&args.input_dir)
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    let mut done: HashSet<PathBuf> = HashSet::new();
+    let mut last_file = Instant::now();
+    info!(
+        "watching {:?}, {} files already there",
+        args.input_dir,
+        pending.len()
+    );
+    loop {
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(Ok(event)) => {
+                for path in event.paths {
+                    if is_seq_file(&path) && !done.contains(&path) {
+                        pending.insert(path);
+                    }
+                }
+            }
+            Ok(Err(e)) => warn!("watch error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        // 改名或删除的文件不再处理
+        pending.retain(|path| path.is_file());
+        let ready: Vec<PathBuf> = pending
+            .iter()
+            .filter(|path| is_settled(path, settle))
+            .cloned()
+            .collect();
+        for path in ready {
+            pending.remove(&path);
+            watch.process(&path, &args.output_dir)?;
+            done.insert(path);
+            last_file = Instant::now();
+        }
+        // 等待写完的文件不算空闲
+        if !pending.is_empty() {
+            last_file = Instant::now();
+        }
+        if idle_timeout.is_some_and(|timeout| last_file.elapsed() >= timeout) {
+            break;
+        }
+    }
+    info!(
+        "{} files ({} skipped), {} reads, {} classified, took: {:?}",
+        done.len(),
+        watch.skipped,
+        watch.totals.seqs,
+        watch.totals.classified,
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    kraken2_rs::logging::init_default_logging();
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+        std::process::exit(kraken2_rs::error::exit_code(&e) as i32);
+    }
+}
@@ -0,0 +1,312 @@
+use crate::compact_hash::{
+    read_row_header, write_row_header, ChunkRecord, RecordReader, Row, ROW_FILE_HEADER_SIZE,
+};
+use crate::error::{Kr2rError, Result};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// 合并有序段时每段每次读取的 Row 数
+const MERGE_BATCH_ROWS: usize = 16 * 1024;
+
+/// 排序的顺序: 先按 seq_id, 同一条序列的 Row 再按 kmer_id 与 value, 结果是确定的, 可以直接比较
+fn sort_key(row: &Row) -> (u32, u64, u32) {
+    (row.seq_id, row.kmer_id, row.value)
+}
+
+/// [`sort_bin_file`] 的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortStats {
+    pub rows: u64,
+    /// 合并的有序段数, 1 表示在内存中排序
+    pub runs: usize,
+}
+
+fn write_rows<W: Write>(writer: &mut W, rows: &[Row]) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(rows.len() * Row::SIZE);
+    for row in rows {
+        row.extend_bytes(&mut bytes);
+    }
+    writer.write_all(&bytes)
+}
+
+/// 长度不是整条记录的文件是残缺的, 排序会丢掉末尾的字节, 因此报错
+fn check_length(path: &Path) -> Result<()> {
+    let len = fs::metadata(path)
+        .map_err(|e| Kr2rError::chunk(path, e))?
+        .len()
+        .saturating_sub(ROW_FILE_HEADER_SIZE);
+    if len % Row::SIZE as u64 != 0 {
+        return Err(Kr2rError::chunk(
+            path,
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} bytes is not a whole number of records", len),
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// 打开 .bin 文件并读过文件头
+fn open_bin(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).map_err(|e| Kr2rError::chunk(path, e))?;
+    let mut reader = BufReader::new(file);
+    read_row_header(&mut reader).map_err(|e| Kr2rError::chunk(path, e))?;
+    Ok(reader)
+}
+
+fn temp_path(path: &Path, dir: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    dir.join(format!("{}.{}", name, suffix))
+}
+
+/// 按 seq_id 排序 annotate 写出的 sample_file_*.bin, 之后同一条序列的 Row 在文件中连续,
+/// 可以用 [`BinGroups`] 逐条读取.
+///
+/// 文件分段读入, 每段最多 `max_rows` 条, 排序后写到 `tmp_dir` 中, 再合并为一个文件替换原文件;
+/// 文件不超过一段时直接在内存中排序. 内存约为 `max_rows * 16` 字节, 临时文件约为原文件大小
+///
+/// ```
+/// use kraken2_rs::binsort::{is_sorted_bin, sort_bin_file, BinGroups};
+/// use kraken2_rs::compact_hash::{write_row_header, ChunkRecord, Row};
+///
+/// let dir = std::env::temp_dir().join(format!("binsort_doctest_{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let path = dir.join("sample_file_1_0.bin");
+/// let mut bytes = Vec::new();
+/// write_row_header(&mut bytes).unwrap();
+/// for (seq_id, kmer_id) in [(3, 7), (1, 5), (3, 2), (2, 9), (1, 1)] {
+///     Row::new(11676, seq_id, kmer_id).extend_bytes(&mut bytes);
+/// }
+/// std::fs::write(&path, bytes).unwrap();
+///
+/// // 每段 2 条, 需要合并 3 个有序段
+/// let stats = sort_bin_file(&path, 2, &dir).unwrap();
+/// assert_eq!((stats.rows, stats.runs), (5, 3));
+/// assert!(is_sorted_bin(&path).unwrap());
+///
+/// let groups: Vec<(u32, Vec<u64>)> = BinGroups::open(&path)
+///     .unwrap()
+///     .map(|group| {
+///         let (seq_id, rows) = group.unwrap();
+///         (seq_id, rows.iter().map(|row| row.kmer_id).collect())
+///     })
+///     .collect();
+/// assert_eq!(groups, vec![(1, vec![1, 5]), (2, vec![9]), (3, vec![2, 7])]);
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn sort_bin_file(path: &Path, max_rows: usize, tmp_dir: &Path) -> Result<SortStats> {
+    let file = open_bin(path)?;
+    check_length(path)?;
+    let max_rows = max_rows.max(1);
+    let mut reader = RecordReader::<_, Row>::new(file, max_rows.min(1 << 16));
+
+    let mut run_files = Vec::new();
+    let mut rows: Vec<Row> = Vec::new();
+    let mut total = 0u64;
+    let result = (|| -> Result<usize> {
+        while let Some(batch) = reader.next_batch().map_err(|e| Kr2rError::chunk(path, e))? {
+            total += batch.len() as u64;
+            rows.extend(batch);
+            if rows.len() >= max_rows {
+                let rest = rows.split_off(max_rows);
+                rows.sort_unstable_by_key(sort_key);
+                let run_file = temp_path(path, tmp_dir, &format!("run{}", run_files.len()));
+                run_files.push(run_file.clone());
+                let mut writer = BufWriter::new(File::create(&run_file)?);
+                write_rows(&mut writer, &rows)?;
+                writer.flush()?;
+                rows = rest;
+            }
+        }
+        rows.sort_unstable_by_key(sort_key);
+        let runs = run_files.len() + usize::from(run_files.is_empty() || !rows.is_empty());
+
+        let sorted_file = temp_path(path, tmp_dir, "sorted");
+        let mut writer = BufWriter::new(File::create(&sorted_file)?);
+        write_row_header(&mut writer)?;
+        if run_files.is_empty() {
+            write_rows(&mut writer, &rows)?;
+        } else {
+            merge_runs(&run_files, std::mem::take(&mut rows), &mut writer)?;
+        }
+        // 文件在改名前关闭
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        if fs::rename(&sorted_file, path).is_err() {
+            // tmp_dir 与原文件不在同一个文件系统
+            fs::copy(&sorted_file, path)?;
+            fs::remove_file(&sorted_file)?;
+        }
+        Ok(runs)
+    })();
+    for run_file in &run_files {
+        let _ = fs::remove_file(run_file);
+    }
+    Ok(SortStats {
+        rows: total,
+        runs: result?,
+    })
+}
+
+/// 合并临时文件中的有序段与内存中剩下的一段 (已排序)
+fn merge_runs<W: Write>(run_files: &[PathBuf], last: Vec<Row>, writer: &mut W) -> Result<()> {
+    let mut sources: Vec<Box<dyn Iterator<Item = io::Result<Row>>>> = Vec::new();
+    for run_file in run_files {
+        let reader =
+            RecordReader::<_, Row>::new(BufReader::new(File::open(run_file)?), MERGE_BATCH_ROWS);
+        sources.push(Box::new(reader.flat_map(|batch| match batch {
+            Ok(rows) => rows.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        })));
+    }
+    sources.push(Box::new(last.into_iter().map(Ok)));
+
+    let mut heap = BinaryHeap::new();
+    for (i, source) in sources.iter_mut().enumerate() {
+        if let Some(row) = source.next().transpose()? {
+            heap.push(Reverse((sort_key(&row), i)));
+        }
+    }
+    let mut out = Vec::with_capacity(MERGE_BATCH_ROWS);
+    while let Some(Reverse(((seq_id, kmer_id, value), i))) = heap.pop() {
+        out.push(Row::new(value, seq_id, kmer_id));
+        if out.len() == MERGE_BATCH_ROWS {
+            write_rows(writer, &out)?;
+            out.clear();
+        }
+        if let Some(row) = sources[i].next().transpose()? {
+            heap.push(Reverse((sort_key(&row), i)));
+        }
+    }
+    write_rows(writer, &out)?;
+    Ok(())
+}
+
+/// 文件是否已按 [`sort_bin_file`] 的顺序排序
+pub fn is_sorted_bin(path: &Path) -> Result<bool> {
+    let file = open_bin(path)?;
+    check_length(path)?;
+    let reader = RecordReader::<_, Row>::new(file, MERGE_BATCH_ROWS);
+    let mut previous = None;
+    for batch in reader {
+        for row in batch.map_err(|e| Kr2rError::chunk(path, e))? {
+            let key = sort_key(&row);
+            if previous.is_some_and(|previous| previous > key) {
+                return Ok(false);
+            }
+            previous = Some(key);
+        }
+    }
+    Ok(true)
+}
+
+/// 逐条序列读取已排序的 .bin 文件, 每次返回一个 seq_id 的全部 Row, 内存只与最长的一组有关
+pub struct BinGroups<R: Read> {
+    reader: RecordReader<R, Row>,
+    batch: std::vec::IntoIter<Row>,
+    /// 下一组的第一条
+    pending: Option<Row>,
+}
+
+impl BinGroups<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self::new(open_bin(path)?))
+    }
+}
+
+impl<R: Read> BinGroups<R> {
+    /// `reader` 已读过 .bin 文件头
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: RecordReader::new(reader, MERGE_BATCH_ROWS),
+            batch: Vec::new().into_iter(),
+            pending: None,
+        }
+    }
+
+    fn next_row(&mut self) -> io::Result<Option<Row>> {
+        loop {
+            if let Some(row) = self.batch.next() {
+                return Ok(Some(row));
+            }
+            match self.reader.next_batch()? {
+                Some(batch) => self.batch = batch.into_iter(),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for BinGroups<R> {
+    type Item = io::Result<(u32, Vec<Row>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = match self.pending.take() {
+            Some(row) => row,
+            None => match self.next_row() {
+                Ok(Some(row)) => row,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            },
+        };
+        let seq_id = first.seq_id;
+        let mut rows = vec![first];
+        loop {
+            match self.next_row() {
+                Ok(Some(row)) if row.seq_id == seq_id => rows.push(row),
+                Ok(Some(row)) => {
+                    self.pending = Some(row);
+                    break;
+                }
+                Ok(None) => break,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok((seq_id, rows)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact_hash::ROW_FILE_MAGIC;
+    use crate::error::EXIT_CORRUPT_CHUNK;
+
+    fn bin_file(name: &str, header: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("kr2r_binsort_{}_{}.bin", name, std::process::id()));
+        let mut bytes = header.to_vec();
+        Row::new(1, 2, 1 << 32).extend_bytes(&mut bytes);
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn open_checks_the_header() {
+        let current = bin_file("current", ROW_FILE_MAGIC);
+        let rows: Vec<Row> = BinGroups::open(&current)
+            .unwrap()
+            .flat_map(|group| group.unwrap().1)
+            .collect();
+        assert_eq!(rows, vec![Row::new(1, 2, 1 << 32)]);
+        fs::remove_file(&current).unwrap();
+
+        let mut newer = *ROW_FILE_MAGIC;
+        newer[7] += 1;
+        // 版本 1 的文件没有文件头
+        for (name, header) in [("old", &[][..]), ("newer", &newer[..])] {
+            let path = bin_file(name, header);
+            let err = BinGroups::open(&path).err().unwrap();
+            assert_eq!(err.exit_code(), EXIT_CORRUPT_CHUNK, "{}", err);
+            let err = sort_bin_file(&path, 16, &std::env::temp_dir()).unwrap_err();
+            assert_eq!(err.exit_code(), EXIT_CORRUPT_CHUNK, "{}", err);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
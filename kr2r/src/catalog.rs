@@ -0,0 +1,483 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Result};
+use std::path::{Path, PathBuf};
+
+/// 数据库目录与 chunk 目录中按编号命名的文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkKind {
+    /// 数据库的 hash 分片 hash_{index}.k2d
+    Hash,
+    /// 构建数据库时的临时文件 chunk_{index}.k2
+    DbChunk,
+    /// splitr 的输出 sample_{index}.k2
+    Sample,
+    /// splitr 的输出 sample_id_{index}.map
+    SampleId,
+    /// annotate 的输出 sample_file_{index}_{sample}.bin
+    SampleBin,
+}
+
+impl ChunkKind {
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            ChunkKind::Hash => "hash",
+            ChunkKind::DbChunk => "chunk",
+            ChunkKind::Sample => "sample",
+            ChunkKind::SampleId => "sample_id",
+            ChunkKind::SampleBin => "sample_file",
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            ChunkKind::Hash => ".k2d",
+            ChunkKind::DbChunk | ChunkKind::Sample => ".k2",
+            ChunkKind::SampleId => ".map",
+            ChunkKind::SampleBin => ".bin",
+        }
+    }
+
+    /// 文件名是否带有第二个编号
+    fn has_sample(&self) -> bool {
+        *self == ChunkKind::SampleBin
+    }
+
+    /// 解析文件名中的编号, 文件名不属于该种类时返回 None
+    ///
+    /// ```
+    /// use kraken2_rs::catalog::ChunkKind;
+    ///
+    /// assert_eq!(ChunkKind::Hash.parse("hash_3.k2d"), Some((3, None)));
+    /// assert_eq!(ChunkKind::Hash.parse("hash_config.k2d"), None);
+    /// assert_eq!(ChunkKind::Sample.parse("sample_id_1.map"), None);
+    /// assert_eq!(ChunkKind::SampleBin.parse("sample_file_2_7.bin"), Some((2, Some(7))));
+    /// assert_eq!(ChunkKind::SampleBin.parse("sample_file_2.bin"), None);
+    /// ```
+    pub fn parse(&self, file_name: &str) -> Option<(usize, Option<usize>)> {
+        let numbers = file_name
+            .strip_prefix(self.prefix())?
+            .strip_prefix('_')?
+            .strip_suffix(self.suffix())?;
+        let parse_number = |s: &str| {
+            if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+                s.parse::<usize>().ok()
+            } else {
+                None
+            }
+        };
+        if self.has_sample() {
+            let (index, sample) = numbers.split_once('_')?;
+            Some((parse_number(index)?, Some(parse_number(sample)?)))
+        } else {
+            Some((parse_number(numbers)?, None))
+        }
+    }
+}
+
+impl fmt::Display for ChunkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.has_sample() {
+            write!(f, "{}_<n>_<m>{}", self.prefix(), self.suffix())
+        } else {
+            write!(f, "{}_<n>{}", self.prefix(), self.suffix())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkEntry {
+    pub kind: ChunkKind,
+    /// 文件名中的第一个编号, 从 1 开始
+    pub index: usize,
+    /// sample_file_{index}_{sample}.bin 的第二个编号
+    pub sample: Option<usize>,
+    pub path: PathBuf,
+}
+
+/// 目录中某一种编号文件的列表, 按 (index, sample) 排序
+///
+/// ```
+/// use kraken2_rs::catalog::{ChunkCatalog, ChunkKind};
+///
+/// let dir = std::env::temp_dir().join(format!("kr2r_catalog_{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// for name in ["hash_2.k2d", "hash_1.k2d", "hash_config.k2d", "sample_file_2_0.bin", "sample_file_1_1.bin", "sample_file_1_0.bin"] {
+///     std::fs::write(dir.join(name), b"").unwrap();
+/// }
+///
+/// let hash = ChunkCatalog::scan(&dir, ChunkKind::Hash).unwrap();
+/// assert_eq!(hash.indices(), vec![1, 2]);
+/// assert!(hash.check_continuous().is_ok());
+/// assert_eq!(hash.paths()[0], dir.join("hash_1.k2d"));
+///
+/// let bins = ChunkCatalog::scan(&dir, ChunkKind::SampleBin).unwrap();
+/// assert_eq!(bins.len(), 3);
+/// assert_eq!(bins.entries()[1].sample, Some(1));
+/// assert_eq!(bins.group_by_index()[&1].len(), 2);
+///
+/// std::fs::remove_file(dir.join("hash_1.k2d")).unwrap();
+/// let hash = ChunkCatalog::scan(&dir, ChunkKind::Hash).unwrap();
+/// assert_eq!(hash.missing_indices(), vec![1]);
+/// let err = hash.check_continuous().unwrap_err();
+/// assert!(err.to_string().contains("missing hash_1.k2d"));
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChunkCatalog {
+    kind: ChunkKind,
+    directory: PathBuf,
+    entries: Vec<ChunkEntry>,
+}
+
+impl ChunkCatalog {
+    pub fn scan<P: AsRef<Path>>(directory: P, kind: ChunkKind) -> Result<Self> {
+        let directory = directory.as_ref();
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if let Some((index, sample)) = kind.parse(file_name) {
+                entries.push(ChunkEntry {
+                    kind,
+                    index,
+                    sample,
+                    path,
+                });
+            }
+        }
+        entries.sort_by_key(|entry| (entry.index, entry.sample));
+
+        Ok(Self {
+            kind,
+            directory: directory.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// 扫描目录并检查编号是否从 1 开始连续
+    pub fn scan_continuous<P: AsRef<Path>>(directory: P, kind: ChunkKind) -> Result<Self> {
+        let catalog = Self::scan(directory, kind)?;
+        catalog.check_continuous()?;
+        Ok(catalog)
+    }
+
+    /// 检查编号是否从 1 开始连续, sample_file 的每个编号可以对应多个文件.
+    /// 编号 0 与同一编号的多个文件 (如 hash_1.k2d 与 hash_01.k2d) 也是错误
+    pub fn check_continuous(&self) -> Result<()> {
+        let invalid = |message: String| {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} files in {}: {}",
+                    self.kind,
+                    self.directory.display(),
+                    message
+                ),
+            ))
+        };
+        if let Some(entry) = self.entries.first().filter(|entry| entry.index == 0) {
+            return invalid(format!(
+                "{} is numbered 0, numbers start at 1",
+                entry.path.display()
+            ));
+        }
+        let duplicates = self.duplicates();
+        if !duplicates.is_empty() {
+            let names: Vec<String> = duplicates
+                .iter()
+                .map(|entry| entry.path.display().to_string())
+                .collect();
+            return invalid(format!("{} have the same number", names.join(", ")));
+        }
+        let missing = self.missing_indices();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(self.missing_error(&missing))
+        }
+    }
+
+    /// 与其他文件编号相同的文件
+    pub fn duplicates(&self) -> Vec<&ChunkEntry> {
+        let key = |entry: &ChunkEntry| (entry.index, entry.sample);
+        let mut counts: BTreeMap<(usize, Option<usize>), usize> = BTreeMap::new();
+        for entry in &self.entries {
+            *counts.entry(key(entry)).or_default() += 1;
+        }
+        self.entries
+            .iter()
+            .filter(|entry| counts[&key(entry)] > 1)
+            .collect()
+    }
+
+    /// 1 到最大编号之间缺少的编号
+    pub fn missing_indices(&self) -> Vec<usize> {
+        let indices = self.indices();
+        let max = indices.last().copied().unwrap_or(0);
+        // 编号 0 不在 1..=max 中, 由 check_continuous 单独报告
+        let mut present = indices
+            .into_iter()
+            .skip_while(|&index| index == 0)
+            .peekable();
+        (1..=max)
+            .filter(|index| present.next_if_eq(index).is_none())
+            .collect()
+    }
+
+    /// 编号为 `index` 的文件名, sample_file 的第二个编号显示为 *
+    pub fn file_name(&self, index: usize) -> String {
+        if self.kind.has_sample() {
+            format!("{}_{}_*{}", self.kind.prefix(), index, self.kind.suffix())
+        } else {
+            format!("{}_{}{}", self.kind.prefix(), index, self.kind.suffix())
+        }
+    }
+
+    /// 缺少 `missing` 中的编号时的错误
+    pub fn missing_error(&self, missing: &[usize]) -> io::Error {
+        let names: Vec<String> = missing.iter().map(|&i| self.file_name(i)).collect();
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "{} files in {} are not numbered continuously from 1: missing {}",
+                self.kind,
+                self.directory.display(),
+                names.join(", ")
+            ),
+        )
+    }
+
+    pub fn kind(&self) -> ChunkKind {
+        self.kind
+    }
+
+    pub fn entries(&self) -> &[ChunkEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 不重复的编号, 从小到大
+    pub fn indices(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = self.entries.iter().map(|entry| entry.index).collect();
+        indices.dedup();
+        indices
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    /// 编号 -> 文件, 每个编号只有一个文件时使用
+    pub fn by_index(&self) -> BTreeMap<usize, PathBuf> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.index, entry.path.clone()))
+            .collect()
+    }
+
+    /// 编号 -> 该编号的所有文件, 按 sample 排序
+    pub fn group_by_index(&self) -> BTreeMap<usize, Vec<PathBuf>> {
+        let mut groups: BTreeMap<usize, Vec<PathBuf>> = BTreeMap::new();
+        for entry in &self.entries {
+            groups
+                .entry(entry.index)
+                .or_default()
+                .push(entry.path.clone());
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在临时目录中创建空文件, 返回目录
+    fn directory(name: &str, files: &[&str]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("kr2r_catalog_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for file in files {
+            fs::write(dir.join(file), b"").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn continuous_from_one() {
+        let dir = directory("continuous", &["hash_3.k2d", "hash_1.k2d", "hash_2.k2d"]);
+        let catalog = ChunkCatalog::scan_continuous(&dir, ChunkKind::Hash).unwrap();
+        assert_eq!(catalog.indices(), vec![1, 2, 3]);
+        assert_eq!(catalog.by_index()[&3], dir.join("hash_3.k2d"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gaps_are_reported() {
+        let dir = directory("gaps", &["sample_2.k2", "sample_5.k2"]);
+        let catalog = ChunkCatalog::scan(&dir, ChunkKind::Sample).unwrap();
+        assert_eq!(catalog.missing_indices(), vec![1, 3, 4]);
+        let err = catalog.check_continuous().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err
+            .to_string()
+            .contains("missing sample_1.k2, sample_3.k2, sample_4.k2"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_directory_is_continuous() {
+        let dir = directory("empty", &[]);
+        let catalog = ChunkCatalog::scan_continuous(&dir, ChunkKind::Hash).unwrap();
+        assert!(catalog.is_empty());
+        assert!(catalog.missing_indices().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn numbering_starts_at_one() {
+        // 旧的 find_* 函数对从 0 开始的编号判断不一致
+        let dir = directory("zero", &["chunk_0.k2", "chunk_1.k2", "chunk_2.k2"]);
+        let catalog = ChunkCatalog::scan(&dir, ChunkKind::DbChunk).unwrap();
+        assert_eq!(catalog.indices(), vec![0, 1, 2]);
+        assert!(catalog.missing_indices().is_empty());
+        let err = catalog.check_continuous().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("numbered 0"));
+
+        fs::remove_file(dir.join("chunk_0.k2")).unwrap();
+        assert!(ChunkCatalog::scan_continuous(&dir, ChunkKind::DbChunk).is_ok());
+        fs::remove_file(dir.join("chunk_1.k2")).unwrap();
+        let catalog = ChunkCatalog::scan(&dir, ChunkKind::DbChunk).unwrap();
+        assert_eq!(catalog.missing_indices(), vec![1]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn duplicate_indexes_are_rejected() {
+        let dir = directory("duplicates", &["hash_1.k2d", "hash_01.k2d", "hash_2.k2d"]);
+        let catalog = ChunkCatalog::scan(&dir, ChunkKind::Hash).unwrap();
+        assert_eq!(catalog.len(), 3);
+        assert_eq!(catalog.indices(), vec![1, 2]);
+        assert_eq!(catalog.duplicates().len(), 2);
+        let err = catalog.check_continuous().unwrap_err();
+        assert!(err.to_string().contains("have the same number"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        // sample_file 的同一个编号有多个文件是正常的, 只有两个编号都相同才算重复
+        let dir = directory(
+            "bin_duplicates",
+            &[
+                "sample_file_1_0.bin",
+                "sample_file_1_1.bin",
+                "sample_file_2_0.bin",
+            ],
+        );
+        let catalog = ChunkCatalog::scan_continuous(&dir, ChunkKind::SampleBin).unwrap();
+        assert!(catalog.duplicates().is_empty());
+        fs::write(dir.join("sample_file_1_01.bin"), b"").unwrap();
+        let catalog = ChunkCatalog::scan(&dir, ChunkKind::SampleBin).unwrap();
+        assert_eq!(catalog.duplicates().len(), 2);
+        assert!(catalog.check_continuous().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn kinds_do_not_mix() {
+        let dir = directory(
+            "mixed",
+            &[
+                "hash_1.k2d",
+                "hash_config.k2d",
+                "chunk_1.k2",
+                "sample_1.k2",
+                "sample_2.k2",
+                "sample_id_1.map",
+                "sample_id_2.map",
+                "sample_file_1_0.bin",
+                "sample_file_1.bin",
+                "sample_file.map",
+                "sample_1.k2.tmp",
+                "sample_x.k2",
+                "sample_.k2",
+            ],
+        );
+        let names = |kind: ChunkKind| -> Vec<String> {
+            ChunkCatalog::scan(&dir, kind)
+                .unwrap()
+                .entries()
+                .iter()
+                .map(|entry| {
+                    entry
+                        .path
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect()
+        };
+        assert_eq!(names(ChunkKind::Hash), vec!["hash_1.k2d"]);
+        assert_eq!(names(ChunkKind::DbChunk), vec!["chunk_1.k2"]);
+        assert_eq!(names(ChunkKind::Sample), vec!["sample_1.k2", "sample_2.k2"]);
+        assert_eq!(
+            names(ChunkKind::SampleId),
+            vec!["sample_id_1.map", "sample_id_2.map"]
+        );
+        assert_eq!(names(ChunkKind::SampleBin), vec!["sample_file_1_0.bin"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bins_group_by_first_number() {
+        let dir = directory(
+            "groups",
+            &[
+                "sample_file_2_0.bin",
+                "sample_file_1_10.bin",
+                "sample_file_1_2.bin",
+                "sample_file_3_0.bin",
+            ],
+        );
+        let catalog = ChunkCatalog::scan_continuous(&dir, ChunkKind::SampleBin).unwrap();
+        let groups = catalog.group_by_index();
+        assert_eq!(groups.keys().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+        // 第二个编号按数值排序, 不按文件名
+        assert_eq!(
+            groups[&1],
+            vec![
+                dir.join("sample_file_1_2.bin"),
+                dir.join("sample_file_1_10.bin")
+            ]
+        );
+        assert_eq!(catalog.file_name(1), "sample_file_1_*.bin");
+
+        fs::remove_file(dir.join("sample_file_2_0.bin")).unwrap();
+        let catalog = ChunkCatalog::scan(&dir, ChunkKind::SampleBin).unwrap();
+        assert_eq!(catalog.missing_indices(), vec![2]);
+        assert!(catalog
+            .check_continuous()
+            .unwrap_err()
+            .to_string()
+            .contains("missing sample_file_2_*.bin"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Result, Write};
+use std::path::{Path, PathBuf};
+
+pub const CHECKPOINT_FILE: &str = "checkpoint.txt";
+
+/// chunk 目录 (build 时为数据库目录) 中的断点记录, 用于中断后重新运行时跳过已完成的 chunk.
+///
+/// 记录只追加写入, 每行为 `阶段\t名称\t内容`, 同一阶段与名称以最后一行为准.
+/// 内容为 `done` 表示已完成, `start\t...` 表示已开始但未完成.
+pub struct Checkpoint {
+    path: PathBuf,
+    entries: HashMap<(String, String), String>,
+}
+
+impl Checkpoint {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let path = dir.as_ref().join(CHECKPOINT_FILE);
+        let mut entries = HashMap::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                let mut parts = line.splitn(3, '\t');
+                if let (Some(stage), Some(name), Some(value)) =
+                    (parts.next(), parts.next(), parts.next())
+                {
+                    entries.insert((stage.to_string(), name.to_string()), value.to_string());
+                }
+            }
+        }
+        Ok(Self { path, entries })
+    }
+
+    pub fn get(&self, stage: &str, name: &str) -> Option<&str> {
+        self.entries
+            .get(&(stage.to_string(), name.to_string()))
+            .map(|value| value.as_str())
+    }
+
+    pub fn is_done(&self, stage: &str, name: &str) -> bool {
+        self.done_value(stage, name).is_some()
+    }
+
+    /// 已完成时返回 `done` 之后的附加内容
+    pub fn done_value(&self, stage: &str, name: &str) -> Option<&str> {
+        let value = self.get(stage, name)?;
+        if value == "done" {
+            Some("")
+        } else {
+            value.strip_prefix("done\t")
+        }
+    }
+
+    /// 已开始但未完成时返回 `start` 之后的内容
+    pub fn started_value(&self, stage: &str, name: &str) -> Option<&str> {
+        let value = self.get(stage, name)?;
+        if value == "start" {
+            Some("")
+        } else {
+            value.strip_prefix("start\t")
+        }
+    }
+
+    /// 一个阶段的所有记录 (名称与内容), 按名称排序
+    pub fn stage_entries(&self, stage: &str) -> Vec<(&str, &str)> {
+        let mut entries: Vec<(&str, &str)> = self
+            .entries
+            .iter()
+            .filter(|((s, _), _)| s == stage)
+            .map(|((_, name), value)| (name.as_str(), value.as_str()))
+            .collect();
+        entries.sort_unstable();
+        entries
+    }
+
+    /// 追加一条记录并同步到磁盘
+    pub fn record(&mut self, stage: &str, name: &str, value: &str) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}\t{}\t{}", stage, name, value)?;
+        file.sync_data()?;
+        self.entries
+            .insert((stage.to_string(), name.to_string()), value.to_string());
+        Ok(())
+    }
+
+    pub fn start(&mut self, stage: &str, name: &str, value: &str) -> Result<()> {
+        self.record(stage, name, &format!("start\t{}", value))
+    }
+
+    pub fn done(&mut self, stage: &str, name: &str, value: &str) -> Result<()> {
+        if value.is_empty() {
+            self.record(stage, name, "done")
+        } else {
+            self.record(stage, name, &format!("done\t{}", value))
+        }
+    }
+
+    /// 删除一个阶段的所有记录, 没有记录时删除文件
+    pub fn clear_stage(&mut self, stage: &str) -> Result<()> {
+        self.entries.retain(|(s, _), _| s != stage);
+        if self.entries.is_empty() {
+            return self.remove();
+        }
+        let mut file = File::create(&self.path)?;
+        for ((stage, name), value) in &self.entries {
+            writeln!(file, "{}\t{}\t{}", stage, name, value)?;
+        }
+        file.sync_data()
+    }
+
+    /// 删除所有记录
+    pub fn remove(&mut self) -> Result<()> {
+        self.entries.clear();
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// 目录中名称满足 `filter` 的文件及其长度, 格式为 `文件名:长度,...`
+pub fn snapshot_files<F>(dir: &Path, filter: F) -> Result<String>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_file() && filter(&name) {
+            files.push(format!("{}:{}", name, entry.metadata()?.len()));
+        }
+    }
+    files.sort_unstable();
+    Ok(files.join(","))
+}
+
+/// 恢复到 `snapshot_files` 记录时的状态: 截断记录中的文件, 删除之后新建的文件
+pub fn restore_files<F>(dir: &Path, filter: F, snapshot: &str) -> Result<()>
+where
+    F: Fn(&str) -> bool,
+{
+    let sizes: HashMap<&str, u64> = snapshot
+        .split(',')
+        .filter_map(|item| item.rsplit_once(':'))
+        .filter_map(|(name, len)| Some((name, len.parse().ok()?)))
+        .collect();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !entry.file_type()?.is_file() || !filter(&name) {
+            continue;
+        }
+        match sizes.get(name.as_str()) {
+            Some(&len) => OpenOptions::new()
+                .write(true)
+                .open(entry.path())?
+                .set_len(len)?,
+            None => fs::remove_file(entry.path())?,
+        }
+    }
+    Ok(())
+}
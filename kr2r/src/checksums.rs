@@ -0,0 +1,125 @@
+use crate::catalog::{ChunkCatalog, ChunkKind};
+use seahash::SeaHasher;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Result, Write};
+use std::path::Path;
+
+/// 数据库目录中各文件大小与 seahash 的清单, 构建时写出, `verify-db` 用于检查复制后的数据库
+pub const CHECKSUM_FILE: &str = "checksums.txt";
+
+/// 清单中 hash shard 之外的数据库文件
+const DATABASE_FILES: [&str; 4] = [
+    "opts.k2d",
+    "taxo.k2d",
+    "hash_config.k2d",
+    "minimizer_cap.txt",
+];
+
+/// 分块计算 seahash, 与一次计算整个内容的结果相同
+///
+/// ```
+/// use kraken2_rs::checksums::{checksum_bytes, file_checksum};
+///
+/// let path = std::env::temp_dir().join("file_checksum_doctest.bin");
+/// let data: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+/// std::fs::write(&path, &data).unwrap();
+/// assert_eq!(file_checksum(&path).unwrap(), checksum_bytes(&data));
+/// assert_ne!(checksum_bytes(&data[1..]), checksum_bytes(&data));
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn checksum_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = SeaHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// 文件内容的 seahash
+pub fn file_checksum<P: AsRef<Path>>(path: P) -> Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = SeaHasher::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buffer[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+/// 清单中的一行
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileChecksum {
+    pub size: u64,
+    pub checksum: u64,
+}
+
+impl FileChecksum {
+    pub fn of_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self {
+            size: fs::metadata(&path)?.len(),
+            checksum: file_checksum(&path)?,
+        })
+    }
+}
+
+/// 计算 opts.k2d, taxo.k2d, hash_config.k2d 与全部 hash shard 的清单并写入 [`CHECKSUM_FILE`]
+pub fn write_checksums(database: &Path) -> Result<BTreeMap<String, FileChecksum>> {
+    let mut files: Vec<String> = DATABASE_FILES
+        .iter()
+        .filter(|name| database.join(name).exists())
+        .map(|name| name.to_string())
+        .collect();
+    let catalog = ChunkCatalog::scan(database, ChunkKind::Hash)?;
+    files.extend(catalog.indices().into_iter().map(|i| catalog.file_name(i)));
+
+    let mut checksums = BTreeMap::new();
+    for name in files {
+        checksums.insert(name.clone(), FileChecksum::of_file(database.join(&name))?);
+    }
+
+    // 先写临时文件再重命名, 中断时不会留下不完整的清单
+    let tmp = database.join(format!("{}.tmp", CHECKSUM_FILE));
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    for (name, file) in &checksums {
+        writeln!(writer, "{}\t{}\t{:016x}", name, file.size, file.checksum)?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(tmp, database.join(CHECKSUM_FILE))?;
+    Ok(checksums)
+}
+
+/// 读取 [`CHECKSUM_FILE`], 没有清单时为 `None`
+pub fn read_checksums(database: &Path) -> Result<Option<BTreeMap<String, FileChecksum>>> {
+    let path = database.join(CHECKSUM_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let malformed = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("malformed line in {:?}: {}", path, line),
+        )
+    };
+    let mut checksums = BTreeMap::new();
+    for line in BufReader::new(File::open(&path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut columns = line.split('\t');
+        let (Some(name), Some(size), Some(checksum)) =
+            (columns.next(), columns.next(), columns.next())
+        else {
+            return Err(malformed(&line));
+        };
+        let size = size.parse::<u64>().map_err(|_| malformed(&line))?;
+        let checksum = u64::from_str_radix(checksum, 16).map_err(|_| malformed(&line))?;
+        checksums.insert(name.to_string(), FileChecksum { size, checksum });
+    }
+    Ok(Some(checksums))
+}
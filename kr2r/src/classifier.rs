@@ -0,0 +1,268 @@
+use crate::catalog::{ChunkCatalog, ChunkKind};
+use crate::classify::{lookup_rows_until, process_hitgroup};
+use crate::compact_hash::{CHTable, HashConfig, Row, ShardChecksums};
+use crate::stats::taxon_name;
+use crate::taxonomy::Taxonomy;
+use crate::{HitGroup, IndexOptions};
+use seqkmer::{scan_sequence, Base, Meros, OptionPair, SeqFormat, SeqHeader};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::time::{Duration, Instant};
+
+/// 单条 read (或一对 reads) 的分类结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Classification {
+    /// 分类到的 external taxid, 0 表示未分类
+    pub taxid: u64,
+    /// 与 kraken 标准输出第 5 列相同的 LCA 映射, 例如 "562:13 561:4 A:31 0:1"
+    pub hit_string: String,
+    /// 每条序列的长度, paired reads 为两个 mate 的长度
+    pub seq_len: OptionPair<usize>,
+}
+
+impl Classification {
+    pub fn is_classified(&self) -> bool {
+        self.taxid > 0
+    }
+}
+
+fn check_hash_config(hash_config: &HashConfig) -> Result<()> {
+    if hash_config.hash_capacity == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "`hash_capacity` can't be zero!",
+        ));
+    }
+    Ok(())
+}
+
+/// 在内存中加载整个数据库, 直接对序列分类, 不经过 splitr/annotate/resolve 的中间文件
+///
+/// ```no_run
+/// use kraken2_rs::classifier::Classifier;
+///
+/// let classifier = Classifier::from_database("kraken_db")
+///     .unwrap()
+///     .with_confidence_threshold(0.1);
+/// let result = classifier.classify_read(b"ACGTTGCATGCATGCATGCATGCATGCATGCATGCA");
+/// println!("{} {}", result.taxid, classifier.taxon_name(result.taxid));
+/// ```
+pub struct Classifier {
+    meros: Meros,
+    hash_config: HashConfig,
+    chtable: CHTable,
+    taxonomy: Taxonomy,
+    confidence_threshold: f64,
+    minimum_hit_groups: usize,
+    time_budget: Option<Duration>,
+}
+
+impl Classifier {
+    pub fn new(idx_opts: &IndexOptions, chtable: CHTable, taxonomy: Taxonomy) -> Self {
+        Self {
+            meros: idx_opts.as_meros(),
+            hash_config: chtable.config,
+            chtable,
+            taxonomy,
+            confidence_threshold: 0.0,
+            minimum_hit_groups: 2,
+            time_budget: None,
+        }
+    }
+
+    /// 从数据库目录读取 opts.k2d, taxo.k2d, hash_config.k2d 与全部 hash_*.k2d
+    pub fn from_database<P: AsRef<Path>>(database: P) -> Result<Self> {
+        Self::load(database.as_ref(), false)
+    }
+
+    /// 与 [`Classifier::from_database`] 相同, 但内存映射 taxo.k2d 与 hash 文件, 数据库已在 page cache 中时几乎不需要加载时间
+    pub fn map_database<P: AsRef<Path>>(database: P) -> Result<Self> {
+        Self::load(database.as_ref(), true)
+    }
+
+    fn load(database: &Path, mapped: bool) -> Result<Self> {
+        let idx_opts = IndexOptions::read_index_options(database.join("opts.k2d"))?;
+        let taxonomy = if mapped {
+            Taxonomy::open_mmap(database.join("taxo.k2d"))?
+        } else {
+            Taxonomy::from_file(database.join("taxo.k2d"))?
+        };
+        let hash_config = HashConfig::from_hash_header(database.join("hash_config.k2d"))?;
+        check_hash_config(&hash_config)?;
+        let checksums = ShardChecksums::from_hash_header(database.join("hash_config.k2d"))?;
+        let hash_files = ChunkCatalog::scan_continuous(database, ChunkKind::Hash)?.paths();
+        let chtable = if mapped {
+            CHTable::map_hash_files(hash_config, &hash_files, checksums.as_ref())?
+        } else {
+            CHTable::from_hash_files(hash_config, &hash_files, checksums.as_ref())?
+        };
+        Ok(Self::new(&idx_opts, chtable, taxonomy))
+    }
+
+    /// 从内存中的数据库文件建立分类器, 不读取文件系统, 例如在浏览器 (wasm32) 中对小数据库分类.
+    /// `hash_shards` 为按顺序的 hash_1.k2d, hash_2.k2d, ... 的内容
+    ///
+    /// ```no_run
+    /// use kraken2_rs::classifier::Classifier;
+    ///
+    /// let read = |name: &str| std::fs::read(format!("kraken_db/{}", name)).unwrap();
+    /// let classifier = Classifier::from_bytes(
+    ///     &read("opts.k2d"),
+    ///     &read("taxo.k2d"),
+    ///     &read("hash_config.k2d"),
+    ///     &[read("hash_1.k2d")],
+    /// )
+    /// .unwrap();
+    /// let result = classifier.classify_read(b"ACGTTGCATGCATGCATGCATGCATGCATGCATGCA");
+    /// ```
+    pub fn from_bytes<B: AsRef<[u8]>>(
+        opts: &[u8],
+        taxonomy: &[u8],
+        hash_config: &[u8],
+        hash_shards: &[B],
+    ) -> Result<Self> {
+        let idx_opts = IndexOptions::from_bytes(opts)?;
+        let taxonomy = Taxonomy::from_bytes(taxonomy)?;
+        let checksums = ShardChecksums::from_bytes(hash_config)?;
+        let hash_config = HashConfig::from_bytes(hash_config)?;
+        check_hash_config(&hash_config)?;
+        if hash_shards.len() != hash_config.partition {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "the database has {} hash shards, {} were given",
+                    hash_config.partition,
+                    hash_shards.len()
+                ),
+            ));
+        }
+        let chtable = CHTable::from_hash_bytes(hash_config, hash_shards, checksums.as_ref())?;
+        Ok(Self::new(&idx_opts, chtable, taxonomy))
+    }
+
+    /// 置信度阈值, 默认为 0
+    pub fn with_confidence_threshold(mut self, confidence_threshold: f64) -> Self {
+        self.confidence_threshold = confidence_threshold;
+        self
+    }
+
+    /// 分类所需的最少 hit group 数, 默认为 2
+    pub fn with_minimum_hit_groups(mut self, minimum_hit_groups: usize) -> Self {
+        self.minimum_hit_groups = minimum_hit_groups;
+        self
+    }
+
+    /// [`Classifier::classify_prefix`] 每条 read 的时间预算, 默认不限制. 超过后不再查找其余的
+    /// minimizer, 用已经找到的命中分类. 需要系统时钟, wasm32 上不能使用
+    pub fn with_time_budget(mut self, time_budget: Duration) -> Self {
+        self.time_budget = Some(time_budget);
+        self
+    }
+
+    pub fn taxonomy(&self) -> &Taxonomy {
+        &self.taxonomy
+    }
+
+    /// external taxid 对应的学名, 0 为 "unclassified"
+    pub fn taxon_name(&self, taxid: u64) -> &str {
+        taxon_name(&self.taxonomy, taxid)
+    }
+
+    /// external taxid 从 root 开始的谱系学名, 0 为空
+    pub fn lineage(&self, taxid: u64) -> Vec<&str> {
+        if taxid == 0 {
+            return Vec::new();
+        }
+        let internal_id = self.taxonomy.get_internal_id(taxid);
+        self.taxonomy
+            .lineage(internal_id)
+            .into_iter()
+            .map(|id| self.taxonomy.name(id))
+            .collect()
+    }
+
+    pub fn classify_read(&self, seq: &[u8]) -> Classification {
+        self.classify(OptionPair::Single(seq.to_vec()), None)
+    }
+
+    /// 只用前 `max_bases` 个碱基分类, 例如 nanopore adaptive sampling (ReadUntil) 根据 read
+    /// 开头的几百个碱基决定是否弹出. 耗时与 `max_bases` 成正比而与 read 的总长度无关,
+    /// 设置了 [`Classifier::with_time_budget`] 时还不超过该时间 (加上最后一批查找).
+    /// 结果的 `seq_len` 为实际使用的碱基数, 小于 `max_bases` 且小于 read 长度时表示超时
+    ///
+    /// ```
+    /// use kraken2_rs::classifier::Classifier;
+    /// use kraken2_rs::compact_hash::{CHTable, HashConfig, HashPage, Page};
+    /// use kraken2_rs::taxonomy::{Taxonomy, TaxonomyNode};
+    /// use kraken2_rs::IndexOptions;
+    /// use seqkmer::OptionPair;
+    /// use std::time::Duration;
+    ///
+    /// // 没有 minimizer 的数据库
+    /// let config = HashConfig::new(1, 1024, 16, 0, 1, 1024);
+    /// let pages = vec![HashPage::Loaded(Page::with_capacity(1, 1024))];
+    /// let opts = IndexOptions::new(35, 31, 0, 0, true, 0);
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.nodes = vec![TaxonomyNode::default()].into();
+    /// let classifier = Classifier::new(&opts, CHTable { config, pages }, taxonomy);
+    ///
+    /// let read = b"ACGTTGCATGCATGCAAGCATGCTTGCATGCAACGTTGCATG".repeat(20);
+    /// let result = classifier.classify_prefix(&read, 400);
+    /// assert_eq!((result.taxid, result.seq_len), (0, OptionPair::Single(400)));
+    ///
+    /// // 预算用完时不再扫描
+    /// let classifier = classifier.with_time_budget(Duration::ZERO);
+    /// let result = classifier.classify_prefix(&read, 400);
+    /// assert_eq!((result.taxid, result.seq_len), (0, OptionPair::Single(0)));
+    /// ```
+    pub fn classify_prefix(&self, seq: &[u8], max_bases: usize) -> Classification {
+        let prefix = seq[..seq.len().min(max_bases)].to_vec();
+        let deadline = self.time_budget.map(|budget| Instant::now() + budget);
+        self.classify(OptionPair::Single(prefix), deadline)
+    }
+
+    pub fn classify_pair(&self, seq1: &[u8], seq2: &[u8]) -> Classification {
+        self.classify(OptionPair::Pair(seq1.to_vec(), seq2.to_vec()), None)
+    }
+
+    fn classify(&self, body: OptionPair<Vec<u8>>, deadline: Option<Instant>) -> Classification {
+        let header = SeqHeader {
+            id: String::new(),
+            file_index: 0,
+            reads_index: 0,
+            format: SeqFormat::Fasta,
+            qual_sum: 0,
+            unmasked_gc: None,
+        };
+        let seq = Base::new(header, body);
+        let mut marker = scan_sequence(&seq, &self.meros);
+        let rows: Vec<Row> = marker.fold(|rows, m_iter, offset| {
+            lookup_rows_until(
+                rows,
+                m_iter,
+                &self.hash_config,
+                &self.chtable,
+                offset,
+                deadline,
+            )
+        });
+        let hits = HitGroup::new(rows, marker.range());
+        let (_, taxid, hit_string, _) = process_hitgroup(
+            &hits,
+            &self.taxonomy,
+            &AtomicUsize::new(0),
+            hits.required_score(self.confidence_threshold),
+            self.minimum_hit_groups,
+            self.hash_config.value_mask,
+            None,
+            None,
+        );
+
+        Classification {
+            taxid,
+            hit_string,
+            seq_len: marker.body.apply(|m_iter| m_iter.scanned_size()),
+        }
+    }
+}
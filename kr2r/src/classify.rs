@@ -1,10 +1,17 @@
-use crate::compact_hash::Compact;
+use crate::args::StopWhen;
+use crate::compact_hash::{CHTable, Compact, HashConfig, Row};
 use crate::readcounts::TaxonCounters;
-use crate::taxonomy::Taxonomy;
+use crate::stats::{normalize_name, output_taxid, report_name, taxon_name};
+use crate::taxonomy::{rank_level, Taxonomy};
 use crate::HitGroup;
-use seqkmer::SpaceDist;
+use seqkmer::{MinimizerIterator, SpaceDist};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+use tracing::{info, warn};
 
 pub fn resolve_tree(
     hit_counts: &HashMap<u32, u64>,
@@ -14,7 +21,7 @@ pub fn resolve_tree(
     let mut max_taxon = 0u32;
     let mut max_score = 0;
 
-    for (&taxon, _) in hit_counts {
+    for &taxon in hit_counts.keys() {
         let mut score = 0;
 
         for (&taxon2, &count2) in hit_counts {
@@ -49,7 +56,44 @@ pub fn resolve_tree(
     max_taxon
 }
 
-fn stat_hits<'a>(
+/// `--min-clade-support`: 返回至少包含 `min_support` 比例命中 k-mer 的最深的 clade,
+/// 同一深度时取命中更多的. 介于严格的 LCA (所有命中的共同祖先) 与默认的 root-to-leaf 打分之间.
+/// clade 内命中数同时需要达到 `required_score` (`--confidence-threshold`)
+pub fn resolve_clade_support(
+    hit_counts: &HashMap<u32, u64>,
+    taxonomy: &Taxonomy,
+    required_score: u64,
+    min_support: f64,
+) -> u32 {
+    let total: u64 = hit_counts.values().sum();
+    let needed = ((min_support * total as f64).ceil() as u64)
+        .max(required_score)
+        .max(1);
+
+    // 每个命中的 taxon 及其祖先的 clade 内命中数与深度
+    let mut clades: HashMap<u32, (u64, usize)> = HashMap::new();
+    for (&taxon, &count) in hit_counts {
+        let mut path = Vec::new();
+        let mut node = taxon;
+        while node != 0 && !path.contains(&node) {
+            path.push(node);
+            node = taxonomy.nodes[node as usize].parent_id as u32;
+        }
+        let depth = path.len();
+        for (i, node) in path.into_iter().enumerate() {
+            clades.entry(node).or_insert((0, depth - i)).0 += count;
+        }
+    }
+
+    clades
+        .into_iter()
+        .filter(|(_, (support, _))| *support >= needed)
+        .max_by_key(|&(taxon, (support, depth))| (depth, support, Reverse(taxon)))
+        .map(|(taxon, _)| taxon)
+        .unwrap_or(0)
+}
+
+fn stat_hits(
     hits: &HitGroup,
     counts: &mut HashMap<u32, u64>,
     value_mask: usize,
@@ -77,6 +121,248 @@ fn stat_hits<'a>(
     space_dist.reduce_str(" |:| ", |str| str.to_string())
 }
 
+/// 输出文件中的 taxid 列, `use_names` 时格式为 `name (taxid N)`. 未分类的 taxid 为 [`crate::stats::unclassified_taxid`]
+pub fn format_call(taxonomy: &Taxonomy, ext_call: u64, use_names: bool) -> String {
+    let taxid = output_taxid(ext_call);
+    if !use_names {
+        return taxid.to_string();
+    }
+    format!(
+        "{} (taxid {})",
+        report_name(taxon_name(taxonomy, ext_call)),
+        taxid
+    )
+}
+
+/// call 的 clade 内命中 k-mer 占 read 全部 k-mer 的比例, 与 `--confidence-threshold` 的含义相同
+pub fn call_confidence(
+    hits: &HitGroup,
+    taxonomy: &Taxonomy,
+    ext_call: u64,
+    value_mask: usize,
+) -> f64 {
+    let capacity = hits.capacity();
+    if ext_call == 0 || capacity == 0 {
+        return 0.0;
+    }
+    let call = taxonomy.get_internal_id(ext_call);
+    let support = hits
+        .rows
+        .iter()
+        .filter(|row| taxonomy.is_a_ancestor_of_b(call, row.value.right(value_mask)))
+        .count();
+    support as f64 / capacity as f64
+}
+
+/// `--read-minimizer-data`: read 命中数据库的不同 minimizer 数, 与各 taxon (external taxid) 命中的
+/// minimizer 数, 按命中数从多到少排列
+///
+/// ```
+/// use kraken2_rs::classify::MinimizerData;
+///
+/// let data = MinimizerData {
+///     distinct: 7,
+///     taxa: vec![(562, 5), (561, 3)],
+/// };
+/// assert_eq!(data.hit_string(), "562:5 561:3");
+/// assert_eq!(MinimizerData::default().hit_string(), "-");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MinimizerData {
+    pub distinct: usize,
+    pub taxa: Vec<(u64, usize)>,
+}
+
+impl MinimizerData {
+    pub fn from_hits(hits: &HitGroup, taxonomy: &Taxonomy, value_mask: usize) -> Self {
+        let mut values: Vec<u32> = hits.rows.iter().map(|row| row.value).collect();
+        values.sort_unstable();
+        values.dedup();
+        let mut counts: HashMap<u64, usize> = HashMap::new();
+        for row in &hits.rows {
+            let taxid = row.value.right(value_mask);
+            *counts
+                .entry(taxonomy.nodes[taxid as usize].external_id)
+                .or_default() += 1;
+        }
+        let mut taxa: Vec<(u64, usize)> = counts.into_iter().collect();
+        taxa.sort_unstable_by_key(|&(taxid, count)| (Reverse(count), taxid));
+        Self {
+            distinct: values.len(),
+            taxa,
+        }
+    }
+
+    /// Kraken 2 命中串格式的 `taxid:count` 列表, 没有命中时为 `-`
+    pub fn hit_string(&self) -> String {
+        if self.taxa.is_empty() {
+            return "-".to_string();
+        }
+        self.taxa
+            .iter()
+            .map(|(taxid, count)| format!("{}:{}", taxid, count))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// `--output-format json` 的一条 read 记录. `seq_size` 为输出文件中的长度列,
+/// paired reads (`150|150`) 的 length 为两个 mate 的长度数组. 有 `minimizers` 时加入
+/// `distinct_minimizers` 与 `minimizer_hits` 字段
+///
+/// ```
+/// use kraken2_rs::classify::json_record;
+/// use kraken2_rs::taxonomy::Taxonomy;
+///
+/// let taxonomy = Taxonomy::default();
+/// let record = json_record("read1", &taxonomy, 0, "150|148", "0:118 |:| 0:116", 0.0, None);
+/// assert_eq!(
+///     record,
+///     r#"{"read_id":"read1","taxid":0,"name":"unclassified","length":[150,148],"lca_hit_string":"0:118 |:| 0:116","confidence":0.0}"#
+/// );
+/// ```
+pub fn json_record(
+    read_id: &str,
+    taxonomy: &Taxonomy,
+    ext_call: u64,
+    seq_size: &str,
+    hit_string: &str,
+    confidence: f64,
+    minimizers: Option<&MinimizerData>,
+) -> String {
+    let lengths: Vec<u64> = seq_size
+        .split('|')
+        .filter_map(|size| size.parse().ok())
+        .collect();
+    let record = ReadRecord {
+        read_id,
+        taxid: output_taxid(ext_call),
+        name: normalize_name(taxon_name(taxonomy, ext_call)),
+        length: match lengths.as_slice() {
+            [length] => ReadLength::Single(*length),
+            _ => ReadLength::Pair(lengths),
+        },
+        lca_hit_string: hit_string,
+        confidence,
+        distinct_minimizers: minimizers.map(|data| data.distinct),
+        minimizer_hits: minimizers.map(|data| data.hit_string()),
+    };
+    serde_json::to_string(&record).unwrap_or_default()
+}
+
+#[derive(Serialize)]
+struct ReadRecord<'a> {
+    read_id: &'a str,
+    taxid: u64,
+    name: Cow<'a, str>,
+    length: ReadLength,
+    lca_hit_string: &'a str,
+    confidence: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distinct_minimizers: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    minimizer_hits: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ReadLength {
+    Single(u64),
+    Pair(Vec<u64>),
+}
+
+/// 解析 [`format_call`] 输出的 taxid 列, 兼容 `name (taxid N)` 格式
+///
+/// ```
+/// use kraken2_rs::classify::parse_call;
+///
+/// assert_eq!(parse_call("562"), Some(562));
+/// assert_eq!(parse_call("Escherichia coli (taxid 562)"), Some(562));
+/// assert_eq!(parse_call("unclassified (taxid 0)"), Some(0));
+/// ```
+pub fn parse_call(column: &str) -> Option<u64> {
+    match column.rsplit_once("(taxid ") {
+        Some((_, rest)) => rest.strip_suffix(')')?.parse().ok(),
+        None => column.parse().ok(),
+    }
+}
+
+/// 每行分类结果的 read id 与 taxid, Kraken 格式为第 2, 3 列, JSON 为 `read_id` 与 `taxid` 字段
+///
+/// ```
+/// use kraken2_rs::classify::parse_output_line;
+///
+/// assert_eq!(
+///     parse_output_line("C\tread1\t562\t150\t562:116"),
+///     Some(("read1".to_string(), 562))
+/// );
+/// assert_eq!(
+///     parse_output_line(r#"{"read_id":"read2","taxid":0}"#),
+///     Some(("read2".to_string(), 0))
+/// );
+/// assert_eq!(parse_output_line("C\tread3"), None);
+/// ```
+pub fn parse_output_line(line: &str) -> Option<(String, u64)> {
+    if line.starts_with('{') {
+        let record: serde_json::Value = serde_json::from_str(line).ok()?;
+        let read_id = record.get("read_id")?.as_str()?.to_string();
+        return Some((read_id, record.get("taxid")?.as_u64()?));
+    }
+    let mut columns = line.split('\t');
+    let read_id = columns.nth(1)?.to_string();
+    Some((read_id, parse_call(columns.next()?)?))
+}
+
+/// 在已加载的哈希表中查找 minimizer, 命中的记录追加到 `rows`, 返回下一段序列的 offset
+pub fn lookup_rows(
+    rows: &mut Vec<Row>,
+    m_iter: &mut MinimizerIterator,
+    hash_config: &HashConfig,
+    chtable: &CHTable,
+    offset: usize,
+) -> usize {
+    lookup_rows_until(rows, m_iter, hash_config, chtable, offset, None)
+}
+
+/// 两次检查 `deadline` 之间查找的 minimizer 数
+const DEADLINE_CHECK_INTERVAL: usize = 32;
+
+/// 与 [`lookup_rows`] 相同, 但过了 `deadline` 后不再查找其余的 minimizer, `m_iter` 停在已扫描的位置
+pub fn lookup_rows_until(
+    rows: &mut Vec<Row>,
+    m_iter: &mut MinimizerIterator,
+    hash_config: &HashConfig,
+    chtable: &CHTable,
+    offset: usize,
+    deadline: Option<Instant>,
+) -> usize {
+    let chunk_size = hash_config.hash_capacity;
+    let value_bits = hash_config.value_bits;
+    let mut looked_up = 0;
+    loop {
+        if let Some(deadline) = deadline {
+            if looked_up % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline {
+                break;
+            }
+        }
+        let Some((sort, hash_key)) = m_iter.next() else {
+            break;
+        };
+        looked_up += 1;
+        let (idx, compacted) = hash_config.compact(hash_key);
+        let partition_index = idx / chunk_size;
+        let index = idx % chunk_size;
+
+        let taxid = chtable.get_from_page(index, compacted, partition_index);
+        if taxid > 0 {
+            let high = u32::combined(compacted, taxid, value_bits);
+            rows.push(Row::new(high, 0, (sort + 1 + offset) as u64));
+        }
+    }
+    m_iter.size + offset
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn process_hitgroup(
     hits: &HitGroup,
     taxonomy: &Taxonomy,
@@ -84,6 +370,8 @@ pub fn process_hitgroup(
     required_score: u64,
     minimum_hit_groups: usize,
     value_mask: usize,
+    rank_ceiling: Option<&RankCeiling>,
+    min_clade_support: Option<f64>,
 ) -> (String, u64, String, TaxonCounters) {
     let mut cur_taxon_counts = TaxonCounters::new();
     let mut counts = HashMap::new();
@@ -96,10 +384,16 @@ pub fn process_hitgroup(
         &mut cur_taxon_counts,
     );
 
-    let mut call = resolve_tree(&counts, taxonomy, required_score);
+    let mut call = match min_clade_support {
+        Some(support) => resolve_clade_support(&counts, taxonomy, required_score, support),
+        None => resolve_tree(&counts, taxonomy, required_score),
+    };
     if call > 0 && hit_groups < minimum_hit_groups {
         call = 0;
     };
+    if let Some(ceiling) = rank_ceiling {
+        call = ceiling.cap(call);
+    }
 
     let ext_call = taxonomy.nodes[call as usize].external_id;
     let clasify = if call > 0 {
@@ -116,3 +410,138 @@ pub fn process_hitgroup(
 
     (clasify.to_owned(), ext_call, hit_string, cur_taxon_counts)
 }
+
+/// `--max-rank` 的分类层级上限: 低于该层级的 call 提升到最近的不低于该层级的祖先.
+///
+/// 没有主要层级的节点 (subfamily, no rank, strain 等) 归入最近的有主要层级的祖先,
+/// 因此上限为 family 时 subfamily 被提升, 上限为 genus 时 family 之下、genus 之上的节点保持不变.
+/// 谱系中缺少该层级时提升到更高的层级
+pub struct RankCeiling {
+    promoted: Vec<u32>,
+}
+
+impl RankCeiling {
+    pub fn new(rank: &str, taxonomy: &Taxonomy) -> Self {
+        let max_level = rank_level(rank).unwrap_or(u8::MAX);
+        let node_count = taxonomy.node_count();
+        // 按 BFS 顺序生成的 taxonomy 中父节点的 internal id 总是小于子节点
+        let mut levels = vec![0u8; node_count];
+        let mut promoted: Vec<u32> = (0..node_count as u32).collect();
+        for id in 2..node_count {
+            let parent = taxonomy.nodes[id].parent_id as usize;
+            let below = match rank_level(taxonomy.rank(id as u32)) {
+                Some(level) => {
+                    levels[id] = level;
+                    level > max_level
+                }
+                None => {
+                    levels[id] = levels[parent];
+                    levels[parent] >= max_level
+                }
+            };
+            if below {
+                promoted[id] = promoted[parent];
+            }
+        }
+        Self { promoted }
+    }
+
+    /// 返回 `call` (internal id) 提升后的 internal id
+    pub fn cap(&self, call: u32) -> u32 {
+        self.promoted.get(call as usize).copied().unwrap_or(call)
+    }
+}
+
+/// 跟踪 `--stop-when` 条件, 满足后通知流水线停止读取新的数据
+#[derive(Debug)]
+pub struct StopTracker {
+    target: u32,
+    reads: u64,
+    hits: AtomicU64,
+    stopped: AtomicBool,
+}
+
+impl StopTracker {
+    pub fn new(rule: &StopWhen, taxonomy: &Taxonomy) -> Self {
+        let target = taxonomy.get_internal_id(rule.taxid);
+        if target == 0 {
+            warn!("stop-when taxid {} not found in taxonomy", rule.taxid);
+        }
+        Self {
+            target,
+            reads: rule.reads,
+            hits: AtomicU64::new(0),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// 记录一条 read 的分类结果 (external taxid), 返回是否已满足条件
+    pub fn observe(&self, taxonomy: &Taxonomy, ext_call: u64) -> bool {
+        if self.target != 0 && ext_call != 0 {
+            let call = taxonomy.get_internal_id(ext_call);
+            if call == self.target || taxonomy.is_a_ancestor_of_b(self.target, call) {
+                let hits = self.hits.fetch_add(1, Ordering::SeqCst) + 1;
+                if hits >= self.reads && !self.stopped.swap(true, Ordering::SeqCst) {
+                    info!("stop condition met: {} reads assigned to target", hits);
+                }
+            }
+        }
+        self.is_stopped()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxonomy::TaxonomyNode;
+    use seqkmer::OptionPair;
+
+    #[test]
+    fn hit_string_covers_positions_past_2_31() {
+        // 0: 空节点, 1: root, 2: taxid 562
+        let mut taxonomy = Taxonomy::default();
+        taxonomy.nodes = vec![
+            TaxonomyNode::default(),
+            TaxonomyNode {
+                external_id: 1,
+                ..Default::default()
+            },
+            TaxonomyNode {
+                parent_id: 1,
+                external_id: 562,
+                ..Default::default()
+            },
+        ]
+        .into();
+
+        let first = (1u64 << 31) + 5;
+        let second = (1u64 << 32) + 10;
+        let rows = [first, first + 1, second, second + 1]
+            .into_iter()
+            .map(|kmer_id| Row::new(2, 0, kmer_id))
+            .collect();
+        let hits = HitGroup::new(
+            rows,
+            OptionPair::Pair((0, first as usize), (first as usize, second as usize)),
+        );
+        assert_eq!(hits.capacity(), second as usize);
+
+        let counter = AtomicUsize::new(0);
+        let (code, call, hit_string, _) =
+            process_hitgroup(&hits, &taxonomy, &counter, 0, 0, 0xFFFF, None, None);
+        assert_eq!((code.as_str(), call), ("C", 562));
+        // 第一条 read 的最后一个位置, 第二条 read 的第一个与最后一个位置; 超出范围的位置被忽略
+        assert_eq!(
+            hit_string,
+            format!(
+                "0:{} 562:1 |:| 562:1 0:{} 562:1",
+                first - 1,
+                second - first - 2
+            )
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+/// 一个聚类: 代表序列与成员 (序列编号与和代表序列的相似度)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    pub representative: usize,
+    pub members: Vec<(usize, f64)>,
+}
+
+impl Cluster {
+    pub fn size(&self) -> usize {
+        self.members.len()
+    }
+}
+
+/// 按 minimizer 相似度的贪心聚类 (与 CD-HIT 相同的思路).
+///
+/// 序列应按长度从长到短加入. 每条序列归入与它共有 minimizer 最多的代表序列,
+/// 共有的 minimizer 占它自身 minimizer 的比例达到 `min_similarity` 时加入该聚类,
+/// 否则成为新聚类的代表序列. 只有代表序列的 minimizer 进入索引,
+/// 出现在超过 `max_postings` 个代表序列中的 minimizer (低复杂度序列) 不用于查找
+///
+/// ```
+/// use kraken2_rs::cluster::Clusterer;
+///
+/// let mut clusterer = Clusterer::new(0.5);
+/// assert_eq!(clusterer.add(0, &[1, 2, 3, 4, 5, 6]), (0, 1.0));
+/// assert_eq!(clusterer.add(1, &[3, 4, 5, 6, 7]), (0, 0.8));
+/// assert_eq!(clusterer.add(2, &[10, 11, 12, 6]), (1, 1.0));
+/// let clusters = clusterer.into_clusters();
+/// assert_eq!(clusters.len(), 2);
+/// assert_eq!(clusters[0].members, vec![(0, 1.0), (1, 0.8)]);
+/// assert_eq!(clusters[1].representative, 2);
+/// ```
+pub struct Clusterer {
+    min_similarity: f64,
+    max_postings: usize,
+    /// minimizer -> 含有它的聚类
+    index: HashMap<u64, Vec<u32>>,
+    clusters: Vec<Cluster>,
+}
+
+/// 索引中每个 minimizer 默认最多对应的聚类数
+pub const MAX_POSTINGS: usize = 1000;
+
+impl Clusterer {
+    pub fn new(min_similarity: f64) -> Self {
+        Self {
+            min_similarity,
+            max_postings: MAX_POSTINGS,
+            index: HashMap::new(),
+            clusters: Vec::new(),
+        }
+    }
+
+    pub fn with_max_postings(mut self, max_postings: usize) -> Self {
+        self.max_postings = max_postings;
+        self
+    }
+
+    /// 加入编号为 `seq` 的序列, `minimizers` 已去重. 返回所在的聚类与相似度
+    pub fn add(&mut self, seq: usize, minimizers: &[u64]) -> (usize, f64) {
+        let mut shared: HashMap<u32, u32> = HashMap::new();
+        for minimizer in minimizers {
+            if let Some(clusters) = self.index.get(minimizer) {
+                if clusters.len() <= self.max_postings {
+                    for &cluster in clusters {
+                        *shared.entry(cluster).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        // 共有最多的聚类, 相同时取较早的
+        let best = shared
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)));
+        if let Some((cluster, count)) = best {
+            let similarity = count as f64 / minimizers.len().max(1) as f64;
+            if similarity >= self.min_similarity {
+                self.clusters[cluster as usize]
+                    .members
+                    .push((seq, similarity));
+                return (cluster as usize, similarity);
+            }
+        }
+
+        let cluster = self.clusters.len();
+        for &minimizer in minimizers {
+            let clusters = self.index.entry(minimizer).or_default();
+            if clusters.len() <= self.max_postings {
+                clusters.push(cluster as u32);
+            }
+        }
+        self.clusters.push(Cluster {
+            representative: seq,
+            members: vec![(seq, 1.0)],
+        });
+        (cluster, 1.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clusters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clusters.is_empty()
+    }
+
+    /// 按加入顺序 (代表序列从长到短) 的聚类
+    pub fn into_clusters(self) -> Vec<Cluster> {
+        self.clusters
+    }
+}
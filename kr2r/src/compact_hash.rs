@@ -1,9 +1,14 @@
+use crate::error::Kr2rError;
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use memmap2::Mmap;
 use std::cmp::Ordering as CmpOrdering;
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::io::{BufWriter, Read, Result, Write};
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Result, Write};
 use std::path::Path;
+use tracing::warn;
+use twox_hash::XxHash64;
 
 /// 1101010101 => left: 11010, right: 10101;
 pub trait Compact: Default + PartialEq + Clone + Copy + Eq + Sized + Send + Sync + Debug {
@@ -49,7 +54,7 @@ impl Compact for u64 {
         Self::compacted(hash_key, value_bits) << (32 + value_bits) | value
     }
     fn compacted(value: u64, value_bits: usize) -> u64 {
-        (value >> (32 + value_bits)) as u64
+        value >> (32 + value_bits)
     }
 
     fn left(&self, value_bits: usize) -> u64 {
@@ -73,33 +78,44 @@ impl Compact for u64 {
     }
 }
 
+/// annotate 写入 .bin 文件, resolve 读取的一条命中记录
+///
+/// `kmer_id` 为 minimizer 在 read 中的位置, 使用 u64 以支持超过 2^32 个 minimizer 的超长序列.
+///
+/// ```
+/// use kraken2_rs::compact_hash::{ChunkRecord, Row};
+///
+/// let kmer_id = (1u64 << 32) + 7;
+/// let row = Row::new(1, 2, kmer_id);
+/// let mut bytes = Vec::new();
+/// row.extend_bytes(&mut bytes);
+/// assert_eq!(bytes.len(), Row::SIZE);
+/// let back = Row::decode(&bytes);
+/// assert_eq!(back.kmer_id, kmer_id);
+/// assert!(Row::new(0, 0, kmer_id) > Row::new(0, 0, u32::MAX as u64));
+/// ```
 #[repr(C)]
 #[derive(PartialEq, Clone, Copy, Eq, Debug)]
 pub struct Row {
     pub value: u32,
     pub seq_id: u32,
-    pub kmer_id: u32,
+    pub kmer_id: u64,
 }
 
 impl Row {
-    pub fn new(value: u32, seq_id: u32, kmer_id: u32) -> Self {
+    pub fn new(value: u32, seq_id: u32, kmer_id: u64) -> Self {
         Self {
             value,
             seq_id,
             kmer_id,
         }
     }
-    #[inline]
-    pub fn as_slice(&self, row_size: usize) -> &[u8] {
-        let slot_ptr = self as *const Self as *const u8;
-        unsafe { std::slice::from_raw_parts(slot_ptr, row_size) }
-    }
 }
 
 // 实现 PartialOrd，只比较 index 字段
 impl PartialOrd for Row {
     fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
-        self.kmer_id.partial_cmp(&other.kmer_id)
+        Some(self.cmp(other))
     }
 }
 
@@ -110,8 +126,64 @@ impl Ord for Row {
     }
 }
 
+/// annotate 写出的 .bin 文件以此开头, 最后一个字节为 [`Row`] 的格式版本.
+/// 没有文件头的 .bin 文件来自 kmer_id 为 u32 的 12 字节 Row (版本 1)
+pub const ROW_FILE_MAGIC: &[u8; 8] = b"K2ROWS\x00\x02";
+pub const ROW_FILE_HEADER_SIZE: u64 = ROW_FILE_MAGIC.len() as u64;
+
+/// 写入 .bin 文件头
+pub fn write_row_header<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(ROW_FILE_MAGIC)
+}
+
+/// 读取并检查 .bin 文件头, 之后 `reader` 位于第一条 Row. 空文件 (还没有写入命中) 也是有效的
+///
+/// ```
+/// use kraken2_rs::compact_hash::{read_row_header, write_row_header, ChunkRecord, Row};
+///
+/// let mut bytes = Vec::new();
+/// write_row_header(&mut bytes).unwrap();
+/// Row::new(1, 2, 3).extend_bytes(&mut bytes);
+/// let mut reader = &bytes[..];
+/// read_row_header(&mut reader).unwrap();
+/// assert_eq!(Row::decode(reader), Row::new(1, 2, 3));
+///
+/// assert!(read_row_header(&mut &[][..]).is_ok());
+/// assert!(read_row_header(&mut &bytes[8..]).is_err());
+/// ```
+pub fn read_row_header<R: Read>(reader: &mut R) -> Result<()> {
+    let mut magic = [0u8; ROW_FILE_MAGIC.len()];
+    let mut len = 0;
+    while len < magic.len() {
+        match reader.read(&mut magic[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if len == 0 {
+        return Ok(());
+    }
+    let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    if len < magic.len() || magic[..7] != ROW_FILE_MAGIC[..7] {
+        return invalid("no .bin file header, it was written by an older version".into());
+    }
+    match magic[7].cmp(&ROW_FILE_MAGIC[7]) {
+        CmpOrdering::Equal => Ok(()),
+        CmpOrdering::Greater => invalid(format!(
+            ".bin format version {} is newer than this program supports",
+            magic[7]
+        )),
+        CmpOrdering::Less => invalid(format!(
+            ".bin format version {} is no longer supported",
+            magic[7]
+        )),
+    }
+}
+
 #[repr(C)]
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Slot<B>
 where
     B: Compact,
@@ -127,11 +199,31 @@ where
     pub fn new(idx: usize, value: B) -> Self {
         Self { idx, value }
     }
+}
 
-    #[inline]
-    pub fn as_slice(&self, slot_size: usize) -> &[u8] {
-        let slot_ptr = self as *const Self as *const u8;
-        unsafe { std::slice::from_raw_parts(slot_ptr, slot_size) }
+/// splitr 写入 slot 的 seq_id: 高 32 位为样本编号, 低 32 位为 read 在样本中的序号 ([`Row::seq_id`]).
+/// 序号放不下 32 位时返回错误, 而不是覆盖样本编号
+///
+/// ```
+/// use kraken2_rs::compact_hash::sample_seq_id;
+///
+/// assert_eq!(sample_seq_id(2, 5).unwrap(), (2 << 32) | 5);
+/// assert!(sample_seq_id(1, 1 << 32).is_err());
+/// ```
+pub fn sample_seq_id(sample_index: usize, reads_index: usize) -> Result<u64> {
+    match (u32::try_from(sample_index), u32::try_from(reads_index)) {
+        (Ok(sample), Ok(read)) => Ok((sample as u64) << 32 | read as u64),
+        (Err(_), _) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("sample number {} is past 2^32 - 1", sample_index),
+        )),
+        (_, Err(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "sample {} has more than 2^32 reads, split it into several samples",
+                sample_index
+            ),
+        )),
     }
 }
 
@@ -139,6 +231,28 @@ impl Slot<u64> {
     pub fn get_seq_id(&self) -> u64 {
         self.value.right(0xFFFFFFFF)
     }
+
+    /// 将 minimizer 在 read 中的位置写入 idx 的高位, 低 `idx_bits` 位保留分块内的索引
+    ///
+    /// 超出剩余位数的位置取可以表示的最大值, 而不是截断: 之后的命中位置相同, 仍然排在前面的命中之后.
+    ///
+    /// ```
+    /// use kraken2_rs::compact_hash::Slot;
+    ///
+    /// let mut slot = Slot::<u64>::new(12345, 0);
+    /// let kmer_id = (1usize << 31) + 3;
+    /// slot.set_kmer_id(kmer_id, 20);
+    /// assert_eq!(slot.get_kmer_id(20), kmer_id as u64);
+    /// assert_eq!(slot.idx & ((1 << 20) - 1), 12345);
+    /// ```
+    pub fn set_kmer_id(&mut self, kmer_id: usize, idx_bits: usize) {
+        let kmer_id = kmer_id.min(usize::MAX >> idx_bits);
+        self.idx = kmer_id << idx_bits | (self.idx & ((1 << idx_bits) - 1));
+    }
+
+    pub fn get_kmer_id(&self, idx_bits: usize) -> u64 {
+        (self.idx >> idx_bits) as u64
+    }
 }
 
 // 实现 PartialOrd，只比较 index 字段
@@ -147,7 +261,7 @@ where
     B: Compact,
 {
     fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
-        self.idx.partial_cmp(&other.idx)
+        Some(self.cmp(other))
     }
 }
 
@@ -161,6 +275,151 @@ where
     }
 }
 
+/// 分块文件中的定长记录: splitr 写出的 sample chunk 与 build 的 k2 chunk 中的 [`Slot`], annotate 写出的 .bin 中的 [`Row`]
+///
+/// 磁盘格式与平台无关: 字段按声明顺序以小端序写出, `usize` 固定为 8 字节, 不足 8 字节的 `Slot` 值补 0.
+/// 这与 64 位小端平台 (x86_64, aarch64) 上的 `#[repr(C)]` 内存布局相同, 因此已有的分块文件仍可读取.
+///
+/// ```
+/// use kraken2_rs::compact_hash::{decode_records, ChunkRecord, Slot};
+///
+/// let slots = vec![Slot::<u32>::new(7, 42), Slot::<u32>::new(1 << 40, 3)];
+/// let mut bytes = Vec::new();
+/// for slot in &slots {
+///     slot.extend_bytes(&mut bytes);
+/// }
+/// assert_eq!(&bytes[..12], &[7, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0]);
+/// assert_eq!(decode_records::<Slot<u32>>(&bytes), slots);
+/// ```
+pub trait ChunkRecord: Copy + Send + Sync {
+    /// 每条记录的字节数
+    const SIZE: usize;
+
+    /// 写入 `out[..Self::SIZE]`
+    fn encode(&self, out: &mut [u8]);
+
+    /// 从 `bytes[..Self::SIZE]` 读取
+    fn decode(bytes: &[u8]) -> Self;
+
+    /// 追加到 `out` 的末尾
+    fn extend_bytes(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.resize(start + Self::SIZE, 0);
+        self.encode(&mut out[start..]);
+    }
+}
+
+impl ChunkRecord for Slot<u32> {
+    const SIZE: usize = 16;
+
+    fn encode(&self, out: &mut [u8]) {
+        LittleEndian::write_u64(&mut out[0..8], self.idx as u64);
+        LittleEndian::write_u32(&mut out[8..12], self.value);
+        out[12..16].fill(0);
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        Self::new(
+            LittleEndian::read_u64(&bytes[0..8]) as usize,
+            LittleEndian::read_u32(&bytes[8..12]),
+        )
+    }
+}
+
+impl ChunkRecord for Slot<u64> {
+    const SIZE: usize = 16;
+
+    fn encode(&self, out: &mut [u8]) {
+        LittleEndian::write_u64(&mut out[0..8], self.idx as u64);
+        LittleEndian::write_u64(&mut out[8..16], self.value);
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        Self::new(
+            LittleEndian::read_u64(&bytes[0..8]) as usize,
+            LittleEndian::read_u64(&bytes[8..16]),
+        )
+    }
+}
+
+impl ChunkRecord for Row {
+    const SIZE: usize = 16;
+
+    fn encode(&self, out: &mut [u8]) {
+        LittleEndian::write_u32(&mut out[0..4], self.value);
+        LittleEndian::write_u32(&mut out[4..8], self.seq_id);
+        LittleEndian::write_u64(&mut out[8..16], self.kmer_id);
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        Self::new(
+            LittleEndian::read_u32(&bytes[0..4]),
+            LittleEndian::read_u32(&bytes[4..8]),
+            LittleEndian::read_u64(&bytes[8..16]),
+        )
+    }
+}
+
+/// 解码 `bytes` 中的全部完整记录, 末尾不足一条记录的字节被忽略
+pub fn decode_records<T: ChunkRecord>(bytes: &[u8]) -> Vec<T> {
+    bytes.chunks_exact(T::SIZE).map(T::decode).collect()
+}
+
+/// 按批读取分块文件中的记录
+///
+/// 每批最多 `batch_size` 条. `read` 返回的字节不足一条记录时留到下一批, 不会把记录截断;
+/// 文件末尾残缺的记录被丢弃.
+pub struct RecordReader<R: Read, T: ChunkRecord> {
+    reader: R,
+    buffer: Vec<u8>,
+    /// 缓冲区中上一次读取剩下的字节数
+    pending: usize,
+    _record: std::marker::PhantomData<T>,
+}
+
+impl<R: Read, T: ChunkRecord> RecordReader<R, T> {
+    pub fn new(reader: R, batch_size: usize) -> Self {
+        Self {
+            reader,
+            buffer: vec![0u8; T::SIZE * batch_size.max(1)],
+            pending: 0,
+            _record: std::marker::PhantomData,
+        }
+    }
+
+    /// 下一批记录, 文件结束时为 `None`
+    pub fn next_batch(&mut self) -> Result<Option<Vec<T>>> {
+        loop {
+            let bytes_read = match self.reader.read(&mut self.buffer[self.pending..]) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let filled = self.pending + bytes_read;
+            let complete = filled / T::SIZE * T::SIZE;
+            if complete == 0 {
+                if bytes_read == 0 {
+                    return Ok(None);
+                }
+                self.pending = filled;
+                continue;
+            }
+            let records = decode_records(&self.buffer[..complete]);
+            self.buffer.copy_within(complete..filled, 0);
+            self.pending = filled - complete;
+            return Ok(Some(records));
+        }
+    }
+}
+
+impl<R: Read, T: ChunkRecord> Iterator for RecordReader<R, T> {
+    type Item = Result<Vec<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_batch().transpose()
+    }
+}
+
 use std::fmt::{self, Debug};
 
 #[derive(Clone, Copy)]
@@ -218,7 +477,12 @@ impl HashConfig {
         }
     }
 
-    pub fn write_to_file<P: AsRef<Path>>(&self, file_path: P) -> Result<()> {
+    /// 写入 hash_config.k2d, `checksums` 写在 48 字节的配置之后, 旧版本读取时忽略
+    pub fn write_to_file<P: AsRef<Path>>(
+        &self,
+        file_path: P,
+        checksums: Option<&ShardChecksums>,
+    ) -> Result<()> {
         // 打开文件用于写入
         let file = File::create(file_path)?;
         let mut writer = BufWriter::new(file);
@@ -228,11 +492,22 @@ impl HashConfig {
         writer.write_u64::<LittleEndian>(self.capacity as u64)?;
         writer.write_u64::<LittleEndian>(self.size as u64)?;
         writer.write_u64::<LittleEndian>(self.value_bits as u64)?;
+        if let Some(checksums) = checksums {
+            writer.write_all(SHARD_CHECKSUMS_MAGIC)?;
+            writer.write_u64::<LittleEndian>(checksums.0.len() as u64)?;
+            for &checksum in &checksums.0 {
+                writer.write_u64::<LittleEndian>(checksum)?;
+            }
+        }
         writer.flush()?;
         Ok(())
     }
 
     pub fn from_kraken2_header<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        Self::read_kraken2_header(&filename).map_err(|e| Kr2rError::index(&filename, e).into())
+    }
+
+    fn read_kraken2_header<P: AsRef<Path>>(filename: P) -> Result<Self> {
         let mut file = OpenOptions::new().read(true).open(&filename)?;
         let capacity = file.read_u64::<LittleEndian>()? as usize;
         let size = file.read_u64::<LittleEndian>()? as usize;
@@ -241,8 +516,22 @@ impl HashConfig {
         Ok(Self::new(0, capacity, value_bits, size, 0, 0))
     }
 
+    /// 读取 hash_config.k2d, 文件不存在或内容不完整时为 [`Kr2rError`]
     pub fn from_hash_header<P: AsRef<Path>>(filename: P) -> Result<Self> {
-        let mut file = OpenOptions::new().read(true).open(&filename)?;
+        Self::read_hash_header(&filename).map_err(|e| Kr2rError::index(&filename, e).into())
+    }
+
+    fn read_hash_header<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        let file = OpenOptions::new().read(true).open(&filename)?;
+        Self::parse_hash_header(file)
+    }
+
+    /// 从内存中 hash_config.k2d 的内容读取
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::parse_hash_header(bytes)
+    }
+
+    fn parse_hash_header<R: Read>(mut file: R) -> Result<Self> {
         let version = file.read_u64::<LittleEndian>()? as usize;
         let partition = file.read_u64::<LittleEndian>()? as usize;
         let hash_capacity = file.read_u64::<LittleEndian>()? as usize;
@@ -296,6 +585,184 @@ impl HashConfig {
     }
 }
 
+/// hash_config.k2d 中 shard 校验和段的标记, 最后一个字节为版本
+const SHARD_CHECKSUMS_MAGIC: &[u8; 8] = b"K2XXH64\x01";
+/// hash_config.k2d 中配置部分的字节数
+const HASH_CONFIG_SIZE: u64 = 48;
+
+/// 数据 `bytes` 的 xxhash64 (seed 0)
+pub fn checksum_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// 一个 shard 文件 (16 字节的文件头与全部单元) 的 xxhash64, 与对整个文件计算的结果相同
+///
+/// ```
+/// use kraken2_rs::compact_hash::{checksum_bytes, shard_checksum};
+///
+/// let cells = [0u32, 7, 0, 9];
+/// let mut file = Vec::new();
+/// file.extend_from_slice(&2u64.to_le_bytes());
+/// file.extend_from_slice(&4u64.to_le_bytes());
+/// cells.iter().for_each(|cell| file.extend_from_slice(&cell.to_le_bytes()));
+/// assert_eq!(shard_checksum(2, &cells), checksum_bytes(&file));
+/// ```
+pub fn shard_checksum(index: usize, cells: &[u32]) -> u64 {
+    let mut hasher = XxHash64::with_seed(0);
+    hasher.write(&(index as u64).to_le_bytes());
+    hasher.write(&(cells.len() as u64).to_le_bytes());
+    // 与 read_page_data 相同, 单元按小端序存放
+    let bytes = unsafe {
+        std::slice::from_raw_parts(cells.as_ptr() as *const u8, std::mem::size_of_val(cells))
+    };
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// 逐块读取文件计算 xxhash64
+pub fn file_checksum<P: AsRef<Path>>(path: P) -> Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = XxHash64::with_seed(0);
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buffer[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+/// 写入的同时计算 xxhash64, 用于写 shard 文件
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: XxHash64,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: XxHash64::with_seed(0),
+        }
+    }
+
+    /// 到目前为止写入内容的 xxhash64
+    pub fn checksum(&self) -> u64 {
+        self.hasher.finish()
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// hash_config.k2d 中记录的每个 hash_*.k2d 文件的 xxhash64, 第 i 个为 hash_{i+1}.k2d
+///
+/// ```
+/// use kraken2_rs::compact_hash::{HashConfig, ShardChecksums};
+///
+/// let path = std::env::temp_dir().join("shard_checksums_doctest.k2d");
+/// let config = HashConfig::new(1, 100, 16, 10, 2, 50);
+/// config.write_to_file(&path, None).unwrap();
+/// assert_eq!(ShardChecksums::from_hash_header(&path).unwrap(), None);
+///
+/// let checksums = ShardChecksums(vec![11, 22]);
+/// config.write_to_file(&path, Some(&checksums)).unwrap();
+/// assert_eq!(HashConfig::from_hash_header(&path).unwrap().partition, 2);
+/// let read = ShardChecksums::from_hash_header(&path).unwrap().unwrap();
+/// assert_eq!(read.get(2), Some(22));
+/// assert!(read.verify("hash_1.k2d", 1, 11).is_ok());
+/// assert!(read.verify("hash_1.k2d", 1, 12).is_err());
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShardChecksums(pub Vec<u64>);
+
+impl ShardChecksums {
+    /// 读取 hash_config.k2d 中的校验和, 旧版本写的文件没有校验和时为 `None`
+    pub fn from_hash_header<P: AsRef<Path>>(filename: P) -> Result<Option<Self>> {
+        Self::read_hash_header(&filename).map_err(|e| Kr2rError::index(&filename, e).into())
+    }
+
+    fn read_hash_header<P: AsRef<Path>>(filename: P) -> Result<Option<Self>> {
+        let file = File::open(&filename)?;
+        if file.metadata()?.len() <= HASH_CONFIG_SIZE {
+            return Ok(None);
+        }
+        let mut reader = BufReader::new(file);
+        io::copy(&mut reader.by_ref().take(HASH_CONFIG_SIZE), &mut io::sink())?;
+        Self::parse(reader).map(Some)
+    }
+
+    /// 从内存中 hash_config.k2d 的内容读取, 没有校验和时为 `None`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Option<Self>> {
+        match bytes.get(HASH_CONFIG_SIZE as usize..) {
+            Some(checksums) if !checksums.is_empty() => Self::parse(checksums).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn parse<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic[..7] != SHARD_CHECKSUMS_MAGIC[..7] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unknown data after the hash table configuration",
+            ));
+        }
+        if magic[7] > SHARD_CHECKSUMS_MAGIC[7] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "shard checksums version {} is newer than this program supports",
+                    magic[7]
+                ),
+            ));
+        }
+        let count = reader.read_u64::<LittleEndian>()? as usize;
+        let checksums = (0..count)
+            .map(|_| reader.read_u64::<LittleEndian>())
+            .collect::<Result<Vec<u64>>>()?;
+        Ok(Self(checksums))
+    }
+
+    /// hash_{index}.k2d 的校验和
+    pub fn get(&self, index: usize) -> Option<u64> {
+        index.checked_sub(1).and_then(|i| self.0.get(i)).copied()
+    }
+
+    /// 比较 hash_{index}.k2d 算出的校验和与记录的校验和, 不一致时为 [`Kr2rError::CorruptChunk`]
+    pub fn verify<P: AsRef<Path>>(&self, path: P, index: usize, checksum: u64) -> Result<()> {
+        match self.get(index) {
+            Some(expected) if expected != checksum => {
+                let e = io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "xxhash64 {:016x} does not match {:016x} recorded in hash_config.k2d \
+                         (the shard may have been damaged in transfer, --no-verify skips the check)",
+                        checksum, expected
+                    ),
+                );
+                Err(Kr2rError::chunk(path, e).into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 fn read_first_block_from_file<P: AsRef<Path>>(filename: P) -> Result<Page> {
     let mut file = std::fs::File::open(filename)?;
 
@@ -313,14 +780,10 @@ fn read_first_block_from_file<P: AsRef<Path>>(filename: P) -> Result<Page> {
 
     while read_pos < capacity {
         let end = usize::min(read_pos + chunk_size, capacity);
-        let bytes_to_read = (end - read_pos) * std::mem::size_of::<u32>();
-        let mut chunk = vec![0u8; bytes_to_read];
-        file.read_exact(&mut chunk)?;
-        let chunk_u32 =
-            unsafe { std::slice::from_raw_parts(chunk.as_ptr() as *const u32, end - read_pos) };
-        data[read_pos..end].copy_from_slice(chunk_u32);
-
-        if let Some(pos) = chunk_u32.iter().position(|&x| x == 0) {
+        // 直接读入按 u32 对齐的 data, 不把 Vec<u8> 强转为 u32 (aarch64 等平台上可能未对齐)
+        read_page_data(&mut file, &mut data[read_pos..end])?;
+
+        if let Some(pos) = data[read_pos..end].iter().position(|&x| x == 0) {
             first_zero_end = read_pos + pos + 1;
             found_zero = true;
             break;
@@ -330,7 +793,7 @@ fn read_first_block_from_file<P: AsRef<Path>>(filename: P) -> Result<Page> {
 
     if !found_zero {
         first_zero_end = capacity;
-        eprintln!("Warning: No zero value found in the data, using full capacity.");
+        warn!("No zero value found in the data, using full capacity.");
     }
 
     data.truncate(first_zero_end);
@@ -344,18 +807,16 @@ fn read_page_metadata(file: &mut File) -> Result<(usize, usize)> {
     Ok((index, capacity))
 }
 
+/// 单元按小端序存放, 与 x86_64 和 aarch64 的主机字节序相同
 fn read_page_data(file: &mut File, data: &mut [u32]) -> Result<()> {
     let data_bytes = unsafe {
-        std::slice::from_raw_parts_mut(
-            data.as_mut_ptr() as *mut u8,
-            data.len() * std::mem::size_of::<u32>(),
-        )
+        std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, std::mem::size_of_val(data))
     };
     file.read_exact(data_bytes)?;
     Ok(())
 }
 
-fn read_page_from_file<P: AsRef<Path>>(filename: P) -> Result<Page> {
+pub fn read_page_from_file<P: AsRef<Path>>(filename: P) -> Result<Page> {
     let mut file = std::fs::File::open(filename)?;
     let (index, capacity) = read_page_metadata(&mut file)?;
     let mut data = vec![0u32; capacity];
@@ -388,21 +849,27 @@ fn read_large_page_from_file<P: AsRef<Path>>(large_page: &mut Page, filename: P)
     Ok(())
 }
 
+/// 读入第 `page_index` 个 hash 文件 (从 0 开始), 给出 `checksums` 时检查文件的校验和
 pub fn read_next_page<P: AsRef<Path> + Debug>(
     large_page: &mut Page,
-    hash_sorted_files: &Vec<P>,
+    hash_sorted_files: &[P],
     page_index: usize,
     config: HashConfig,
+    checksums: Option<&ShardChecksums>,
 ) -> Result<()> {
     let mut hash_file = &hash_sorted_files[page_index];
     let parition = config.partition;
     read_large_page_from_file(large_page, hash_file)?;
+    if let Some(checksums) = checksums {
+        let checksum = shard_checksum(large_page.index, &large_page.data);
+        checksums.verify(hash_file, page_index + 1, checksum)?;
+    }
 
-    let next_page = if large_page.data.last().map_or(false, |&x| x != 0) {
+    let next_page = if large_page.data.last().is_some_and(|&x| x != 0) {
         if config.version < 1 {
             hash_file = &hash_sorted_files[(page_index + 1) % parition]
         }
-        read_first_block_from_file(&hash_file)?
+        read_first_block_from_file(hash_file)?
     } else {
         Page::default()
     };
@@ -411,6 +878,180 @@ pub fn read_next_page<P: AsRef<Path> + Debug>(
     Ok(())
 }
 
+/// 以内存映射方式打开 hash 文件, 不读入内存
+pub fn map_page_from_file<P: AsRef<Path>>(filename: P) -> Result<MappedPage> {
+    let file = File::open(&filename)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    if mmap.len() < 16 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} is too short", filename.as_ref()),
+        ));
+    }
+    let index = LittleEndian::read_u64(&mmap[0..8]) as usize;
+    let capacity = LittleEndian::read_u64(&mmap[8..16]) as usize;
+    if mmap.len() < 16 + capacity * std::mem::size_of::<u32>() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} is truncated", filename.as_ref()),
+        ));
+    }
+    Ok(MappedPage {
+        index,
+        size: capacity,
+        capacity,
+        mmap,
+        tail: Vec::new(),
+    })
+}
+
+/// 与 `read_next_page` 相同, 但以内存映射方式打开, 只有接在末尾的下一个 shard 的开头读入内存.
+/// 检查校验和时会读取整个文件
+pub fn map_next_page<P: AsRef<Path> + Debug>(
+    hash_sorted_files: &[P],
+    page_index: usize,
+    config: HashConfig,
+    checksums: Option<&ShardChecksums>,
+) -> Result<MappedPage> {
+    let mut hash_file = &hash_sorted_files[page_index];
+    let mut page = map_page_from_file(hash_file)?;
+    if let Some(checksums) = checksums {
+        checksums.verify(hash_file, page_index + 1, checksum_bytes(&page.mmap))?;
+    }
+
+    let next_page = if page.cells().last().is_some_and(|&x| x != 0) {
+        if config.version < 1 {
+            hash_file = &hash_sorted_files[(page_index + 1) % config.partition]
+        }
+        read_first_block_from_file(hash_file)?
+    } else {
+        Page::default()
+    };
+    page.merge(next_page);
+
+    Ok(page)
+}
+
+/// 线性探测查找 `compacted_key`, 遇到空单元时结束
+fn probe_cells<F>(
+    cell_at: F,
+    size: usize,
+    index: usize,
+    compacted_key: u32,
+    value_bits: usize,
+    value_mask: usize,
+) -> u32
+where
+    F: Fn(usize) -> Option<u32>,
+{
+    let mut idx = index;
+    if idx >= size {
+        return 0;
+    }
+
+    loop {
+        if let Some(cell) = cell_at(idx) {
+            if cell.right(value_mask) == 0 || cell.left(value_bits) == compacted_key {
+                return cell.right(value_mask);
+            }
+
+            idx += 1;
+            if idx >= size {
+                break;
+            }
+        } else {
+            return 0;
+        }
+    }
+    0
+}
+
+/// 一批查找中重复出现的 minimizer 的结果缓存, 高覆盖度或扩增子数据中同一 minimizer
+/// 在一批 reads 里出现很多次, 命中时不再探测 hash 表.
+///
+/// 直接映射, 冲突时覆盖旧的结果, 大小固定. 分区内的下标不小于 2^32 - 1 时不缓存
+///
+/// ```
+/// use kraken2_rs::compact_hash::LookupCache;
+///
+/// let mut cache = LookupCache::new(1024);
+/// let mut probes = 0;
+/// for _ in 0..3 {
+///     let taxid = cache.get_or_insert_with(42, 7, || {
+///         probes += 1;
+///         562
+///     });
+///     assert_eq!(taxid, 562);
+/// }
+/// assert_eq!((probes, cache.hits(), cache.lookups()), (1, 2, 3));
+///
+/// // 大小为 0 时不缓存
+/// let mut cache = LookupCache::new(0);
+/// assert_eq!(cache.get_or_insert_with(42, 7, || 562), 562);
+/// assert_eq!(cache.hits(), 0);
+/// ```
+pub struct LookupCache {
+    /// 分区内的下标 (高 32 位) 与 compacted key (低 32 位), 空位为 `u64::MAX`
+    keys: Vec<u64>,
+    values: Vec<u32>,
+    hits: u64,
+    lookups: u64,
+}
+
+/// `--lookup-cache` 的默认项数, 约 192 KiB, 放得进 L2 缓存
+pub const LOOKUP_CACHE_ENTRIES: usize = 1 << 14;
+
+impl LookupCache {
+    /// `entries` 向上取整为 2 的幂, 0 表示不缓存
+    pub fn new(entries: usize) -> Self {
+        let entries = if entries == 0 {
+            0
+        } else {
+            entries.next_power_of_two()
+        };
+        Self {
+            keys: vec![u64::MAX; entries],
+            values: vec![0; entries],
+            hits: 0,
+            lookups: 0,
+        }
+    }
+
+    /// 查找 `index` 处的 `compacted`, 没有缓存时调用 `lookup` (探测 hash 表) 并记下结果
+    pub fn get_or_insert_with<F: FnOnce() -> u32>(
+        &mut self,
+        index: usize,
+        compacted: u32,
+        lookup: F,
+    ) -> u32 {
+        self.lookups += 1;
+        // u32::MAX 的下标与空位的标记冲突
+        if self.keys.is_empty() || index >= u32::MAX as usize {
+            return lookup();
+        }
+        let key = ((index as u64) << 32) | compacted as u64;
+        // 下标由 minimizer 的 hash 得到, 已经均匀分布
+        let pos = index & (self.keys.len() - 1);
+        if self.keys[pos] == key {
+            self.hits += 1;
+            return self.values[pos];
+        }
+        let value = lookup();
+        self.keys[pos] = key;
+        self.values[pos] = value;
+        value
+    }
+
+    /// 由缓存回答的查找次数
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn lookups(&self) -> u64 {
+        self.lookups
+    }
+}
+
 #[derive(Clone)]
 pub struct Page {
     pub index: usize,
@@ -433,6 +1074,46 @@ impl Page {
         Self { index, size, data }
     }
 
+    /// 从内存中 hash 文件的内容读取, 不要求按 u32 对齐
+    ///
+    /// ```
+    /// use kraken2_rs::compact_hash::Page;
+    ///
+    /// let mut bytes = vec![];
+    /// bytes.extend_from_slice(&1u64.to_le_bytes());
+    /// bytes.extend_from_slice(&2u64.to_le_bytes());
+    /// bytes.extend_from_slice(&[7, 0, 0, 0, 0, 0, 0, 0]);
+    /// let page = Page::from_bytes(&bytes).unwrap();
+    /// assert_eq!((page.index, page.size, page.data), (1, 2, vec![7, 0]));
+    /// assert!(Page::from_bytes(&bytes[..20]).is_err());
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let truncated = || {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "hash table shard is truncated",
+            )
+        };
+        let header = bytes.get(..16).ok_or_else(truncated)?;
+        let index = LittleEndian::read_u64(&header[0..8]) as usize;
+        let capacity = LittleEndian::read_u64(&header[8..16]) as usize;
+        let cells = capacity
+            .checked_mul(std::mem::size_of::<u32>())
+            .and_then(|len| bytes.get(16..16 + len))
+            .ok_or_else(truncated)?;
+        let data = cells.chunks_exact(4).map(LittleEndian::read_u32).collect();
+        Ok(Self::new(index, capacity, data))
+    }
+
+    /// 接在上一个 shard 末尾的开头部分: 到第一个空单元 (含) 为止, 与 `read_first_block_from_file` 相同
+    fn first_block(&self) -> Self {
+        let end = self.data[..self.size]
+            .iter()
+            .position(|&x| x == 0)
+            .map_or(self.size, |pos| pos + 1);
+        Self::new(self.index, end, self.data[..end].to_vec())
+    }
+
     pub fn start(&self) -> usize {
         self.index * self.size
     }
@@ -457,71 +1138,297 @@ impl Page {
         value_bits: usize,
         value_mask: usize,
     ) -> u32 {
-        let mut idx = index;
-        if idx >= self.size {
-            return 0;
-        }
+        probe_cells(
+            |idx| self.data.get(idx).copied(),
+            self.size,
+            index,
+            compacted_key,
+            value_bits,
+            value_mask,
+        )
+    }
+}
 
-        loop {
-            if let Some(cell) = self.data.get(idx) {
-                if cell.right(value_mask) == 0 || cell.left(value_bits) == compacted_key {
-                    return cell.right(value_mask);
-                }
+/// 内存映射的 hash 文件, 同一节点上的多个进程共享 page cache, 而不是各自读入整个 shard
+pub struct MappedPage {
+    pub index: usize,
+    /// 可查找的单元数, 包括接在末尾的 `tail`
+    pub size: usize,
+    capacity: usize,
+    mmap: Mmap,
+    tail: Vec<u32>,
+}
 
-                idx = idx + 1;
-                if idx >= self.size {
-                    break;
-                }
-            } else {
-                return 0;
+impl MappedPage {
+    /// 文件中的单元
+    pub fn cells(&self) -> &[u32] {
+        let bytes = &self.mmap[16..16 + self.capacity * std::mem::size_of::<u32>()];
+        // mmap 按页对齐, 跳过 16 字节的文件头后仍按 u32 对齐
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const u32, self.capacity) }
+    }
+
+    pub fn merge(&mut self, other: Page) {
+        self.tail.extend_from_slice(&other.data[..other.size]);
+        self.size += other.size;
+    }
+
+    pub fn find_index(
+        &self,
+        index: usize,
+        compacted_key: u32,
+        value_bits: usize,
+        value_mask: usize,
+    ) -> u32 {
+        let cells = self.cells();
+        probe_cells(
+            |idx| match cells.get(idx) {
+                Some(&cell) => Some(cell),
+                None => self.tail.get(idx - cells.len()).copied(),
+            },
+            self.size,
+            index,
+            compacted_key,
+            value_bits,
+            value_mask,
+        )
+    }
+}
+
+/// 按需读取单元的 hash 表页的数据来源, 例如对象存储中的 shard (见 `remote_db`)
+pub trait CellSource: Send + Sync {
+    /// 第 `idx` 个单元 (包括接在末尾的下一个 shard 的开头), 超出范围或读取失败时为 None
+    fn cell(&self, idx: usize) -> Option<u32>;
+
+    /// 当前在内存中的字节数
+    fn byte_size(&self) -> usize;
+
+    /// 读取单元时的第一个错误, 出错后查找结果不完整
+    fn take_error(&self) -> Option<io::Error>;
+}
+
+/// 按需读取的 hash 表页, 不需要先读入整个 shard
+pub struct LazyPage {
+    pub index: usize,
+    /// 可查找的单元数, 包括接在末尾的下一个 shard 的开头
+    pub size: usize,
+    pub source: Box<dyn CellSource>,
+}
+
+impl LazyPage {
+    pub fn find_index(
+        &self,
+        index: usize,
+        compacted_key: u32,
+        value_bits: usize,
+        value_mask: usize,
+    ) -> u32 {
+        probe_cells(
+            |idx| self.source.cell(idx),
+            self.size,
+            index,
+            compacted_key,
+            value_bits,
+            value_mask,
+        )
+    }
+}
+
+/// 读入内存 (`Page`), 内存映射 (`MappedPage`) 或按需读取 (`LazyPage`) 的 hash 表页
+pub enum HashPage {
+    Loaded(Page),
+    Mapped(MappedPage),
+    Lazy(LazyPage),
+}
+
+impl HashPage {
+    /// 页占用的字节数
+    pub fn byte_size(&self) -> usize {
+        match self {
+            HashPage::Loaded(page) => page.data.len() * std::mem::size_of::<u32>(),
+            HashPage::Mapped(page) => {
+                page.mmap.len() + page.tail.len() * std::mem::size_of::<u32>()
+            }
+            HashPage::Lazy(page) => page.source.byte_size(),
+        }
+    }
+
+    /// 将页锁定在内存中 (mlock), 不会被换出
+    #[cfg(unix)]
+    pub fn lock(&self) -> Result<()> {
+        fn lock_cells(cells: &[u32]) -> Result<()> {
+            if cells.is_empty() {
+                return Ok(());
             }
+            let len = std::mem::size_of_val(cells);
+            let ret = unsafe { libc::mlock(cells.as_ptr() as *const libc::c_void, len) };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+        match self {
+            HashPage::Loaded(page) => lock_cells(&page.data),
+            HashPage::Mapped(page) => {
+                page.mmap.lock()?;
+                lock_cells(&page.tail)
+            }
+            HashPage::Lazy(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "lazily read hash tables can't be locked in memory",
+            )),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn lock(&self) -> Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "locking memory is only supported on unix systems",
+        ))
+    }
+
+    pub fn find_index(
+        &self,
+        index: usize,
+        compacted_key: u32,
+        value_bits: usize,
+        value_mask: usize,
+    ) -> u32 {
+        match self {
+            HashPage::Loaded(page) => page.find_index(index, compacted_key, value_bits, value_mask),
+            HashPage::Mapped(page) => page.find_index(index, compacted_key, value_bits, value_mask),
+            HashPage::Lazy(page) => page.find_index(index, compacted_key, value_bits, value_mask),
         }
-        0
     }
 }
 
 #[allow(unused)]
 pub struct CHTable {
     pub config: HashConfig,
-    pub pages: Vec<Page>,
+    pub pages: Vec<HashPage>,
 }
 
 impl CHTable {
+    /// 读入全部 hash 文件, 给出 `checksums` 时检查每个文件的校验和
     pub fn from_hash_files<P: AsRef<Path> + Debug>(
         config: HashConfig,
-        hash_sorted_files: &Vec<P>,
+        hash_sorted_files: &[P],
+        checksums: Option<&ShardChecksums>,
     ) -> Result<CHTable> {
         let end = hash_sorted_files.len();
-        Self::from_range(config, hash_sorted_files, 0, end)
+        Self::from_range(config, hash_sorted_files, 0, end, checksums)
+    }
+
+    /// 以内存映射方式打开全部 hash 文件 (`--memory-mapping`)
+    pub fn map_hash_files<P: AsRef<Path> + Debug>(
+        config: HashConfig,
+        hash_sorted_files: &[P],
+        checksums: Option<&ShardChecksums>,
+    ) -> Result<CHTable> {
+        let pages = (0..hash_sorted_files.len())
+            .map(|i| map_next_page(hash_sorted_files, i, config, checksums).map(HashPage::Mapped))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(CHTable { config, pages })
+    }
+
+    /// 从内存中按顺序的 hash_1.k2d, hash_2.k2d, ... 的内容建立 hash 表, 不读取文件系统,
+    /// 给出 `checksums` 时检查每个 shard 的校验和
+    pub fn from_hash_bytes<B: AsRef<[u8]>>(
+        config: HashConfig,
+        shards: &[B],
+        checksums: Option<&ShardChecksums>,
+    ) -> Result<CHTable> {
+        let pages = shards
+            .iter()
+            .map(|bytes| Page::from_bytes(bytes.as_ref()))
+            .collect::<Result<Vec<Page>>>()?;
+        if let Some(checksums) = checksums {
+            for (i, page) in pages.iter().enumerate() {
+                let checksum = shard_checksum(page.index, &page.data);
+                checksums.verify(format!("hash_{}.k2d", i + 1), i + 1, checksum)?;
+            }
+        }
+        // 与 from_range 相同: 末尾不是空单元时接上下一个 (版本 0) 或本 shard 的开头
+        let next_pages: Vec<Page> = (0..pages.len())
+            .map(|i| {
+                if pages[i].data.last().is_some_and(|&x| x != 0) {
+                    let next = if config.version < 1 {
+                        (i + 1) % pages.len()
+                    } else {
+                        i
+                    };
+                    pages[next].first_block()
+                } else {
+                    Page::default()
+                }
+            })
+            .collect();
+        let pages = pages
+            .into_iter()
+            .zip(next_pages)
+            .map(|(mut page, next_page)| {
+                page.merge(next_page);
+                HashPage::Loaded(page)
+            })
+            .collect();
+        Ok(CHTable { config, pages })
     }
 
     pub fn from_range<P: AsRef<Path> + Debug>(
         config: HashConfig,
-        hash_sorted_files: &Vec<P>,
+        hash_sorted_files: &[P],
         start: usize,
         end: usize,
+        checksums: Option<&ShardChecksums>,
     ) -> Result<CHTable> {
-        let mut pages = vec![Page::default(); start];
+        let mut pages: Vec<HashPage> = (0..start)
+            .map(|_| HashPage::Loaded(Page::default()))
+            .collect();
         let parition = hash_sorted_files.len();
         for i in start..end {
             let mut hash_file = &hash_sorted_files[i];
-            let mut page = read_page_from_file(&hash_file)?;
-            let next_page = if page.data.last().map_or(false, |&x| x != 0) {
+            let mut page = read_page_from_file(hash_file)?;
+            if let Some(checksums) = checksums {
+                checksums.verify(hash_file, i + 1, shard_checksum(page.index, &page.data))?;
+            }
+            let next_page = if page.data.last().is_some_and(|&x| x != 0) {
                 if config.version < 1 {
                     hash_file = &hash_sorted_files[(i + 1) % parition]
                 }
-                read_first_block_from_file(&hash_file)?
+                read_first_block_from_file(hash_file)?
             } else {
                 Page::default()
             };
             page.merge(next_page);
-            pages.push(page);
+            pages.push(HashPage::Loaded(page));
         }
 
         let chtm = CHTable { config, pages };
         Ok(chtm)
     }
 
+    /// 已载入的 hash 表占用的字节数
+    pub fn byte_size(&self) -> usize {
+        self.pages.iter().map(HashPage::byte_size).sum()
+    }
+
+    /// 按需读取的页在读取单元时的第一个错误, 其他页没有错误
+    pub fn check_pages(&self) -> Result<()> {
+        for page in &self.pages {
+            if let HashPage::Lazy(page) = page {
+                if let Some(e) = page.source.take_error() {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 将所有页锁定在内存中 (`--mlock`)
+    pub fn lock_in_memory(&self) -> Result<()> {
+        self.pages.iter().try_for_each(HashPage::lock)
+    }
+
     pub fn get_from_page(&self, indx: usize, compacted: u32, page_index: usize) -> u32 {
         if let Some(page) = self.pages.get(page_index) {
             page.find_index(
@@ -535,3 +1442,97 @@ impl CHTable {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LARGE_KMER_IDS: [u64; 5] = [
+        1 << 31,
+        (1 << 31) + 1,
+        u32::MAX as u64,
+        (1 << 32) + 7,
+        u64::MAX,
+    ];
+
+    #[test]
+    fn row_round_trips_large_kmer_ids() {
+        let rows: Vec<Row> = LARGE_KMER_IDS
+            .iter()
+            .map(|&kmer_id| Row::new(0xDEAD_BEEF, u32::MAX, kmer_id))
+            .collect();
+        let mut bytes = Vec::new();
+        for row in &rows {
+            row.extend_bytes(&mut bytes);
+        }
+        assert_eq!(bytes.len(), rows.len() * Row::SIZE);
+        assert_eq!(decode_records::<Row>(&bytes), rows);
+
+        let mut sorted = rows.clone();
+        sorted.reverse();
+        sorted.sort();
+        assert_eq!(sorted, rows);
+    }
+
+    #[test]
+    fn slot_round_trips_large_kmer_ids() {
+        let idx_bits = 20;
+        for kmer_id in LARGE_KMER_IDS.map(|kmer_id| kmer_id.min(1 << 40) as usize) {
+            let mut slot = Slot::<u64>::new(0xABCDE, 42);
+            slot.set_kmer_id(kmer_id, idx_bits);
+            let mut bytes = Vec::new();
+            slot.extend_bytes(&mut bytes);
+            let back = Slot::<u64>::decode(&bytes);
+            assert_eq!(back, slot);
+            assert_eq!(back.get_kmer_id(idx_bits), kmer_id as u64);
+            assert_eq!(back.idx & ((1 << idx_bits) - 1), 0xABCDE);
+            assert_eq!(back.value, 42);
+        }
+    }
+
+    #[test]
+    fn set_kmer_id_saturates() {
+        let idx_bits = 20;
+        let mut slot = Slot::<u64>::new(0xABCDE, 0);
+        slot.set_kmer_id(usize::MAX, idx_bits);
+        assert_eq!(slot.get_kmer_id(idx_bits), (usize::MAX >> idx_bits) as u64);
+        assert_eq!(slot.idx & ((1 << idx_bits) - 1), 0xABCDE);
+
+        let mut last = Slot::<u64>::new(0xABCDE, 0);
+        last.set_kmer_id(usize::MAX >> idx_bits, idx_bits);
+        assert_eq!(last, slot);
+    }
+
+    #[test]
+    fn sample_seq_id_keeps_the_sample_bits() {
+        for read in [0, 1 << 31, u32::MAX as usize] {
+            let seq_id = sample_seq_id(7, read).unwrap();
+            // annotate 取 seq_id 的低 32 位写入 Row
+            let slot = Slot::<u64>::new(0, seq_id);
+            assert_eq!(slot.get_seq_id() as u32, read as u32);
+            assert_eq!(seq_id >> 32, 7);
+        }
+        assert!(sample_seq_id(7, 1 << 32).is_err());
+        assert!(sample_seq_id(7, usize::MAX).is_err());
+        assert!(sample_seq_id(1 << 32, 0).is_err());
+    }
+
+    #[test]
+    fn row_header_rejects_other_versions() {
+        let mut bytes = Vec::new();
+        write_row_header(&mut bytes).unwrap();
+        assert_eq!(bytes.len() as u64, ROW_FILE_HEADER_SIZE);
+        assert!(read_row_header(&mut &bytes[..]).is_ok());
+
+        // 版本 1: 没有文件头的 12 字节 Row
+        let old: Vec<u8> = (0..24).collect();
+        assert!(read_row_header(&mut &old[..]).is_err());
+        assert!(read_row_header(&mut &bytes[..4]).is_err());
+
+        let mut newer = bytes.clone();
+        newer[7] += 1;
+        let e = read_row_header(&mut &newer[..]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("newer"));
+    }
+}
@@ -1,20 +1,143 @@
 // 使用时需要引用模块路径
-use crate::compact_hash::{Compact, HashConfig, Slot};
+use crate::args::BatchSize;
+use crate::compact_hash::{
+    read_page_from_file, shard_checksum, ChecksumWriter, ChunkRecord, Compact, HashConfig,
+    RecordReader, ShardChecksums, Slot,
+};
 // use crate::mmscanner::MinimizerScanner;
 use crate::taxonomy::{NCBITaxonomy, Taxonomy};
-use seqkmer::{read_parallel, BufferFastaReader, Meros};
+use seqkmer::{read_parallel_with, Base, BufferFastaReader, Dust, Meros};
 
 use crate::utils::open_file;
 use byteorder::{LittleEndian, WriteBytesExt};
 use rayon::prelude::*;
-use std::collections::HashMap;
-use std::fs::File;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
 use std::io::{BufReader, BufWriter, Read, Result as IOResult, Write};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
-// 定义每批次处理的 Cell 数量
-const BATCH_SIZE: usize = 81920;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use tracing::info;
+/// 默认每批次处理的 Cell 数量
+pub const BATCH_SIZE: usize = 81920;
+
+/// 构建时记录 minimizer 频率上限的文件, 列在 checksums.txt 清单中
+pub const MINIMIZER_CAP_FILE: &str = "minimizer_cap.txt";
+
+/// 构建时丢弃出现在太多基因组中的 minimizer (高度保守, 只会被分类到很高的层级).
+///
+/// 基因组按 chunk 中连续的相同 taxid 计数, 同一 taxid 的多条序列 (染色体, 质粒, contig)
+/// 算作一个基因组. 两个上限都给出时取较小的一个
+///
+/// ```
+/// use kraken2_rs::db::MinimizerCap;
+///
+/// let cap = MinimizerCap { max_genomes: Some(50), max_fraction: Some(0.1) };
+/// assert_eq!(cap.limit(200), Some(20));
+/// assert_eq!(cap.limit(10_000), Some(50));
+/// assert_eq!(MinimizerCap::default().limit(200), None);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MinimizerCap {
+    /// 最多出现在多少个基因组中
+    pub max_genomes: Option<u32>,
+    /// 最多出现在 library 中多大比例的基因组中
+    pub max_fraction: Option<f64>,
+}
+
+impl MinimizerCap {
+    pub fn is_set(&self) -> bool {
+        self.max_genomes.is_some() || self.max_fraction.is_some()
+    }
+
+    /// library 有 `genomes` 个基因组时, 保留的 minimizer 最多出现在的基因组数
+    pub fn limit(&self, genomes: usize) -> Option<u32> {
+        let fraction = self
+            .max_fraction
+            .map(|fraction| ((fraction * genomes as f64).floor() as u32).max(1));
+        match (self.max_genomes, fraction) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// 写出 [`MINIMIZER_CAP_FILE`]: 上限, library 的基因组数, 实际的上限与丢弃的 minimizer 数
+pub fn write_minimizer_cap(
+    database: &Path,
+    cap: &MinimizerCap,
+    genomes: usize,
+    dropped: usize,
+) -> IOResult<()> {
+    let mut writer = BufWriter::new(File::create(database.join(MINIMIZER_CAP_FILE))?);
+    let option = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    writeln!(
+        writer,
+        "max_genomes\t{}",
+        option(cap.max_genomes.map(|n| n.to_string()))
+    )?;
+    writeln!(
+        writer,
+        "max_fraction\t{}",
+        option(cap.max_fraction.map(|f| f.to_string()))
+    )?;
+    writeln!(writer, "library_genomes\t{}", genomes)?;
+    writeln!(
+        writer,
+        "limit\t{}",
+        option(cap.limit(genomes).map(|n| n.to_string()))
+    )?;
+    writeln!(writer, "dropped_minimizers\t{}", dropped)?;
+    writer.flush()
+}
+
+/// 读取 [`MINIMIZER_CAP_FILE`] 的 `名称\t值` 行, 没有该文件时为空
+pub fn read_minimizer_cap(database: &Path) -> IOResult<Vec<(String, String)>> {
+    let path = database.join(MINIMIZER_CAP_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(path)?;
+    Ok(text
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect())
+}
+
+/// 单元中 minimizer 的基因组计数 (高 32 位为上一个 taxid, 低 32 位为基因组数):
+/// taxid 与上一个不同时加一
+fn count_genome(counter: &AtomicU64, taxid: u32) {
+    let _ = counter.fetch_update(Ordering::SeqCst, Ordering::Relaxed, |current| {
+        let (last, genomes) = ((current >> 32) as u32, current as u32);
+        (last != taxid).then(|| ((taxid as u64) << 32) | genomes.saturating_add(1) as u64)
+    });
+}
+
+/// 把 `value` 合并到单元的当前值 `current`: 空单元直接写入, 相同的 key 取 LCA, 其他 key 时为 `None`
+fn merge_cell(
+    taxonomy: &Taxonomy,
+    current: u32,
+    value: u32,
+    value_bits: usize,
+    value_mask: usize,
+) -> Option<u32> {
+    let item_taxid: u32 = value.right(value_mask).to_u32();
+    let compact_key = value.left(value_bits);
+    let current_taxid = current.right(value_mask).to_u32();
+    let current_key = current.left(value_bits);
+
+    if current == 0 || current_taxid == u32::default() {
+        Some(value)
+    } else if current_key == compact_key {
+        let new_taxid = taxonomy.lca(item_taxid, current_taxid);
+        Some(u32::combined(compact_key, new_taxid, value_bits))
+    } else {
+        None // 当前值不匹配，尝试下一个索引
+    }
+}
 
+/// 把单元写入 page, 返回写入的位置; 与其他 key 冲突时为 `None`
 fn set_page_cell(
     taxonomy: &Taxonomy,
     page: &[AtomicU32],
@@ -22,28 +145,19 @@ fn set_page_cell(
     page_size: usize,
     value_bits: usize,
     value_mask: usize,
-) {
+) -> Option<usize> {
     let mut idx = item.idx % page_size;
-    let item_taxid: u32 = item.value.right(value_mask).to_u32();
-    let compact_key = item.value.left(value_bits);
     let first_idx = idx;
 
     loop {
         let result = page[idx].fetch_update(Ordering::SeqCst, Ordering::Relaxed, |current| {
-            let current_taxid = current.right(value_mask).to_u32();
-            let current_key = current.left(value_bits);
-
-            if current == 0 || current_taxid == u32::default() {
-                Some(item.value)
-            } else if current_key == compact_key {
-                let new_taxid = taxonomy.lca(item_taxid, current_taxid);
-                Some(u32::combined(compact_key, new_taxid, value_bits))
-            } else {
-                None // 当前值不匹配，尝试下一个索引
-            }
+            merge_cell(taxonomy, current, item.value, value_bits, value_mask)
         });
 
-        if result.is_ok() || idx == first_idx {
+        if result.is_ok() {
+            return Some(idx);
+        }
+        if idx == first_idx {
             break;
         }
 
@@ -52,6 +166,7 @@ fn set_page_cell(
             break;
         }
     }
+    None
 }
 
 fn write_hashtable_to_file(
@@ -59,10 +174,10 @@ fn write_hashtable_to_file(
     file_path: &PathBuf,
     page_index: u64,
     capacity: u64,
-) -> IOResult<usize> {
+) -> IOResult<(usize, u64)> {
     // 打开文件用于写入
     let file = File::create(file_path)?;
-    let mut writer = BufWriter::new(file);
+    let mut writer = ChecksumWriter::new(BufWriter::new(file));
     let mut count = 0;
     writer.write_u64::<LittleEndian>(page_index)?;
     writer.write_u64::<LittleEndian>(capacity)?;
@@ -77,17 +192,225 @@ fn write_hashtable_to_file(
     }
 
     writer.flush()?; // 确保所有内容都被写入文件
-    Ok(count)
+    Ok((count, writer.checksum()))
+}
+
+/// 外部排序时每个 run 至少的单元数
+const MIN_RUN_CELLS: usize = 4096;
+/// 多路归并时每个 run 至少读取的单元数
+const MIN_MERGE_CELLS: usize = 1024;
+
+/// 外部排序的临时 run 文件, 写在数据库目录中, shard 写完后删除
+fn run_file(database: &Path, page_index: usize, run: usize) -> PathBuf {
+    database.join(format!("build_run_{}_{}.tmp", page_index, run))
+}
+
+/// 外部排序已写出的 run 文件, drop 时删除, 构建中途出错时也不会留在数据库目录中
+struct RunFiles(Vec<PathBuf>);
+
+impl Drop for RunFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// 把按单元位置 (shard 内) 稳定排序的 `cells` 写成一个 run 文件
+fn write_run(path: &Path, cells: &mut [Slot<u32>], capacity: usize) -> IOResult<()> {
+    cells.par_sort_by_key(|slot| slot.idx % capacity);
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut bytes = Vec::with_capacity(Slot::<u32>::SIZE);
+    for slot in cells.iter() {
+        bytes.clear();
+        Slot::new(slot.idx % capacity, slot.value).extend_bytes(&mut bytes);
+        writer.write_all(&bytes)?;
+    }
+    writer.flush()
+}
+
+/// 逐条读取一个 run 文件
+struct RunReader {
+    reader: RecordReader<BufReader<File>, Slot<u32>>,
+    batch: std::vec::IntoIter<Slot<u32>>,
 }
 
+impl RunReader {
+    fn open(path: &Path, batch_size: usize) -> IOResult<Self> {
+        Ok(Self {
+            reader: RecordReader::new(BufReader::new(File::open(path)?), batch_size),
+            batch: Vec::new().into_iter(),
+        })
+    }
+
+    fn next_slot(&mut self) -> IOResult<Option<Slot<u32>>> {
+        loop {
+            if let Some(slot) = self.batch.next() {
+                return Ok(Some(slot));
+            }
+            match self.reader.next_batch()? {
+                Some(batch) => self.batch = batch.into_iter(),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+fn write_zero_cells<W: Write>(writer: &mut W, cells: usize) -> IOResult<()> {
+    let zeros = [0u8; 4096];
+    let mut bytes = cells * 4;
+    while bytes > 0 {
+        let n = bytes.min(zeros.len());
+        writer.write_all(&zeros[..n])?;
+        bytes -= n;
+    }
+    Ok(())
+}
+
+/// shard 放不进 `max_memory` 时的构建: chunk 的单元分批按位置排序后写到临时 run 文件,
+/// 再多路归并, 按位置顺序逐个单元写出 shard, 内存中只有 run 的缓冲区.
+///
+/// 同一位置的单元按在 chunk 中的顺序合并 (在内存中构建时为并行处理的顺序),
+/// 与其他 key 冲突时也是先写入的单元保留
+#[allow(clippy::too_many_arguments)]
+fn build_page_external<R: Read>(
+    config: HashConfig,
+    database: &Path,
+    chunk: R,
+    cells_per_batch: usize,
+    taxonomy: &Taxonomy,
+    page_file: &Path,
+    page_index: usize,
+    capacity: usize,
+    max_memory: usize,
+    max_genomes: Option<u32>,
+) -> IOResult<(usize, u64, usize)> {
+    // 排序需要约一倍的额外内存
+    let run_cells = (max_memory / (3 * Slot::<u32>::SIZE)).max(MIN_RUN_CELLS);
+    let mut reader = RecordReader::<_, Slot<u32>>::new(chunk, cells_per_batch.min(run_cells));
+    let mut runs = RunFiles(Vec::new());
+    let mut cells = Vec::new();
+    while let Some(batch) = reader.next_batch()? {
+        cells.extend(batch);
+        if cells.len() >= run_cells {
+            let path = run_file(database, page_index, runs.0.len());
+            runs.0.push(path.clone());
+            write_run(&path, &mut cells, capacity)?;
+            cells.clear();
+        }
+    }
+    if !cells.is_empty() {
+        let path = run_file(database, page_index, runs.0.len());
+        runs.0.push(path.clone());
+        write_run(&path, &mut cells, capacity)?;
+    }
+    drop(cells);
+    info!(
+        "shard {}: {} cells do not fit in {} bytes, merging {} sorted runs",
+        page_index,
+        capacity,
+        max_memory,
+        runs.0.len()
+    );
+
+    let merge_cells =
+        (max_memory / (2 * Slot::<u32>::SIZE * runs.0.len().max(1))).max(MIN_MERGE_CELLS);
+    let mut readers = runs
+        .0
+        .iter()
+        .map(|path| RunReader::open(path, merge_cells))
+        .collect::<IOResult<Vec<_>>>()?;
+    // 位置相同时先取编号小的 run, 保持 chunk 中的顺序
+    let mut heap = BinaryHeap::new();
+    for (run, reader) in readers.iter_mut().enumerate() {
+        if let Some(slot) = reader.next_slot()? {
+            heap.push(Reverse((slot.idx, run, slot.value)));
+        }
+    }
+
+    let file = File::create(page_file)?;
+    let mut writer = ChecksumWriter::new(BufWriter::new(file));
+    writer.write_u64::<LittleEndian>(page_index as u64)?;
+    writer.write_u64::<LittleEndian>(capacity as u64)?;
+    let mut count = 0;
+    let mut dropped = 0;
+    // 下一个要写出的位置, 与正在合并的单元
+    let mut next_idx = 0;
+    let mut current: Option<(usize, u32)> = None;
+    let genomes = AtomicU64::new(0);
+    let mut write_cell = |writer: &mut ChecksumWriter<BufWriter<File>>, idx: usize, value: u32| {
+        let over_limit =
+            max_genomes.is_some_and(|limit| genomes.load(Ordering::Relaxed) as u32 > limit);
+        let value = if over_limit {
+            dropped += 1;
+            0
+        } else {
+            value
+        };
+        write_zero_cells(writer, idx - next_idx)?;
+        writer.write_u32::<LittleEndian>(value)?;
+        if value != 0 {
+            count += 1;
+        }
+        next_idx = idx + 1;
+        IOResult::Ok(())
+    };
+    while let Some(Reverse((idx, run, value))) = heap.pop() {
+        if let Some(slot) = readers[run].next_slot()? {
+            heap.push(Reverse((slot.idx, run, slot.value)));
+        }
+        let taxid = value.right(config.value_mask).to_u32();
+        current = match current {
+            Some((current_idx, current_value)) if current_idx == idx => {
+                match merge_cell(
+                    taxonomy,
+                    current_value,
+                    value,
+                    config.value_bits,
+                    config.value_mask,
+                ) {
+                    Some(merged) => {
+                        count_genome(&genomes, taxid);
+                        Some((idx, merged))
+                    }
+                    None => Some((idx, current_value)),
+                }
+            }
+            previous => {
+                if let Some((previous_idx, previous_value)) = previous {
+                    write_cell(&mut writer, previous_idx, previous_value)?;
+                }
+                genomes.store(0, Ordering::Relaxed);
+                count_genome(&genomes, taxid);
+                Some((idx, value))
+            }
+        };
+    }
+    if let Some((idx, value)) = current {
+        write_cell(&mut writer, idx, value)?;
+    }
+    write_zero_cells(&mut writer, capacity - next_idx)?;
+    writer.flush()?;
+
+    drop(readers);
+    drop(runs);
+    Ok((count, writer.checksum(), dropped))
+}
+
+/// 由 chunk 文件构建 hash_{page_index}.k2d, 返回写入的单元数, shard 的校验和,
+/// 与因出现在超过 `max_genomes` 个基因组中而丢弃的 minimizer 数
+#[allow(clippy::too_many_arguments)]
 pub fn process_k2file(
     config: HashConfig,
-    database: &PathBuf,
+    database: &Path,
     chunk_file: &PathBuf,
     taxonomy: &Taxonomy,
     page_size: usize,
     page_index: usize,
-) -> IOResult<usize> {
+    batch_size: BatchSize,
+    max_memory: Option<usize>,
+    max_genomes: Option<u32>,
+) -> IOResult<(usize, u64, usize)> {
     let total_counter = AtomicUsize::new(0);
 
     let value_mask = config.value_mask;
@@ -99,54 +422,176 @@ pub fn process_k2file(
     let capacity = end_index - start_index;
     let page_file = database.join(format!("hash_{}.k2d", page_index));
 
-    let page: Vec<AtomicU32> = (0..capacity).map(|_| AtomicU32::new(0)).collect();
-
-    let file = open_file(&chunk_file)?;
-    let mut reader = BufReader::new(file);
-
-    let cell_size = std::mem::size_of::<Slot<u32>>();
-    let batch_buffer_size = cell_size * BATCH_SIZE;
-    let mut batch_buffer = vec![0u8; batch_buffer_size];
-
-    while let Ok(bytes_read) = reader.read(&mut batch_buffer) {
-        if bytes_read == 0 {
-            break;
-        } // 文件末尾
+    let file = open_file(chunk_file)?;
+    let file_len = file.metadata()?.len();
+    let cells_per_batch = batch_size.resolve(file_len, Slot::<u32>::SIZE);
+
+    if let Some(max_memory) = max_memory.filter(|&limit| capacity * 4 > limit) {
+        return build_page_external(
+            config,
+            database,
+            BufReader::new(file),
+            cells_per_batch,
+            taxonomy,
+            &page_file,
+            page_index,
+            capacity,
+            max_memory,
+            max_genomes,
+        );
+    }
 
-        // 处理读取的数据批次
-        let cells_in_batch = bytes_read / cell_size;
+    let mut reader = RecordReader::<_, Slot<u32>>::new(BufReader::new(file), cells_per_batch);
+    let page: Vec<AtomicU32> = (0..capacity).map(|_| AtomicU32::new(0)).collect();
+    // 每个单元的基因组计数, 只在有上限时分配
+    let genomes: Vec<AtomicU64> = match max_genomes {
+        Some(_) => (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+        None => Vec::new(),
+    };
 
-        let cells = unsafe {
-            std::slice::from_raw_parts(batch_buffer.as_ptr() as *const Slot<u32>, cells_in_batch)
-        };
+    while let Some(cells) = reader.next_batch()? {
         cells.par_iter().for_each(|item| {
-            set_page_cell(taxonomy, &page, item, capacity, value_bits, value_mask);
+            if let Some(counter) =
+                set_page_cell(taxonomy, &page, item, capacity, value_bits, value_mask)
+                    .and_then(|idx| genomes.get(idx))
+            {
+                count_genome(counter, item.value.right(value_mask).to_u32());
+            }
         });
         total_counter.fetch_add(cells.len(), Ordering::SeqCst);
     }
 
-    let size_count =
+    let mut dropped = 0;
+    if let Some(limit) = max_genomes {
+        for (cell, counter) in page.iter().zip(&genomes) {
+            if counter.load(Ordering::Relaxed) as u32 > limit {
+                cell.store(0, Ordering::Relaxed);
+                dropped += 1;
+            }
+        }
+    }
+
+    let (count, checksum) =
         write_hashtable_to_file(&page, &page_file, page_index as u64, capacity as u64)?;
-    Ok(size_count)
+    Ok((count, checksum, dropped))
+}
+
+/// [`insert_k2file`] 向已有 shard 插入单元的统计
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InsertStats {
+    /// 写入空单元的新 minimizer
+    pub inserted: usize,
+    /// 已在 shard 中, LCA 改变的 minimizer
+    pub updated: usize,
+    /// 从单元位置到 shard 末尾没有空单元, 未能写入的 minimizer
+    pub dropped: usize,
+}
+
+/// 向后线性探测插入一个单元, 与查找 (`probe_cells`) 一样不回绕到 shard 开头
+fn insert_cell(
+    taxonomy: &Taxonomy,
+    cells: &mut [u32],
+    item: &Slot<u32>,
+    value_bits: usize,
+    value_mask: usize,
+    stats: &mut InsertStats,
+) {
+    for idx in item.idx % cells.len()..cells.len() {
+        let current = cells[idx];
+        if let Some(value) = merge_cell(taxonomy, current, item.value, value_bits, value_mask) {
+            if current == 0 || current.right(value_mask) == 0 {
+                stats.inserted += 1;
+            } else if value != current {
+                stats.updated += 1;
+            }
+            cells[idx] = value;
+            return;
+        }
+    }
+    stats.dropped += 1;
+}
+
+/// 把 chunk 文件中的单元插入已有的 hash_{page_index}.k2d, 与已有的 key 相同时取 LCA.
+///
+/// shard 整个读入内存, 有 `checksums` 时先校验. 新内容先写临时文件再重命名,
+/// 使用旧文件的进程 (如内存映射) 不受影响. 返回统计与新 shard 的 xxhash64
+pub fn insert_k2file(
+    config: HashConfig,
+    chunk_file: &Path,
+    page_file: &Path,
+    page_index: usize,
+    taxonomy: &Taxonomy,
+    batch_size: BatchSize,
+    checksums: Option<&ShardChecksums>,
+) -> IOResult<(InsertStats, u64)> {
+    let mut page = read_page_from_file(page_file)?;
+    if let Some(checksums) = checksums {
+        checksums.verify(
+            page_file,
+            page_index,
+            shard_checksum(page.index, &page.data),
+        )?;
+    }
+
+    let file = open_file(chunk_file)?;
+    let file_len = file.metadata()?.len();
+    let cells_per_batch = batch_size.resolve(file_len, Slot::<u32>::SIZE);
+    let mut reader = RecordReader::<_, Slot<u32>>::new(BufReader::new(file), cells_per_batch);
+    let mut stats = InsertStats::default();
+    while let Some(cells) = reader.next_batch()? {
+        for item in &cells {
+            insert_cell(
+                taxonomy,
+                &mut page.data,
+                item,
+                config.value_bits,
+                config.value_mask,
+                &mut stats,
+            );
+        }
+    }
+
+    let mut tmp = page_file.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let file = File::create(&tmp)?;
+    let mut writer = ChecksumWriter::new(BufWriter::new(file));
+    writer.write_u64::<LittleEndian>(page.index as u64)?;
+    writer.write_u64::<LittleEndian>(page.data.len() as u64)?;
+    for &value in &page.data {
+        writer.write_u32::<LittleEndian>(value)?;
+    }
+    writer.flush()?;
+    let checksum = writer.checksum();
+    drop(writer);
+    fs::rename(tmp, page_file)?;
+    Ok((stats, checksum))
 }
 
 /// 生成taxonomy树文件
 pub fn generate_taxonomy(
-    ncbi_taxonomy_directory: &PathBuf,
+    ncbi_taxonomy_directory: &Path,
     taxonomy_filename: &PathBuf,
     id_map: &HashMap<String, u64>,
 ) -> IOResult<Taxonomy> {
     let nodes_filename = ncbi_taxonomy_directory.join("nodes.dmp");
     let names_filename = ncbi_taxonomy_directory.join("names.dmp");
-    let mut ncbi = NCBITaxonomy::from_ncbi(nodes_filename, names_filename)?;
+    let ncbi = NCBITaxonomy::from_ncbi(nodes_filename, names_filename)?;
+    write_taxonomy(ncbi, taxonomy_filename, id_map)
+}
 
-    for (_, id) in id_map.into_iter() {
+/// 保留 `id_map` 用到的节点及其祖先, 写入 taxonomy树文件 (如 `NCBITaxonomy::from_gtdb` 生成的分类树)
+pub fn write_taxonomy(
+    mut ncbi: NCBITaxonomy,
+    taxonomy_filename: &PathBuf,
+    id_map: &HashMap<String, u64>,
+) -> IOResult<Taxonomy> {
+    for (_, id) in id_map.iter() {
         ncbi.mark_node(*id);
     }
     let mut taxo = ncbi.convert_to_kraken_taxonomy();
     taxo.generate_external_to_internal_id_map();
     taxo.build_path_cache();
-    taxo.write_to_disk(&taxonomy_filename)?;
+    taxo.write_to_disk(taxonomy_filename)?;
 
     Ok(taxo)
 }
@@ -160,32 +605,40 @@ pub fn get_bits_for_taxid(
     let bits_needed_for_value = (node_count.log2().ceil() as usize).max(1);
 
     // 检查是否需要更多位来存储taxid
-    if requested_bits_for_taxid > 0 && bits_needed_for_value > requested_bits_for_taxid as usize {
+    if requested_bits_for_taxid > 0 && bits_needed_for_value > requested_bits_for_taxid {
         return Err("more bits required for storing taxid".to_string());
     }
 
     Ok(bits_needed_for_value.max(requested_bits_for_taxid))
 }
 
+/// 用 DUST 屏蔽参考序列的低复杂度区域, 屏蔽的碱基不提取 minimizer
+pub fn mask_sequence(masker: Option<Dust>, seq: &mut Base<Vec<u8>>) {
+    if let Some(dust) = masker {
+        seq.body.apply_mut(|body| dust.mask(body));
+    }
+}
+
 /// 将fna文件转换成k2格式的临时文件
+#[allow(clippy::too_many_arguments)]
 pub fn convert_fna_to_k2_format<P: AsRef<Path>>(
     fna_file: P,
     meros: Meros,
     taxonomy: &Taxonomy,
     id_to_taxon_map: &HashMap<String, u64>,
     hash_config: HashConfig,
-    writers: &mut Vec<BufWriter<File>>,
+    writers: &mut [BufWriter<File>],
     chunk_size: usize,
     threads: usize,
+    masker: Option<Dust>,
 ) {
     let mut reader = BufferFastaReader::from_path(fna_file, 1).unwrap();
     let value_bits = hash_config.value_bits;
-    let cell_size = std::mem::size_of::<Slot<u32>>();
-
-    read_parallel(
+    read_parallel_with(
         &mut reader,
         threads,
         &meros,
+        |seq| mask_sequence(masker, seq),
         |seqs| {
             let mut k2_cell_list = Vec::new();
 
@@ -215,10 +668,12 @@ pub fn convert_fna_to_k2_format<P: AsRef<Path>>(
         |record_sets| {
             while let Some(data) = record_sets.next() {
                 let k2_cell_map = data.unwrap();
+                let mut bytes = [0u8; Slot::<u32>::SIZE];
                 for cell in k2_cell_map {
                     let partition_index = cell.0;
                     if let Some(writer) = writers.get_mut(partition_index) {
-                        writer.write_all(&cell.1.as_slice(cell_size)).unwrap();
+                        cell.1.encode(&mut bytes);
+                        writer.write_all(&bytes).unwrap();
                     }
                 }
             }
@@ -226,3 +681,117 @@ pub fn convert_fna_to_k2_format<P: AsRef<Path>>(
     )
     .expect("failed");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxonomy::TaxonomyNode;
+
+    const VALUE_BITS: usize = 16;
+    const CAPACITY: usize = 8192;
+
+    /// 0: 空节点, 1: root, 2 与 3: root 的子节点
+    fn taxonomy() -> Taxonomy {
+        let mut taxonomy = Taxonomy::default();
+        taxonomy.nodes = vec![
+            TaxonomyNode::default(),
+            TaxonomyNode {
+                external_id: 1,
+                ..Default::default()
+            },
+            TaxonomyNode {
+                parent_id: 1,
+                external_id: 562,
+                ..Default::default()
+            },
+            TaxonomyNode {
+                parent_id: 1,
+                external_id: 561,
+                ..Default::default()
+            },
+        ]
+        .into();
+        taxonomy
+    }
+
+    /// 超过 `MIN_RUN_CELLS` 几倍的单元, 位置超出 shard 容量;
+    /// 同一位置只有一个 key, 两种构建方式都不会遇到 key 冲突
+    fn write_chunk(dir: &Path) -> PathBuf {
+        let mut bytes = Vec::new();
+        for i in 0..5 * MIN_RUN_CELLS {
+            let idx = (i * 7919) % (6 * CAPACITY);
+            let key = (idx % CAPACITY % 1000) as u32 + 1;
+            let taxid = 2 + (i % 2) as u32;
+            Slot::new(idx, (key << VALUE_BITS) | taxid).extend_bytes(&mut bytes);
+        }
+        let path = dir.join("sample.k2");
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kr2r_db_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn run_files(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().ends_with(".tmp"))
+            .collect()
+    }
+
+    #[test]
+    fn external_build_matches_the_in_memory_build() {
+        let config = HashConfig::new(1, CAPACITY, VALUE_BITS, 0, 1, CAPACITY);
+        let taxonomy = taxonomy();
+        let mut shards = Vec::new();
+        for (name, max_memory) in [("memory", None), ("external", Some(1))] {
+            let dir = test_dir(name);
+            let chunk = write_chunk(&dir);
+            let result = process_k2file(
+                config,
+                &dir,
+                &chunk,
+                &taxonomy,
+                CAPACITY,
+                1,
+                BatchSize::Fixed(1000),
+                max_memory,
+                None,
+            )
+            .unwrap();
+            assert_eq!(run_files(&dir), Vec::<PathBuf>::new());
+            shards.push((result, fs::read(dir.join("hash_1.k2d")).unwrap()));
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        assert!(shards[0].0 .0 > 0);
+        assert_eq!(shards[0], shards[1]);
+    }
+
+    #[test]
+    fn external_build_removes_runs_on_error() {
+        let config = HashConfig::new(1, CAPACITY, VALUE_BITS, 0, 1, CAPACITY);
+        let dir = test_dir("external_error");
+        let chunk = write_chunk(&dir);
+        // shard 写到不存在的目录, 在所有 run 写出之后失败
+        let result = build_page_external(
+            config,
+            &dir,
+            BufReader::new(File::open(&chunk).unwrap()),
+            1000,
+            &taxonomy(),
+            &dir.join("missing").join("hash_1.k2d"),
+            1,
+            CAPACITY,
+            1,
+            None,
+        );
+        assert!(result.is_err());
+        assert_eq!(run_files(&dir), Vec::<PathBuf>::new());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+use crate::compact_hash::{ChunkRecord, Compact, HashConfig, HashPage, LookupCache, Slot};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, BufWriter, Read, Result, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+
+/// 连接开始时双方发送的协议标识
+const MAGIC: [u8; 4] = *b"K2SW";
+
+/// 协议版本, 不同版本的 coordinator 与 worker 不能通信
+const VERSION: u32 = 1;
+
+/// 应答中表示出错的记录数, 之后是错误信息的长度与内容
+const ERROR_REPLY: u64 = u64::MAX;
+
+/// coordinator 每次发送给 worker 的最多 slot 数 (16 MiB)
+pub const REMOTE_BATCH_SLOTS: usize = 1 << 20;
+
+/// 错误应答中错误信息的最大长度
+const MAX_ERROR_MESSAGE: usize = 64 * 1024;
+
+/// worker 连接时报告的数据库参数与持有的 shard (从 0 开始的分区序号)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerHello {
+    pub hash_capacity: usize,
+    pub value_bits: usize,
+    pub partition: usize,
+    pub shards: Vec<usize>,
+}
+
+impl WorkerHello {
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_u32::<LittleEndian>(VERSION)?;
+        writer.write_u64::<LittleEndian>(self.hash_capacity as u64)?;
+        writer.write_u64::<LittleEndian>(self.value_bits as u64)?;
+        writer.write_u64::<LittleEndian>(self.partition as u64)?;
+        writer.write_u64::<LittleEndian>(self.shards.len() as u64)?;
+        for &shard in &self.shards {
+            writer.write_u64::<LittleEndian>(shard as u64)?;
+        }
+        writer.flush()
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        read_magic(reader)?;
+        let hash_capacity = reader.read_u64::<LittleEndian>()? as usize;
+        let value_bits = reader.read_u64::<LittleEndian>()? as usize;
+        let partition = reader.read_u64::<LittleEndian>()? as usize;
+        let count = reader.read_u64::<LittleEndian>()? as usize;
+        let shards = (0..count)
+            .map(|_| {
+                reader
+                    .read_u64::<LittleEndian>()
+                    .map(|shard| shard as usize)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            hash_capacity,
+            value_bits,
+            partition,
+            shards,
+        })
+    }
+
+    /// 与本地 hash_config.k2d 不一致时返回错误, 例如 worker 使用了另一个数据库
+    pub fn check(&self, config: &HashConfig) -> Result<()> {
+        if self.hash_capacity != config.hash_capacity
+            || self.value_bits != config.value_bits
+            || self.partition != config.partition
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "worker database (capacity {}, value bits {}, {} shards) doesn't match hash_config.k2d (capacity {}, value bits {}, {} shards)",
+                    self.hash_capacity,
+                    self.value_bits,
+                    self.partition,
+                    config.hash_capacity,
+                    config.value_bits,
+                    config.partition
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn write_magic<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_u32::<LittleEndian>(VERSION)?;
+    writer.flush()
+}
+
+fn read_magic<R: Read>(reader: &mut R) -> Result<()> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the peer is not a kraken2_rs shard worker",
+        ));
+    }
+    let version = reader.read_u32::<LittleEndian>()?;
+    if version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "shard worker protocol version {}, expected {}",
+                version, VERSION
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// 在 `page` 中查找每个 slot 的 taxid, 没有找到为 0. 与 annotate 的查找相同, 在 `pool` 中并行
+pub fn lookup_slots(
+    pool: &ThreadPool,
+    page: &HashPage,
+    config: &HashConfig,
+    slots: &[Slot<u64>],
+    lookup_cache: usize,
+) -> Vec<u32> {
+    let value_mask = config.get_value_mask();
+    let value_bits = config.get_value_bits();
+    let idx_mask = config.get_idx_mask();
+    pool.install(|| {
+        slots
+            .par_chunks(64 * 1024)
+            .flat_map_iter(|slots| {
+                let mut cache = LookupCache::new(lookup_cache);
+                slots
+                    .iter()
+                    .map(|slot| {
+                        let indx = slot.idx & idx_mask;
+                        let compacted = slot.value.left(value_bits) as u32;
+                        cache.get_or_insert_with(indx, compacted, || {
+                            page.find_index(indx, compacted, value_bits, value_mask)
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    })
+}
+
+/// worker 一端: 回应一个 coordinator 连接的所有请求, coordinator 关闭连接时返回.
+///
+/// 每个请求为分区序号, slot 数与 slot 记录, 应答为相同个数的 taxid (u32).
+/// 一批超过 [`REMOTE_BATCH_SLOTS`] 个 slot 时关闭连接, 不按对方给出的长度分配内存
+pub fn serve_connection(
+    stream: TcpStream,
+    config: &HashConfig,
+    pages: &BTreeMap<usize, HashPage>,
+    lookup_cache: usize,
+    pool: &ThreadPool,
+) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    read_magic(&mut reader)?;
+    WorkerHello {
+        hash_capacity: config.hash_capacity,
+        value_bits: config.value_bits,
+        partition: config.partition,
+        shards: pages.keys().copied().collect(),
+    }
+    .write_to(&mut writer)?;
+
+    let mut bytes = Vec::new();
+    loop {
+        let shard = match reader.read_u64::<LittleEndian>() {
+            Ok(shard) => shard as usize,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let count = reader.read_u64::<LittleEndian>()?;
+        let len = usize::try_from(count)
+            .ok()
+            .filter(|&count| count <= REMOTE_BATCH_SLOTS)
+            .and_then(|count| count.checked_mul(Slot::<u64>::SIZE))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "request of {} slots, at most {} are allowed",
+                        count, REMOTE_BATCH_SLOTS
+                    ),
+                )
+            })?;
+        bytes.resize(len, 0);
+        reader.read_exact(&mut bytes)?;
+        let Some(page) = pages.get(&shard) else {
+            let message = format!("this worker doesn't hold hash_{}.k2d", shard + 1);
+            writer.write_u64::<LittleEndian>(ERROR_REPLY)?;
+            writer.write_u64::<LittleEndian>(message.len() as u64)?;
+            writer.write_all(message.as_bytes())?;
+            writer.flush()?;
+            continue;
+        };
+        let slots: Vec<Slot<u64>> = bytes
+            .chunks_exact(Slot::<u64>::SIZE)
+            .map(Slot::decode)
+            .collect();
+        let taxids = lookup_slots(pool, page, config, &slots, lookup_cache);
+        writer.write_u64::<LittleEndian>(taxids.len() as u64)?;
+        let mut out = vec![0u8; taxids.len() * 4];
+        LittleEndian::write_u32_into(&taxids, &mut out);
+        writer.write_all(&out)?;
+        writer.flush()?;
+    }
+}
+
+/// coordinator 一端的连接. 请求与应答分开读写, 发送下一批时不必等待上一批的应答
+pub struct WorkerConnection {
+    pub address: String,
+    pub hello: WorkerHello,
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl WorkerConnection {
+    pub fn connect(address: &str) -> Result<Self> {
+        let addr = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can't resolve worker address {}", address),
+            )
+        })?;
+        let stream = TcpStream::connect(addr).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("can't connect to worker {}: {}", address, e),
+            )
+        })?;
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        write_magic(&mut writer)?;
+        let hello = WorkerHello::read_from(&mut reader)?;
+        Ok(Self {
+            address: address.to_string(),
+            hello,
+            reader,
+            writer,
+        })
+    }
+
+    /// 拆分为请求与应答两端, 可以在不同线程中使用
+    pub fn split(&mut self) -> (WorkerSender<'_>, WorkerReceiver<'_>) {
+        (
+            WorkerSender {
+                writer: &mut self.writer,
+            },
+            WorkerReceiver {
+                address: &self.address,
+                reader: &mut self.reader,
+            },
+        )
+    }
+}
+
+pub struct WorkerSender<'a> {
+    writer: &'a mut BufWriter<TcpStream>,
+}
+
+impl WorkerSender<'_> {
+    /// 发送分区 `shard` 的一批 slot
+    pub fn send(&mut self, shard: usize, slots: &[Slot<u64>]) -> Result<()> {
+        self.writer.write_u64::<LittleEndian>(shard as u64)?;
+        self.writer.write_u64::<LittleEndian>(slots.len() as u64)?;
+        let mut bytes = vec![0u8; slots.len() * Slot::<u64>::SIZE];
+        for (slot, out) in slots.iter().zip(bytes.chunks_exact_mut(Slot::<u64>::SIZE)) {
+            slot.encode(out);
+        }
+        self.writer.write_all(&bytes)?;
+        self.writer.flush()
+    }
+}
+
+pub struct WorkerReceiver<'a> {
+    address: &'a str,
+    reader: &'a mut BufReader<TcpStream>,
+}
+
+impl WorkerReceiver<'_> {
+    /// 按发送的顺序读取一批 slot 的 taxid, `expected` 为这一批发送的 slot 数.
+    /// 应答的个数不同时返回错误, 不按对方给出的长度分配内存
+    pub fn receive(&mut self, expected: usize) -> Result<Vec<u32>> {
+        let count = self.reader.read_u64::<LittleEndian>()?;
+        if count == ERROR_REPLY {
+            let len = self.reader.read_u64::<LittleEndian>()?;
+            let len = usize::try_from(len)
+                .ok()
+                .filter(|&len| len <= MAX_ERROR_MESSAGE)
+                .ok_or_else(|| self.invalid(format!("error message of {} bytes", len)))?;
+            let mut message = vec![0u8; len];
+            self.reader.read_exact(&mut message)?;
+            return Err(io::Error::other(format!(
+                "worker {}: {}",
+                self.address,
+                String::from_utf8_lossy(&message)
+            )));
+        }
+        if count != expected as u64 {
+            return Err(self.invalid(format!("answered {} lookups for {} slots", count, expected)));
+        }
+        let mut bytes = vec![0u8; expected * 4];
+        self.reader.read_exact(&mut bytes)?;
+        let mut taxids = vec![0u32; expected];
+        LittleEndian::read_u32_into(&bytes, &mut taxids);
+        Ok(taxids)
+    }
+
+    /// 关闭连接的两端, 阻塞在发送上的线程随之返回错误
+    pub fn shutdown(&self) {
+        let _ = self.reader.get_ref().shutdown(Shutdown::Both);
+    }
+
+    fn invalid(&self, message: String) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("worker {}: {}", self.address, message),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact_hash::Page;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const VALUE_BITS: usize = 16;
+
+    fn config() -> HashConfig {
+        HashConfig::new(1, 64, VALUE_BITS, 1, 1, 64)
+    }
+
+    /// compacted key 在 u64 slot 中的位置: 低 32 位为序列号, 之后是 value_bits 位
+    fn slot(idx: usize, compacted: u64) -> Slot<u64> {
+        Slot::new(idx, compacted << (32 + VALUE_BITS))
+    }
+
+    /// 分区 0 的 cell 3 存放 compacted key 5 -> taxid 9
+    fn pages() -> BTreeMap<usize, HashPage> {
+        let mut page = Page::with_capacity(0, 64);
+        page.data[3] = (5 << VALUE_BITS) | 9;
+        BTreeMap::from([(0, HashPage::Loaded(page))])
+    }
+
+    /// 在本机的空闲端口上启动一个 worker, 只接受一个连接, 返回地址与 serve_connection 的结果
+    fn spawn_worker() -> (String, thread::JoinHandle<Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept()?;
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(1)
+                .build()
+                .unwrap();
+            serve_connection(stream, &config(), &pages(), 0, &pool)
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn hello_round_trips() {
+        let hello = WorkerHello {
+            hash_capacity: 64,
+            value_bits: VALUE_BITS,
+            partition: 4,
+            shards: vec![1, 3],
+        };
+        let mut bytes = Vec::new();
+        hello.write_to(&mut bytes).unwrap();
+        assert_eq!(
+            WorkerHello::read_from(&mut bytes.as_slice()).unwrap(),
+            hello
+        );
+    }
+
+    #[test]
+    fn read_magic_rejects_other_peers_and_versions() {
+        let err = read_magic(&mut &b"HTTP/1.1"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&(VERSION + 1).to_le_bytes());
+        let err = read_magic(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn lookups_round_trip() {
+        let (address, worker) = spawn_worker();
+        {
+            let mut connection = WorkerConnection::connect(&address).unwrap();
+            assert_eq!(connection.hello.shards, vec![0]);
+            connection.hello.check(&config()).unwrap();
+            let (mut sender, mut receiver) = connection.split();
+
+            let slots = vec![slot(3, 5), slot(3, 6), slot(10, 5)];
+            sender.send(0, &slots).unwrap();
+            assert_eq!(receiver.receive(slots.len()).unwrap(), vec![9, 0, 0]);
+
+            // 没有持有的分区得到错误应答, 连接仍然可用
+            sender.send(5, &slots).unwrap();
+            let err = receiver.receive(slots.len()).unwrap_err();
+            assert!(err.to_string().contains("hash_6.k2d"), "{}", err);
+
+            // 应答的个数与发送的不同
+            sender.send(0, &slots[..1]).unwrap();
+            let err = receiver.receive(2).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        worker.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn oversized_request_closes_the_connection() {
+        let (address, worker) = spawn_worker();
+        let mut stream = TcpStream::connect(&address).unwrap();
+        write_magic(&mut stream).unwrap();
+        WorkerHello::read_from(&mut stream).unwrap();
+        for count in [REMOTE_BATCH_SLOTS as u64 + 1, u64::MAX] {
+            stream.write_u64::<LittleEndian>(0).unwrap();
+            stream.write_u64::<LittleEndian>(count).unwrap();
+        }
+        let err = worker.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_request_is_an_error() {
+        let (address, worker) = spawn_worker();
+        {
+            let mut stream = TcpStream::connect(&address).unwrap();
+            write_magic(&mut stream).unwrap();
+            WorkerHello::read_from(&mut stream).unwrap();
+            stream.write_u64::<LittleEndian>(0).unwrap();
+            stream.write_u64::<LittleEndian>(4).unwrap();
+            stream.write_all(&[0u8; 8]).unwrap();
+        }
+        let err = worker.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::readcounts::TaxonCounters;
+use crate::taxonomy::Taxonomy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use tracing::warn;
+
+/// `--output-dir` 中的运行摘要
+pub const RUN_SUMMARY: &str = "run_summary.json";
+
+/// 分类结果的大类, Human 从 Eukaryota 中单独列出
+pub const DOMAINS: [&str; 7] = [
+    "Bacteria",
+    "Archaea",
+    "Eukaryota",
+    "Viruses",
+    "Human",
+    "Other",
+    "Unclassified",
+];
+const BACTERIA: usize = 0;
+const ARCHAEA: usize = 1;
+const EUKARYOTA: usize = 2;
+const VIRUSES: usize = 3;
+const HUMAN: usize = 4;
+const OTHER: usize = 5;
+const UNCLASSIFIED: usize = 6;
+
+/// NCBI taxid 或 GTDB (`d__` 前缀) 名称对应的大类
+fn node_domain(external_id: u64, name: &str) -> Option<usize> {
+    match (external_id, name.trim_start_matches("d__")) {
+        (9606, _) | (_, "Homo sapiens") => Some(HUMAN),
+        (2, _) | (_, "Bacteria") => Some(BACTERIA),
+        (2157, _) | (_, "Archaea") => Some(ARCHAEA),
+        (2759, _) | (_, "Eukaryota") => Some(EUKARYOTA),
+        (10239, _) | (_, "Viruses") => Some(VIRUSES),
+        _ => None,
+    }
+}
+
+/// 每个样本按大类统计的 reads, 输出到终端与 [`RUN_SUMMARY`]
+///
+/// ```
+/// use kraken2_rs::domains::DomainSummary;
+///
+/// let summary = DomainSummary::from_counts("output_1", &[10, 0, 5, 0, 80, 0, 5]);
+/// assert_eq!(summary.reads, 100);
+/// assert_eq!(summary.percent("Human"), Some(80.0));
+/// assert_eq!(summary.percent("Unclassified"), Some(5.0));
+///
+/// let summary = summary.with_metadata(&[("patient_id".to_string(), "P1".to_string())]);
+/// let json = serde_json::to_value(&summary).unwrap();
+/// assert_eq!(json["metadata"]["patient_id"], "P1");
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainSummary {
+    pub sample: String,
+    /// 样本表中的元数据 (patient_id, specimen_type 等), 没有时不写出
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    pub reads: u64,
+    /// 与 [`DOMAINS`] 顺序相同的 (大类, reads 数, 百分比)
+    pub domains: Vec<DomainCount>,
+    /// `--qc-gate` 的结果, 没有设置时不写出
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qc: Option<QcResult>,
+    /// resolve 因 id 不在 sample_id map 中而丢弃的 reads, 没有时不写出
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dropped_reads: Vec<DroppedReads>,
+}
+
+/// 一个 sample_file_*.bin 中命中了但 id 不在 sample_id map 中的 reads 数
+///
+/// ```
+/// use kraken2_rs::domains::{DomainSummary, DroppedReads};
+///
+/// let dropped = DroppedReads::new("temp_chunk/sample_file_1_0.bin", 3);
+/// let summary = DomainSummary::from_counts("output_1", &[0, 0, 0, 0, 0, 0, 10])
+///     .with_dropped_reads(&[dropped]);
+/// let json = serde_json::to_value(&summary).unwrap();
+/// assert_eq!(json["dropped_reads"][0]["chunk"], "sample_file_1_0.bin");
+/// assert_eq!(json["dropped_reads"][0]["reads"], 3);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DroppedReads {
+    pub chunk: String,
+    pub reads: u64,
+}
+
+impl DroppedReads {
+    pub fn new<P: AsRef<Path>>(chunk: P, reads: u64) -> Self {
+        Self {
+            chunk: chunk.as_ref().file_name().map_or_else(
+                || chunk.as_ref().display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            ),
+            reads,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QcResult {
+    pub pass: bool,
+    /// 未满足的条件, 例如 `classified 41.20% < 50%`
+    pub failures: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainCount {
+    pub domain: &'static str,
+    pub reads: u64,
+    pub percent: f64,
+}
+
+impl DomainSummary {
+    /// `counts` 与 [`DOMAINS`] 顺序相同
+    pub fn from_counts(sample: &str, counts: &[u64; DOMAINS.len()]) -> Self {
+        let reads: u64 = counts.iter().sum();
+        let domains = DOMAINS
+            .iter()
+            .zip(counts.iter())
+            .map(|(&domain, &count)| DomainCount {
+                domain,
+                reads: count,
+                percent: count as f64 * 100.0 / reads.max(1) as f64,
+            })
+            .collect();
+        Self {
+            sample: sample.to_string(),
+            metadata: BTreeMap::new(),
+            reads,
+            domains,
+            qc: None,
+            dropped_reads: Vec::new(),
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: &[(String, String)]) -> Self {
+        self.metadata.extend(metadata.iter().cloned());
+        self
+    }
+
+    pub fn with_dropped_reads(mut self, dropped: &[DroppedReads]) -> Self {
+        self.dropped_reads.extend(dropped.iter().cloned());
+        self
+    }
+
+    /// 由 internal taxid 的分类数统计, 沿谱系向上找到最近的大类
+    pub fn from_taxon_counts(
+        sample: &str,
+        taxonomy: &Taxonomy,
+        call_counters: &TaxonCounters,
+        unclassified: u64,
+    ) -> Self {
+        let mut counts = [0u64; DOMAINS.len()];
+        counts[UNCLASSIFIED] = unclassified;
+        for (&taxid, counter) in call_counters {
+            let domain = taxonomy
+                .lineage(taxid as u32)
+                .iter()
+                .rev()
+                .find_map(|&node| {
+                    let external_id = taxonomy.nodes.get(node as usize)?.external_id;
+                    node_domain(external_id, taxonomy.name(node))
+                })
+                .unwrap_or(OTHER);
+            counts[domain] += counter.read_count();
+        }
+        Self::from_counts(sample, &counts)
+    }
+
+    pub fn percent(&self, domain: &str) -> Option<f64> {
+        self.domains
+            .iter()
+            .find(|count| count.domain == domain)
+            .map(|count| count.percent)
+    }
+}
+
+/// `--qc-gate` 的一个条件所比较的值
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum QcMetric {
+    Reads,
+    /// 已分类 reads 的百分比
+    Classified,
+    /// [`DOMAINS`] 中一个大类的百分比
+    Domain(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct QcRule {
+    metric: QcMetric,
+    /// true 为 `>=`, false 为 `<=`
+    at_least: bool,
+    value: f64,
+}
+
+/// `--qc-gate` 的样本 QC 条件, 如 `reads>=100000,classified>=50,host<=10`.
+///
+/// 条件之间用逗号或空格分隔, 每个条件为 `名称>=值` 或 `名称<=值`: `reads` 为 reads 数,
+/// `classified` 为已分类的百分比, 大类名 (`bacteria`, `viruses`, `human`, `unclassified` 等,
+/// 不区分大小写) 为该大类的百分比, `host` 与 `human` 相同
+///
+/// ```
+/// use kraken2_rs::domains::{DomainSummary, QcGate};
+///
+/// let gate: QcGate = "reads>=100,classified>=90,host<=10".parse().unwrap();
+/// let passed = DomainSummary::from_counts("output_1", &[95, 0, 0, 0, 2, 0, 3]).with_qc(&gate);
+/// assert!(passed.qc.as_ref().unwrap().pass);
+/// let failed = DomainSummary::from_counts("output_2", &[10, 0, 0, 0, 20, 0, 20]).with_qc(&gate);
+/// let qc = failed.qc.unwrap();
+/// assert!(!qc.pass);
+/// assert_eq!(qc.failures, vec!["reads 50 < 100", "classified 60.00% < 90%", "host 40.00% > 10%"]);
+/// assert!("reads>100".parse::<QcGate>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct QcGate {
+    rules: Vec<(String, QcRule)>,
+}
+
+impl FromStr for QcGate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+        for part in s.split([' ', ',']).filter(|p| !p.is_empty()) {
+            let (name, at_least, value) = if let Some((name, value)) = part.split_once(">=") {
+                (name, true, value)
+            } else if let Some((name, value)) = part.split_once("<=") {
+                (name, false, value)
+            } else {
+                return Err(format!(
+                    "invalid QC condition '{}', expected name>=value or name<=value",
+                    part
+                ));
+            };
+            let name = name.to_ascii_lowercase();
+            let metric = match name.as_str() {
+                "reads" => QcMetric::Reads,
+                "classified" => QcMetric::Classified,
+                "host" => QcMetric::Domain(HUMAN),
+                _ => match DOMAINS.iter().position(|d| d.eq_ignore_ascii_case(&name)) {
+                    Some(domain) => QcMetric::Domain(domain),
+                    None => return Err(format!("unknown QC metric '{}'", name)),
+                },
+            };
+            let value = value
+                .trim_end_matches('%')
+                .parse::<f64>()
+                .map_err(|_| format!("invalid QC threshold '{}'", value))?;
+            rules.push((
+                name,
+                QcRule {
+                    metric,
+                    at_least,
+                    value,
+                },
+            ));
+        }
+        if rules.is_empty() {
+            return Err("empty QC gate".to_string());
+        }
+        Ok(Self { rules })
+    }
+}
+
+impl QcGate {
+    /// 样本未满足的条件
+    pub fn failures(&self, summary: &DomainSummary) -> Vec<String> {
+        let mut failures = Vec::new();
+        for (name, rule) in &self.rules {
+            let value = match rule.metric {
+                QcMetric::Reads => summary.reads as f64,
+                QcMetric::Classified => 100.0 - summary.domains[UNCLASSIFIED].percent,
+                QcMetric::Domain(domain) => summary.domains[domain].percent,
+            };
+            let pass = if rule.at_least {
+                value >= rule.value
+            } else {
+                value <= rule.value
+            };
+            if pass {
+                continue;
+            }
+            let op = if rule.at_least { "<" } else { ">" };
+            failures.push(match rule.metric {
+                QcMetric::Reads => format!("{} {} {} {}", name, value, op, rule.value),
+                _ => format!("{} {:.2}% {} {}%", name, value, op, rule.value),
+            });
+        }
+        failures
+    }
+}
+
+impl DomainSummary {
+    pub fn with_qc(mut self, gate: &QcGate) -> Self {
+        let failures = gate.failures(&self);
+        self.qc = Some(QcResult {
+            pass: failures.is_empty(),
+            failures,
+        });
+        self
+    }
+}
+
+/// 对各样本应用 `--qc-gate`, 未通过的样本输出警告, 返回未通过的样本数
+pub fn apply_qc_gate(summaries: &mut [DomainSummary], gate: &QcGate) -> usize {
+    let mut failed = 0;
+    for summary in summaries.iter_mut() {
+        let failures = gate.failures(summary);
+        if !failures.is_empty() {
+            failed += 1;
+            warn!(
+                "sample {} failed the QC gate: {}",
+                summary.sample,
+                failures.join(", ")
+            );
+        }
+        summary.qc = Some(QcResult {
+            pass: failures.is_empty(),
+            failures,
+        });
+    }
+    failed
+}
+
+/// 在终端输出一屏的样本大类百分比表. 每个 read 的结果写到标准输出时 `to_stderr`,
+/// 表格写到标准错误, 不混入下游程序读取的结果
+pub fn print_domain_summaries(summaries: &[DomainSummary], to_stderr: bool) {
+    let print = |line: &str| {
+        if to_stderr {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    };
+    if summaries.is_empty() {
+        return;
+    }
+    let width = summaries
+        .iter()
+        .map(|summary| summary.sample.len())
+        .max()
+        .unwrap_or(0)
+        .max("sample".len());
+    let with_qc = summaries.iter().any(|summary| summary.qc.is_some());
+    let mut header = format!("{:<width$} {:>12}", "sample", "reads");
+    for domain in DOMAINS {
+        header.push_str(&format!(" {:>12}", domain));
+    }
+    if with_qc {
+        header.push_str("     qc");
+    }
+    print(&header);
+    for summary in summaries {
+        let mut line = format!("{:<width$} {:>12}", summary.sample, summary.reads);
+        for count in &summary.domains {
+            line.push_str(&format!(" {:>11.2}%", count.percent));
+        }
+        if let Some(qc) = &summary.qc {
+            line.push_str(if qc.pass { "   pass" } else { "   FAIL" });
+        }
+        print(&line);
+    }
+}
+
+/// 写出 [`RUN_SUMMARY`]: 每个样本的 reads 数与各大类的 reads 数和百分比.
+/// `missing_files` 为缺少而没有处理的 chunk 文件, 为空时不写出
+pub fn write_run_summary<P: AsRef<Path>>(
+    filename: P,
+    summaries: &[DomainSummary],
+    missing_files: &[String],
+) -> io::Result<()> {
+    #[derive(Serialize)]
+    struct RunSummary<'a> {
+        version: &'static str,
+        samples: &'a [DomainSummary],
+        #[serde(skip_serializing_if = "<[String]>::is_empty")]
+        missing_files: &'a [String],
+    }
+
+    let mut writer = BufWriter::new(File::create(filename)?);
+    let summary = RunSummary {
+        version: env!("CARGO_PKG_VERSION"),
+        samples: summaries,
+        missing_files,
+    };
+    serde_json::to_writer_pretty(&mut writer, &summary).map_err(io::Error::other)?;
+    writeln!(writer)?;
+    writer.flush()
+}
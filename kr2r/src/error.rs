@@ -0,0 +1,177 @@
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// 按失败类型区分的错误, 进程退出码见 [`Kr2rError::exit_code`]
+///
+/// 与 `io::Error` 可以互相转换, 返回 `io::Result` 的函数中仍可用 `?` 传递,
+/// 转换后的 `io::Error` 在 [`exit_code`] 中还原出原来的类型
+///
+/// ```
+/// use kraken2_rs::error::{exit_code, Kr2rError};
+/// use std::io;
+///
+/// let err = Kr2rError::MissingIndex("/db/taxo.k2d".into());
+/// assert_eq!(err.exit_code(), 3);
+///
+/// let err: io::Error = err.into();
+/// assert_eq!(err.kind(), io::ErrorKind::NotFound);
+/// assert_eq!(exit_code(&err), 3);
+///
+/// let err = io::Error::new(io::ErrorKind::TimedOut, "nfs read timed out");
+/// assert_eq!(exit_code(&err), 75);
+/// ```
+#[derive(Debug, Error)]
+pub enum Kr2rError {
+    /// 数据库目录中缺少文件 (taxo.k2d, opts.k2d, hash_config.k2d, hash_*.k2d, seqid2taxid.map)
+    #[error("database file not found: {0:?}")]
+    MissingIndex(PathBuf),
+
+    /// 数据库文件或中间 chunk 文件的内容无法解析, 需要重新生成
+    #[error("corrupt chunk file {path:?}: {source}")]
+    CorruptChunk { path: PathBuf, source: io::Error },
+
+    /// 输入的 FASTA/FASTQ/BAM 文件格式错误或被截断
+    #[error("bad input file {path:?}: {source}")]
+    BadInput { path: PathBuf, source: io::Error },
+
+    /// 参数或配置错误
+    #[error("{0}")]
+    Config(String),
+
+    /// 有样本未通过 `--qc-gate` (`--qc-fail-exit`), 所有输出已写完
+    #[error("{0}")]
+    QcFailed(String),
+
+    /// 其他 I/O 错误, 暂时性的错误 (超时, 中断, 磁盘已满等) 的退出码为 75, 可以重试
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Kr2rError>;
+
+/// 其他错误
+pub const EXIT_FAILURE: u8 = 1;
+/// 缺少数据库文件
+pub const EXIT_MISSING_INDEX: u8 = 3;
+/// 数据库或 chunk 文件损坏
+pub const EXIT_CORRUPT_CHUNK: u8 = 4;
+/// 输入文件格式错误
+pub const EXIT_BAD_INPUT: u8 = 5;
+/// 参数或配置错误
+pub const EXIT_CONFIG: u8 = 6;
+/// 有样本未通过 QC
+pub const EXIT_QC_FAILED: u8 = 7;
+/// 暂时性的 I/O 错误 (sysexits 的 EX_TEMPFAIL), 重试可能成功
+pub const EXIT_TRANSIENT: u8 = 75;
+
+/// 重试可能成功的 I/O 错误
+fn is_transient(kind: io::ErrorKind) -> bool {
+    use io::ErrorKind::*;
+    matches!(
+        kind,
+        Interrupted
+            | TimedOut
+            | WouldBlock
+            | StorageFull
+            | QuotaExceeded
+            | ResourceBusy
+            | OutOfMemory
+            | ConnectionReset
+            | ConnectionAborted
+            | ConnectionRefused
+            | BrokenPipe
+            | StaleNetworkFileHandle
+    )
+}
+
+/// 已经由 [`Kr2rError`] 转换而来的 `io::Error`
+fn is_classified(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|e| e.is::<Kr2rError>())
+}
+
+impl Kr2rError {
+    /// 读取数据库文件 `path` 的错误: 文件不存在为 [`Kr2rError::MissingIndex`], 内容错误为 [`Kr2rError::CorruptChunk`]
+    pub fn index<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        match source.kind() {
+            _ if is_classified(&source) => Kr2rError::Io(source),
+            io::ErrorKind::NotFound => Kr2rError::MissingIndex(path.as_ref().to_path_buf()),
+            _ => Self::chunk(path, source),
+        }
+    }
+
+    /// 读取 chunk 文件 `path` 的错误: 内容错误为 [`Kr2rError::CorruptChunk`]
+    pub fn chunk<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        match source.kind() {
+            _ if is_classified(&source) => Kr2rError::Io(source),
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Kr2rError::CorruptChunk {
+                path: path.as_ref().to_path_buf(),
+                source,
+            },
+            _ => Kr2rError::Io(source),
+        }
+    }
+
+    /// 读取输入文件 `path` 的错误: 暂时性的 I/O 错误之外都为 [`Kr2rError::BadInput`]
+    pub fn input<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        if is_transient(source.kind()) || is_classified(&source) {
+            Kr2rError::Io(source)
+        } else {
+            Kr2rError::BadInput {
+                path: path.as_ref().to_path_buf(),
+                source,
+            }
+        }
+    }
+
+    /// 进程退出码
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Kr2rError::MissingIndex(_) => EXIT_MISSING_INDEX,
+            Kr2rError::CorruptChunk { .. } => EXIT_CORRUPT_CHUNK,
+            Kr2rError::BadInput { .. } => EXIT_BAD_INPUT,
+            Kr2rError::Config(_) => EXIT_CONFIG,
+            Kr2rError::QcFailed(_) => EXIT_QC_FAILED,
+            Kr2rError::Io(e) => exit_code(e),
+        }
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            Kr2rError::MissingIndex(_) => io::ErrorKind::NotFound,
+            Kr2rError::CorruptChunk { .. } => io::ErrorKind::InvalidData,
+            Kr2rError::BadInput { .. } => io::ErrorKind::InvalidData,
+            Kr2rError::Config(_) => io::ErrorKind::InvalidInput,
+            Kr2rError::QcFailed(_) => io::ErrorKind::Other,
+            Kr2rError::Io(e) => e.kind(),
+        }
+    }
+}
+
+impl From<Kr2rError> for io::Error {
+    fn from(err: Kr2rError) -> Self {
+        match err {
+            Kr2rError::Io(e) => e,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
+
+/// 任意错误的进程退出码, 包在 `io::Error` 中的 [`Kr2rError`] 按原来的类型计算
+pub fn exit_code(err: &(dyn Error + 'static)) -> u8 {
+    if let Some(err) = err.downcast_ref::<Kr2rError>() {
+        return err.exit_code();
+    }
+    if let Some(err) = err.downcast_ref::<io::Error>() {
+        if let Some(inner) = err.get_ref() {
+            if inner.is::<Kr2rError>() || inner.is::<io::Error>() {
+                return exit_code(inner);
+            }
+        }
+        if is_transient(err.kind()) {
+            return EXIT_TRANSIENT;
+        }
+    }
+    EXIT_FAILURE
+}
@@ -0,0 +1,179 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 流程中的一个事件: 阶段开始 (`start`) 与结束 (`end`), 完成一个 chunk (`chunk`),
+/// 文件 (`file`) 或样本 (`sample`), 以及 warn / error 级别的日志 (`alert`)
+///
+/// ```
+/// use kraken2_rs::events::Event;
+///
+/// let event = Event::new("resolve", "sample", "sample 1: 1000 reads").with_field("sample", 1);
+/// let json = serde_json::to_value(&event).unwrap();
+/// assert_eq!(json["stage"], "resolve");
+/// assert_eq!(json["event"], "sample");
+/// assert_eq!(json["sample"], 1);
+/// ```
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    /// 阶段名 (`splitr`, `annotate`, `resolve`, `classify`, `build` 等), 不属于阶段的 alert 为空
+    pub stage: String,
+    /// 事件类型
+    pub event: String,
+    /// 日志级别 (`INFO`, `WARN`, `ERROR`)
+    pub level: String,
+    pub message: String,
+    /// 事件发生的时间, 自 Unix 纪元起的毫秒数
+    pub timestamp_ms: u64,
+    /// 事件的其他字段 (`sample`, `chunk`, `elapsed_ms`, `reads` 等)
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+impl Event {
+    pub fn new(stage: &str, event: &str, message: &str) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        Self {
+            stage: stage.to_string(),
+            event: event.to_string(),
+            level: "INFO".to_string(),
+            message: message.to_string(),
+            timestamp_ms,
+            fields: Map::new(),
+        }
+    }
+
+    pub fn with_field<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        self.fields.insert(name.to_string(), value.into());
+        self
+    }
+}
+
+type Listener = Box<dyn Fn(&Event) + Send + Sync>;
+
+static LISTENERS: RwLock<Vec<Listener>> = RwLock::new(Vec::new());
+static HAS_LISTENERS: AtomicBool = AtomicBool::new(false);
+
+/// 注册一个事件回调, 之后 [`emit`] 的每个事件都会在发出事件的线程中调用它.
+/// 回调应尽快返回, 耗时的处理 (如网络请求) 放到其他线程
+///
+/// ```
+/// use kraken2_rs::events::{add_listener, emit, Event};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// let count = Arc::new(AtomicUsize::new(0));
+/// let seen = count.clone();
+/// add_listener(move |event| {
+///     if event.event == "end" {
+///         seen.fetch_add(1, Ordering::Relaxed);
+///     }
+/// });
+/// emit(&Event::new("build", "end", "build k2 db took: 1s"));
+/// assert_eq!(count.load(Ordering::Relaxed), 1);
+/// ```
+pub fn add_listener<F: Fn(&Event) + Send + Sync + 'static>(listener: F) {
+    let mut listeners = LISTENERS.write().unwrap_or_else(|e| e.into_inner());
+    listeners.push(Box::new(listener));
+    HAS_LISTENERS.store(true, Ordering::Release);
+}
+
+/// 是否注册了回调, 没有时不必构造事件
+pub fn has_listeners() -> bool {
+    HAS_LISTENERS.load(Ordering::Acquire)
+}
+
+/// 把事件交给所有已注册的回调
+pub fn emit(event: &Event) {
+    if !has_listeners() {
+        return;
+    }
+    let listeners = LISTENERS.read().unwrap_or_else(|e| e.into_inner());
+    for listener in listeners.iter() {
+        listener(event);
+    }
+}
+
+#[cfg(feature = "cli")]
+pub use webhook::Webhook;
+
+#[cfg(feature = "cli")]
+mod webhook {
+    use super::{add_listener, Event};
+    use std::sync::mpsc::{self, Sender};
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+    use tracing::warn;
+
+    /// 每个请求的超时
+    const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// `--event-webhook`: 在后台线程中把每个事件以 JSON POST 到 `url`, 不阻塞流程.
+    ///
+    /// 请求按事件的顺序逐个发出, 失败的请求不重试. drop 时等待已发出的事件发送完,
+    /// 因此最后的 `end` 事件也会送达
+    pub struct Webhook {
+        sender: Option<Sender<String>>,
+        worker: Option<JoinHandle<()>>,
+    }
+
+    impl Webhook {
+        pub fn start(url: &str) -> std::io::Result<Self> {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .map_err(std::io::Error::other)?;
+            let url = url.to_string();
+            let (sender, receiver) = mpsc::channel::<String>();
+            let worker = thread::spawn(move || {
+                // 连续失败时只提示第一次, 直到再次发送成功
+                let mut failing = false;
+                for body in receiver {
+                    if body.is_empty() {
+                        break;
+                    }
+                    let result = client
+                        .post(&url)
+                        .header("content-type", "application/json")
+                        .body(body)
+                        .send()
+                        .and_then(|response| response.error_for_status());
+                    match result {
+                        Ok(_) => failing = false,
+                        Err(e) if !failing => {
+                            failing = true;
+                            warn!("event webhook {}: {}", url, e);
+                        }
+                        Err(_) => {}
+                    }
+                }
+            });
+            let listener_sender = sender.clone();
+            add_listener(move |event: &Event| {
+                if let Ok(body) = serde_json::to_string(event) {
+                    let _ = listener_sender.send(body);
+                }
+            });
+            Ok(Self {
+                sender: Some(sender),
+                worker: Some(worker),
+            })
+        }
+    }
+
+    impl Drop for Webhook {
+        fn drop(&mut self) {
+            // 回调中的 sender 仍然存在, 发送一个空请求体作为结束标记
+            if let Some(sender) = self.sender.take() {
+                let _ = sender.send(String::new());
+            }
+            if let Some(worker) = self.worker.take() {
+                let _ = worker.join();
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// 输出目录中样本编号, 样本名称与输出文件名 (不含扩展名) 的对应关系
+pub const SAMPLE_NAMES_FILE: &str = "sample_names.tsv";
+
+/// 文件名 (不含扩展名) 的最大字节数, 加上 `.kreport2.zst` 等扩展名后仍在常见文件系统 255 字节的限制内
+pub const MAX_STEM_BYTES: usize = 200;
+
+/// 输出目录中已有的文件名, 样本不能使用
+const RESERVED_STEMS: [&str; 4] = [
+    "sample_file",
+    "sample_metadata",
+    "sample_names",
+    "run_summary",
+];
+
+/// Windows 的设备名, 带扩展名时也不能作为文件名
+const WINDOWS_DEVICES: [&str; 4] = ["con", "prn", "aux", "nul"];
+
+/// 把样本名称变为可在 Linux, macOS 与 Windows 上使用的文件名 (不含扩展名).
+///
+/// 字母 (包括非 ASCII 字母), 数字与 `-_.+` 保留, 其他字符 (路径分隔符, 空白, 控制字符,
+/// Windows 不允许的 `<>:"|?*` 等) 的连续一段变为一个 `_`. 去掉开头的 `.` 与 `-` 和末尾的 `.`,
+/// Windows 的设备名后加 `_`, 截断到 [`MAX_STEM_BYTES`], 结果为空时为 "sample"
+///
+/// ```
+/// use kraken2_rs::filenames::sanitize_file_stem;
+///
+/// assert_eq!(sanitize_file_stem("patient 1/gut"), "patient_1_gut");
+/// assert_eq!(sanitize_file_stem("../../etc/passwd"), "_.._etc_passwd");
+/// assert_eq!(sanitize_file_stem("Küste:Probe*3"), "Küste_Probe_3");
+/// assert_eq!(sanitize_file_stem("CON"), "CON_");
+/// assert_eq!(sanitize_file_stem("..."), "sample");
+/// assert_eq!(sanitize_file_stem(&"é".repeat(300)).len(), 200);
+/// ```
+pub fn sanitize_file_stem(name: &str) -> String {
+    let mut stem = String::with_capacity(name.len());
+    let mut replaced = false;
+    for c in name.chars() {
+        if c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '+') {
+            stem.push(c);
+            replaced = false;
+        } else if !replaced {
+            stem.push('_');
+            replaced = true;
+        }
+    }
+    let mut stem = stem
+        .trim_start_matches(['.', '-'])
+        .trim_end_matches('.')
+        .to_string();
+    if stem.is_empty() {
+        stem = "sample".to_string();
+    }
+    let lower = stem.to_lowercase();
+    let device = lower.split('.').next().unwrap_or_default();
+    if WINDOWS_DEVICES.contains(&device)
+        || ((device.starts_with("com") || device.starts_with("lpt"))
+            && device.len() == 4
+            && device.as_bytes()[3].is_ascii_digit())
+    {
+        stem.push('_');
+    }
+    truncate_stem(&mut stem, MAX_STEM_BYTES);
+    stem
+}
+
+/// 在字符边界上截断到最多 `max_bytes` 字节
+fn truncate_stem(stem: &mut String, max_bytes: usize) {
+    if stem.len() > max_bytes {
+        let mut end = max_bytes;
+        while !stem.is_char_boundary(end) {
+            end -= 1;
+        }
+        stem.truncate(end);
+    }
+}
+
+/// 各样本输出文件名 (不含扩展名): 有名称的样本为 [`sanitize_file_stem`] 的结果, 其他为 `output_{编号}`.
+///
+/// 名称相同或只有大小写不同 (macOS 与 Windows 的文件系统不区分大小写) 时, 编号较大的样本加上
+/// `_2`, `_3` 等后缀, 也不会与 `reserved` (例如合并报告的 `output_1-3`), 没有名称的样本以及输出目录中
+/// 的其他文件重名
+///
+/// ```
+/// use kraken2_rs::filenames::output_stems;
+/// use std::collections::BTreeMap;
+///
+/// let names = BTreeMap::from([
+///     (1, "Gut A".to_string()),
+///     (2, "gut a".to_string()),
+///     (3, "output_4".to_string()),
+/// ]);
+/// let stems = output_stems([1, 2, 3, 4], &names, &[]);
+/// assert_eq!(stems[&1], "Gut_A");
+/// assert_eq!(stems[&2], "gut_a_2");
+/// assert_eq!(stems[&3], "output_4_2");
+/// assert_eq!(stems[&4], "output_4");
+/// ```
+pub fn output_stems<I: IntoIterator<Item = usize>>(
+    indices: I,
+    names: &BTreeMap<usize, String>,
+    reserved: &[String],
+) -> BTreeMap<usize, String> {
+    let indices: Vec<usize> = indices.into_iter().collect();
+    let mut taken: HashSet<String> = RESERVED_STEMS
+        .iter()
+        .map(|stem| stem.to_string())
+        .chain(reserved.iter().map(|stem| stem.to_lowercase()))
+        .collect();
+    let mut stems = BTreeMap::new();
+    // 先给没有名称的样本编号, 名称不会占用它们的文件名
+    for &index in &indices {
+        if !names.contains_key(&index) {
+            let stem = format!("output_{}", index);
+            taken.insert(stem.clone());
+            stems.insert(index, stem);
+        }
+    }
+    for &index in &indices {
+        let Some(name) = names.get(&index) else {
+            continue;
+        };
+        let base = sanitize_file_stem(name);
+        let mut stem = base.clone();
+        let mut n = 1;
+        while taken.contains(&stem.to_lowercase()) {
+            n += 1;
+            let suffix = format!("_{}", n);
+            stem = base.clone();
+            truncate_stem(&mut stem, MAX_STEM_BYTES - suffix.len());
+            stem.push_str(&suffix);
+        }
+        taken.insert(stem.to_lowercase());
+        stems.insert(index, stem);
+    }
+    stems
+}
+
+/// 写出 [`SAMPLE_NAMES_FILE`]: `sample<TAB>name<TAB>file` 表头之后每个样本一行, 没有名称的样本 name 为空
+pub fn write_output_stems<P: AsRef<Path>>(
+    filename: P,
+    names: &BTreeMap<usize, String>,
+    stems: &BTreeMap<usize, String>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writeln!(writer, "sample\tname\tfile")?;
+    for (index, stem) in stems {
+        let name = names.get(index).map_or("", String::as_str);
+        writeln!(writer, "{}\t{}\t{}", index, name, stem)?;
+    }
+    writer.flush()
+}
+
+/// 读取 [`SAMPLE_NAMES_FILE`], 返回样本名称到输出文件名的对应; 文件不存在时为空
+pub fn read_output_stems<P: AsRef<Path>>(filename: P) -> io::Result<HashMap<String, String>> {
+    let file = match File::open(filename) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut stems = HashMap::new();
+    for line in BufReader::new(file).lines().skip(1) {
+        let line = line?;
+        let mut columns = line.split('\t');
+        if let (Some(_), Some(name), Some(stem)) = (columns.next(), columns.next(), columns.next())
+        {
+            if !name.is_empty() {
+                stems
+                    .entry(name.to_string())
+                    .or_insert_with(|| stem.to_string());
+            }
+        }
+    }
+    Ok(stems)
+}
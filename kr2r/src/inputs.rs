@@ -0,0 +1,467 @@
+use flate2::read::GzDecoder;
+use seqkmer::{detect_file_format, dyn_reader, SeqFormat};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+const SEQ_EXTENSIONS: [&str; 6] = ["fa", "fasta", "fna", "fq", "fastq", "fas"];
+const COMPRESSED_EXTENSIONS: [&str; 4] = [".gz", ".zst", ".bz2", ".xz"];
+
+/// 根据扩展名判断是否为 FASTA/FASTQ 文件 (可带 .gz/.zst/.bz2/.xz)
+///
+/// ```
+/// use kraken2_rs::inputs::is_seq_file;
+///
+/// assert!(is_seq_file("run1/sample_R1.fastq.gz"));
+/// assert!(is_seq_file("contigs.FA"));
+/// assert!(is_seq_file("SRR000001.fastq.zst"));
+/// assert!(!is_seq_file("run1/README.txt"));
+/// ```
+pub fn is_seq_file<P: AsRef<Path>>(path: P) -> bool {
+    let name = path
+        .as_ref()
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let name = COMPRESSED_EXTENSIONS
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(&name);
+    name.rsplit_once('.')
+        .is_some_and(|(_, ext)| SEQ_EXTENSIONS.contains(&ext))
+}
+
+/// 由文件名中的 R1/R2 标记 (`_R1_001`, `_R2.fq`, `.R1.`, `_1.fastq` 等) 判断 mate,
+/// 返回去掉标记后的名称 (用于配对) 与 mate 编号. 同时有多个标记时取最后一个, `R` 形式优先
+///
+/// ```
+/// use kraken2_rs::inputs::mate_key;
+///
+/// assert_eq!(mate_key("S1_L001_R1_001.fastq.gz"), Some(("S1_L001_R#_001.fastq.gz".to_string(), 1)));
+/// assert_eq!(mate_key("run/sample_2.fq"), Some(("run/sample_#.fq".to_string(), 2)));
+/// assert_eq!(mate_key("lane_1.sample.r2.fq"), Some(("lane_1.sample.r#.fq".to_string(), 2)));
+/// assert_eq!(mate_key("contigs.fa"), None);
+/// ```
+pub fn mate_key<P: AsRef<Path>>(path: P) -> Option<(String, u8)> {
+    let path = path.as_ref();
+    let name = path.file_name()?.to_string_lossy().to_string();
+    let bytes = name.as_bytes();
+    let is_sep = |c: u8| matches!(c, b'.' | b'_' | b'-');
+    let mut marked = None;
+    let mut bare = None;
+    for (i, &c) in bytes.iter().enumerate() {
+        if !is_sep(c) {
+            continue;
+        }
+        let (digit, with_r) = match bytes.get(i + 1) {
+            Some(b'R' | b'r') => (i + 2, true),
+            _ => (i + 1, false),
+        };
+        let (Some(&d @ (b'1' | b'2')), true) = (
+            bytes.get(digit),
+            bytes.get(digit + 1).is_none_or(|&c| is_sep(c)),
+        ) else {
+            continue;
+        };
+        if with_r {
+            marked = Some((digit, d - b'0'));
+        } else {
+            bare = Some((digit, d - b'0'));
+        }
+    }
+    let (digit, mate) = marked.or(bare)?;
+    let key = format!("{}#{}", &name[..digit], &name[digit + 1..]);
+    let key = match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => dir.join(key).to_string_lossy().to_string(),
+        None => key,
+    };
+    Some((key, mate))
+}
+
+fn is_fastq(file: &String) -> bool {
+    detect_file_format(file).is_ok_and(|format| format == SeqFormat::Fastq)
+}
+
+/// 依次两两配对的文件是否都是同一样本的 R1 与 R2
+fn is_consecutive_pairs(files: &[String]) -> bool {
+    !files.is_empty()
+        && files.iter().all(is_fastq)
+        && files.len().is_multiple_of(2)
+        && files.chunks(2).all(|pair| {
+            matches!(
+                (mate_key(&pair[0]), mate_key(&pair[1])),
+                (Some((key1, 1)), Some((key2, 2))) if key1 == key2
+            )
+        })
+}
+
+/// 未指定 `-P` 时由文件名判断输入是否为 paired 文件, 是则返回按 R1, R2 排列的输入.
+///
+/// 只配对 FASTQ 文件. 输入都是文件时按名称配对 (顺序可以任意), 含目录时要求目录中排序后的文件
+/// 依次为 R1, R2. tarball 在解压前无法判断, 返回 None
+pub fn detect_pairs(inputs: &[String]) -> Option<Vec<String>> {
+    if inputs.iter().any(is_tarball) {
+        return None;
+    }
+    if inputs.iter().any(|input| Path::new(input).is_dir()) {
+        let files: Vec<String> = inputs
+            .iter()
+            .flat_map(|input| {
+                if Path::new(input).is_dir() {
+                    find_seq_files(input)
+                } else {
+                    vec![input.clone()]
+                }
+            })
+            .collect();
+        return is_consecutive_pairs(&files).then(|| inputs.to_vec());
+    }
+
+    let mut keys: Vec<String> = Vec::new();
+    let mut mates: HashMap<String, [Option<&String>; 2]> = HashMap::new();
+    for input in inputs {
+        let (key, mate) = mate_key(input)?;
+        let slot = &mut mates.entry(key.clone()).or_insert_with(|| {
+            keys.push(key);
+            [None, None]
+        })[mate as usize - 1];
+        if slot.replace(input).is_some() {
+            return None;
+        }
+    }
+    let mut paired = Vec::with_capacity(inputs.len());
+    for key in &keys {
+        let [Some(r1), Some(r2)] = mates[key] else {
+            return None;
+        };
+        paired.push(r1.clone());
+        paired.push(r2.clone());
+    }
+    Some(paired).filter(|paired| !paired.is_empty() && paired.iter().all(is_fastq))
+}
+
+/// 是否为 .tar / .tar.gz / .tgz 文件
+pub fn is_tarball<P: AsRef<Path>>(path: P) -> bool {
+    let name = path.as_ref().to_string_lossy().to_lowercase();
+    name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// 递归查找目录下的序列文件, 按路径排序 (paired 文件依次为 R1, R2)
+pub fn find_seq_files<P: AsRef<Path>>(dir: P) -> Vec<String> {
+    let mut files: Vec<String> = WalkDir::new(dir)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && is_seq_file(entry.path()))
+        .map(|entry| entry.path().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    files
+}
+
+/// 解压 tarball 到 `work_dir` 下, 返回其中的序列文件
+fn unpack_tarball(tarball: &Path, work_dir: &Path) -> Result<Vec<String>> {
+    let name = tarball
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = name
+        .trim_end_matches(".gz")
+        .trim_end_matches(".tgz")
+        .trim_end_matches(".tar");
+    let dest = work_dir.join(format!("{}_files", stem));
+    fs::create_dir_all(&dest)?;
+
+    let file = File::open(tarball)?;
+    let lower = name.to_lowercase();
+    if lower.ends_with(".gz") || lower.ends_with(".tgz") {
+        tar::Archive::new(GzDecoder::new(file)).unpack(&dest)?;
+    } else {
+        tar::Archive::new(file).unpack(&dest)?;
+    }
+    info!("unpacked {:?} into {:?}", tarball, dest);
+    Ok(find_seq_files(&dest))
+}
+
+/// 将多个文件 (可为压缩文件) 合并为一个未压缩文件
+fn concat_files(files: &[&String], dest: &Path) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(dest)?);
+    let mut buffer = vec![0u8; 1 << 20];
+    for file in files {
+        let mut reader = dyn_reader(file)?;
+        let mut last = b'\n';
+        loop {
+            let n = reader.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            writer.write_all(&buffer[..n])?;
+            last = buffer[n - 1];
+        }
+        // 保证下一个文件从新的一行开始
+        if last != b'\n' {
+            writer.write_all(b"\n")?;
+        }
+    }
+    writer.flush()
+}
+
+/// 展开输入参数中的目录与 tarball
+///
+/// 目录会被递归查找, tarball 解压到 `work_dir`. `merge` 时一个目录或 tarball 中的所有文件
+/// 合并为一个样本 (paired 时分别合并 R1 与 R2), 否则每个文件 (或每对文件) 为一个样本.
+pub fn expand_inputs(
+    inputs: &[String],
+    paired: bool,
+    merge: bool,
+    work_dir: &Path,
+) -> Result<Vec<String>> {
+    let mut expanded = Vec::new();
+    for (i, input) in inputs.iter().enumerate() {
+        let path = Path::new(input);
+        let files = if path.is_dir() {
+            find_seq_files(path)
+        } else if path.is_file() && is_tarball(path) {
+            fs::create_dir_all(work_dir)?;
+            unpack_tarball(path, work_dir)?
+        } else {
+            expanded.push(input.clone());
+            continue;
+        };
+
+        if files.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no FASTA/FASTQ files found in {}", input),
+            ));
+        }
+        if paired && !files.len().is_multiple_of(2) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "paired-end input {} has an odd number of files ({})",
+                    input,
+                    files.len()
+                ),
+            ));
+        }
+        info!("{}: {} sequence files", input, files.len());
+
+        if !merge {
+            expanded.extend(files);
+            continue;
+        }
+        fs::create_dir_all(work_dir)?;
+        let mates: Vec<Vec<&String>> = if paired {
+            vec![
+                files.iter().step_by(2).collect(),
+                files.iter().skip(1).step_by(2).collect(),
+            ]
+        } else {
+            vec![files.iter().collect()]
+        };
+        for (mate, mate_files) in mates.iter().enumerate() {
+            let dest: PathBuf = if paired {
+                work_dir.join(format!("merged_input_{}_{}.fx", i + 1, mate + 1))
+            } else {
+                work_dir.join(format!("merged_input_{}.fx", i + 1))
+            };
+            concat_files(mate_files, &dest)?;
+            expanded.push(dest.to_string_lossy().to_string());
+        }
+    }
+    Ok(expanded)
+}
+
+/// `--sample-sheet` 中的一个样本, 同名的多行 (如多个 lane) 合并为一个样本
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SheetSample {
+    pub name: String,
+    /// 单端时为一个文件, 双端时为 R1 与 R2
+    pub files: Vec<String>,
+    /// 表头中文件列以外的列 (如 patient_id, specimen_type), 按表头顺序, 空值不记录
+    pub metadata: Vec<(String, String)>,
+}
+
+/// 表头中的文件列名, 有这些列名时其余的列为样本的元数据
+fn is_file_column(name: &str) -> bool {
+    ["r1", "r2", "fastq_1", "fastq_2"].contains(&name.to_ascii_lowercase().as_str())
+}
+
+/// 解析样本表: 每行为 `样本名<TAB>R1[<TAB>R2]`, 空行与 `#` 开头的行被忽略,
+/// 第一列为 `sample` 的第一行视为表头. 相对路径相对于样本表所在的目录.
+/// 表头中有 `R1`/`R2` (或 `fastq_1`/`fastq_2`) 列时只有这些列是文件, 其余的列为样本的元数据
+///
+/// 返回样本表中的各行与是否为双端数据, 同名的行在 `read_sample_sheet` 中合并
+///
+/// ```
+/// use kraken2_rs::inputs::parse_sample_sheet;
+/// use std::path::Path;
+///
+/// let sheet = "sample\tR1\tR2\nA\ta_1.fq\ta_2.fq\n# comment\nB\t/data/b_1.fq\t/data/b_2.fq\n";
+/// let (samples, paired) = parse_sample_sheet(sheet, Path::new("/runs")).unwrap();
+/// assert!(paired);
+/// assert_eq!(samples[0].name, "A");
+/// assert_eq!(samples[0].files, vec!["/runs/a_1.fq", "/runs/a_2.fq"]);
+/// assert_eq!(samples[1].files, vec!["/data/b_1.fq", "/data/b_2.fq"]);
+///
+/// assert!(parse_sample_sheet("A\ta.fq\nB\tb_1.fq\tb_2.fq\n", Path::new(".")).is_err());
+///
+/// let sheet = "sample\tpatient_id\tR1\tspecimen\nA\tP1\ta.fq\tstool\nB\tP2\tb.fq\t\n";
+/// let (samples, paired) = parse_sample_sheet(sheet, Path::new("")).unwrap();
+/// assert!(!paired);
+/// assert_eq!(samples[0].files, vec!["a.fq"]);
+/// let metadata = [("patient_id", "P1"), ("specimen", "stool")].map(|(k, v)| (k.to_string(), v.to_string()));
+/// assert_eq!(samples[0].metadata, metadata);
+/// assert_eq!(samples[1].metadata, vec![("patient_id".to_string(), "P2".to_string())]);
+/// ```
+pub fn parse_sample_sheet(content: &str, base_dir: &Path) -> Result<(Vec<SheetSample>, bool)> {
+    let mut samples: Vec<SheetSample> = Vec::new();
+    let mut paired: Option<bool> = None;
+    // 有文件列名的表头: 各列的名称
+    let mut columns: Option<Vec<String>> = None;
+    let invalid = |line_num: usize, message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("sample sheet line {}: {}", line_num, message),
+        )
+    };
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').map(|field| field.trim()).collect();
+        if samples.is_empty() && fields[0].eq_ignore_ascii_case("sample") {
+            if fields[1..].iter().any(|name| is_file_column(name)) {
+                columns = Some(fields[1..].iter().map(|name| name.to_string()).collect());
+            }
+            continue;
+        }
+        let name = fields[0];
+        let mut metadata = Vec::new();
+        let files: Vec<&str> = match &columns {
+            Some(columns) => {
+                let mut files = Vec::new();
+                for (column, value) in columns.iter().zip(&fields[1..]) {
+                    if value.is_empty() {
+                        continue;
+                    }
+                    if is_file_column(column) {
+                        files.push(*value);
+                    } else {
+                        metadata.push((column.clone(), value.to_string()));
+                    }
+                }
+                files
+            }
+            None => fields[1..]
+                .iter()
+                .copied()
+                .filter(|f| !f.is_empty())
+                .collect(),
+        };
+        if name.is_empty() {
+            return Err(invalid(i + 1, "empty sample name".to_string()));
+        }
+        if files.is_empty() || files.len() > 2 {
+            return Err(invalid(
+                i + 1,
+                format!(
+                    "expected 1 (single-end) or 2 (R1, R2) files, found {}",
+                    files.len()
+                ),
+            ));
+        }
+        let row_paired = files.len() == 2;
+        if *paired.get_or_insert(row_paired) != row_paired {
+            return Err(invalid(
+                i + 1,
+                "single-end and paired-end samples can not be mixed".to_string(),
+            ));
+        }
+        let files = files
+            .iter()
+            .map(|f| base_dir.join(f).to_string_lossy().to_string());
+        samples.push(SheetSample {
+            name: name.to_string(),
+            files: files.collect(),
+            metadata,
+        });
+    }
+
+    if samples.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "sample sheet has no samples",
+        ));
+    }
+    Ok((samples, paired.unwrap_or(false)))
+}
+
+/// 读取样本表并检查文件是否存在, 同名样本的多行 (如多个 lane) 合并到 `work_dir` 中的一个文件
+pub fn read_sample_sheet<P: AsRef<Path>>(
+    sheet: P,
+    work_dir: &Path,
+) -> Result<(Vec<SheetSample>, bool)> {
+    let sheet = sheet.as_ref();
+    let content = fs::read_to_string(sheet)?;
+    let base_dir = sheet.parent().unwrap_or_else(|| Path::new(""));
+    let (rows, paired) = parse_sample_sheet(&content, base_dir)?;
+
+    for file in rows.iter().flat_map(|row| &row.files) {
+        if !Path::new(file).is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("sample sheet file {} does not exist", file),
+            ));
+        }
+    }
+
+    let mut lanes: Vec<(String, Vec<&SheetSample>)> = Vec::new();
+    for row in &rows {
+        match lanes.iter_mut().find(|(name, _)| *name == row.name) {
+            Some((_, group)) => group.push(row),
+            None => lanes.push((row.name.clone(), vec![row])),
+        }
+    }
+
+    let mut samples = Vec::with_capacity(lanes.len());
+    for (index, (name, group)) in lanes.into_iter().enumerate() {
+        if group.len() == 1 {
+            samples.push(group[0].clone());
+            continue;
+        }
+        fs::create_dir_all(work_dir)?;
+        let mut files = Vec::new();
+        for mate in 0..group[0].files.len() {
+            let mate_files: Vec<&String> = group.iter().map(|row| &row.files[mate]).collect();
+            let dest = work_dir.join(format!("sheet_sample_{}_{}.fx", index + 1, mate + 1));
+            concat_files(&mate_files, &dest)?;
+            files.push(dest.to_string_lossy().to_string());
+        }
+        info!(
+            "sample {}: merged {} rows of the sample sheet",
+            name,
+            group.len()
+        );
+        // 各 lane 的元数据应当相同, 使用第一行的
+        if group.iter().any(|row| row.metadata != group[0].metadata) {
+            warn!(
+                "sample {}: the rows of the sample sheet have different metadata, using the first row",
+                name
+            );
+        }
+        let metadata = group[0].metadata.clone();
+        samples.push(SheetSample {
+            name,
+            files,
+            metadata,
+        });
+    }
+    Ok((samples, paired))
+}
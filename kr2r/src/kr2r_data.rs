@@ -1,11 +1,11 @@
 use crate::compact_hash::Row;
-use crate::utils::open_file;
+use crate::error::Kr2rError;
 // use crate::{Meros, CURRENT_REVCOM_VERSION};
-use seqkmer::Meros;
 use seqkmer::OptionPair;
 use seqkmer::CURRENT_REVCOM_VERSION;
+use seqkmer::{Alphabet, Meros};
 use std::fs::File;
-use std::io::{Read, Result as IoResult, Write};
+use std::io::{self, Read, Result as IoResult, Write};
 use std::mem;
 use std::path::Path;
 
@@ -32,6 +32,19 @@ pub fn u64_to_option(value: u64) -> Option<u64> {
     Option::from(value).filter(|&x| x != 0)
 }
 
+/// 一条 read 的所有命中记录
+///
+/// range 与 capacity 均为 usize, 超长 read (> 2^32 个 minimizer) 也不会被截断.
+///
+/// ```
+/// use kraken2_rs::HitGroup;
+/// use seqkmer::OptionPair;
+///
+/// let len = (1usize << 31) + 10;
+/// let hits = HitGroup::new(vec![], OptionPair::Pair((0, len), (len, 2 * len)));
+/// assert_eq!(hits.capacity(), 2 * len);
+/// assert_eq!(hits.required_score(0.5), len as u64);
+/// ```
 pub struct HitGroup {
     pub rows: Vec<Row>,
     /// example: (0..10], 左开右闭
@@ -52,6 +65,9 @@ impl HitGroup {
     }
 }
 
+/// 64 位平台上 opts.k2d 的字节数, 即 `IndexOptions` 的大小
+const OPTS_FILE_SIZE: usize = 64;
+
 /// 顺序不能错
 #[repr(C)]
 #[derive(Debug)]
@@ -90,17 +106,66 @@ impl IndexOptions {
     }
 
     pub fn read_index_options<P: AsRef<Path>>(file_path: P) -> IoResult<Self> {
-        let mut file = open_file(file_path)?;
-        let mut buffer = vec![0; std::mem::size_of::<Self>()];
-        file.read_exact(&mut buffer)?;
+        let file_path = file_path.as_ref();
+        let mut file = File::open(file_path).map_err(|e| Kr2rError::index(file_path, e))?;
+        let mut buffer = vec![0; OPTS_FILE_SIZE];
+        file.read_exact(&mut buffer)
+            .map_err(|e| Kr2rError::index(file_path, e))?;
+        Self::from_bytes(&buffer).map_err(|e| Kr2rError::index(file_path, e).into())
+    }
 
-        let idx_opts = unsafe {
-            // 确保这种转换是安全的，这依赖于数据的确切布局和来源
-            std::ptr::read(buffer.as_ptr() as *const Self)
+    /// 从 opts.k2d 的内容解析, 按 64 位平台上 `#[repr(C)]` 的布局逐个字段读取,
+    /// 因此在 usize 为 32 位的 wasm32 上也能读取
+    ///
+    /// ```
+    /// use kraken2_rs::IndexOptions;
+    ///
+    /// let path = std::env::temp_dir().join(format!("kr2r_opts_doc_{}.k2d", std::process::id()));
+    /// IndexOptions::new(35, 31, 0, 0, true, 0).write_to_file(&path).unwrap();
+    /// let opts = IndexOptions::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+    /// assert_eq!((opts.k, opts.l, opts.dna_db), (35, 31, true));
+    /// assert!(IndexOptions::from_bytes(&[0; 16]).is_err());
+    /// std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> IoResult<Self> {
+        if bytes.len() < OPTS_FILE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "opts.k2d is {} bytes, expected {}",
+                    bytes.len(),
+                    OPTS_FILE_SIZE
+                ),
+            ));
+        }
+        let u64_at = |offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"))
+        };
+        let i32_at = |offset: usize| {
+            i32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+        };
+        let idx_opts = Self {
+            k: u64_at(0) as usize,
+            l: u64_at(8) as usize,
+            spaced_seed_mask: u64_at(16),
+            toggle_mask: u64_at(24),
+            dna_db: bytes[32] != 0,
+            minimum_acceptable_hash_value: u64_at(40),
+            revcom_version: i32_at(48),
+            db_version: i32_at(52),
+            db_type: i32_at(56),
         };
         if idx_opts.revcom_version != CURRENT_REVCOM_VERSION as i32 {
-            // 如果版本为0，直接触发 panic
-            panic!("Unsupported version (revcom_version == 0)");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported revcom_version {}", idx_opts.revcom_version),
+            ));
+        }
+        if !idx_opts.dna_db && !cfg!(feature = "protein") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "protein database, kraken2_rs was built without the `protein` feature",
+            ));
         }
 
         Ok(idx_opts)
@@ -129,13 +194,20 @@ impl IndexOptions {
             meros.l_mer,
             meros.spaced_seed_mask,
             meros.toggle_mask,
-            true,
+            !meros.alphabet.is_protein(),
             meros.min_clear_hash_value.unwrap_or_default(),
         )
     }
 
+    /// 蛋白数据库的 reads 按 6 个读码框翻译后查找
     pub fn as_meros(&self) -> Meros {
-        Meros::new(
+        let alphabet = if self.dna_db {
+            Alphabet::Dna
+        } else {
+            Alphabet::Translated
+        };
+        Meros::with_alphabet(
+            alphabet,
             self.k,
             self.l,
             u64_to_option(self.spaced_seed_mask),
@@ -1,7 +1,40 @@
+pub mod abundance;
+pub mod binsort;
+pub mod catalog;
+pub mod checkpoint;
+pub mod checksums;
+pub mod cluster;
+#[cfg(feature = "server")]
+pub mod distributed;
+pub mod domains;
+pub mod error;
+pub mod events;
+pub mod filenames;
+pub mod inputs;
 mod kr2r_data;
 mod kv_store;
+#[cfg(feature = "cli")]
+pub mod logging;
+#[cfg(feature = "cli")]
+pub mod progress;
+#[cfg(feature = "project")]
+pub mod project;
+pub mod read_index;
 pub mod readcounts;
+pub mod registry;
+#[cfg(feature = "cloud")]
+pub mod remote;
+#[cfg(feature = "cloud")]
+pub mod remote_db;
 pub mod report;
+pub mod result_cache;
+pub mod segments;
+pub mod seqout;
+pub mod shm;
+pub mod simulate;
+pub mod split_output;
+pub mod stats;
+pub mod subdb;
 pub mod taxonomy;
 pub mod utils;
 
@@ -11,5 +44,6 @@ pub use kv_store::*;
 pub use readcounts::TaxonCounts;
 
 pub mod args;
+pub mod classifier;
 pub mod classify;
 pub mod compact_hash;
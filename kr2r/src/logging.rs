@@ -0,0 +1,193 @@
+use crate::events::{self, Event};
+use crate::progress;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Registry;
+
+/// `--log-format`: 日志的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// 每行一条, 带时间与级别
+    #[default]
+    Text,
+    /// 每行一个 JSON 对象, 事件的字段 (stage, event, chunk, reads_per_sec 等) 在顶层
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("invalid log format '{}', expected text or json", s)),
+        }
+    }
+}
+
+/// 写到 stderr 的日志, 写出前清除进度条
+struct StderrWriter;
+
+impl Write for StderrWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        progress::suspend(|| io::stderr().write(buf))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        progress::suspend(|| io::stderr().write_all(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// 收集一条日志的字段
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Map<String, Value>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields.insert(field.name().to_string(), value.into());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.fields
+                .insert(field.name().to_string(), format!("{:?}", value).into());
+        }
+    }
+}
+
+/// 把带 `stage` 与 `event` 字段的日志, 以及 warn / error 级别的日志 (`alert`) 交给
+/// [`events::emit`], 不受 `--log-level` 影响
+struct EventLayer;
+
+impl<S: Subscriber> Layer<S> for EventLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        if !events::has_listeners() {
+            return;
+        }
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let level = *event.metadata().level();
+        let stage = visitor.fields.remove("stage");
+        let kind = visitor.fields.remove("event");
+        let (stage, kind) = match (stage, kind) {
+            (Some(Value::String(stage)), Some(Value::String(kind))) => (stage, kind),
+            (stage, _) if level <= Level::WARN => (
+                match stage {
+                    Some(Value::String(stage)) => stage,
+                    _ => String::new(),
+                },
+                "alert".to_string(),
+            ),
+            _ => return,
+        };
+        let mut emitted = Event::new(&stage, &kind, &visitor.message);
+        emitted.level = level.to_string();
+        emitted.fields = visitor.fields;
+        events::emit(&emitted);
+    }
+}
+
+/// 设置全局日志: 不低于 `level` 的事件写到 stderr, 有 `log_file` 时改为追加到该文件.
+///
+/// `level` 只过滤写出的日志, info 级别的阶段事件总是交给 [`EventLayer`].
+/// 依赖中用 `log` 写的日志 (例如 NCBI 下载) 也转为 tracing 事件
+pub fn init_logging(
+    level: LevelFilter,
+    format: LogFormat,
+    log_file: Option<&Path>,
+) -> io::Result<()> {
+    // NCBI 下载的重试中间件每次重试都写一条警告, 只保留错误
+    let level = Targets::new()
+        .with_target("reqwest_retry::middleware", level.min(LevelFilter::ERROR))
+        .with_default(level);
+    let layer = tracing_subscriber::fmt::layer().with_target(false);
+    let fmt_layer: Box<dyn Layer<Registry> + Send + Sync> = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            let layer = layer.with_ansi(false).with_writer(Mutex::new(file));
+            match format {
+                LogFormat::Text => layer.with_filter(level).boxed(),
+                LogFormat::Json => layer.json().flatten_event(true).with_filter(level).boxed(),
+            }
+        }
+        None => {
+            let layer = layer
+                .with_ansi(io::stderr().is_terminal())
+                .with_writer(|| StderrWriter);
+            match format {
+                LogFormat::Text => layer.with_filter(level).boxed(),
+                LogFormat::Json => layer.json().flatten_event(true).with_filter(level).boxed(),
+            }
+        }
+    };
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(EventLayer.with_filter(LevelFilter::INFO))
+        .try_init()
+        .map_err(|e| io::Error::other(e.to_string()))
+}
+
+/// 单独运行的子命令使用的默认日志: info 级别的文本写到 stderr
+pub fn init_default_logging() {
+    let _ = init_logging(LevelFilter::INFO, LogFormat::Text, None);
+}
+
+/// 事件中的吞吐量字段: 每秒处理的数量
+///
+/// ```
+/// use kraken2_rs::logging::per_sec;
+/// use std::time::Duration;
+///
+/// assert_eq!(per_sec(3000, Duration::from_secs(2)), 1500.0);
+/// assert_eq!(per_sec(10, Duration::ZERO), 0.0);
+/// ```
+pub fn per_sec(count: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        count as f64 / secs
+    } else {
+        0.0
+    }
+}
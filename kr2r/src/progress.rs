@@ -0,0 +1,90 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+fn bars() -> &'static MultiProgress {
+    BARS.get_or_init(MultiProgress::new)
+}
+
+/// 暂时清除显示中的进度条, 运行 `f` (例如写一行日志到 stderr) 后重新绘制
+pub fn suspend<F: FnOnce() -> R, R>(f: F) -> R {
+    bars().suspend(f)
+}
+
+/// `--quiet`: 不显示进度条
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// 一个阶段的进度条: 已完成的单元 (文件, chunk 或样本), 已处理的 reads 数与 ETA
+///
+/// 进度条写到 stderr, 只在终端上显示, `--quiet` 时隐藏. 日志经过 [`suspend`] 写出,
+/// 不会打乱进度条.
+///
+/// ```
+/// use kraken2_rs::progress::{set_quiet, StageProgress};
+///
+/// set_quiet(true);
+/// let progress = StageProgress::new("resolve", 2, "samples");
+/// progress.add_reads(1000);
+/// progress.inc();
+/// assert_eq!(progress.reads(), 1000);
+/// progress.finish();
+/// ```
+pub struct StageProgress {
+    bar: ProgressBar,
+    unit: String,
+    reads: AtomicU64,
+}
+
+impl StageProgress {
+    pub fn new(stage: &str, len: usize, unit: &str) -> Self {
+        let style = ProgressStyle::with_template(
+            "{prefix} [{elapsed_precise}] [{bar:30}] {pos}/{len} {msg} (ETA {eta})",
+        )
+        .unwrap()
+        .progress_chars("=> ");
+        let bar = if is_quiet() {
+            ProgressBar::hidden()
+        } else {
+            bars().add(ProgressBar::new(len as u64))
+        }
+        .with_style(style)
+        .with_prefix(stage.to_string())
+        .with_message(unit.to_string());
+        Self {
+            bar,
+            unit: unit.to_string(),
+            reads: AtomicU64::new(0),
+        }
+    }
+
+    /// 完成一个单元
+    pub fn inc(&self) {
+        self.bar.inc(1);
+    }
+
+    /// 累加已处理的 reads
+    pub fn add_reads(&self, reads: u64) {
+        let total = self.reads.fetch_add(reads, Ordering::Relaxed) + reads;
+        self.bar
+            .set_message(format!("{}, {} reads", self.unit, total));
+    }
+
+    pub fn reads(&self) -> u64 {
+        self.reads.load(Ordering::Relaxed)
+    }
+
+    /// 结束并清除进度条
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+        bars().remove(&self.bar);
+    }
+}
@@ -0,0 +1,432 @@
+use crate::abundance::read_kreport;
+use crate::domains::RUN_SUMMARY;
+use crate::filenames::{read_output_stems, SAMPLE_NAMES_FILE};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+
+/// 项目库的 schema 版本, 保存在 `PRAGMA user_version` 中
+const SCHEMA_VERSION: i64 = 1;
+/// samples: 每个样本一行, 批次, 日期与 reads 数; sample_metadata: 样本表的元数据, 每个键一行;
+/// taxa: 每个样本的每个 taxon 一行
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS samples (
+    run TEXT NOT NULL,
+    sample TEXT NOT NULL,
+    date TEXT NOT NULL,
+    reads INTEGER NOT NULL,
+    PRIMARY KEY (run, sample)
+);
+CREATE TABLE IF NOT EXISTS sample_metadata (
+    run TEXT NOT NULL,
+    sample TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (run, sample, key)
+);
+CREATE TABLE IF NOT EXISTS taxa (
+    run TEXT NOT NULL,
+    sample TEXT NOT NULL,
+    taxid INTEGER NOT NULL,
+    rank TEXT NOT NULL,
+    name TEXT NOT NULL,
+    clade_reads INTEGER NOT NULL,
+    percent REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS taxa_taxid ON taxa (taxid);
+CREATE INDEX IF NOT EXISTS taxa_name ON taxa (name COLLATE NOCASE);
+CREATE INDEX IF NOT EXISTS taxa_sample ON taxa (run, sample);
+";
+/// 样本元数据中的采样日期, 没有时使用批次的日期
+const SAMPLE_DATE_KEYS: [&str; 2] = ["collection_date", "date"];
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// 项目中的一个样本
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectSample {
+    pub run: String,
+    pub sample: String,
+    /// 采样日期 (元数据中的 collection_date 或 date) 或批次的日期
+    pub date: String,
+    pub reads: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// 项目中一个样本的一个 taxon
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaxonRecord {
+    pub run: String,
+    pub sample: String,
+    pub taxid: u64,
+    pub rank: String,
+    pub name: String,
+    pub clade_reads: u64,
+    pub percent: f64,
+}
+
+/// `project trend` 的 taxon: taxid 或名称 (不区分大小写)
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaxonQuery {
+    Taxid(u64),
+    Name(String),
+}
+
+impl std::str::FromStr for TaxonQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("empty taxon".to_string());
+        }
+        Ok(match s.parse() {
+            Ok(taxid) => TaxonQuery::Taxid(taxid),
+            Err(_) => TaxonQuery::Name(s.to_string()),
+        })
+    }
+}
+
+impl TaxonQuery {
+    /// taxa 表中选出该 taxon 的条件, 参数为 `?1`
+    fn condition(&self) -> (&'static str, rusqlite::types::Value) {
+        match self {
+            TaxonQuery::Taxid(taxid) => ("taxid = ?1", (*taxid as i64).into()),
+            TaxonQuery::Name(name) => ("name = ?1 COLLATE NOCASE", name.clone().into()),
+        }
+    }
+}
+
+/// 一个样本中 taxon 的 reads 数与百分比, 没有检出时为 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrendPoint {
+    /// `--by` 的元数据值, 没有分组时为空
+    pub group: String,
+    pub date: String,
+    pub run: String,
+    pub sample: String,
+    pub reads: u64,
+    pub clade_reads: u64,
+    pub percent: f64,
+}
+
+/// 多个批次的结果汇总的 SQLite 项目库, 表 `samples`, `sample_metadata` 与 `taxa`.
+///
+/// 每个批次以名称区分, 可以整批替换或删除. 其他查询可以直接用 `sqlite3` 或 DuckDB 打开项目库
+///
+/// ```
+/// use kraken2_rs::project::{Project, TaxonQuery};
+///
+/// let dir = std::env::temp_dir().join(format!("project_doctest_{}", std::process::id()));
+/// let run_dir = dir.join("run1");
+/// std::fs::create_dir_all(&run_dir).unwrap();
+/// std::fs::write(
+///     run_dir.join("run_summary.json"),
+///     r#"{"samples": [{"sample": "output_1", "metadata": {"patient_id": "P1"}, "reads": 100}]}"#,
+/// )
+/// .unwrap();
+/// std::fs::write(
+///     run_dir.join("output_1.kreport2"),
+///     " 10.00\t10\t10\tU\t0\tunclassified\n 90.00\t90\t0\tR\t1\troot\n 40.00\t40\t40\tS\t562\t    Escherichia coli\n",
+/// )
+/// .unwrap();
+///
+/// let mut project = Project::open(dir.join("project.sqlite")).unwrap();
+/// assert_eq!(project.add_run(&run_dir, "run1", "2024-03-01", false).unwrap(), 1);
+/// assert!(project.add_run(&run_dir, "run1", "2024-03-01", false).is_err());
+///
+/// let query: TaxonQuery = "escherichia coli".parse().unwrap();
+/// let trend = project.trend(&query, Some("patient_id")).unwrap();
+/// assert_eq!(trend.len(), 1);
+/// assert_eq!((trend[0].group.as_str(), trend[0].clade_reads), ("P1", 40));
+/// assert_eq!(project.remove_run("run1").unwrap(), 1);
+/// assert!(project.samples().unwrap().is_empty());
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct Project {
+    conn: Connection,
+}
+
+impl Project {
+    /// 打开项目库, 不存在时创建
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let conn =
+            Connection::open(path).map_err(|e| io::Error::other(format!("{:?}: {}", path, e)))?;
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(sql_error)?;
+        if version > SCHEMA_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{:?} has project schema version {}, this program reads up to {}",
+                    path, version, SCHEMA_VERSION
+                ),
+            ));
+        }
+        conn.execute_batch(SCHEMA).map_err(sql_error)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)
+            .map_err(sql_error)?;
+        Ok(Self { conn })
+    }
+
+    /// 所有样本与其元数据, 按导入的顺序
+    pub fn samples(&self) -> io::Result<Vec<ProjectSample>> {
+        let mut metadata: BTreeMap<(String, String), BTreeMap<String, String>> = BTreeMap::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT run, sample, key, value FROM sample_metadata")
+            .map_err(sql_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .map_err(sql_error)?;
+        for row in rows {
+            let (run, sample, key, value) = row.map_err(sql_error)?;
+            metadata
+                .entry((run, sample))
+                .or_default()
+                .insert(key, value);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT run, sample, date, reads FROM samples ORDER BY rowid")
+            .map_err(sql_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(ProjectSample {
+                    run: row.get(0)?,
+                    sample: row.get(1)?,
+                    date: row.get(2)?,
+                    reads: row.get(3)?,
+                    metadata: BTreeMap::new(),
+                })
+            })
+            .map_err(sql_error)?;
+        rows.map(|row| {
+            let mut sample = row.map_err(sql_error)?;
+            if let Some(values) = metadata.remove(&(sample.run.clone(), sample.sample.clone())) {
+                sample.metadata = values;
+            }
+            Ok(sample)
+        })
+        .collect()
+    }
+
+    /// 逐行读取 taxa 表, 按导入的顺序
+    pub fn for_each_taxon<F: FnMut(TaxonRecord)>(&self, mut f: F) -> io::Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT run, sample, taxid, rank, name, clade_reads, percent FROM taxa ORDER BY rowid",
+            )
+            .map_err(sql_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(TaxonRecord {
+                    run: row.get(0)?,
+                    sample: row.get(1)?,
+                    taxid: row.get(2)?,
+                    rank: row.get(3)?,
+                    name: row.get(4)?,
+                    clade_reads: row.get(5)?,
+                    percent: row.get(6)?,
+                })
+            })
+            .map_err(sql_error)?;
+        for row in rows {
+            f(row.map_err(sql_error)?);
+        }
+        Ok(())
+    }
+
+    /// 导入一个输出目录 (`direct` 或 `classify` 的 `--output-dir`) 中的 run_summary.json 与
+    /// 各样本的 kreport2, 返回导入的样本数. 已有同名批次时报错, `replace` 时替换.
+    /// 在一个事务中写入, 失败时项目库不变
+    pub fn add_run(
+        &mut self,
+        output_dir: &Path,
+        run: &str,
+        date: &str,
+        replace: bool,
+    ) -> io::Result<usize> {
+        if run.is_empty() || run.contains(['\t', '\n']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid run name '{}'", run),
+            ));
+        }
+        let summaries = read_run_summary(&output_dir.join(RUN_SUMMARY))?;
+        let exists = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM samples WHERE run = ?1 LIMIT 1",
+                [run],
+                |_| Ok(()),
+            )
+            .optional()
+            .map_err(sql_error)?
+            .is_some();
+        if exists && !replace {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("run '{}' is already in the project, use --replace", run),
+            ));
+        }
+        // 样本有名称时 kreport 的文件名见 sample_names.tsv
+        let stems = read_output_stems(output_dir.join(SAMPLE_NAMES_FILE))?;
+        // 先读入全部 kreport, 缺少文件时不写入任何记录
+        let mut reports = Vec::with_capacity(summaries.len());
+        for summary in &summaries {
+            let stem = stems.get(&summary.sample).unwrap_or(&summary.sample);
+            let kreport = output_dir.join(format!("{}.kreport2", stem));
+            reports.push(
+                read_kreport(&kreport)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", kreport, e)))?,
+            );
+        }
+
+        let tx = self.conn.transaction().map_err(sql_error)?;
+        delete_run(&tx, run)?;
+        {
+            let mut samples = tx
+                .prepare("INSERT INTO samples (run, sample, date, reads) VALUES (?1, ?2, ?3, ?4)")
+                .map_err(sql_error)?;
+            let mut metadata = tx
+                .prepare(
+                    "INSERT INTO sample_metadata (run, sample, key, value) VALUES (?1, ?2, ?3, ?4)",
+                )
+                .map_err(sql_error)?;
+            let mut taxa = tx
+                .prepare(
+                    "INSERT INTO taxa (run, sample, taxid, rank, name, clade_reads, percent)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )
+                .map_err(sql_error)?;
+            for (summary, report) in summaries.iter().zip(reports) {
+                let sample_date = SAMPLE_DATE_KEYS
+                    .iter()
+                    .find_map(|key| summary.metadata.get(*key))
+                    .map_or(date, String::as_str);
+                samples
+                    .execute(params![run, summary.sample, sample_date, summary.reads])
+                    .map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("sample '{}': {}", summary.sample, e),
+                        )
+                    })?;
+                for (key, value) in &summary.metadata {
+                    metadata
+                        .execute(params![run, summary.sample, key, value])
+                        .map_err(sql_error)?;
+                }
+                for line in report {
+                    let percent = if summary.reads > 0 {
+                        line.clade_reads as f64 * 100.0 / summary.reads as f64
+                    } else {
+                        0.0
+                    };
+                    taxa.execute(params![
+                        run,
+                        summary.sample,
+                        line.taxid,
+                        line.rank,
+                        line.name,
+                        line.clade_reads,
+                        percent
+                    ])
+                    .map_err(sql_error)?;
+                }
+            }
+        }
+        tx.commit().map_err(sql_error)?;
+        Ok(summaries.len())
+    }
+
+    /// 删除一个批次的全部记录, 返回删除的样本数
+    pub fn remove_run(&mut self, run: &str) -> io::Result<usize> {
+        let tx = self.conn.transaction().map_err(sql_error)?;
+        let removed = delete_run(&tx, run)?;
+        tx.commit().map_err(sql_error)?;
+        Ok(removed)
+    }
+
+    /// taxon 在每个样本中的 reads 数与百分比, 按 `by` 的元数据值分组, 组内按日期排序.
+    /// 没有检出该 taxon 的样本记为 0, 没有 `by` 元数据的样本分在空组中
+    pub fn trend(&self, taxon: &TaxonQuery, by: Option<&str>) -> io::Result<Vec<TrendPoint>> {
+        let (condition, value) = taxon.condition();
+        // 同名的多个 taxon 合计
+        let sql = format!(
+            "SELECT COALESCE(m.value, ''), s.date, s.run, s.sample, s.reads,
+                    COALESCE(t.clade_reads, 0), COALESCE(t.percent, 0.0)
+             FROM samples s
+             LEFT JOIN (SELECT run, sample, SUM(clade_reads) AS clade_reads, SUM(percent) AS percent
+                        FROM taxa WHERE {} GROUP BY run, sample) t
+               ON t.run = s.run AND t.sample = s.sample
+             LEFT JOIN sample_metadata m
+               ON m.run = s.run AND m.sample = s.sample AND m.key = ?2
+             ORDER BY COALESCE(m.value, ''), s.date, s.run, s.rowid",
+            condition
+        );
+        let mut stmt = self.conn.prepare(&sql).map_err(sql_error)?;
+        let rows = stmt
+            .query_map(params![value, by], |row| {
+                Ok(TrendPoint {
+                    group: row.get(0)?,
+                    date: row.get(1)?,
+                    run: row.get(2)?,
+                    sample: row.get(3)?,
+                    reads: row.get(4)?,
+                    clade_reads: row.get(5)?,
+                    percent: row.get(6)?,
+                })
+            })
+            .map_err(sql_error)?;
+        rows.map(|row| row.map_err(sql_error)).collect()
+    }
+}
+
+/// 删除一个批次在三个表中的记录, 返回删除的样本数
+fn delete_run(conn: &Connection, run: &str) -> io::Result<usize> {
+    conn.execute("DELETE FROM taxa WHERE run = ?1", [run])
+        .map_err(sql_error)?;
+    conn.execute("DELETE FROM sample_metadata WHERE run = ?1", [run])
+        .map_err(sql_error)?;
+    conn.execute("DELETE FROM samples WHERE run = ?1", [run])
+        .map_err(sql_error)
+}
+
+/// run_summary.json 中导入需要的字段
+#[derive(Debug, Deserialize)]
+struct SummarySample {
+    sample: String,
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+    reads: u64,
+}
+
+fn read_run_summary(path: &Path) -> io::Result<Vec<SummarySample>> {
+    #[derive(Deserialize)]
+    struct RunSummary {
+        samples: Vec<SummarySample>,
+    }
+
+    let file =
+        File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{:?}: {}", path, e)))?;
+    let summary: RunSummary = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", path, e)))?;
+    Ok(summary.samples)
+}
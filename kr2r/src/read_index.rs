@@ -0,0 +1,280 @@
+use crate::args::{OutputCompression, OutputFormat};
+use crate::checksums::checksum_bytes;
+use crate::utils::create_output_writer;
+use byteorder::{ByteOrder, LittleEndian};
+use memmap2::Mmap;
+use noodles::bgzf::{self, VirtualPosition};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{BufRead, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+
+/// read id 索引文件的后缀, 接在输出文件名之后 (`output_1.txt.gz.ridx`)
+pub const READ_INDEX_SUFFIX: &str = ".ridx";
+
+const MAGIC: &[u8; 8] = b"KR2RRIX1";
+const HEADER_SIZE: usize = 24;
+const ENTRY_SIZE: usize = 16;
+
+/// 输出文件对应的 read id 索引
+pub fn read_index_path<P: AsRef<Path>>(output: P) -> PathBuf {
+    let mut path = output.as_ref().as_os_str().to_owned();
+    path.push(READ_INDEX_SUFFIX);
+    PathBuf::from(path)
+}
+
+#[derive(Deserialize)]
+struct JsonReadId<'a> {
+    #[serde(borrow)]
+    read_id: Cow<'a, str>,
+}
+
+/// 一行逐条 read 输出中的 read id: kraken 格式为第二列, JSON 格式为 `read_id` 字段
+///
+/// ```
+/// use kraken2_rs::args::OutputFormat;
+/// use kraken2_rs::read_index::line_read_id;
+///
+/// let line = b"C\tread1\t9606\t150\t9606:116\n";
+/// assert_eq!(line_read_id(OutputFormat::Kraken, line).as_deref(), Some("read1"));
+/// let line = br#"{"read_id":"read2","taxid":0}"#;
+/// assert_eq!(line_read_id(OutputFormat::Json, line).as_deref(), Some("read2"));
+/// ```
+pub fn line_read_id(format: OutputFormat, line: &[u8]) -> Option<Cow<'_, str>> {
+    match format {
+        OutputFormat::Kraken => line
+            .split(|&b| b == b'\t')
+            .nth(1)
+            .and_then(|id| std::str::from_utf8(id).ok())
+            .map(Cow::Borrowed),
+        OutputFormat::Json => serde_json::from_slice::<JsonReadId>(line)
+            .ok()
+            .map(|record| record.read_id),
+    }
+}
+
+fn format_code(format: OutputFormat) -> u64 {
+    match format {
+        OutputFormat::Kraken => 0,
+        OutputFormat::Json => 1,
+    }
+}
+
+/// bgzf 压缩的逐条 read 输出, 同时记录每行起始的 virtual offset
+///
+/// 索引为 read id 的 seahash 与 virtual offset 的有序数组, 在 `flush` 时写出,
+/// 每条 read 在内存中占 16 字节
+pub struct IndexedOutputWriter {
+    writer: bgzf::io::Writer<File>,
+    index_path: PathBuf,
+    format: OutputFormat,
+    /// 当前行已写出的内容与起始位置
+    line: Vec<u8>,
+    line_start: Option<VirtualPosition>,
+    entries: Vec<(u64, u64)>,
+    indexed: Option<usize>,
+}
+
+impl IndexedOutputWriter {
+    pub fn create<P: AsRef<Path>>(path: P, format: OutputFormat) -> Result<Self> {
+        let path = path.as_ref();
+        Ok(Self {
+            writer: bgzf::io::Writer::new(File::create(path)?),
+            index_path: read_index_path(path),
+            format,
+            line: Vec::new(),
+            line_start: None,
+            entries: Vec::new(),
+            indexed: None,
+        })
+    }
+
+    fn end_line(&mut self) {
+        let line = self.line.strip_suffix(b"\n").unwrap_or(&self.line);
+        if let (Some(id), Some(start)) = (line_read_id(self.format, line), self.line_start) {
+            self.entries
+                .push((checksum_bytes(id.as_bytes()), u64::from(start)));
+        }
+        self.line.clear();
+        self.line_start = None;
+    }
+
+    /// 先写临时文件再重命名
+    fn write_index(&mut self) -> Result<()> {
+        if self.indexed == Some(self.entries.len()) {
+            return Ok(());
+        }
+        self.entries.sort_unstable();
+        let mut tmp = self.index_path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        let mut header = [0u8; HEADER_SIZE];
+        header[..8].copy_from_slice(MAGIC);
+        LittleEndian::write_u64(&mut header[8..16], self.entries.len() as u64);
+        LittleEndian::write_u64(&mut header[16..24], format_code(self.format));
+        writer.write_all(&header)?;
+        let mut entry = [0u8; ENTRY_SIZE];
+        for &(hash, position) in &self.entries {
+            LittleEndian::write_u64(&mut entry[..8], hash);
+            LittleEndian::write_u64(&mut entry[8..], position);
+            writer.write_all(&entry)?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(tmp, &self.index_path)?;
+        self.indexed = Some(self.entries.len());
+        Ok(())
+    }
+}
+
+impl Write for IndexedOutputWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if self.line_start.is_none() {
+                self.line_start = Some(self.writer.virtual_position());
+            }
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(rest.len(), |pos| pos + 1);
+            let (segment, tail) = rest.split_at(end);
+            self.writer.write_all(segment)?;
+            self.line.extend_from_slice(segment);
+            if segment.ends_with(b"\n") {
+                self.end_line();
+            }
+            rest = tail;
+        }
+        Ok(buf.len())
+    }
+
+    /// 同时写出索引
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.write_index()
+    }
+}
+
+/// 逐条 read 输出的 writer: bgzf 压缩时为 [`IndexedOutputWriter`], 否则同 [`create_output_writer`]
+pub fn create_read_output_writer<P: AsRef<Path>>(
+    path: P,
+    compression: OutputCompression,
+    format: OutputFormat,
+) -> Result<Box<dyn Write + Send>> {
+    match compression {
+        OutputCompression::Bgzf => Ok(Box::new(IndexedOutputWriter::create(path, format)?)),
+        _ => create_output_writer(path, compression),
+    }
+}
+
+/// 按 read id 随机读取 bgzf 逐条 read 输出中的行
+///
+/// ```
+/// use kraken2_rs::args::OutputFormat;
+/// use kraken2_rs::read_index::{IndexedOutput, IndexedOutputWriter};
+/// use std::io::Write;
+///
+/// let path = std::env::temp_dir().join("indexed_output_doctest.txt.gz");
+/// let mut writer = IndexedOutputWriter::create(&path, OutputFormat::Kraken).unwrap();
+/// for i in 0..50_000 {
+///     writeln!(writer, "C\tread{}\t9606\t150\t9606:116", i).unwrap();
+/// }
+/// writer.flush().unwrap();
+/// drop(writer);
+///
+/// let mut output = IndexedOutput::open(&path).unwrap();
+/// assert_eq!(output.len(), 50_000);
+/// assert_eq!(
+///     output.get("read43210").unwrap().as_deref(),
+///     Some("C\tread43210\t9606\t150\t9606:116")
+/// );
+/// assert_eq!(output.get("read50000").unwrap(), None);
+/// std::fs::remove_file(kraken2_rs::read_index::read_index_path(&path)).unwrap();
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct IndexedOutput {
+    index: Mmap,
+    reader: bgzf::io::Reader<File>,
+    format: OutputFormat,
+}
+
+impl IndexedOutput {
+    pub fn open<P: AsRef<Path>>(output: P) -> Result<Self> {
+        let output = output.as_ref();
+        let index_path = read_index_path(output);
+        let index = unsafe { Mmap::map(&File::open(&index_path)?)? };
+        let invalid = |message: &str| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?}: {}", index_path, message),
+            )
+        };
+        if index.len() < HEADER_SIZE || &index[..8] != MAGIC {
+            return Err(invalid("not a read id index"));
+        }
+        let count = LittleEndian::read_u64(&index[8..16]) as usize;
+        if index.len() != HEADER_SIZE + count * ENTRY_SIZE {
+            return Err(invalid("truncated read id index"));
+        }
+        let format = match LittleEndian::read_u64(&index[16..24]) {
+            0 => OutputFormat::Kraken,
+            1 => OutputFormat::Json,
+            _ => return Err(invalid("unknown output format")),
+        };
+        Ok(Self {
+            index,
+            reader: bgzf::io::Reader::new(File::open(output)?),
+            format,
+        })
+    }
+
+    /// 索引中的 read 数
+    pub fn len(&self) -> usize {
+        (self.index.len() - HEADER_SIZE) / ENTRY_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn entry(&self, i: usize) -> (u64, u64) {
+        let offset = HEADER_SIZE + i * ENTRY_SIZE;
+        (
+            LittleEndian::read_u64(&self.index[offset..offset + 8]),
+            LittleEndian::read_u64(&self.index[offset + 8..offset + 16]),
+        )
+    }
+
+    /// read id 对应的输出行 (不含换行), 不在输出中时为 `None`
+    pub fn get(&mut self, read_id: &str) -> Result<Option<String>> {
+        let hash = checksum_bytes(read_id.as_bytes());
+        let mut low = 0;
+        let mut high = self.len();
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.entry(mid).0 < hash {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        // seahash 相同的 read id 逐个核对
+        let mut line = String::new();
+        for i in low..self.len() {
+            let (entry_hash, position) = self.entry(i);
+            if entry_hash != hash {
+                break;
+            }
+            self.reader.seek(VirtualPosition::from(position))?;
+            line.clear();
+            self.reader.read_line(&mut line)?;
+            let text = line.trim_end_matches(['\n', '\r']);
+            if line_read_id(self.format, text.as_bytes()).as_deref() == Some(read_id) {
+                return Ok(Some(text.to_string()));
+            }
+        }
+        Ok(None)
+    }
+}
@@ -1,6 +1,7 @@
 use crate::KBuildHasher;
 use dashmap::DashMap;
 use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::hash::BuildHasher;
@@ -65,7 +66,7 @@ impl Unionable for HashSet<u64> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ReadCounts<T>
 where
     T: Unionable,
@@ -149,7 +150,7 @@ pub type ReadCounter = ReadCounts<HyperLogLogPlus<u64, KBuildHasher>>;
 impl Default for ReadCounter {
     fn default() -> Self {
         let kmers: HyperLogLogPlus<u64, KBuildHasher> =
-            HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap();
+            HyperLogLogPlus::new(16, KBuildHasher).unwrap();
         ReadCounts::with_capacity(kmers, 0, 0)
     }
 }
@@ -158,7 +159,7 @@ impl Default for ReadCounter {
 impl ReadCounter {
     pub fn new(n_reads: u64, n_kmers: u64) -> Self {
         let kmers: HyperLogLogPlus<u64, KBuildHasher> =
-            HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap();
+            HyperLogLogPlus::new(16, KBuildHasher).unwrap();
         ReadCounts::with_capacity(kmers, n_reads, n_kmers)
     }
 }
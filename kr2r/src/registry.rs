@@ -0,0 +1,121 @@
+use crate::compact_hash::HashConfig;
+use crate::IndexOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 用户配置目录下的数据库注册文件
+pub const REGISTRY_FILE: &str = "kun_peng/databases.toml";
+
+/// 注册的数据库路径与添加时读取的数据库信息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseEntry {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub k: usize,
+    #[serde(default)]
+    pub l: usize,
+    /// hash shard 数
+    #[serde(default)]
+    pub shards: usize,
+    /// 哈希表中的 minimizer 数
+    #[serde(default)]
+    pub minimizers: usize,
+    /// 添加时间 (unix 秒)
+    #[serde(default)]
+    pub added: u64,
+}
+
+impl DatabaseEntry {
+    /// 读取数据库目录中的 opts.k2d 与 hash_config.k2d
+    pub fn from_database(path: &Path, description: Option<String>) -> Result<Self> {
+        let config_file = path.join("hash_config.k2d");
+        if !config_file.is_file() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{:?} is not a database, hash_config.k2d is missing", path),
+            ));
+        }
+        let config = HashConfig::from_hash_header(config_file)?;
+        let opts = IndexOptions::read_index_options(path.join("opts.k2d"))?;
+        let added = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        Ok(Self {
+            path: fs::canonicalize(path)?,
+            description,
+            k: opts.k,
+            l: opts.l,
+            shards: config.partition,
+            minimizers: config.size,
+            added,
+        })
+    }
+}
+
+/// 名称到数据库的注册表, 保存在 `~/.config/kun_peng/databases.toml` (设置了 `XDG_CONFIG_HOME` 时在其中)
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Registry {
+    #[serde(default)]
+    pub databases: BTreeMap<String, DatabaseEntry>,
+}
+
+impl Registry {
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join(REGISTRY_FILE))
+    }
+
+    /// 读取注册表, 文件不存在时为空
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Registry::default());
+        }
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("malformed database registry {:?}: {}", path, e),
+            )
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let tmp = path.with_extension("toml.tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(tmp, path)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DatabaseEntry> {
+        self.databases.get(name)
+    }
+}
+
+/// `--db` 的值: 存在的目录照原样使用, 否则按名称在注册表中查找, 都找不到时照原样返回
+pub fn resolve_database(db: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(db);
+    if path.exists() || db.contains(std::path::MAIN_SEPARATOR) {
+        return Ok(path);
+    }
+    let Some(registry_file) = Registry::default_path() else {
+        return Ok(path);
+    };
+    let registry = Registry::load(&registry_file)?;
+    Ok(registry
+        .get(db)
+        .map(|entry| entry.path.clone())
+        .unwrap_or(path))
+}
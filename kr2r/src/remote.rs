@@ -0,0 +1,353 @@
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{HeaderValue, CONTENT_RANGE, ETAG, IF_MATCH, RANGE};
+use reqwest::StatusCode;
+use seqkmer::ThreadedReader;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// 每个 range 请求读取的字节数
+const RANGE_SIZE: u64 = 8 << 20;
+/// 每个请求最多的尝试次数
+const MAX_ATTEMPTS: u32 = 6;
+/// 第一次重试前的等待, 之后每次加倍
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// 一个 range 请求 (包括读完响应) 的超时
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// URL 中不需要转义的字符 (RFC 3986 的 unreserved 与路径分隔符)
+fn is_url_safe(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~' | b'/')
+}
+
+fn escape_key(key: &str) -> String {
+    let mut escaped = String::with_capacity(key.len());
+    for &byte in key.as_bytes() {
+        if is_url_safe(byte) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+/// `s3://` 与 `gs://` 换成对象存储的 HTTPS 地址, 其他 URL 不变.
+///
+/// `s3://` 使用 `AWS_ENDPOINT_URL` (path-style, 适用于 MinIO 等) 或 `AWS_REGION` /
+/// `AWS_DEFAULT_REGION` 的 virtual-hosted 地址. 请求不签名, 只能读取公开的对象;
+/// 私有的对象请使用预签名的 https URL (`aws s3 presign`, `gcloud storage sign-url`)
+///
+/// ```
+/// use kraken2_rs::remote::object_url;
+///
+/// assert_eq!(
+///     object_url("gs://bucket/runs/reads 1.fq.gz"),
+///     "https://storage.googleapis.com/bucket/runs/reads%201.fq.gz"
+/// );
+/// assert_eq!(object_url("https://example.org/r.fq"), "https://example.org/r.fq");
+/// ```
+pub fn object_url(url: &str) -> String {
+    if let Some(path) = url.strip_prefix("gs://") {
+        return format!("https://storage.googleapis.com/{}", escape_key(path));
+    }
+    let Some(path) = url.strip_prefix("s3://") else {
+        return url.to_string();
+    };
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    if let Some(endpoint) = env("AWS_ENDPOINT_URL_S3").or_else(|| env("AWS_ENDPOINT_URL")) {
+        return format!(
+            "{}/{}/{}",
+            endpoint.trim_end_matches('/'),
+            bucket,
+            escape_key(key)
+        );
+    }
+    match env("AWS_REGION").or_else(|| env("AWS_DEFAULT_REGION")) {
+        Some(region) => format!(
+            "https://{}.s3.{}.amazonaws.com/{}",
+            bucket,
+            region,
+            escape_key(key)
+        ),
+        None => format!("https://{}.s3.amazonaws.com/{}", bucket, escape_key(key)),
+    }
+}
+
+/// 一次请求的失败, `retry` 表示重试可能成功 (超时, 连接错误, 429 与 5xx)
+struct Failure {
+    error: io::Error,
+    retry: bool,
+}
+
+impl From<reqwest::Error> for Failure {
+    fn from(e: reqwest::Error) -> Self {
+        let kind = if e.is_timeout() {
+            io::ErrorKind::TimedOut
+        } else if e.is_connect() {
+            io::ErrorKind::ConnectionRefused
+        } else {
+            io::ErrorKind::ConnectionReset
+        };
+        Failure {
+            error: io::Error::new(kind, e),
+            retry: true,
+        }
+    }
+}
+
+fn status_failure(status: StatusCode) -> Failure {
+    let kind = match status {
+        StatusCode::NOT_FOUND => io::ErrorKind::NotFound,
+        StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED => io::ErrorKind::PermissionDenied,
+        _ if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() => {
+            io::ErrorKind::ConnectionReset
+        }
+        _ => io::ErrorKind::Other,
+    };
+    Failure {
+        error: io::Error::new(kind, format!("HTTP {}", status)),
+        retry: kind == io::ErrorKind::ConnectionReset,
+    }
+}
+
+/// 执行一次请求, 失败时按 1, 2, 4 ... 秒等待后重试, 最多 [`MAX_ATTEMPTS`] 次
+fn with_retries<T, F>(url: &str, pos: u64, mut request: F) -> io::Result<T>
+where
+    F: FnMut() -> Result<T, Failure>,
+{
+    let mut delay = RETRY_DELAY;
+    for attempt in 1.. {
+        match request() {
+            Ok(value) => return Ok(value),
+            Err(failure) if failure.retry && attempt < MAX_ATTEMPTS => {
+                warn!(
+                    "{} at byte {}: {}, retry in {:?}",
+                    url, pos, failure.error, delay
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            Err(failure) => {
+                return Err(io::Error::new(
+                    failure.error.kind(),
+                    format!("{}: {}", url, failure.error),
+                ))
+            }
+        }
+    }
+    unreachable!()
+}
+
+pub fn http_client() -> io::Result<Client> {
+    Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(io::Error::other)
+}
+
+/// 读取 URL 中从 `start` 开始的 `len` 个字节 (文件末尾时可能更少), 失败时重试
+///
+/// ```
+/// use kraken2_rs::remote::{fetch_range, http_client};
+///
+/// // 长度为 0 时不发出请求
+/// let client = http_client().unwrap();
+/// assert!(fetch_range(&client, "http://127.0.0.1:9/none", 0, 0).unwrap().is_empty());
+/// ```
+pub fn fetch_range(client: &Client, url: &str, start: u64, len: u64) -> io::Result<Vec<u8>> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    let url = object_url(url);
+    with_retries(&url, start, || {
+        let response = client
+            .get(&url)
+            .header(RANGE, format!("bytes={}-{}", start, start + len - 1))
+            .send()?;
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => Ok(response.bytes()?.to_vec()),
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(Vec::new()),
+            // 服务器不支持 range 请求时返回整个文件
+            StatusCode::OK => {
+                let body = response.bytes()?;
+                let start = (start as usize).min(body.len());
+                let end = (start + len as usize).min(body.len());
+                Ok(body[start..end].to_vec())
+            }
+            status => Err(status_failure(status)),
+        }
+    })
+}
+
+/// 下载整个 URL 到 `dest` (先写入临时文件再重命名), 返回字节数
+pub fn fetch_file(url: &str, dest: &Path) -> io::Result<u64> {
+    let tmp = dest.with_extension(format!("tmp.{}", std::process::id()));
+    let result = File::create(&tmp).and_then(|mut file| {
+        let len = io::copy(&mut RemoteReader::open(url)?, &mut file)?;
+        file.sync_all()?;
+        Ok(len)
+    });
+    match result.and_then(|len| fs::rename(&tmp, dest).map(|_| len)) {
+        Ok(len) => Ok(len),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// Content-Range (`bytes 0-8388607/73400320`) 中的文件大小
+fn content_size(response: &Response) -> Option<u64> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    range.rsplit_once('/')?.1.parse().ok()
+}
+
+/// 以 range 请求逐段读取 URL, 每段失败时等待后重试; 服务器不支持 range 时读取整个响应
+pub struct RemoteReader {
+    client: Client,
+    url: String,
+    /// 下一个请求的开始位置
+    pos: u64,
+    /// 由 Content-Range 得到的文件大小
+    size: Option<u64>,
+    chunk: Vec<u8>,
+    offset: usize,
+    /// 服务器不支持 range 请求时的整个响应
+    stream: Option<Response>,
+    /// 第一个响应的 (strong) ETag, 之后的请求带上 If-Match, 读取过程中对象被替换时报错
+    etag: Option<HeaderValue>,
+}
+
+impl RemoteReader {
+    /// 打开 URL 并读取第一段, 因此不存在或没有权限的对象在打开时报错
+    pub fn open(url: &str) -> io::Result<Self> {
+        let mut reader = Self {
+            client: http_client()?,
+            url: object_url(url),
+            pos: 0,
+            size: None,
+            chunk: Vec::new(),
+            offset: 0,
+            stream: None,
+            etag: None,
+        };
+        reader.fetch()?;
+        Ok(reader)
+    }
+
+    fn at_end(&self) -> bool {
+        self.size.is_some_and(|size| self.pos >= size)
+    }
+
+    /// 读取下一段, 失败时重试
+    fn fetch(&mut self) -> io::Result<()> {
+        let url = self.url.clone();
+        let pos = self.pos;
+        with_retries(&url, pos, || self.fetch_once())
+    }
+
+    fn fetch_once(&mut self) -> Result<(), Failure> {
+        let end = self.pos + RANGE_SIZE - 1;
+        let mut request = self
+            .client
+            .get(&self.url)
+            .header(RANGE, format!("bytes={}-{}", self.pos, end));
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_MATCH, etag.clone());
+        }
+        let response = request.send()?;
+        let status = response.status();
+        if self.pos == 0 && self.etag.is_none() {
+            // weak ETag (W/"...") 不能用于 If-Match
+            self.etag = response
+                .headers()
+                .get(ETAG)
+                .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+                .cloned();
+        }
+        match status {
+            StatusCode::PARTIAL_CONTENT => {
+                self.size = content_size(&response).or(self.size);
+                let body = response.bytes()?;
+                if body.is_empty() {
+                    self.size = Some(self.pos);
+                }
+                self.pos += body.len() as u64;
+                self.chunk = body.to_vec();
+                self.offset = 0;
+                Ok(())
+            }
+            // 空文件或已经读到文件末尾
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                self.size = Some(self.pos);
+                self.chunk.clear();
+                self.offset = 0;
+                Ok(())
+            }
+            StatusCode::OK if self.pos == 0 => {
+                // 输入会被打开多次 (判断格式, 配对等), 只提示一次
+                static WARNED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+                let mut warned = WARNED.get_or_init(Default::default).lock().unwrap();
+                if warned.insert(self.url.clone()) {
+                    warn!(
+                        "{} does not support range requests, read it as one stream without retries",
+                        self.url
+                    );
+                }
+                self.stream = Some(response);
+                Ok(())
+            }
+            StatusCode::PRECONDITION_FAILED => Err(Failure {
+                error: io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "the object changed while it was read (at byte {}), read it again",
+                        self.pos
+                    ),
+                ),
+                retry: false,
+            }),
+            _ => Err(status_failure(status)),
+        }
+    }
+}
+
+impl Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(stream) = &mut self.stream {
+            return stream.read(buf);
+        }
+        while self.offset == self.chunk.len() {
+            if self.at_end() {
+                return Ok(0);
+            }
+            self.fetch()?;
+            if self.chunk.is_empty() {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.offset);
+        buf[..n].copy_from_slice(&self.chunk[self.offset..self.offset + n]);
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+/// 打开 URL 输入, 在后台线程中预先读取, 与 reads 的解析和分类重叠
+pub fn open_remote(url: &str) -> io::Result<Box<dyn Read + Send>> {
+    Ok(Box::new(ThreadedReader::new(RemoteReader::open(url)?)))
+}
+
+/// 允许 `s3://`, `gs://` 与 `https://` 的 FASTA/FASTQ 输入
+pub fn enable_remote_inputs() {
+    seqkmer::set_remote_opener(open_remote);
+}
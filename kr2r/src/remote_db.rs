@@ -0,0 +1,515 @@
+use crate::compact_hash::{CellSource, HashConfig, HashPage, LazyPage};
+use crate::error::Kr2rError;
+use crate::remote::{fetch_file, fetch_range, http_client};
+use byteorder::{ByteOrder, LittleEndian};
+use reqwest::blocking::Client;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// 每个 range 请求读取的单元数 (1 MiB), 也是磁盘缓存中每个块文件的大小
+const BLOCK_CELLS: usize = 256 * 1024;
+const BLOCK_BYTES: u64 = (BLOCK_CELLS * std::mem::size_of::<u32>()) as u64;
+/// 内存中保留的块数 (256 MiB)
+const MEMORY_BLOCKS: usize = 256;
+/// hash_{n}.k2d 的文件头: index 与 capacity
+const HEADER_SIZE: u64 = 16;
+/// 与 hash shard 一起使用的小文件, 每次运行都读取 hash_config.k2d 以发现数据库的更新
+const DATABASE_FILES: [&str; 2] = ["opts.k2d", "taxo.k2d"];
+const HASH_CONFIG: &str = "hash_config.k2d";
+
+/// 不存在的远程文件与本地数据库相同, 报告为缺少的数据库文件
+fn missing_index(url: &str, e: io::Error) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::NotFound => Kr2rError::MissingIndex(PathBuf::from(url)).into(),
+        _ => e,
+    }
+}
+
+fn fetch_database_file(url: &str, dest: &Path) -> io::Result<u64> {
+    fetch_file(url, dest).map_err(|e| missing_index(url, e))
+}
+
+/// 没有 `--db-cache` 时的缓存目录, 由 URL 决定
+pub fn default_cache_dir(url: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    url.trim_end_matches('/').hash(&mut hasher);
+    std::env::temp_dir().join(format!("kraken2_rs-db-{:016x}", hasher.finish()))
+}
+
+/// (shard 序号, 从 1 开始; shard 中的块序号)
+type BlockKey = (usize, usize);
+
+fn block_name((shard, block): BlockKey) -> String {
+    format!("{}_{}.blk", shard, block)
+}
+
+fn parse_block_name(name: &str) -> Option<BlockKey> {
+    let (shard, block) = name.strip_suffix(".blk")?.split_once('_')?;
+    Some((shard.parse().ok()?, block.parse().ok()?))
+}
+
+fn decode_cells(bytes: &[u8]) -> Option<Vec<u32>> {
+    if !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+    Some(bytes.chunks_exact(4).map(LittleEndian::read_u32).collect())
+}
+
+const NIL: usize = usize::MAX;
+/// 内存中的块分到多个互相独立加锁的分片, 读取线程不在同一把锁上排队
+const MEMORY_SHARDS: usize = 16;
+
+struct Node<V> {
+    key: BlockKey,
+    value: Option<V>,
+    prev: usize,
+    next: usize,
+}
+
+/// 节点以下标相连的双向链表, 表头为最近使用的块; 查找, 使用与淘汰都是 O(1)
+struct Lru<V> {
+    index: HashMap<BlockKey, usize>,
+    nodes: Vec<Node<V>>,
+    free: Vec<usize>,
+    head: usize,
+    tail: usize,
+}
+
+impl<V> Default for Lru<V> {
+    fn default() -> Self {
+        Self {
+            index: HashMap::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+}
+
+impl<V> Lru<V> {
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.nodes[i].prev, self.nodes[i].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.nodes[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.nodes[next].prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.nodes[i].prev = NIL;
+        self.nodes[i].next = self.head;
+        match self.head {
+            NIL => self.tail = i,
+            head => self.nodes[head].prev = i,
+        }
+        self.head = i;
+    }
+
+    /// 返回块并将其标记为最近使用
+    fn get(&mut self, key: &BlockKey) -> Option<&V> {
+        let i = *self.index.get(key)?;
+        if self.head != i {
+            self.unlink(i);
+            self.push_front(i);
+        }
+        self.nodes[i].value.as_ref()
+    }
+
+    /// 插入或替换一个块并将其标记为最近使用, 返回替换前的值
+    fn insert(&mut self, key: BlockKey, value: V) -> Option<V> {
+        if let Some(&i) = self.index.get(&key) {
+            self.unlink(i);
+            self.push_front(i);
+            return self.nodes[i].value.replace(value);
+        }
+        let node = Node {
+            key,
+            value: Some(value),
+            prev: NIL,
+            next: NIL,
+        };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = node;
+                i
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.index.insert(key, i);
+        self.push_front(i);
+        None
+    }
+
+    /// 移除最近最少使用的块
+    fn pop_lru(&mut self) -> Option<(BlockKey, V)> {
+        let i = self.tail;
+        if i == NIL {
+            return None;
+        }
+        self.unlink(i);
+        self.free.push(i);
+        let key = self.nodes[i].key;
+        self.index.remove(&key);
+        self.nodes[i].value.take().map(|value| (key, value))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&BlockKey, &V)> + '_ {
+        self.index
+            .iter()
+            .filter_map(|(key, &i)| self.nodes[i].value.as_ref().map(|value| (key, value)))
+    }
+}
+
+/// 磁盘上的块的字节数
+#[derive(Default)]
+struct DiskState {
+    blocks: Lru<u64>,
+    bytes: u64,
+}
+
+fn memory_shard((shard, block): BlockKey) -> usize {
+    (block ^ shard.wrapping_mul(0x9E37_79B9)) % MEMORY_SHARDS
+}
+
+/// 远程 hash shard 的块缓存: 内存中保留最近使用的 [`MEMORY_BLOCKS`] 个块 (每个分片各自淘汰),
+/// 磁盘上保留最多 `max_bytes` 字节, 都按最近最少使用淘汰. 磁盘上的块在运行之间保留,
+/// 按修改时间恢复使用顺序
+struct BlockCache {
+    url: String,
+    dir: PathBuf,
+    max_bytes: u64,
+    client: Client,
+    memory: Vec<Mutex<Lru<Arc<Vec<u32>>>>>,
+    disk: Mutex<DiskState>,
+    /// 本次运行下载的字节数
+    fetched: AtomicU64,
+    failed: AtomicBool,
+    error: Mutex<Option<io::Error>>,
+}
+
+impl BlockCache {
+    fn open(url: &str, dir: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let mut blocks = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(key) = name.to_str().and_then(parse_block_name) else {
+                // 中断的写入留下的临时文件
+                let _ = fs::remove_file(entry.path());
+                continue;
+            };
+            let metadata = entry.metadata()?;
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            blocks.push((modified, key, metadata.len()));
+        }
+        blocks.sort();
+        let mut disk = DiskState::default();
+        for (_, key, len) in blocks {
+            disk.blocks.insert(key, len);
+            disk.bytes += len;
+        }
+        let cache = Self {
+            url: url.trim_end_matches('/').to_string(),
+            dir,
+            max_bytes,
+            client: http_client()?,
+            memory: (0..MEMORY_SHARDS).map(|_| Mutex::default()).collect(),
+            disk: Mutex::new(disk),
+            fetched: AtomicU64::new(0),
+            failed: AtomicBool::new(false),
+            error: Mutex::new(None),
+        };
+        cache.evict_disk(&mut cache.disk.lock().unwrap());
+        Ok(cache)
+    }
+
+    fn shard_url(&self, shard: usize) -> String {
+        format!("{}/hash_{}.k2d", self.url, shard)
+    }
+
+    fn block(&self, key: BlockKey) -> io::Result<Arc<Vec<u32>>> {
+        let memory = &self.memory[memory_shard(key)];
+        if let Some(block) = memory.lock().unwrap().get(&key) {
+            return Ok(block.clone());
+        }
+        let on_disk = self.disk.lock().unwrap().blocks.get(&key).is_some();
+        // 读取与下载时不持有锁, 其他线程可以继续使用已缓存的块
+        let path = self.dir.join(block_name(key));
+        let cached = on_disk
+            .then(|| fs::read(&path).ok().and_then(|bytes| decode_cells(&bytes)))
+            .flatten();
+        let cells = match cached {
+            Some(cells) => {
+                if let Ok(file) = File::options().write(true).open(&path) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+                cells
+            }
+            None => self.download(key)?,
+        };
+
+        let block = Arc::new(cells);
+        let mut memory = memory.lock().unwrap();
+        memory.insert(key, block.clone());
+        while memory.len() > MEMORY_BLOCKS / MEMORY_SHARDS {
+            memory.pop_lru();
+        }
+        Ok(block)
+    }
+
+    fn download(&self, key: BlockKey) -> io::Result<Vec<u32>> {
+        let (shard, block) = key;
+        let start = HEADER_SIZE + block as u64 * BLOCK_BYTES;
+        let bytes = fetch_range(&self.client, &self.shard_url(shard), start, BLOCK_BYTES)?;
+        let cells = decode_cells(&bytes).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is truncated", self.shard_url(shard)),
+            )
+        })?;
+        self.fetched
+            .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        if self.max_bytes > 0 {
+            if let Err(e) = self.store(key, &bytes) {
+                warn!("failed to cache a block of hash_{}.k2d: {}", shard, e);
+            }
+        }
+        Ok(cells)
+    }
+
+    /// 先写入临时文件再重命名, 中断的运行不会留下不完整的块
+    fn store(&self, key: BlockKey, bytes: &[u8]) -> io::Result<()> {
+        let path = self.dir.join(block_name(key));
+        let tmp = self
+            .dir
+            .join(format!("{}.{}.tmp", block_name(key), std::process::id()));
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, &path)?;
+
+        let mut disk = self.disk.lock().unwrap();
+        let len = bytes.len() as u64;
+        if let Some(previous) = disk.blocks.insert(key, len) {
+            disk.bytes -= previous;
+        }
+        disk.bytes += len;
+        self.evict_disk(&mut disk);
+        Ok(())
+    }
+
+    fn evict_disk(&self, disk: &mut DiskState) {
+        while disk.bytes > self.max_bytes {
+            let Some((oldest, len)) = disk.blocks.pop_lru() else {
+                break;
+            };
+            disk.bytes -= len;
+            let _ = fs::remove_file(self.dir.join(block_name(oldest)));
+        }
+    }
+
+    fn record_error(&self, e: io::Error) {
+        if !self.failed.swap(true, Ordering::Relaxed) {
+            *self.error.lock().unwrap() = Some(e);
+        }
+    }
+
+    fn memory_bytes(&self, shard: usize) -> usize {
+        self.memory
+            .iter()
+            .map(|memory| {
+                let memory = memory.lock().unwrap();
+                memory
+                    .iter()
+                    .filter(|((block_shard, _), _)| *block_shard == shard)
+                    .map(|(_, block)| block.len() * std::mem::size_of::<u32>())
+                    .sum::<usize>()
+            })
+            .sum()
+    }
+}
+
+/// 对象存储中的一个 hash shard, 后面接着下一个 shard (版本 0) 或自身 (版本 1 起) 的开头,
+/// 与读入本地文件时的页相同
+struct RemoteShard {
+    cache: Arc<BlockCache>,
+    shard: usize,
+    capacity: usize,
+    tail_shard: usize,
+}
+
+impl CellSource for RemoteShard {
+    fn cell(&self, idx: usize) -> Option<u32> {
+        // 一次读取失败后不再下载, 本次运行以错误结束
+        if self.cache.failed.load(Ordering::Relaxed) {
+            return None;
+        }
+        let (shard, idx) = if idx < self.capacity {
+            (self.shard, idx)
+        } else {
+            (self.tail_shard, idx - self.capacity)
+        };
+        match self.cache.block((shard, idx / BLOCK_CELLS)) {
+            Ok(block) => block.get(idx % BLOCK_CELLS).copied(),
+            Err(e) => {
+                self.cache.record_error(e);
+                None
+            }
+        }
+    }
+
+    fn byte_size(&self) -> usize {
+        self.cache.memory_bytes(self.shard)
+    }
+
+    fn take_error(&self) -> Option<io::Error> {
+        self.cache.error.lock().unwrap().take()
+    }
+}
+
+/// 对象存储 (`s3://`, `gs://`) 或 HTTPS 上的数据库, hash shard 按需以 range 请求读取.
+///
+/// opts.k2d, taxo.k2d 与 hash_config.k2d 下载到本地的缓存目录, 可以像本地数据库一样使用;
+/// hash_config.k2d 与缓存中的不同时 (数据库已重建), 清空缓存重新下载
+pub struct RemoteDatabase {
+    url: String,
+    dir: PathBuf,
+    cache: Arc<BlockCache>,
+}
+
+impl RemoteDatabase {
+    pub fn open(url: &str, cache_dir: Option<&Path>, max_bytes: u64) -> io::Result<Self> {
+        let url = url.trim_end_matches('/').to_string();
+        let dir = cache_dir.map_or_else(|| default_cache_dir(&url), Path::to_path_buf);
+        fs::create_dir_all(&dir)?;
+
+        let config_file = dir.join(HASH_CONFIG);
+        let fresh_config = dir.join(format!("{}.new", HASH_CONFIG));
+        fetch_database_file(&format!("{}/{}", url, HASH_CONFIG), &fresh_config)?;
+        let changed = fs::read(&config_file).ok() != Some(fs::read(&fresh_config)?);
+        if changed {
+            if config_file.exists() {
+                info!("{} has changed, clear the cache {:?}", url, dir);
+            }
+            let _ = fs::remove_dir_all(dir.join("blocks"));
+            for name in DATABASE_FILES {
+                let _ = fs::remove_file(dir.join(name));
+            }
+        }
+        for name in DATABASE_FILES {
+            let path = dir.join(name);
+            if !path.exists() {
+                let len = fetch_database_file(&format!("{}/{}", url, name), &path)?;
+                info!("downloaded {} ({} bytes) to {:?}", name, len, dir);
+            }
+        }
+        // 其他文件都下载后才更新, 中断的下载在下次运行时重新开始
+        fs::rename(&fresh_config, &config_file)?;
+
+        let cache = BlockCache::open(&url, dir.join("blocks"), max_bytes)?;
+        Ok(Self {
+            url,
+            dir,
+            cache: Arc::new(cache),
+        })
+    }
+
+    /// 下载了 opts.k2d, taxo.k2d 与 hash_config.k2d 的本地目录
+    pub fn local_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// 每个 shard 一个按需读取的页, 只读取文件头
+    pub fn hash_pages(&self, config: &HashConfig) -> io::Result<Vec<HashPage>> {
+        let mut headers = Vec::with_capacity(config.partition);
+        for shard in 1..=config.partition {
+            let url = self.cache.shard_url(shard);
+            let header = fetch_range(&self.cache.client, &url, 0, HEADER_SIZE)
+                .map_err(|e| missing_index(&url, e))?;
+            if header.len() < HEADER_SIZE as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is too short", url),
+                ));
+            }
+            let index = LittleEndian::read_u64(&header[0..8]) as usize;
+            let capacity = LittleEndian::read_u64(&header[8..16]) as usize;
+            headers.push((index, capacity));
+        }
+
+        let pages = (1..=config.partition)
+            .map(|shard| {
+                let tail_shard = if config.version < 1 {
+                    shard % config.partition + 1
+                } else {
+                    shard
+                };
+                let (index, capacity) = headers[shard - 1];
+                let source = RemoteShard {
+                    cache: self.cache.clone(),
+                    shard,
+                    capacity,
+                    tail_shard,
+                };
+                HashPage::Lazy(LazyPage {
+                    index,
+                    size: capacity + headers[tail_shard - 1].1,
+                    source: Box::new(source),
+                })
+            })
+            .collect();
+        Ok(pages)
+    }
+
+    /// 本次运行下载的 hash shard 字节数
+    pub fn fetched_bytes(&self) -> u64 {
+        self.cache.fetched.load(Ordering::Relaxed)
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let mut lru = Lru::default();
+        for block in 0..4 {
+            lru.insert((1, block), block);
+        }
+        assert_eq!(lru.get(&(1, 0)), Some(&0));
+        assert_eq!(lru.insert((1, 2), 20), Some(2));
+        assert_eq!(lru.pop_lru(), Some(((1, 1), 1)));
+        assert_eq!(lru.pop_lru(), Some(((1, 3), 3)));
+        lru.insert((2, 0), 5);
+        assert_eq!(lru.len(), 3);
+        assert_eq!(lru.pop_lru(), Some(((1, 0), 0)));
+        assert_eq!(lru.pop_lru(), Some(((1, 2), 20)));
+        assert_eq!(lru.pop_lru(), Some(((2, 0), 5)));
+        assert_eq!(lru.pop_lru(), None);
+        assert_eq!(lru.iter().count(), 0);
+    }
+}
@@ -1,9 +1,15 @@
+use crate::abundance::KreportLine;
+use crate::args::OutputCompression;
 use crate::readcounts::{ReadCounter, TaxonCounters};
+use crate::stats::{
+    is_unclassified, normalize_name, report_name, unclassified_label, unclassified_taxid,
+};
 use crate::taxonomy::Taxonomy;
+use crate::utils::create_output_writer;
 use std::collections::HashMap;
 
 use std::fs::File;
-use std::io::{self, Write};
+use std::io::{self, BufWriter, Write};
 use std::path::Path;
 
 pub fn get_clade_counts(taxonomy: &Taxonomy, call_counts: &HashMap<u64, u64>) -> HashMap<u64, u64> {
@@ -28,7 +34,7 @@ pub fn get_clade_counters(taxonomy: &Taxonomy, call_counters: &TaxonCounters) ->
         while current_taxid != 0 {
             let _ = clade_counters
                 .entry(current_taxid)
-                .or_insert_with(ReadCounter::default)
+                .or_default()
                 .merge(counter);
             current_taxid = taxonomy.nodes[current_taxid as usize].parent_id;
         }
@@ -84,7 +90,7 @@ fn mpa_report_dfs(
     if rank_code != '\0' {
         let name_str = extract_string_from_offset(&taxonomy.name_data, node.name_offset as usize);
 
-        let name = format!("{}__{}", rank_code, name_str);
+        let name = format!("{}__{}", rank_code, report_name(name_str));
         taxonomy_names.push(name);
         let taxonomy_line = taxonomy_names.join("|");
         print_mpa_style_report_line(
@@ -104,7 +110,7 @@ fn mpa_report_dfs(
             clade_counts
                 .get(&b)
                 .unwrap_or(&0)
-                .cmp(&clade_counts.get(&a).unwrap_or(&0))
+                .cmp(clade_counts.get(&a).unwrap_or(&0))
         });
 
         for child in children {
@@ -152,8 +158,9 @@ pub fn report_mpa_style<P: AsRef<Path>>(
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn print_kraken_style_report_line(
-    file: &mut File,
+    file: &mut dyn Write,
     report_kmer_data: bool,
     total_seqs: u64,
     clade_counter: &mut ReadCounter,
@@ -189,12 +196,13 @@ pub fn print_kraken_style_report_line(
         write!(file, "  ")?;
     }
 
-    writeln!(file, "{}", sci_name)
+    writeln!(file, "{}", report_name(sci_name))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn kraken_report_dfs(
     taxid: u64,
-    file: &mut File,
+    file: &mut dyn Write,
     report_zeros: bool,
     report_kmer_data: bool,
     taxonomy: &Taxonomy,
@@ -234,22 +242,16 @@ pub fn kraken_report_dfs(
         format!("{}{}", new_rank_code, new_rank_depth)
     };
 
-    let name = std::str::from_utf8(&taxonomy.name_data[node.name_offset as usize..])
-        .unwrap_or_default()
-        .split('\0')
-        .next()
-        .unwrap_or("");
+    let name = taxonomy.name(taxid as u32);
 
     // let mut clade_counter = clade_counters.get_mut(&taxid).unwrap();
-    let mut clade_counter = clade_counters
-        .entry(taxid)
-        .or_insert_with(ReadCounter::default);
+    let clade_counter = clade_counters.entry(taxid).or_default();
 
     print_kraken_style_report_line(
         file,
         report_kmer_data,
         total_seqs,
-        &mut clade_counter,
+        clade_counter,
         call_counters.get(&taxid).unwrap_or(&ReadCounter::default()),
         &rank_str,
         node.external_id as u32,
@@ -287,6 +289,115 @@ pub fn kraken_report_dfs(
     Ok(())
 }
 
+/// 将 unix 时间格式化为 UTC 的 ISO 8601 时间
+///
+/// ```
+/// use kraken2_rs::report::format_utc;
+///
+/// assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+/// assert_eq!(format_utc(1_709_210_096), "2024-02-29T12:34:56Z");
+/// ```
+pub fn format_utc(secs: u64) -> String {
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Howard Hinnant 的 civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// `--report-headers` 时写在 kreport 开头的 `#` 注释行: 样本, 数据库及其哈希, 命令行, 版本与时间
+///
+/// ```
+/// use kraken2_rs::report::ReportHeader;
+///
+/// let header = ReportHeader::new("db", "0123456789abcdef").with_sample("output_1");
+/// let mut out = Vec::new();
+/// header.write_to(&mut out).unwrap();
+/// let text = String::from_utf8(out).unwrap();
+/// assert!(text.lines().all(|line| line.starts_with("# ")));
+/// assert!(text.starts_with("# sample: output_1\n# database: db\n# database_hash: 0123456789abcdef\n"));
+///
+/// let header = header.with_metadata(&[("patient_id".to_string(), "P1".to_string())]);
+/// let mut out = Vec::new();
+/// header.write_to(&mut out).unwrap();
+/// assert!(String::from_utf8(out).unwrap().starts_with("# sample: output_1\n# metadata.patient_id: P1\n"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReportHeader {
+    sample: Option<String>,
+    /// 样本表中的元数据, 写在样本名之后
+    metadata: Vec<(String, String)>,
+    database: String,
+    database_hash: String,
+    command: String,
+    date: String,
+}
+
+impl ReportHeader {
+    pub fn new(database: &str, database_hash: &str) -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        Self {
+            sample: None,
+            metadata: Vec::new(),
+            database: database.to_string(),
+            database_hash: database_hash.to_string(),
+            command: std::env::args().collect::<Vec<_>>().join(" "),
+            date: format_utc(now),
+        }
+    }
+
+    pub fn with_sample(&self, sample: &str) -> Self {
+        Self {
+            sample: Some(sample.to_string()),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_metadata(&self, metadata: &[(String, String)]) -> Self {
+        Self {
+            metadata: metadata.to_vec(),
+            ..self.clone()
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        if let Some(sample) = &self.sample {
+            writeln!(writer, "# sample: {}", sample)?;
+        }
+        for (key, value) in &self.metadata {
+            writeln!(writer, "# metadata.{}: {}", key, value)?;
+        }
+        writeln!(writer, "# database: {}", self.database)?;
+        writeln!(writer, "# database_hash: {}", self.database_hash)?;
+        writeln!(writer, "# command: {}", self.command)?;
+        writeln!(
+            writer,
+            "# version: {} {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(writer, "# date: {}", self.date)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn report_kraken_style<P: AsRef<Path>>(
     filename: P,
     report_zeros: bool,
@@ -295,10 +406,15 @@ pub fn report_kraken_style<P: AsRef<Path>>(
     call_counters: &HashMap<u64, ReadCounter>,
     total_seqs: u64,
     total_unclassified: u64,
+    header: Option<&ReportHeader>,
 ) -> io::Result<()> {
     let mut clade_counters = get_clade_counters(taxonomy, call_counters);
 
-    let mut file = File::create(filename)?;
+    let compression = OutputCompression::from_path(&filename);
+    let mut file = create_output_writer(filename, compression)?;
+    if let Some(header) = header {
+        header.write_to(&mut file)?;
+    }
 
     // 处理未分类序列的特殊情况
     if total_unclassified != 0 || report_zeros {
@@ -311,8 +427,16 @@ pub fn report_kraken_style<P: AsRef<Path>>(
             &mut rc,
             &trc,
             "U",
-            0,
-            "unclassified",
+            u32::try_from(unclassified_taxid()).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "--unclassified-taxid {} doesn't fit the taxid column of a kreport",
+                        unclassified_taxid()
+                    ),
+                )
+            })?,
+            unclassified_label(),
             0,
         )?;
     }
@@ -330,5 +454,435 @@ pub fn report_kraken_style<P: AsRef<Path>>(
         'R',
         -1,
         0,
+    )?;
+    file.flush()
+}
+
+/// 写出 Krona 文本格式 (ktImportText): 每行为直接分配到该 taxon 的 reads 数与从根开始的名称路径,
+/// 只有数字的行 (未分类与 root) 归到根节点
+pub fn report_krona_text<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    total_unclassified: u64,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(filename)?);
+    if total_unclassified > 0 {
+        writeln!(file, "{}", total_unclassified)?;
+    }
+
+    let mut taxids: Vec<&u64> = call_counters.keys().collect();
+    taxids.sort_unstable();
+    for &taxid in taxids {
+        let count = call_counters[&taxid].read_count();
+        if count == 0 {
+            continue;
+        }
+        let mut lineage = Vec::new();
+        let mut current = taxid;
+        while current > 1 {
+            lineage.push(taxonomy.name(current as u32));
+            current = taxonomy.nodes[current as usize].parent_id;
+        }
+        lineage.reverse();
+        write!(file, "{}", count)?;
+        for name in lineage {
+            write!(file, "\t{}", report_name(name))?;
+        }
+        writeln!(file)?;
+    }
+    file.flush()
+}
+
+/// 旭日图中的一个扇区, 角度为弧度, 从正上方顺时针
+struct KronaWedge {
+    depth: usize,
+    start: f64,
+    end: f64,
+    ext_id: u64,
+    name: String,
+    rank: String,
+    reads: u64,
+}
+
+const KRONA_CENTER: f64 = 450.0;
+const KRONA_HOLE: f64 = 60.0;
+const KRONA_RING: f64 = 42.0;
+const KRONA_MAX_DEPTH: usize = 9;
+
+fn is_major_rank(rank: &str) -> bool {
+    matches!(
+        rank,
+        "superkingdom"
+            | "domain"
+            | "kingdom"
+            | "phylum"
+            | "class"
+            | "order"
+            | "family"
+            | "genus"
+            | "species"
+    )
+}
+
+/// 计算旭日图各扇区的位置
+struct KronaLayout<'a> {
+    taxonomy: &'a Taxonomy,
+    clade_counts: &'a HashMap<u64, u64>,
+    /// 每条 read 对应的弧度
+    scale: f64,
+    wedges: Vec<KronaWedge>,
+}
+
+impl KronaLayout<'_> {
+    /// 只画主要分类层级 (以及 species 之下的第一层), 中间层级的 reads 计入最近的可见祖先
+    fn descend(&mut self, taxid: u64, depth: usize, below_species: bool, start: f64) {
+        let node = &self.taxonomy.nodes[taxid as usize];
+        let mut children: Vec<u64> = (0..node.child_count)
+            .map(|i| node.first_child + i)
+            .filter(|child| self.clade_counts.get(child).is_some_and(|&count| count > 0))
+            .collect();
+        children.sort_by_key(|child| std::cmp::Reverse(self.clade_counts[child]));
+
+        let mut cursor = start;
+        for child in children {
+            let reads = self.clade_counts[&child];
+            let end = cursor + reads as f64 * self.scale;
+            let rank = self.taxonomy.rank(child as u32);
+            if depth < KRONA_MAX_DEPTH && (below_species || is_major_rank(rank)) {
+                self.wedges.push(KronaWedge {
+                    depth,
+                    start: cursor,
+                    end,
+                    ext_id: self.taxonomy.nodes[child as usize].external_id,
+                    name: normalize_name(self.taxonomy.name(child as u32)).into_owned(),
+                    rank: rank.to_string(),
+                    reads,
+                });
+                if !below_species {
+                    self.descend(child, depth + 1, rank == "species", cursor);
+                }
+            } else if depth < KRONA_MAX_DEPTH {
+                self.descend(child, depth, below_species, cursor);
+            }
+            cursor = end;
+        }
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn polar(radius: f64, angle: f64) -> (f64, f64) {
+    (
+        KRONA_CENTER + radius * angle.sin(),
+        KRONA_CENTER - radius * angle.cos(),
+    )
+}
+
+fn arc_path(inner: f64, outer: f64, start: f64, end: f64) -> String {
+    let end = end.min(start + std::f64::consts::TAU * 0.9999);
+    let large = if end - start > std::f64::consts::PI {
+        1
+    } else {
+        0
+    };
+    let (x0, y0) = polar(outer, start);
+    let (x1, y1) = polar(outer, end);
+    let (x2, y2) = polar(inner, end);
+    let (x3, y3) = polar(inner, start);
+    format!(
+        "M{:.2},{:.2}A{:.2},{:.2} 0 {} 1 {:.2},{:.2}L{:.2},{:.2}A{:.2},{:.2} 0 {} 0 {:.2},{:.2}Z",
+        x0, y0, outer, outer, large, x1, y1, x2, y2, inner, inner, large, x3, y3
+    )
+}
+
+/// 写出不依赖外部脚本的 HTML 旭日图 (SVG), 鼠标悬停显示 taxon 信息, 下方附带各层级的 reads 表
+pub fn report_krona_html<P: AsRef<Path>>(
+    filename: P,
+    title: &str,
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    total_unclassified: u64,
+) -> io::Result<()> {
+    let call_counts: HashMap<u64, u64> = call_counters
+        .iter()
+        .map(|(&taxid, counter)| (taxid, counter.read_count()))
+        .collect();
+    let clade_counts = get_clade_counts(taxonomy, &call_counts);
+    let classified = clade_counts.get(&1).copied().unwrap_or(0);
+    let total = classified + total_unclassified;
+
+    let mut layout = KronaLayout {
+        taxonomy,
+        clade_counts: &clade_counts,
+        scale: std::f64::consts::TAU / total.max(1) as f64,
+        wedges: Vec::new(),
+    };
+    layout.descend(1, 0, false, 0.0);
+    if total_unclassified > 0 {
+        layout.wedges.push(KronaWedge {
+            depth: 0,
+            start: classified as f64 * layout.scale,
+            end: std::f64::consts::TAU,
+            ext_id: unclassified_taxid(),
+            name: normalize_name(unclassified_label()).into_owned(),
+            rank: String::new(),
+            reads: total_unclassified,
+        });
+    }
+    let wedges = layout.wedges;
+
+    let size = 2.0 * KRONA_CENTER;
+    let mut file = BufWriter::new(File::create(filename)?);
+    let title = html_escape(title);
+    writeln!(
+        file,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>",
+        title
+    )?;
+    writeln!(
+        file,
+        "<style>body{{font-family:sans-serif;margin:20px}}path{{stroke:#fff;stroke-width:0.5}}path:hover{{opacity:0.7}}text{{font-size:10px;pointer-events:none}}table{{border-collapse:collapse;font-size:13px}}th,td{{padding:2px 8px;text-align:left}}td.n{{text-align:right}}</style>\n</head>\n<body>"
+    )?;
+    writeln!(
+        file,
+        "<h2>{}</h2>\n<p>{} reads, {} classified ({:.2}%)</p>",
+        title,
+        total,
+        classified,
+        if total > 0 {
+            100.0 * classified as f64 / total as f64
+        } else {
+            0.0
+        }
+    )?;
+    writeln!(
+        file,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{0}\" viewBox=\"0 0 {0} {0}\">",
+        size
+    )?;
+    writeln!(
+        file,
+        "<circle cx=\"{0}\" cy=\"{0}\" r=\"{1}\" fill=\"#eeeeee\"><title>all reads: {2}</title></circle>",
+        KRONA_CENTER, KRONA_HOLE, total
+    )?;
+    for wedge in &wedges {
+        let inner = KRONA_HOLE + wedge.depth as f64 * KRONA_RING;
+        let outer = inner + KRONA_RING;
+        let mid = (wedge.start + wedge.end) / 2.0;
+        let color = if is_unclassified(wedge.ext_id) {
+            "#bbbbbb".to_string()
+        } else {
+            format!(
+                "hsl({:.0},60%,{}%)",
+                mid.to_degrees(),
+                (40 + wedge.depth * 6).min(85)
+            )
+        };
+        writeln!(
+            file,
+            "<path d=\"{}\" fill=\"{}\"><title>{} (taxid {})\n{}\n{} reads ({:.2}%)</title></path>",
+            arc_path(inner, outer, wedge.start, wedge.end),
+            color,
+            html_escape(&wedge.name),
+            wedge.ext_id,
+            wedge.rank,
+            wedge.reads,
+            100.0 * wedge.reads as f64 / total as f64
+        )?;
+        // 只在足够宽的扇区内标注名称
+        let arc_len = (wedge.end - wedge.start) * (inner + outer) / 2.0;
+        if arc_len > 30.0 {
+            let max_chars = ((arc_len / 6.0) as usize)
+                .min(KRONA_RING as usize / 3)
+                .max(3);
+            let label: String = wedge.name.chars().take(max_chars).collect();
+            let (x, y) = polar((inner + outer) / 2.0, mid);
+            writeln!(
+                file,
+                "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"middle\" dominant-baseline=\"middle\">{}</text>",
+                x,
+                y,
+                html_escape(&label)
+            )?;
+        }
+    }
+    writeln!(file, "</svg>")?;
+
+    writeln!(
+        file,
+        "<table>\n<tr><th>name</th><th>rank</th><th>taxid</th><th>reads</th><th>%</th></tr>"
+    )?;
+    for wedge in &wedges {
+        writeln!(
+            file,
+            "<tr><td style=\"padding-left:{}px\">{}</td><td>{}</td><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{:.2}</td></tr>",
+            8 + wedge.depth * 16,
+            html_escape(&wedge.name),
+            wedge.rank,
+            wedge.ext_id,
+            wedge.reads,
+            100.0 * wedge.reads as f64 / total as f64
+        )?;
+    }
+    writeln!(file, "</table>\n</body>\n</html>")?;
+    file.flush()
+}
+
+/// 写出 `{stem}.krona.txt` 与 `{stem}.krona.html`
+pub fn report_krona<P: AsRef<Path>>(
+    output_dir: P,
+    stem: &str,
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    total_unclassified: u64,
+) -> io::Result<()> {
+    let output_dir = output_dir.as_ref();
+    report_krona_text(
+        output_dir.join(format!("{}.krona.txt", stem)),
+        taxonomy,
+        call_counters,
+        total_unclassified,
+    )?;
+    report_krona_html(
+        output_dir.join(format!("{}.krona.html", stem)),
+        stem,
+        taxonomy,
+        call_counters,
+        total_unclassified,
     )
 }
+
+/// 合并后的一个分类单元, `reads` 为每个 sample 的 clade reads
+#[derive(Debug, Clone)]
+pub struct MergedTaxon {
+    pub taxid: u64,
+    pub rank: String,
+    pub name: String,
+    pub reads: Vec<u64>,
+}
+
+/// 多个 kreport2 合并成的宽表 (分类单元 × sample)
+///
+/// ```
+/// use kraken2_rs::abundance::KreportLine;
+/// use kraken2_rs::report::MergedReports;
+///
+/// let line = |clade_reads, direct_reads, rank: &str, taxid, name: &str| KreportLine {
+///     clade_reads,
+///     direct_reads,
+///     rank: rank.to_string(),
+///     taxid,
+///     name: name.to_string(),
+///     depth: 0,
+/// };
+/// let a = vec![line(2, 2, "U", 0, "unclassified"), line(8, 0, "R", 1, "root"), line(8, 8, "S", 562, "Escherichia coli")];
+/// let b = vec![line(5, 0, "R", 1, "root"), line(5, 5, "S", 1280, "Staphylococcus aureus")];
+/// let merged = MergedReports::merge(vec![("a".into(), a), ("b".into(), b)]);
+///
+/// assert_eq!(merged.totals, vec![10, 5]);
+/// assert_eq!(merged.taxa.len(), 4);
+/// assert_eq!(merged.taxa[2].reads, vec![8, 0]);
+/// assert_eq!(merged.taxa[3].reads, vec![0, 5]);
+///
+/// let mut out = Vec::new();
+/// merged.write_tsv(&mut out, Some("S")).unwrap();
+/// let table = String::from_utf8(out).unwrap();
+/// assert_eq!(table.lines().next().unwrap(), "taxid\trank\tname\ta_reads\ta_pct\tb_reads\tb_pct");
+/// assert_eq!(table.lines().nth(1).unwrap(), "562\tS\tEscherichia coli\t8\t80.00\t0\t0.00");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MergedReports {
+    pub samples: Vec<String>,
+    /// 每个 sample 的总 reads 数 (含 unclassified)
+    pub totals: Vec<u64>,
+    pub taxa: Vec<MergedTaxon>,
+}
+
+impl MergedReports {
+    /// 按 taxid 合并, 分类单元按在报告中第一次出现的顺序排列
+    pub fn merge(reports: Vec<(String, Vec<KreportLine>)>) -> Self {
+        let sample_count = reports.len();
+        let mut merged = MergedReports::default();
+        let mut rows: HashMap<u64, usize> = HashMap::new();
+        for (i, (sample, lines)) in reports.into_iter().enumerate() {
+            merged.samples.push(sample);
+            merged
+                .totals
+                .push(lines.iter().map(|line| line.direct_reads).sum());
+            for line in lines {
+                let row = *rows.entry(line.taxid).or_insert_with(|| {
+                    merged.taxa.push(MergedTaxon {
+                        taxid: line.taxid,
+                        rank: line.rank.clone(),
+                        name: line.name.clone(),
+                        reads: vec![0; sample_count],
+                    });
+                    merged.taxa.len() - 1
+                });
+                merged.taxa[row].reads[i] += line.clade_reads;
+            }
+        }
+        merged
+    }
+
+    /// 用数据库的 taxonomy 统一名称, 并按分类树的先序排列 (unclassified 在最前),
+    /// 返回 taxonomy 中不存在的 taxid, 这些分类单元保留报告中的名称并排在最后
+    pub fn align_taxonomy(&mut self, taxonomy: &Taxonomy) -> Vec<u64> {
+        let mut unknown = Vec::new();
+        let mut keyed: Vec<(Option<Vec<u32>>, MergedTaxon)> = Vec::new();
+        for mut taxon in std::mem::take(&mut self.taxa) {
+            let internal_id = taxonomy.get_internal_id(taxon.taxid);
+            let path = if is_unclassified(taxon.taxid) {
+                Some(Vec::new())
+            } else if internal_id == 0 {
+                unknown.push(taxon.taxid);
+                None
+            } else {
+                taxon.name = taxonomy.name(internal_id).to_string();
+                Some(taxonomy.lineage(internal_id))
+            };
+            keyed.push((path, taxon));
+        }
+        // 祖先的路径是子孙路径的前缀, 按路径排序即为先序; None 排在最后
+        keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        });
+        self.taxa = keyed.into_iter().map(|(_, taxon)| taxon).collect();
+        unknown
+    }
+
+    /// 写出 tsv: taxid, rank, name 和每个 sample 的 reads 与百分比, `rank` 只保留该 rank code 的行
+    pub fn write_tsv<W: Write>(&self, writer: &mut W, rank: Option<&str>) -> io::Result<()> {
+        write!(writer, "taxid\trank\tname")?;
+        for sample in &self.samples {
+            write!(writer, "\t{}_reads\t{}_pct", sample, sample)?;
+        }
+        writeln!(writer)?;
+
+        for taxon in &self.taxa {
+            if rank.is_some_and(|rank| rank != taxon.rank) {
+                continue;
+            }
+            write!(
+                writer,
+                "{}\t{}\t{}",
+                taxon.taxid,
+                taxon.rank,
+                report_name(&taxon.name)
+            )?;
+            for (reads, total) in taxon.reads.iter().zip(&self.totals) {
+                let pct = 100.0 * *reads as f64 / (*total).max(1) as f64;
+                write!(writer, "\t{}\t{:.2}", reads, pct)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use crate::readcounts::TaxonCounters;
+use crate::stats::{TaxonLengthStats, TaxonQcStats};
+use seahash::SeaHasher;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// 缓存条目中的 Kraken 输出
+pub const CACHED_OUTPUT: &str = "output.txt";
+/// 缓存条目中的样本统计
+pub const CACHED_SUMMARY: &str = "summary.json";
+
+/// 单个样本的统计结果, 用于写出样本报告并累加到总报告
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SampleSummary {
+    pub sequences: u64,
+    pub unclassified: u64,
+    pub taxon_counts: TaxonCounters,
+    pub length_stats: TaxonLengthStats,
+    pub qc_stats: TaxonQcStats,
+}
+
+impl SampleSummary {
+    pub fn merge(&mut self, other: &Self) {
+        self.sequences += other.sequences;
+        self.unclassified += other.unclassified;
+        for (taxid, counts) in &other.taxon_counts {
+            self.taxon_counts
+                .entry(*taxid)
+                .or_default()
+                .merge(counts)
+                .unwrap();
+        }
+        for (taxid, hist) in &other.length_stats {
+            match self.length_stats.get_mut(taxid) {
+                Some(total) => total.merge(hist),
+                None => {
+                    self.length_stats.insert(*taxid, hist.clone());
+                }
+            }
+        }
+        for (taxid, qc) in &other.qc_stats {
+            self.qc_stats.entry(*taxid).or_default().merge(qc);
+        }
+    }
+}
+
+fn hash_file_contents(hasher: &mut SeaHasher, path: &Path) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buffer[..n]);
+    }
+    Ok(())
+}
+
+fn hash_database_files(hasher: &mut SeaHasher, database: &Path) -> Result<()> {
+    for name in ["opts.k2d", "hash_config.k2d", "taxo.k2d"] {
+        hash_file_contents(hasher, &database.join(name))?;
+    }
+    Ok(())
+}
+
+/// 数据库的 opts.k2d, hash_config.k2d 与 taxo.k2d 内容的哈希, 不读取 hash shard
+pub fn database_fingerprint(database: &Path) -> Result<u64> {
+    let mut hasher = SeaHasher::new();
+    hash_database_files(&mut hasher, database)?;
+    Ok(hasher.finish())
+}
+
+/// 按样本缓存分类结果的目录, 每个条目以 (输入文件内容, 数据库, 参数) 的哈希为名.
+///
+/// 数据库以 opts.k2d, hash_config.k2d, taxo.k2d 的内容与 hash shard 的名称, 大小和修改时间计算,
+/// 不读取整个哈希表. 条目先写入临时目录再重命名, 中断的运行不会留下不完整的条目
+pub struct ResultCache {
+    dir: PathBuf,
+    database_hash: u64,
+}
+
+impl ResultCache {
+    pub fn open<P: AsRef<Path>>(dir: P, database: &Path, hash_files: &[PathBuf]) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut hasher = SeaHasher::new();
+        hash_database_files(&mut hasher, database)?;
+        for file in hash_files {
+            let metadata = fs::metadata(file)?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_secs());
+            hasher.write(file.file_name().unwrap_or_default().as_encoded_bytes());
+            hasher.write_u64(metadata.len());
+            hasher.write_u64(modified);
+        }
+        Ok(Self {
+            dir,
+            database_hash: hasher.finish(),
+        })
+    }
+
+    /// 样本的缓存键, `params` 为影响结果的参数
+    pub fn sample_key(&self, files: &[String], params: &str) -> Result<String> {
+        let mut hasher = SeaHasher::new();
+        hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.write_u64(self.database_hash);
+        hasher.write(params.as_bytes());
+        for file in files {
+            hasher.write_u8(0xff);
+            hash_file_contents(&mut hasher, Path::new(file))?;
+        }
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+
+    /// 返回缓存的 Kraken 输出路径与样本统计
+    pub fn load(&self, key: &str) -> Result<Option<(PathBuf, SampleSummary)>> {
+        let entry = self.dir.join(key);
+        let output = entry.join(CACHED_OUTPUT);
+        let summary_file = entry.join(CACHED_SUMMARY);
+        if !output.is_file() || !summary_file.is_file() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(File::open(&summary_file)?);
+        let summary = serde_json::from_reader(reader).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("malformed result cache {:?}: {}", summary_file, e),
+            )
+        })?;
+        Ok(Some((output, summary)))
+    }
+
+    /// 保存样本的 Kraken 输出 (复制 `output`) 与统计
+    pub fn store(&self, key: &str, output: &Path, summary: &SampleSummary) -> Result<()> {
+        let entry = self.dir.join(key);
+        let tmp = self.dir.join(format!("{}.{}.tmp", key, std::process::id()));
+        let result = (|| {
+            fs::create_dir_all(&tmp)?;
+            fs::copy(output, tmp.join(CACHED_OUTPUT))?;
+            let mut writer = BufWriter::new(File::create(tmp.join(CACHED_SUMMARY))?);
+            serde_json::to_writer(&mut writer, summary)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+            writer.flush()?;
+            if entry.exists() {
+                fs::remove_dir_all(&entry)?;
+            }
+            fs::rename(&tmp, &entry)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&tmp);
+        }
+        result
+    }
+}
@@ -0,0 +1,156 @@
+use crate::args::OutputCompression;
+use crate::classify::parse_call;
+use crate::utils::create_output_writer;
+use seqkmer::dyn_reader;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+/// read 中归到同一个 taxon 的一段, 坐标为 0 起始的半开区间 (与 BED 相同)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub start: usize,
+    pub end: usize,
+    pub taxid: u64,
+    /// 段内命中该 taxon 的位置所占的千分比 (BED 的 score, 0-1000)
+    pub score: u32,
+}
+
+/// 由一个 mate 的命中串 (`taxid:count ...`) 得到分类的片段.
+///
+/// 命中串中的位置是 minimizer 的序号, 而不是碱基位置, 因此按 minimizer 在 read 的
+/// `length - k + 1` 个 k-mer 上均匀分布换算为碱基坐标. 同一 taxon 的命中之间的空位
+/// (`0`) 与模糊位置 (`A`) 并入片段, 遇到另一个 taxon 的命中时开始新的片段
+///
+/// ```
+/// use kraken2_rs::segments::{hit_segments, Segment};
+///
+/// // 100 bp, k = 21: 80 个 k-mer 对应 8 个位置, 每个位置 10 个 k-mer
+/// let segments = hit_segments("562:2 0:1 562:1 561:2 0:2", 100, 21);
+/// assert_eq!(
+///     segments,
+///     vec![
+///         Segment { start: 0, end: 60, taxid: 562, score: 750 },
+///         Segment { start: 40, end: 80, taxid: 561, score: 1000 },
+///     ]
+/// );
+/// assert!(hit_segments("0:8", 100, 21).is_empty());
+/// ```
+pub fn hit_segments(hit_string: &str, length: usize, k: usize) -> Vec<Segment> {
+    let mut runs = Vec::new();
+    for token in hit_string.split_whitespace() {
+        let Some((taxid, count)) = token.split_once(':') else {
+            continue;
+        };
+        let Ok(count) = count.parse::<usize>() else {
+            continue;
+        };
+        // 模糊位置 (A) 与没有命中的位置相同
+        runs.push((taxid.parse::<u64>().unwrap_or(0), count));
+    }
+    let positions: usize = runs.iter().map(|&(_, count)| count).sum();
+    if positions == 0 {
+        return Vec::new();
+    }
+    let kmers = (length + 1).saturating_sub(k).max(1);
+    let first_base = |pos: usize| pos * kmers / positions;
+    let last_base = |pos: usize| ((pos + 1) * kmers).div_ceil(positions) + k - 1;
+
+    let mut segments = Vec::new();
+    // 当前片段: (taxid, 第一个位置, 最后一个命中的位置, 命中数)
+    let mut current: Option<(u64, usize, usize, usize)> = None;
+    let mut pos = 0;
+    let mut finish = |(taxid, first, last, hits): (u64, usize, usize, usize)| {
+        let span = last - first + 1;
+        segments.push(Segment {
+            start: first_base(first),
+            end: last_base(last).min(length),
+            taxid,
+            score: (hits * 1000 / span) as u32,
+        });
+    };
+    for (taxid, count) in runs {
+        if taxid != 0 {
+            current = match current {
+                Some((current_taxid, first, _, hits)) if current_taxid == taxid => {
+                    Some((taxid, first, pos + count - 1, hits + count))
+                }
+                previous => {
+                    if let Some(previous) = previous {
+                        finish(previous);
+                    }
+                    Some((taxid, pos, pos + count - 1, count))
+                }
+            };
+        }
+        pos += count;
+    }
+    if let Some(previous) = current {
+        finish(previous);
+    }
+    segments
+}
+
+/// 每行的 read id, 长度列与命中串, Kraken 格式为第 2, 4, 5 列, JSON 为 `read_id`, `length` 与
+/// `lca_hit_string` 字段. paired reads 的长度与命中串以 `|` 与 `|:|` 分隔
+fn parse_read_line(line: &str) -> Option<(String, Vec<usize>, String)> {
+    if line.starts_with('{') {
+        let record: serde_json::Value = serde_json::from_str(line).ok()?;
+        let read_id = record.get("read_id")?.as_str()?.to_string();
+        let lengths = match record.get("length")? {
+            serde_json::Value::Array(lengths) => lengths
+                .iter()
+                .filter_map(|length| length.as_u64().map(|length| length as usize))
+                .collect(),
+            length => vec![length.as_u64()? as usize],
+        };
+        let hit_string = record.get("lca_hit_string")?.as_str()?.to_string();
+        return Some((read_id, lengths, hit_string));
+    }
+    let columns: Vec<&str> = line.split('\t').collect();
+    if columns.len() < 5 || parse_call(columns[2]).is_none() {
+        return None;
+    }
+    let lengths = columns[3]
+        .split('|')
+        .filter_map(|length| length.parse().ok())
+        .collect();
+    Some((columns[1].to_string(), lengths, columns[4].to_string()))
+}
+
+/// 由 per-read 输出 (Kraken 或 JSON 格式, 可为压缩文件) 写出各 read 的分类片段 (BED:
+/// read, start, end, taxid, score), paired reads 的两个 mate 分别记为 `id/1` 与 `id/2`.
+/// 返回写出的片段数
+pub fn write_segments_bed<P: AsRef<Path>, Q: AsRef<Path>>(
+    output_file: P,
+    bed_file: Q,
+    k: usize,
+) -> io::Result<usize> {
+    let reader = BufReader::new(dyn_reader(output_file)?);
+    let compression = OutputCompression::from_path(&bed_file);
+    let mut writer = create_output_writer(bed_file, compression)?;
+    let mut count = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let Some((read_id, lengths, hit_string)) = parse_read_line(&line) else {
+            continue;
+        };
+        let paired = lengths.len() > 1;
+        for (mate, (length, hits)) in lengths.iter().zip(hit_string.split("|:|")).enumerate() {
+            for segment in hit_segments(hits, *length, k) {
+                if paired {
+                    write!(writer, "{}/{}", read_id, mate + 1)?;
+                } else {
+                    write!(writer, "{}", read_id)?;
+                }
+                writeln!(
+                    writer,
+                    "\t{}\t{}\t{}\t{}",
+                    segment.start, segment.end, segment.taxid, segment.score
+                )?;
+                count += 1;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(count)
+}
@@ -0,0 +1,326 @@
+use crate::args::OutputCompression;
+use crate::utils::create_output_writer;
+use seqkmer::{
+    is_stdin_path, pair_id, AlignmentOptions, OptionPair, PairOptions, PairSync, RecordReader,
+    SeqRecord,
+};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// 输出路径是否为 FIFO, `/dev/fd/N` (process substitution) 等只能顺序写入的流
+///
+/// ```
+/// use kraken2_rs::seqout::is_stream;
+///
+/// assert!(!is_stream(std::env::temp_dir()));
+/// assert!(!is_stream("does/not/exist.fq"));
+/// ```
+pub fn is_stream<P: AsRef<Path>>(path: P) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if let Ok(metadata) = fs::metadata(path) {
+            let file_type = metadata.file_type();
+            return file_type.is_fifo() || file_type.is_char_device() || file_type.is_socket();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    false
+}
+
+/// 写出 reads 时要重新读取输入文件, 标准输入 (`-`) 在分类时已经读完, 不能再读取
+pub fn check_rereadable(files: &[String]) -> Result<()> {
+    if files.iter().any(is_stdin_path) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--classified-out and --unclassified-out read the input files again, which is not possible for reads from standard input (-)",
+        ));
+    }
+    Ok(())
+}
+
+/// 将模板中的 `#` 替换为 `_1`/`_2`, paired reads 的模板必须包含 `#`
+///
+/// ```
+/// use kraken2_rs::seqout::mate_paths;
+/// use seqkmer::OptionPair;
+///
+/// assert_eq!(
+///     mate_paths("cseqs#.fq", true).unwrap(),
+///     OptionPair::Pair("cseqs_1.fq".into(), "cseqs_2.fq".into())
+/// );
+/// assert_eq!(
+///     mate_paths("cseqs.fq", false).unwrap(),
+///     OptionPair::Single("cseqs.fq".into())
+/// );
+/// assert!(mate_paths("cseqs.fq", true).is_err());
+/// ```
+pub fn mate_paths(template: &str, paired: bool) -> Result<OptionPair<PathBuf>> {
+    if !paired {
+        return Ok(OptionPair::Single(PathBuf::from(template)));
+    }
+    if !template.contains('#') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "paired reads output filename must contain '#': {}",
+                template
+            ),
+        ));
+    }
+    Ok(OptionPair::Pair(
+        PathBuf::from(template.replacen('#', "_1", 1)),
+        PathBuf::from(template.replacen('#', "_2", 1)),
+    ))
+}
+
+/// 多个样本时在文件名的扩展名前插入样本序号
+///
+/// ```
+/// use kraken2_rs::seqout::sample_template;
+///
+/// assert_eq!(sample_template("out/cseqs#.fq", 2), "out/cseqs#_2.fq");
+/// assert_eq!(sample_template("cseqs", 3), "cseqs_3");
+/// ```
+pub fn sample_template(template: &str, index: usize) -> String {
+    let name_start = template.rfind('/').map_or(0, |pos| pos + 1);
+    match template[name_start..].find('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, ext) = template.split_at(name_start + dot);
+            format!("{}_{}{}", stem, index, ext)
+        }
+        _ => format!("{}_{}", template, index),
+    }
+}
+
+/// 一个样本的输出, 写入流的 paired reads 没有 `#` 时两个 mate 交替写入同一个流
+enum MateWriters {
+    Single(Box<dyn Write + Send>),
+    Pair(Box<dyn Write + Send>, Box<dyn Write + Send>),
+    Interleaved(Box<dyn Write + Send>),
+}
+
+impl MateWriters {
+    fn write(&mut self, records: &OptionPair<SeqRecord>, suffix: &str) -> Result<()> {
+        match (self, records) {
+            (MateWriters::Single(writer), OptionPair::Single(record)) => {
+                record.write_to(writer, suffix)
+            }
+            (MateWriters::Pair(writer1, writer2), OptionPair::Pair(record1, record2)) => {
+                record1.write_to(writer1, suffix)?;
+                record2.write_to(writer2, suffix)
+            }
+            (MateWriters::Interleaved(writer), OptionPair::Pair(record1, record2)) => {
+                record1.write_to(writer, suffix)?;
+                record2.write_to(writer, suffix)
+            }
+            _ => unreachable!("writers and records have the same pairing"),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            MateWriters::Single(writer) | MateWriters::Interleaved(writer) => writer.flush(),
+            MateWriters::Pair(writer1, writer2) => {
+                writer1.flush()?;
+                writer2.flush()
+            }
+        }
+    }
+}
+
+/// `--classified-out` / `--unclassified-out` 的输出设置
+///
+/// 输出路径为 FIFO 或 `/dev/fd/N` 时所有样本依次写入同一个流, 流在所有样本写完后才关闭,
+/// 每个样本写完时 flush, 下游程序 (如组装软件) 可以直接读取, 不需要中间文件
+#[derive(Debug, Clone)]
+pub struct SeqOutput {
+    classified: Option<String>,
+    unclassified: Option<String>,
+    multi_sample: bool,
+    pairs: PairOptions,
+    alignment: AlignmentOptions,
+    compression: OutputCompression,
+    /// 已打开的流, 关闭后下游会读到 EOF, 所以在样本之间保持打开
+    streams: Arc<Mutex<HashMap<PathBuf, File>>>,
+}
+
+impl SeqOutput {
+    /// 两个模板都为空时返回 None
+    pub fn new(
+        classified: Option<String>,
+        unclassified: Option<String>,
+        multi_sample: bool,
+    ) -> Option<Self> {
+        if classified.is_none() && unclassified.is_none() {
+            return None;
+        }
+        Some(Self {
+            classified,
+            unclassified,
+            multi_sample,
+            pairs: PairOptions::default(),
+            alignment: AlignmentOptions::default(),
+            compression: OutputCompression::None,
+            streams: Arc::default(),
+        })
+    }
+
+    /// 与分类时相同的 mate 配对方式, 保证 reads 序号一致
+    pub fn with_pairs(mut self, pairs: PairOptions) -> Self {
+        self.pairs = pairs;
+        self
+    }
+
+    /// 与分类时相同的 BAM/CRAM 过滤方式
+    pub fn with_alignment(mut self, alignment: AlignmentOptions) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// 压缩写出的文件 (文件名追加 .gz 或 .zst), 流不压缩
+    pub fn with_compression(mut self, compression: OutputCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// 在分类开始前检查模板是否满足 paired reads 的要求
+    pub fn check(&self, paired: bool) -> Result<()> {
+        for template in self.classified.iter().chain(self.unclassified.iter()) {
+            if !Self::interleaved_stream(template, paired) {
+                mate_paths(template, paired)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// paired reads 写入一个没有 `#` 的流时交替写出两个 mate
+    fn interleaved_stream(template: &str, paired: bool) -> bool {
+        paired && !template.contains('#') && is_stream(template)
+    }
+
+    /// 打开输出文件, 流只打开一次, 之后的样本写入复制的文件描述符
+    fn open(&self, path: PathBuf) -> Result<Box<dyn Write + Send>> {
+        if !is_stream(&path) {
+            let path = self.compression.compressed_path(path);
+            return create_output_writer(path, self.compression);
+        }
+        let mut streams = self.streams.lock().unwrap();
+        let file = match streams.entry(path) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let file = fs::OpenOptions::new().write(true).open(entry.key())?;
+                entry.insert(file)
+            }
+        };
+        let file = file.try_clone()?;
+        Ok(Box::new(BufWriter::new(file)))
+    }
+
+    fn writers(
+        &self,
+        template: &Option<String>,
+        index: usize,
+        paired: bool,
+    ) -> Result<Option<MateWriters>> {
+        let Some(template) = template else {
+            return Ok(None);
+        };
+        if Self::interleaved_stream(template, paired) {
+            let writer = self.open(PathBuf::from(template))?;
+            return Ok(Some(MateWriters::Interleaved(writer)));
+        }
+        // 流不能按样本分成不同的文件
+        let template = if self.multi_sample && !is_stream(template) {
+            sample_template(template, index)
+        } else {
+            template.clone()
+        };
+        let writers = match mate_paths(&template, paired)? {
+            OptionPair::Single(path) => MateWriters::Single(self.open(path)?),
+            OptionPair::Pair(path1, path2) => {
+                MateWriters::Pair(self.open(path1)?, self.open(path2)?)
+            }
+        };
+        Ok(Some(writers))
+    }
+
+    /// 重新读取样本的原始序列, 按分类结果分别写出
+    ///
+    /// `calls` 的 key 为 read 在文件中的序号 (从 1 开始), value 为 external taxid.
+    /// 不在 `calls` 中的 read 视为未分类.
+    pub fn write_sample(
+        &self,
+        files: &[String],
+        index: usize,
+        calls: &HashMap<usize, u64>,
+    ) -> Result<()> {
+        self.write_sample_reads(files, index, calls, |_| true)
+    }
+
+    /// 与 `write_sample` 相同, 只写出 `keep` 返回 true 的 reads, 用于 `--sample-regex`
+    /// 从同一文件分出的样本
+    pub fn write_sample_reads<F>(
+        &self,
+        files: &[String],
+        index: usize,
+        calls: &HashMap<usize, u64>,
+        keep: F,
+    ) -> Result<()>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let paired = files.len() > 1 || self.pairs.interleaved;
+        let mut classified = self.writers(&self.classified, index, paired)?;
+        let mut unclassified = self.writers(&self.unclassified, index, paired)?;
+        let mut readers = files
+            .iter()
+            .map(|file| RecordReader::from_path_with_alignment(file, &self.alignment, paired))
+            .collect::<Result<Vec<_>>>()?;
+        let mut sync = PairSync::new(self.pairs);
+        // interleaved 时只有一个 reader
+        let last = readers.len() - 1;
+
+        let mut reads_index = 0;
+        loop {
+            let records = if paired {
+                sync.next_pair(
+                    |mate| readers[mate.min(last)].next_record(),
+                    |record| pair_id(&record.header),
+                )?
+                .map(|(record1, record2)| OptionPair::Pair(record1, record2))
+            } else {
+                readers[0].next_record()?.map(OptionPair::Single)
+            };
+            let Some(records) = records else {
+                break;
+            };
+            reads_index += 1;
+            if !keep(reads_index) {
+                continue;
+            }
+
+            let call = calls.get(&reads_index).copied().unwrap_or(0);
+            let writers = if call > 0 {
+                classified.as_mut()
+            } else {
+                unclassified.as_mut()
+            };
+            let Some(writers) = writers else {
+                continue;
+            };
+            let suffix = format!(" kraken:taxid|{}", call);
+            writers.write(&records, &suffix)?;
+        }
+
+        for writers in classified.iter_mut().chain(unclassified.iter_mut()) {
+            writers.flush()?;
+        }
+        Ok(())
+    }
+}
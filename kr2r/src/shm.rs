@@ -0,0 +1,80 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, Metadata};
+use std::hash::{Hash, Hasher};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tracing::info;
+
+/// 共享内存 (tmpfs) 目录, 其中的文件在进程退出后仍保留在内存中, 直到删除或重启
+pub const SHM_DIR: &str = "/dev/shm";
+
+fn modified_secs(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs())
+}
+
+/// 数据库在共享内存中的目录, 由数据库路径与 shard 的大小和修改时间决定, 数据库重建后换用新的目录
+pub fn shm_cache_dir(database: &Path, hash_files: &[PathBuf]) -> Result<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    fs::canonicalize(database)?.hash(&mut hasher);
+    for file in hash_files {
+        let metadata = fs::metadata(file)?;
+        file.file_name().hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        modified_secs(&metadata).hash(&mut hasher);
+    }
+    Ok(Path::new(SHM_DIR).join(format!("kraken2_rs-{:016x}", hasher.finish())))
+}
+
+/// 把 hash shard 复制到共享内存中并返回复制后的路径, 之前的运行已复制的 shard 直接使用.
+///
+/// 返回的文件与原 shard 相同, 以内存映射方式打开时不再读取磁盘, 多个进程共享同一份内存.
+/// 复制先写入临时文件再重命名, 并发运行不会看到复制了一半的 shard
+pub fn shm_cache_files(database: &Path, hash_files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    if !Path::new(SHM_DIR).is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("{} is not available for --shm-cache", SHM_DIR),
+        ));
+    }
+    let cache_dir = shm_cache_dir(database, hash_files)?;
+    fs::create_dir_all(&cache_dir)?;
+
+    let mut cached = Vec::with_capacity(hash_files.len());
+    let mut copied = 0;
+    for file in hash_files {
+        let name = file.file_name().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid hash file {:?}", file),
+            )
+        })?;
+        let target = cache_dir.join(name);
+        let len = fs::metadata(file)?.len();
+        if fs::metadata(&target).map_or(true, |metadata| metadata.len() != len) {
+            let tmp = cache_dir.join(format!(
+                "{}.{}.tmp",
+                name.to_string_lossy(),
+                std::process::id()
+            ));
+            if let Err(e) = fs::copy(file, &tmp).and_then(|_| fs::rename(&tmp, &target)) {
+                let _ = fs::remove_file(&tmp);
+                return Err(e);
+            }
+            copied += 1;
+        }
+        cached.push(target);
+    }
+    info!(
+        "shm cache {:?}: {} of {} hash files copied, {} reused",
+        cache_dir,
+        copied,
+        hash_files.len(),
+        hash_files.len() - copied
+    );
+    Ok(cached)
+}
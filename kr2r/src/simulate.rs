@@ -0,0 +1,425 @@
+use crate::taxonomy::{rank_level, Taxonomy};
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::str::FromStr;
+
+/// 可由 seed 复现的伪随机数 (splitmix64)
+///
+/// ```
+/// use kraken2_rs::simulate::SimRng;
+///
+/// let mut a = SimRng::new(7);
+/// let mut b = SimRng::new(7);
+/// assert_eq!(a.next_u64(), b.next_u64());
+/// assert!(a.below(10) < 10);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SimRng {
+    state: u64,
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// [0, 1) 上的均匀分布
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// [0, n) 上的均匀分布, n 为 0 时返回 0
+    pub fn below(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    /// 标准正态分布 (Box-Muller)
+    pub fn normal(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+
+    fn base(&mut self) -> u8 {
+        b"ACGT"[self.below(4) as usize]
+    }
+
+    /// 与 `base` 不同的碱基
+    fn other_base(&mut self, base: u8) -> u8 {
+        loop {
+            let other = self.base();
+            if other != base.to_ascii_uppercase() {
+                return other;
+            }
+        }
+    }
+}
+
+/// 模拟 reads 的测序错误模型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorModel {
+    /// 没有错误, 质量值都为 Q40
+    None,
+    /// 只有替换错误, 错误率沿 read 从 0.1% 升到 1%
+    Illumina,
+    /// 约 2% 替换, 1.5% 插入与 1.5% 缺失, 长度为对数正态分布
+    Ont,
+}
+
+impl FromStr for ErrorModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "illumina" => Ok(Self::Illumina),
+            "ont" | "nanopore" => Ok(Self::Ont),
+            _ => Err(format!(
+                "unknown error model '{}', expected none, illumina or ont",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ErrorModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Illumina => "illumina",
+            Self::Ont => "ont",
+        })
+    }
+}
+
+/// ONT 的替换, 插入与缺失率
+const ONT_SUBSTITUTION: f64 = 0.02;
+const ONT_INSERTION: f64 = 0.015;
+const ONT_DELETION: f64 = 0.015;
+
+/// 错误概率对应的 phred+33 质量字符, 在 Q2 与 Q40 之间
+fn quality_char(error: f64) -> u8 {
+    let phred = (-10.0 * error.max(1e-4).log10()).round().clamp(2.0, 40.0);
+    b'!' + phred as u8
+}
+
+impl ErrorModel {
+    /// 一条 read 在参考序列上的长度; ONT 为中位数 `mean` 的对数正态分布, 至少 50 bp
+    pub fn read_length(&self, rng: &mut SimRng, mean: usize) -> usize {
+        match self {
+            Self::Ont => ((mean as f64) * (0.5 * rng.normal()).exp())
+                .round()
+                .max(50.0) as usize,
+            _ => mean,
+        }
+    }
+
+    /// 对参考序列片段加入测序错误, 返回 read 与质量值
+    ///
+    /// ```
+    /// use kraken2_rs::simulate::{ErrorModel, SimRng};
+    ///
+    /// let mut rng = SimRng::new(1);
+    /// let (seq, qual) = ErrorModel::None.sequence(&mut rng, b"ACGTACGT");
+    /// assert_eq!(seq, b"ACGTACGT");
+    /// assert_eq!(qual, b"IIIIIIII");
+    ///
+    /// let (seq, qual) = ErrorModel::Ont.sequence(&mut rng, &[b'A'; 1000]);
+    /// assert_eq!(seq.len(), qual.len());
+    /// ```
+    pub fn sequence(&self, rng: &mut SimRng, template: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut seq = Vec::with_capacity(template.len() + template.len() / 16);
+        let mut qual = Vec::with_capacity(seq.capacity());
+        match self {
+            Self::None => {
+                seq.extend_from_slice(template);
+                qual.resize(template.len(), quality_char(1e-4));
+            }
+            Self::Illumina => {
+                let len = template.len().max(1) as f64;
+                for (i, &base) in template.iter().enumerate() {
+                    let error = 0.001 + 0.009 * (i as f64 / len).powi(2);
+                    seq.push(if rng.chance(error) {
+                        rng.other_base(base)
+                    } else {
+                        base
+                    });
+                    qual.push(quality_char(error));
+                }
+            }
+            Self::Ont => {
+                let q = quality_char(ONT_SUBSTITUTION + ONT_INSERTION + ONT_DELETION);
+                for &base in template {
+                    while rng.chance(ONT_INSERTION) {
+                        seq.push(rng.base());
+                        qual.push(q);
+                    }
+                    if rng.chance(ONT_DELETION) {
+                        continue;
+                    }
+                    seq.push(if rng.chance(ONT_SUBSTITUTION) {
+                        rng.other_base(base)
+                    } else {
+                        base
+                    });
+                    qual.push(q);
+                }
+            }
+        }
+        (seq, qual)
+    }
+}
+
+/// 反向互补, 非 ACGT 的碱基变为 N
+///
+/// ```
+/// use kraken2_rs::simulate::reverse_complement;
+///
+/// assert_eq!(reverse_complement(b"AACGTN"), b"NACGTT");
+/// ```
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|base| match base.to_ascii_uppercase() {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            _ => b'N',
+        })
+        .collect()
+}
+
+/// 解析 `562:0.5,1280:0.3` 形式的 taxid 与丰度; 省略丰度时为 1
+///
+/// ```
+/// use kraken2_rs::simulate::parse_abundances;
+///
+/// assert_eq!(
+///     parse_abundances("562:0.5, 1280").unwrap(),
+///     vec![(562, 0.5), (1280, 1.0)]
+/// );
+/// assert!(parse_abundances("562:-1").is_err());
+/// ```
+pub fn parse_abundances(s: &str) -> Result<Vec<(u64, f64)>> {
+    let invalid = |item: &str| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid abundance '{}', expected TAXID[:ABUNDANCE]", item),
+        )
+    };
+    let mut abundances = Vec::new();
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (taxid, abundance) = match item.split_once(':') {
+            Some((taxid, abundance)) => (taxid.trim(), abundance.trim()),
+            None => (item, "1"),
+        };
+        let taxid = taxid.parse::<u64>().map_err(|_| invalid(item))?;
+        let abundance = abundance.parse::<f64>().map_err(|_| invalid(item))?;
+        if !abundance.is_finite() || abundance < 0.0 {
+            return Err(invalid(item));
+        }
+        abundances.push((taxid, abundance));
+    }
+    Ok(abundances)
+}
+
+/// 按权重把 `total` 条 reads 分给各项 (最大余数法), 各项之和恰为 `total`
+///
+/// ```
+/// use kraken2_rs::simulate::allocate;
+///
+/// assert_eq!(allocate(10, &[0.5, 0.25, 0.25]), vec![5, 3, 2]);
+/// assert_eq!(allocate(3, &[1.0, 0.0]), vec![3, 0]);
+/// ```
+pub fn allocate(total: u64, weights: &[f64]) -> Vec<u64> {
+    let sum: f64 = weights.iter().sum();
+    if sum <= 0.0 {
+        return vec![0; weights.len()];
+    }
+    let shares: Vec<f64> = weights.iter().map(|w| total as f64 * w / sum).collect();
+    let mut counts: Vec<u64> = shares.iter().map(|share| share.floor() as u64).collect();
+    let mut order: Vec<usize> = (0..weights.len()).collect();
+    order.sort_by(|&a, &b| {
+        let rem = |i: usize| shares[i] - counts[i] as f64;
+        rem(b).total_cmp(&rem(a)).then(a.cmp(&b))
+    });
+    let assigned: u64 = counts.iter().sum();
+    for &i in order.iter().take(total.saturating_sub(assigned) as usize) {
+        counts[i] += 1;
+    }
+    counts
+}
+
+/// evaluate 比较的层级, 由低到高; `taxid` 比较 taxon 本身
+pub const EVALUATION_RANKS: [&str; 8] = [
+    "taxid",
+    "species",
+    "genus",
+    "family",
+    "order",
+    "class",
+    "phylum",
+    "superkingdom",
+];
+
+/// 一条 read 的分类结果与真实 taxon 在某个层级上的比较
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Correct,
+    Incorrect,
+    /// 分类到真实 taxon 在该层级之上的祖先
+    TooGeneral,
+    Unclassified,
+}
+
+/// 一个层级上各种结果的 reads 数
+///
+/// ```
+/// use kraken2_rs::simulate::{Outcome, RankScore};
+///
+/// let mut score = RankScore::default();
+/// for outcome in [Outcome::Correct, Outcome::Correct, Outcome::Correct, Outcome::Incorrect] {
+///     score.add(outcome);
+/// }
+/// score.add(Outcome::Unclassified);
+/// assert_eq!(score.reads(), 5);
+/// assert_eq!(score.sensitivity(), 0.6);
+/// assert_eq!(score.precision(), 0.75);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RankScore {
+    pub correct: u64,
+    pub incorrect: u64,
+    pub too_general: u64,
+    pub unclassified: u64,
+}
+
+impl RankScore {
+    pub fn add(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Correct => self.correct += 1,
+            Outcome::Incorrect => self.incorrect += 1,
+            Outcome::TooGeneral => self.too_general += 1,
+            Outcome::Unclassified => self.unclassified += 1,
+        }
+    }
+
+    pub fn reads(&self) -> u64 {
+        self.correct + self.incorrect + self.too_general + self.unclassified
+    }
+
+    /// 正确的 reads 占所有 reads 的比例
+    pub fn sensitivity(&self) -> f64 {
+        self.correct as f64 / self.reads().max(1) as f64
+    }
+
+    /// 正确的 reads 占分类到该层级 (正确与错误) 的 reads 的比例
+    pub fn precision(&self) -> f64 {
+        self.correct as f64 / (self.correct + self.incorrect).max(1) as f64
+    }
+}
+
+/// lineage 中层级为 `rank` 的节点 (internal id), superkingdom 也匹配 domain 与 realm
+pub fn ancestor_at_rank(taxonomy: &Taxonomy, internal_id: u32, rank: &str) -> Option<u32> {
+    let level = rank_level(rank)?;
+    taxonomy
+        .lineage(internal_id)
+        .into_iter()
+        .rev()
+        .find(|&node| rank_level(taxonomy.rank(node)) == Some(level))
+}
+
+/// 在 `rank` ([`EVALUATION_RANKS`]) 上比较分类结果 `call` 与真实的 taxon `truth` (internal id,
+/// 未分类为 0). 真实的 taxon 在该层级没有祖先时返回 `None`, 不计入该层级
+pub fn score_call(taxonomy: &Taxonomy, truth: u32, call: u32, rank: &str) -> Option<Outcome> {
+    let target = match rank {
+        "taxid" => truth,
+        _ => ancestor_at_rank(taxonomy, truth, rank)?,
+    };
+    if call == 0 {
+        return Some(Outcome::Unclassified);
+    }
+    let call_at = match rank {
+        "taxid" => Some(call),
+        _ => ancestor_at_rank(taxonomy, call, rank),
+    };
+    Some(if call_at == Some(target) {
+        Outcome::Correct
+    } else if taxonomy.is_a_ancestor_of_b(call, target) {
+        Outcome::TooGeneral
+    } else {
+        Outcome::Incorrect
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxonomy::TaxonomyNode;
+
+    /// 1: root, 2: Bacteria, 3: Escherichia, 4: E. coli, 5: E. albertii, 6: E. coli 的菌株
+    fn taxonomy() -> Taxonomy {
+        let ranks = b"no rank\0superkingdom\0genus\0species\0strain\0";
+        let offset = |rank: &str| {
+            let needle = format!("{}\0", rank);
+            ranks
+                .windows(needle.len())
+                .position(|w| w == needle.as_bytes())
+                .unwrap() as u64
+        };
+        let node = |parent_id: u64, external_id: u64, rank: &str| TaxonomyNode {
+            parent_id,
+            external_id,
+            rank_offset: offset(rank),
+            ..Default::default()
+        };
+        let mut taxonomy = Taxonomy::default();
+        taxonomy.nodes = vec![
+            TaxonomyNode::default(),
+            node(0, 1, "no rank"),
+            node(1, 2, "superkingdom"),
+            node(2, 561, "genus"),
+            node(3, 562, "species"),
+            node(3, 208962, "species"),
+            node(4, 83333, "strain"),
+        ]
+        .into();
+        taxonomy.rank_data = ranks.to_vec().into();
+        taxonomy
+    }
+
+    #[test]
+    fn calls_are_scored_at_each_rank() {
+        let taxonomy = taxonomy();
+        let score = |call: u32, rank: &str| score_call(&taxonomy, 6, call, rank);
+        assert_eq!(score(6, "taxid"), Some(Outcome::Correct));
+        assert_eq!(score(4, "taxid"), Some(Outcome::TooGeneral));
+        assert_eq!(score(4, "species"), Some(Outcome::Correct));
+        assert_eq!(score(6, "species"), Some(Outcome::Correct));
+        assert_eq!(score(5, "species"), Some(Outcome::Incorrect));
+        assert_eq!(score(5, "genus"), Some(Outcome::Correct));
+        assert_eq!(score(3, "species"), Some(Outcome::TooGeneral));
+        assert_eq!(score(1, "genus"), Some(Outcome::TooGeneral));
+        assert_eq!(score(0, "genus"), Some(Outcome::Unclassified));
+        assert_eq!(score(2, "superkingdom"), Some(Outcome::Correct));
+        // 没有 family 一级时不计入
+        assert_eq!(score(4, "family"), None);
+        assert_eq!(score_call(&taxonomy, 3, 3, "species"), None);
+    }
+}
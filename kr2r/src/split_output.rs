@@ -0,0 +1,176 @@
+use crate::args::{OutputCompression, OutputFormat};
+use crate::read_index::create_read_output_writer;
+use std::fs::{self, File};
+use std::io::{BufWriter, Result, Write};
+use std::path::{Path, PathBuf};
+
+/// 一个分卷在索引中的一行: 文件名, 第一条与最后一条 read 的序号 (按输出顺序, 从 1 开始), 未压缩字节数
+#[derive(Debug, Clone)]
+struct Part {
+    file_name: String,
+    first_read: u64,
+    reads: u64,
+    bytes: u64,
+}
+
+/// 按大小把逐条 read 的输出分成编号的分卷, 并写出分卷索引
+///
+/// `output_1.txt` 的分卷为 `output_1.part0001.txt`, `output_1.part0002.txt`, ...,
+/// 压缩时再加上压缩后缀. 当前分卷的未压缩大小达到 `max_bytes` 后, 下一行写入新的分卷,
+/// 一行不会跨两个分卷. 索引 `output_1.parts.tsv` 在 `flush` 时重写.
+/// bgzf 压缩时每个分卷有各自的 read id 索引
+///
+/// ```
+/// use kraken2_rs::args::OutputCompression;
+/// use kraken2_rs::split_output::SplitWriter;
+/// use std::io::Write;
+///
+/// let dir = std::env::temp_dir().join("split_writer_doctest");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let mut writer = SplitWriter::new(dir.join("output_1.txt"), OutputCompression::None, 10);
+/// writer.write_all(b"C\tread1\t9606\n").unwrap();
+/// writer.write_all(b"U\tread2\t0\nU\tread3\t0\n").unwrap();
+/// writer.flush().unwrap();
+///
+/// let index = std::fs::read_to_string(dir.join("output_1.parts.tsv")).unwrap();
+/// assert_eq!(
+///     index,
+///     "part\tfirst_read\tlast_read\tbytes\n\
+///      output_1.part0001.txt\t1\t1\t13\n\
+///      output_1.part0002.txt\t2\t3\t20\n"
+/// );
+/// let part = std::fs::read_to_string(dir.join("output_1.part0002.txt")).unwrap();
+/// assert_eq!(part, "U\tread2\t0\nU\tread3\t0\n");
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct SplitWriter {
+    directory: PathBuf,
+    stem: String,
+    extension: String,
+    compression: OutputCompression,
+    format: OutputFormat,
+    max_bytes: u64,
+    writer: Option<Box<dyn Write + Send>>,
+    parts: Vec<Part>,
+    at_line_start: bool,
+}
+
+impl SplitWriter {
+    /// `path` 为不分卷时的输出文件 (不含压缩后缀)
+    pub fn new<P: AsRef<Path>>(path: P, compression: OutputCompression, max_bytes: u64) -> Self {
+        let path = path.as_ref();
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (stem, extension) = match file_name.rsplit_once('.') {
+            Some((stem, extension)) => (stem.to_string(), format!(".{}", extension)),
+            None => (file_name, String::new()),
+        };
+        Self {
+            directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            stem,
+            extension,
+            compression,
+            format: OutputFormat::Kraken,
+            max_bytes: max_bytes.max(1),
+            writer: None,
+            parts: Vec::new(),
+            at_line_start: true,
+        }
+    }
+
+    /// 输出格式, 用于 bgzf 分卷的 read id 索引, 默认为 kraken 格式
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// 分卷索引的路径
+    pub fn index_path(&self) -> PathBuf {
+        self.directory.join(format!("{}.parts.tsv", self.stem))
+    }
+
+    /// 关闭当前分卷并打开下一个
+    fn roll(&mut self) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        let first_read = self
+            .parts
+            .last()
+            .map_or(1, |part| part.first_read + part.reads);
+        let file_name = format!(
+            "{}.part{:04}{}{}",
+            self.stem,
+            self.parts.len() + 1,
+            self.extension,
+            self.compression.extension()
+        );
+        self.writer = Some(create_read_output_writer(
+            self.directory.join(&file_name),
+            self.compression,
+            self.format,
+        )?);
+        self.parts.push(Part {
+            file_name,
+            first_read,
+            reads: 0,
+            bytes: 0,
+        });
+        Ok(())
+    }
+
+    /// 先写临时文件再重命名, 读取索引的程序不会看到写了一半的文件
+    fn write_index(&self) -> Result<()> {
+        let path = self.index_path();
+        let tmp = self.directory.join(format!("{}.parts.tsv.tmp", self.stem));
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        writeln!(writer, "part\tfirst_read\tlast_read\tbytes")?;
+        for part in &self.parts {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                part.file_name,
+                part.first_read,
+                part.first_read + part.reads - 1,
+                part.bytes
+            )?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(tmp, path)
+    }
+}
+
+impl Write for SplitWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let full = self
+            .parts
+            .last()
+            .is_some_and(|part| part.bytes >= self.max_bytes);
+        if self.writer.is_none() || (full && self.at_line_start) {
+            self.roll()?;
+        }
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write_all(buf)?;
+        }
+        if let Some(part) = self.parts.last_mut() {
+            part.bytes += buf.len() as u64;
+            part.reads += buf.iter().filter(|&&b| b == b'\n').count() as u64;
+        }
+        self.at_line_start = buf.ends_with(b"\n");
+        Ok(buf.len())
+    }
+
+    /// 同时重写分卷索引
+    fn flush(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        self.write_index()
+    }
+}
@@ -0,0 +1,435 @@
+use crate::taxonomy::Taxonomy;
+use dashmap::DashMap;
+use seqkmer::{Base, MinimizerIterator, SeqFormat};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+pub const DEFAULT_LENGTH_BIN_SIZE: usize = 50;
+
+/// 单个分类单元的读长直方图
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LengthHistogram {
+    bin_size: usize,
+    bins: BTreeMap<usize, u64>,
+    count: u64,
+    total: u64,
+    min: usize,
+    max: usize,
+}
+
+impl LengthHistogram {
+    pub fn new(bin_size: usize) -> Self {
+        Self {
+            bin_size: bin_size.max(1),
+            bins: BTreeMap::new(),
+            count: 0,
+            total: 0,
+            min: usize::MAX,
+            max: 0,
+        }
+    }
+
+    pub fn add(&mut self, length: usize) {
+        *self.bins.entry(length / self.bin_size).or_insert(0) += 1;
+        self.count += 1;
+        self.total += length as u64;
+        self.min = self.min.min(length);
+        self.max = self.max.max(length);
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (bin, count) in &other.bins {
+            *self.bins.entry(*bin).or_insert(0) += count;
+        }
+        self.count += other.count;
+        self.total += other.total;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total as f64 / self.count as f64
+        }
+    }
+
+    /// 格式化为 `start-end:count,...`
+    pub fn fmt_bins(&self) -> String {
+        self.bins
+            .iter()
+            .map(|(bin, count)| {
+                let start = bin * self.bin_size;
+                format!("{}-{}:{}", start, start + self.bin_size - 1, count)
+            })
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+/// key 为 external taxid, 0 表示 unclassified
+pub type TaxonLengthStats = HashMap<u64, LengthHistogram>;
+pub type TaxonLengthStatsDash = DashMap<u64, LengthHistogram>;
+
+/// 解析 "150" 或 "150|150" 形式的序列长度, pair 的长度为两端之和
+pub fn parse_seq_size(seq_size: &str) -> usize {
+    seq_size
+        .split('|')
+        .filter_map(|s| s.parse::<usize>().ok())
+        .sum()
+}
+
+/// 未分类 read 在输出与报告中的 taxid 与名称 (`--unclassified-taxid`, `--unclassified-label`)
+static UNCLASSIFIED: OnceLock<(u64, String)> = OnceLock::new();
+
+/// 设置未分类 read 在输出与报告中的 taxid 与名称, 默认为 0 与 `unclassified`.
+/// 内部仍以 0 表示未分类, 只在写出时替换. 只能设置一次, 再次设置返回错误
+///
+/// ```
+/// use kraken2_rs::stats::{is_unclassified, set_unclassified, taxon_name, unclassified_taxid};
+/// use kraken2_rs::taxonomy::Taxonomy;
+///
+/// set_unclassified(12908, "unclassified sequences").unwrap();
+/// assert_eq!(unclassified_taxid(), 12908);
+/// assert_eq!(taxon_name(&Taxonomy::default(), 0), "unclassified sequences");
+/// assert_eq!(taxon_name(&Taxonomy::default(), 12908), "unclassified sequences");
+/// assert!(is_unclassified(0) && is_unclassified(12908) && !is_unclassified(562));
+/// assert!(set_unclassified(0, "unclassified").is_err());
+/// ```
+pub fn set_unclassified(taxid: u64, label: &str) -> io::Result<()> {
+    UNCLASSIFIED
+        .set((taxid, label.to_string()))
+        .map_err(|_| io::Error::other("the unclassified taxid and label are already set"))
+}
+
+pub fn unclassified_taxid() -> u64 {
+    UNCLASSIFIED.get().map_or(0, |(taxid, _)| *taxid)
+}
+
+/// 内部的 0 与 [`unclassified_taxid`] 都表示未分类
+pub fn is_unclassified(taxid: u64) -> bool {
+    taxid == 0 || taxid == unclassified_taxid()
+}
+
+/// 写出的 taxid: 内部的未分类 (0) 写为 [`unclassified_taxid`]
+pub fn output_taxid(ext_call: u64) -> u64 {
+    if ext_call == 0 {
+        unclassified_taxid()
+    } else {
+        ext_call
+    }
+}
+
+/// [`unclassified_taxid`] 不能是 taxonomy 中的分类单元, 否则未分类的 reads 与该分类单元无法区分;
+/// 还需要放得下 kreport 中 32 位的 taxid 列
+pub fn check_unclassified_taxid(taxonomy: &Taxonomy) -> io::Result<()> {
+    let taxid = unclassified_taxid();
+    if u32::try_from(taxid).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "--unclassified-taxid {} doesn't fit the taxid column of a kreport",
+                taxid
+            ),
+        ));
+    }
+    let internal_id = if taxid == 0 {
+        0
+    } else {
+        taxonomy.get_internal_id(taxid)
+    };
+    if internal_id != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "--unclassified-taxid {} is {:?} in the database taxonomy, choose a taxid that is not in it",
+                taxid,
+                taxonomy.name(internal_id)
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub fn unclassified_label() -> &'static str {
+    UNCLASSIFIED
+        .get()
+        .map_or("unclassified", |(_, label)| label.as_str())
+}
+
+/// 根据 external taxid 获取物种名称, 0 与 [`unclassified_taxid`] 为未分类 ([`unclassified_label`])
+pub fn taxon_name(taxonomy: &Taxonomy, taxid: u64) -> &str {
+    if is_unclassified(taxid) {
+        unclassified_label()
+    } else {
+        taxonomy.name(taxonomy.get_internal_id(taxid))
+    }
+}
+
+/// 报告中分类名称的规范化方式 (`--name-normalization`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NameNormalization {
+    /// 按原样写出
+    #[default]
+    None,
+    /// Unicode NFC, 同一个名称不论来源的写法 (组合字符或预组合字符) 都写成相同的字节
+    Nfc,
+    /// 去掉重音等组合符号, 其余非 ASCII 字符写为 `?`
+    Ascii,
+}
+
+impl FromStr for NameNormalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(NameNormalization::None),
+            "nfc" => Ok(NameNormalization::Nfc),
+            "ascii" => Ok(NameNormalization::Ascii),
+            _ => Err(format!(
+                "invalid name normalization '{}', expected none, nfc or ascii",
+                s
+            )),
+        }
+    }
+}
+
+static NAME_NORMALIZATION: OnceLock<NameNormalization> = OnceLock::new();
+
+/// 设置报告与输出中分类名称的规范化方式, 默认不规范化. 只有第一次设置生效
+///
+/// ```
+/// use kraken2_rs::stats::{normalize_name, report_name, set_name_normalization, NameNormalization};
+///
+/// set_name_normalization(NameNormalization::Ascii);
+/// assert_eq!(normalize_name("Candida albicans Schröter"), "Candida albicans Schroter");
+/// assert_eq!(report_name("Æ\tsp."), "?\\tsp.");
+/// ```
+pub fn set_name_normalization(normalization: NameNormalization) {
+    let _ = NAME_NORMALIZATION.set(normalization);
+}
+
+/// 按 [`set_name_normalization`] 的设置规范化名称, JSON 输出使用 (控制字符由 JSON 转义)
+pub fn normalize_name(name: &str) -> Cow<'_, str> {
+    match NAME_NORMALIZATION.get().copied().unwrap_or_default() {
+        NameNormalization::None => Cow::Borrowed(name),
+        NameNormalization::Nfc => {
+            if unicode_normalization::is_nfc(name) {
+                Cow::Borrowed(name)
+            } else {
+                Cow::Owned(name.nfc().collect())
+            }
+        }
+        NameNormalization::Ascii => {
+            if name.is_ascii() {
+                Cow::Borrowed(name)
+            } else {
+                Cow::Owned(
+                    name.nfkd()
+                        .filter(|&c| !is_combining_mark(c))
+                        .map(|c| if c.is_ascii() { c } else { '?' })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+/// 写入 tab 分隔的报告 (kreport, mpa, Krona 文本, `--use-names` 等) 的名称: 规范化后把 tab, 换行
+/// 与其他控制字符转义为 `\t`, `\n`, `\r` 与 `\u{..}`, 名称不会打乱报告的列与行
+///
+/// ```
+/// use kraken2_rs::stats::report_name;
+///
+/// assert_eq!(report_name("Escherichia coli"), "Escherichia coli");
+/// assert_eq!(report_name("strain\tA\n"), "strain\\tA\\n");
+/// assert_eq!(report_name("bad\u{1}name"), "bad\\u{1}name");
+/// assert_eq!(report_name("Bacillus sp. Ø"), "Bacillus sp. Ø");
+/// ```
+pub fn report_name(name: &str) -> Cow<'_, str> {
+    let name = normalize_name(name);
+    if !name.chars().any(char::is_control) {
+        return name;
+    }
+    let mut escaped = String::with_capacity(name.len() + 8);
+    for c in name.chars() {
+        match c {
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+pub fn write_length_stats<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+    stats: &TaxonLengthStats,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writeln!(writer, "#taxid\tname\treads\tmin\tmax\tmean\thistogram")?;
+
+    let mut taxids: Vec<&u64> = stats.keys().collect();
+    taxids.sort_unstable();
+    for taxid in taxids {
+        let hist = &stats[taxid];
+        if hist.count() == 0 {
+            continue;
+        }
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{:.2}\t{}",
+            output_taxid(*taxid),
+            report_name(taxon_name(taxonomy, *taxid)),
+            hist.count(),
+            hist.min,
+            hist.max,
+            hist.mean(),
+            hist.fmt_bins()
+        )?;
+    }
+    writer.flush()
+}
+
+/// 单条 read 的 GC 与质量值统计, paired reads 为两端之和
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadQc {
+    pub gc_bases: u64,
+    pub acgt_bases: u64,
+    /// FASTA 输入没有质量值
+    pub qual_sum: Option<u64>,
+}
+
+impl ReadQc {
+    pub fn from_marker(marker: &Base<MinimizerIterator>) -> Self {
+        // 质量过滤会把碱基替换为 'x', GC 按替换前的序列计算
+        let (gc, acgt) = marker
+            .header
+            .unmasked_gc
+            .unwrap_or_else(|| marker.gc_count());
+        let qual_sum = match marker.header.format {
+            SeqFormat::Fastq => Some(marker.header.qual_sum),
+            SeqFormat::Fasta => None,
+        };
+        Self {
+            gc_bases: gc as u64,
+            acgt_bases: acgt as u64,
+            qual_sum,
+        }
+    }
+
+    /// sample_id map 中的 qc 列, 格式为 `gc,acgt[,qual_sum]`
+    pub fn to_column(&self) -> String {
+        match self.qual_sum {
+            Some(qual_sum) => format!("{},{},{}", self.gc_bases, self.acgt_bases, qual_sum),
+            None => format!("{},{}", self.gc_bases, self.acgt_bases),
+        }
+    }
+
+    pub fn parse_column(column: &str) -> Option<Self> {
+        let mut parts = column.split(',');
+        let gc_bases = parts.next()?.parse().ok()?;
+        let acgt_bases = parts.next()?.parse().ok()?;
+        let qual_sum = parts.next().and_then(|s| s.parse().ok());
+        Some(Self {
+            gc_bases,
+            acgt_bases,
+            qual_sum,
+        })
+    }
+}
+
+/// 单个分类单元的 GC 与平均碱基质量汇总
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct QcSummary {
+    reads: u64,
+    gc_bases: u64,
+    acgt_bases: u64,
+    qual_sum: u64,
+    qual_bases: u64,
+}
+
+impl QcSummary {
+    /// `seq_len` 为 read 的总长度, 用于计算平均质量值
+    pub fn add(&mut self, qc: &ReadQc, seq_len: usize) {
+        self.reads += 1;
+        self.gc_bases += qc.gc_bases;
+        self.acgt_bases += qc.acgt_bases;
+        if let Some(qual_sum) = qc.qual_sum {
+            self.qual_sum += qual_sum;
+            self.qual_bases += seq_len as u64;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.reads += other.reads;
+        self.gc_bases += other.gc_bases;
+        self.acgt_bases += other.acgt_bases;
+        self.qual_sum += other.qual_sum;
+        self.qual_bases += other.qual_bases;
+    }
+
+    pub fn reads(&self) -> u64 {
+        self.reads
+    }
+
+    pub fn gc_content(&self) -> Option<f64> {
+        (self.acgt_bases > 0).then(|| self.gc_bases as f64 / self.acgt_bases as f64)
+    }
+
+    pub fn mean_quality(&self) -> Option<f64> {
+        (self.qual_bases > 0).then(|| self.qual_sum as f64 / self.qual_bases as f64)
+    }
+}
+
+/// key 为 external taxid, 0 表示 unclassified
+pub type TaxonQcStats = HashMap<u64, QcSummary>;
+pub type TaxonQcStatsDash = DashMap<u64, QcSummary>;
+
+fn fmt_option(value: Option<f64>, precision: usize) -> String {
+    value.map_or("NA".to_string(), |v| format!("{:.*}", precision, v))
+}
+
+pub fn write_qc_stats<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+    stats: &TaxonQcStats,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    writeln!(writer, "#taxid\tname\treads\tmean_gc\tmean_quality")?;
+
+    let mut taxids: Vec<&u64> = stats.keys().collect();
+    taxids.sort_unstable();
+    for taxid in taxids {
+        let qc = &stats[taxid];
+        if qc.reads() == 0 {
+            continue;
+        }
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            output_taxid(*taxid),
+            report_name(taxon_name(taxonomy, *taxid)),
+            qc.reads(),
+            fmt_option(qc.gc_content(), 4),
+            fmt_option(qc.mean_quality(), 2)
+        )?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,104 @@
+use crate::classify::resolve_tree;
+use crate::taxonomy::Taxonomy;
+use seqkmer::{read_parallel, Base, BufferFastaReader, Meros, MinimizerIterator};
+use std::collections::{HashMap, HashSet};
+use std::io::Result;
+use std::path::Path;
+
+/// 从 library 中为若干 genus 抽取的物种级别子数据库
+///
+/// 每个 genus 只使用其子树下的参考序列建立 minimizer -> taxid (LCA) 映射,
+/// 因此与其它 genus 共享的 minimizer 不会被提升到更高的分类层级.
+#[derive(Debug, Default)]
+pub struct SubDatabase {
+    tables: HashMap<u32, HashMap<u64, u32>>,
+}
+
+impl SubDatabase {
+    /// `genera` 为 internal taxid
+    pub fn build<P: AsRef<Path>>(
+        library_files: &[P],
+        meros: Meros,
+        taxonomy: &Taxonomy,
+        id_to_taxon_map: &HashMap<String, u64>,
+        genera: &HashSet<u32>,
+        threads: usize,
+    ) -> Result<Self> {
+        let mut tables: HashMap<u32, HashMap<u64, u32>> = HashMap::new();
+
+        for library_file in library_files {
+            let mut reader = BufferFastaReader::from_path(library_file, 1)?;
+            read_parallel(
+                &mut reader,
+                threads,
+                &meros,
+                |seqs| {
+                    let mut cells: Vec<(u32, u32, Vec<u64>)> = Vec::new();
+                    for record in seqs {
+                        let Some(ext_taxid) = id_to_taxon_map.get(&record.header.id) else {
+                            continue;
+                        };
+                        let taxid = taxonomy.get_internal_id(*ext_taxid);
+                        let Some(genus) = taxonomy
+                            .path_cache
+                            .get(&taxid)
+                            .and_then(|path| path.iter().find(|t| genera.contains(t)))
+                        else {
+                            continue;
+                        };
+                        let mut keys = Vec::new();
+                        record.body.apply_mut(|m_iter| {
+                            keys.extend(m_iter.map(|(_, hash_key)| hash_key));
+                        });
+                        cells.push((*genus, taxid, keys));
+                    }
+                    cells
+                },
+                |dataset| {
+                    while let Some(data) = dataset.next() {
+                        for (genus, taxid, keys) in data.unwrap() {
+                            let table = tables.entry(genus).or_default();
+                            for key in keys {
+                                table
+                                    .entry(key)
+                                    .and_modify(|value| *value = taxonomy.lca(*value, taxid))
+                                    .or_insert(taxid);
+                            }
+                        }
+                    }
+                },
+            )?;
+        }
+
+        Ok(Self { tables })
+    }
+
+    pub fn contains(&self, genus: u32) -> bool {
+        self.tables.contains_key(&genus)
+    }
+
+    /// 在 genus 对应的子数据库中重新分类, 返回 internal taxid (0 表示没有命中)
+    pub fn classify(
+        &self,
+        genus: u32,
+        marker: &mut Base<MinimizerIterator>,
+        taxonomy: &Taxonomy,
+        confidence_threshold: f64,
+    ) -> u32 {
+        let Some(table) = self.tables.get(&genus) else {
+            return 0;
+        };
+        let mut counts: HashMap<u32, u64> = HashMap::new();
+        let mut total = 0;
+        marker.body.apply_mut(|m_iter| {
+            for (_, hash_key) in m_iter.by_ref() {
+                total += 1;
+                if let Some(taxid) = table.get(&hash_key) {
+                    *counts.entry(*taxid).or_insert(0) += 1;
+                }
+            }
+        });
+        let required_score = (confidence_threshold * total as f64).ceil() as u64;
+        resolve_tree(&counts, taxonomy, required_score)
+    }
+}
@@ -1,11 +1,16 @@
+use crate::error::Kr2rError;
 use crate::utils::open_file;
-use std::collections::{HashMap, HashSet, VecDeque};
+use memmap2::Mmap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::ops::Deref;
 use std::path::Path;
+use std::sync::Arc;
 
 /// 解析 ncbi 文件的 taxonomy nodes 文件
+#[allow(clippy::type_complexity)]
 pub fn parse_nodes_file<P: AsRef<Path>>(
     nodes_filename: P,
 ) -> Result<(
@@ -72,7 +77,7 @@ pub fn parse_names_file<P: AsRef<Path>>(names_filename: P) -> Result<HashMap<u64
         if line.is_empty() || line.starts_with('#') {
             continue;
         }
-        let line = line.trim_end_matches(|c| c == '\t' || c == '|' || c == '\n');
+        let line = line.trim_end_matches(['\t', '|', '\n']);
         // 分割行为字段
         let fields: Vec<_> = line.split("\t|\t").collect();
         if fields.len() < 4 {
@@ -92,8 +97,128 @@ pub fn parse_names_file<P: AsRef<Path>>(names_filename: P) -> Result<HashMap<u64
     Ok(name_map)
 }
 
-/// 结构体定义
-#[derive(Debug)]
+/// 主要分类层级由高到低的顺序, 其他层级 (no rank, clade, strain 等) 返回 None
+///
+/// ```
+/// use kraken2_rs::taxonomy::rank_level;
+///
+/// assert!(rank_level("genus") < rank_level("species"));
+/// assert_eq!(rank_level("domain"), rank_level("superkingdom"));
+/// assert_eq!(rank_level("strain"), None);
+/// ```
+pub fn rank_level(rank: &str) -> Option<u8> {
+    match rank {
+        "superkingdom" | "domain" | "realm" => Some(1),
+        "kingdom" => Some(2),
+        "phylum" => Some(3),
+        "class" => Some(4),
+        "order" => Some(5),
+        "family" => Some(6),
+        "genus" => Some(7),
+        "species" => Some(8),
+        _ => None,
+    }
+}
+
+/// GTDB 分类字符串的层级前缀与写入 taxo.k2d 的 rank
+const GTDB_RANKS: [(&str, &str); 7] = [
+    ("d__", "superkingdom"),
+    ("p__", "phylum"),
+    ("c__", "class"),
+    ("o__", "order"),
+    ("f__", "family"),
+    ("g__", "genus"),
+    ("s__", "species"),
+];
+
+/// 解析 GTDB 的分类字符串, 返回各层级的 (rank, 名称), 名称为空的层级 (如 `s__`) 被跳过
+///
+/// ```
+/// use kraken2_rs::taxonomy::parse_gtdb_lineage;
+///
+/// let lineage = parse_gtdb_lineage("d__Bacteria;p__Pseudomonadota;c__;s__").unwrap();
+/// assert_eq!(lineage, vec![("superkingdom", "Bacteria"), ("phylum", "Pseudomonadota")]);
+/// assert!(parse_gtdb_lineage("p__Pseudomonadota;d__Bacteria").is_err());
+/// ```
+pub fn parse_gtdb_lineage(lineage: &str) -> Result<Vec<(&'static str, &str)>> {
+    let mut ranks = GTDB_RANKS.iter();
+    let mut parsed = Vec::new();
+    for field in lineage.trim().split(';') {
+        let field = field.trim();
+        let (prefix, rank) = ranks
+            .find(|(prefix, _)| field.starts_with(prefix))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid GTDB taxonomy string: {}", lineage),
+                )
+            })?;
+        let name = &field[prefix.len()..];
+        if !name.is_empty() {
+            parsed.push((*rank, name));
+        }
+    }
+    Ok(parsed)
+}
+
+/// 读取 GTDB 的 taxonomy 文件 (`bac120_taxonomy.tsv`, 每行 `<accession>\t<分类字符串>`)
+/// 或 metadata 文件 (有 `accession` 与 `gtdb_taxonomy` 列的表头), 支持 `.gz`
+fn read_gtdb_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let file = open_file(path)?;
+    let reader: Box<dyn BufRead> = if path.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+
+    let mut columns = (0, 1);
+    let mut records = Vec::new();
+    for (line_num, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if line_num == 0 {
+            if let Some(taxonomy) = fields.iter().position(|&f| f == "gtdb_taxonomy") {
+                let accession = fields.iter().position(|&f| f == "accession").unwrap_or(0);
+                columns = (accession, taxonomy);
+                continue;
+            }
+        }
+        match (fields.get(columns.0), fields.get(columns.1)) {
+            (Some(accession), Some(lineage)) => {
+                records.push((accession.to_string(), lineage.to_string()))
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{:?} line {}: missing GTDB taxonomy", path, line_num + 1),
+                ))
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// 去掉 GTDB accession 的 `RS_`/`GB_` 前缀, 与 NCBI assembly accession 一致
+///
+/// ```
+/// use kraken2_rs::taxonomy::gtdb_genome_accession;
+///
+/// assert_eq!(gtdb_genome_accession("RS_GCF_000005845.2"), "GCF_000005845.2");
+/// assert_eq!(gtdb_genome_accession("GCA_000008865.2"), "GCA_000008865.2");
+/// ```
+pub fn gtdb_genome_accession(accession: &str) -> &str {
+    accession
+        .strip_prefix("RS_")
+        .or_else(|| accession.strip_prefix("GB_"))
+        .unwrap_or(accession)
+}
+
+/// 结构体定义, 与 taxo.k2d 中每个节点的 56 字节布局相同
+#[repr(C)]
+#[derive(Debug, Default)]
 pub struct TaxonomyNode {
     pub parent_id: u64,
     pub first_child: u64,
@@ -104,20 +229,6 @@ pub struct TaxonomyNode {
     pub godparent_id: u64,
 }
 
-impl Default for TaxonomyNode {
-    fn default() -> Self {
-        Self {
-            parent_id: 0,
-            first_child: 0,
-            child_count: 0,
-            name_offset: 0,
-            rank_offset: 0,
-            external_id: 0,
-            godparent_id: 0,
-        }
-    }
-}
-
 // NCBITaxonomy 类型定义
 pub struct NCBITaxonomy {
     parent_map: HashMap<u64, u64>,
@@ -148,6 +259,81 @@ impl NCBITaxonomy {
         })
     }
 
+    /// 由 GTDB 的 taxonomy 或 metadata 文件 (如 bac120 与 ar53) 构建分类树,
+    /// 返回分类树与基因组 accession (去掉 `RS_`/`GB_` 前缀) 到其最低层级 taxid 的映射.
+    ///
+    /// GTDB 没有 taxid, 根节点为 1, 其余节点按分类路径排序后从 2 开始编号,
+    /// 相同的 GTDB 文件总是得到相同的 taxid
+    pub fn from_gtdb<P: AsRef<Path>>(taxonomy_files: &[P]) -> Result<(Self, HashMap<String, u64>)> {
+        let mut records = Vec::new();
+        for path in taxonomy_files {
+            records.extend(read_gtdb_file(path.as_ref())?);
+        }
+
+        // 分类路径 -> (rank, 名称, 上级路径), 路径区分不同分支下的同名节点
+        let mut nodes: BTreeMap<String, (&str, String, String)> = BTreeMap::new();
+        let mut genomes = Vec::with_capacity(records.len());
+        for (accession, lineage) in &records {
+            let mut path = String::new();
+            for (rank, name) in parse_gtdb_lineage(lineage)? {
+                let parent = path.clone();
+                if !path.is_empty() {
+                    path.push(';');
+                }
+                path.push_str(rank);
+                path.push(':');
+                path.push_str(name);
+                nodes
+                    .entry(path.clone())
+                    .or_insert_with(|| (rank, name.to_string(), parent));
+            }
+            genomes.push((gtdb_genome_accession(accession).to_string(), path));
+        }
+
+        let mut taxids = HashMap::new();
+        taxids.insert(String::new(), 1u64);
+        for (taxid, path) in nodes.keys().enumerate() {
+            taxids.insert(path.clone(), taxid as u64 + 2);
+        }
+
+        let mut parent_map = HashMap::new();
+        let mut child_map: HashMap<u64, HashSet<u64>> = HashMap::new();
+        let mut rank_map = HashMap::new();
+        let mut name_map = HashMap::new();
+        let mut known_ranks = HashSet::new();
+        parent_map.insert(1, 0);
+        child_map.entry(0).or_default().insert(1);
+        rank_map.insert(1, "no rank".to_string());
+        name_map.insert(1, "root".to_string());
+        known_ranks.insert("no rank".to_string());
+        for (path, (rank, name, parent)) in &nodes {
+            let taxid = taxids[path];
+            let parent_id = taxids[parent];
+            parent_map.insert(taxid, parent_id);
+            child_map.entry(parent_id).or_default().insert(taxid);
+            rank_map.insert(taxid, rank.to_string());
+            name_map.insert(taxid, name.clone());
+            known_ranks.insert(rank.to_string());
+        }
+
+        let accessions = genomes
+            .into_iter()
+            .map(|(accession, path)| (accession, taxids[&path]))
+            .collect();
+
+        let mut marked_nodes = HashSet::new();
+        marked_nodes.insert(1);
+        let taxonomy = NCBITaxonomy {
+            parent_map,
+            name_map,
+            rank_map,
+            child_map,
+            known_ranks,
+            marked_nodes,
+        };
+        Ok((taxonomy, accessions))
+    }
+
     pub fn mark_node(&mut self, taxid: u64) {
         let mut current_taxid = taxid;
         while !self.marked_nodes.contains(&current_taxid) {
@@ -179,8 +365,8 @@ impl NCBITaxonomy {
     pub fn convert_to_kraken_taxonomy(&self) -> Taxonomy {
         let mut taxo = Taxonomy::default();
         // 预分配内存
-        taxo.nodes.reserve(self.marked_nodes.len() + 1);
-        taxo.nodes.push(TaxonomyNode::default());
+        let mut nodes = Vec::with_capacity(self.marked_nodes.len() + 1);
+        nodes.push(TaxonomyNode::default());
 
         let mut name_data = String::new();
         let (rank_offsets, rank_data) = self.get_rank_offset_data();
@@ -196,14 +382,15 @@ impl NCBITaxonomy {
             internal_node_id += 1;
             external_id_map.insert(external_node_id, internal_node_id);
 
-            let mut node = TaxonomyNode::default();
-            node.parent_id = external_id_map
-                .get(&self.parent_map[&external_node_id])
-                .unwrap()
-                .clone();
-            node.external_id = external_node_id;
-            node.rank_offset = *rank_offsets.get(&self.rank_map[&external_node_id]).unwrap();
-            node.name_offset = name_data.len() as u64;
+            let mut node = TaxonomyNode {
+                parent_id: *external_id_map
+                    .get(&self.parent_map[&external_node_id])
+                    .unwrap(),
+                external_id: external_node_id,
+                rank_offset: *rank_offsets.get(&self.rank_map[&external_node_id]).unwrap(),
+                name_offset: name_data.len() as u64,
+                ..Default::default()
+            };
 
             let name = self.name_map.get(&external_node_id).unwrap();
             name_data.push_str(name);
@@ -222,50 +409,124 @@ impl NCBITaxonomy {
                     }
                 }
             }
-            taxo.nodes.push(node);
+            nodes.push(node);
         }
 
-        taxo.name_data = name_data.into_bytes();
-        taxo.rank_data = rank_data.into_bytes();
+        taxo.nodes = nodes.into();
+        taxo.name_data = name_data.into_bytes().into();
+        taxo.rank_data = rank_data.into_bytes().into();
 
         taxo
     }
 }
 
-// Taxonomy 类型定义
+/// taxonomy 的定长数组, 读入内存或直接引用内存映射的 taxo.k2d 中的一段
 #[derive(Debug)]
-pub struct Taxonomy {
-    pub path_cache: HashMap<u32, Vec<u32>>,
-    pub nodes: Vec<TaxonomyNode>,
-    pub name_data: Vec<u8>, // 字符串数据以 Vec<u8> 存储
-    pub rank_data: Vec<u8>, // 字符串数据以 Vec<u8> 存储
-    external_to_internal_id_map: HashMap<u64, u32>,
+pub struct TaxoData<T>(Storage<T>);
+
+#[derive(Debug)]
+enum Storage<T> {
+    Owned(Vec<T>),
+    Mapped {
+        mmap: Arc<Mmap>,
+        offset: usize,
+        len: usize,
+    },
 }
 
-impl Default for Taxonomy {
+impl<T> TaxoData<T> {
+    /// 引用 `mmap` 中从 `offset` 开始的 `len` 个元素, 越界或未对齐时返回 None
+    fn mapped(mmap: &Arc<Mmap>, offset: usize, len: usize) -> Option<Self> {
+        let end = len
+            .checked_mul(std::mem::size_of::<T>())?
+            .checked_add(offset)?;
+        if end > mmap.len()
+            || !(mmap.as_ptr() as usize + offset).is_multiple_of(std::mem::align_of::<T>())
+        {
+            return None;
+        }
+        Some(TaxoData(Storage::Mapped {
+            mmap: mmap.clone(),
+            offset,
+            len,
+        }))
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.0, Storage::Mapped { .. })
+    }
+}
+
+impl<T> Default for TaxoData<T> {
     fn default() -> Self {
-        Taxonomy {
-            path_cache: HashMap::new(),
-            nodes: Vec::new(),
-            name_data: Vec::new(),
-            rank_data: Vec::new(),
-            external_to_internal_id_map: HashMap::new(),
+        TaxoData(Storage::Owned(Vec::new()))
+    }
+}
+
+impl<T> From<Vec<T>> for TaxoData<T> {
+    fn from(data: Vec<T>) -> Self {
+        TaxoData(Storage::Owned(data))
+    }
+}
+
+impl<T> Deref for TaxoData<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match &self.0 {
+            Storage::Owned(data) => data,
+            // 范围与对齐在 mapped 中已检查, T 只用于没有无效位模式的整数结构
+            Storage::Mapped { mmap, offset, len } => unsafe {
+                std::slice::from_raw_parts(mmap.as_ptr().add(*offset) as *const T, *len)
+            },
         }
     }
 }
 
+// Taxonomy 类型定义
+#[derive(Debug, Default)]
+pub struct Taxonomy {
+    pub path_cache: HashMap<u32, Vec<u32>>,
+    pub nodes: TaxoData<TaxonomyNode>,
+    pub name_data: TaxoData<u8>, // 字符串数据以 u8 数组存储
+    pub rank_data: TaxoData<u8>, // 字符串数据以 u8 数组存储
+    external_to_internal_id_map: HashMap<u64, u32>,
+    /// open_mmap 时代替 external_to_internal_id_map, 按 external id 排序的 (external id, internal id)
+    external_index: TaxoData<[u64; 2]>,
+}
+
 impl Taxonomy {
     const MAGIC: &'static [u8] = b"K2TAXDAT"; // 替换为实际的 magic bytes
+    /// 节点之后 (8 字节对齐) 的 external id 索引, 旧版本读取时忽略
+    const INDEX_MAGIC: &'static [u8] = b"K2TAXIDX";
 
     pub fn from_file<P: AsRef<Path> + Debug>(filename: P) -> Result<Taxonomy> {
-        let mut file = open_file(&filename)?;
+        Self::read_file(&filename).map_err(|e| Kr2rError::index(&filename, e).into())
+    }
+
+    fn read_file<P: AsRef<Path> + Debug>(filename: P) -> Result<Taxonomy> {
+        let file = open_file(&filename)?;
+        Self::read_from(file, &filename)
+    }
+
+    /// 从内存中 taxo.k2d 的内容读取, 例如没有文件系统的 wasm32 中由调用方取得的数据库
+    ///
+    /// ```
+    /// use kraken2_rs::taxonomy::Taxonomy;
+    ///
+    /// assert!(Taxonomy::from_bytes(b"not a taxonomy").is_err());
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Result<Taxonomy> {
+        Self::read_from(bytes, &"taxo.k2d")
+    }
 
+    fn read_from<R: Read>(mut file: R, source: &dyn Debug) -> Result<Taxonomy> {
         let mut magic = vec![0; Self::MAGIC.len()];
         file.read_exact(&mut magic)?;
         if magic != Self::MAGIC {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
-                format!("Malformed taxonomy file {:?}", &filename),
+                format!("Malformed taxonomy file {:?}", source),
             ));
         }
 
@@ -296,15 +557,82 @@ impl Taxonomy {
 
         let mut taxo = Taxonomy {
             path_cache: HashMap::new(),
-            nodes,
-            name_data,
-            rank_data,
+            nodes: nodes.into(),
+            name_data: name_data.into(),
+            rank_data: rank_data.into(),
             external_to_internal_id_map,
+            external_index: TaxoData::default(),
         };
         taxo.build_path_cache();
         Ok(taxo)
     }
 
+    /// 内存映射 taxo.k2d, 节点与名称直接引用映射的文件, 同一节点上的多个进程共享 page cache.
+    ///
+    /// 不建立 path_cache, 祖先与 LCA 沿 parent 查找. 没有 external id 索引的旧文件在内存中建立索引
+    pub fn open_mmap<P: AsRef<Path> + Debug>(filename: P) -> Result<Taxonomy> {
+        Self::read_mmap(&filename).map_err(|e| Kr2rError::index(&filename, e).into())
+    }
+
+    fn read_mmap<P: AsRef<Path> + Debug>(filename: P) -> Result<Taxonomy> {
+        let malformed = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Malformed taxonomy file {:?}", &filename),
+            )
+        };
+        let file = File::open(&filename)?;
+        let mmap = Arc::new(unsafe { Mmap::map(&file)? });
+        if mmap.len() < 32 || &mmap[..Self::MAGIC.len()] != Self::MAGIC {
+            return Err(malformed());
+        }
+        let read_u64 = |offset: usize| -> Option<usize> {
+            let bytes = mmap.get(offset..offset + 8)?;
+            usize::try_from(u64::from_le_bytes(bytes.try_into().ok()?)).ok()
+        };
+        let node_count = read_u64(8).ok_or_else(malformed)?;
+        let name_data_len = read_u64(16).ok_or_else(malformed)?;
+        let rank_data_len = read_u64(24).ok_or_else(malformed)?;
+
+        let nodes: TaxoData<TaxonomyNode> =
+            TaxoData::mapped(&mmap, 32, node_count).ok_or_else(malformed)?;
+        let name_offset = 32 + node_count * std::mem::size_of::<TaxonomyNode>();
+        let name_data =
+            TaxoData::mapped(&mmap, name_offset, name_data_len).ok_or_else(malformed)?;
+        let rank_offset = name_offset + name_data_len;
+        let rank_data =
+            TaxoData::mapped(&mmap, rank_offset, rank_data_len).ok_or_else(malformed)?;
+
+        let index_offset = (rank_offset + rank_data_len).next_multiple_of(8);
+        let external_index = match mmap.get(index_offset..index_offset + Self::INDEX_MAGIC.len()) {
+            Some(magic) if magic == Self::INDEX_MAGIC => {
+                let count = read_u64(index_offset + 8).ok_or_else(malformed)?;
+                TaxoData::mapped(&mmap, index_offset + 16, count).ok_or_else(malformed)?
+            }
+            _ => Self::external_index_of(&nodes).into(),
+        };
+
+        Ok(Taxonomy {
+            path_cache: HashMap::new(),
+            nodes,
+            name_data,
+            rank_data,
+            external_to_internal_id_map: HashMap::new(),
+            external_index,
+        })
+    }
+
+    /// 按 external id 排序的 (external id, internal id)
+    fn external_index_of(nodes: &[TaxonomyNode]) -> Vec<[u64; 2]> {
+        let mut index: Vec<[u64; 2]> = nodes
+            .iter()
+            .enumerate()
+            .map(|(internal_id, node)| [node.external_id, internal_id as u64])
+            .collect();
+        index.sort_unstable();
+        index
+    }
+
     pub fn _is_a_ancestor_of_b(&self, a: u32, b: u32) -> bool {
         if a == 0 || b == 0 {
             return false;
@@ -327,6 +655,11 @@ impl Taxonomy {
             return false;
         }
 
+        // open_mmap 时没有 path_cache
+        if self.path_cache.is_empty() {
+            return self._is_a_ancestor_of_b(a, b);
+        }
+
         // 尝试从path_cache中获取b的祖先路径
         if let Some(path) = self.path_cache.get(&b) {
             // 检查路径中是否包含a
@@ -342,6 +675,10 @@ impl Taxonomy {
             return if a != 0 { a } else { b };
         }
 
+        if self.path_cache.is_empty() {
+            return self.lowest_common_ancestor(a, b);
+        }
+
         let default: Vec<u32> = vec![0];
         let path_a = self.path_cache.get(&a).unwrap_or(&default);
         let path_b = self.path_cache.get(&b).unwrap_or(&default);
@@ -415,12 +752,57 @@ impl Taxonomy {
         }
     }
 
+    /// 根据 internal id 获取物种名称
+    pub fn name(&self, internal_id: u32) -> &str {
+        self.nodes
+            .get(internal_id as usize)
+            .and_then(|node| {
+                let data = self.name_data.get(node.name_offset as usize..)?;
+                let end = data.iter().position(|&c| c == b'\0').unwrap_or(data.len());
+                std::str::from_utf8(&data[..end]).ok()
+            })
+            .unwrap_or("")
+    }
+
+    /// 根据 internal id 获取分类层级, 例如 "genus"
+    pub fn rank(&self, internal_id: u32) -> &str {
+        self.nodes
+            .get(internal_id as usize)
+            .and_then(|node| {
+                let data = self.rank_data.get(node.rank_offset as usize..)?;
+                let end = data.iter().position(|&c| c == b'\0').unwrap_or(data.len());
+                std::str::from_utf8(&data[..end]).ok()
+            })
+            .unwrap_or("")
+    }
+
+    /// 从 root 到 `internal_id` (含) 的 internal id
+    pub fn lineage(&self, internal_id: u32) -> Vec<u32> {
+        let mut path = Vec::new();
+        let mut node = internal_id;
+        while node != 0 && !path.contains(&node) {
+            path.push(node);
+            node = self
+                .nodes
+                .get(node as usize)
+                .map_or(0, |n| n.parent_id as u32);
+        }
+        path.reverse();
+        path
+    }
+
     pub fn node_count(&self) -> usize {
         self.nodes.len()
     }
 
     // get_internal_id 函数的优化
     pub fn get_internal_id(&self, external_id: u64) -> u32 {
+        if !self.external_index.is_empty() {
+            return self
+                .external_index
+                .binary_search_by_key(&external_id, |entry| entry[0])
+                .map_or(0, |i| self.external_index[i][1] as u32);
+        }
         *self
             .external_to_internal_id_map
             .get(&external_id)
@@ -438,7 +820,7 @@ impl Taxonomy {
     }
 
     pub fn write_to_disk<P: AsRef<Path>>(&self, filename: P) -> Result<()> {
-        let mut file = File::create(filename)?;
+        let mut file = BufWriter::new(File::create(filename)?);
 
         // Write file magic
         file.write_all(Taxonomy::MAGIC)?;
@@ -452,7 +834,7 @@ impl Taxonomy {
         file.write_all(&rank_data_len.to_le_bytes())?;
 
         // Write nodes as binary data
-        for node in &self.nodes {
+        for node in self.nodes.iter() {
             file.write_all(&node.parent_id.to_le_bytes())?;
             file.write_all(&node.first_child.to_le_bytes())?;
             file.write_all(&node.child_count.to_le_bytes())?;
@@ -466,6 +848,18 @@ impl Taxonomy {
         file.write_all(&self.name_data)?;
         file.write_all(&self.rank_data)?;
 
+        // Write the external id index, 8-byte aligned for open_mmap
+        let end = 32 + self.nodes.len() * 56 + self.name_data.len() + self.rank_data.len();
+        file.write_all(&vec![0u8; end.next_multiple_of(8) - end])?;
+        let index = Self::external_index_of(&self.nodes);
+        file.write_all(Self::INDEX_MAGIC)?;
+        file.write_all(&(index.len() as u64).to_le_bytes())?;
+        for [external_id, internal_id] in index {
+            file.write_all(&external_id.to_le_bytes())?;
+            file.write_all(&internal_id.to_le_bytes())?;
+        }
+        file.flush()?;
+
         Ok(())
     }
 }
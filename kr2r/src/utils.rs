@@ -1,18 +1,27 @@
-use std::collections::{BTreeMap as Map, HashMap};
+use crate::args::OutputCompression;
+use crate::error::{self, Kr2rError};
+use flate2::write::GzEncoder;
+use noodles::bgzf;
+use seqkmer::QualityEncoding;
+use std::collections::HashMap;
 use std::fs::{self, create_dir_all, File, OpenOptions};
-use std::io::{self, BufRead, BufReader, BufWriter, Result};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Result, Seek, SeekFrom, Write};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::sync_channel;
+use tracing::warn;
 use walkdir::WalkDir;
 
 /// 读取 seqid2taxid.map 文件。为了裁剪 ncbi 的 taxonomy 树
-pub fn read_id_to_taxon_map<P: AsRef<Path>>(filename: P) -> Result<HashMap<String, u64>> {
-    let file = open_file(filename)?;
+pub fn read_id_to_taxon_map<P: AsRef<Path>>(filename: P) -> error::Result<HashMap<String, u64>> {
+    let filename = filename.as_ref();
+    let file = File::open(filename).map_err(|e| Kr2rError::index(filename, e))?;
     let reader = BufReader::new(file);
     let mut id_map = HashMap::new();
 
     for line in reader.lines() {
-        let line = line?;
-        let parts: Vec<&str> = line.trim().split_whitespace().collect();
+        let line = line.map_err(|e| Kr2rError::index(filename, e))?;
+        let parts: Vec<&str> = line.split_whitespace().collect();
         if parts.len() < 2 {
             continue;
         }
@@ -32,7 +41,7 @@ pub fn read_id_to_taxon_map<P: AsRef<Path>>(filename: P) -> Result<HashMap<Strin
 /// Basic usage:
 ///
 /// ```
-/// # use kr2r::utils::expand_spaced_seed_mask; // Replace with the appropriate crate name
+/// # use kraken2_rs::utils::expand_spaced_seed_mask;
 /// // Expanding 0b1010 (binary for 10) with a factor of 2
 /// assert_eq!(expand_spaced_seed_mask(0b1010, 2), 204);
 ///
@@ -43,7 +52,7 @@ pub fn read_id_to_taxon_map<P: AsRef<Path>>(filename: P) -> Result<HashMap<Strin
 /// When the bit expansion factor is zero or greater than 64:
 ///
 /// ```
-/// # use kr2r::utils::expand_spaced_seed_mask;
+/// # use kraken2_rs::utils::expand_spaced_seed_mask;
 /// // No expansion, factor is 0
 /// assert_eq!(expand_spaced_seed_mask(0b1010, 0), 0b1010);
 ///
@@ -103,11 +112,62 @@ pub fn format_bytes(size: f64) -> String {
     format!("{:.2}{}", size, current_suffix)
 }
 
+/// 当前进程的常驻内存 (RSS) 字节数, 读取 /proc/self/statm, 非 Linux 系统返回 None
+pub fn resident_memory() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        let statm = fs::read_to_string("/proc/self/statm").ok()?;
+        let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        Some(pages * page_size.max(0) as usize)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// 最后一级 CPU 缓存的字节数, 读取 /sys/devices/system/cpu/cpu0/cache, 非 Linux 系统返回 None
+pub fn last_level_cache_size() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        let entries = fs::read_dir("/sys/devices/system/cpu/cpu0/cache").ok()?;
+        entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let level: u32 = fs::read_to_string(path.join("level"))
+                    .ok()?
+                    .trim()
+                    .parse()
+                    .ok()?;
+                // 大小的格式为 "32K", "2048K" 或 "30M"
+                let size = fs::read_to_string(path.join("size")).ok()?;
+                let size = size.trim();
+                let num = size.trim_end_matches(char::is_alphabetic);
+                let unit = &size[num.len()..];
+                let bytes = num.parse::<usize>().ok()?
+                    * match unit {
+                        "K" => 1 << 10,
+                        "M" => 1 << 20,
+                        "G" => 1 << 30,
+                        _ => 1,
+                    };
+                Some((level, bytes))
+            })
+            .max()
+            .map(|(_, bytes)| bytes)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
 #[cfg(unix)]
 extern crate libc;
 
 #[cfg(unix)]
-use libc::{getrlimit, rlimit, setrlimit, RLIMIT_NOFILE};
+use libc::{getrlimit, rlimit, setrlimit, RLIMIT_MEMLOCK, RLIMIT_NOFILE, RLIM_INFINITY};
 
 #[cfg(unix)]
 pub fn get_file_limit() -> usize {
@@ -124,7 +184,7 @@ pub fn get_file_limit() -> usize {
         limits.rlim_cur as usize
     } else {
         // 如果失败，输出错误并可能返回一个默认值或panic
-        eprintln!("Failed to get file limit");
+        warn!("Failed to get file limit");
         0
     }
 }
@@ -143,238 +203,369 @@ pub fn set_fd_limit(new_limit: u64) -> io::Result<()> {
     Ok(())
 }
 
-#[cfg(windows)]
+/// RLIMIT_MEMLOCK (`ulimit -l`) 的软限制, 没有限制时为 None
+#[cfg(unix)]
+pub fn memlock_limit() -> Option<usize> {
+    let mut limits = rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    let result = unsafe { getrlimit(RLIMIT_MEMLOCK, &mut limits) };
+    if result != 0 || limits.rlim_cur == RLIM_INFINITY {
+        return None;
+    }
+    Some(limits.rlim_cur as usize)
+}
+
+#[cfg(not(unix))]
+pub fn memlock_limit() -> Option<usize> {
+    None
+}
+
+/// 锁定 `requested` 字节失败时的说明: 当前的 RLIMIT_MEMLOCK 以及如何提高
+pub fn memlock_diagnostic(requested: usize) -> String {
+    let limit = match memlock_limit() {
+        Some(limit) => format!(
+            "RLIMIT_MEMLOCK (`ulimit -l`) is {}",
+            format_bytes(limit as f64)
+        ),
+        None => "RLIMIT_MEMLOCK (`ulimit -l`) is unlimited".to_string(),
+    };
+    format!(
+        "{}, {} must be locked; raise the limit with `ulimit -l unlimited`, a memlock entry in /etc/security/limits.conf or LimitMEMLOCK= for a systemd service, or grant CAP_IPC_LOCK",
+        limit,
+        format_bytes(requested as f64)
+    )
+}
+
+#[cfg(not(unix))]
 pub fn get_file_limit() -> usize {
     8192
 }
 
-#[cfg(windows)]
-pub fn set_fd_limit(new_limit: u64) -> io::Result<()> {
+#[cfg(not(unix))]
+pub fn set_fd_limit(_new_limit: u64) -> io::Result<()> {
     Ok(())
 }
 
-pub fn create_partition_files(partition: usize, base_path: &PathBuf, prefix: &str) -> Vec<PathBuf> {
-    create_dir_all(&base_path).expect(&format!("create dir error {:?}", base_path));
+/// `--low-power` 时进程的 nice 值
+pub const LOW_POWER_NICE: i32 = 10;
+
+/// `--low-power` 时最多使用的线程数: CPU 数的四分之一, 至少 3 个 (读取, 处理与汇总各一个)
+pub fn low_power_threads(threads: usize) -> usize {
+    threads.min((num_cpus::get() / 4).max(3))
+}
+
+/// 把调用线程 (以及之后创建的线程) 的 nice 值提高到 `nice`, 已经更低优先级时不变
+#[cfg(unix)]
+pub fn lower_priority(nice: i32) -> io::Result<()> {
+    // 出错时 getpriority 返回 -1, 此时直接尝试设置
+    let current = unsafe { libc::getpriority(libc::PRIO_PROCESS as _, 0) };
+    if current >= nice {
+        return Ok(());
+    }
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn lower_priority(_nice: i32) -> io::Result<()> {
+    Ok(())
+}
+
+pub fn create_partition_files(
+    partition: usize,
+    base_path: &PathBuf,
+    prefix: &str,
+) -> Result<Vec<PathBuf>> {
+    create_dir_all(base_path).map_err(|e| {
+        io::Error::new(e.kind(), format!("create dir error {:?}: {}", base_path, e))
+    })?;
     let file_path = base_path.clone();
-    (1..=partition)
-        .into_iter()
+    Ok((1..=partition)
         .map(|item| file_path.join(format!("{}_{}.k2", prefix, item)))
-        .collect()
+        .collect())
 }
 
-pub fn create_partition_writers(partition_files: &Vec<PathBuf>) -> Vec<BufWriter<File>> {
+pub fn create_partition_writers(partition_files: &[PathBuf]) -> Result<Vec<BufWriter<File>>> {
     partition_files
-        .into_iter()
+        .iter()
         .map(|item| {
             // 尝试创建文件，如果失败则直接返回错误
             let file = OpenOptions::new()
-                .write(true)
                 .append(true) // 确保以追加模式打开文件
                 .create(true) // 如果文件不存在，则创建
-                .open(item)
-                .unwrap();
-            BufWriter::new(file)
+                .open(item)?;
+            Ok(BufWriter::new(file))
         })
         .collect()
 }
 
-pub fn create_sample_file<P: AsRef<Path>>(filename: P) -> BufWriter<File> {
+/// 解析 `--quality-encoding`, 自动检测到的编码不是 Phred+33 时提示用户
+pub fn resolve_quality_encoding(
+    encoding: QualityEncoding,
+    file: &str,
+    file_index: usize,
+) -> error::Result<QualityEncoding> {
+    let resolved = encoding
+        .resolve(file)
+        .map_err(|e| Kr2rError::input(file, e))?;
+    if encoding == QualityEncoding::Auto && resolved != QualityEncoding::Phred33 {
+        warn!(
+            "file {} ({}) uses {} quality encoding, quality scores are converted accordingly",
+            file_index, file, resolved
+        );
+    }
+    Ok(resolved)
+}
+
+pub fn create_sample_file<P: AsRef<Path>>(filename: P) -> Result<BufWriter<File>> {
     let file = OpenOptions::new()
-        .write(true)
         .append(true) // 确保以追加模式打开文件
         .create(true) // 如果文件不存在，则创建
-        .open(filename)
-        .unwrap();
-    BufWriter::new(file)
+        .open(filename)?;
+    Ok(BufWriter::new(file))
 }
 
-use regex::Regex;
-
-pub fn find_and_trans_bin_files(
-    directory: &Path,
-    prefix: &str,
-    suffix: &str,
-    check: bool,
-) -> io::Result<Map<usize, Vec<PathBuf>>> {
-    // 改为聚合相同数字的文件路径
-    // 构建正则表达式以匹配文件名中的第一个数字
-    let pattern = format!(r"{}_(\d+)_\d+{}", prefix, suffix);
-    let re = Regex::new(&pattern).expect("Invalid regex pattern");
-
-    // 读取指定目录下的所有条目
-    let mut map_entries = Map::new();
-    for entry in fs::read_dir(directory)? {
-        let path = entry?.path();
-
-        if path.is_file() {
-            if let Some(file_name) = path.file_name().and_then(|name| name.to_str()) {
-                // 使用正则表达式匹配文件名，并提取第一个数字部分
-                if let Some(cap) = re.captures(file_name) {
-                    if let Some(m) = cap.get(1) {
-                        if let Ok(num) = m.as_str().parse::<usize>() {
-                            map_entries.entry(num).or_insert_with(Vec::new).push(path);
-                        }
-                    }
-                }
-            }
+pub fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    File::open(&path).map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            io::Error::new(e.kind(), format!("File not found: {:?}", path.as_ref()))
+        } else {
+            e
         }
-    }
+    })
+}
 
-    if check {
-        // 检查数字是否从1开始连续
-        let mut keys: Vec<_> = map_entries.keys().cloned().collect();
-        keys.sort_unstable();
-        for (i, &key) in keys.iter().enumerate() {
-            if i + 1 != key {
-                return Err(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "File numbers are not continuous starting from 1.",
-                ));
-            }
+/// 按 `compression` 创建输出文件, 压缩流在 writer drop 时结束
+///
+/// ```
+/// use kraken2_rs::args::OutputCompression;
+/// use kraken2_rs::utils::create_output_writer;
+/// use std::io::{Read, Write};
+///
+/// let path = std::env::temp_dir().join("create_output_writer_doctest.txt.zst");
+/// let mut writer = create_output_writer(&path, OutputCompression::Zstd).unwrap();
+/// writer.write_all(b"C\tread1\t9606\n").unwrap();
+/// drop(writer);
+///
+/// let mut text = String::new();
+/// seqkmer::dyn_reader(&path).unwrap().read_to_string(&mut text).unwrap();
+/// assert_eq!(text, "C\tread1\t9606\n");
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn create_output_writer<P: AsRef<Path>>(
+    path: P,
+    compression: OutputCompression,
+) -> Result<Box<dyn Write + Send>> {
+    let file = File::create(path)?;
+    let writer: Box<dyn Write + Send> = match compression {
+        OutputCompression::None => Box::new(BufWriter::new(file)),
+        OutputCompression::Gzip => Box::new(BufWriter::new(GzEncoder::new(
+            file,
+            flate2::Compression::default(),
+        ))),
+        #[cfg(not(target_arch = "wasm32"))]
+        OutputCompression::Zstd => Box::new(BufWriter::new(
+            zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?.auto_finish(),
+        )),
+        #[cfg(target_arch = "wasm32")]
+        OutputCompression::Zstd => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "zstd compressed outputs are not supported in wasm32 builds",
+            ))
         }
-    }
+        OutputCompression::Bgzf => Box::new(BufWriter::new(bgzf::io::Writer::new(file))),
+    };
+    Ok(writer)
+}
+
+/// 文件序号计数器, 与 sample_file.map 在同一目录, 内容为已分配的最大序号
+pub const FILE_INDEX_COUNTER: &str = "sample_file.index";
 
-    // 返回聚合后的文件路径
-    Ok(map_entries)
+/// sample_file.map 中最大的文件序号, 文件不存在或为空时为 0
+fn max_file_index(file_path: &Path) -> Result<usize> {
+    let file_content = match fs::read_to_string(file_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    Ok(file_content
+        .lines()
+        .filter_map(|line| line.split('\t').next())
+        .filter_map(|num_str| num_str.parse::<usize>().ok())
+        .max()
+        .unwrap_or(0))
 }
 
-pub fn find_and_trans_files(
-    directory: &Path,
-    prefix: &str,
-    suffix: &str,
-    check: bool,
-) -> io::Result<Map<usize, PathBuf>> {
-    // 构建正则表达式以匹配文件名中的数字
-    let pattern = format!(r"{}_(\d+){}", prefix, suffix);
-    let re = Regex::new(&pattern).unwrap();
-
-    // 读取指定目录下的所有条目
-    let entries = fs::read_dir(directory)?
-        .filter_map(Result::ok)
-        .map(|entry| entry.path())
-        .filter(|path| {
-            path.is_file()
-                && path
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .map_or(false, |s| s.starts_with(prefix) && s.ends_with(suffix))
-        })
-        .collect::<Vec<PathBuf>>();
-
-    // 使用正则表达式提取数字，并将它们存入BTreeMap
-    let mut map_entries = Map::new();
-    for path in entries {
-        if let Some(fname) = path.file_name().and_then(|name| name.to_str()) {
-            if let Some(cap) = re.captures(fname) {
-                if let Some(m) = cap.get(1) {
-                    if let Ok(num) = m.as_str().parse::<usize>() {
-                        map_entries.insert(num, path);
-                    }
-                }
+/// 在计数器文件的排他锁下用 `update` 更新已分配的最大序号, 返回更新前的值.
+/// 计数器不存在或内容无效 (例如写入时中断) 时从 sample_file.map 中的最大序号开始
+fn update_file_index<F>(file_path: &Path, update: F) -> Result<usize>
+where
+    F: FnOnce(usize) -> Result<usize>,
+{
+    let mut counter = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(file_path.with_file_name(FILE_INDEX_COUNTER))?;
+    // 锁在 counter 关闭时释放
+    counter.lock()?;
+    let mut content = String::new();
+    counter.read_to_string(&mut content)?;
+    let current = match content.trim().parse::<usize>() {
+        Ok(index) => index,
+        Err(_) => max_file_index(file_path)?,
+    };
+    let next = update(current)?;
+    counter.set_len(0)?;
+    counter.seek(SeekFrom::Start(0))?;
+    writeln!(counter, "{}", next)?;
+    counter.sync_data()?;
+    Ok(current)
+}
+
+/// 为 `file_path` (sample_file.map) 分配 `count` 个连续的文件序号, 返回第一个.
+///
+/// 序号由同一目录中的 [`FILE_INDEX_COUNTER`] 在文件锁下分配, 多个进程 (例如同时向一个 chunk 目录运行的 splitr)
+/// 不会得到重叠的序号
+pub fn reserve_file_indexes(file_path: &Path, count: usize) -> Result<usize> {
+    Ok(update_file_index(file_path, |current| Ok(current + count))? + 1)
+}
+
+/// 把计数器恢复为 sample_file.map 中的最大序号, 用于截断 sample_file.map 之后 (例如 splitr 从断点恢复)
+pub fn reset_file_index(file_path: &Path) -> Result<()> {
+    update_file_index(file_path, |_| max_file_index(file_path)).map(|_| ())
+}
+
+/// 读取 sample_file.map, 每行为 `序号\t文件1[,文件2]`, `--sample-regex` 分出的样本另有第三列样本名称
+pub fn read_sample_file_map<P: AsRef<Path>>(filename: P) -> Result<Vec<(usize, Vec<String>)>> {
+    let reader = BufReader::new(open_file(filename)?);
+    let mut samples = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if let Some((index, files)) = line.trim().split_once('\t') {
+            if let Ok(index) = index.parse::<usize>() {
+                let files = files.split('\t').next().unwrap_or_default();
+                let files = files.split(',').map(|f| f.to_string()).collect();
+                samples.push((index, files));
             }
         }
     }
+    Ok(samples)
+}
 
-    if check {
-        // 检查数字是否从0开始连续
-        let mut keys: Vec<_> = map_entries.keys().cloned().collect();
-        keys.sort_unstable();
-        for (i, key) in keys.iter().enumerate() {
-            if i + 1 != *key {
-                return Err(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "File numbers are not continuous starting from 1.",
-                ));
+/// sample_file.map 第三列的样本名称 (`--sample-sheet`), 没有名称的样本不在结果中
+pub fn read_sample_names<P: AsRef<Path>>(filename: P) -> Result<HashMap<usize, String>> {
+    let reader = BufReader::new(open_file(filename)?);
+    let mut names = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let mut columns = line.trim().split('\t');
+        if let (Some(index), Some(_), Some(name)) = (columns.next(), columns.next(), columns.next())
+        {
+            if let Ok(index) = index.parse::<usize>() {
+                names.insert(index, name.to_string());
             }
         }
     }
-
-    // 返回排序后的文件路径
-    Ok(map_entries)
+    Ok(names)
 }
 
-// 函数定义
-pub fn find_and_sort_files(
-    directory: &Path,
-    prefix: &str,
-    suffix: &str,
-    check: bool,
-) -> io::Result<Vec<PathBuf>> {
-    // 构建正则表达式以匹配文件名中的数字
-    let pattern = format!(r"{}_(\d+){}", prefix, suffix);
-    let re = Regex::new(&pattern).unwrap();
-
-    // 读取指定目录下的所有条目
-    let entries = fs::read_dir(directory)?
-        .filter_map(Result::ok)
-        .map(|entry| entry.path())
-        .filter(|path| {
-            path.is_file()
-                && path
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .map_or(false, |s| s.starts_with(prefix) && s.ends_with(suffix))
-        })
-        .collect::<Vec<PathBuf>>();
-
-    // 使用正则表达式提取数字并排序
-    let mut sorted_entries = entries
-        .into_iter()
-        .filter_map(|path| {
-            re.captures(path.file_name()?.to_str()?)
-                .and_then(|caps| caps.get(1).map(|m| m.as_str().parse::<usize>().ok()))
-                .flatten()
-                .map(|num| (path, num))
-        })
-        .collect::<Vec<(PathBuf, usize)>>();
-
-    sorted_entries.sort_by_key(|k| k.1);
-
-    if check {
-        // 检查数字是否从0开始连续
-        for (i, (_, num)) in sorted_entries.iter().enumerate() {
-            let a_idx = i + 1;
-            if a_idx != *num {
-                return Err(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "File numbers are not continuous starting from 1.",
-                ));
+/// sample_metadata.map 中各样本的元数据 (样本表中文件列以外的列), 每行为 `样本编号<TAB>列名<TAB>值`.
+/// 文件不存在时 (样本表没有元数据) 返回空
+pub fn read_sample_metadata<P: AsRef<Path>>(
+    filename: P,
+) -> Result<HashMap<usize, Vec<(String, String)>>> {
+    let mut metadata: HashMap<usize, Vec<(String, String)>> = HashMap::new();
+    let file = match File::open(filename) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(metadata),
+        Err(e) => return Err(e),
+    };
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let mut columns = line.splitn(3, '\t');
+        if let (Some(index), Some(key), Some(value)) =
+            (columns.next(), columns.next(), columns.next())
+        {
+            if let Ok(index) = index.parse::<usize>() {
+                metadata
+                    .entry(index)
+                    .or_default()
+                    .push((key.to_string(), value.to_string()));
             }
         }
     }
-
-    // 返回排序后的文件路径
-    Ok(sorted_entries
-        .iter()
-        .map(|(path, _)| path.clone())
-        .collect())
+    Ok(metadata)
 }
 
-pub fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
-    File::open(&path).map_err(|e| {
-        if e.kind() == io::ErrorKind::NotFound {
-            io::Error::new(e.kind(), format!("File not found: {:?}", path.as_ref()))
-        } else {
-            e
+/// 依次读取并处理 `paths` 中的文件, 读取在后台线程中进行: 处理当前文件时下一个文件已在读取 (双缓冲),
+/// I/O 与计算重叠. 内存中最多同时有两个文件的内容.
+///
+/// `process` 返回 `ControlFlow::Break` 时停止, 不再读取之后的文件
+///
+/// ```
+/// use kraken2_rs::utils::read_ahead;
+/// use std::ops::ControlFlow;
+///
+/// let mut loaded = Vec::new();
+/// read_ahead(
+///     &["a", "b", "c"],
+///     |path| Ok(path.to_string_lossy().to_uppercase()),
+///     |name| {
+///         loaded.push(name);
+///         Ok(ControlFlow::Continue(()))
+///     },
+/// )
+/// .unwrap();
+/// assert_eq!(loaded, ["A", "B", "C"]);
+/// ```
+pub fn read_ahead<P, T, L, F>(paths: &[P], load: L, mut process: F) -> Result<()>
+where
+    P: AsRef<Path> + Sync,
+    T: Send,
+    L: Fn(&Path) -> Result<T> + Sync,
+    F: FnMut(T) -> Result<ControlFlow<()>>,
+{
+    std::thread::scope(|scope| {
+        // 容量为 0: 后台线程读完下一个文件后等待, 直到当前文件处理完
+        let (sender, receiver) = sync_channel(0);
+        let load = &load;
+        scope.spawn(move || {
+            for path in paths {
+                let loaded = load(path.as_ref());
+                let failed = loaded.is_err();
+                if sender.send(loaded).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        // 提前返回时 receiver 被丢弃, 后台线程的 send 失败后退出
+        for loaded in receiver {
+            if process(loaded?)?.is_break() {
+                break;
+            }
         }
+        Ok(())
     })
 }
 
-/// 获取最新的文件序号
-pub fn get_lastest_file_index(file_path: &PathBuf) -> Result<usize> {
-    let file_content = fs::read_to_string(&file_path)?;
-    // 如果文件内容为空，则默认最大值为0
-    let index = if file_content.is_empty() {
-        0
-    } else {
-        file_content
-            .lines() // 将内容按行分割
-            .filter_map(|line| line.split('\t').next()) // 获取每行的第一列
-            .filter_map(|num_str| num_str.parse::<usize>().ok()) // 尝试将第一列的字符串转换为整型
-            .max() // 找到最大值
-            .unwrap_or(1)
-    };
-    Ok(index)
+/// 与 [`read_ahead`] 相同, 但在当前线程中读完一个文件再处理, 没有后台读取 (`--low-power`)
+pub fn read_in_order<P, T, L, F>(paths: &[P], load: L, mut process: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    L: Fn(&Path) -> Result<T>,
+    F: FnMut(T) -> Result<ControlFlow<()>>,
+{
+    for path in paths {
+        if process(load(path.as_ref())?)?.is_break() {
+            break;
+        }
+    }
+    Ok(())
 }
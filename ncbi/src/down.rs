@@ -2,7 +2,7 @@ use crate::client::retry_client;
 use anyhow::Result;
 use futures_util::StreamExt;
 use reqwest::{self, header, StatusCode};
-use std::path::PathBuf;
+use std::path::Path;
 use std::str::FromStr;
 use tokio::fs::{self, OpenOptions};
 use tokio::io::AsyncWriteExt;
@@ -28,7 +28,7 @@ fn get_content_length(response: &reqwest::Response) -> u64 {
 }
 
 /// 断点续传
-async fn download_resume(url: &str, file_name: &PathBuf, etag: &str) -> Result<String> {
+async fn download_resume(url: &str, file_name: &Path, etag: &str) -> Result<String> {
     let client = retry_client();
 
     // 检查本地文件大小
@@ -43,7 +43,7 @@ async fn download_resume(url: &str, file_name: &PathBuf, etag: &str) -> Result<S
     let content_length = get_content_length(&head_resp);
 
     // 如果文件下载完成，并且 etag 没有更新，则不下载
-    if file_size == content_length && latest_etag != "" && latest_etag == etag {
+    if file_size == content_length && !latest_etag.is_empty() && latest_etag == etag {
         return Ok(etag.into());
     }
 
@@ -76,12 +76,7 @@ async fn download_resume(url: &str, file_name: &PathBuf, etag: &str) -> Result<S
 }
 
 /// 下载文件
-pub async fn retry_download(
-    url: &str,
-    file_name: &PathBuf,
-    etag: &str,
-    retry: i32,
-) -> Result<String> {
+pub async fn retry_download(url: &str, file_name: &Path, etag: &str, retry: i32) -> Result<String> {
     let mut count = retry;
     let mut err = anyhow::anyhow!("download failed");
     while count > 0 {
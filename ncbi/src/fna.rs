@@ -2,7 +2,7 @@ use async_compression::tokio::bufread::GzipDecoder;
 use regex::Regex;
 use std::collections::HashMap;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use tokio::fs::OpenOptions;
 use tokio::{
     fs::File,
@@ -13,8 +13,8 @@ use anyhow::Result;
 use tar::Archive;
 
 pub async fn decompress_and_extract_tar_gz(
-    gz_path: &PathBuf,
-    out_path: &PathBuf,
+    gz_path: &Path,
+    out_path: &Path,
     files_to_extract: Vec<String>,
 ) -> std::io::Result<()> {
     // Open the .tar.gz file
@@ -80,7 +80,7 @@ async fn delete_hllp_json_files(out_dir: &Path) -> Result<()> {
 
 pub async fn parse_assembly_fna(
     site: &str,
-    data_dir: &PathBuf,
+    data_dir: &Path,
     asm_levels: &Vec<&str>,
 ) -> Result<HashMap<String, String>> {
     let mut gz_files: HashMap<String, String> = HashMap::new();
@@ -110,7 +110,7 @@ pub async fn parse_assembly_fna(
             let fna_file_name = format!(
                 "{}/{}_genomic.fna.gz",
                 site,
-                ftp_path.split('/').last().unwrap_or_default()
+                ftp_path.split('/').next_back().unwrap_or_default()
             );
             gz_files.insert(fna_file_name, taxid.into());
         }
@@ -122,8 +122,8 @@ pub async fn write_to_fna(
     site: &str,
     group: &str,
     asm_levels: &Vec<&str>,
-    data_dir: &PathBuf,
-    out_dir: &PathBuf,
+    data_dir: &Path,
+    out_dir: &Path,
 ) -> Result<()> {
     log::info!("{} {} write to fna...", group, site);
 
@@ -142,14 +142,16 @@ pub async fn write_to_fna(
         OpenOptions::new()
             .create(true)
             .write(true)
+            .truncate(true)
             .open(&library_fna_path)
             .await?,
     );
-    delete_hllp_json_files(&out_dir).await?;
+    delete_hllp_json_files(out_dir).await?;
     let mut map_writer = BufWriter::new(
         OpenOptions::new()
             .create(true)
             .write(true)
+            .truncate(true)
             .open(&prelim_map_path)
             .await?,
     );
@@ -5,12 +5,12 @@ use crate::meta::get_local_etag;
 use crate::meta::insert_local_etag;
 use anyhow::{anyhow, Result};
 use futures::Future;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::fs::{remove_file, File};
 use tokio::io::{AsyncBufReadExt, BufReader};
 
-pub const NCBI_GEN_URL: &'static str = "https://ftp.ncbi.nlm.nih.gov/genomes/";
-pub const NCBI_TAXO_URL: &'static str = "https://ftp.ncbi.nlm.nih.gov/pub/taxonomy";
+pub const NCBI_GEN_URL: &str = "https://ftp.ncbi.nlm.nih.gov/genomes/";
+pub const NCBI_TAXO_URL: &str = "https://ftp.ncbi.nlm.nih.gov/pub/taxonomy";
 
 #[derive(Debug)]
 pub struct DownTuple {
@@ -24,7 +24,7 @@ impl DownTuple {
         Self { url, file, etag }
     }
 
-    pub async fn new_taxo(url_path: String, taxo_dir: &PathBuf) -> Self {
+    pub async fn new_taxo(url_path: String, taxo_dir: &Path) -> Self {
         let taxdump_url = format!("{}/{}", NCBI_TAXO_URL, url_path);
         let file_name = if url_path.contains("/") {
             url_path.split("/").last().unwrap().to_string()
@@ -61,7 +61,7 @@ pub enum NcbiFile {
 }
 
 impl NcbiFile {
-    pub async fn from_group(group: &str, data_dir: &PathBuf, site: &str) -> Self {
+    pub async fn from_group(group: &str, data_dir: &Path, site: &str) -> Self {
         let url = format!("{}{}/{}/assembly_summary.txt", NCBI_GEN_URL, site, group);
         let output_path = data_dir.join(format!("assembly_summary_{}.txt", site));
 
@@ -73,11 +73,11 @@ impl NcbiFile {
         ))
     }
 
-    pub async fn from_file(site: &str, data_dir: &PathBuf, fna_url: &str) -> Self {
+    pub async fn from_file(site: &str, data_dir: &Path, fna_url: &str) -> Self {
         let fna_file_name = fna_url.split("/").last().unwrap_or_default();
         let ftp_path = fna_url.trim_end_matches(fna_file_name);
-        let fna_file = data_dir.join(site).join(&fna_file_name);
-        let fna_etag = get_local_etag(&fna_url).await.unwrap_or_default();
+        let fna_file = data_dir.join(site).join(fna_file_name);
+        let fna_etag = get_local_etag(fna_url).await.unwrap_or_default();
         let genomic_dt = DownTuple::new(fna_url.to_string(), fna_file, fna_etag);
         let md5_url = format!("{}/md5checksums.txt", ftp_path);
         let md5_file = data_dir
@@ -88,7 +88,7 @@ impl NcbiFile {
         NcbiFile::Genomic(genomic_dt, md5_dt)
     }
 
-    pub async fn new_taxo(taxo_dir: &PathBuf, url_path: &str) -> Self {
+    pub async fn new_taxo(taxo_dir: &Path, url_path: &str) -> Self {
         let taxo = DownTuple::new_taxo(url_path.to_string(), taxo_dir).await;
         let md5_file = format!("{}.md5", url_path);
         let taxo_md5 = DownTuple::new_taxo(md5_file, taxo_dir).await;
@@ -96,14 +96,14 @@ impl NcbiFile {
         NcbiFile::Taxonomy(taxo, taxo_md5)
     }
 
-    pub async fn decompress(&self, data_dir: &PathBuf) -> Result<()> {
+    pub async fn decompress(&self, data_dir: &Path) -> Result<()> {
         match self {
             NcbiFile::Summary(_) => {}
             NcbiFile::Genomic(_, _) => {}
             NcbiFile::Taxonomy(dt1, _) => {
                 let taxo_files: Vec<String> =
                     vec!["names.dmp".to_string(), "nodes.dmp".to_string()];
-                decompress_and_extract_tar_gz(&dt1.file, &data_dir, taxo_files).await?;
+                decompress_and_extract_tar_gz(&dt1.file, data_dir, taxo_files).await?;
             }
         }
         Ok(())
@@ -153,7 +153,7 @@ impl NcbiFile {
 
     pub async fn check(&self) -> Result<()> {
         match self {
-            NcbiFile::Summary(_) => return Err(anyhow!("不用校验")),
+            NcbiFile::Summary(_) => Err(anyhow!("不用校验")),
             NcbiFile::Genomic(dt1, dt2) => {
                 let result = check_md5sum_file(&dt1.file, &dt2.file).await?;
                 if !result {
@@ -186,7 +186,7 @@ impl NcbiFile {
     pub async fn process_summary_and_apply<F, Fut>(
         &self,
         site: &str,
-        data_dir: &PathBuf,
+        data_dir: &Path,
         asm_levels: &Vec<&str>,
         callback: F,
     ) -> Result<()>
@@ -223,7 +223,7 @@ impl NcbiFile {
 
                         let fna_file_name = format!(
                             "{}_genomic.fna.gz",
-                            ftp_path.split('/').last().unwrap_or_default()
+                            ftp_path.split('/').next_back().unwrap_or_default()
                         );
 
                         let fna_url = format!("{}/{}", ftp_path, fna_file_name);
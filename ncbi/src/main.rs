@@ -1,17 +1,15 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand, ValueEnum};
-use lazy_static::lazy_static;
 use ncbi_dl::fna::write_to_fna;
 use ncbi_dl::meta::{init_meta, save_meta};
 use ncbi_dl::plas::download_plas_files;
 use ncbi_dl::task;
-use ncbi_dl::utils;
-use std::collections::HashMap;
+use ncbi_dl::utils::{self, NCBI_ASM_LEVELS};
 use std::fmt;
 use std::path::PathBuf;
 use tokio::runtime::Builder;
 
-const NCBI_LIBRARY: &'static [&str] = &[
+const NCBI_LIBRARY: &[&str] = &[
     "archaea",
     "bacteria",
     "viral",
@@ -25,28 +23,11 @@ const NCBI_LIBRARY: &'static [&str] = &[
     "plasmid",
 ];
 
-lazy_static! {
-    static ref NCBI_ASM_LEVELS: HashMap<String, Vec<&'static str>> = {
-        let mut m = HashMap::new();
-        m.insert("complete_genome".to_string(), vec!["Complete Genome"]);
-        m.insert("chromosome".to_string(), vec!["Chromosome"]);
-        m.insert("scaffold".to_string(), vec!["Scaffold"]);
-        m.insert("contig".into(), vec!["Contig"]);
-        m.insert("basic".into(), vec!["Complete Genome", "Chromosome"]);
-        m.insert("uncomplete".into(), vec!["Scaffold", "Contig"]);
-        m.insert(
-            "all".into(),
-            vec!["Complete Genome", "Chromosome", "Scaffold", "Contig"],
-        );
-        m
-    };
-}
-
 fn validate_group(group: &str) -> Result<String, String> {
-    let groups = utils::parse_comma_separated_list(&group);
+    let groups = utils::parse_comma_separated_list(group);
     for grp in &groups {
         if !NCBI_LIBRARY.contains(&grp.as_str()) {
-            return Err(format!("group not in ncbi library"));
+            return Err("group not in ncbi library".to_string());
         }
     }
     Ok(group.to_string())
@@ -199,11 +180,11 @@ async fn async_run(args: Args) -> Result<()> {
                 match site {
                     Site::All => {
                         for s in [Site::Genbank, Site::Refseq].iter() {
-                            utils::create_dir(&data_dir.join(&s.to_string()))?;
+                            utils::create_dir(&data_dir.join(s.to_string()))?;
                         }
                     }
                     _ => {
-                        utils::create_dir(&data_dir.join(&site.to_string()))?;
+                        utils::create_dir(&data_dir.join(site.to_string()))?;
                     }
                 }
 
@@ -223,7 +204,7 @@ async fn async_run(args: Args) -> Result<()> {
                                     &site.to_string(),
                                     &trans_group,
                                     &data_dir,
-                                    &levels,
+                                    levels,
                                     args.num_threads,
                                 )
                                 .await;
@@ -234,7 +215,7 @@ async fn async_run(args: Args) -> Result<()> {
                                 &site.to_string(),
                                 &trans_group,
                                 &data_dir,
-                                &levels,
+                                levels,
                                 args.num_threads,
                             )
                             .await;
@@ -250,7 +231,7 @@ async fn async_run(args: Args) -> Result<()> {
                         write_to_fna(
                             &site.to_string(),
                             &trans_group,
-                            &levels,
+                            levels,
                             &data_dir,
                             &fna_out_dir,
                         )
@@ -262,7 +243,7 @@ async fn async_run(args: Args) -> Result<()> {
                                 let _ = task::run_assembly(
                                     &s.to_string(),
                                     &trans_group,
-                                    &levels,
+                                    levels,
                                     &data_dir,
                                 )
                                 .await;
@@ -272,7 +253,7 @@ async fn async_run(args: Args) -> Result<()> {
                             let _ = task::run_assembly(
                                 &site.to_string(),
                                 &trans_group,
-                                &levels,
+                                levels,
                                 &data_dir,
                             )
                             .await;
@@ -283,7 +264,7 @@ async fn async_run(args: Args) -> Result<()> {
                             log::error!("Must specify a suitable site");
                         } else {
                             let result =
-                                task::run_download_file(&site.to_string(), &data_dir, &url).await;
+                                task::run_download_file(&site.to_string(), &data_dir, url).await;
                             if result.is_err() {
                                 log::error!("download error... {:?}", result);
                             }
@@ -296,7 +277,7 @@ async fn async_run(args: Args) -> Result<()> {
                                     &s.to_string(),
                                     &trans_group,
                                     &data_dir,
-                                    &&levels,
+                                    levels,
                                     args.num_threads,
                                 )
                                 .await;
@@ -307,7 +288,7 @@ async fn async_run(args: Args) -> Result<()> {
                                 &site.to_string(),
                                 &trans_group,
                                 &data_dir,
-                                &&levels,
+                                levels,
                                 args.num_threads,
                             )
                             .await;
@@ -329,7 +310,7 @@ fn main() -> Result<()> {
         .init();
 
     let args = Args::parse();
-    let num_thread = args.num_threads.clone();
+    let num_thread = args.num_threads;
     // 创建一个 Runtime 实例，并配置线程数
     let runtime = Builder::new_multi_thread()
         .enable_all()
@@ -1,10 +1,10 @@
 use anyhow::{anyhow, Result};
 use md5::{Digest, Md5};
-use std::path::PathBuf;
+use std::path::Path;
 use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 
-async fn get_md5_for_file(target_file: &PathBuf, md5_file: &PathBuf) -> Result<String> {
+async fn get_md5_for_file(target_file: &Path, md5_file: &Path) -> Result<String> {
     let file = File::open(md5_file).await.map_err(|e| {
         tokio::io::Error::new(
             tokio::io::ErrorKind::NotFound,
@@ -37,7 +37,7 @@ async fn get_md5_for_file(target_file: &PathBuf, md5_file: &PathBuf) -> Result<S
     Err(anyhow!("MD5 checksum not found for file {}", file_name))
 }
 
-pub async fn check_md5sum_file(target_file: &PathBuf, md5_file: &PathBuf) -> Result<bool> {
+pub async fn check_md5sum_file(target_file: &Path, md5_file: &Path) -> Result<bool> {
     let md5_value = get_md5_for_file(target_file, md5_file).await?;
 
     // 异步读取目标文件并计算其 MD5 散列值
@@ -1,15 +1,15 @@
 use anyhow::Result;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::sync::Mutex;
 
-const META_FILE_NAME: &'static str = ".metadata";
+const META_FILE_NAME: &str = ".metadata";
 
-async fn parse_metadata(filename: &PathBuf) -> Result<HashMap<String, String>> {
+async fn parse_metadata(filename: &Path) -> Result<HashMap<String, String>> {
     let mut meta: HashMap<String, String> = HashMap::new();
     if !filename.exists() {
         File::create(filename).await?;
@@ -40,6 +40,12 @@ pub struct Meta {
     inner: HashMap<String, String>,
 }
 
+impl Default for Meta {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Meta {
     pub fn new() -> Self {
         Self {
@@ -47,8 +53,8 @@ impl Meta {
         }
     }
 
-    pub async fn init(&mut self, db_path: &PathBuf) {
-        let meta_file: PathBuf = db_path.clone().join(META_FILE_NAME);
+    pub async fn init(&mut self, db_path: &Path) {
+        let meta_file: PathBuf = db_path.join(META_FILE_NAME);
         if let Ok(meta) = parse_metadata(&meta_file).await {
             self.inner = meta;
         }
@@ -63,7 +69,7 @@ impl Meta {
     }
 }
 
-pub async fn init_meta(db_path: &PathBuf) {
+pub async fn init_meta(db_path: &Path) {
     let mut meta = META.lock().await;
     meta.init(db_path).await;
 }
@@ -78,7 +84,7 @@ pub async fn insert_local_etag(key: String, new_etag: String) {
     meta.insert_or_update(key, new_etag);
 }
 
-pub async fn save_meta(db_path: &PathBuf) -> Result<()> {
+pub async fn save_meta(db_path: &Path) -> Result<()> {
     let meta = META.lock().await;
     let meta_file: PathBuf = db_path.join(META_FILE_NAME);
     let mut file = File::create(meta_file).await?;
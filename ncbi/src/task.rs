@@ -1,7 +1,7 @@
 use crate::load::NcbiFile;
 use anyhow::Result;
 use futures::stream::StreamExt;
-use std::path::PathBuf;
+use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::sync::{mpsc, Semaphore};
@@ -67,7 +67,7 @@ async fn process_tasks(
 async fn process_assembly_tasks(
     site: &str,
     group: &str,
-    data_dir: &PathBuf,
+    data_dir: &Path,
     asm_levels: &Vec<&str>,
     tx: mpsc::Sender<(NcbiFile, tokio::sync::OwnedSemaphorePermit)>,
     semaphore: Arc<Semaphore>,
@@ -103,7 +103,7 @@ async fn process_assembly_tasks(
 pub async fn run_task(
     site: &str,
     group: &str,
-    data_dir: &PathBuf,
+    data_dir: &Path,
     asm_levels: &Vec<&str>,
     num_threads: usize,
 ) -> Result<()> {
@@ -131,7 +131,7 @@ pub async fn run_task(
 pub async fn run_check(
     site: &str,
     group: &str,
-    data_dir: &PathBuf,
+    data_dir: &Path,
     asm_levels: &Vec<&str>,
     num_threads: usize,
 ) -> Result<()> {
@@ -153,7 +153,7 @@ pub async fn run_check(
     Ok(())
 }
 
-pub async fn run_taxo(taxo_dir: &PathBuf) -> Result<()> {
+pub async fn run_taxo(taxo_dir: &Path) -> Result<()> {
     log::info!("download taxonomy...");
     let files = [
         "taxdump.tar.gz",
@@ -161,9 +161,9 @@ pub async fn run_taxo(taxo_dir: &PathBuf) -> Result<()> {
         // "accession2taxid/nucl_wgs.accession2taxid.gz",
     ];
     for url_path in files.iter() {
-        let ncbi_file = NcbiFile::new_taxo(taxo_dir, &url_path).await;
+        let ncbi_file = NcbiFile::new_taxo(taxo_dir, url_path).await;
         let result = ncbi_file.run().await;
-        if result.is_ok() && url_path.to_string() == "taxdump.tar.gz" {
+        if result.is_ok() && *url_path == "taxdump.tar.gz" {
             ncbi_file.decompress(taxo_dir).await?;
         }
     }
@@ -171,7 +171,7 @@ pub async fn run_taxo(taxo_dir: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-pub async fn run_download_file(site: &str, data_dir: &PathBuf, fna_url: &str) -> Result<()> {
+pub async fn run_download_file(site: &str, data_dir: &Path, fna_url: &str) -> Result<()> {
     let ncbi_file = NcbiFile::from_file(site, data_dir, fna_url).await;
     log::info!("{} download file start...", fna_url);
     ncbi_file.clear().await;
@@ -185,7 +185,7 @@ pub async fn run_assembly(
     site: &str,
     group: &str,
     asm_levels: &Vec<&str>,
-    data_dir: &PathBuf,
+    data_dir: &Path,
 ) -> Result<()> {
     let assembly = NcbiFile::from_group(group, data_dir, site).await;
     if !assembly.file_exists() {
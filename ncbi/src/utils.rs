@@ -1,11 +1,34 @@
 use anyhow::Result;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
 use std::fs::{create_dir_all, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+lazy_static! {
+    /// assembly level 参数对应 assembly_summary 中的 assembly_level
+    pub static ref NCBI_ASM_LEVELS: HashMap<String, Vec<&'static str>> = {
+        let mut m = HashMap::new();
+        m.insert("complete_genome".to_string(), vec!["Complete Genome"]);
+        m.insert("chromosome".to_string(), vec!["Chromosome"]);
+        m.insert("scaffold".to_string(), vec!["Scaffold"]);
+        m.insert("contig".into(), vec!["Contig"]);
+        m.insert("basic".into(), vec!["Complete Genome", "Chromosome"]);
+        m.insert("uncomplete".into(), vec!["Scaffold", "Contig"]);
+        m.insert(
+            "all".into(),
+            vec!["Complete Genome", "Chromosome", "Scaffold", "Contig"],
+        );
+        m
+    };
+}
+
 // 获取 url 地址的最后一层目录
 pub fn get_last_segment_of_url(url: &str) -> &str {
-    url.trim_end_matches('/').split('/').last().unwrap_or("")
+    url.trim_end_matches('/')
+        .split('/')
+        .next_back()
+        .unwrap_or("")
 }
 
 // 创建文件，并检查文件所在目录是否存在，不存在则创建
@@ -21,17 +44,17 @@ pub fn create_file_in_dir(filename: &str) -> Result<File> {
     Ok(file)
 }
 
-pub fn create_data_dir(dirname: &PathBuf) -> Result<PathBuf, anyhow::Error> {
+pub fn create_data_dir(dirname: &Path) -> Result<PathBuf, anyhow::Error> {
     // let path: PathBuf = PathBuf::from_str(dirname).unwrap();
     if !dirname.exists() {
-        create_dir_all(&dirname)?;
+        create_dir_all(dirname)?;
     }
-    Ok(dirname.clone())
+    Ok(dirname.to_path_buf())
 }
 
-pub fn create_dir(dirname: &PathBuf) -> Result<(), anyhow::Error> {
+pub fn create_dir(dirname: &Path) -> Result<(), anyhow::Error> {
     if !dirname.exists() {
-        create_dir_all(&dirname)?;
+        create_dir_all(dirname)?;
     }
     Ok(())
 }
@@ -0,0 +1,374 @@
+use crate::pairs::{pair_id, PairOptions, PairStats, PairSync};
+use crate::reader::{is_remote_path, is_stdin_path, open_file, trim_pair_info, Reader};
+use crate::record::SeqRecord;
+use crate::seq::{gc_count, Base, SeqFormat, SeqHeader};
+use crate::utils::OptionPair;
+use crossbeam_channel::{bounded, Receiver};
+use flate2::read::MultiGzDecoder;
+use noodles::sam::alignment::io::Read as AlignmentRead;
+use noodles::sam::alignment::record::Flags;
+use noodles::sam::alignment::Record;
+use noodles::{bam, cram, fasta, sam};
+use std::io::{self, Read, Result, Seek};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+/// 后台线程每次发送的 reads 数
+const RECORD_BATCH: usize = 1024;
+
+/// 比对文件格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignmentFormat {
+    Bam,
+    Cram,
+}
+
+impl AlignmentFormat {
+    /// 由文件开头判断是否为 BAM (BGZF 压缩的 "BAM\1") 或 CRAM, 其他文件返回 None
+    /// URL 输入 (见 [`is_remote_path`]) 与标准输入只支持 FASTA/FASTQ, 返回 None
+    pub fn detect<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        if is_remote_path(&path) || is_stdin_path(&path) {
+            return Ok(None);
+        }
+        let mut file = open_file(path)?;
+        let mut magic = [0u8; 4];
+        if file.read(&mut magic)? < magic.len() {
+            return Ok(None);
+        }
+        if &magic == b"CRAM" {
+            return Ok(Some(AlignmentFormat::Cram));
+        }
+        if magic[..2] != [0x1F, 0x8B] {
+            return Ok(None);
+        }
+        file.rewind()?;
+        let mut decoder = MultiGzDecoder::new(file);
+        let mut magic = [0u8; 4];
+        match decoder.read_exact(&mut magic) {
+            Ok(()) if &magic == b"BAM\x01" => Ok(Some(AlignmentFormat::Bam)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// BAM/CRAM 输入的读取设置
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AlignmentOptions {
+    /// 只读取未比对上的 reads (paired 时两个 mate 都未比对上), 用于去宿主
+    pub unmapped_only: bool,
+    /// CRAM 解码所需的参考序列 FASTA (需要 .fai 索引), 只含未比对 reads 的 CRAM 不需要
+    pub reference: Option<PathBuf>,
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' | b'U' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' | b'u' => b'a',
+        b'M' => b'K',
+        b'K' => b'M',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        base => base,
+    }
+}
+
+/// 转换为与 `samtools fastq` 相同的 read: 反向比对的 reads 恢复为测序方向,
+/// paired 时 header 追加 /1 或 /2. secondary/supplementary 记录以及被过滤的 reads 返回 None
+fn to_seq_record(
+    record: &dyn Record,
+    options: &AlignmentOptions,
+    paired: bool,
+) -> Result<Option<SeqRecord>> {
+    let flags = record.flags()?;
+    if flags.intersects(Flags::SECONDARY | Flags::SUPPLEMENTARY) {
+        return Ok(None);
+    }
+    if options.unmapped_only
+        && (!flags.is_unmapped() || (paired && flags.is_segmented() && !flags.is_mate_unmapped()))
+    {
+        return Ok(None);
+    }
+
+    let mut header = record
+        .name()
+        .map(|name| name.to_vec())
+        .unwrap_or_else(|| b"*".to_vec());
+    if paired && flags.is_segmented() {
+        if flags.is_first_segment() {
+            header.extend_from_slice(b"/1");
+        } else if flags.is_last_segment() {
+            header.extend_from_slice(b"/2");
+        }
+    }
+
+    let mut seq: Vec<u8> = record.sequence().iter().collect();
+    let mut qual = record
+        .quality_scores()
+        .iter()
+        .map(|score| score.map(|score| score.saturating_add(b'!')))
+        .collect::<Result<Vec<u8>>>()?;
+    if flags.is_reverse_complemented() {
+        seq.reverse();
+        seq.iter_mut().for_each(|base| *base = complement(*base));
+        qual.reverse();
+    }
+
+    Ok(Some(SeqRecord {
+        header,
+        seq,
+        qual: Some(qual).filter(|qual| !qual.is_empty()),
+    }))
+}
+
+fn send_records<R, A>(
+    mut reader: A,
+    header: sam::Header,
+    options: AlignmentOptions,
+    paired: bool,
+    sender: crossbeam_channel::Sender<Result<Vec<SeqRecord>>>,
+) where
+    A: AlignmentRead<R>,
+{
+    let mut batch = Vec::with_capacity(RECORD_BATCH);
+    for result in reader.alignment_records(&header) {
+        let record =
+            match result.and_then(|record| to_seq_record(record.as_ref(), &options, paired)) {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            };
+        batch.push(record);
+        if batch.len() >= RECORD_BATCH {
+            let batch = std::mem::replace(&mut batch, Vec::with_capacity(RECORD_BATCH));
+            // 接收端已关闭
+            if sender.send(Ok(batch)).is_err() {
+                return;
+            }
+        }
+    }
+    if !batch.is_empty() {
+        let _ = sender.send(Ok(batch));
+    }
+}
+
+/// 逐条读取 BAM/CRAM 中的 reads, 解码在后台线程中进行
+pub struct AlignmentRecords {
+    receiver: Receiver<Result<Vec<SeqRecord>>>,
+    batch: std::vec::IntoIter<SeqRecord>,
+    handle: Option<JoinHandle<()>>,
+    path: PathBuf,
+}
+
+impl AlignmentRecords {
+    /// `paired` 时 header 追加 /1 与 /2, `unmapped_only` 要求两个 mate 都未比对上
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        options: &AlignmentOptions,
+        paired: bool,
+    ) -> Result<Self> {
+        let format = AlignmentFormat::detect(&path)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} is not a BAM or CRAM file", path.as_ref()),
+            )
+        })?;
+        let (sender, receiver) = bounded(4);
+        let options = options.clone();
+        let handle = match format {
+            AlignmentFormat::Bam => {
+                let mut reader = bam::io::Reader::new(open_file(&path)?);
+                let header = reader.read_header()?;
+                thread::spawn(move || send_records(reader, header, options, paired, sender))
+            }
+            AlignmentFormat::Cram => {
+                let repository = options
+                    .reference
+                    .as_ref()
+                    .map(|src| fasta::io::indexed_reader::Builder::default().build_from_path(src))
+                    .transpose()?
+                    .map(fasta::repository::adapters::IndexedReader::new)
+                    .map(fasta::Repository::new)
+                    .unwrap_or_default();
+                let mut reader = cram::io::reader::Builder::default()
+                    .set_reference_sequence_repository(repository)
+                    .build_from_reader(open_file(&path)?);
+                let header = reader.read_header()?;
+                thread::spawn(move || send_records(reader, header, options, paired, sender))
+            }
+        };
+        Ok(Self {
+            receiver,
+            batch: Vec::new().into_iter(),
+            handle: Some(handle),
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<SeqRecord>> {
+        loop {
+            if let Some(record) = self.batch.next() {
+                return Ok(Some(record));
+            }
+            match self.receiver.recv() {
+                Ok(batch) => self.batch = batch?.into_iter(),
+                // 后台线程已结束, 解码出错时 noodles 可能 panic
+                Err(_) => {
+                    if let Some(Err(_)) = self.handle.take().map(JoinHandle::join) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "failed to decode {:?} (CRAM files with mapped reads need --cram-reference)",
+                                self.path
+                            ),
+                        ));
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// BAM/CRAM 的 reads 读取器, paired 时相邻的 mate 组成一对 (需要按 read 名称排列, 例如 `samtools collate`
+/// 的输出或比对软件直接输出的未排序 BAM)
+pub struct AlignmentReader {
+    records: AlignmentRecords,
+    file_index: usize,
+    reads_index: usize,
+    quality_score: i32,
+    batch_size: usize,
+    pairs: Option<PairSync<SeqRecord>>,
+}
+
+impl AlignmentReader {
+    /// `pairs` 为 None 时每条记录作为单端 read
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        file_index: usize,
+        quality_score: i32,
+        pairs: Option<PairOptions>,
+        options: &AlignmentOptions,
+    ) -> Result<Self> {
+        Ok(Self {
+            records: AlignmentRecords::from_path(path, options, pairs.is_some())?,
+            file_index,
+            reads_index: 0,
+            quality_score,
+            batch_size: 30,
+            pairs: pairs.map(|pairs| {
+                PairSync::new(PairOptions {
+                    interleaved: true,
+                    ..pairs
+                })
+            }),
+        })
+    }
+
+    /// 返回序列 (低质量碱基替换为 'x'), 质量值之和与替换前的 GC 计数
+    fn prepare(&self, record: SeqRecord) -> (Vec<u8>, u64, Option<(usize, usize)>) {
+        let Some(qual) = record.qual else {
+            return (record.seq, 0, None);
+        };
+        let mut seq = record.seq;
+        let mut unmasked_gc = None;
+        if self.quality_score > 0 {
+            unmasked_gc = Some(gc_count(&seq));
+            for (base, &q) in seq.iter_mut().zip(qual.iter()) {
+                if (q.saturating_sub(b'!') as i32) < self.quality_score {
+                    *base = b'x';
+                }
+            }
+        }
+        let qual_sum = qual.iter().map(|&q| q.saturating_sub(b'!') as u64).sum();
+        (seq, qual_sum, unmasked_gc)
+    }
+
+    fn create_seq_header(&self, record: &SeqRecord, qual_sum: u64) -> SeqHeader {
+        let name = String::from_utf8_lossy(&record.header);
+        SeqHeader {
+            id: trim_pair_info(name.split_whitespace().next().unwrap_or_default()),
+            file_index: self.file_index,
+            reads_index: self.reads_index,
+            format: if record.qual.is_some() {
+                SeqFormat::Fastq
+            } else {
+                SeqFormat::Fasta
+            },
+            qual_sum,
+            unmasked_gc: None,
+        }
+    }
+
+    pub fn read_next(&mut self) -> Result<Option<Base<Vec<u8>>>> {
+        let records = &mut self.records;
+        let Some(sync) = self.pairs.as_mut() else {
+            let Some(record) = records.next_record()? else {
+                return Ok(None);
+            };
+            self.reads_index += 1;
+            let header = self.create_seq_header(&record, 0);
+            let (seq, qual_sum, unmasked_gc) = self.prepare(record);
+            return Ok(Some(Base::new(
+                SeqHeader {
+                    qual_sum,
+                    unmasked_gc,
+                    ..header
+                },
+                OptionPair::Single(seq),
+            )));
+        };
+        let Some((mate1, mate2)) =
+            sync.next_pair(|_| records.next_record(), |record| pair_id(&record.header))?
+        else {
+            return Ok(None);
+        };
+        self.reads_index += 1;
+        let header = self.create_seq_header(&mate1, 0);
+        let (seq1, qual_sum1, gc1) = self.prepare(mate1);
+        let (seq2, qual_sum2, gc2) = self.prepare(mate2);
+        // 一端为 FASTA 记录时没有质量值, 未被替换的那端直接按序列计数
+        let unmasked_gc = match (gc1, gc2) {
+            (None, None) => None,
+            (gc1, gc2) => {
+                let (g1, a1) = gc1.unwrap_or_else(|| gc_count(&seq1));
+                let (g2, a2) = gc2.unwrap_or_else(|| gc_count(&seq2));
+                Some((g1 + g2, a1 + a2))
+            }
+        };
+        Ok(Some(Base::new(
+            SeqHeader {
+                qual_sum: qual_sum1 + qual_sum2,
+                unmasked_gc,
+                ..header
+            },
+            OptionPair::Pair(seq1, seq2),
+        )))
+    }
+}
+
+impl Reader for AlignmentReader {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        let seqs: Vec<Base<Vec<u8>>> = (0..self.batch_size)
+            .filter_map(|_| self.read_next().transpose())
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(seqs).filter(|v| !v.is_empty()))
+    }
+
+    fn pair_stats(&self) -> Option<PairStats> {
+        self.pairs.as_ref().map(|sync| sync.stats())
+    }
+}
@@ -0,0 +1,139 @@
+use crossbeam_channel::{bounded, Receiver};
+use noodles::bgzf;
+use std::fs::File;
+use std::io::{self, Read, Result, Seek};
+use std::num::NonZero;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// 后台解压时每块的大小
+const CHUNK_SIZE: usize = 1 << 20;
+/// 后台线程最多预先解压的块数
+const CHUNK_QUEUE: usize = 8;
+
+static DECOMPRESS_THREADS: AtomicUsize = AtomicUsize::new(1);
+
+/// 设置压缩输入的解压线程数, 1 表示在读取线程中解压
+///
+/// BGZF (bgzip) 文件的各个 block 并行解压; 普通 gzip, zstd, bzip2 与 xz 只能顺序解压,
+/// 大于 1 时在单独的线程中预先解压, 与 reads 的解析和分类重叠
+pub fn set_decompress_threads(threads: usize) {
+    DECOMPRESS_THREADS.store(threads.max(1), Ordering::Relaxed);
+}
+
+pub fn decompress_threads() -> usize {
+    DECOMPRESS_THREADS.load(Ordering::Relaxed)
+}
+
+/// 判断 gzip 文件开头是否为 BGZF: 第一个 member 的 extra field 中有 `BC` 子字段
+///
+/// ```
+/// use seqkmer::is_bgzf_header;
+///
+/// let bgzf = [31, 139, 8, 4, 0, 0, 0, 0, 0, 255, 6, 0, b'B', b'C', 2, 0, 27, 0];
+/// assert!(is_bgzf_header(&bgzf));
+/// assert!(!is_bgzf_header(&[31, 139, 8, 0, 0, 0, 0, 0, 0, 3]));
+/// ```
+pub fn is_bgzf_header(header: &[u8]) -> bool {
+    header.len() >= 16
+        && header[..4] == [0x1F, 0x8B, 0x08, 0x04]
+        && header[12..14] == *b"BC"
+        && header[14..16] == [2, 0]
+}
+
+/// 读取文件开头判断是否为 BGZF, 之后重置文件指针
+fn detect_bgzf(file: &mut File) -> Result<bool> {
+    let mut header = [0u8; 16];
+    let mut len = 0;
+    while len < header.len() {
+        match file.read(&mut header[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    file.rewind()?;
+    Ok(is_bgzf_header(&header[..len]))
+}
+
+/// 在后台线程中读取 `inner`, 按块交给读取方
+pub struct ThreadedReader {
+    chunks: Receiver<Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ThreadedReader {
+    pub fn new<R: Read + Send + 'static>(mut inner: R) -> Self {
+        let (sender, chunks) = bounded(CHUNK_QUEUE);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0u8; CHUNK_SIZE];
+            let mut len = 0;
+            let result = loop {
+                match inner.read(&mut chunk[len..]) {
+                    Ok(0) => break Ok(()),
+                    Ok(n) => {
+                        len += n;
+                        if len == chunk.len() {
+                            break Ok(());
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => break Err(e),
+                }
+            };
+            chunk.truncate(len);
+            let done = len == 0 || result.is_err();
+            let message = result.map(|_| chunk);
+            // 读取方已经关闭
+            if sender.send(message).is_err() || done {
+                break;
+            }
+        });
+        Self {
+            chunks,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ThreadedReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.pos == self.chunk.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                // 后台线程已结束
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// 解压线程数大于 1 时 BGZF 文件并行解压, 普通 gzip 在后台线程中解压
+pub(crate) fn gzip_reader(mut file: File) -> Result<Box<dyn Read + Send>> {
+    let threads = decompress_threads();
+    if threads > 1 && detect_bgzf(&mut file)? {
+        let workers = NonZero::new(threads).unwrap_or(NonZero::<usize>::MIN);
+        return Ok(Box::new(bgzf::io::MultithreadedReader::with_worker_count(
+            workers, file,
+        )));
+    }
+    // 多个 member 拼接的 gzip 需要读到最后一个 member
+    Ok(background(flate2::read::MultiGzDecoder::new(file)))
+}
+
+/// 解压线程数大于 1 时在后台线程中读取
+pub(crate) fn background<R: Read + Send + 'static>(reader: R) -> Box<dyn Read + Send> {
+    if decompress_threads() > 1 {
+        Box::new(ThreadedReader::new(reader))
+    } else {
+        Box::new(reader)
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::VecDeque;
+
+/// DUST 计算分数的窗口长度, 与 dustmasker 的 -window 默认值相同
+pub const DEFAULT_DUST_WINDOW: usize = 64;
+/// DUST 的分数阈值, 与 dustmasker 的 -level 默认值相同
+pub const DEFAULT_DUST_THRESHOLD: u32 = 20;
+
+/// 三联体的长度
+const WORD_LEN: usize = 3;
+/// 三联体的种类数
+const WORD_COUNT: usize = 1 << (2 * WORD_LEN);
+const WORD_MASK: usize = WORD_COUNT - 1;
+
+#[inline]
+fn nt4(ch: u8) -> usize {
+    match ch {
+        b'A' | b'a' => 0,
+        b'C' | b'c' => 1,
+        b'G' | b'g' => 2,
+        b'T' | b't' => 3,
+        _ => 4,
+    }
+}
+
+/// 分数超过阈值, 且不包含分数更高的子区间的区间
+struct PerfectInterval {
+    start: usize,
+    finish: usize,
+    r: u32,
+    l: u32,
+}
+
+/// symmetric DUST (Morgulis et al. 2006) 的扫描状态, 实现与 minimap2 的 sdust 相同
+struct Scanner {
+    threshold: u32,
+    window_size: usize,
+    /// 当前窗口内的三联体
+    window: VecDeque<usize>,
+    /// 按起始位置从大到小排列的 perfect interval
+    perfect: Vec<PerfectInterval>,
+    regions: Vec<(usize, usize)>,
+    /// 窗口内三联体的计数与分数
+    cw: [u32; WORD_COUNT],
+    rw: u32,
+    /// 窗口末尾 `suffix_len` 个三联体的计数与分数, 其中每种三联体的分数都不超过阈值
+    cv: [u32; WORD_COUNT],
+    rv: u32,
+    suffix_len: usize,
+}
+
+impl Scanner {
+    fn new(threshold: u32, window_size: usize) -> Self {
+        Self {
+            threshold,
+            window_size,
+            window: VecDeque::with_capacity(window_size),
+            perfect: Vec::new(),
+            regions: Vec::new(),
+            cw: [0; WORD_COUNT],
+            rw: 0,
+            cv: [0; WORD_COUNT],
+            rv: 0,
+            suffix_len: 0,
+        }
+    }
+
+    fn reset_window(&mut self) {
+        self.window.clear();
+        self.cw = [0; WORD_COUNT];
+        self.cv = [0; WORD_COUNT];
+        self.rw = 0;
+        self.rv = 0;
+        self.suffix_len = 0;
+    }
+
+    fn shift_window(&mut self, word: usize) {
+        if self.window.len() > self.window_size - WORD_LEN {
+            if let Some(s) = self.window.pop_front() {
+                self.cw[s] -= 1;
+                self.rw -= self.cw[s];
+                if self.suffix_len > self.window.len() {
+                    self.suffix_len -= 1;
+                    self.cv[s] -= 1;
+                    self.rv -= self.cv[s];
+                }
+            }
+        }
+        self.window.push_back(word);
+        self.suffix_len += 1;
+        self.rw += self.cw[word];
+        self.cw[word] += 1;
+        self.rv += self.cv[word];
+        self.cv[word] += 1;
+        if self.cv[word] * 10 > self.threshold * 2 {
+            loop {
+                let s = self.window[self.window.len() - self.suffix_len];
+                self.cv[s] -= 1;
+                self.rv -= self.cv[s];
+                self.suffix_len -= 1;
+                if s == word {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// 保存已经移出窗口的 perfect interval, 与上一个屏蔽区间重叠时合并
+    fn save_masked_regions(&mut self, start: usize) {
+        let Some(p) = self.perfect.last() else {
+            return;
+        };
+        if p.start >= start {
+            return;
+        }
+        match self.regions.last_mut() {
+            Some(last) if p.start <= last.1 => last.1 = last.1.max(p.finish),
+            _ => self.regions.push((p.start, p.finish)),
+        }
+        while self.perfect.last().is_some_and(|p| p.start < start) {
+            self.perfect.pop();
+        }
+    }
+
+    fn find_perfect(&mut self, start: usize) {
+        let mut c = self.cv;
+        let mut r = self.rv;
+        let (mut max_r, mut max_l) = (0, 0);
+        let size = self.window.len();
+        for i in (0..size - self.suffix_len).rev() {
+            let t = self.window[i];
+            r += c[t];
+            c[t] += 1;
+            let new_l = (size - i - 1) as u32;
+            if r * 10 > self.threshold * new_l {
+                let mut j = 0;
+                while j < self.perfect.len() && self.perfect[j].start >= i + start {
+                    let p = &self.perfect[j];
+                    if max_r == 0 || p.r * max_l > max_r * p.l {
+                        max_r = p.r;
+                        max_l = p.l;
+                    }
+                    j += 1;
+                }
+                if max_r == 0 || r * max_l >= max_r * new_l {
+                    max_r = r;
+                    max_l = new_l;
+                    let interval = PerfectInterval {
+                        start: i + start,
+                        finish: size + WORD_LEN - 1 + start,
+                        r,
+                        l: new_l,
+                    };
+                    self.perfect.insert(j, interval);
+                }
+            }
+        }
+    }
+}
+
+/// 用 DUST 屏蔽核苷酸序列中的低复杂度区域 (单碱基重复, 短串联重复等)
+///
+/// 屏蔽的碱基替换为 N, 构建数据库时不会从中提取 minimizer; 换行符不计入位置
+///
+/// ```
+/// use seqkmer::Dust;
+///
+/// let mut seq = b"GATTACAGCTTGCATCGGATCCAGTCATG\nAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_vec();
+/// let dust = Dust::default();
+/// assert_eq!(dust.regions(&seq), vec![(29, 59)]);
+/// assert_eq!(dust.mask(&mut seq), 30);
+/// assert!(seq.ends_with(&[b'N'; 30]));
+/// assert!(seq.starts_with(b"GATTACAGCTTGCATCGGATCCAGTCATG\n"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dust {
+    /// 分数阈值, 越大屏蔽的区域越少
+    pub threshold: u32,
+    pub window: usize,
+}
+
+impl Default for Dust {
+    fn default() -> Self {
+        Self::new(DEFAULT_DUST_THRESHOLD)
+    }
+}
+
+impl Dust {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            window: DEFAULT_DUST_WINDOW,
+        }
+    }
+
+    /// 低复杂度区域 [start, end), 位置为去掉换行符后的碱基位置
+    pub fn regions(&self, seq: &[u8]) -> Vec<(usize, usize)> {
+        let mut scanner = Scanner::new(self.threshold, self.window.max(WORD_LEN + 1));
+        let bases = seq.iter().filter(|&&ch| ch != b'\n' && ch != b'\r');
+        // l 为当前连续 A/C/G/T 的长度, N 将序列分成互不影响的片段
+        let (mut l, mut word, mut i) = (0, 0, 0);
+        for b in bases.map(|&ch| nt4(ch)).chain(std::iter::once(4)) {
+            if b < 4 {
+                l += 1;
+                word = ((word << 2) | b) & WORD_MASK;
+                if l >= WORD_LEN {
+                    let start = l.saturating_sub(self.window) + (i + 1 - l);
+                    scanner.save_masked_regions(start);
+                    scanner.shift_window(word);
+                    if scanner.rw * 10 > scanner.suffix_len as u32 * self.threshold {
+                        scanner.find_perfect(start);
+                    }
+                }
+            } else {
+                let mut start = (l + 1).saturating_sub(self.window) + (i + 1 - l);
+                while !scanner.perfect.is_empty() {
+                    scanner.save_masked_regions(start);
+                    start += 1;
+                }
+                scanner.reset_window();
+                l = 0;
+                word = 0;
+            }
+            i += 1;
+        }
+        scanner.regions
+    }
+
+    /// 将低复杂度区域的碱基替换为 N, 返回屏蔽的碱基数
+    pub fn mask(&self, seq: &mut [u8]) -> usize {
+        let regions = self.regions(seq);
+        let mut regions = regions.iter().peekable();
+        let (mut pos, mut masked) = (0, 0);
+        for ch in seq.iter_mut().filter(|ch| **ch != b'\n' && **ch != b'\r') {
+            while regions.next_if(|(_, end)| *end <= pos).is_some() {}
+            match regions.peek() {
+                Some((start, _)) if *start <= pos => {
+                    *ch = b'N';
+                    masked += 1;
+                }
+                Some(_) => {}
+                None => break,
+            }
+            pos += 1;
+        }
+        masked
+    }
+}
@@ -0,0 +1,277 @@
+/// 每个 u64 保存的碱基数
+const BASES_PER_WORD: usize = 32;
+
+/// 将 32 位展开到 u64 的偶数位, 与 2-bit 编码的位置对应
+#[inline]
+fn spread_bits(x: u32) -> u64 {
+    let x = x as u64;
+    let x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    let x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    let x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    let x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+/// 不使用 SIMD 的编码. aarch64 上只在测试中编译, 与 NEON 的结果对比
+#[cfg(any(not(target_arch = "aarch64"), test))]
+mod scalar {
+    use super::BASES_PER_WORD;
+
+    /// 每个字节的最低位
+    const LO: u64 = 0x0101_0101_0101_0101;
+    /// 每个字节的最高位
+    const HI: u64 = 0x8080_8080_8080_8080;
+
+    /// 每个字节不为 0 时, 该字节的最高位为 1
+    #[inline]
+    fn nonzero_bytes(x: u64) -> u64 {
+        (((x & !HI).wrapping_add(!HI)) | x) & HI
+    }
+
+    /// 收集 8 个字节的最高位, 第 i 位对应第 i 个字节
+    #[inline]
+    fn movemask(x: u64) -> u32 {
+        (((x & HI) >> 7).wrapping_mul(0x0102_0408_1020_4080) >> 56) as u32
+    }
+
+    /// 将 8 个字节各自的低 2 位紧凑为 16 位
+    #[inline]
+    fn pack_pairs(x: u64) -> u64 {
+        let x = (x | (x >> 6)) & 0x000F_000F_000F_000F;
+        let x = (x | (x >> 12)) & 0x0000_00FF_0000_00FF;
+        (x | (x >> 24)) & 0xFFFF
+    }
+
+    /// 编码 8 个字节, 返回 (2-bit 编码, 不能编码的字节, 换行符).
+    ///
+    /// A/C/G/T (不区分大小写) 的 ASCII 码第 1, 2 位依次为 0, 1, 3, 2, 异或右移一位后即为 0, 1, 2, 3
+    #[inline]
+    fn encode8(chunk: u64) -> (u64, u32, u32) {
+        let lower = chunk | (0x20 * LO);
+        let not_base = nonzero_bytes(lower ^ (b'a' as u64 * LO))
+            & nonzero_bytes(lower ^ (b'c' as u64 * LO))
+            & nonzero_bytes(lower ^ (b'g' as u64 * LO))
+            & nonzero_bytes(lower ^ (b't' as u64 * LO));
+        let breaks = !(nonzero_bytes(chunk ^ (b'\n' as u64 * LO))
+            & nonzero_bytes(chunk ^ (b'\r' as u64 * LO)));
+        let x = (chunk >> 1) & (3 * LO);
+        let code = x ^ ((x >> 1) & LO);
+        (pack_pairs(code), movemask(not_base), movemask(breaks))
+    }
+
+    /// 编码 32 个字节, 返回 (2-bit 编码, 不能编码的字节, 换行符)
+    #[inline]
+    pub fn encode32(chunk: &[u8; BASES_PER_WORD]) -> (u64, u32, u32) {
+        let (mut word, mut not_base, mut line_breaks) = (0u64, 0u32, 0u32);
+        for (i, bytes) in chunk.chunks_exact(8).enumerate() {
+            let (code, n, br) = encode8(u64::from_le_bytes(bytes.try_into().unwrap()));
+            word |= code << (16 * i);
+            not_base |= n << (8 * i);
+            line_breaks |= br << (8 * i);
+        }
+        (word, not_base, line_breaks)
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+use scalar::encode32;
+
+/// 与 `scalar::encode8` 相同的编码, 每次用 NEON 处理 16 个字节 (Apple Silicon, Graviton 等)
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    /// 每个字节在位图中对应的位, 前后 8 个字节分别求和
+    const BIT_WEIGHTS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+    /// 2-bit 编码在每 4 个字节合成的一个字节中的位移
+    const CODE_SHIFTS: [i8; 16] = [0, 2, 4, 6, 0, 2, 4, 6, 0, 2, 4, 6, 0, 2, 4, 6];
+
+    /// 收集比较结果 (每个字节为 0 或 0xFF) 的位图, 第 i 位对应第 i 个字节
+    #[inline]
+    unsafe fn movemask(mask: uint8x16_t) -> u16 {
+        let bits = vandq_u8(mask, vld1q_u8(BIT_WEIGHTS.as_ptr()));
+        vaddv_u8(vget_low_u8(bits)) as u16 | (vaddv_u8(vget_high_u8(bits)) as u16) << 8
+    }
+
+    /// 编码 `bytes` 的前 16 个字节, 返回 (2-bit 编码, 不能编码的字节, 换行符)
+    #[inline]
+    pub fn encode16(bytes: &[u8]) -> (u32, u16, u16) {
+        assert!(bytes.len() >= 16);
+        // NEON 是 aarch64 的基本指令集, 不需要运行时检测; vld1q_u8 不要求地址对齐
+        unsafe {
+            let chunk = vld1q_u8(bytes.as_ptr());
+            let lower = vorrq_u8(chunk, vdupq_n_u8(0x20));
+            let is_base = vorrq_u8(
+                vorrq_u8(
+                    vceqq_u8(lower, vdupq_n_u8(b'a')),
+                    vceqq_u8(lower, vdupq_n_u8(b'c')),
+                ),
+                vorrq_u8(
+                    vceqq_u8(lower, vdupq_n_u8(b'g')),
+                    vceqq_u8(lower, vdupq_n_u8(b't')),
+                ),
+            );
+            let breaks = vorrq_u8(
+                vceqq_u8(chunk, vdupq_n_u8(b'\n')),
+                vceqq_u8(chunk, vdupq_n_u8(b'\r')),
+            );
+            let x = vandq_u8(vshrq_n_u8::<1>(chunk), vdupq_n_u8(3));
+            let code = veorq_u8(x, vandq_u8(vshrq_n_u8::<1>(x), vdupq_n_u8(1)));
+            // 移位后两次两两相加, 前 4 个字节各为 4 个碱基的编码
+            let shifted = vshlq_u8(code, vld1q_s8(CODE_SHIFTS.as_ptr()));
+            let pairs = vpaddq_u8(shifted, shifted);
+            let quads = vpaddq_u8(pairs, pairs);
+            let code = vgetq_lane_u32::<0>(vreinterpretq_u32_u8(quads));
+            (code, !movemask(is_base), movemask(breaks))
+        }
+    }
+}
+
+/// 编码 32 个字节, 返回 (2-bit 编码, 不能编码的字节, 换行符)
+#[cfg(target_arch = "aarch64")]
+#[inline]
+fn encode32(chunk: &[u8; BASES_PER_WORD]) -> (u64, u32, u32) {
+    let (code_lo, n_lo, br_lo) = neon::encode16(&chunk[..16]);
+    let (code_hi, n_hi, br_hi) = neon::encode16(&chunk[16..]);
+    (
+        code_lo as u64 | (code_hi as u64) << 32,
+        n_lo as u32 | (n_hi as u32) << 16,
+        br_lo as u32 | (br_hi as u32) << 16,
+    )
+}
+
+/// 2-bit 编码后的核苷酸序列, 每个 u64 保存 32 个碱基 (A=0, C=1, G=2, T=3).
+///
+/// 编码时同时记录不能编码的碱基 (N 等, 包括 `-Q` 屏蔽的碱基) 与换行符的位图,
+/// 扫描 minimizer 与统计 N 的数量, 最长 N 区间和 GC 含量时不需要再遍历原始序列
+///
+/// ```
+/// use seqkmer::EncodedSeq;
+///
+/// let seq = EncodedSeq::encode(b"ACGTNNac\ngtN");
+/// assert_eq!(seq.base(1), Some(1));
+/// assert_eq!(seq.base(3), Some(3));
+/// assert_eq!(seq.base(4), None);
+/// assert!(seq.is_line_break(8));
+/// assert_eq!(seq.ambiguous_count(), 3);
+/// assert_eq!(seq.ambiguous_runs(), vec![(4, 2), (11, 1)]);
+/// assert_eq!(seq.gc_count(), (4, 8));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EncodedSeq {
+    bases: Vec<u64>,
+    ambiguous: Vec<u32>,
+    breaks: Vec<u32>,
+    len: usize,
+}
+
+impl EncodedSeq {
+    pub fn encode(seq: &[u8]) -> Self {
+        Self::encode_with(seq, encode32)
+    }
+
+    /// 不使用 NEON 编码, 用于在 aarch64 上对比两种实现
+    #[cfg(all(test, target_arch = "aarch64"))]
+    pub(crate) fn encode_scalar(seq: &[u8]) -> Self {
+        Self::encode_with(seq, scalar::encode32)
+    }
+
+    fn encode_with(seq: &[u8], encode32: fn(&[u8; BASES_PER_WORD]) -> (u64, u32, u32)) -> Self {
+        let words = seq.len().div_ceil(BASES_PER_WORD);
+        let mut bases = Vec::with_capacity(words);
+        let mut ambiguous = Vec::with_capacity(words);
+        let mut breaks = Vec::with_capacity(words);
+
+        for chunk in seq.chunks(BASES_PER_WORD) {
+            // 最后不足 32 个碱基时以换行符补齐, 不计入碱基和 N
+            let mut padded = [b'\n'; BASES_PER_WORD];
+            padded[..chunk.len()].copy_from_slice(chunk);
+
+            let (word, not_base, line_breaks) = encode32(&padded);
+            bases.push(word);
+            ambiguous.push(not_base & !line_breaks);
+            breaks.push(line_breaks);
+        }
+
+        Self {
+            bases,
+            ambiguous,
+            breaks,
+            len: seq.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 第 `pos` 个字符的 2-bit 编码, N 等不能编码的碱基和换行符返回 None
+    #[inline]
+    pub fn base(&self, pos: usize) -> Option<u64> {
+        let (word, bit) = (pos / BASES_PER_WORD, pos % BASES_PER_WORD);
+        if (self.ambiguous[word] | self.breaks[word]) >> bit & 1 == 1 {
+            None
+        } else {
+            Some(self.bases[word] >> (2 * bit) & 3)
+        }
+    }
+
+    #[inline]
+    pub fn is_line_break(&self, pos: usize) -> bool {
+        self.breaks[pos / BASES_PER_WORD] >> (pos % BASES_PER_WORD) & 1 == 1
+    }
+
+    /// 不能编码的碱基数
+    pub fn ambiguous_count(&self) -> usize {
+        self.ambiguous.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// 连续的不能编码的碱基区间 (起始位置, 碱基数), 区间内的换行符不打断区间
+    pub fn ambiguous_runs(&self) -> Vec<(usize, usize)> {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        // 上一个区间最后一个碱基之后的位置
+        let mut run_end = 0;
+        for (word, &bits) in self.ambiguous.iter().enumerate() {
+            let mut bits = bits;
+            while bits != 0 {
+                let pos = word * BASES_PER_WORD + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                match runs.last_mut() {
+                    Some((_, len)) if (run_end..pos).all(|p| self.is_line_break(p)) => *len += 1,
+                    _ => runs.push((pos, 1)),
+                }
+                run_end = pos + 1;
+            }
+        }
+        runs
+    }
+
+    /// 最长的连续不能编码的碱基数
+    pub fn longest_ambiguous_run(&self) -> usize {
+        self.ambiguous_runs()
+            .iter()
+            .map(|(_, len)| *len)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 返回 (G/C 碱基数, A/C/G/T 碱基数)
+    pub fn gc_count(&self) -> (usize, usize) {
+        self.bases
+            .iter()
+            .zip(self.ambiguous.iter().zip(&self.breaks))
+            .fold((0, 0), |(gc, acgt), (&word, (&n, &br))| {
+                let valid = !(n | br);
+                // C=01 与 G=10 的两位不同
+                let gc_bits = (word ^ (word >> 1)) & spread_bits(valid);
+                (
+                    gc + gc_bits.count_ones() as usize,
+                    acgt + valid.count_ones() as usize,
+                )
+            })
+    }
+}
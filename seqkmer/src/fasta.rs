@@ -4,8 +4,6 @@ use crate::utils::OptionPair;
 use std::io::{BufRead, BufReader, Read, Result};
 use std::path::Path;
 
-const SEQ_LIMIT: u64 = u64::pow(2, 32);
-
 /// FastaReader
 pub struct FastaReader<R>
 where
@@ -61,13 +59,6 @@ where
             return Ok(None);
         }
 
-        let seq_len = self.seq.len();
-        // 检查seq的长度是否大于2的32次方
-        if seq_len as u64 > SEQ_LIMIT {
-            eprintln!("Sequence length exceeds 2^32, which is not handled.");
-            return Ok(None);
-        }
-
         let seq_id = unsafe {
             let slice = if self.header.starts_with(b">") {
                 &self.header[1..]
@@ -90,9 +81,11 @@ where
             reads_index: self.reads_index,
             format: SeqFormat::Fasta,
             id: seq_id.to_owned(),
+            qual_sum: 0,
+            unmasked_gc: None,
         };
         Ok(Some((
-            seq_len,
+            self.seq.len(),
             Base::new(seq_header, OptionPair::Single(self.seq.to_owned())),
         )))
     }
@@ -167,16 +160,14 @@ where
 
     pub fn read_next(&mut self) -> Result<Option<()>> {
         // 读取fastq文件header部分
-        if self.header.is_empty() {
-            if self.reader.read_until(b'\n', &mut self.header)? == 0 {
-                return Ok(None);
-            }
+        if self.header.is_empty() && self.reader.read_until(b'\n', &mut self.header)? == 0 {
+            return Ok(None);
         }
 
         if self.reader.read_until(b'\n', &mut self.seq)? == 0 {
             return Ok(None);
         }
-        if self.seq.starts_with(&[b'>']) {
+        if self.seq.starts_with(b">") {
             self.header = self.seq.clone();
             self.seq.clear();
             if self.reader.read_until(b'\n', &mut self.seq)? == 0 {
@@ -196,13 +187,6 @@ where
             }
         }
 
-        let seq_len = self.seq.len();
-        // 检查seq的长度是否大于2的32次方
-        if seq_len as u64 > SEQ_LIMIT {
-            eprintln!("Sequence length exceeds 2^32, which is not handled.");
-            return Ok(None);
-        }
-
         let seq_id = unsafe {
             let slice = if self.header.starts_with(b">") {
                 &self.header[1..]
@@ -230,6 +214,8 @@ where
             reads_index: self.reads_index,
             format: SeqFormat::Fasta,
             id: seq_id.to_owned(),
+            qual_sum: 0,
+            unmasked_gc: None,
         };
         Ok(Some(Base::new(
             seq_header,
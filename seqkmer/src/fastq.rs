@@ -1,5 +1,7 @@
+use crate::pairs::{pair_id, PairOptions, PairStats, PairSync};
+use crate::quality::QualityEncoding;
 use crate::reader::{dyn_reader, trim_end, trim_pair_info, Reader, BUFSIZE};
-use crate::seq::{Base, SeqFormat, SeqHeader};
+use crate::seq::{gc_count, Base, SeqFormat, SeqHeader};
 use crate::utils::OptionPair;
 use std::io::{BufRead, BufReader, Read, Result};
 use std::path::Path;
@@ -12,6 +14,10 @@ struct QReader<R: Read + Send> {
     seq: Vec<u8>,
     plus: Vec<u8>,
     quals: Vec<u8>,
+    qual_sum: u64,
+    unmasked_gc: Option<(usize, usize)>,
+    // 质量字符对应的 phred 分数
+    phred: [u8; 256],
 }
 
 impl<R> QReader<R>
@@ -26,7 +32,10 @@ where
             seq: Vec::new(),
             plus: Vec::new(),
             quals: Vec::new(),
+            qual_sum: 0,
+            unmasked_gc: None,
             quality_score,
+            phred: QualityEncoding::Phred33.phred_table(),
         }
     }
 
@@ -54,11 +63,21 @@ where
         if self.reader.read_until(b'\n', &mut self.quals)? == 0 {
             return Ok(None);
         }
-        trim_end(&mut self.quals);
+        // '@' 与 '>' 也是合法的质量字符, 只去掉换行
+        while let Some(&b'\n' | &b'\r') = self.quals.last() {
+            self.quals.pop();
+        }
+        self.qual_sum = self
+            .quals
+            .iter()
+            .map(|&qscore| self.phred[qscore as usize] as u64)
+            .sum();
 
+        self.unmasked_gc = None;
         if self.quality_score > 0 {
+            self.unmasked_gc = Some(gc_count(&self.seq));
             for (base, &qscore) in self.seq.iter_mut().zip(self.quals.iter()) {
-                if (qscore as i32 - '!' as i32) < self.quality_score {
+                if (self.phred[qscore as usize] as i32) < self.quality_score {
                     *base = b'x';
                 }
             }
@@ -66,6 +85,23 @@ where
 
         Ok(Some(()))
     }
+
+    fn to_mate(&self) -> Mate {
+        Mate {
+            header: self.header.clone(),
+            seq: self.seq.clone(),
+            qual_sum: self.qual_sum,
+            unmasked_gc: self.unmasked_gc,
+        }
+    }
+}
+
+/// 配对时缓存的单条 read
+struct Mate {
+    header: Vec<u8>,
+    seq: Vec<u8>,
+    qual_sum: u64,
+    unmasked_gc: Option<(usize, usize)>,
 }
 
 pub struct FastqReader<R: Read + Send> {
@@ -74,6 +110,8 @@ pub struct FastqReader<R: Read + Send> {
     reads_index: usize,
     // 批量读取
     batch_size: usize,
+    pairs: PairOptions,
+    sync: PairSync<Mate>,
 }
 
 impl<R> FastqReader<R>
@@ -84,7 +122,7 @@ where
         Self::with_capacity(readers, file_index, BUFSIZE, quality_score, 30)
     }
 
-    pub fn with_capacity<'a>(
+    pub fn with_capacity(
         readers: OptionPair<R>,
         file_index: usize,
         capacity: usize,
@@ -106,12 +144,38 @@ where
             file_index,
             reads_index: 0,
             batch_size,
+            pairs: PairOptions::default(),
+            sync: PairSync::new(PairOptions::default()),
         }
     }
 
-    fn create_seq_header(reader: &QReader<R>, file_index: usize, reads_index: usize) -> SeqHeader {
+    /// 设置质量值编码, 用于 `quality_score` 过滤与质量统计. `Auto` 按 Phred+33 处理,
+    /// 需要检测时先调用 `QualityEncoding::resolve`
+    pub fn with_quality_encoding(mut self, encoding: QualityEncoding) -> Self {
+        let phred = match encoding {
+            QualityEncoding::Auto => QualityEncoding::Phred33.phred_table(),
+            encoding => encoding.phred_table(),
+        };
+        self.inner.apply_mut(|reader| reader.phred = phred);
+        self
+    }
+
+    /// 设置 paired reads 的读取方式, `interleaved` 时单个文件中的相邻 reads 组成一对
+    pub fn with_pairs(mut self, pairs: PairOptions) -> Self {
+        self.pairs = pairs;
+        self.sync = PairSync::new(pairs);
+        self
+    }
+
+    fn create_seq_header(
+        header: &[u8],
+        file_index: usize,
+        reads_index: usize,
+        qual_sum: u64,
+        unmasked_gc: Option<(usize, usize)>,
+    ) -> SeqHeader {
         let seq_id = unsafe {
-            let s = std::str::from_utf8_unchecked(&reader.header[1..]);
+            let s = std::str::from_utf8_unchecked(&header[1..]);
             let first_space_index = s
                 .find(|c: char| c.is_whitespace() || c == '\u{1}')
                 .unwrap_or(s.len());
@@ -124,40 +188,60 @@ where
             reads_index,
             format: SeqFormat::Fastq,
             id: trim_pair_info(seq_id),
+            qual_sum,
+            unmasked_gc,
         }
     }
 
     pub fn read_next(&mut self) -> Result<Option<Base<Vec<u8>>>> {
         match &mut self.inner {
-            OptionPair::Single(reader) => {
+            OptionPair::Single(reader) if !self.pairs.interleaved => {
                 if reader.read_next()?.is_none() {
                     return Ok(None);
                 }
 
                 self.reads_index += 1;
 
-                let seq_header =
-                    Self::create_seq_header(&reader, self.file_index, self.reads_index);
+                let seq_header = Self::create_seq_header(
+                    &reader.header,
+                    self.file_index,
+                    self.reads_index,
+                    reader.qual_sum,
+                    reader.unmasked_gc,
+                );
                 Ok(Some(Base::new(
                     seq_header,
                     OptionPair::Single(reader.seq.to_owned()),
                 )))
             }
-            OptionPair::Pair(reader1, reader2) => {
-                if reader1.read_next()?.is_none() {
+            inner => {
+                let mates = self.sync.next_pair(
+                    |mate| {
+                        let reader = match &mut *inner {
+                            OptionPair::Single(reader) => reader,
+                            OptionPair::Pair(reader1, _) if mate == 0 => reader1,
+                            OptionPair::Pair(_, reader2) => reader2,
+                        };
+                        Ok(reader.read_next()?.map(|_| reader.to_mate()))
+                    },
+                    |mate| pair_id(mate.header.get(1..).unwrap_or_default()),
+                )?;
+                let Some((mate1, mate2)) = mates else {
                     return Ok(None);
-                }
-                if reader2.read_next()?.is_none() {
-                    return Ok(None);
-                }
+                };
 
                 self.reads_index += 1;
-                let seq_header =
-                    Self::create_seq_header(&reader1, self.file_index, self.reads_index);
+                let seq_header = Self::create_seq_header(
+                    &mate1.header,
+                    self.file_index,
+                    self.reads_index,
+                    mate1.qual_sum + mate2.qual_sum,
+                    add_gc(mate1.unmasked_gc, mate2.unmasked_gc),
+                );
 
                 Ok(Some(Base::new(
                     seq_header,
-                    OptionPair::Pair(reader1.seq.to_owned(), reader2.seq.to_owned()),
+                    OptionPair::Pair(mate1.seq, mate2.seq),
                 )))
             }
         }
@@ -187,4 +271,16 @@ where
 
         Ok(Some(seqs).filter(|v| !v.is_empty()))
     }
+
+    fn pair_stats(&self) -> Option<PairStats> {
+        match self.inner {
+            OptionPair::Single(_) if !self.pairs.interleaved => None,
+            _ => Some(self.sync.stats()),
+        }
+    }
+}
+
+/// 两端 mate 共用同一质量过滤设置, 要么都有记录要么都没有
+fn add_gc(gc1: Option<(usize, usize)>, gc2: Option<(usize, usize)>) -> Option<(usize, usize)> {
+    gc1.zip(gc2).map(|((g1, a1), (g2, a2))| (g1 + g2, a1 + a2))
 }
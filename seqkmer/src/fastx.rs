@@ -1,9 +1,12 @@
+use crate::bam::{AlignmentFormat, AlignmentOptions, AlignmentReader};
 use crate::fasta::{BufferFastaReader, FastaReader};
 use crate::fastq::FastqReader;
+use crate::pairs::{PairOptions, PairStats};
+use crate::quality::QualityEncoding;
 use crate::reader::{detect_file_format, Reader};
 use crate::seq::{Base, SeqFormat};
 use crate::utils::OptionPair;
-use std::io::Result;
+use std::io::{self, Result};
 use std::path::Path;
 
 pub struct FastxReader<R: Reader> {
@@ -20,6 +23,10 @@ impl<R: Reader> Reader for FastxReader<R> {
     fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
         self.inner.next()
     }
+
+    fn pair_stats(&self) -> Option<PairStats> {
+        self.inner.pair_stats()
+    }
 }
 impl FastxReader<Box<dyn Reader + Send>> {
     pub fn from_paths<P: AsRef<Path>>(
@@ -27,16 +34,64 @@ impl FastxReader<Box<dyn Reader + Send>> {
         file_index: usize,
         quality_score: i32,
     ) -> Result<Self> {
+        Self::from_paths_with_options(
+            paths,
+            file_index,
+            quality_score,
+            PairOptions::default(),
+            QualityEncoding::Phred33,
+            &AlignmentOptions::default(),
+        )
+    }
+
+    /// 同 `from_paths`, 并指定 paired reads 的 mate 校验方式 (以及是否为 interleaved 文件),
+    /// FASTQ 质量值编码 (`Auto` 时从第一个文件检测) 与 BAM/CRAM 输入的读取设置
+    pub fn from_paths_with_options<P: AsRef<Path>>(
+        paths: OptionPair<P>,
+        file_index: usize,
+        quality_score: i32,
+        pairs: PairOptions,
+        encoding: QualityEncoding,
+        alignment: &AlignmentOptions,
+    ) -> Result<Self> {
+        match paths.map(|path: &P| AlignmentFormat::detect(path))? {
+            OptionPair::Single(Some(_)) => {
+                let path = paths.single().unwrap().as_ref();
+                let pairs = pairs.interleaved.then_some(pairs);
+                let reader =
+                    AlignmentReader::from_path(path, file_index, quality_score, pairs, alignment)?;
+                return Ok(Self::new(Box::new(reader) as Box<dyn Reader + Send>));
+            }
+            OptionPair::Pair(format1, format2) if format1.is_some() || format2.is_some() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "paired BAM/CRAM input must be one file holding both mates (-P -S)",
+                ));
+            }
+            _ => {}
+        }
+
         let file_format = paths.map(|path: &P| detect_file_format(path));
 
         match file_format? {
+            OptionPair::Single(SeqFormat::Fasta) if pairs.interleaved => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "interleaved paired reads must be in FASTQ format",
+            )),
             OptionPair::Single(SeqFormat::Fasta) => {
                 let reader = FastaReader::from_path(paths.single().unwrap().as_ref(), file_index)?;
                 Ok(Self::new(Box::new(reader) as Box<dyn Reader + Send>))
             }
             OptionPair::Single(SeqFormat::Fastq)
             | OptionPair::Pair(SeqFormat::Fastq, SeqFormat::Fastq) => {
-                let reader = FastqReader::from_path(paths, file_index, quality_score)?;
+                let encoding = match &paths {
+                    OptionPair::Single(path) | OptionPair::Pair(path, _) => {
+                        encoding.resolve(path)?
+                    }
+                };
+                let reader = FastqReader::from_path(paths, file_index, quality_score)?
+                    .with_pairs(pairs)
+                    .with_quality_encoding(encoding);
                 Ok(Self::new(Box::new(reader) as Box<dyn Reader + Send>))
             }
             _ => panic!("Unsupported file format combination"),
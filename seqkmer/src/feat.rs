@@ -1,22 +1,48 @@
-#[cfg(feature = "dna")]
 pub mod constants {
     pub const DEFAULT_KMER_LENGTH: u64 = 35;
     pub const DEFAULT_MINIMIZER_LENGTH: u8 = 31;
     pub const DEFAULT_MINIMIZER_SPACES: u8 = 7;
 
     pub const BITS_PER_CHAR: usize = 2;
+
+    /// 蛋白数据库 (`--protein`) 的默认值, 与 Kraken 2 相同
+    pub const DEFAULT_PROTEIN_KMER_LENGTH: u64 = 15;
+    pub const DEFAULT_PROTEIN_MINIMIZER_LENGTH: u8 = 12;
+    pub const DEFAULT_PROTEIN_MINIMIZER_SPACES: u8 = 0;
+
+    pub const PROTEIN_BITS_PER_CHAR: usize = 4;
 }
 
-#[cfg(feature = "protein")]
-pub mod constants {
-    pub const DEFAULT_KMER_LENGTH: u64 = 15;
-    pub const DEFAULT_MINIMIZER_LENGTH: u8 = 12;
-    pub const DEFAULT_MINIMIZER_SPACES: u8 = 0;
+/// minimizer 的字母表
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Alphabet {
+    /// 核苷酸, 使用 canonical k-mer
+    #[default]
+    Dna,
+    /// 氨基酸序列, 例如蛋白数据库的参考序列
+    Protein,
+    /// 核苷酸序列按 6 个读码框翻译后以氨基酸查找, 用于在蛋白数据库中分类 reads
+    Translated,
+}
+
+impl Alphabet {
+    pub fn bits_per_char(&self) -> usize {
+        match self {
+            Alphabet::Dna => constants::BITS_PER_CHAR,
+            Alphabet::Protein | Alphabet::Translated => constants::PROTEIN_BITS_PER_CHAR,
+        }
+    }
 
-    pub const BITS_PER_CHAR: usize = 4;
+    pub fn is_protein(&self) -> bool {
+        *self != Alphabet::Dna
+    }
+
+    /// minimizer 最多的字符数, 编码后需少于 64 位
+    pub fn max_minimizer_length(&self) -> usize {
+        63 / self.bits_per_char()
+    }
 }
 
-#[cfg(feature = "dna")]
 #[inline]
 pub fn char_to_value(c: u8) -> Option<u64> {
     match c {
@@ -28,9 +54,9 @@ pub fn char_to_value(c: u8) -> Option<u64> {
     }
 }
 
-#[cfg(feature = "protein")]
+/// 氨基酸的 4 位编码, 性质相近的氨基酸合并, 与 Kraken 2 的蛋白数据库相同
 #[inline]
-pub fn char_to_value(c: u8) -> Option<64> {
+pub fn protein_to_value(c: u8) -> Option<u64> {
     match c {
         // stop codons/rare amino acids
         b'*' | b'U' | b'u' | b'O' | b'o' => Some(0x00),
@@ -85,7 +111,7 @@ fn reverse_complement(mut kmer: u64, n: usize) -> u64 {
     kmer = (kmer >> 16 & 0x0000FFFF0000FFFF) | (kmer << 16 & 0xFFFF0000FFFF0000);
 
     // Swap the two halves of the 64-bit word
-    kmer = (kmer >> 32) | (kmer << 32);
+    kmer = kmer.rotate_left(32);
 
     // Complement the bits, shift to the right length, and mask to get the desired length
     (!kmer >> (64 - n * 2)) & ((1u64 << (n * 2)) - 1)
@@ -99,7 +125,6 @@ fn reverse_complement(mut kmer: u64, n: usize) -> u64 {
     // }
 }
 
-#[cfg(feature = "dna")]
 #[inline]
 pub fn canonical_representation(kmer: u64, n: usize) -> u64 {
     let revcom = reverse_complement(kmer, n);
@@ -110,12 +135,6 @@ pub fn canonical_representation(kmer: u64, n: usize) -> u64 {
     }
 }
 
-#[cfg(feature = "protein")]
-#[inline]
-pub fn canonical_representation(kmer: u64, n: usize, revcom_version: u8) -> u64 {
-    kmer
-}
-
 pub const DEFAULT_TOGGLE_MASK: u64 = 0xe37e28c4271b5a2d;
 pub const DEFAULT_SPACED_SEED_MASK: u64 = 0;
 pub const CURRENT_REVCOM_VERSION: u8 = 1;
@@ -128,7 +147,7 @@ const M2: u64 = 0xc4ceb9fe1a85ec53;
 /// # Examples
 ///
 /// ```
-/// # use kr2r::fmix64;
+/// # use seqkmer::fmix64;
 /// let key: u64 = 123;
 /// let hash = fmix64(key);
 /// assert_eq!(hash, 9208534749291869864);
@@ -153,6 +172,7 @@ pub struct Meros {
     pub spaced_seed_mask: u64,
     pub toggle_mask: u64,
     pub min_clear_hash_value: Option<u64>,
+    pub alphabet: Alphabet,
 }
 
 impl Meros {
@@ -162,9 +182,27 @@ impl Meros {
         spaced_seed_mask: Option<u64>,
         toggle_mask: Option<u64>,
         min_clear_hash_value: Option<u64>,
+    ) -> Self {
+        Self::with_alphabet(
+            Alphabet::Dna,
+            k_mer,
+            l_mer,
+            spaced_seed_mask,
+            toggle_mask,
+            min_clear_hash_value,
+        )
+    }
+
+    pub fn with_alphabet(
+        alphabet: Alphabet,
+        k_mer: usize,
+        l_mer: usize,
+        spaced_seed_mask: Option<u64>,
+        toggle_mask: Option<u64>,
+        min_clear_hash_value: Option<u64>,
     ) -> Self {
         let mut mask = 1u64;
-        mask <<= l_mer * constants::BITS_PER_CHAR;
+        mask <<= l_mer * alphabet.bits_per_char();
         mask -= 1;
 
         Self {
@@ -174,7 +212,16 @@ impl Meros {
             spaced_seed_mask: spaced_seed_mask.unwrap_or(DEFAULT_SPACED_SEED_MASK),
             toggle_mask: toggle_mask.unwrap_or(DEFAULT_TOGGLE_MASK) & mask,
             min_clear_hash_value,
+            alphabet,
+        }
+    }
+
+    /// 扫描数据库参考序列时使用的配置: 蛋白数据库的参考序列是氨基酸, 不需要翻译
+    pub fn for_references(mut self) -> Self {
+        if self.alphabet == Alphabet::Translated {
+            self.alphabet = Alphabet::Protein;
         }
+        self
     }
 
     pub fn window_size(&self) -> usize {
@@ -197,6 +244,7 @@ impl Default for Meros {
             spaced_seed_mask: DEFAULT_SPACED_SEED_MASK,
             toggle_mask: DEFAULT_TOGGLE_MASK & mask,
             min_clear_hash_value: None,
+            alphabet: Alphabet::Dna,
         }
     }
 }
@@ -1,20 +1,38 @@
+mod bam;
+mod decompress;
+mod dust;
+mod encode;
 mod fasta;
 mod fastq;
 mod fastx;
 mod feat;
 mod mmscanner;
+mod pairs;
 mod parallel;
+mod quality;
 mod reader;
+mod record;
 mod seq;
+mod translate;
 mod utils;
+mod window;
 
+pub use bam::*;
+pub use decompress::{decompress_threads, is_bgzf_header, set_decompress_threads, ThreadedReader};
+pub use dust::{Dust, DEFAULT_DUST_THRESHOLD, DEFAULT_DUST_WINDOW};
+pub use encode::EncodedSeq;
 pub use fasta::*;
 pub use fastq::*;
 pub use fastx::*;
 pub use feat::constants::*;
 pub use feat::*;
-pub use mmscanner::MinimizerIterator;
+pub use mmscanner::{scan_sequence, MinimizerIterator};
+pub use pairs::*;
 pub use parallel::*;
+pub use quality::QualityEncoding;
 pub use reader::*;
+pub use record::*;
 pub use seq::*;
+pub use translate::{translate_frames, FRAME_SEPARATOR};
 pub use utils::OptionPair;
+pub use window::WindowReader;
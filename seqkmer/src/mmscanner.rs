@@ -2,13 +2,20 @@
 use crate::seq::Base;
 use crate::utils::OptionPair;
 use crate::{
-    canonical_representation, char_to_value, fmix64 as murmur_hash3, Meros, BITS_PER_CHAR,
+    canonical_representation, fmix64 as murmur_hash3, protein_to_value, translate_frames, Alphabet,
+    EncodedSeq, Meros,
 };
+use std::borrow::Cow;
 use std::collections::VecDeque;
 
 #[inline]
 fn to_candidate_lmer(meros: &Meros, lmer: u64) -> u64 {
-    let mut canonical_lmer = canonical_representation(lmer, meros.l_mer);
+    // 氨基酸没有反向互补
+    let mut canonical_lmer = if meros.alphabet.is_protein() {
+        lmer
+    } else {
+        canonical_representation(lmer, meros.l_mer)
+    };
     if meros.spaced_seed_mask > 0 {
         canonical_lmer &= meros.spaced_seed_mask;
     }
@@ -76,7 +83,7 @@ impl MinimizerWindow {
         self.queue.push_back(data);
 
         while !self.queue.is_empty()
-            && self.queue.front().map_or(false, |front| {
+            && self.queue.front().is_some_and(|front| {
                 self.count >= self.capacity && front.pos < self.count - self.capacity
             })
         {
@@ -105,20 +112,22 @@ pub struct Cursor {
     capacity: usize,
     value: u64,
     mask: u64,
+    bits_per_char: usize,
 }
 
 impl Cursor {
-    fn new(capacity: usize, mask: u64) -> Self {
+    fn new(capacity: usize, mask: u64, bits_per_char: usize) -> Self {
         Self {
             pos: 0,
             value: 0,
             capacity,
             mask,
+            bits_per_char,
         }
     }
 
     fn next_lmer(&mut self, item: u64) -> Option<u64> {
-        self.value = ((self.value << BITS_PER_CHAR) | item) & self.mask;
+        self.value = ((self.value << self.bits_per_char) | item) & self.mask;
         // 更新当前位置
         self.pos += 1;
         // 检查是否达到了容量
@@ -139,7 +148,12 @@ impl Cursor {
 pub struct MinimizerIterator<'a> {
     cursor: Cursor,
     window: MinimizerWindow,
-    seq: &'a [u8],
+    /// 扫描的序列, `Alphabet::Translated` 时为翻译后的 6 个读码框
+    seq: Cow<'a, [u8]>,
+    /// 原始的核苷酸序列
+    nucleotides: &'a [u8],
+    /// `Alphabet::Dna` 时 2-bit 编码后的序列
+    encoded: Option<EncodedSeq>,
     meros: &'a Meros,
     pos: usize,
     end: usize,
@@ -148,14 +162,22 @@ pub struct MinimizerIterator<'a> {
 
 impl<'a> MinimizerIterator<'a> {
     pub fn new(seq: &'a [u8], cursor: Cursor, window: MinimizerWindow, meros: &'a Meros) -> Self {
+        let scanned = if meros.alphabet == Alphabet::Translated {
+            Cow::Owned(translate_frames(seq))
+        } else {
+            Cow::Borrowed(seq)
+        };
+        let encoded = (meros.alphabet == Alphabet::Dna).then(|| EncodedSeq::encode(seq));
         MinimizerIterator {
             cursor,
             window,
-            seq,
+            end: scanned.len(),
+            seq: scanned,
+            nucleotides: seq,
+            encoded,
             meros,
             pos: 0,
             size: 0,
-            end: seq.len(),
         }
     }
 
@@ -165,7 +187,30 @@ impl<'a> MinimizerIterator<'a> {
     }
 
     pub fn seq_size(&self) -> usize {
-        self.end
+        self.nucleotides.len()
+    }
+
+    /// 已扫描的原始序列长度, 扫描完时等于 [`MinimizerIterator::seq_size`];
+    /// 扫描 6 个读码框的翻译时按比例折算
+    pub fn scanned_size(&self) -> usize {
+        if self.pos >= self.end {
+            self.nucleotides.len()
+        } else {
+            self.pos * self.nucleotides.len() / self.end
+        }
+    }
+
+    /// 2-bit 编码后的序列与其 N 位图, 只有 `Alphabet::Dna` 时存在
+    pub fn encoded(&self) -> Option<&EncodedSeq> {
+        self.encoded.as_ref()
+    }
+
+    /// 返回 (G/C 碱基数, A/C/G/T 碱基数)
+    pub fn gc_count(&self) -> (usize, usize) {
+        if let Some(encoded) = &self.encoded {
+            return encoded.gc_count();
+        }
+        crate::seq::gc_count(self.nucleotides)
     }
 }
 
@@ -174,27 +219,31 @@ impl<'a> Iterator for MinimizerIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         while self.pos < self.end {
-            let ch = self.seq[self.pos];
+            let pos = self.pos;
             self.pos += 1;
-            if ch == b'\n' || ch == b'\r' {
-                continue;
-            } else {
-                let data = match char_to_value(ch) {
-                    Some(code) => self.cursor.next_lmer(code).and_then(|lmer| {
-                        let candidate_lmer = to_candidate_lmer(&self.meros, lmer);
-                        self.window
-                            .next(candidate_lmer)
-                            .map(|minimizer| murmur_hash3(minimizer ^ self.meros.toggle_mask))
-                    }),
-                    None => {
-                        self.clear_state();
-                        None
-                    }
-                };
-                if data.is_some() {
-                    self.size += 1;
-                    return Some((self.size, data.unwrap()));
+            let code = match &self.encoded {
+                Some(encoded) if encoded.is_line_break(pos) => continue,
+                Some(encoded) => encoded.base(pos),
+                None => match self.seq[pos] {
+                    b'\n' | b'\r' => continue,
+                    ch => protein_to_value(ch),
+                },
+            };
+            let data = match code {
+                Some(code) => self.cursor.next_lmer(code).and_then(|lmer| {
+                    let candidate_lmer = to_candidate_lmer(self.meros, lmer);
+                    self.window
+                        .next(candidate_lmer)
+                        .map(|minimizer| murmur_hash3(minimizer ^ self.meros.toggle_mask))
+                }),
+                None => {
+                    self.clear_state();
+                    None
                 }
+            };
+            if let Some(minimizer) = data {
+                self.size += 1;
+                return Some((self.size, minimizer));
             }
         }
         None
@@ -211,6 +260,13 @@ impl<'a> Base<MinimizerIterator<'a>> {
             .reduce_str("|", |m_iter| m_iter.seq_size().to_string())
     }
 
+    pub fn gc_count(&self) -> (usize, usize) {
+        self.body.reduce((0, 0), |(gc, acgt), m_iter| {
+            let (g, a) = m_iter.gc_count();
+            (gc + g, acgt + a)
+        })
+    }
+
     pub fn fmt_size(&self) -> String {
         self.body.reduce_str("|", |m_iter| m_iter.size.to_string())
     }
@@ -249,7 +305,7 @@ pub fn scan_sequence<'a>(
     meros: &'a Meros,
 ) -> Base<MinimizerIterator<'a>> {
     let func = |seq: &'a Vec<u8>| {
-        let cursor = Cursor::new(meros.l_mer, meros.mask);
+        let cursor = Cursor::new(meros.l_mer, meros.mask, meros.alphabet.bits_per_char());
         let window = MinimizerWindow::new(meros.window_size());
         MinimizerIterator::new(seq, cursor, window, meros)
     };
@@ -257,10 +313,66 @@ pub fn scan_sequence<'a>(
     match &sequence.body {
         OptionPair::Pair(seq1, seq2) => Base::new(
             sequence.header.clone(),
-            OptionPair::Pair(func(&seq1), func(&seq2)),
+            OptionPair::Pair(func(seq1), func(seq2)),
         ),
         OptionPair::Single(seq1) => {
-            Base::new(sequence.header.clone(), OptionPair::Single(func(&seq1)))
+            Base::new(sequence.header.clone(), OptionPair::Single(func(seq1)))
+        }
+    }
+}
+
+#[cfg(all(test, target_arch = "aarch64"))]
+mod tests {
+    use super::*;
+
+    type Minimizers = Vec<(usize, u64)>;
+
+    /// 用 NEON 与不使用 SIMD 的编码扫描同一条序列的 minimizer
+    fn minimizers(seq: &[u8], meros: &Meros) -> (Minimizers, Minimizers) {
+        let scan = |encoded: Option<EncodedSeq>| {
+            let cursor = Cursor::new(meros.l_mer, meros.mask, meros.alphabet.bits_per_char());
+            let window = MinimizerWindow::new(meros.window_size());
+            let mut iter = MinimizerIterator::new(seq, cursor, window, meros);
+            if let Some(encoded) = encoded {
+                iter.encoded = Some(encoded);
+            }
+            iter.collect::<Vec<_>>()
+        };
+        (scan(None), scan(Some(EncodedSeq::encode_scalar(seq))))
+    }
+
+    #[test]
+    fn neon_and_scalar_encodings_give_the_same_minimizers() {
+        let meros = Meros::default();
+        // 伪随机的碱基, 混入小写, N, IUPAC 码与换行符, 长度不是 32 的倍数
+        let alphabet = b"ACGTACGTACGTacgtNnRY\n\r-*";
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let seq: Vec<u8> = (0..10_007)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                alphabet[(state % alphabet.len() as u64) as usize]
+            })
+            .collect();
+        for len in [0, 1, 31, 32, 33, 100, 1000, seq.len()] {
+            let (neon, scalar) = minimizers(&seq[..len], &meros);
+            assert_eq!(neon, scalar, "length {}", len);
+        }
+        let (neon, _) = minimizers(&seq, &meros);
+        assert!(!neon.is_empty());
+
+        // 每个字节值单独出现, 覆盖 NEON 比较的所有分支
+        let all_bytes: Vec<u8> = (0..=255u8).flat_map(|b| [b'A', b, b'C', b'G']).collect();
+        let (neon, scalar) = minimizers(&all_bytes, &meros);
+        assert_eq!(neon, scalar);
+        let (neon, scalar) = (
+            EncodedSeq::encode(&all_bytes),
+            EncodedSeq::encode_scalar(&all_bytes),
+        );
+        for (pos, byte) in all_bytes.iter().enumerate() {
+            assert_eq!(neon.base(pos), scalar.base(pos), "byte {}", byte);
+            assert_eq!(neon.is_line_break(pos), scalar.is_line_break(pos));
         }
     }
 }
@@ -0,0 +1,417 @@
+use crate::seq::Base;
+use crate::utils::OptionPair;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Result};
+use std::str::FromStr;
+
+/// repair 模式下单侧最多缓存的 reads 数, 超过时说明两个文件的 reads 顺序不一致
+const MAX_PENDING: usize = 100_000;
+
+/// 从 header (不含 '@' 或 '>') 中取出用于配对的 read id: 第一个单词, 去掉末尾的 /1 或 /2
+///
+/// ```
+/// use seqkmer::pair_id;
+///
+/// assert_eq!(pair_id(b"read7/1 extra"), b"read7");
+/// assert_eq!(pair_id(b"read7 1:N:0:ACGT\n"), b"read7");
+/// assert_eq!(pair_id(b"/2"), b"/2");
+/// ```
+pub fn pair_id(header: &[u8]) -> &[u8] {
+    let end = header
+        .iter()
+        .position(|&c| c.is_ascii_whitespace() || c == 1)
+        .unwrap_or(header.len());
+    match &header[..end] {
+        [id @ .., b'/', b'1' | b'2'] if !id.is_empty() => id,
+        id => id,
+    }
+}
+
+/// paired reads 的 mate 校验方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PairCheck {
+    /// 不校验, 按顺序配对
+    Off,
+    /// mate 的 id 不一致或两端 reads 数不同时报错
+    #[default]
+    Strict,
+    /// 丢弃没有 mate 的 reads (orphans) 后继续
+    Repair,
+}
+
+impl FromStr for PairCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(PairCheck::Off),
+            "strict" => Ok(PairCheck::Strict),
+            "repair" => Ok(PairCheck::Repair),
+            _ => Err(format!(
+                "invalid pair check '{}', expected off, strict or repair",
+                s
+            )),
+        }
+    }
+}
+
+/// paired reads 的打分方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PairMode {
+    /// 两个 mate 分别扫描, 作为 `OptionPair` 的两个区间打分
+    #[default]
+    Range,
+    /// 两个 mate 以一个 N 连接为一条序列打分, 与旧版 Kraken 相同
+    Concat,
+}
+
+impl FromStr for PairMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "range" => Ok(PairMode::Range),
+            "concat" => Ok(PairMode::Concat),
+            _ => Err(format!(
+                "invalid pair mode '{}', expected range or concat",
+                s
+            )),
+        }
+    }
+}
+
+impl PairMode {
+    /// `PairMode::Concat` 时将 paired read 合并为 `mate1 + N + mate2` 的单条序列
+    ///
+    /// ```
+    /// use seqkmer::{Base, OptionPair, PairMode, SeqFormat, SeqHeader};
+    ///
+    /// let header = SeqHeader {
+    ///     id: "read1".to_string(),
+    ///     file_index: 1,
+    ///     reads_index: 1,
+    ///     format: SeqFormat::Fasta,
+    ///     qual_sum: 0,
+    ///     unmasked_gc: None,
+    /// };
+    /// let mut seq = Base::new(header, OptionPair::Pair(b"ACGT".to_vec(), b"TTGA".to_vec()));
+    /// PairMode::Range.apply(&mut seq);
+    /// assert!(matches!(seq.body, OptionPair::Pair(..)));
+    /// PairMode::Concat.apply(&mut seq);
+    /// assert_eq!(seq.body, OptionPair::Single(b"ACGTNTTGA".to_vec()));
+    /// ```
+    pub fn apply(&self, seq: &mut Base<Vec<u8>>) {
+        if *self != PairMode::Concat {
+            return;
+        }
+        if let OptionPair::Pair(mate1, mate2) = &mut seq.body {
+            let mut joined = std::mem::take(mate1);
+            joined.reserve(mate2.len() + 1);
+            joined.push(b'N');
+            joined.append(mate2);
+            seq.body = OptionPair::Single(joined);
+        }
+    }
+}
+
+/// paired reads 的读取设置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PairOptions {
+    /// 两个 mate 依次存放在同一个文件中
+    pub interleaved: bool,
+    pub check: PairCheck,
+}
+
+/// 配对结果统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PairStats {
+    pub pairs: u64,
+    /// 丢弃的 mate 1 / mate 2 reads 数 (interleaved 时只统计在 mate 1)
+    pub orphans: [u64; 2],
+}
+
+impl PairStats {
+    pub fn orphan_count(&self) -> u64 {
+        self.orphans[0] + self.orphans[1]
+    }
+}
+
+impl fmt::Display for PairStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} pairs, {} orphan reads dropped (mate 1: {}, mate 2: {})",
+            self.pairs,
+            self.orphan_count(),
+            self.orphans[0],
+            self.orphans[1]
+        )
+    }
+}
+
+/// 等待配对的 reads, 按 id 建索引
+struct Pending<T> {
+    items: VecDeque<(Vec<u8>, T)>,
+    index: HashMap<Vec<u8>, u64>,
+    popped: u64,
+}
+
+impl<T> Pending<T> {
+    fn new() -> Self {
+        Self {
+            items: VecDeque::new(),
+            index: HashMap::new(),
+            popped: 0,
+        }
+    }
+
+    fn push(&mut self, id: Vec<u8>, item: T) {
+        let pos = self.popped + self.items.len() as u64;
+        self.index.insert(id.clone(), pos);
+        self.items.push_back((id, item));
+    }
+
+    fn position(&self, id: &[u8]) -> Option<usize> {
+        self.index
+            .get(id)
+            .filter(|&&pos| pos >= self.popped)
+            .map(|&pos| (pos - self.popped) as usize)
+    }
+
+    fn pop_front(&mut self) -> Option<T> {
+        let (id, item) = self.items.pop_front()?;
+        if self.index.get(&id) == Some(&self.popped) {
+            self.index.remove(&id);
+        }
+        self.popped += 1;
+        Some(item)
+    }
+
+    /// 丢弃前 n 个 reads, 返回丢弃数
+    fn drop_front(&mut self, n: usize) -> u64 {
+        for _ in 0..n {
+            self.pop_front();
+        }
+        n as u64
+    }
+
+    fn front_id(&self) -> Option<&[u8]> {
+        self.items.front().map(|(id, _)| id.as_slice())
+    }
+
+    fn back_id(&self) -> Option<&[u8]> {
+        self.items.back().map(|(id, _)| id.as_slice())
+    }
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+/// 流式配对两个 mate 文件 (或 interleaved 的单个文件)
+///
+/// 校验 mate id 一致与两端 reads 数相同. repair 模式下假设两个文件中 reads 的相对顺序一致,
+/// 只是缺失了部分 mate, 没有 mate 的 reads 会被丢弃并计数.
+pub struct PairSync<T> {
+    options: PairOptions,
+    pending: [Pending<T>; 2],
+    eof: [bool; 2],
+    stats: PairStats,
+}
+
+impl<T> PairSync<T> {
+    pub fn new(options: PairOptions) -> Self {
+        Self {
+            options,
+            pending: [Pending::new(), Pending::new()],
+            eof: [false, false],
+            stats: PairStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> PairStats {
+        self.stats
+    }
+
+    fn mismatch(&self, id1: &[u8], id2: &[u8]) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "mate ids do not match at pair {}: '{}' vs '{}' (use --pair-check repair to drop orphan reads)",
+                self.stats.pairs + 1,
+                String::from_utf8_lossy(id1),
+                String::from_utf8_lossy(id2)
+            ),
+        )
+    }
+
+    fn unequal_counts(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "paired inputs have different numbers of reads, mates ran out after {} pairs (use --pair-check repair to drop orphan reads)",
+                self.stats.pairs
+            ),
+        )
+    }
+
+    /// 读取下一对 mates
+    ///
+    /// `next(mate)` 读取 mate 0 或 mate 1 的下一条 read (interleaved 时忽略参数),
+    /// `key` 返回 read 的配对 id.
+    ///
+    /// ```
+    /// use seqkmer::{PairCheck, PairOptions, PairSync};
+    ///
+    /// let mut mates = [vec!["a", "b", "d"], vec!["a", "c", "d"]].map(|v| v.into_iter());
+    /// let options = PairOptions { interleaved: false, check: PairCheck::Repair };
+    /// let mut sync = PairSync::new(options);
+    /// let mut pairs = Vec::new();
+    /// while let Some(pair) = sync
+    ///     .next_pair(|mate| Ok(mates[mate].next()), |id| id.as_bytes())
+    ///     .unwrap()
+    /// {
+    ///     pairs.push(pair);
+    /// }
+    /// assert_eq!(pairs, vec![("a", "a"), ("d", "d")]);
+    /// assert_eq!(sync.stats().orphans, [1, 1]);
+    /// ```
+    pub fn next_pair<F, K>(&mut self, mut next: F, key: K) -> Result<Option<(T, T)>>
+    where
+        F: FnMut(usize) -> Result<Option<T>>,
+        K: Fn(&T) -> &[u8],
+    {
+        if self.options.interleaved {
+            return self.next_interleaved(next, key);
+        }
+        loop {
+            if self.eof[0] && self.eof[1] {
+                if let Some(pair) = self.match_pending() {
+                    return Ok(Some(pair));
+                }
+                for (side, pending) in self.pending.iter_mut().enumerate() {
+                    let len = pending.len();
+                    self.stats.orphans[side] += pending.drop_front(len);
+                }
+                return Ok(None);
+            }
+
+            let mut reads = [None, None];
+            for (mate, read) in reads.iter_mut().enumerate() {
+                if !self.eof[mate] {
+                    *read = next(mate)?;
+                    self.eof[mate] = read.is_none();
+                }
+            }
+            let [read1, read2] = reads;
+
+            if self.pending[0].is_empty() && self.pending[1].is_empty() {
+                match (read1, read2) {
+                    (Some(read1), Some(read2)) => {
+                        if self.options.check == PairCheck::Off || key(&read1) == key(&read2) {
+                            self.stats.pairs += 1;
+                            return Ok(Some((read1, read2)));
+                        }
+                        if self.options.check == PairCheck::Strict {
+                            return Err(self.mismatch(key(&read1), key(&read2)));
+                        }
+                        self.pending[0].push(key(&read1).to_vec(), read1);
+                        self.pending[1].push(key(&read2).to_vec(), read2);
+                    }
+                    (None, None) => return Ok(None),
+                    (read1, read2) => match self.options.check {
+                        PairCheck::Off => return Ok(None),
+                        PairCheck::Strict => return Err(self.unequal_counts()),
+                        PairCheck::Repair => {
+                            for (side, read) in [read1, read2].into_iter().enumerate() {
+                                if let Some(read) = read {
+                                    self.pending[side].push(key(&read).to_vec(), read);
+                                }
+                            }
+                        }
+                    },
+                }
+            } else {
+                for (side, read) in [read1, read2].into_iter().enumerate() {
+                    if let Some(read) = read {
+                        self.pending[side].push(key(&read).to_vec(), read);
+                    }
+                }
+            }
+
+            if let Some(pair) = self.match_pending() {
+                return Ok(Some(pair));
+            }
+            if self.pending[0].len() > MAX_PENDING || self.pending[1].len() > MAX_PENDING {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "no mate found within {} reads, the paired inputs are not in the same order",
+                        MAX_PENDING
+                    ),
+                ));
+            }
+        }
+    }
+
+    /// 在等待队列中寻找下一对 mates, 匹配位置之前的 reads 都是 orphans
+    fn match_pending(&mut self) -> Option<(T, T)> {
+        let (front1, front2) = (self.pending[0].front_id()?, self.pending[1].front_id()?);
+        if front1 != front2 {
+            let (drop1, drop2) = if let Some(pos) = self.pending[0]
+                .back_id()
+                .and_then(|id| self.pending[1].position(id))
+            {
+                (self.pending[0].len() - 1, pos)
+            } else if let Some(pos) = self.pending[1]
+                .back_id()
+                .and_then(|id| self.pending[0].position(id))
+            {
+                (pos, self.pending[1].len() - 1)
+            } else {
+                return None;
+            };
+            self.stats.orphans[0] += self.pending[0].drop_front(drop1);
+            self.stats.orphans[1] += self.pending[1].drop_front(drop2);
+        }
+        self.stats.pairs += 1;
+        Some((self.pending[0].pop_front()?, self.pending[1].pop_front()?))
+    }
+
+    fn next_interleaved<F, K>(&mut self, mut next: F, key: K) -> Result<Option<(T, T)>>
+    where
+        F: FnMut(usize) -> Result<Option<T>>,
+        K: Fn(&T) -> &[u8],
+    {
+        let Some(mut read1) = next(0)? else {
+            return Ok(None);
+        };
+        loop {
+            let Some(read2) = next(1)? else {
+                return match self.options.check {
+                    PairCheck::Off => Ok(None),
+                    PairCheck::Strict => Err(self.unequal_counts()),
+                    PairCheck::Repair => {
+                        self.stats.orphans[0] += 1;
+                        Ok(None)
+                    }
+                };
+            };
+            if self.options.check == PairCheck::Off || key(&read1) == key(&read2) {
+                self.stats.pairs += 1;
+                return Ok(Some((read1, read2)));
+            }
+            if self.options.check == PairCheck::Strict {
+                return Err(self.mismatch(key(&read1), key(&read2)));
+            }
+            // read1 没有 mate, read2 作为下一对的第一个 mate
+            self.stats.orphans[0] += 1;
+            read1 = read2;
+        }
+    }
+}
@@ -28,11 +28,48 @@ where
     P: Send,
 {
     #[inline]
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<ParallelItem<P>> {
         self.recv.recv().ok().map(ParallelItem)
     }
 }
 
+/// 并行处理的线程划分: `compute` 个处理线程, 与 `io` 个读取与汇总 (写出) 线程.
+/// 两者在各自的线程池中, 处理线程再忙也不会占用读取与写出的线程. 读取与汇总各需要一个线程, 所以 `io` 至少按 2 计算
+///
+/// ```
+/// use seqkmer::Threads;
+///
+/// // 与原来的 `n_threads` 相同: 一个读取线程, 一个汇总线程, 其余用于处理
+/// assert_eq!(Threads::from(8), Threads::new(6, 2));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threads {
+    pub compute: usize,
+    pub io: usize,
+}
+
+impl Threads {
+    pub fn new(compute: usize, io: usize) -> Self {
+        Self {
+            compute: compute.max(1),
+            io,
+        }
+    }
+
+    fn io_pool_size(&self) -> u32 {
+        self.io.max(2) as u32
+    }
+}
+
+impl From<usize> for Threads {
+    /// 共 `n_threads` 个线程 (至少 3 个), 其中两个用于读取与汇总
+    fn from(n_threads: usize) -> Self {
+        assert!(n_threads > 2);
+        Self::new(n_threads - 2, 2)
+    }
+}
+
 pub fn create_reader(
     file_pair: &[String],
     file_index: usize,
@@ -49,8 +86,27 @@ pub fn create_reader(
 
 pub fn read_parallel<R, W, O, F, Out>(
     reader: &mut R,
-    n_threads: usize,
+    threads: impl Into<Threads>,
+    meros: &Meros,
+    work: W,
+    func: F,
+) -> Result<()>
+where
+    R: Reader,
+    O: Send,
+    Out: Send + Default,
+    W: Send + Sync + Fn(&mut Vec<Base<MinimizerIterator>>) -> O,
+    F: FnOnce(&mut ParallelResult<O>) -> Out + Send,
+{
+    read_parallel_with(reader, threads, meros, |_| {}, work, func)
+}
+
+/// 与 `read_parallel` 相同, 扫描 minimizer 之前先在工作线程中用 `prepare` 处理每条序列 (如 DUST 屏蔽)
+pub fn read_parallel_with<R, P, W, O, F, Out>(
+    reader: &mut R,
+    threads: impl Into<Threads>,
     meros: &Meros,
+    prepare: P,
     work: W,
     func: F,
 ) -> Result<()>
@@ -58,130 +114,147 @@ where
     R: Reader,
     O: Send,
     Out: Send + Default,
+    P: Send + Sync + Fn(&mut Base<Vec<u8>>),
     W: Send + Sync + Fn(&mut Vec<Base<MinimizerIterator>>) -> O,
     F: FnOnce(&mut ParallelResult<O>) -> Out + Send,
 {
-    assert!(n_threads > 2);
-    let buffer_len = n_threads + 2;
+    let threads = threads.into();
+    let buffer_len = threads.compute + 4;
     let (sender, receiver) = bounded::<Vec<Base<Vec<u8>>>>(buffer_len);
     let (done_send, done_recv) = bounded::<O>(buffer_len);
     let receiver = Arc::new(receiver); // 使用 Arc 来共享 receiver
     let done_send = Arc::new(done_send);
-    let mut pool = Pool::new(n_threads as u32);
+    let mut io_pool = Pool::new(threads.io_pool_size());
+    let mut compute_pool = Pool::new(threads.compute as u32);
 
     let mut parallel_result = ParallelResult { recv: done_recv };
+    // 读取出错时停止生产, 错误在所有线程结束后返回
+    let mut read_result = Ok(());
+    let read_result_ref = &mut read_result;
 
-    pool.scoped(|pool_scope| {
-        // 生产者线程
-        pool_scope.execute(move || {
-            while let Ok(Some(seqs)) = reader.next() {
-                sender.send(seqs).expect("Failed to send sequences");
+    io_pool.scoped(|io_scope| {
+        compute_pool.scoped(|compute_scope| {
+            // 生产者线程
+            io_scope.execute(move || loop {
+                match reader.next() {
+                    Ok(Some(seqs)) => sender.send(seqs).expect("Failed to send sequences"),
+                    Ok(None) => break,
+                    Err(e) => {
+                        *read_result_ref = Err(e);
+                        break;
+                    }
+                }
+            });
+
+            // 消费者线程
+            for _ in 0..threads.compute {
+                let receiver = Arc::clone(&receiver);
+                let work = &work;
+                let prepare = &prepare;
+                let done_send = Arc::clone(&done_send);
+                compute_scope.execute(move || {
+                    while let Ok(mut seqs) = receiver.recv() {
+                        seqs.iter_mut().for_each(prepare);
+                        let mut markers: Vec<Base<MinimizerIterator<'_>>> = seqs
+                            .iter_mut()
+                            .map(|seq| scan_sequence(seq, meros))
+                            .collect();
+                        let output = work(&mut markers);
+                        done_send.send(output).expect("Failed to send outputs");
+                    }
+                });
             }
-        });
 
-        // 消费者线程
-        for _ in 0..n_threads - 2 {
-            let receiver = Arc::clone(&receiver);
-            let work = &work;
-            let done_send = Arc::clone(&done_send);
-            pool_scope.execute(move || {
-                while let Ok(mut seqs) = receiver.recv() {
-                    let mut markers: Vec<Base<MinimizerIterator<'_>>> = seqs
-                        .iter_mut()
-                        .map(|seq| scan_sequence(seq, &meros))
-                        .collect();
-                    let output = work(&mut markers);
-                    done_send.send(output).expect("Failed to send outputs");
-                }
+            // 引用计数减掉一个,这样都子线程结束时, done_send还能完全释放
+            drop(done_send);
+            io_scope.execute(move || {
+                let _ = func(&mut parallel_result);
             });
-        }
 
-        // 引用计数减掉一个,这样都子线程结束时, done_send还能完全释放
-        drop(done_send);
-        pool_scope.execute(move || {
-            let _ = func(&mut parallel_result);
+            compute_scope.join_all();
         });
-
-        pool_scope.join_all();
     });
 
-    Ok(())
+    read_result
 }
 
-pub fn buffer_read_parallel<R, D, W, O, F, Out>(
-    reader: &mut R,
-    n_threads: usize,
-    buffer_size: usize,
+/// 在一个生产者线程中从 `batches` 取出记录批次, 由 `compute` 个处理线程处理.
+///
+/// 读取出错时停止读取, 已读取的批次处理完后返回该错误
+pub fn buffer_read_parallel<I, D, W, O, F, Out>(
+    batches: I,
+    threads: impl Into<Threads>,
     work: W,
     func: F,
 ) -> Result<()>
 where
-    D: Send + Sized + Sync + Clone,
-    R: std::io::Read + Send,
+    D: Send,
+    I: Iterator<Item = Result<Vec<D>>> + Send,
     O: Send,
     Out: Send + Default,
     W: Send + Sync + Fn(Vec<D>) -> O,
     F: FnOnce(&mut ParallelResult<O>) -> Out + Send,
 {
-    assert!(n_threads > 2);
-    let buffer_len = n_threads + 2;
+    let threads = threads.into();
+    let buffer_len = threads.compute + 4;
     let (sender, receiver) = bounded::<Vec<D>>(buffer_len);
     let (done_send, done_recv) = bounded::<O>(buffer_len);
     let receiver = Arc::new(receiver); // 使用 Arc 来共享 receiver
     let done_send = Arc::new(done_send);
-    let mut pool = Pool::new(n_threads as u32);
+    let mut io_pool = Pool::new(threads.io_pool_size());
+    let mut compute_pool = Pool::new(threads.compute as u32);
 
-    let slot_size = std::mem::size_of::<D>();
     let mut parallel_result = ParallelResult { recv: done_recv };
+    let mut read_error = None;
+
+    io_pool.scoped(|io_scope| {
+        compute_pool.scoped(|compute_scope| {
+            let read_error = &mut read_error;
+            // 生产者线程
+            io_scope.execute(move || {
+                for batch in batches {
+                    match batch {
+                        Ok(batch) => sender.send(batch).expect("Failed to send sequences"),
+                        Err(e) => {
+                            *read_error = Some(e);
+                            break;
+                        }
+                    }
+                }
+            });
 
-    pool.scoped(|pool_scope| {
-        // 生产者线程
-        pool_scope.execute(move || {
-            let mut batch_buffer = vec![0u8; slot_size * buffer_size];
-
-            while let Ok(bytes_read) = reader.read(&mut batch_buffer) {
-                if bytes_read == 0 {
-                    break;
-                } // 文件末尾
-
-                let slots_in_batch = bytes_read / slot_size;
-                let slots = unsafe {
-                    std::slice::from_raw_parts(batch_buffer.as_ptr() as *const D, slots_in_batch)
-                };
-                sender
-                    .send(slots.to_vec())
-                    .expect("Failed to send sequences");
+            // 消费者线程
+            for _ in 0..threads.compute {
+                let receiver = Arc::clone(&receiver);
+                let work = &work;
+                let done_send = Arc::clone(&done_send);
+                compute_scope.execute(move || {
+                    while let Ok(seqs) = receiver.recv() {
+                        let output = work(seqs);
+                        done_send.send(output).expect("Failed to send outputs");
+                    }
+                });
             }
-        });
 
-        // 消费者线程
-        for _ in 0..n_threads - 2 {
-            let receiver = Arc::clone(&receiver);
-            let work = &work;
-            let done_send = Arc::clone(&done_send);
-            pool_scope.execute(move || {
-                while let Ok(seqs) = receiver.recv() {
-                    let output = work(seqs);
-                    done_send.send(output).expect("Failed to send outputs");
-                }
+            // 引用计数减掉一个,这样都子线程结束时, done_send还能完全释放
+            drop(done_send);
+            io_scope.execute(move || {
+                let _ = func(&mut parallel_result);
             });
-        }
 
-        // 引用计数减掉一个,这样都子线程结束时, done_send还能完全释放
-        drop(done_send);
-        pool_scope.execute(move || {
-            let _ = func(&mut parallel_result);
+            compute_scope.join_all();
         });
-
-        pool_scope.join_all();
     });
 
-    Ok(())
+    match read_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
 }
 
 pub fn buffer_map_parallel<D, W, O, F, Out>(
     map: &HashMap<u32, Vec<D>>,
-    n_threads: usize,
+    threads: impl Into<Threads>,
     work: W,
     func: F,
 ) -> Result<()>
@@ -192,44 +265,47 @@ where
     W: Send + Sync + Fn((&u32, &Vec<D>)) -> O,
     F: FnOnce(&mut ParallelResult<O>) -> Out + Send,
 {
-    assert!(n_threads > 2);
-    let buffer_len = n_threads + 2;
+    let threads = threads.into();
+    let buffer_len = threads.compute + 4;
     let (sender, receiver) = bounded::<(&u32, &Vec<D>)>(buffer_len);
     let (done_send, done_recv) = bounded::<O>(buffer_len);
     let receiver = Arc::new(receiver); // 使用 Arc 来共享 receiver
     let done_send = Arc::new(done_send);
-    let mut pool = Pool::new(n_threads as u32);
+    let mut io_pool = Pool::new(threads.io_pool_size());
+    let mut compute_pool = Pool::new(threads.compute as u32);
 
     let mut parallel_result = ParallelResult { recv: done_recv };
 
-    pool.scoped(|pool_scope| {
-        // 生产者线程
-        pool_scope.execute(move || {
-            for entry in map {
-                sender.send(entry).expect("Failed to send sequences");
+    io_pool.scoped(|io_scope| {
+        compute_pool.scoped(|compute_scope| {
+            // 生产者线程
+            io_scope.execute(move || {
+                for entry in map {
+                    sender.send(entry).expect("Failed to send sequences");
+                }
+            });
+
+            // 消费者线程
+            for _ in 0..threads.compute {
+                let receiver = Arc::clone(&receiver);
+                let work = &work;
+                let done_send = Arc::clone(&done_send);
+                compute_scope.execute(move || {
+                    while let Ok(seqs) = receiver.recv() {
+                        let output = work(seqs);
+                        done_send.send(output).expect("Failed to send outputs");
+                    }
+                });
             }
-        });
 
-        // 消费者线程
-        for _ in 0..n_threads - 2 {
-            let receiver = Arc::clone(&receiver);
-            let work = &work;
-            let done_send = Arc::clone(&done_send);
-            pool_scope.execute(move || {
-                while let Ok(seqs) = receiver.recv() {
-                    let output = work(seqs);
-                    done_send.send(output).expect("Failed to send outputs");
-                }
+            // 引用计数减掉一个,这样都子线程结束时, done_send还能完全释放
+            drop(done_send);
+            io_scope.execute(move || {
+                let _ = func(&mut parallel_result);
             });
-        }
 
-        // 引用计数减掉一个,这样都子线程结束时, done_send还能完全释放
-        drop(done_send);
-        pool_scope.execute(move || {
-            let _ = func(&mut parallel_result);
+            compute_scope.join_all();
         });
-
-        pool_scope.join_all();
     });
 
     Ok(())
@@ -0,0 +1,139 @@
+use crate::bam::AlignmentFormat;
+use crate::reader::{detect_file_format, peek_reader};
+use crate::seq::SeqFormat;
+use std::fmt;
+use std::io::{BufRead, BufReader, Result};
+use std::path::Path;
+use std::str::FromStr;
+
+/// 检测编码时最多读取的 reads 数
+const DETECT_READS: usize = 10_000;
+
+/// FASTQ 质量值编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QualityEncoding {
+    /// 根据文件中出现的质量字符自动判断
+    #[default]
+    Auto,
+    /// Sanger / Illumina 1.8+
+    Phred33,
+    /// Illumina 1.3 - 1.7
+    Phred64,
+    /// Solexa / Illumina 1.0, 分数需换算为 phred
+    Solexa64,
+}
+
+impl FromStr for QualityEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(QualityEncoding::Auto),
+            "phred33" => Ok(QualityEncoding::Phred33),
+            "phred64" => Ok(QualityEncoding::Phred64),
+            "solexa" => Ok(QualityEncoding::Solexa64),
+            _ => Err(format!(
+                "invalid quality encoding '{}', expected auto, phred33, phred64 or solexa",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for QualityEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            QualityEncoding::Auto => "auto",
+            QualityEncoding::Phred33 => "Phred+33",
+            QualityEncoding::Phred64 => "Phred+64",
+            QualityEncoding::Solexa64 => "Solexa+64",
+        };
+        f.write_str(name)
+    }
+}
+
+impl QualityEncoding {
+    /// 由出现过的最小和最大质量字符推断编码. 只有最大值超过 Phred+33 常见的上限 `J` (Q41) 时
+    /// 才可能是 +64 编码, 无法区分时按 Phred+33 处理
+    ///
+    /// ```
+    /// use seqkmer::QualityEncoding;
+    ///
+    /// assert_eq!(QualityEncoding::from_range(b'#', b'J'), QualityEncoding::Phred33);
+    /// assert_eq!(QualityEncoding::from_range(b'B', b'h'), QualityEncoding::Phred64);
+    /// assert_eq!(QualityEncoding::from_range(b';', b'h'), QualityEncoding::Solexa64);
+    /// // 长读长数据的高质量值
+    /// assert_eq!(QualityEncoding::from_range(b'@', b'~'), QualityEncoding::Phred33);
+    /// // 只有高质量值的 Phred+33 reads (Q31 - Q41) 也落在 +64 的字符范围内
+    /// assert_eq!(QualityEncoding::from_range(b'@', b'J'), QualityEncoding::Phred33);
+    /// assert_eq!(QualityEncoding::from_range(b'@', b'K'), QualityEncoding::Phred64);
+    /// assert_eq!(QualityEncoding::from_range(b';', b'J'), QualityEncoding::Phred33);
+    /// assert_eq!(QualityEncoding::from_range(b':', b'h'), QualityEncoding::Phred33);
+    /// ```
+    pub fn from_range(min: u8, max: u8) -> Self {
+        if min < b';' || max <= b'J' || max > b'j' {
+            QualityEncoding::Phred33
+        } else if min < b'@' {
+            QualityEncoding::Solexa64
+        } else {
+            QualityEncoding::Phred64
+        }
+    }
+
+    /// 读取文件开头的 reads 检测编码, FASTA, BAM/CRAM 或没有质量值时为 Phred+33
+    pub fn detect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if AlignmentFormat::detect(&path)?.is_some()
+            || detect_file_format(&path)? != SeqFormat::Fastq
+        {
+            return Ok(QualityEncoding::Phred33);
+        }
+        let reader = BufReader::new(peek_reader(&path)?);
+        let (mut min, mut max) = (u8::MAX, u8::MIN);
+        for line in reader.split(b'\n').skip(3).step_by(4).take(DETECT_READS) {
+            for &c in line?.iter().filter(|c| !c.is_ascii_whitespace()) {
+                min = min.min(c);
+                max = max.max(c);
+            }
+        }
+        if min > max {
+            return Ok(QualityEncoding::Phred33);
+        }
+        Ok(Self::from_range(min, max))
+    }
+
+    /// `Auto` 时检测文件, 否则原样返回
+    pub fn resolve<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        match self {
+            QualityEncoding::Auto => Self::detect(path),
+            encoding => Ok(encoding),
+        }
+    }
+
+    /// 质量字符到 phred 分数的查找表
+    ///
+    /// ```
+    /// use seqkmer::QualityEncoding;
+    ///
+    /// assert_eq!(QualityEncoding::Phred33.phred_table()[b'I' as usize], 40);
+    /// assert_eq!(QualityEncoding::Phred64.phred_table()[b'h' as usize], 40);
+    /// assert_eq!(QualityEncoding::Solexa64.phred_table()[b';' as usize], 1);
+    /// assert_eq!(QualityEncoding::Solexa64.phred_table()[b'h' as usize], 40);
+    /// ```
+    pub fn phred_table(&self) -> [u8; 256] {
+        let mut table = [0u8; 256];
+        for (c, phred) in table.iter_mut().enumerate() {
+            let c = c as i32;
+            *phred = match self {
+                QualityEncoding::Phred64 => (c - 64).max(0) as u8,
+                QualityEncoding::Solexa64 => {
+                    let solexa = (c - 64) as f64;
+                    (10.0 * (10f64.powf(solexa / 10.0) + 1.0).log10())
+                        .round()
+                        .clamp(0.0, 255.0) as u8
+                }
+                _ => (c - 33).max(0) as u8,
+            };
+        }
+        table
+    }
+}
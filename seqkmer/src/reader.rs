@@ -1,26 +1,229 @@
+use crate::decompress::{background, gzip_reader};
+use crate::pairs::PairStats;
 use crate::seq::{Base, SeqFormat};
 use crate::utils::OptionPair;
-use flate2::read::GzDecoder;
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
 use std::fmt;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, Read, Result, Seek};
 use std::path::Path;
+use std::sync::{Mutex, OnceLock};
 
-pub(crate) fn dyn_reader<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read + Send>> {
-    let mut file = open_file(path)?;
-    if is_gzipped(&mut file)? {
-        let decoder = GzDecoder::new(file);
-        Ok(Box::new(decoder))
-    } else {
-        Ok(Box::new(file))
+/// 输入文件的压缩格式, 由文件开头的 magic bytes 判断
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// ```
+    /// use seqkmer::Compression;
+    ///
+    /// assert_eq!(Compression::from_magic(&[0x1F, 0x8B, 0x08]), Compression::Gzip);
+    /// assert_eq!(Compression::from_magic(&[0x28, 0xB5, 0x2F, 0xFD]), Compression::Zstd);
+    /// assert_eq!(Compression::from_magic(b"BZh9"), Compression::Bzip2);
+    /// assert_eq!(Compression::from_magic(b"\xFD7zXZ\x00"), Compression::Xz);
+    /// assert_eq!(Compression::from_magic(b"@read1"), Compression::None);
+    /// ```
+    pub fn from_magic(magic: &[u8]) -> Self {
+        match magic {
+            [0x1F, 0x8B, ..] => Compression::Gzip,
+            [0x28, 0xB5, 0x2F, 0xFD, ..] => Compression::Zstd,
+            [b'B', b'Z', b'h', ..] => Compression::Bzip2,
+            [0xFD, b'7', b'z', b'X', b'Z', 0x00, ..] => Compression::Xz,
+            _ => Compression::None,
+        }
+    }
+
+    /// 读取文件开头判断压缩格式, 之后重置文件指针
+    pub fn detect(file: &mut File) -> Result<Self> {
+        let mut magic = [0u8; 6];
+        let mut len = 0;
+        while len < magic.len() {
+            match file.read(&mut magic[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        file.rewind()?; // 重置文件指针到开头
+        Ok(Self::from_magic(&magic[..len]))
+    }
+}
+
+/// 打开 URL 输入的函数, 返回未解压的字节流
+pub type RemoteOpener = fn(&str) -> Result<Box<dyn Read + Send>>;
+
+static REMOTE_OPENER: OnceLock<RemoteOpener> = OnceLock::new();
+
+/// 设置打开 URL 输入 (见 [`is_remote_path`]) 的函数. seqkmer 不发送网络请求, 由调用方提供,
+/// 没有设置时打开 URL 返回错误
+pub fn set_remote_opener(opener: RemoteOpener) {
+    let _ = REMOTE_OPENER.set(opener);
+}
+
+/// 是否为 `s3://`, `gs://`, `http://` 或 `https://` 开头的 URL 输入
+///
+/// ```
+/// use seqkmer::is_remote_path;
+///
+/// assert!(is_remote_path("s3://bucket/reads_1.fq.gz"));
+/// assert!(is_remote_path("https://example.org/reads.fq"));
+/// assert!(!is_remote_path("data/s3://reads.fq"));
+/// assert!(!is_remote_path("reads.fq"));
+/// ```
+pub fn is_remote_path<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref().to_string_lossy();
+    ["s3://", "gs://", "http://", "https://"]
+        .iter()
+        .any(|scheme| path.starts_with(scheme))
+}
+
+fn remote_reader(url: &str) -> Result<Box<dyn Read + Send>> {
+    let opener = REMOTE_OPENER.get().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: URL inputs are not supported by this program", url),
+        )
+    })?;
+    stream_reader(opener(url)?)
+}
+
+/// 是否为表示标准输入的 `-`
+///
+/// ```
+/// use seqkmer::is_stdin_path;
+///
+/// assert!(is_stdin_path("-"));
+/// assert!(!is_stdin_path("./-"));
+/// assert!(!is_stdin_path("reads.fq"));
+/// ```
+pub fn is_stdin_path<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().as_os_str() == "-"
+}
+
+/// 标准输入预先读入 (解压后) 的字节数, 用于判断格式与质量值编码
+const STDIN_PEEK_SIZE: u64 = 4 << 20;
+
+/// 标准输入只能读取一次: 开头预先读入, 其余部分只能交给一个读取方
+struct StdinInput {
+    head: Vec<u8>,
+    rest: Option<Box<dyn Read + Send>>,
+}
+
+static STDIN: Mutex<Option<StdinInput>> = Mutex::new(None);
+
+fn with_stdin<T>(f: impl FnOnce(&mut StdinInput) -> Result<T>) -> Result<T> {
+    let mut stdin = STDIN.lock().unwrap_or_else(|e| e.into_inner());
+    let input = match stdin.as_mut() {
+        Some(input) => input,
+        None => {
+            let mut reader = stream_reader(io::stdin())?;
+            let mut head = Vec::new();
+            reader
+                .by_ref()
+                .take(STDIN_PEEK_SIZE)
+                .read_to_end(&mut head)?;
+            stdin.insert(StdinInput {
+                head,
+                rest: Some(reader),
+            })
+        }
+    };
+    f(input)
+}
+
+fn stdin_read_twice() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "standard input (-) can only be read once",
+    )
+}
+
+/// 打开标准输入, 一次运行中只能打开一次
+fn stdin_reader() -> Result<Box<dyn Read + Send>> {
+    with_stdin(|input| {
+        let rest = input.rest.take().ok_or_else(stdin_read_twice)?;
+        let head = std::mem::take(&mut input.head);
+        Ok(Box::new(io::Cursor::new(head).chain(rest)) as Box<dyn Read + Send>)
+    })
+}
+
+/// 与 [`dyn_reader`] 相同, 用于读取输入的开头判断格式. 标准输入 (`-`) 时读取预先读入的开头,
+/// 之后 [`dyn_reader`] 仍从头读取
+pub fn peek_reader<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read + Send>> {
+    if !is_stdin_path(&path) {
+        return dyn_reader(path);
     }
+    with_stdin(|input| match input.rest {
+        Some(_) => Ok(Box::new(io::Cursor::new(input.head.clone())) as Box<dyn Read + Send>),
+        None => Err(stdin_read_twice()),
+    })
+}
+
+/// URL 与标准输入不能回到开头, 先读入开头的几个字节判断压缩格式
+fn stream_reader<R: Read + Send + 'static>(mut inner: R) -> Result<Box<dyn Read + Send>> {
+    let mut magic = Vec::with_capacity(6);
+    inner.by_ref().take(6).read_to_end(&mut magic)?;
+    let compression = Compression::from_magic(&magic);
+    let reader = BufReader::new(io::Cursor::new(magic).chain(inner));
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => background(MultiGzDecoder::new(reader)),
+        Compression::Zstd => zstd_reader(reader)?,
+        Compression::Bzip2 => background(MultiBzDecoder::new(reader)),
+        Compression::Xz => xz_reader(reader)?,
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn zstd_reader<R: BufRead + Send + 'static>(reader: R) -> Result<Box<dyn Read + Send>> {
+    Ok(background(zstd::Decoder::with_buffer(reader)?))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn xz_reader<R: Read + Send + 'static>(reader: R) -> Result<Box<dyn Read + Send>> {
+    Ok(background(xz2::read::XzDecoder::new_multi_decoder(reader)))
+}
+
+/// wasm32 上没有 zstd 与 xz 的 C 库
+#[cfg(target_arch = "wasm32")]
+fn zstd_reader<R: BufRead + Send + 'static>(_reader: R) -> Result<Box<dyn Read + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "zstd compressed inputs are not supported in wasm32 builds",
+    ))
 }
 
-pub(crate) fn is_gzipped(file: &mut File) -> Result<bool> {
-    let mut buffer = [0; 2];
-    file.read_exact(&mut buffer)?;
-    file.rewind()?; // 重置文件指针到开头
-    Ok(buffer == [0x1F, 0x8B])
+#[cfg(target_arch = "wasm32")]
+fn xz_reader<R: Read + Send + 'static>(_reader: R) -> Result<Box<dyn Read + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "xz compressed inputs are not supported in wasm32 builds",
+    ))
+}
+
+/// 打开文件, gzip/zstd/bzip2/xz 压缩的文件以流的方式解压, 解压线程数见 [`set_decompress_threads`].
+/// URL 输入由 [`set_remote_opener`] 设置的函数读取, `-` 读取标准输入 (一次运行中只能读取一次)
+pub fn dyn_reader<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read + Send>> {
+    if is_remote_path(&path) {
+        return remote_reader(&path.as_ref().to_string_lossy());
+    }
+    if is_stdin_path(&path) {
+        return stdin_reader();
+    }
+    let mut file = open_file(path)?;
+    Ok(match Compression::detect(&mut file)? {
+        Compression::None => Box::new(file),
+        Compression::Gzip => gzip_reader(file)?,
+        Compression::Zstd => zstd_reader(BufReader::new(file))?,
+        Compression::Bzip2 => background(MultiBzDecoder::new(file)),
+        Compression::Xz => xz_reader(file)?,
+    })
 }
 
 pub fn trim_pair_info(id: &str) -> String {
@@ -44,11 +247,13 @@ pub fn open_file<P: AsRef<Path>>(path: P) -> Result<File> {
     })
 }
 
-pub(crate) fn detect_file_format<P: AsRef<Path>>(path: P) -> io::Result<SeqFormat> {
-    // let mut file = open_file(path)?;
-    let read1: Box<dyn io::Read + Send> = dyn_reader(path)?;
+/// 由内容判断 FASTA 或 FASTQ (压缩格式由 [`dyn_reader`] 识别), 跳过开头的空行
+pub fn detect_file_format<P: AsRef<Path>>(path: P) -> io::Result<SeqFormat> {
+    let read1: Box<dyn io::Read + Send> = peek_reader(&path)?;
     let reader = BufReader::new(read1);
-    let mut lines = reader.lines();
+    let mut lines = reader
+        .lines()
+        .skip_while(|line| line.as_ref().is_ok_and(|line| line.trim().is_empty()));
 
     if let Some(first_line) = lines.next() {
         let line = first_line?;
@@ -63,18 +268,13 @@ pub(crate) fn detect_file_format<P: AsRef<Path>>(path: P) -> io::Result<SeqForma
                     return Ok(SeqFormat::Fastq);
                 }
             }
-        } else {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Unrecognized fasta(fastq) file format",
-            ));
         }
     }
 
-    Err(io::Error::new(
-        io::ErrorKind::Other,
-        "Unrecognized fasta(fastq) file format",
-    ))
+    Err(io::Error::other(format!(
+        "Unrecognized fasta(fastq) file format: {:?}",
+        path.as_ref()
+    )))
 }
 
 pub(crate) fn trim_end(buffer: &mut Vec<u8>) {
@@ -87,12 +287,21 @@ pub const BUFSIZE: usize = 16 * 1024 * 1024;
 
 pub trait Reader: Send {
     fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>>;
+
+    /// paired reads 的配对统计, 单端 reads 为 None
+    fn pair_stats(&self) -> Option<PairStats> {
+        None
+    }
 }
 
 impl Reader for Box<dyn Reader + Send> {
     fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
         (**self).next()
     }
+
+    fn pair_stats(&self) -> Option<PairStats> {
+        (**self).pair_stats()
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,161 @@
+use crate::bam::{AlignmentFormat, AlignmentOptions, AlignmentRecords};
+use crate::reader::{detect_file_format, dyn_reader};
+use crate::seq::SeqFormat;
+use std::io::{self, BufRead, BufReader, Read, Result, Write};
+use std::path::Path;
+
+/// 保留完整 header 与质量值的原始序列记录, 用于按分类结果输出 reads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqRecord {
+    /// 不含 '>' 或 '@' 的完整 header 行
+    pub header: Vec<u8>,
+    pub seq: Vec<u8>,
+    /// FASTA 为 None
+    pub qual: Option<Vec<u8>>,
+}
+
+impl SeqRecord {
+    /// 写出记录, `suffix` 追加在 header 之后 (例如 " kraken:taxid|562")
+    ///
+    /// ```
+    /// use seqkmer::SeqRecord;
+    ///
+    /// let record = SeqRecord {
+    ///     header: b"read1 sample".to_vec(),
+    ///     seq: b"ACGT".to_vec(),
+    ///     qual: Some(b"IIII".to_vec()),
+    /// };
+    /// let mut out = Vec::new();
+    /// record.write_to(&mut out, " kraken:taxid|562").unwrap();
+    /// assert_eq!(out, b"@read1 sample kraken:taxid|562\nACGT\n+\nIIII\n");
+    /// ```
+    pub fn write_to<W: Write>(&self, writer: &mut W, suffix: &str) -> Result<()> {
+        let marker = if self.qual.is_some() { b'@' } else { b'>' };
+        writer.write_all(&[marker])?;
+        writer.write_all(&self.header)?;
+        writer.write_all(suffix.as_bytes())?;
+        writer.write_all(b"\n")?;
+        writer.write_all(&self.seq)?;
+        writer.write_all(b"\n")?;
+        if let Some(qual) = &self.qual {
+            writer.write_all(b"+\n")?;
+            writer.write_all(qual)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// 逐条读取 FASTA/FASTQ (支持压缩文件) 或 BAM/CRAM 的原始记录, 多行 FASTA 会被合并为一行
+pub struct RecordReader<R: Read + Send> {
+    reader: BufReader<R>,
+    format: SeqFormat,
+    line: Vec<u8>,
+    /// FASTA 中已读到的下一条记录的 header
+    pending: Option<Vec<u8>>,
+    /// BAM/CRAM 输入时不使用 `reader`
+    alignment: Option<AlignmentRecords>,
+}
+
+impl<R: Read + Send> RecordReader<R> {
+    pub fn new(reader: R, format: SeqFormat) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            format,
+            line: Vec::new(),
+            pending: None,
+            alignment: None,
+        }
+    }
+
+    fn read_line(&mut self) -> Result<bool> {
+        self.line.clear();
+        if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+            return Ok(false);
+        }
+        while let Some(&b'\n' | &b'\r') = self.line.last() {
+            self.line.pop();
+        }
+        Ok(true)
+    }
+
+    fn next_fasta(&mut self) -> Result<Option<SeqRecord>> {
+        let header = match self.pending.take() {
+            Some(header) => header,
+            None => loop {
+                if !self.read_line()? {
+                    return Ok(None);
+                }
+                if self.line.starts_with(b">") {
+                    break self.line[1..].to_vec();
+                }
+            },
+        };
+        let mut seq = Vec::new();
+        while self.read_line()? {
+            if self.line.starts_with(b">") {
+                self.pending = Some(self.line[1..].to_vec());
+                break;
+            }
+            seq.extend_from_slice(&self.line);
+        }
+        Ok(Some(SeqRecord {
+            header,
+            seq,
+            qual: None,
+        }))
+    }
+
+    fn next_fastq(&mut self) -> Result<Option<SeqRecord>> {
+        if !self.read_line()? {
+            return Ok(None);
+        }
+        let header = self.line.strip_prefix(b"@").unwrap_or(&self.line).to_vec();
+        let mut lines = Vec::with_capacity(3);
+        for _ in 0..3 {
+            if !self.read_line()? {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated fastq record",
+                ));
+            }
+            lines.push(self.line.clone());
+        }
+        let qual = lines.pop();
+        lines.pop();
+        let seq = lines.pop().unwrap_or_default();
+        Ok(Some(SeqRecord { header, seq, qual }))
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<SeqRecord>> {
+        if let Some(alignment) = &mut self.alignment {
+            return alignment.next_record();
+        }
+        match self.format {
+            SeqFormat::Fasta => self.next_fasta(),
+            SeqFormat::Fastq => self.next_fastq(),
+        }
+    }
+}
+
+impl RecordReader<Box<dyn Read + Send>> {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_path_with_alignment(path, &AlignmentOptions::default(), false)
+    }
+
+    /// 同 `from_path`, BAM/CRAM 输入按 `options` 过滤, `paired` 时 header 追加 /1 与 /2,
+    /// 与 `AlignmentReader` 读到的 reads 一致
+    pub fn from_path_with_alignment<P: AsRef<Path>>(
+        path: P,
+        options: &AlignmentOptions,
+        paired: bool,
+    ) -> Result<Self> {
+        if AlignmentFormat::detect(&path)?.is_some() {
+            let mut reader = Self::new(Box::new(io::empty()), SeqFormat::Fastq);
+            reader.alignment = Some(AlignmentRecords::from_path(path, options, paired)?);
+            return Ok(reader);
+        }
+        let format = detect_file_format(&path)?;
+        Ok(Self::new(dyn_reader(path)?, format))
+    }
+}
@@ -12,6 +12,19 @@ pub struct SeqHeader {
     pub file_index: usize,
     pub reads_index: usize,
     pub format: SeqFormat,
+    /// FASTQ 碱基质量值 (phred) 之和, paired reads 为两端之和, FASTA 为 0
+    pub qual_sum: u64,
+    /// 质量过滤把低质量碱基替换为 'x' 之前的 (G/C, A/C/G/T) 碱基数, 只在过滤生效时记录
+    pub unmasked_gc: Option<(usize, usize)>,
+}
+
+/// 返回 (G/C 碱基数, A/C/G/T 碱基数)
+pub fn gc_count(seq: &[u8]) -> (usize, usize) {
+    seq.iter().fold((0, 0), |(gc, acgt), &ch| match ch {
+        b'G' | b'C' | b'g' | b'c' => (gc + 1, acgt + 1),
+        b'A' | b'T' | b'a' | b't' => (gc, acgt + 1),
+        _ => (gc, acgt),
+    })
 }
 
 #[derive(Debug)]
@@ -29,7 +42,7 @@ impl<T> Base<T> {
     where
         F: FnMut(&T) -> Result<U, E>,
     {
-        self.body.map(|t| f(&t)).map(|body| Base {
+        self.body.map(|t| f(t)).map(|body| Base {
             header: self.header.clone(),
             body,
         })
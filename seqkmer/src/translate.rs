@@ -0,0 +1,75 @@
+/// 标准遗传密码表, 下标为 3 个碱基的 2 位编码 (A=0, C=1, G=2, T=3) 依次拼接
+const CODON_TABLE: &[u8; 64] = b"KNKNTTTTRSRSIIMIQHQHPPPPRRRRLLLLEDEDAAAAGGGGVVVV*Y*YSSSS*CWCLFLF";
+
+/// 读码框之间的分隔符, 不是有效的氨基酸, 因此 k-mer 不会跨读码框
+pub const FRAME_SEPARATOR: u8 = b'-';
+
+#[inline]
+fn base_code(base: u8) -> Option<usize> {
+    match base {
+        b'A' | b'a' => Some(0),
+        b'C' | b'c' => Some(1),
+        b'G' | b'g' => Some(2),
+        b'T' | b't' => Some(3),
+        _ => None,
+    }
+}
+
+#[inline]
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' | b'a' => b'T',
+        b'C' | b'c' => b'G',
+        b'G' | b'g' => b'C',
+        b'T' | b't' => b'A',
+        _ => b'N',
+    }
+}
+
+fn translate_frame(seq: &[u8], frame: usize, out: &mut Vec<u8>) {
+    for codon in seq.get(frame..).unwrap_or_default().chunks_exact(3) {
+        let aa = match (
+            base_code(codon[0]),
+            base_code(codon[1]),
+            base_code(codon[2]),
+        ) {
+            (Some(b1), Some(b2), Some(b3)) => CODON_TABLE[b1 * 16 + b2 * 4 + b3],
+            // 含有不确定碱基的密码子
+            _ => b'X',
+        };
+        out.push(aa);
+    }
+}
+
+/// 按标准遗传密码翻译正链与反向互补链的 6 个读码框, 以 [`FRAME_SEPARATOR`] 分隔, 忽略换行符
+///
+/// ```
+/// use seqkmer::translate_frames;
+///
+/// assert_eq!(translate_frames(b"ATGGCC"), b"MA-W-G-GH-A-P");
+/// assert_eq!(translate_frames(b"ATGNNN\nTAA"), b"MX*-XX-XX-LXH-XX-XX");
+/// ```
+pub fn translate_frames(seq: &[u8]) -> Vec<u8> {
+    let forward: Vec<u8> = seq
+        .iter()
+        .copied()
+        .filter(|&base| base != b'\n' && base != b'\r')
+        .collect();
+    let reverse: Vec<u8> = forward.iter().rev().map(|&base| complement(base)).collect();
+
+    let mut out = Vec::with_capacity(forward.len() * 2 + 6);
+    for (strand, frame) in [
+        (&forward, 0),
+        (&forward, 1),
+        (&forward, 2),
+        (&reverse, 0),
+        (&reverse, 1),
+        (&reverse, 2),
+    ] {
+        if !out.is_empty() {
+            out.push(FRAME_SEPARATOR);
+        }
+        translate_frame(strand, frame, &mut out);
+    }
+    out
+}
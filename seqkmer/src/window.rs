@@ -0,0 +1,88 @@
+use crate::reader::Reader;
+use crate::seq::{Base, SeqFormat, SeqHeader};
+use crate::utils::OptionPair;
+use std::io::Result;
+
+/// 将完整序列 (基因组) 切分为固定长度的窗口, 每个窗口作为一条 read
+///
+/// 窗口的 `reads_index` 为其在序列中的序号 (从 1 开始), 起始位置为 `(reads_index - 1) * window_step`.
+pub struct WindowReader<R: Reader> {
+    inner: R,
+    window_size: usize,
+    window_step: usize,
+    batch_size: usize,
+    /// (序列 id, 序列长度), 按读取顺序
+    genomes: Vec<(String, usize)>,
+    pending: Vec<Base<Vec<u8>>>,
+}
+
+impl<R: Reader> WindowReader<R> {
+    pub fn new(inner: R, window_size: usize, window_step: usize) -> Self {
+        assert!(window_size > 0 && window_step > 0);
+        Self {
+            inner,
+            window_size,
+            window_step,
+            batch_size: 64,
+            genomes: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// 已读取的序列及其长度
+    pub fn genomes(&self) -> &[(String, usize)] {
+        &self.genomes
+    }
+
+    fn split(&mut self, genome: Base<Vec<u8>>) {
+        let OptionPair::Single(seq) = genome.body else {
+            return;
+        };
+        let seq: Vec<u8> = seq
+            .into_iter()
+            .filter(|base| !base.is_ascii_whitespace())
+            .collect();
+        self.genomes.push((genome.header.id.clone(), seq.len()));
+
+        let mut start = 0;
+        let mut window_index = 0;
+        while start < seq.len() {
+            let end = (start + self.window_size).min(seq.len());
+            window_index += 1;
+            let header = SeqHeader {
+                id: genome.header.id.clone(),
+                file_index: genome.header.file_index,
+                reads_index: window_index,
+                format: SeqFormat::Fasta,
+                qual_sum: 0,
+                unmasked_gc: None,
+            };
+            self.pending.push(Base::new(
+                header,
+                OptionPair::Single(seq[start..end].to_vec()),
+            ));
+            if end == seq.len() {
+                break;
+            }
+            start += self.window_step;
+        }
+    }
+}
+
+impl<R: Reader> Reader for WindowReader<R> {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        while self.pending.len() < self.batch_size {
+            match self.inner.next()? {
+                Some(genomes) => genomes.into_iter().for_each(|genome| self.split(genome)),
+                None => break,
+            }
+        }
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        let rest = self
+            .pending
+            .split_off(self.batch_size.min(self.pending.len()));
+        Ok(Some(std::mem::replace(&mut self.pending, rest)))
+    }
+}